@@ -0,0 +1,42 @@
+//! Stand-ins for a build without the `audio` feature: no devices, and calls say why.
+
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use super::{AudioStats, AudioSupport};
+
+const REASON: &str = "built without audio support";
+
+pub fn detect() -> AudioSupport {
+    AudioSupport::Unavailable(REASON.to_string())
+}
+
+pub fn device_names() -> (Vec<String>, Vec<String>) {
+    (Vec::new(), Vec::new())
+}
+
+/// Never constructed: `start` always fails
+pub struct AudioPipeline;
+
+impl AudioPipeline {
+    pub fn start(_stats: Arc<AudioStats>, _capture: bool) -> Result<Self> {
+        anyhow::bail!(REASON)
+    }
+
+    pub fn playback_only() -> Result<Self> {
+        anyhow::bail!(REASON)
+    }
+
+    pub fn take_capture_rx(&mut self) -> Option<mpsc::UnboundedReceiver<Vec<u8>>> {
+        None
+    }
+
+    pub fn play(&mut self, _opus_data: &[u8]) -> Result<f32> {
+        Ok(0.0)
+    }
+
+    pub fn play_pcm(&self, _pcm: Vec<f32>) {}
+
+    pub fn stop(&self) {}
+}
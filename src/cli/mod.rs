@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use wsp::{client, relay, util};
 
 #[derive(Parser)]
 #[command(name = "wsp")]
@@ -9,42 +10,157 @@ pub struct Cli {
     pub command: Commands,
 }
 
+// Parsed once at start, so Chat's many flags making it the big variant costs nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     /// Initialize a new identity (generates keypair)
     Init {
-        /// Path to save identity file
-        #[arg(short, long, default_value = "~/.wsp/identity")]
-        path: String,
+        /// Path to save identity file [default: the profile's identity file]
+        #[arg(short, long)]
+        path: Option<String>,
+
+        /// Create the identity for a named profile (kept apart from the default one)
+        #[arg(long, value_parser = profile_name)]
+        profile: Option<String>,
     },
     
     /// Start a chat session
     Chat {
-        /// Relay server URL
-        #[arg(short, long, default_value = "ws://localhost:8899")]
-        relay: String,
+        /// An invite link from /invite-link (wsp://host:port/#<key>): chat on its relay and
+        /// open a DM with whoever made it once they're online
+        #[arg(value_parser = clap::value_parser!(client::InviteLink), conflicts_with = "relay")]
+        link: Option<client::InviteLink>,
 
-        /// Identity file path
-        #[arg(short, long, default_value = "~/.wsp/identity")]
-        identity: String,
+        /// Relay server URL [default: from the config, else ws://localhost:8899]
+        #[arg(short, long)]
+        relay: Option<String>,
+
+        /// Reach the relay through a proxy: socks5://[user:pass@]host:port (Tor: socks5://127.0.0.1:9050)
+        /// or http://[user:pass@]host:port
+        #[arg(long, value_parser = clap::value_parser!(client::Proxy))]
+        proxy: Option<client::Proxy>,
+
+        /// Identity file path (created on first run) [default: the profile's identity file]
+        #[arg(short, long)]
+        identity: Option<String>,
+
+        /// Config file with default relay and nickname
+        #[arg(short, long, default_value_t = util::default_config_path().display().to_string())]
+        config: String,
+
+        /// Chat as a named profile: its identity, and its relay and nickname from the config
+        #[arg(long, value_parser = profile_name)]
+        profile: Option<String>,
 
         /// Save chat history (encrypted locally)
         #[arg(short, long)]
         save: bool,
 
+        /// Keep everything in memory: no history, and commands that write files refuse or warn
+        #[arg(long)]
+        ephemeral: bool,
+
+        /// With --ephemeral: use a one-time identity that's never saved
+        #[arg(long, requires = "ephemeral")]
+        ephemeral_identity: bool,
+
+        /// Connect under a random session id rather than one derived from your identity
+        /// key, so the relay can't link your sessions (--ephemeral does this too)
+        #[arg(long)]
+        ephemeral_session: bool,
+
         /// Your nickname (visible to other users after E2EE)
         #[arg(short, long)]
         name: Option<String>,
+
+        /// Start in low-bandwidth mode: text only, until /lowbw off
+        #[arg(long)]
+        low_bandwidth: bool,
+
+        /// Ask the relay to deflate frames both ways (see /stats for the savings)
+        #[arg(long)]
+        compress: bool,
+
+        /// Lobby-less: no Global chat, sent or received — only DMs and groups
+        #[arg(long)]
+        no_global: bool,
+
+        /// Only use a relay with this fingerprint (from `wsp relay --show-id`); without
+        /// it, the relay's key is pinned the first time and a change is refused
+        #[arg(long, value_name = "FINGERPRINT")]
+        expect_relay: Option<String>,
+
+        /// Screen-reader friendly: print messages as lines and read commands from stdin,
+        /// with no full-screen UI (no calls in this mode)
+        #[arg(long)]
+        plain: bool,
     },
     
+    /// Check the identity, relay, terminal and audio, and say what's wrong
+    Doctor {
+        /// Relay server URL [default: from the config, else ws://localhost:8899]
+        #[arg(short, long)]
+        relay: Option<String>,
+
+        /// Identity file path [default: the profile's identity file]
+        #[arg(short, long)]
+        identity: Option<String>,
+
+        /// Config file with default relay and nickname
+        #[arg(short, long, default_value_t = util::default_config_path().display().to_string())]
+        config: String,
+
+        /// Check a named profile's identity and relay
+        #[arg(long, value_parser = profile_name)]
+        profile: Option<String>,
+    },
+
+    /// List profiles and their key fingerprints
+    Profiles,
+
     /// Run a relay server
     Relay {
-        /// Address to bind to
-        #[arg(short, long, default_value = "0.0.0.0:8899")]
-        addr: String,
+        /// Address to bind to; repeat it or separate with commas for more (e.g. `0.0.0.0:8899,[::]:8899`)
+        #[arg(short, long, value_delimiter = ',', default_value = "0.0.0.0:8899")]
+        addr: Vec<String>,
+
+        /// Most sessions allowed in one group room
+        #[arg(long, default_value_t = relay::DEFAULT_MAX_ROOM_MEMBERS)]
+        max_room_members: usize,
+
+        /// Most sessions connected at once; new ones past this are turned away
+        #[arg(long, default_value_t = relay::DEFAULT_MAX_SESSIONS)]
+        max_sessions: usize,
+
+        /// Print a one-line status (sessions, rooms, msgs/s, bytes/s) every this many seconds
+        #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        status_interval: Option<u64>,
+
+        /// Take operator commands (sessions, rooms, kick, limits, set) on a loopback
+        /// host:port or a unix socket path
+        #[arg(long, value_name = "ADDR")]
+        admin_addr: Option<String>,
+
+        /// Where the relay keeps the key clients know it by (made on first start)
+        #[arg(long, value_name = "PATH")]
+        key: Option<String>,
+
+        /// Print the relay's fingerprint, for publishing, and exit
+        #[arg(long)]
+        show_id: bool,
     },
 }
 
+/// Profile names end up as directory names, so only plain ones are taken
+fn profile_name(name: &str) -> Result<String, String> {
+    if util::valid_profile_name(name) {
+        Ok(name.to_string())
+    } else {
+        Err(format!("use letters, digits, - and _ (up to {} characters)", util::MAX_PROFILE_NAME))
+    }
+}
+
 impl Cli {
     pub fn parse_args() -> Self {
         Self::parse()
@@ -0,0 +1,261 @@
+//! Sender-key bookkeeping for group chats: our chain per group and who holds it, the
+//! chains other members gave us, and group frames waiting on a key we asked for.
+
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+use crate::crypto::sender_key::{SenderChain, SenderKeyHeader, SenderKeyReceiver};
+use crate::protocol::SenderKeyUpdate;
+
+/// Group key state, shared between the receiver and sender tasks (persists across reconnects)
+pub(super) type SharedGroupKeys = std::sync::Arc<std::sync::Mutex<GroupKeys>>;
+
+/// Group frames kept per sender while their key is on its way
+const MAX_PENDING: usize = 32;
+/// Keys kept per sender, so frames sealed just before a rotation still open
+const KEPT_KEYS: usize = 2;
+
+/// Our sending chain for one group
+struct OwnKey {
+    chain: SenderChain,
+    /// Members we've given this key to, and the iteration they got it from
+    holders: HashMap<String, u32>,
+}
+
+/// A group frame that arrived before its key
+pub(super) struct PendingFrame {
+    header: SenderKeyHeader,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+/// What came of opening a group frame
+pub(super) enum Opened {
+    Plaintext(Vec<u8>),
+    Failed(anyhow::Error),
+    /// We don't have the key yet; the frame is kept until it arrives
+    MissingKey(SenderKeyHeader),
+}
+
+#[derive(Default)]
+pub(super) struct GroupKeys {
+    /// Our chains, by group id
+    own: HashMap<String, OwnKey>,
+    /// Other members' chains by (group id, sender), newest last
+    received: HashMap<(String, String), Vec<SenderKeyReceiver>>,
+    pending: HashMap<(String, String), Vec<PendingFrame>>,
+    /// (group id, sender, key id) we've already asked for
+    requested: HashSet<(String, String, u32)>,
+}
+
+impl GroupKeys {
+    /// Get our key for `group_id` ready to send to `members`. If anyone holding the
+    /// current key is no longer a member, a new key replaces it. Returns the
+    /// distributions owed to members who don't have it yet and can be reached
+    /// (`reachable`); they're counted as holders from here on.
+    pub fn prepare_send(
+        &mut self,
+        group_id: &str,
+        members: &[String],
+        reachable: impl Fn(&str) -> bool,
+    ) -> Vec<(String, SenderKeyUpdate)> {
+        let stale = self.own.get(group_id)
+            .is_none_or(|own| own.holders.keys().any(|holder| !members.contains(holder)));
+        if stale {
+            self.own.insert(group_id.to_string(), OwnKey { chain: SenderChain::generate(), holders: HashMap::new() });
+        }
+        let own = self.own.get_mut(group_id).expect("just inserted");
+
+        let mut owed = Vec::new();
+        for member in members {
+            if own.holders.contains_key(member) || !reachable(member) {
+                continue;
+            }
+            let distribution = own.chain.distribution_from(own.chain.iteration());
+            own.holders.insert(member.clone(), distribution.iteration);
+            owed.push((member.clone(), SenderKeyUpdate::Distribution { group_id: group_id.to_string(), distribution }));
+        }
+        owed
+    }
+
+    /// Encrypt one group frame under our current key (after `prepare_send`)
+    pub fn seal(&mut self, group_id: &str, plaintext: &[u8]) -> Result<(SenderKeyHeader, Vec<u8>, Vec<u8>)> {
+        let own = self.own.get_mut(group_id).ok_or_else(|| anyhow::anyhow!("no sender key for group"))?;
+        own.chain.encrypt(plaintext)
+    }
+
+    /// Answer `member`'s request for our key: only for the current key, only to someone
+    /// already holding it, and never from before they were given it
+    pub fn resend(&self, group_id: &str, member: &str, key_id: u32, iteration: u32) -> Option<SenderKeyUpdate> {
+        let own = self.own.get(group_id).filter(|own| own.chain.key_id() == key_id)?;
+        let given_from = *own.holders.get(member)?;
+        let distribution = own.chain.distribution_from(iteration.max(given_from));
+        Some(SenderKeyUpdate::Distribution { group_id: group_id.to_string(), distribution })
+    }
+
+    /// Open a group frame from `sender`, or hold on to it if we lack the key
+    pub fn open(&mut self, group_id: &str, sender: &str, header: SenderKeyHeader, nonce: Vec<u8>, ciphertext: Vec<u8>) -> Opened {
+        let slot = (group_id.to_string(), sender.to_string());
+        let receiver = self.received.get_mut(&slot)
+            .and_then(|chains| chains.iter_mut().find(|chain| chain.key_id() == header.key_id));
+        if let Some(receiver) = receiver {
+            return match receiver.decrypt(&header, &nonce, &ciphertext) {
+                Ok(plaintext) => Opened::Plaintext(plaintext),
+                Err(e) => Opened::Failed(e),
+            };
+        }
+
+        let pending = self.pending.entry(slot).or_default();
+        if pending.len() >= MAX_PENDING {
+            pending.remove(0);
+        }
+        pending.push(PendingFrame { header, nonce, ciphertext });
+        Opened::MissingKey(header)
+    }
+
+    /// Whether to ask `sender` for a key (once per key)
+    pub fn should_request(&mut self, group_id: &str, sender: &str, key_id: u32) -> bool {
+        self.requested.insert((group_id.to_string(), sender.to_string(), key_id))
+    }
+
+    /// Store a key `sender` gave us and open whatever was waiting on it
+    pub fn accept(&mut self, group_id: &str, sender: &str, update: &SenderKeyUpdate) -> Vec<Result<Vec<u8>>> {
+        let SenderKeyUpdate::Distribution { distribution, .. } = update else {
+            return Vec::new();
+        };
+        let slot = (group_id.to_string(), sender.to_string());
+        let chains = self.received.entry(slot.clone()).or_default();
+        if chains.iter().any(|chain| chain.key_id() == distribution.key_id) {
+            return Vec::new();
+        }
+        chains.push(SenderKeyReceiver::new(distribution));
+        if chains.len() > KEPT_KEYS {
+            chains.remove(0);
+        }
+        let receiver = chains.last_mut().expect("just pushed");
+
+        let Some(pending) = self.pending.get_mut(&slot) else {
+            return Vec::new();
+        };
+        let (ready, waiting): (Vec<_>, Vec<_>) = pending.drain(..).partition(|f| f.header.key_id == distribution.key_id);
+        *pending = waiting;
+        ready.into_iter()
+            .map(|frame| receiver.decrypt(&frame.header, &frame.nonce, &frame.ciphertext))
+            .collect()
+    }
+
+    /// Drop everything about a group we've left
+    pub fn forget_group(&mut self, group_id: &str) {
+        self.own.remove(group_id);
+        self.received.retain(|(group, _), _| group != group_id);
+        self.pending.retain(|(group, _), _| group != group_id);
+        self.requested.retain(|(group, _, _)| group != group_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    /// Hand the distributions to each member's own GroupKeys
+    fn deliver(owed: Vec<(String, SenderKeyUpdate)>, to: &mut HashMap<String, GroupKeys>) {
+        for (member, update) in owed {
+            to.entry(member).or_default().accept("g1", "alice", &update);
+        }
+    }
+
+    fn open(keys: &mut GroupKeys, (header, nonce, ct): (SenderKeyHeader, Vec<u8>, Vec<u8>)) -> Option<Vec<u8>> {
+        match keys.open("g1", "alice", header, nonce, ct) {
+            Opened::Plaintext(pt) => Some(pt),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_key_rotates_when_a_member_is_removed() {
+        let mut alice = GroupKeys::default();
+        let mut others = HashMap::new();
+
+        let owed = alice.prepare_send("g1", &members(&["bob", "carol"]), |_| true);
+        assert_eq!(owed.len(), 2);
+        deliver(owed, &mut others);
+        let frame = alice.seal("g1", b"hi both").unwrap();
+        let first_key = frame.0.key_id;
+        assert_eq!(open(others.get_mut("carol").unwrap(), frame).unwrap(), b"hi both");
+
+        // Same members: nothing to redistribute, same key
+        assert!(alice.prepare_send("g1", &members(&["bob", "carol"]), |_| true).is_empty());
+
+        // Carol leaves: a new key goes to bob only, and carol can't read on
+        let owed = alice.prepare_send("g1", &members(&["bob"]), |_| true);
+        assert_eq!(owed.iter().map(|(m, _)| m.as_str()).collect::<Vec<_>>(), vec!["bob"]);
+        deliver(owed, &mut others);
+        let frame = alice.seal("g1", b"just bob").unwrap();
+        let rotated_key = frame.0.key_id;
+        assert_ne!(rotated_key, first_key);
+        let copy = (frame.0, frame.1.clone(), frame.2.clone());
+        assert_eq!(open(others.get_mut("bob").unwrap(), frame).unwrap(), b"just bob");
+        assert!(open(others.get_mut("carol").unwrap(), copy).is_none());
+        assert!(alice.resend("g1", "carol", rotated_key, 0).is_none());
+    }
+
+    #[test]
+    fn test_added_member_gets_key_from_now_on() {
+        let mut alice = GroupKeys::default();
+        let mut others = HashMap::new();
+        deliver(alice.prepare_send("g1", &members(&["bob"]), |_| true), &mut others);
+        let before = alice.seal("g1", b"before dave").unwrap();
+
+        let owed = alice.prepare_send("g1", &members(&["bob", "dave"]), |_| true);
+        assert_eq!(owed.len(), 1);
+        deliver(owed, &mut others);
+        let after = alice.seal("g1", b"with dave").unwrap();
+        assert_eq!(before.0.key_id, after.0.key_id);
+
+        let dave = others.get_mut("dave").unwrap();
+        assert!(open(dave, before).is_none());
+        assert_eq!(open(dave, after).unwrap(), b"with dave");
+    }
+
+    #[test]
+    fn test_frames_wait_for_requested_key() {
+        let mut alice = GroupKeys::default();
+        // Bob had no session when the key went out
+        assert!(alice.prepare_send("g1", &members(&["bob"]), |_| false).is_empty());
+        let frame = alice.seal("g1", b"early").unwrap();
+
+        let mut bob = GroupKeys::default();
+        let Opened::MissingKey(header) = bob.open("g1", "alice", frame.0, frame.1, frame.2) else {
+            panic!("expected a missing key");
+        };
+        assert!(bob.should_request("g1", "alice", header.key_id));
+        assert!(!bob.should_request("g1", "alice", header.key_id));
+
+        // Not a holder yet, so no resend; once the session is up bob becomes one
+        assert!(alice.resend("g1", "bob", header.key_id, header.iteration).is_none());
+        assert_eq!(alice.prepare_send("g1", &members(&["bob"]), |_| true).len(), 1);
+        let update = alice.resend("g1", "bob", header.key_id, header.iteration).unwrap();
+        // The resend can't reach back before bob was given the key
+        let SenderKeyUpdate::Distribution { ref distribution, .. } = update else { unreachable!() };
+        assert_eq!(distribution.iteration, 1);
+        let opened = bob.accept("g1", "alice", &update);
+        assert_eq!(opened.len(), 1);
+        assert!(opened[0].is_err());
+    }
+
+    #[test]
+    fn test_pending_frames_open_on_distribution() {
+        let mut alice = GroupKeys::default();
+        let owed = alice.prepare_send("g1", &members(&["bob"]), |_| true);
+        let frame = alice.seal("g1", b"overtook its key").unwrap();
+
+        let mut bob = GroupKeys::default();
+        assert!(matches!(bob.open("g1", "alice", frame.0, frame.1, frame.2), Opened::MissingKey(_)));
+        let opened = bob.accept("g1", "alice", &owed[0].1);
+        assert_eq!(opened.into_iter().map(Result::unwrap).collect::<Vec<_>>(), vec![b"overtook its key".to_vec()]);
+    }
+}
@@ -0,0 +1,107 @@
+//! Chat sent but not yet confirmed. A message pushed into a websocket that is about
+//! to die looks sent but never arrives, so DMs and group messages are held here until
+//! a recipient answers with `Message::Delivered`. After a reconnect whatever is still
+//! held (and recent) goes out again through the normal send path, encrypted afresh:
+//! the lost ciphertexts already used up their ratchet keys. The copy carries the same
+//! message id, so a recipient that did get the first one drops it as a duplicate.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::OutgoingMessage;
+
+/// Older unconfirmed messages aren't resent: the conversation has moved on
+const RESEND_WITHIN: Duration = Duration::from_secs(180);
+/// Most messages held at once; past it the oldest is given up on
+const MAX_INFLIGHT: usize = 256;
+
+pub(super) type SharedInflight = Arc<Mutex<Inflight>>;
+
+#[derive(Default)]
+pub(super) struct Inflight {
+    /// Oldest first: when each went out, and a copy to resend
+    sent: VecDeque<(Instant, OutgoingMessage)>,
+}
+
+impl Inflight {
+    /// Hold on to `msg` if it's chat a recipient will confirm
+    pub fn track(&mut self, msg: &OutgoingMessage) {
+        let copy = match msg {
+            OutgoingMessage::Direct { target_id, message } if message.awaits_delivery() => OutgoingMessage::Direct {
+                target_id: target_id.clone(),
+                message: message.clone(),
+            },
+            OutgoingMessage::Group { group_id, member_ids, message } if message.awaits_delivery() => OutgoingMessage::Group {
+                group_id: group_id.clone(),
+                member_ids: member_ids.clone(),
+                message: message.clone(),
+            },
+            _ => return,
+        };
+        self.expire();
+        if self.sent.len() >= MAX_INFLIGHT {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((Instant::now(), copy));
+    }
+
+    /// A recipient confirmed `message_id` (every part of it, if it was split)
+    pub fn delivered(&mut self, message_id: &str) {
+        self.sent.retain(|(_, msg)| message_of(msg).and_then(|m| m.message_id.as_deref()) != Some(message_id));
+    }
+
+    /// Everything still unconfirmed and recent enough to send again, oldest first.
+    /// Sending them tracks them afresh.
+    pub fn take_for_resend(&mut self) -> Vec<OutgoingMessage> {
+        self.expire();
+        self.sent.drain(..).map(|(_, msg)| msg).collect()
+    }
+
+    fn expire(&mut self) {
+        while self.sent.front().is_some_and(|(at, _)| at.elapsed() >= RESEND_WITHIN) {
+            self.sent.pop_front();
+        }
+    }
+}
+
+fn message_of(msg: &OutgoingMessage) -> Option<&crate::protocol::PlainMessage> {
+    match msg {
+        OutgoingMessage::Direct { message, .. } | OutgoingMessage::Group { message, .. } => Some(message),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PlainMessage;
+
+    fn dm(text: &str) -> OutgoingMessage {
+        OutgoingMessage::Direct { target_id: "peer".to_string(), message: PlainMessage::direct("me".to_string(), text.to_string()) }
+    }
+
+    fn id_of(msg: &OutgoingMessage) -> String {
+        message_of(msg).and_then(|m| m.message_id.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_unconfirmed_chat_is_resent() {
+        let mut inflight = Inflight::default();
+        let (first, second) = (dm("first"), dm("second"));
+        inflight.track(&first);
+        inflight.track(&second);
+        // Not chat: typing, nicknames and other housekeeping aren't held
+        inflight.track(&OutgoingMessage::Global(PlainMessage::new("me".to_string(), "hi all".to_string())));
+        inflight.track(&OutgoingMessage::Nickname("me".to_string()));
+
+        inflight.delivered(&id_of(&first));
+        let resend = inflight.take_for_resend();
+        assert_eq!(resend.iter().map(id_of).collect::<Vec<_>>(), [id_of(&second)]);
+        assert!(inflight.take_for_resend().is_empty());
+
+        // Too old to bother
+        inflight.sent.push_back((Instant::now() - RESEND_WITHIN, dm("stale")));
+        assert!(inflight.take_for_resend().is_empty());
+    }
+}
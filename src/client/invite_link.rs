@@ -0,0 +1,137 @@
+//! Invite links: a relay and an identity key in one string to hand a friend, so they
+//! don't have to type either. `wsp://host:port/#<key>` points at a plain ws:// relay and
+//! `wsps://` at one behind TLS; the key is the 32-byte identity key in unpadded URL-safe
+//! base64. Whoever opens the link (`wsp chat <link>`) connects to that relay and looks
+//! for the session the key derives.
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64::Engine;
+
+use super::proxy::relay_target;
+use super::session_id_for;
+
+const B64: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+/// A relay and the identity key to find there
+#[derive(Clone, PartialEq, Eq)]
+pub struct InviteLink {
+    /// Relay over TLS (wss://)
+    tls: bool,
+    /// host:port
+    addr: String,
+    /// Path on the relay, if it isn't served at the root ("" or "/ws")
+    path: String,
+    public_key: [u8; 32],
+}
+
+impl InviteLink {
+    /// The link to `public_key` on the relay at `relay_url` (ws:// or wss://)
+    pub fn new(relay_url: &str, public_key: &[u8]) -> Result<Self, String> {
+        let (scheme, rest) = relay_url.split_once("://").ok_or_else(|| format!("relay URL {} has no scheme", relay_url))?;
+        let tls = match scheme.to_ascii_lowercase().as_str() {
+            "ws" => false,
+            "wss" => true,
+            other => return Err(format!("can't link to a {}:// relay (only ws:// and wss://)", other)),
+        };
+        let (host, port) = relay_target(relay_url).map_err(|e| e.to_string())?;
+        let host = if host.contains(':') { format!("[{}]", host) } else { host };
+        let path = rest.find('/').map_or("", |at| &rest[at..]).trim_end_matches('/');
+        if path.contains(['?', '#']) {
+            return Err("relay URLs with a query or fragment can't go in a link".to_string());
+        }
+        let public_key = <[u8; 32]>::try_from(public_key).map_err(|_| "an identity key is 32 bytes".to_string())?;
+        Ok(Self { tls, addr: format!("{}:{}", host, port), path: path.to_string(), public_key })
+    }
+
+    /// The relay to connect to
+    pub fn relay_url(&self) -> String {
+        format!("{}://{}{}", if self.tls { "wss" } else { "ws" }, self.addr, self.path)
+    }
+
+    /// The identity key of whoever made the link
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public_key
+    }
+
+    /// The session their identity key derives (unless they chose a random one)
+    pub fn session_id(&self) -> String {
+        session_id_for(&self.public_key)
+    }
+}
+
+impl FromStr for InviteLink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const USAGE: &str = "expected wsp://host:port/#<identity key>";
+        let (scheme, rest) = s.trim().split_once("://").ok_or(USAGE)?;
+        let tls = match scheme.to_ascii_lowercase().as_str() {
+            "wsp" => false,
+            "wsps" => true,
+            _ => return Err(USAGE.to_string()),
+        };
+        let (location, key) = rest.split_once('#').ok_or("the link has no identity key after #")?;
+        let (addr, path) = location.split_once('/').map_or((location, ""), |(addr, path)| (addr, path));
+        if addr.contains('@') || location.contains('?') {
+            return Err("the link can only hold host:port and a path before the #".to_string());
+        }
+        let port = addr.rsplit_once(':').filter(|(host, _)| !host.is_empty() && !host.ends_with(':')).map(|(_, port)| port);
+        if port.and_then(|port| port.parse::<u16>().ok()).is_none_or(|port| port == 0) {
+            return Err(format!("the link's relay {} needs a host and a port", addr));
+        }
+        let key = B64.decode(key).map_err(|_| "the identity key in the link isn't valid base64".to_string())?;
+        let relay_url = format!("{}://{}/{}", if tls { "wss" } else { "ws" }, addr, path);
+        Self::new(&relay_url, &key).map_err(|e| format!("bad invite link: {}", e))
+    }
+}
+
+impl fmt::Display for InviteLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.tls { "wsps" } else { "wsp" };
+        write!(f, "{}://{}{}/#{}", scheme, self.addr, self.path, B64.encode(self.public_key))
+    }
+}
+
+impl fmt::Debug for InviteLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InviteLink({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invite_link_round_trip_and_validation() {
+        let link = InviteLink::new("ws://relay.example:8899", &[7; 32]).unwrap();
+        let text = link.to_string();
+        assert_eq!(text, format!("wsp://relay.example:8899/#{}", B64.encode([7; 32])));
+        let parsed: InviteLink = text.parse().unwrap();
+        assert_eq!(parsed, link);
+        assert_eq!(parsed.relay_url(), "ws://relay.example:8899");
+        assert_eq!(parsed.session_id(), session_id_for(&[7; 32]));
+
+        // TLS relays, default ports and paths survive the trip
+        let tls = InviteLink::new("wss://relay.example/ws/", &[7; 32]).unwrap();
+        assert!(tls.to_string().starts_with("wsps://relay.example:443/ws/#"), "{}", tls);
+        assert_eq!(tls.to_string().parse::<InviteLink>().unwrap().relay_url(), "wss://relay.example:443/ws");
+
+        let key = B64.encode([7; 32]);
+        for bad in [
+            format!("https://relay.example:8899/#{}", key),
+            format!("wsp://relay.example/#{}", key),
+            format!("wsp://relay.example:0/#{}", key),
+            format!("wsp://:8899/#{}", key),
+            format!("wsp://user@relay.example:8899/#{}", key),
+            format!("wsp://relay.example:8899/?x=1#{}", key),
+            "wsp://relay.example:8899/".to_string(),
+            format!("wsp://relay.example:8899/#{}", B64.encode([7; 31])),
+            format!("wsp://relay.example:8899/#{}=", key),
+        ] {
+            assert!(bad.parse::<InviteLink>().is_err(), "{}", bad);
+        }
+    }
+}
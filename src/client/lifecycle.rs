@@ -0,0 +1,163 @@
+//! Peers who have gone. Leaving doesn't take anyone out of the peer map, so after a long
+//! session on a busy relay it fills with sessions that will never be used again, and
+//! every Global send ratchets for each of them. A peer goes inactive when they say
+//! goodbye, or when they've been quiet for the idle window and the relay, asked with a
+//! Discover, says they aren't connected (a quiet peer who is still there isn't touched).
+//! Inactive peers get no Global chat and the TUI lists them under "recently seen";
+//! anything from them, such as the key exchange they send on reconnecting, makes them
+//! active again on the session they had. A peer inactive for FORGET_AFTER is dropped,
+//! ratchet and all (its keys are zeroized as it goes).
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::protocol::{codec, Message};
+
+use super::PeerInfo;
+
+/// How long a peer can be quiet before we check they're still connected
+pub(super) const DEFAULT_PEER_IDLE: Duration = Duration::from_secs(10 * 60);
+/// How long an inactive peer is kept in case they come back
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+/// How long the relay has to say a probed peer isn't there; silence means they are
+const PROBE_GRACE: Duration = Duration::from_secs(20);
+
+/// Whether a peer is still around, as far as we can tell
+#[derive(Debug)]
+pub(super) struct Activity {
+    /// When something last came from them, or a probe found them connected
+    last_seen: Instant,
+    /// When we asked the relay about them, while waiting to hear
+    probed: Option<Instant>,
+    /// When they went inactive
+    inactive_since: Option<Instant>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self { last_seen: Instant::now(), probed: None, inactive_since: None }
+    }
+}
+
+impl Activity {
+    pub fn is_active(&self) -> bool {
+        self.inactive_since.is_none()
+    }
+
+    /// Something came from the peer. True if that brings them back.
+    pub fn seen(&mut self) -> bool {
+        self.last_seen = Instant::now();
+        self.probed = None;
+        self.inactive_since.take().is_some()
+    }
+
+    /// The peer said goodbye, or the relay says they aren't there. True if they were active.
+    pub fn gone(&mut self) -> bool {
+        self.probed = None;
+        if !self.is_active() {
+            return false;
+        }
+        self.inactive_since = Some(Instant::now());
+        true
+    }
+}
+
+/// What a sweep of the peer map came to
+#[derive(Debug, Default)]
+pub(super) struct Sweep {
+    /// Discover frames asking the relay about quiet peers, by peer
+    pub probes: Vec<(String, Vec<u8>)>,
+    /// Peers the TUI should hear about again (forgotten ones, here)
+    pub changed: Vec<String>,
+}
+
+/// Check on peers quiet for `idle` (None: never) and forget the ones long gone
+pub(super) fn sweep(peers: &mut HashMap<String, PeerInfo>, own_id: &str, idle: Option<Duration>, now: Instant) -> Sweep {
+    let mut sweep = Sweep::default();
+    peers.retain(|id, peer| {
+        let activity = &mut peer.activity;
+        if let Some(since) = activity.inactive_since {
+            if now.saturating_duration_since(since) < FORGET_AFTER {
+                return true;
+            }
+            sweep.changed.push(id.clone());
+            return false;
+        }
+        match activity.probed {
+            // The relay would have said by now if they weren't there
+            Some(at) if now.saturating_duration_since(at) >= PROBE_GRACE => {
+                activity.probed = None;
+                activity.last_seen = now;
+            }
+            Some(_) => {}
+            None if idle.is_some_and(|idle| now.saturating_duration_since(activity.last_seen) >= idle) => {
+                let probe = Message::Discover { target_session: id.clone(), from: own_id.to_string() };
+                if let Ok(frame) = codec::encode(&probe) {
+                    activity.probed = Some(now);
+                    sweep.probes.push((id.clone(), frame));
+                }
+            }
+            None => {}
+        }
+        true
+    });
+    sweep
+}
+
+/// The relay says `id` isn't connected. True if that answers our probe (and so is ours
+/// to deal with, not news for the TUI).
+pub(super) fn not_found(peers: &mut HashMap<String, PeerInfo>, id: &str) -> bool {
+    match peers.get_mut(id) {
+        Some(peer) if peer.activity.probed.is_some() => {
+            peer.activity.gone();
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ratchet::RatchetSession;
+    use super::super::rekey::SessionHealth;
+
+    fn peer() -> PeerInfo {
+        PeerInfo {
+            ratchet: RatchetSession::init(&[1; 32], true),
+            nickname: None,
+            public_key: vec![1; 32],
+            health: SessionHealth::default(),
+            capabilities: 0,
+            activity: Activity::default(),
+        }
+    }
+
+    #[test]
+    fn test_quiet_peers_are_probed_then_forgotten() {
+        let idle = Duration::from_secs(600);
+        let mut peers: HashMap<String, PeerInfo> = ["quiet", "gone", "chatty"].iter().map(|id| (id.to_string(), peer())).collect();
+        let later = Instant::now() + idle;
+
+        // Everyone quiet for the window is asked about; the one who speaks up isn't
+        peers.get_mut("chatty").unwrap().activity.last_seen = later;
+        let first = sweep(&mut peers, "me", Some(idle), later);
+        assert_eq!(first.probes.len(), 2);
+        assert!(sweep(&mut peers, "me", None, later).probes.is_empty());
+
+        // The relay says one isn't there; the other it says nothing about, so stays
+        assert!(not_found(&mut peers, "gone"));
+        assert!(!not_found(&mut peers, "chatty"));
+        assert!(!peers["gone"].activity.is_active());
+        sweep(&mut peers, "me", Some(idle), later + PROBE_GRACE);
+        assert!(peers["quiet"].activity.is_active() && peers["quiet"].activity.probed.is_none());
+
+        // Back on the session they had, or forgotten once gone long enough
+        assert!(peers.get_mut("gone").unwrap().activity.seen());
+        assert!(peers.get_mut("gone").unwrap().activity.gone());
+        let much_later = Instant::now() + FORGET_AFTER;
+        let last = sweep(&mut peers, "me", None, much_later);
+        assert_eq!(last.changed, ["gone"]);
+        assert!(!peers.contains_key("gone") && peers.len() == 2);
+    }
+}
@@ -1,15 +1,84 @@
+//! Relay client: connects, exchanges keys with every peer and encrypts/decrypts
+//! chat traffic, reconnecting with backoff under the same session id.
+
 use anyhow::{Context, Result};
 use futures_util::{SinkExt, StreamExt};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use tokio::net::TcpStream;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::sleep;
-use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::{client_async_tls_with_config, connect_async_with_config};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::{protocol::WebSocketConfig, Message as WsMessage};
+use zeroize::Zeroize;
 
 use crate::crypto::{decrypt_message, encrypt_message, Identity};
-use crate::crypto::ratchet::{RatchetHeader, RatchetSession};
-use crate::protocol::{Message, PlainMessage};
+use crate::crypto::ratchet::{self, Padding, RatchetHeader, RatchetSession};
+use crate::crypto::relay_key::RelayChallenge;
+use crate::crypto::sender_key::SenderKeyHeader;
+use crate::strings::{self, Key};
+use crate::protocol::{codec, decode_bincode, sanitize_text, short_id, CallSalt, ErrorCode, Message, PlainMessage, SenderKeyUpdate, CAPABILITIES, CAP_PADDING, MAX_MESSAGE_SIZE};
+
+mod group_keys;
+mod inflight;
+mod invite_link;
+mod lifecycle;
+mod outbox;
+mod peer_updates;
+mod proxy;
+mod rekey;
+mod relay_pins;
+mod rooms;
+mod scheduler;
+mod stats;
+mod status;
+
+use group_keys::{Opened, SharedGroupKeys};
+use inflight::SharedInflight;
+use lifecycle::{Activity, DEFAULT_PEER_IDLE};
+use outbox::OutgoingReceiver;
+use peer_updates::{spawn_peer_updates, PeerChanges};
+use rekey::{Decrypted, SessionHealth};
+use relay_pins::{Checked, RelayPins, SharedRelayPins};
+use rooms::SharedRooms;
+use scheduler::{Frame, Lane, SendScheduler};
+pub use invite_link::InviteLink;
+pub use outbox::{OutgoingSender, SendError};
+pub use peer_updates::PeerUpdate;
+pub use proxy::{Proxy, ProxyError, ProxyKind};
+pub use relay_pins::RelayMismatch;
+pub use stats::{ClientStats, Compression, StatsSnapshot, Traffic, TrafficSplit};
+pub use status::{relay_host, ClientStatus, ConnectionState, Refusal, SetupTimeout};
+
+/// Delay before the first reconnect attempt (doubles on each failure)
+const RECONNECT_INITIAL: Duration = Duration::from_secs(1);
+/// Upper bound for the reconnect delay
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+/// Longest wait for the websocket handshake, and again for the relay's Ack to our Connect
+const SETUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Reconnect delays are this many times longer while `slow_reconnect` is set
+const SLOW_RECONNECT_FACTOR: u32 = 4;
+/// Reconnect when the relay has passed on nothing for this long after we sent chat
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(120);
+/// A peer whose session carried a message this recently is resuming it, not joining
+const RESUME_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Key derivation context for session ids derived from an identity key
+const SESSION_ID_CONTEXT: &str = "wsp session id v1";
+
+/// All peer sessions, shared between the receiver and sender tasks (persists across reconnects)
+type PeerMap = std::sync::Arc<tokio::sync::RwLock<HashMap<String, PeerInfo>>>;
+/// Our current nickname: sent to each new peer after key exchange, changed by `OutgoingMessage::Nickname`
+type SharedNickname = std::sync::Arc<std::sync::RwLock<Option<String>>>;
+
+/// Voice keys of calls in progress, by peer, each with its call's id. Kept apart from
+/// the peer map so audio frames never wait on it; only call setup and hang-up touch the peers.
+type SharedCallKeys = std::sync::Arc<std::sync::RwLock<HashMap<String, (String, [u8; 32])>>>;
+
+/// Sending half of the status channel
+type StatusSender = mpsc::UnboundedSender<ClientStatus>;
 
 /// Display-only peer info sent to the TUI (no crypto state)
 #[derive(Clone, Debug)]
@@ -20,135 +89,364 @@ pub struct PeerDisplay {
 }
 
 /// Full peer state (held by client, not exposed to TUI)
-pub struct PeerInfo {
-    pub ratchet: RatchetSession,
-    pub nickname: Option<String>,
+struct PeerInfo {
+    ratchet: RatchetSession,
+    nickname: Option<String>,
     /// Peer's identity public key
-    pub public_key: Vec<u8>,
+    public_key: Vec<u8>,
+    /// Decrypt failures, and a replacement session while one is being set up
+    health: SessionHealth,
+    /// What the peer said it can handle in its last key exchange (`CAP_*` bits)
+    capabilities: u32,
+    /// Whether they're still around, or gone (see lifecycle.rs)
+    activity: Activity,
+}
+
+impl PeerInfo {
+    /// Whether the peer can read padded plaintexts
+    fn reads_padding(&self) -> bool {
+        self.capabilities & CAP_PADDING != 0
+    }
 }
 
+/// Something for the client to put on the wire
+#[derive(Debug)]
 pub enum OutgoingMessage {
+    /// Send to every peer with an established session
     Global(PlainMessage),
+    /// Send to one peer by session id
     Direct { target_id: String, message: PlainMessage },
-    /// Send a group message — fan out to each member using pairwise encryption
+    /// Send a group message — encrypted once under our sender key for the group, which
+    /// members who don't have it yet are sent first (a member gone from `member_ids`
+    /// means a new key)
     Group {
         group_id: String,
         member_ids: Vec<String>,
         message: PlainMessage,
     },
-    /// Tell the relay to join a group room
-    JoinRoom { group_id: String },
+    /// Tell the relay to join a group room, presenting the room's join token if it has one
+    JoinRoom { group_id: String, join_token: Option<Vec<u8>> },
     /// Tell the relay to leave a group room
     LeaveRoom { group_id: String },
     /// Send an encrypted audio frame (raw, no PlainMessage overhead)
     Audio { target_id: String, data: Vec<u8> },
+    /// Set up the voice key for a call with `peer_id` from the caller's and callee's
+    /// salts, or (None) wipe it when the call with them ends
+    CallKey { peer_id: String, salts: Option<(CallSalt, CallSalt)> },
     /// Lightweight signal — bypasses ratchet, sent as plaintext
     Signal(crate::protocol::Message),
+    /// Change our nickname: announced to every peer now, and to peers who join later
+    Nickname(String),
+    /// Send the TUI the whole peer list again (after a reconnect)
+    ResyncPeers,
+    /// Close the connection once everything queued before this is on the wire, and stop
+    /// reconnecting. `done` fires after the websocket close has been flushed.
+    Shutdown { done: oneshot::Sender<()> },
 }
 
+/// Connection to a relay under one identity and session id
 pub struct ChatClient {
     identity: Identity,
     relay_url: String,
     session_id: String,
     nickname: Option<String>,
+    reconnect_initial: Duration,
+    reconnect_max: Duration,
+    /// Give up on a connection that isn't set up within this (each of handshake and Ack)
+    setup_timeout: Duration,
+    /// Give up on a connection the relay has gone quiet on (None: never)
+    silence_timeout: Option<Duration>,
+    /// Pad chat to bucket sizes for peers that can read it
+    padding: Padding,
+    /// Reach the relay through this proxy (`--proxy`)
+    proxy: Option<Proxy>,
+    /// Ask the relay to deflate frames (`--compress`)
+    compress: bool,
+    /// Neither send nor take Global chat (`--no-global`)
+    no_global: bool,
+    /// Check on peers this quiet, and stop sending them Global chat if they've gone (None: never)
+    peer_idle: Option<Duration>,
+    /// Where relay keys are pinned (None: for this run only)
+    relay_pins: Option<std::path::PathBuf>,
+    /// The relay fingerprint to insist on (`--expect-relay`)
+    expect_relay: Option<String>,
+    /// All peer sessions (persists across reconnects)
+    peers: PeerMap,
+    counters: std::sync::Arc<stats::Counters>,
+    /// Back off longer between reconnects (low-bandwidth mode); may change while connected
+    slow_reconnect: std::sync::Arc<AtomicBool>,
 }
 
 impl ChatClient {
+    /// Create a client whose session id is derived from the identity key (see
+    /// `set_ephemeral_session`). Nothing connects until `connect()`.
     pub fn new(identity: Identity, relay_url: String, nickname: Option<String>) -> Self {
-        let session_id = generate_session_id();
+        let session_id = session_id_for(&identity.public_key_bytes());
         Self {
             identity,
             relay_url,
             session_id,
             nickname,
+            reconnect_initial: RECONNECT_INITIAL,
+            reconnect_max: RECONNECT_MAX,
+            setup_timeout: SETUP_TIMEOUT,
+            silence_timeout: Some(SILENCE_TIMEOUT),
+            padding: Padding::default(),
+            proxy: None,
+            compress: false,
+            no_global: false,
+            peer_idle: Some(DEFAULT_PEER_IDLE),
+            relay_pins: None,
+            expect_relay: None,
+            peers: PeerMap::default(),
+            counters: Default::default(),
+            slow_reconnect: Default::default(),
         }
     }
 
+    /// Session id peers address us by (stable across reconnects, and across restarts
+    /// unless it's ephemeral)
     pub fn session_id(&self) -> &str {
         &self.session_id
     }
 
+    /// Use a random session id instead of the one derived from the identity key, so the
+    /// relay can't link this session to others under the same identity
+    /// (`--ephemeral-session`). Must be called before `connect()`.
+    pub fn set_ephemeral_session(&mut self) {
+        self.session_id = generate_session_id();
+    }
+
+    /// Our identity public key, base64-encoded
     pub fn identity_id(&self) -> String {
         self.identity.public_key_b64()
     }
 
+    /// Our identity public key as raw bytes
     pub fn identity_public_key_bytes(&self) -> Vec<u8> {
         self.identity.public_key_bytes()
     }
 
+    /// Nickname announced to peers after key exchange
     pub fn nickname(&self) -> Option<&str> {
         self.nickname.as_deref()
     }
 
+    /// Set the nickname announced to peers. Must be called before `connect()`.
     pub fn set_nickname(&mut self, nickname: String) {
         self.nickname = Some(nickname);
     }
 
+    /// Handle for reading traffic, ratchet and reconnect statistics
+    pub fn stats(&self) -> ClientStats {
+        ClientStats { counters: self.counters.clone(), peers: self.peers.clone() }
+    }
+
+    /// Override the reconnect backoff (first delay, and the cap it doubles up to).
+    /// Must be called before `connect()`.
+    pub fn set_reconnect_backoff(&mut self, initial: Duration, max: Duration) {
+        self.reconnect_initial = initial;
+        self.reconnect_max = max.max(initial);
+    }
+
+    /// How long the websocket handshake, and then the relay's Ack, may take before the
+    /// attempt counts as failed. Must be called before `connect()`.
+    pub fn set_setup_timeout(&mut self, timeout: Duration) {
+        self.setup_timeout = timeout;
+    }
+
+    /// Switch for longer reconnect backoff: while it's set, every delay is
+    /// SLOW_RECONNECT_FACTOR times as long. Can be flipped at any time.
+    pub fn slow_reconnect(&self) -> std::sync::Arc<AtomicBool> {
+        self.slow_reconnect.clone()
+    }
+
+    /// Reconnect once the relay has passed on nothing for `timeout` since we sent it
+    /// chat (None: only the ping watchdog). Must be called before `connect()`.
+    pub fn set_silence_timeout(&mut self, timeout: Option<Duration>) {
+        self.silence_timeout = timeout;
+    }
+
+    /// Pad chat plaintexts (or not) before encrypting them; file chunks never are.
+    /// Must be called before `connect()`.
+    pub fn set_padding(&mut self, padding: Padding) {
+        self.padding = padding;
+    }
+
+    /// Connect to the relay through `proxy`. Must be called before `connect()`.
+    pub fn set_proxy(&mut self, proxy: Proxy) {
+        self.proxy = Some(proxy);
+    }
+
+    /// Ask the relay to deflate frames both ways; it may not. Must be called before
+    /// `connect()`.
+    pub fn set_compression(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    /// Check whether peers quiet for `idle` are still connected, and leave the ones
+    /// that aren't out of Global chat (None: never check)
+    pub fn set_peer_idle(&mut self, idle: Option<Duration>) {
+        self.peer_idle = idle;
+    }
+
+    /// Pin the relay's key in `path` on first use and refuse a relay that answers with
+    /// another later (without it, pins last this run only). Must be called before `connect()`.
+    pub fn set_relay_pins(&mut self, path: std::path::PathBuf) {
+        self.relay_pins = Some(path);
+    }
+
+    /// Only use a relay whose key has this fingerprint (`--expect-relay`). Must be
+    /// called before `connect()`.
+    pub fn expect_relay(&mut self, fingerprint: String) {
+        self.expect_relay = Some(fingerprint);
+    }
+
+    /// Refuse to send Global chat and drop any that arrives, for people who only use
+    /// DMs and groups. Must be called before `connect()`.
+    pub fn set_no_global(&mut self) {
+        self.no_global = true;
+    }
+
+    /// Start the connection loop in the background and return its channels:
+    /// outgoing queue, decrypted messages, status updates, peer list updates and
+    /// decrypted audio frames. Lost connections are re-established automatically.
     pub async fn connect(&mut self) -> Result<(
-        mpsc::UnboundedSender<OutgoingMessage>,
+        OutgoingSender,
         mpsc::UnboundedReceiver<PlainMessage>,
-        mpsc::UnboundedReceiver<String>, // Status messages
-        mpsc::UnboundedReceiver<HashMap<String, PeerDisplay>>, // Peer updates
-        mpsc::UnboundedReceiver<(String, Vec<u8>)>, // Incoming audio frames (peer_id, decrypted_opus_data)
+        mpsc::UnboundedReceiver<ClientStatus>, // Connection state and events
+        mpsc::UnboundedReceiver<Vec<PeerUpdate>>, // Peer list changes, in batches
+        mpsc::UnboundedReceiver<(String, String, Vec<u8>)>, // Incoming audio frames (peer_id, call_id, decrypted_opus_data)
     )> {
         // Channels for communication with TUI (persist across reconnects)
-        let (msg_tx, msg_rx) = mpsc::unbounded_channel::<OutgoingMessage>();
         let (incoming_tx, incoming_rx) = mpsc::unbounded_channel::<PlainMessage>();
-        let (status_tx, status_rx) = mpsc::unbounded_channel::<String>();
-        let (peer_update_tx, peer_update_rx) = mpsc::unbounded_channel::<HashMap<String, PeerDisplay>>();
-        let (audio_in_tx, audio_in_rx) = mpsc::unbounded_channel::<(String, Vec<u8>)>();
+        let (status_tx, status_rx) = mpsc::unbounded_channel::<ClientStatus>();
+        let (msg_tx, msg_rx) = outbox::outbox(status_tx.clone());
+        let (peer_update_tx, peer_update_rx) = mpsc::unbounded_channel::<Vec<PeerUpdate>>();
+        let peers_changed = std::sync::Arc::new(PeerChanges::default());
+        let (audio_in_tx, audio_in_rx) = mpsc::unbounded_channel::<(String, String, Vec<u8>)>();
 
         let identity = self.identity.clone_for_thread();
         let session_id = self.session_id.clone();
         let public_key_bytes = self.identity.public_key_bytes();
-        let my_nickname = self.nickname.clone();
+        let my_nickname: SharedNickname = std::sync::Arc::new(std::sync::RwLock::new(self.nickname.clone()));
         let relay_url = self.relay_url.clone();
+        let proxy = self.proxy.clone();
+        let (reconnect_initial, reconnect_max) = (self.reconnect_initial, self.reconnect_max);
+        let setup_timeout = self.setup_timeout;
+        let silence_timeout = self.silence_timeout;
+        let padding = self.padding;
+        let compress = self.compress;
+        let no_global = self.no_global;
+        let peer_idle = self.peer_idle;
+        let slow_reconnect = self.slow_reconnect.clone();
+        let relay_pins: SharedRelayPins = std::sync::Arc::new(std::sync::Mutex::new(
+            RelayPins::load(self.relay_pins.clone(), self.expect_relay.clone()),
+        ));
         
-        // Track all peers (persists across reconnects)
-        let peers = std::sync::Arc::new(tokio::sync::RwLock::new(HashMap::<String, PeerInfo>::new()));
+        let peers = self.peers.clone();
+        let counters = self.counters.clone();
+        let group_keys = SharedGroupKeys::default();
+        let call_keys = SharedCallKeys::default();
+        let inflight = SharedInflight::default();
+        let rooms = SharedRooms::default();
         
         // Wrap receiver in Arc<Mutex> so it can be shared across reconnection attempts
         let msg_rx = std::sync::Arc::new(tokio::sync::Mutex::new(msg_rx));
 
+        // Coalesce peer list changes: a burst of joins and nicknames becomes one TUI update
+        spawn_peer_updates(peers.clone(), peers_changed.clone(), peer_update_tx);
+
         // Spawn reconnection loop
         let peers_reconnect = peers.clone();
         let status_tx_reconnect = status_tx.clone();
         let audio_in_tx_reconnect = audio_in_tx.clone();
         tokio::spawn(async move {
-            let mut reconnect_delay = 1u64;
+            let mut reconnect_delay = reconnect_initial;
             let mut attempt = 0u32;
             
             loop {
+                if attempt == 0 {
+                    let _ = status_tx_reconnect.send(ConnectionState::Connecting.into());
+                }
                 // Attempt connection
                 match Self::establish_connection(
                     &relay_url,
+                    proxy.as_ref(),
                     &session_id,
                     &public_key_bytes,
                     &identity,
                     &my_nickname,
                     peers_reconnect.clone(),
+                    group_keys.clone(),
+                    call_keys.clone(),
+                    inflight.clone(),
+                    rooms.clone(),
                     msg_rx.clone(),
                     incoming_tx.clone(),
                     status_tx_reconnect.clone(),
-                    peer_update_tx.clone(),
+                    peers_changed.clone(),
                     audio_in_tx_reconnect.clone(),
+                    counters.clone(),
+                    setup_timeout,
+                    silence_timeout,
+                    padding,
+                    compress,
+                    no_global,
+                    peer_idle,
+                    relay_pins.clone(),
                     attempt,
                 ).await {
                     Ok(_) => {
-                        // Connection ended gracefully, reset backoff
-                        reconnect_delay = 1;
-                        attempt = 0;
+                        // Closed on request (OutgoingMessage::Shutdown)
+                        let _ = status_tx_reconnect.send(ConnectionState::Disconnected.into());
+                        break;
                     }
-                    Err(_e) => {
+                    Err(e) => {
+                        // Not the relay we know: trying again would only reach it again
+                        if let Some(mismatch) = e.downcast_ref::<RelayMismatch>() {
+                            let _ = status_tx_reconnect.send(ConnectionState::Refused { reason: mismatch.to_string() }.into());
+                            break;
+                        }
+                        // Some refusals would only come again; a full relay gets a long wait
+                        let refusal = e.downcast_ref::<Refusal>();
+                        if let Some(refusal) = refusal.filter(|r| r.code.is_fatal()) {
+                            let _ = status_tx_reconnect.send(ConnectionState::Refused { reason: refusal.advice() }.into());
+                            break;
+                        }
+                        // The proxy failing is told apart from the relay failing
+                        let proxy_error = e.downcast_ref::<ProxyError>();
+                        if let Some(proxy_error) = proxy_error.filter(|p| p.is_fatal()) {
+                            let _ = status_tx_reconnect.send(ConnectionState::ProxyFailed { reason: proxy_error.to_string(), next_retry_in: None }.into());
+                            break;
+                        }
+                        if refusal.is_some_and(|r| r.code == ErrorCode::SessionLimit) {
+                            reconnect_delay = reconnect_max;
+                        }
+                        let wait = match slow_reconnect.load(Ordering::Relaxed) {
+                            true => reconnect_delay * SLOW_RECONNECT_FACTOR,
+                            false => reconnect_delay,
+                        };
                         attempt += 1;
-                        let _ = status_tx_reconnect.send(format!(
-                            "Connection lost, reconnecting (attempt {})...",
-                            attempt
-                        ));
+                        counters.reconnected();
+                        let setup_timeout = e.downcast_ref::<SetupTimeout>().copied();
+                        let _ = status_tx_reconnect.send(match setup_timeout {
+                            Some(timeout) => format!("⏱️ Gave up on the relay: {}, reconnecting (attempt {})...", timeout, attempt),
+                            None => format!("Connection lost, reconnecting (attempt {})...", attempt),
+                        }.into());
+                        let state = match (proxy_error, setup_timeout) {
+                            (Some(proxy_error), _) => ConnectionState::ProxyFailed {
+                                reason: proxy_error.to_string(),
+                                next_retry_in: Some(wait),
+                            },
+                            (None, Some(timeout)) => ConnectionState::Unresponsive { timeout, attempt, next_retry_in: wait },
+                            (None, None) => ConnectionState::Reconnecting { attempt, next_retry_in: wait },
+                        };
+                        let _ = status_tx_reconnect.send(state.into());
                         
-                        // Exponential backoff: 1s, 2s, 4s, 8s, max 30s
-                        sleep(Duration::from_secs(reconnect_delay)).await;
-                        reconnect_delay = (reconnect_delay * 2).min(30);
+                        // Exponential backoff: 1s, 2s, 4s, 8s, max 30s (by default)
+                        sleep(wait).await;
+                        reconnect_delay = (reconnect_delay * 2).min(reconnect_max);
                     }
                 }
             }
@@ -159,77 +457,227 @@ impl ChatClient {
 
     async fn establish_connection(
         relay_url: &str,
+        proxy: Option<&Proxy>,
         session_id: &str,
         public_key_bytes: &[u8],
         identity: &Identity,
-        my_nickname: &Option<String>,
-        peers: std::sync::Arc<tokio::sync::RwLock<HashMap<String, PeerInfo>>>,
-        outgoing_rx: std::sync::Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<OutgoingMessage>>>,
+        my_nickname: &SharedNickname,
+        peers: PeerMap,
+        group_keys: SharedGroupKeys,
+        call_keys: SharedCallKeys,
+        inflight: SharedInflight,
+        rooms: SharedRooms,
+        outgoing_rx: std::sync::Arc<tokio::sync::Mutex<OutgoingReceiver>>,
         incoming_tx: mpsc::UnboundedSender<PlainMessage>,
-        status_tx: mpsc::UnboundedSender<String>,
-        peer_update_tx: mpsc::UnboundedSender<HashMap<String, PeerDisplay>>,
-        audio_in_tx: mpsc::UnboundedSender<(String, Vec<u8>)>,
+        status_tx: StatusSender,
+        peers_changed: std::sync::Arc<PeerChanges>,
+        audio_in_tx: mpsc::UnboundedSender<(String, String, Vec<u8>)>,
+        counters: std::sync::Arc<stats::Counters>,
+        setup_timeout: Duration,
+        silence_timeout: Option<Duration>,
+        padding: Padding,
+        compress: bool,
+        no_global: bool,
+        peer_idle: Option<Duration>,
+        relay_pins: SharedRelayPins,
         attempt: u32,
     ) -> Result<()> {
-        // Connect to relay
-        let (ws_stream, _) = connect_async(relay_url)
+        // Connect to relay. The relay (or anyone posing as it) can't push oversized frames at us.
+        let config = WebSocketConfig {
+            max_message_size: Some(MAX_MESSAGE_SIZE),
+            max_frame_size: Some(MAX_MESSAGE_SIZE),
+            ..Default::default()
+        };
+        let mut request = relay_url.into_client_request().context("Invalid relay URL")?;
+        if compress {
+            request.headers_mut().insert(codec::COMPRESS_HEADER, HeaderValue::from_static(codec::COMPRESS_DEFLATE));
+        }
+        // A blackholed host would otherwise hold the attempt for as long as TCP keeps trying
+        let handshake = async {
+            match proxy {
+                Some(proxy) => {
+                    let (host, port) = proxy::relay_target(relay_url)?;
+                    let tunnel = proxy.connect(&host, port).await?;
+                    client_async_tls_with_config(request, tunnel, Some(config), None)
+                        .await
+                        .context("Failed to connect to relay")
+                }
+                None => connect_async_with_config(request, Some(config), false)
+                    .await
+                    .context("Failed to connect to relay"),
+            }
+        };
+        let (ws_stream, response) = tokio::time::timeout(setup_timeout, handshake)
             .await
-            .context("Failed to connect to relay")?;
+            .map_err(|_| SetupTimeout::Unreachable)??;
+        // Relays that don't deflate leave the header out of their answer
+        let deflating = compress && response.headers().get(codec::COMPRESS_HEADER).is_some_and(|v| v == codec::COMPRESS_DEFLATE);
+        counters.set_compression(match (compress, deflating) {
+            (false, _) => Compression::Off,
+            (true, false) => Compression::Refused,
+            (true, true) => Compression::On,
+        });
 
-        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+        let (ws_sender, mut ws_receiver) = ws_stream.split();
+        // Every frame we write is deflated if agreed, and counted on its way out
+        let counters_out = counters.clone();
+        let mut ws_sender = ws_sender.with(move |frame: WsMessage| {
+            let frame_len = frame.len();
+            let frame = match frame {
+                WsMessage::Binary(data) if deflating => WsMessage::Binary(codec::deflate(data)),
+                frame => frame,
+            };
+            counters_out.frame_sent(frame.len(), frame_len);
+            futures_util::future::ready(Ok::<_, tokio_tungstenite::tungstenite::Error>(frame))
+        });
 
-        // Send connect message with same session_id (for session resumption)
+        // Send connect message with same session_id (for session resumption), and a
+        // challenge for the relay to prove it's the one we know
+        let challenge = RelayChallenge::default();
         let connect_msg = Message::Connect {
             session_id: session_id.to_string(),
+            challenge: challenge.bytes(),
         };
-        let data = bincode::serialize(&connect_msg)?;
+        let data = codec::encode(&connect_msg)?;
         ws_sender.send(WsMessage::Binary(data)).await?;
 
         // Send key exchange to re-establish E2EE with all peers.
         // Initial broadcast has no dh_ratchet_key (ratchet doesn't exist yet).
         // The reply KE (sent after ratchet creation) will include our ratchet DH key.
+        // Holding no sessions at all, we may be a restart under the same session id:
+        // `reset` tells peers to drop whatever session they still have with us.
         let key_exchange_msg = Message::KeyExchange {
             from: session_id.to_string(),
             public_key: public_key_bytes.to_vec(),
             dh_ratchet_key: vec![],
+            target: String::new(),
+            reset: peers.read().await.is_empty(),
+            capabilities: CAPABILITIES,
         };
-        let ke_data = bincode::serialize(&key_exchange_msg)?;
+        let ke_data = codec::encode(&key_exchange_msg)?;
         ws_sender.send(WsMessage::Binary(ke_data)).await?;
 
-        // Channels for internal communication
+        // The relay dropped us from our rooms along with the last connection; back in
+        // before anything queued for them goes out
+        let (rejoins, mut rejoined) = {
+            let rooms = rooms.lock().unwrap();
+            (rooms.rejoin(session_id), rooms.group_ids())
+        };
+        for join_msg in rejoins {
+            ws_sender.send(WsMessage::Binary(codec::encode(&join_msg)?)).await?;
+        }
+
+        // Frames the receiver needs sent (key exchange replies and the nickname that follows them)
         let (ke_reply_tx, mut ke_reply_rx) = mpsc::unbounded_channel::<Vec<u8>>();
-        let (nickname_tx, mut nickname_rx) = mpsc::unbounded_channel::<(String, Vec<u8>)>();
         let (pong_tx, mut pong_rx) = mpsc::unbounded_channel::<()>();
 
-        // Channels for signaling connection failure
+        // Channels for signaling connection failure, or a requested shutdown
         let (failure_tx, mut failure_rx) = mpsc::unbounded_channel::<String>();
+        let (refused_tx, mut refused_rx) = mpsc::unbounded_channel::<Refusal>();
+        let (mismatch_tx, mut mismatch_rx) = mpsc::unbounded_channel::<RelayMismatch>();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        // Fired by the relay's Ack; until then the connection isn't set up
+        let (acked_tx, acked_rx) = oneshot::channel::<()>();
 
         // Spawn receiver task
         let peers_recv = peers.clone();
+        let call_keys_recv = call_keys.clone();
+        let group_keys_recv = group_keys.clone();
         let status_tx_recv = status_tx.clone();
+        let relay_url_recv = relay_url.to_string();
         let session_id_recv = session_id.to_string();
         let public_key_bytes_recv = public_key_bytes.to_vec();
         let my_nickname_recv = my_nickname.clone();
         let identity_recv = identity.clone_for_thread();
         let pong_tx_clone = pong_tx.clone();
         let failure_tx_recv = failure_tx.clone();
+        let counters_recv = counters.clone();
+        let inflight_recv = inflight.clone();
+        // The sender task needs it after the receiver has taken the original
+        let peers_changed_send = peers_changed.clone();
         
-        tokio::spawn(async move {
+        let recv_task = tokio::spawn(async move {
+            let mut acked_tx = Some(acked_tx);
             while let Some(msg) = ws_receiver.next().await {
                 match msg {
                     Ok(WsMessage::Binary(data)) => {
-                        if let Ok(message) = bincode::deserialize::<Message>(&data) {
+                        let wire_len = data.len();
+                        // Deflated frames only on a connection that agreed to them
+                        // (anything else is left empty, and so not decoded)
+                        let data = match codec::inflate(&data) {
+                            Ok(Cow::Owned(inflated)) if deflating => inflated,
+                            Ok(Cow::Borrowed(_)) => data,
+                            _ => Vec::new(),
+                        };
+                        let frame_len = data.len();
+                        counters_recv.frame_received(wire_len, frame_len);
+                        if let Ok(message) = codec::decode(&data) {
                             match message {
-                                Message::Ack => {
+                                Message::Ack { relay_key, proof } => {
+                                    let checked = relay_pins.lock().unwrap().check(&relay_url_recv, &challenge, &session_id_recv, &relay_key, &proof);
+                                    match checked {
+                                        Ok(Checked::Known) => {}
+                                        Ok(Checked::FirstSeen { fingerprint }) => {
+                                            let _ = status_tx_recv.send(format!("📌 First time on this relay; pinned its key {}", fingerprint).into());
+                                        }
+                                        Ok(Checked::Anonymous) if attempt == 0 => {
+                                            let _ = status_tx_recv.send("⚠️ This relay can't prove which relay it is (older version), so a swap would go unnoticed".into());
+                                        }
+                                        Ok(Checked::Anonymous) => {}
+                                        Err(mismatch) => {
+                                            let _ = status_tx_recv.send(mismatch.to_string().into());
+                                            let _ = mismatch_tx.send(mismatch);
+                                            break;
+                                        }
+                                    }
+                                    if let Some(acked_tx) = acked_tx.take() {
+                                        let _ = acked_tx.send(());
+                                    }
+                                    let relay = relay_host(&relay_url_recv).to_string();
+                                    let _ = status_tx_recv.send(ConnectionState::Connected { relay }.into());
                                     if attempt == 0 {
-                                        let _ = status_tx_recv.send("Connected to relay".to_string());
+                                        let _ = status_tx_recv.send("Connected to relay".into());
                                     } else {
-                                        let _ = status_tx_recv.send("Reconnected".to_string());
+                                        let _ = status_tx_recv.send("Reconnected".into());
+                                    }
+                                    if !rejoined.is_empty() {
+                                        let _ = status_tx_recv.send(ClientStatus::RoomsRejoined { group_ids: std::mem::take(&mut rejoined) });
                                     }
                                 }
-                                Message::KeyExchange { from, public_key, dh_ratchet_key } => {
-                                    if from == session_id_recv {
-                                        continue; // Ignore our own key exchange
+                                Message::KeyExchange { from, public_key, dh_ratchet_key, target, reset, capabilities } => {
+                                    if from == session_id_recv || !(target.is_empty() || target == session_id_recv) {
+                                        continue; // Ignore our own key exchange, and ones meant for someone else
+                                    }
+
+                                    // A peer whose session with us stopped working offers a new one
+                                    let mut resumed = false;
+                                    if reset {
+                                        let mut peers_map = peers_recv.write().await;
+                                        if let Some(peer_info) = peers_map.get_mut(&from) {
+                                            if peer_info.public_key != public_key {
+                                                continue;
+                                            }
+                                            // ...or has restarted, and has no session with us at all:
+                                            // start a new one below, as with a peer we've never met
+                                            if dh_ratchet_key.is_empty() && target.is_empty() {
+                                                resumed = peers_map.remove(&from).is_some_and(|old| old.health.opened_within(RESUME_WINDOW));
+                                            } else {
+                                                match rekey::on_reset_offer(&identity_recv, &session_id_recv, &from, peer_info, &dh_ratchet_key) {
+                                                    Ok(frames) if frames.is_empty() => {
+                                                        let _ = status_tx_recv.send(format!("⚠️ Ignored session reset from {} (too many this hour)", short_id(&from)).into());
+                                                    }
+                                                    Ok(frames) => {
+                                                        for frame in frames {
+                                                            let _ = ke_reply_tx.send(frame);
+                                                        }
+                                                    }
+                                                    Err(e) => {
+                                                        let _ = status_tx_recv.send(format!("❌ Session reset with {} failed: {:#}", short_id(&from), e).into());
+                                                    }
+                                                }
+                                                continue;
+                                            }
+                                        }
                                     }
                                     
                                     // Perform key exchange
@@ -237,6 +685,9 @@ impl ChatClient {
                                         Ok(secret) => {
                                             let mut peers_map = peers_recv.write().await;
                                             let is_new_peer = !peers_map.contains_key(&from);
+                                            // Back under a new session id (--ephemeral-session) counts as resuming too
+                                            let resumed = resumed || peers_map.values()
+                                                .any(|p| p.public_key == public_key && p.health.opened_within(RESUME_WINDOW));
                                             
                                             // Determine role: lower session_id = Alice (initiates DH ratchet)
                                             let is_alice = session_id_recv < from;
@@ -257,36 +708,44 @@ impl ChatClient {
                                                     ratchet,
                                                     nickname: None,
                                                     public_key: public_key.clone(),
+                                                    health: SessionHealth::default(),
+                                                    capabilities,
+                                                    activity: Activity::default(),
                                                 });
                                             } else {
                                                 // Already have a ratchet for this peer.
                                                 // Don't re-create (would reset state and desync),
                                                 // but DO process the dh_ratchet_key if present —
                                                 // this is the reply KE carrying the peer's initial DH key.
-                                                if dh_ratchet_key.len() == 32 {
-                                                    if let Some(peer_info) = peers_map.get_mut(&from) {
+                                                if let Some(peer_info) = peers_map.get_mut(&from) {
+                                                    if dh_ratchet_key.len() == 32 {
                                                         let mut key = [0u8; 32];
                                                         key.copy_from_slice(&dh_ratchet_key);
                                                         peer_info.ratchet.set_remote_dh(key);
                                                     }
+                                                    // They may have restarted with another version
+                                                    peer_info.capabilities = capabilities;
+                                                    // Back from being inactive, on the session they had
+                                                    if peer_info.activity.seen() {
+                                                        peers_changed.mark(&from);
+                                                    }
                                                 }
                                                 continue;
                                             }
                                             
-                                            let _ = status_tx_recv.send(format!("🔐 Double Ratchet session established with {}", &from[..12]));
+                                            let _ = status_tx_recv.send(format!("🔐 Double Ratchet session established with {}", short_id(&from)).into());
                                             
                                             // Send peer display update (no crypto state)
-                                            let display_map: HashMap<String, PeerDisplay> = peers_map.iter()
-                                                .map(|(k, v)| (k.clone(), PeerDisplay { nickname: v.nickname.clone(), public_key: v.public_key.clone() }))
-                                                .collect();
-                                            let _ = peer_update_tx.send(display_map);
+                                            peers_changed.mark(&from);
                                             
-                                            // Show join notification
-                                            if is_new_peer {
-                                                let join_msg = PlainMessage::system(
+                                            // Show join notification, unless they were only away briefly
+                                            if is_new_peer && !resumed {
+                                                let mut join_msg = PlainMessage::notice(
                                                     from.clone(),
-                                                    format!("{} has joined", &from[..12]),
+                                                    Key::Joined,
+                                                    &[("name", short_id(&from))],
                                                 );
+                                                strings::localize(&mut join_msg);
                                                 let _ = incoming_tx.send(join_msg);
                                             }
 
@@ -300,48 +759,37 @@ impl ChatClient {
                                                     from: session_id_recv.clone(),
                                                     public_key: public_key_bytes_recv.clone(),
                                                     dh_ratchet_key: our_dh_key,
+                                                    target: from.clone(),
+                                                    reset: false,
+                                                    capabilities: CAPABILITIES,
                                                 };
-                                                if let Ok(reply_data) = bincode::serialize(&reply) {
+                                                if let Ok(reply_data) = codec::encode(&reply) {
                                                     let _ = ke_reply_tx.send(reply_data);
                                                 }
                                                 
-                                                // Send our nickname after key exchange (using ratchet)
-                                                if let Some(ref nick) = my_nickname_recv {
-                                                    let nick = nick.clone();
-                                                    let session_id_nick = session_id_recv.clone();
-                                                    let nickname_tx_clone = nickname_tx.clone();
-                                                    let from_clone = from.clone();
-                                                    let peers_nick = peers_recv.clone();
-                                                    tokio::spawn(async move {
-                                                        tokio::time::sleep(Duration::from_millis(500)).await;
-                                                        let nickname_msg = PlainMessage::nickname(
-                                                            session_id_nick.clone(),
-                                                            nick,
-                                                        );
-                                                        if let Ok(serialized) = rmp_serde::to_vec(&nickname_msg) {
-                                                            let mut peers_map = peers_nick.write().await;
-                                                            if let Some(peer) = peers_map.get_mut(&from_clone) {
-                                                                if let Ok((header, nonce, ciphertext)) = peer.ratchet.encrypt(&serialized) {
-                                                                    let header_bytes = bincode::serialize(&header).unwrap_or_default();
-                                                                    let encrypted_msg = Message::Encrypted {
-                                                                        from: session_id_nick,
-                                                                        target: from_clone.clone(),
-                                                                        header: header_bytes,
-                                                                        nonce,
-                                                                        ciphertext,
-                                                                    };
-                                                                    if let Ok(data) = bincode::serialize(&encrypted_msg) {
-                                                                        let _ = nickname_tx_clone.send((from_clone, data));
-                                                                    }
-                                                                }
-                                                            }
-                                                        }
+                                                // Our nickname goes out right behind the reply, on the same queue, so
+                                                // the relay delivers it after the key exchange it depends on
+                                                let nick = my_nickname_recv.read().unwrap().clone();
+                                                if let Some(nick) = nick {
+                                                    let nickname_msg = PlainMessage::nickname(session_id_recv.clone(), nick);
+                                                    let sealed = encode_plain(&nickname_msg).and_then(|serialized| {
+                                                        let peer = peers_map.get_mut(&from).context("peer vanished")?;
+                                                        let padded = pads(padding, &nickname_msg) && peer.reads_padding();
+                                                        seal_frame(&mut peer.ratchet, &session_id_recv, &from, &serialized, padded)
                                                     });
+                                                    match sealed {
+                                                        Ok(data) => {
+                                                            let _ = ke_reply_tx.send(data);
+                                                        }
+                                                        Err(e) => {
+                                                            let _ = status_tx_recv.send(format!("❌ Nickname not sent to {}: {:#}", short_id(&from), e).into());
+                                                        }
+                                                    }
                                                 }
                                             }
                                         }
                                         Err(e) => {
-                                            let _ = status_tx_recv.send(format!("❌ Key exchange failed: {}", e));
+                                            let _ = status_tx_recv.send(format!("❌ Key exchange failed: {}", e).into());
                                         }
                                     }
                                 }
@@ -354,47 +802,93 @@ impl ChatClient {
                                     if let Some(peer_info) = peers_map.get_mut(&from) {
                                         // Decrypt using Double Ratchet if header present, else fallback to static key
                                         let plaintext = if !header.is_empty() {
-                                            match bincode::deserialize::<RatchetHeader>(&header) {
+                                            match decode_bincode::<RatchetHeader>(&header) {
                                                 Ok(ratchet_header) => {
-                                                    match peer_info.ratchet.decrypt(&ratchet_header, &nonce, &ciphertext) {
-                                                        Ok(pt) => Some(pt),
-                                                        Err(e) => {
-                                                            let _ = status_tx_recv.send(format!("⚠️ Ratchet decrypt failed from {}: {}", &from[..12], e));
+                                                    match rekey::decrypt(peer_info, &ratchet_header, &nonce, &ciphertext) {
+                                                        Decrypted::Current(pt) => Some(pt),
+                                                        Decrypted::Reset(pt) => {
+                                                            let name = peer_info.nickname.clone().unwrap_or_else(|| short_id(&from).to_string());
+                                                            let text = format!("🔐 Secure session with {} was re-established", name);
+                                                            let _ = status_tx_recv.send(text.clone().into());
+                                                            let notice = PlainMessage { direct: true, ..PlainMessage::system(from.clone(), text) };
+                                                            let _ = incoming_tx.send(notice);
+                                                            Some(pt)
+                                                        }
+                                                        Decrypted::Failed { error, offer_reset } => {
+                                                            let _ = status_tx_recv.send(format!("⚠️ Ratchet decrypt failed from {}: {}", short_id(&from), error).into());
+                                                            if offer_reset {
+                                                                // Persistent failures: offer the peer a new session
+                                                                match rekey::offer_reset(&identity_recv, &session_id_recv, &from, peer_info) {
+                                                                    Ok(offer) => {
+                                                                        let _ = ke_reply_tx.send(offer);
+                                                                        let _ = status_tx_recv.send(format!("🔄 Re-keying session with {}", short_id(&from)).into());
+                                                                    }
+                                                                    Err(e) => {
+                                                                        let _ = status_tx_recv.send(format!("❌ Session reset with {} failed: {:#}", short_id(&from), e).into());
+                                                                    }
+                                                                }
+                                                            }
                                                             None
                                                         }
                                                     }
                                                 }
                                                 Err(e) => {
-                                                    let _ = status_tx_recv.send(format!("⚠️ Header deserialize failed from {}: {}", &from[..12], e));
+                                                    let _ = status_tx_recv.send(format!("⚠️ Header deserialize failed from {}: {}", short_id(&from), e).into());
                                                     None
                                                 }
                                             }
                                         } else {
-                                            let _ = status_tx_recv.send(format!("⚠️ Empty header from {} (legacy?)", &from[..12]));
+                                            let _ = status_tx_recv.send(format!("⚠️ Empty header from {} (legacy?)", short_id(&from)).into());
                                             None
                                         };
                                         
                                         if let Some(plaintext) = plaintext {
-                                            if let Ok(plain_msg) = rmp_serde::from_slice::<PlainMessage>(&plaintext)
-                                                .or_else(|_| bincode::deserialize::<PlainMessage>(&plaintext)) {
-                                                // Handle nickname updates
-                                                if plain_msg.system && plain_msg.nickname.is_some() {
+                                            if peer_info.activity.seen() {
+                                                peers_changed.mark(&from);
+                                            }
+                                            if let Some(plain_msg) = open_plaintext(&plaintext, &from, &status_tx_recv) {
+                                                if plain_msg.is_goodbye() && peer_info.activity.gone() {
+                                                    peers_changed.mark(&from);
+                                                }
+                                                if let Some(kind) = Traffic::of(&plain_msg) {
+                                                    counters_recv.kind_received(kind, frame_len);
+                                                }
+                                                if plain_msg.session_reset {
+                                                    // Only there to confirm a new session, which opening it just did
+                                                } else if let Some(update) = plain_msg.sender_key {
+                                                    // Group key housekeeping never reaches the TUI
+                                                    let replies = on_sender_key_update(
+                                                        &group_keys_recv,
+                                                        &session_id_recv,
+                                                        &from,
+                                                        update,
+                                                        &mut peer_info.ratchet,
+                                                        &incoming_tx,
+                                                        &status_tx_recv,
+                                                    );
+                                                    for data in replies {
+                                                        let _ = ke_reply_tx.send(data);
+                                                    }
+                                                } else if plain_msg.system && plain_msg.nickname.is_some() {
+                                                    // Nickname update
                                                     let new_nick = plain_msg.nickname.clone().unwrap();
                                                     let old_nick = peer_info.nickname.clone();
                                                     peer_info.nickname = Some(new_nick.clone());
-                                                    let display_map: HashMap<String, PeerDisplay> = peers_map.iter()
-                                                        .map(|(k, v)| (k.clone(), PeerDisplay { nickname: v.nickname.clone(), public_key: v.public_key.clone() }))
-                                                        .collect();
-                                                    let _ = peer_update_tx.send(display_map);
                                                     drop(peers_map);
-                                                    let display = old_nick.unwrap_or_else(|| from[..12.min(from.len())].to_string());
+                                                    peers_changed.mark(&from);
+                                                    let display = old_nick.unwrap_or_else(|| short_id(&from).to_string());
                                                     let notify = PlainMessage::system(
                                                         from.clone(),
                                                         format!("{} is now known as {}", display, new_nick),
                                                     );
                                                     let _ = incoming_tx.send(notify);
+                                                } else if no_global && plain_msg.is_global_chat() {
+                                                    // Lobby-less: Global chat isn't wanted here
                                                 } else {
                                                     drop(peers_map);
+                                                    if let Some(ack) = delivery_ack(&session_id_recv, &from, &plain_msg) {
+                                                        let _ = ke_reply_tx.send(ack);
+                                                    }
                                                     let _ = incoming_tx.send(plain_msg);
                                                 }
                                             }
@@ -406,40 +900,66 @@ impl ChatClient {
                                         continue; // Ignore our own messages
                                     }
                                     
-                                    // Try to decrypt with sender's pairwise ratchet
-                                    let mut peers_map = peers_recv.write().await;
-                                    if let Some(peer_info) = peers_map.get_mut(&from) {
-                                        let plaintext = if !header.is_empty() {
-                                            if let Ok(ratchet_header) = bincode::deserialize::<RatchetHeader>(&header) {
-                                                peer_info.ratchet.decrypt(&ratchet_header, &nonce, &ciphertext).ok()
-                                            } else {
-                                                None
+                                    let header = match decode_bincode::<SenderKeyHeader>(&header) {
+                                        Ok(header) => header,
+                                        Err(e) => {
+                                            let _ = status_tx_recv.send(format!("⚠️ Group header deserialize failed from {}: {}", short_id(&from), e).into());
+                                            continue;
+                                        }
+                                    };
+                                    let opened = group_keys_recv.lock().unwrap().open(&group_id, &from, header, nonce, ciphertext);
+                                    match opened {
+                                        Opened::Plaintext(plaintext) => {
+                                            let mut acks = Vec::new();
+                                            if let Some(kind) = deliver_group_message(&plaintext, &session_id_recv, &from, &group_id, &incoming_tx, &status_tx_recv, &mut acks) {
+                                                counters_recv.kind_received(kind, frame_len);
                                             }
-                                        } else {
-                                            None
-                                        };
-                                        
-                                        if let Some(plaintext) = plaintext {
-                                            if let Ok(mut plain_msg) = rmp_serde::from_slice::<PlainMessage>(&plaintext)
-                                                .or_else(|_| bincode::deserialize::<PlainMessage>(&plaintext)) {
-                                                plain_msg.group_id = Some(group_id);
-                                                drop(peers_map);
-                                                let _ = incoming_tx.send(plain_msg);
+                                            for ack in acks {
+                                                let _ = ke_reply_tx.send(ack);
+                                            }
+                                        }
+                                        Opened::Failed(e) => {
+                                            let _ = status_tx_recv.send(format!("⚠️ Group decrypt failed from {}: {}", short_id(&from), e).into());
+                                        }
+                                        Opened::MissingKey(header) => {
+                                            // The frame waits while we ask the sender for their key, once,
+                                            // over our pairwise session (none yet: their next key reaches us)
+                                            let mut peers_map = peers_recv.write().await;
+                                            let Some(peer_info) = peers_map.get_mut(&from) else {
+                                                continue;
+                                            };
+                                            if !group_keys_recv.lock().unwrap().should_request(&group_id, &from, header.key_id) {
+                                                continue;
+                                            }
+                                            let request = SenderKeyUpdate::Request {
+                                                group_id,
+                                                key_id: header.key_id,
+                                                iteration: header.iteration,
+                                            };
+                                            let sealed = encode_plain(&PlainMessage::sender_key(session_id_recv.clone(), request))
+                                                .and_then(|serialized| seal_frame(&mut peer_info.ratchet, &session_id_recv, &from, &serialized, false));
+                                            match sealed {
+                                                Ok(data) => {
+                                                    let _ = ke_reply_tx.send(data);
+                                                }
+                                                Err(e) => {
+                                                    let _ = status_tx_recv.send(format!("❌ Group key request not sent to {}: {:#}", short_id(&from), e).into());
+                                                }
                                             }
                                         }
                                     }
                                 }
-                                Message::AudioFrame { from, nonce, ciphertext } => {
+                                Message::AudioFrame { from, nonce, ciphertext, call_id } => {
                                     if from == session_id_recv {
                                         continue;
                                     }
-                                    // Decrypt audio frame with cached voice key — low latency path
-                                    let mut peers_map = peers_recv.write().await;
-                                    if let Some(peer_info) = peers_map.get_mut(&from) {
-                                        let voice_key = peer_info.ratchet.derive_voice_key();
-                                        if let Ok(opus_data) = decrypt_message(&voice_key, &nonce, &ciphertext) {
-                                            let _ = audio_in_tx.send((from, opus_data));
-                                        }
+                                    // Low latency path: no peer map, just the call's voice key.
+                                    // No call with them, no key, or a frame from an earlier
+                                    // call: the frame is dropped.
+                                    if let Some((call_id, opus_data)) = open_audio(&call_keys_recv, &from, &call_id, &nonce, &ciphertext) {
+                                        counters_recv.audio_received();
+                                        counters_recv.kind_received(Traffic::Voice, frame_len);
+                                        let _ = audio_in_tx.send((from, call_id, opus_data));
                                     }
                                 }
                                 Message::Typing { from, target: _, is_typing } => {
@@ -454,6 +974,51 @@ impl ChatClient {
                                     let msg = PlainMessage::read_receipt(from, message_id, false);
                                     let _ = incoming_tx.send(msg);
                                 }
+                                Message::Error { message, code, session } => {
+                                    let message = sanitize_text(&message, false);
+                                    if let (ErrorCode::PeerNotFound, Some(session_id)) = (code, session) {
+                                        let session_id = sanitize_text(&session_id, false);
+                                        // The answer to our check on a quiet peer: they've gone
+                                        if lifecycle::not_found(&mut *peers_recv.write().await, &session_id) {
+                                            peers_changed.mark(&session_id);
+                                            continue;
+                                        }
+                                        let _ = status_tx_recv.send(ClientStatus::PeerNotFound { session_id });
+                                    } else if code.closes_connection() {
+                                        let _ = refused_tx.send(Refusal { code, message });
+                                    } else {
+                                        let _ = status_tx_recv.send(format!("⚠️  Relay: {}", message).into());
+                                    }
+                                }
+                                Message::Delivered { from, target: _, message_id } => {
+                                    if from == session_id_recv { continue; }
+                                    inflight_recv.lock().unwrap().delivered(&message_id);
+                                    let _ = status_tx_recv.send(ClientStatus::Delivered { message_id });
+                                }
+                                Message::RoomPresence { group_id, count } => {
+                                    let _ = status_tx_recv.send(ClientStatus::RoomPresence { group_id, count });
+                                }
+                                Message::Discover { target_session, from } => {
+                                    // Someone who missed our broadcast asks for a key exchange: send one
+                                    // their way, and the usual reply and nickname follow
+                                    if target_session != session_id_recv || from.is_empty() || from == session_id_recv {
+                                        continue;
+                                    }
+                                    if peers_recv.read().await.contains_key(&from) {
+                                        continue;
+                                    }
+                                    let key_exchange = Message::KeyExchange {
+                                        from: session_id_recv.clone(),
+                                        public_key: public_key_bytes_recv.clone(),
+                                        dh_ratchet_key: vec![],
+                                        target: from,
+                                        reset: false,
+                                        capabilities: CAPABILITIES,
+                                    };
+                                    if let Ok(data) = codec::encode(&key_exchange) {
+                                        let _ = ke_reply_tx.send(data);
+                                    }
+                                }
                                 _ => {}
                             }
                         }
@@ -474,16 +1039,46 @@ impl ChatClient {
 
         // Spawn sender task
         let peers_send = peers.clone();
+        let call_keys_send = call_keys.clone();
+        let group_keys_send = group_keys.clone();
         let session_id_send = session_id.to_string();
         let status_tx_send = status_tx.clone();
         let failure_tx_send = failure_tx.clone();
         let outgoing_rx_clone = outgoing_rx.clone();
+        let my_nickname_send = my_nickname.clone();
+        let rooms_send = rooms.clone();
         
-        tokio::spawn(async move {
+        let send_task = tokio::spawn(async move {
             // Send ping every 30 seconds, expect pong within 10 seconds
             let mut ping_interval = tokio::time::interval(Duration::from_secs(30));
             let mut pending_pong = false;
+            // Frames dropped because they couldn't be encoded/encrypted (sender keeps running)
+            let mut send_errors: u64 = 0;
             let mut pong_deadline = tokio::time::Instant::now();
+            let connected_at = tokio::time::Instant::now();
+            // Sealed frames waiting for the socket, written one at a time
+            let mut scheduler = SendScheduler::default();
+            // Chat the last connection may have lost goes out again first
+            let mut resend: VecDeque<OutgoingMessage> = match attempt {
+                0 => VecDeque::new(),
+                _ => inflight.lock().unwrap().take_for_resend().into(),
+            };
+            if !resend.is_empty() {
+                let _ = status_tx_send.send(format!("↻ Resending {} message(s) the connection may have lost", resend.len()).into());
+            }
+            // Peers whose sessions outlived the last connection hear our nickname again
+            // (new ones get it with their key exchange)
+            let nick = my_nickname_send.read().unwrap().clone();
+            if let Some(nick) = nick.filter(|_| attempt > 0) {
+                let message = PlainMessage::nickname(session_id_send.clone(), nick);
+                match encode_plain(&message) {
+                    Ok(serialized) => {
+                        let frames = seal_fanout(&peers_send, &session_id_send, None, &serialized, pads(padding, &message)).await;
+                        queue_frames(&mut scheduler, Lane::Chat, frames, &status_tx_send, &mut send_errors, None);
+                    }
+                    Err(e) => report_send_error(&status_tx_send, &mut send_errors, &e),
+                }
+            }
             
             loop {
                 // Check if pong deadline exceeded
@@ -494,213 +1089,233 @@ impl ChatClient {
                 
                 // Lock the receiver before select!
                 let mut outgoing_locked = outgoing_rx_clone.lock().await;
-                
-                tokio::select! {
-                    _ = ping_interval.tick() => {
-                        // Send WebSocket Ping
-                        if ws_sender.send(WsMessage::Ping(vec![])).await.is_err() {
-                            let _ = failure_tx_send.send("Failed to send ping".to_string());
-                            break;
+
+                // Voice and control that turned up while the last frame was being written
+                // go ahead of the rest of the message it belonged to
+                while let Ok(ke_data) = ke_reply_rx.try_recv() {
+                    scheduler.push(Lane::Urgent, Frame::new(ke_data, None));
+                }
+                let outgoing = match outgoing_locked.try_recv_urgent() {
+                    Some(urgent) => urgent,
+                    None => {
+                        if let Some(frame) = scheduler.pop() {
+                            if write_frame(&mut ws_sender, frame, &counters).await.is_err() {
+                                let _ = failure_tx_send.send("Send failed".to_string());
+                                break;
+                            }
+                            continue;
+                        }
+                        // Nothing left to write: wait for the next message
+                        tokio::select! {
+                            _ = ping_interval.tick() => {
+                                // Some NATs pass our pings (and the pongs) but drop what the relay
+                                // forwards: a link that owes us receipts and stays silent is dead too
+                                if let Some(window) = silence_timeout {
+                                    let quiet = connected_at.elapsed() >= window
+                                        && counters.gone_quiet(chrono::Utc::now().timestamp(), window);
+                                    if quiet && !peers_send.read().await.is_empty() {
+                                        let _ = status_tx_send.send(format!("⚠️  Nothing from the relay for {}s, reconnecting", window.as_secs()).into());
+                                        let _ = failure_tx_send.send("Relay went quiet".to_string());
+                                        break;
+                                    }
+                                }
+                                // Ask the relay about peers gone quiet, and forget the long gone
+                                let sweep = lifecycle::sweep(&mut *peers_send.write().await, &session_id_send, peer_idle, std::time::Instant::now());
+                                for id in &sweep.changed {
+                                    peers_changed_send.mark(id);
+                                }
+                                let probes = sweep.probes.into_iter().map(|(id, frame)| (id, Ok(frame))).collect();
+                                queue_frames(&mut scheduler, Lane::Urgent, probes, &status_tx_send, &mut send_errors, None);
+                                // Send WebSocket Ping
+                                scheduler.push(Lane::Urgent, Frame { data: WsMessage::Ping(vec![]), kind: None });
+                                pending_pong = true;
+                                pong_deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+                                continue;
+                            }
+                            Some(_) = pong_rx.recv() => {
+                                // Pong received
+                                pending_pong = false;
+                                continue;
+                            }
+                            Some(ke_data) = ke_reply_rx.recv() => {
+                                scheduler.push(Lane::Urgent, Frame::new(ke_data, None));
+                                continue;
+                            }
+                            outgoing = async { match resend.pop_front() {
+                                Some(msg) => Some(msg),
+                                None => outgoing_locked.recv().await,
+                            } } => match outgoing {
+                                Some(outgoing) => outgoing,
+                                None => continue,
+                            },
                         }
-                        pending_pong = true;
-                        pong_deadline = tokio::time::Instant::now() + Duration::from_secs(10);
                     }
-                    Some(_) = pong_rx.recv() => {
-                        // Pong received
-                        pending_pong = false;
+                };
+
+                // Held until a recipient confirms it
+                inflight.lock().unwrap().track(&outgoing);
+                match outgoing {
+                    OutgoingMessage::Direct { target_id, message } => {
+                        let serialized = match encode_plain(&message) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                report_send_error(&status_tx_send, &mut send_errors, &e);
+                                continue;
+                            }
+                        };
+                        let recipients = [target_id.clone()];
+                        let frames = seal_fanout(&peers_send, &session_id_send, Some(&recipients), &serialized, pads(padding, &message)).await;
+                        if frames.is_empty() {
+                            let _ = status_tx_send.send(format!("❌ No session with peer {}", short_id(&target_id)).into());
+                        } else {
+                            queue_frames(&mut scheduler, Lane::of(&message), frames, &status_tx_send, &mut send_errors, Traffic::of(&message));
+                        }
                     }
-                    Some(ke_data) = ke_reply_rx.recv() => {
-                        if ws_sender.send(WsMessage::Binary(ke_data)).await.is_err() {
-                            let _ = failure_tx_send.send("Send failed".to_string());
-                            break;
+                    OutgoingMessage::Global(message) if no_global && message.is_global_chat() => {
+                        let _ = status_tx_send.send("⚠️  Not sent: Global is off (--no-global)".into());
+                    }
+                    OutgoingMessage::Global(message) => {
+                        // Serialize once, ratchet per peer under the lock; the frames go out after releasing it
+                        let serialized = match encode_plain(&message) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                report_send_error(&status_tx_send, &mut send_errors, &e);
+                                continue;
+                            }
+                        };
+                        let frames = seal_fanout(&peers_send, &session_id_send, None, &serialized, pads(padding, &message)).await;
+                        if frames.is_empty() {
+                            let _ = status_tx_send.send("⚠️  No peers connected".into());
+                        } else {
+                            queue_frames(&mut scheduler, Lane::of(&message), frames, &status_tx_send, &mut send_errors, Traffic::of(&message));
                         }
                     }
-                    Some((_target, data)) = nickname_rx.recv() => {
-                        if ws_sender.send(WsMessage::Binary(data)).await.is_err() {
-                            let _ = failure_tx_send.send("Send failed".to_string());
-                            break;
+                    OutgoingMessage::Group { group_id, member_ids, message } => {
+                        // Encrypt once under our sender key; the relay fans the frame out to the room
+                        let serialized = match encode_plain(&message) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                report_send_error(&status_tx_send, &mut send_errors, &e);
+                                continue;
+                            }
+                        };
+                        let skipped: Vec<String> = {
+                            let peers = peers_send.read().await;
+                            member_ids.iter().filter(|id| **id != session_id_send && !peers.contains_key(*id)).cloned().collect()
+                        };
+                        let frames = seal_group(&peers_send, &group_keys_send, &session_id_send, &group_id, &member_ids, &serialized, pads(padding, &message)).await;
+                        queue_frames(&mut scheduler, Lane::of(&message), frames, &status_tx_send, &mut send_errors, Traffic::of(&message));
+                        if !skipped.is_empty() {
+                            let _ = status_tx_send.send(ClientStatus::GroupSkipped { group_id, members: skipped });
                         }
                     }
-                    outgoing = outgoing_locked.recv() => {
-                        if let Some(outgoing) = outgoing {
-                            match outgoing {
-                                OutgoingMessage::Direct { target_id, message } => {
-                                    let mut peers_map = peers_send.write().await;
-                                    if let Some(peer_info) = peers_map.get_mut(&target_id) {
-                                        let serialized = rmp_serde::to_vec(&message).unwrap();
-                                        match peer_info.ratchet.encrypt(&serialized) {
-                                            Ok((header, nonce, ciphertext)) => {
-                                                let header_bytes = bincode::serialize(&header).unwrap_or_default();
-                                                let encrypted_msg = Message::Encrypted {
-                                                    from: session_id_send.clone(),
-                                                    target: target_id.clone(),
-                                                    header: header_bytes,
-                                                    nonce,
-                                                    ciphertext,
-                                                };
-                                                let data = bincode::serialize(&encrypted_msg).unwrap();
-                                                drop(peers_map);
-                                                if ws_sender.send(WsMessage::Binary(data)).await.is_err() {
-                                                    let _ = failure_tx_send.send("Send failed".to_string());
-                                                    break;
-                                                }
-                                            }
-                                            Err(e) => {
-                                                let _ = status_tx_send.send(format!("❌ Encryption failed: {}", e));
-                                            }
-                                        }
-                                    } else {
-                                        let _ = status_tx_send.send(format!("❌ No session with peer {}", &target_id[..12.min(target_id.len())]));
-                                    }
-                                }
-                                OutgoingMessage::Global(message) => {
-                                    let mut peers_map = peers_send.write().await;
-                                    if peers_map.is_empty() {
-                                        let _ = status_tx_send.send("⚠️  No peers connected".to_string());
-                                    } else {
-                                        // Collect peer IDs first to avoid borrow issues
-                                        let peer_ids: Vec<String> = peers_map.keys().cloned().collect();
-                                        for peer_id in &peer_ids {
-                                            let serialized = rmp_serde::to_vec(&message).unwrap();
-                                            if let Some(peer_info) = peers_map.get_mut(peer_id) {
-                                                match peer_info.ratchet.encrypt(&serialized) {
-                                                    Ok((header, nonce, ciphertext)) => {
-                                                        let header_bytes = bincode::serialize(&header).unwrap_or_default();
-                                                        let encrypted_msg = Message::Encrypted {
-                                                            from: session_id_send.clone(),
-                                                            target: peer_id.clone(),
-                                                            header: header_bytes,
-                                                            nonce,
-                                                            ciphertext,
-                                                        };
-                                                        let data = bincode::serialize(&encrypted_msg).unwrap();
-                                                        if ws_sender.send(WsMessage::Binary(data)).await.is_err() {
-                                                            let _ = failure_tx_send.send("Send failed".to_string());
-                                                            break;
-                                                        }
-                                                    }
-                                                    Err(e) => {
-                                                        let _ = status_tx_send.send(format!("❌ Encryption failed for {}: {}", &peer_id[..12], e));
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                OutgoingMessage::Group { group_id, member_ids, message } => {
-                                    // Fan-out: encrypt once per member using pairwise ratchets,
-                                    // send as GroupEncrypted so relay routes via room
-                                    let mut peers_map = peers_send.write().await;
-                                    let mut sent = 0;
-                                    for member_id in &member_ids {
-                                        if let Some(peer_info) = peers_map.get_mut(member_id) {
-                                            let serialized = rmp_serde::to_vec(&message).unwrap();
-                                            match peer_info.ratchet.encrypt(&serialized) {
-                                                Ok((header, nonce, ciphertext)) => {
-                                                    let header_bytes = bincode::serialize(&header).unwrap_or_default();
-                                                    let encrypted_msg = Message::GroupEncrypted {
-                                                        from: session_id_send.clone(),
-                                                        group_id: group_id.clone(),
-                                                        header: header_bytes,
-                                                        nonce,
-                                                        ciphertext,
-                                                    };
-                                                    let data = bincode::serialize(&encrypted_msg).unwrap();
-                                                    if ws_sender.send(WsMessage::Binary(data)).await.is_err() {
-                                                        let _ = failure_tx_send.send("Send failed".to_string());
-                                                        break;
-                                                    }
-                                                    sent += 1;
-                                                }
-                                                Err(e) => {
-                                                    let _ = status_tx_send.send(format!("❌ Group encrypt failed for {}: {}", &member_id[..12], e));
-                                                }
-                                            }
-                                        }
-                                    }
-                                    if sent == 0 && !member_ids.is_empty() {
-                                        let _ = status_tx_send.send("⚠️  No group members online".to_string());
-                                    }
-                                }
-                                OutgoingMessage::JoinRoom { group_id } => {
-                                    let join_msg = Message::GroupJoin {
-                                        session_id: session_id_send.clone(),
-                                        group_id,
-                                    };
-                                    let data = bincode::serialize(&join_msg).unwrap();
-                                    if ws_sender.send(WsMessage::Binary(data)).await.is_err() {
-                                        let _ = failure_tx_send.send("Send failed".to_string());
-                                        break;
-                                    }
-                                }
-                                OutgoingMessage::LeaveRoom { group_id } => {
-                                    let leave_msg = Message::GroupLeave {
-                                        session_id: session_id_send.clone(),
-                                        group_id,
-                                    };
-                                    let data = bincode::serialize(&leave_msg).unwrap();
-                                    if ws_sender.send(WsMessage::Binary(data)).await.is_err() {
-                                        let _ = failure_tx_send.send("Send failed".to_string());
-                                        break;
-                                    }
-                                }
-                                OutgoingMessage::Audio { target_id, data: audio_data } => {
-                                    // Encrypt audio frame with cached voice key — fast path
-                                    let mut peers_map = peers_send.write().await;
-                                    if let Some(peer_info) = peers_map.get_mut(&target_id) {
-                                        let voice_key = peer_info.ratchet.derive_voice_key();
-                                        if let Ok((nonce, ciphertext)) = encrypt_message(&voice_key, &audio_data) {
-                                            let audio_msg = Message::AudioFrame {
-                                                from: session_id_send.clone(),
-                                                nonce,
-                                                ciphertext,
-                                            };
-                                            if let Ok(data) = bincode::serialize(&audio_msg) {
-                                                drop(peers_map);
-                                                if ws_sender.send(WsMessage::Binary(data)).await.is_err() {
-                                                    let _ = failure_tx_send.send("Send failed".to_string());
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                OutgoingMessage::Signal(message) => {
-                                    // Send directly without encryption
-                                    if let Ok(data) = bincode::serialize(&message) {
-                                        if ws_sender.send(WsMessage::Binary(data)).await.is_err() {
-                                            let _ = failure_tx_send.send("Send failed".to_string());
-                                            break;
-                                        }
-                                    }
-                                }
+                    OutgoingMessage::JoinRoom { group_id, join_token } => {
+                        rooms_send.lock().unwrap().joined(&group_id, join_token.clone());
+                        let join_msg = Message::GroupJoin {
+                            session_id: session_id_send.clone(),
+                            group_id,
+                            join_token,
+                        };
+                        match codec::encode(&join_msg) {
+                            Ok(data) => scheduler.push(Lane::Chat, Frame::new(data, None)),
+                            Err(e) => report_send_error(&status_tx_send, &mut send_errors, &e.into()),
+                        }
+                    }
+                    OutgoingMessage::LeaveRoom { group_id } => {
+                        group_keys_send.lock().unwrap().forget_group(&group_id);
+                        rooms_send.lock().unwrap().left(&group_id);
+                        let leave_msg = Message::GroupLeave {
+                            session_id: session_id_send.clone(),
+                            group_id,
+                        };
+                        match codec::encode(&leave_msg) {
+                            Ok(data) => scheduler.push(Lane::Chat, Frame::new(data, None)),
+                            Err(e) => report_send_error(&status_tx_send, &mut send_errors, &e.into()),
+                        }
+                    }
+                    OutgoingMessage::Audio { target_id, data: audio_data } => {
+                        // Fast path: sealed with the call's voice key, no peer map
+                        if let Some(data) = seal_audio(&call_keys_send, &session_id_send, &target_id, &audio_data) {
+                            scheduler.push(Lane::Urgent, Frame::new(data, Some(Traffic::Voice)));
+                        }
+                    }
+                    OutgoingMessage::CallKey { peer_id, salts } => {
+                        // The one time a call needs the peer's session
+                        let key = match salts {
+                            Some((caller, callee)) => peers_send.read().await.get(&peer_id)
+                                .map(|p| (caller.call_id.clone(), p.ratchet.derive_call_key(&caller.salt, &callee.salt, &caller.call_id))),
+                            None => None,
+                        };
+                        set_call_key(&call_keys_send, &peer_id, key);
+                    }
+                    OutgoingMessage::Nickname(nick) => {
+                        // One batch of pairwise frames; peers who join later get it after key exchange
+                        *my_nickname_send.write().unwrap() = Some(nick.clone());
+                        let message = PlainMessage::nickname(session_id_send.clone(), nick);
+                        let serialized = match encode_plain(&message) {
+                            Ok(s) => s,
+                            Err(e) => {
+                                report_send_error(&status_tx_send, &mut send_errors, &e);
+                                continue;
                             }
+                        };
+                        let frames = seal_fanout(&peers_send, &session_id_send, None, &serialized, pads(padding, &message)).await;
+                        queue_frames(&mut scheduler, Lane::Chat, frames, &status_tx_send, &mut send_errors, None);
+                    }
+                    OutgoingMessage::ResyncPeers => peers_changed_send.resync(),
+                    OutgoingMessage::Signal(message) => {
+                        // Send directly without encryption
+                        if let Ok(data) = codec::encode(&message) {
+                            scheduler.push(Lane::Urgent, Frame::new(data, None));
                         }
                     }
+                    OutgoingMessage::Shutdown { done } => {
+                        // Only taken once every frame before it has been written; close() flushes them
+                        let _ = ws_sender.close().await;
+                        let _ = done.send(());
+                        let _ = shutdown_tx.send(());
+                        break;
+                    }
                 }
             }
         });
 
-        // Wait for connection failure signal
-        if let Some(reason) = failure_rx.recv().await {
-            Err(anyhow::anyhow!("Connection lost: {}", reason))
-        } else {
-            Err(anyhow::anyhow!("Connection lost"))
-        }
-    }
-
-    pub async fn initiate_handshake(
-        &mut self,
-        ws_sender: &mut futures_util::stream::SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>,
-    ) -> Result<()> {
-        let key_exchange = Message::KeyExchange {
-            from: self.session_id.clone(),
-            public_key: self.identity.public_key_bytes(),
-            dh_ratchet_key: vec![], // No ratchet DH key on initial broadcast
+        // Wait for connection failure signal (or a shutdown request)
+        let reason = tokio::select! {
+            // The relay says why before it closes, so its reason wins
+            biased;
+            Some(refusal) = refused_rx.recv() => {
+                recv_task.abort();
+                send_task.abort();
+                return Err(refusal.into());
+            }
+            Some(mismatch) = mismatch_rx.recv() => {
+                recv_task.abort();
+                send_task.abort();
+                return Err(mismatch.into());
+            }
+            reason = failure_rx.recv() => reason,
+            Ok(()) = shutdown_rx => {
+                recv_task.abort();
+                return Ok(());
+            }
+            // Connect (or its Ack) got lost: nothing else would ever end this attempt
+            Err(_) = tokio::time::timeout(setup_timeout, acked_rx) => {
+                recv_task.abort();
+                send_task.abort();
+                return Err(SetupTimeout::NoAck.into());
+            }
         };
-        let data = bincode::serialize(&key_exchange)?;
-        ws_sender.send(WsMessage::Binary(data)).await?;
-        Ok(())
+
+        // Tear down both halves before the next attempt: a stale sender would otherwise
+        // keep holding the outgoing queue and swallow the first message after reconnecting
+        recv_task.abort();
+        send_task.abort();
+
+        match reason {
+            Some(reason) => Err(anyhow::anyhow!("Connection lost: {}", reason)),
+            None => Err(anyhow::anyhow!("Connection lost")),
+        }
     }
 }
 
@@ -713,8 +1328,647 @@ impl Identity {
     }
 }
 
+/// Decode a decrypted payload from `from` and bring it within protocol limits.
+/// Anything repaired or rejected is logged against the peer instead of reaching the UI as-is.
+/// The sender is whoever's session opened it, whatever the payload claims.
+fn open_plaintext(plaintext: &[u8], from: &str, status_tx: &StatusSender) -> Option<PlainMessage> {
+    // Padded or not, whichever the sender chose
+    let plaintext = if ratchet::is_padded(plaintext) {
+        match ratchet::unpad(plaintext) {
+            Ok(plaintext) => plaintext,
+            Err(e) => {
+                let _ = status_tx.send(format!("⚠️ Dropped message from {}: {}", short_id(from), e).into());
+                return None;
+            }
+        }
+    } else {
+        plaintext
+    };
+    let Some(mut msg) = PlainMessage::decode(plaintext) else {
+        let _ = status_tx.send(format!("⚠️ Undecodable message from {}", short_id(from)).into());
+        return None;
+    };
+    if msg.sender != from {
+        let _ = status_tx.send(format!("⚠️ Message from {} claimed to be from {}", short_id(from), short_id(&msg.sender)).into());
+        msg.sender = from.to_string();
+    }
+    match msg.sanitize() {
+        Ok(repairs) => {
+            if !repairs.is_empty() {
+                let _ = status_tx.send(format!("⚠️ Message from {}: {}", short_id(from), repairs.join(", ")).into());
+            }
+            strings::localize(&mut msg);
+            Some(msg)
+        }
+        Err(reason) => {
+            let _ = status_tx.send(format!("⚠️ Dropped message from {}: {}", short_id(from), reason).into());
+            None
+        }
+    }
+}
+
+/// Start using `key` (with its call's id) for the call with `peer_id`, or (None) wipe
+/// the one we had
+fn set_call_key(call_keys: &SharedCallKeys, peer_id: &str, key: Option<(String, [u8; 32])>) {
+    let mut call_keys = call_keys.write().unwrap();
+    let old = match key {
+        Some(key) => call_keys.insert(peer_id.to_string(), key),
+        None => call_keys.remove(peer_id),
+    };
+    if let Some((_, mut old)) = old {
+        old.zeroize();
+    }
+}
+
+/// Encrypt an audio frame for `target` with our call's key, if we're in a call with them
+fn seal_audio(call_keys: &SharedCallKeys, from: &str, target: &str, opus_data: &[u8]) -> Option<Vec<u8>> {
+    let (call_id, key) = call_keys.read().unwrap().get(target).cloned()?;
+    let (nonce, ciphertext) = encrypt_message(&key, opus_data).ok()?;
+    codec::encode(&Message::AudioFrame { from: from.to_string(), nonce, ciphertext, call_id }).ok()
+}
+
+/// Decrypt an audio frame from `from` if it's for the call we're in with them, and
+/// return that call's id with it. A frame without a call id (from an older client)
+/// is tried against the current call's key, which only opens this call's frames.
+fn open_audio(call_keys: &SharedCallKeys, from: &str, call_id: &str, nonce: &[u8], ciphertext: &[u8]) -> Option<(String, Vec<u8>)> {
+    let (current, key) = call_keys.read().unwrap().get(from).cloned()?;
+    if !call_id.is_empty() && call_id != current {
+        return None;
+    }
+    let opus_data = decrypt_message(&key, nonce, ciphertext).ok()?;
+    Some((current, opus_data))
+}
+
+/// Serialize a PlainMessage for encryption
+fn encode_plain(message: &PlainMessage) -> Result<Vec<u8>> {
+    rmp_serde::to_vec(message).context("failed to encode message, not sent")
+}
+
+/// Whether `message` is padded under `padding`: chat is, file chunks aren't (they're
+/// all one size anyway, and the overhead would add up over a transfer)
+fn pads(padding: Padding, message: &PlainMessage) -> bool {
+    padding == Padding::Buckets && message.file_chunk.is_none()
+}
+
+/// Ratchet-encrypt an encoded PlainMessage into a frame for `target`, `padded` if asked.
+/// Encryption, header and frame encoding failures all come back through one error.
+fn seal_frame(ratchet: &mut RatchetSession, from: &str, target: &str, plaintext: &[u8], padded: bool) -> Result<Vec<u8>> {
+    let (header, nonce, ciphertext) = match padded {
+        true => ratchet.encrypt_padded(plaintext)?,
+        false => ratchet.encrypt(plaintext)?,
+    };
+    let header = bincode::serialize(&header).context("failed to encode ratchet header")?;
+    let message = Message::Encrypted {
+        from: from.to_string(),
+        target: target.to_string(),
+        header,
+        nonce,
+        ciphertext,
+    };
+    codec::encode(&message).context("failed to encode frame, not sent")
+}
+
+/// Ratchet-encrypt one encoded message for several peers. The peers lock is held only
+/// for the ratchet steps; the sealed frames are returned so the caller can send them
+/// after it has been released. `recipients: None` means every peer still around (see
+/// lifecycle.rs). With `pad`, it's padded for the peers that can read that.
+async fn seal_fanout(
+    peers: &PeerMap,
+    from: &str,
+    recipients: Option<&[String]>,
+    plaintext: &[u8],
+    pad: bool,
+) -> Vec<(String, Result<Vec<u8>>)> {
+    let mut peers_map = peers.write().await;
+    let ids: Vec<String> = match recipients {
+        Some(ids) => ids.to_vec(),
+        None => peers_map.iter().filter(|(_, peer)| peer.activity.is_active()).map(|(id, _)| id.clone()).collect(),
+    };
+    ids.into_iter()
+        .filter_map(|id| {
+            let peer = peers_map.get_mut(&id)?;
+            let padded = pad && peer.reads_padding();
+            let frame = seal_frame(&mut peer.ratchet, from, &id, plaintext, padded);
+            Some((id, frame))
+        })
+        .collect()
+}
+
+/// Encrypt one encoded message for a group under our sender key. Members who haven't
+/// been given the key get it first, over their pairwise ratchet, so the frames come back
+/// in sending order with the group frame last. Empty if no member has a session with us.
+/// With `pad`, it's padded if every member can read that (they all get the one frame).
+async fn seal_group(
+    peers: &PeerMap,
+    group_keys: &SharedGroupKeys,
+    from: &str,
+    group_id: &str,
+    member_ids: &[String],
+    plaintext: &[u8],
+    pad: bool,
+) -> Vec<(String, Result<Vec<u8>>)> {
+    let mut peers_map = peers.write().await;
+    let members: Vec<String> = member_ids.iter().filter(|id| *id != from).cloned().collect();
+    if !members.iter().any(|id| peers_map.contains_key(id)) {
+        return Vec::new();
+    }
+    let mut keys = group_keys.lock().unwrap();
+
+    let owed = keys.prepare_send(group_id, &members, |id| peers_map.contains_key(id));
+    let mut frames: Vec<_> = owed.into_iter()
+        .map(|(id, update)| {
+            let frame = encode_plain(&PlainMessage::sender_key(from.to_string(), update)).and_then(|serialized| {
+                let peer = peers_map.get_mut(&id).context("peer vanished")?;
+                seal_frame(&mut peer.ratchet, from, &id, &serialized, false)
+            });
+            (id, frame)
+        })
+        .collect();
+
+    let padded = pad && members.iter().filter_map(|id| peers_map.get(id)).all(PeerInfo::reads_padding);
+    let plaintext = match padded {
+        true => std::borrow::Cow::Owned(ratchet::pad(plaintext)),
+        false => std::borrow::Cow::Borrowed(plaintext),
+    };
+    let group_frame = keys.seal(group_id, &plaintext).and_then(|(header, nonce, ciphertext)| {
+        let header = bincode::serialize(&header).context("failed to encode sender key header")?;
+        let message = Message::GroupEncrypted {
+            from: from.to_string(),
+            group_id: group_id.to_string(),
+            header,
+            nonce,
+            ciphertext,
+        };
+        codec::encode(&message).context("failed to encode frame, not sent")
+    });
+    frames.push((group_id.to_string(), group_frame));
+    frames
+}
+
+/// Decode a decrypted group frame from `from` and hand it to the TUI, saying what it
+/// carried. The delivery confirmation it asks for, if any, is added to `acks`.
+fn deliver_group_message(
+    plaintext: &[u8],
+    own_id: &str,
+    from: &str,
+    group_id: &str,
+    incoming_tx: &mpsc::UnboundedSender<PlainMessage>,
+    status_tx: &StatusSender,
+    acks: &mut Vec<Vec<u8>>,
+) -> Option<Traffic> {
+    let mut plain_msg = open_plaintext(plaintext, from, status_tx)?;
+    plain_msg.group_id = Some(group_id.to_string());
+    let kind = Traffic::of(&plain_msg);
+    acks.extend(delivery_ack(own_id, from, &plain_msg));
+    let _ = incoming_tx.send(plain_msg);
+    kind
+}
+
+/// The `Delivered` frame telling `from` that `msg` arrived, if it wants one. A split
+/// message is confirmed with its last part, which the relay delivers after the others.
+/// Copies that turn up again are confirmed again: the first confirmation may be what
+/// got lost.
+fn delivery_ack(own_id: &str, from: &str, msg: &PlainMessage) -> Option<Vec<u8>> {
+    let last_part = msg.part_index.zip(msg.part_total).is_none_or(|(index, total)| index + 1 == total);
+    if !msg.awaits_delivery() || !last_part {
+        return None;
+    }
+    let ack = Message::Delivered {
+        from: own_id.to_string(),
+        target: from.to_string(),
+        message_id: msg.message_id.clone()?,
+    };
+    codec::encode(&ack).ok()
+}
+
+/// Act on a sender-key message from `from`: store their key and deliver the group frames
+/// it unlocks, or answer their request for ours. Returns the frames to send back: our
+/// key sealed for `from`, or confirmations for what the key unlocked.
+fn on_sender_key_update(
+    group_keys: &SharedGroupKeys,
+    own_id: &str,
+    from: &str,
+    update: SenderKeyUpdate,
+    ratchet: &mut RatchetSession,
+    incoming_tx: &mpsc::UnboundedSender<PlainMessage>,
+    status_tx: &StatusSender,
+) -> Vec<Vec<u8>> {
+    match update {
+        SenderKeyUpdate::Distribution { ref group_id, .. } => {
+            let opened = group_keys.lock().unwrap().accept(group_id, from, &update);
+            let mut acks = Vec::new();
+            for result in opened {
+                match result {
+                    Ok(plaintext) => {
+                        deliver_group_message(&plaintext, own_id, from, group_id, incoming_tx, status_tx, &mut acks);
+                    }
+                    Err(e) => {
+                        let _ = status_tx.send(format!("⚠️ Group message from {} lost: {}", short_id(from), e).into());
+                    }
+                }
+            }
+            acks
+        }
+        SenderKeyUpdate::Request { group_id, key_id, iteration } => {
+            let Some(update) = group_keys.lock().unwrap().resend(&group_id, from, key_id, iteration) else {
+                return Vec::new();
+            };
+            let sealed = encode_plain(&PlainMessage::sender_key(own_id.to_string(), update))
+                .and_then(|serialized| seal_frame(ratchet, own_id, from, &serialized, false));
+            match sealed {
+                Ok(data) => vec![data],
+                Err(e) => {
+                    let _ = status_tx.send(format!("❌ Group key not resent to {}: {:#}", short_id(from), e).into());
+                    Vec::new()
+                }
+            }
+        }
+    }
+}
+
+/// Queue sealed frames in `lane`, to be counted as `kind` when written.
+/// Per-peer seal failures are reported and skipped.
+fn queue_frames(
+    scheduler: &mut SendScheduler,
+    lane: Lane,
+    frames: Vec<(String, Result<Vec<u8>>)>,
+    status_tx: &StatusSender,
+    errors: &mut u64,
+    kind: Option<Traffic>,
+) {
+    for (peer_id, frame) in frames {
+        match frame {
+            Ok(data) => scheduler.push(lane, Frame::new(data, kind)),
+            Err(e) => {
+                let e = e.context(format!("peer {}", short_id(&peer_id)));
+                report_send_error(status_tx, errors, &e);
+            }
+        }
+    }
+}
+
+/// Write one frame and flush it, so whatever comes next can still go ahead of the
+/// rest. An `Err` means the socket itself failed.
+async fn write_frame<S>(ws_sender: &mut S, frame: Frame, counters: &stats::Counters) -> std::result::Result<(), S::Error>
+where
+    S: futures_util::Sink<WsMessage> + Unpin,
+{
+    if let (Some(kind), WsMessage::Binary(data)) = (frame.kind, &frame.data) {
+        counters.kind_sent(kind, data.len());
+        if kind == Traffic::Voice {
+            counters.audio_sent();
+        }
+    }
+    ws_sender.send(frame.data).await
+}
+
+/// Surface a dropped outgoing frame to the TUI without tearing down the sender
+fn report_send_error(status_tx: &StatusSender, errors: &mut u64, e: &anyhow::Error) {
+    *errors += 1;
+    let _ = status_tx.send(format!("❌ {:#} ({} send errors)", e, errors).into());
+}
+
+/// The session id `public_key` connects under: the same every time, so a second chat
+/// with one identity takes the session over on the relay instead of joining beside it
+pub fn session_id_for(public_key: &[u8]) -> String {
+    hex::encode(&blake3::derive_key(SESSION_ID_CONTEXT, public_key)[..16])
+}
+
 fn generate_session_id() -> String {
     use rand::Rng;
     let random_bytes: Vec<u8> = (0..16).map(|_| rand::thread_rng().gen()).collect();
     hex::encode(random_bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Verification;
+
+    fn paired_ratchets() -> (RatchetSession, RatchetSession) {
+        let shared = [7u8; 32];
+        let mut alice = RatchetSession::init(&shared, true);
+        let mut bob = RatchetSession::init(&shared, false);
+        let (alice_dh, bob_dh) = (alice.public_key(), bob.public_key());
+        alice.set_remote_dh(bob_dh);
+        bob.set_remote_dh(alice_dh);
+        (alice, bob)
+    }
+
+    #[test]
+    fn test_seal_frame_roundtrip() {
+        let (mut alice, mut bob) = paired_ratchets();
+        let msg = PlainMessage::direct("alice".to_string(), "hi bob".to_string());
+        let plaintext = encode_plain(&msg).unwrap();
+
+        let frame = seal_frame(&mut alice, "alice", "bob", &plaintext, false).unwrap();
+        match codec::decode(&frame).unwrap() {
+            Message::Encrypted { from, target, header, nonce, ciphertext } => {
+                assert_eq!(from, "alice");
+                assert_eq!(target, "bob");
+                let header: RatchetHeader = bincode::deserialize(&header).unwrap();
+                let pt = bob.decrypt(&header, &nonce, &ciphertext).unwrap();
+                let decoded: PlainMessage = rmp_serde::from_slice(&pt).unwrap();
+                assert_eq!(decoded.content, "hi bob");
+            }
+            other => panic!("unexpected frame: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_each_call_gets_its_own_voice_key() {
+        let (alice, bob) = paired_ratchets();
+        let (alice_keys, bob_keys) = (SharedCallKeys::default(), SharedCallKeys::default());
+
+        let mut keys = Vec::new();
+        for call_id in ["call1", "call2"] {
+            let (caller, callee) = (CallSalt::generate(call_id.to_string()), CallSalt::generate(call_id.to_string()));
+            let key = alice.derive_call_key(&caller.salt, &callee.salt, call_id);
+            set_call_key(&alice_keys, "bob", Some((call_id.to_string(), key)));
+            set_call_key(&bob_keys, "alice", Some((call_id.to_string(), bob.derive_call_key(&caller.salt, &callee.salt, call_id))));
+
+            let frame = seal_audio(&alice_keys, "alice", "bob", b"opus").unwrap();
+            let Ok(Message::AudioFrame { nonce, ciphertext, call_id: sent_in, .. }) = codec::decode(&frame) else { panic!() };
+            assert_eq!(sent_in, call_id);
+            let (opened_in, opus) = open_audio(&bob_keys, "alice", &sent_in, &nonce, &ciphertext).unwrap();
+            assert_eq!((opened_in.as_str(), opus.as_slice()), (call_id, &b"opus"[..]));
+            keys.push(key);
+
+            // Hanging up wipes it: nothing goes out, nothing comes in
+            set_call_key(&alice_keys, "bob", None);
+            assert!(seal_audio(&alice_keys, "alice", "bob", b"opus").is_none());
+            set_call_key(&bob_keys, "alice", None);
+            assert!(open_audio(&bob_keys, "alice", &sent_in, &nonce, &ciphertext).is_none());
+        }
+        assert_ne!(keys[0], keys[1]);
+    }
+
+    #[test]
+    fn test_late_frames_from_the_last_call_are_dropped() {
+        let (alice, bob) = paired_ratchets();
+        let (alice_keys, bob_keys) = (SharedCallKeys::default(), SharedCallKeys::default());
+        let key_call = |call_id: &str| {
+            let (caller, callee) = (CallSalt::generate(call_id.to_string()), CallSalt::generate(call_id.to_string()));
+            set_call_key(&alice_keys, "bob", Some((call_id.to_string(), alice.derive_call_key(&caller.salt, &callee.salt, call_id))));
+            set_call_key(&bob_keys, "alice", Some((call_id.to_string(), bob.derive_call_key(&caller.salt, &callee.salt, call_id))));
+        };
+        let sealed = || codec::decode(&seal_audio(&alice_keys, "alice", "bob", b"opus").unwrap()).unwrap();
+
+        // A frame from call1 is still in flight when they hang up and call2 starts straight away
+        key_call("call1");
+        let Message::AudioFrame { nonce, ciphertext, call_id, .. } = sealed() else { panic!() };
+        key_call("call2");
+        assert!(open_audio(&bob_keys, "alice", &call_id, &nonce, &ciphertext).is_none());
+        // Relabelled as call2, or sent without a label, it still doesn't open under call2's key
+        assert!(open_audio(&bob_keys, "alice", "call2", &nonce, &ciphertext).is_none());
+        assert!(open_audio(&bob_keys, "alice", "", &nonce, &ciphertext).is_none());
+
+        // call2's own frames come through, labelled or not
+        let Message::AudioFrame { nonce, ciphertext, call_id, .. } = sealed() else { panic!() };
+        assert_eq!(open_audio(&bob_keys, "alice", &call_id, &nonce, &ciphertext).unwrap().0, "call2");
+        assert_eq!(open_audio(&bob_keys, "alice", "", &nonce, &ciphertext).unwrap().0, "call2");
+    }
+
+    #[tokio::test]
+    async fn test_audio_never_waits_on_the_peer_map() {
+        let peers: PeerMap = std::sync::Arc::new(tokio::sync::RwLock::new(HashMap::new()));
+        let (ours, theirs) = paired_ratchets();
+        peers.write().await.insert("bob".to_string(), PeerInfo { ratchet: ours, nickname: None, public_key: vec![], health: SessionHealth::default(), capabilities: 0, activity: Activity::default() });
+        let call_keys = SharedCallKeys::default();
+        set_call_key(&call_keys, "bob", Some(("call1".to_string(), [5; 32])));
+
+        // A chat flood sealing a burst of messages under the peers lock, over and over
+        let flood_peers = peers.clone();
+        let flood = tokio::spawn(async move {
+            let chat = encode_plain(&PlainMessage::new("me".to_string(), "x".repeat(2000))).unwrap();
+            loop {
+                let frames = seal_fanout(&flood_peers, "me", None, &chat, false).await;
+                assert!(frames.iter().all(|(_, frame)| frame.is_ok()));
+                tokio::task::yield_now().await;
+            }
+        });
+
+        // ...and, to be sure, the lock held the whole time audio runs
+        let held = peers.write().await;
+        let audio = tokio::task::spawn_blocking(move || {
+            let mut slowest = Duration::ZERO;
+            for _ in 0..500 {
+                let started = std::time::Instant::now();
+                let frame = seal_audio(&call_keys, "me", "bob", &[1; 160]).unwrap();
+                let Ok(Message::AudioFrame { nonce, ciphertext, call_id, .. }) = codec::decode(&frame) else { panic!() };
+                open_audio(&call_keys, "bob", &call_id, &nonce, &ciphertext).unwrap();
+                slowest = slowest.max(started.elapsed());
+            }
+            slowest
+        });
+        let slowest = tokio::time::timeout(Duration::from_secs(5), audio).await.expect("audio blocked on the peer map").unwrap();
+        assert!(slowest < Duration::from_millis(50), "slowest frame took {:?}", slowest);
+        drop(held);
+        flood.abort();
+        drop(theirs);
+    }
+
+    #[tokio::test]
+    async fn test_seal_group_encrypts_once_and_sends_key_once() {
+        let peers: PeerMap = std::sync::Arc::new(tokio::sync::RwLock::new(HashMap::new()));
+        let group_keys = SharedGroupKeys::default();
+        let mut members = HashMap::new();
+        for id in ["bob", "carol", "dave"] {
+            let (ours, theirs) = paired_ratchets();
+            peers.write().await.insert(id.to_string(), PeerInfo { ratchet: ours, nickname: None, public_key: vec![], health: SessionHealth::default(), capabilities: 0, activity: Activity::default() });
+            members.insert(id.to_string(), (theirs, group_keys::GroupKeys::default()));
+        }
+        let member_ids: Vec<String> = vec!["me".into(), "bob".into(), "carol".into(), "dave".into()];
+
+        for (round, expected_frames) in [("first", 4), ("second", 1)] {
+            let plaintext = encode_plain(&PlainMessage::group("me".into(), round.into(), "g1".into())).unwrap();
+            let frames = seal_group(&peers, &group_keys, "me", "g1", &member_ids, &plaintext, false).await;
+            assert_eq!(frames.len(), expected_frames, "{round}");
+
+            let mut group_frame = None;
+            for (id, frame) in frames {
+                match codec::decode(&frame.unwrap()).unwrap() {
+                    Message::Encrypted { target, header, nonce, ciphertext, .. } => {
+                        assert_eq!(target, id);
+                        let (ratchet, keys) = members.get_mut(&target).unwrap();
+                        let header: RatchetHeader = bincode::deserialize(&header).unwrap();
+                        let pt = ratchet.decrypt(&header, &nonce, &ciphertext).unwrap();
+                        let update = PlainMessage::decode(&pt).unwrap().sender_key.unwrap();
+                        keys.accept("g1", "me", &update);
+                    }
+                    Message::GroupEncrypted { header, nonce, ciphertext, .. } => {
+                        group_frame = Some((bincode::deserialize::<SenderKeyHeader>(&header).unwrap(), nonce, ciphertext));
+                    }
+                    other => panic!("unexpected frame: {:?}", other),
+                }
+            }
+
+            // Every member opens the one group frame
+            let (header, nonce, ciphertext) = group_frame.expect("no group frame");
+            for (_, keys) in members.values_mut() {
+                let Opened::Plaintext(pt) = keys.open("g1", "me", header, nonce.clone(), ciphertext.clone()) else {
+                    panic!("member couldn't open the group frame");
+                };
+                assert_eq!(PlainMessage::decode(&pt).unwrap().content, round);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fanout_releases_peer_lock_before_sending() {
+        // 30 mock peers, each with a paired ratchet we can decrypt with
+        let peers = PeerMap::default();
+        let mut receivers = HashMap::new();
+        for i in 0..30 {
+            let (ours, theirs) = paired_ratchets();
+            let id = format!("{:032x}", i);
+            peers.write().await.insert(id.clone(), PeerInfo { ratchet: ours, nickname: None, public_key: vec![], health: SessionHealth::default(), capabilities: 0, activity: Activity::default() });
+            receivers.insert(id, theirs);
+        }
+        let plaintext = encode_plain(&PlainMessage::new("me".to_string(), "hello everyone".to_string())).unwrap();
+
+        // The sender loop's steps, writing into a socket with room for one frame that
+        // nobody reads yet: the writes stall with frames still queued
+        let (socket, mut wire) = mpsc::channel::<WsMessage>(1);
+        let sender_peers = peers.clone();
+        let sender = tokio::spawn(async move {
+            let mut ws_sender = tokio_util::sync::PollSender::new(socket);
+            let (status_tx, _status_rx) = mpsc::unbounded_channel();
+            let (mut scheduler, mut errors, counters) = (SendScheduler::default(), 0, stats::Counters::default());
+            let frames = seal_fanout(&sender_peers, "me", None, &plaintext, false).await;
+            queue_frames(&mut scheduler, Lane::Chat, frames, &status_tx, &mut errors, None);
+            while let Some(frame) = scheduler.pop() {
+                write_frame(&mut ws_sender, frame, &counters).await.unwrap();
+            }
+            plaintext
+        });
+        while wire.capacity() > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!sender.is_finished());
+        assert!(peers.try_write().is_ok(), "peers lock held while frames wait for the socket");
+
+        // Every peer gets its own frame once the socket drains
+        let mut frames = Vec::new();
+        while let Some(WsMessage::Binary(frame)) = wire.recv().await {
+            frames.push(frame);
+        }
+        let plaintext = sender.await.unwrap();
+        assert_eq!(frames.len(), 30);
+        for frame in frames {
+            let Message::Encrypted { target, header, nonce, ciphertext, .. } = codec::decode(&frame).unwrap() else {
+                panic!("expected Encrypted frame");
+            };
+            let header: RatchetHeader = bincode::deserialize(&header).unwrap();
+            assert_eq!(receivers.get_mut(&target).unwrap().decrypt(&header, &nonce, &ciphertext).unwrap(), plaintext);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_global_leaves_out_peers_who_have_gone() {
+        // A long session on a busy relay: 10 peers still here, 400 gone
+        let peers = PeerMap::default();
+        for i in 0..410 {
+            let (ours, _) = paired_ratchets();
+            let peer = PeerInfo { ratchet: ours, nickname: None, public_key: vec![], health: SessionHealth::default(), capabilities: 0, activity: Activity::default() };
+            peers.write().await.insert(format!("{:032x}", i), peer);
+        }
+        let plaintext = encode_plain(&PlainMessage::new("me".to_string(), "hello everyone".to_string())).unwrap();
+        let seal_all = || async { seal_fanout(&peers, "me", None, &plaintext, false).await.len() };
+        // Every seal steps a ratchet, so this counts the ones done so far
+        let seals = || async { peers.read().await.values().map(|p| p.ratchet.stats().send_chain_len).sum::<u32>() };
+
+        assert_eq!(seal_all().await, 410);
+        for peer in peers.write().await.values_mut().skip(10) {
+            peer.activity.gone();
+        }
+        assert_eq!(seal_all().await, 10);
+        // Nothing was sealed for the 400 gone only to be thrown away
+        assert_eq!(seals().await, 410 + 10);
+
+        // A direct message still reaches someone gone, and they're back when they answer
+        let gone = peers.read().await.iter().find(|(_, p)| !p.activity.is_active()).map(|(id, _)| id.clone()).unwrap();
+        assert_eq!(seal_fanout(&peers, "me", Some(std::slice::from_ref(&gone)), &plaintext, false).await.len(), 1);
+        assert!(peers.write().await.get_mut(&gone).unwrap().activity.seen());
+        assert_eq!(seal_all().await, 11);
+    }
+
+    #[tokio::test]
+    async fn test_chat_padded_only_for_peers_that_read_it() {
+        let peers = PeerMap::default();
+        let mut receivers = HashMap::new();
+        for (id, capabilities) in [("new", CAPABILITIES), ("old", 0)] {
+            let (ours, theirs) = paired_ratchets();
+            peers.write().await.insert(id.to_string(), PeerInfo { ratchet: ours, nickname: None, public_key: vec![], health: SessionHealth::default(), capabilities, activity: Activity::default() });
+            receivers.insert(id.to_string(), theirs);
+        }
+        let chat = PlainMessage::new("me".to_string(), "ok".to_string());
+        let chunk = PlainMessage {
+            file_chunk: Some(crate::protocol::FileChunk { file_id: "f1".to_string(), index: 0, data: vec![0; 100] }),
+            ..chat.clone()
+        };
+        assert!(pads(Padding::Buckets, &chat));
+        assert!(!pads(Padding::Buckets, &chunk) && !pads(Padding::Off, &chat));
+
+        let plaintext = encode_plain(&chat).unwrap();
+        let (status_tx, _status_rx) = mpsc::unbounded_channel();
+        for (peer_id, frame) in seal_fanout(&peers, "me", None, &plaintext, true).await {
+            let Message::Encrypted { header, nonce, ciphertext, .. } = codec::decode(&frame.unwrap()).unwrap() else {
+                panic!("expected Encrypted frame");
+            };
+            let header: RatchetHeader = bincode::deserialize(&header).unwrap();
+            let pt = receivers.get_mut(&peer_id).unwrap().decrypt(&header, &nonce, &ciphertext).unwrap();
+            // An old client gets exactly what it always did
+            assert_eq!(ratchet::is_padded(&pt), peer_id == "new");
+            assert_eq!(open_plaintext(&pt, "me", &status_tx).unwrap().content, "ok");
+        }
+    }
+
+    #[test]
+    fn test_a_message_is_from_whoever_sealed_it() {
+        // Bob's payload says Carol sent it: it still reaches the UI as Bob's
+        let (status_tx, mut status_rx) = mpsc::unbounded_channel();
+        let claimed = PlainMessage::verification("carol".to_string(), Verification::Request { challenge: vec![1; 16] });
+        let msg = open_plaintext(&encode_plain(&claimed).unwrap(), "bob", &status_tx).unwrap();
+        assert_eq!(msg.sender, "bob");
+        assert!(status_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue_frames_skips_failed_seals() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut errors = 0;
+        let frames = vec![
+            ("a".to_string(), Ok(vec![1u8])),
+            ("b".to_string(), Err(anyhow::anyhow!("boom"))),
+            ("c".to_string(), Ok(vec![2u8])),
+        ];
+        let mut scheduler = SendScheduler::default();
+        queue_frames(&mut scheduler, Lane::Bulk, frames, &tx, &mut errors, Some(Traffic::Files));
+        let mut sink = futures_util::sink::drain();
+        let stats = ClientStats { counters: Default::default(), peers: PeerMap::default() };
+        let mut sent = 0;
+        while let Some(frame) = scheduler.pop() {
+            write_frame(&mut sink, frame, &stats.counters).await.unwrap();
+            sent += 1;
+        }
+        assert_eq!(sent, 2);
+        assert_eq!(stats.snapshot().sent.files, 2);
+        assert_eq!(errors, 1);
+        assert!(rx.try_recv().unwrap().to_string().contains("boom"));
+    }
+
+    #[test]
+    fn test_session_id_follows_the_identity_unless_ephemeral() {
+        let identity = Identity::generate();
+        let first = ChatClient::new(identity.clone_for_thread(), String::new(), None);
+        let mut second = ChatClient::new(identity, String::new(), None);
+        assert_eq!(first.session_id(), second.session_id());
+        assert_eq!(first.session_id().len(), 32);
+        assert_ne!(first.session_id(), ChatClient::new(Identity::generate(), String::new(), None).session_id());
+
+        second.set_ephemeral_session();
+        assert_ne!(first.session_id(), second.session_id());
+        assert_eq!(second.session_id().len(), 32);
+    }
+}
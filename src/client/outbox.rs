@@ -0,0 +1,201 @@
+//! Bounded outbound queue between the TUI and the websocket sender task.
+//!
+//! Each kind of traffic gets its own policy so a stalled socket can't grow memory:
+//! - chat messages: generous bound, overflow is reported on the status channel
+//! - control (plaintext signals, call keys): the same, but ahead of chat
+//! - bulk (file chunks): small bound, producers `send_bulk().await` for flow control
+//! - audio frames: drop-oldest ring — stale voice is worse than lost voice
+//!
+//! Audio and control are urgent: the sender task takes them between the frames of a
+//! message it's writing (see scheduler.rs), chat and bulk only once that's done.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, Notify};
+
+use super::{ClientStatus, OutgoingMessage};
+
+/// Max queued chat messages before sends start failing
+pub const CHAT_QUEUE: usize = 1024;
+/// Max queued control messages before sends start failing
+pub const CONTROL_QUEUE: usize = 256;
+/// Max queued bulk messages (file chunks) before producers wait
+pub const BULK_QUEUE: usize = 64;
+/// Max queued audio frames (20ms each) before the oldest is dropped
+pub const AUDIO_QUEUE: usize = 16;
+
+/// Why a message couldn't be queued
+#[derive(Debug, thiserror::Error)]
+pub enum SendError {
+    #[error("outbound queue full")]
+    Full,
+    #[error("client has shut down")]
+    Closed,
+}
+
+/// Drop-oldest queue for audio frames
+struct AudioQueue {
+    frames: Mutex<VecDeque<OutgoingMessage>>,
+    notify: Notify,
+}
+
+impl AudioQueue {
+    fn push(&self, msg: OutgoingMessage) {
+        let mut frames = self.frames.lock().unwrap_or_else(|e| e.into_inner());
+        if frames.len() >= AUDIO_QUEUE {
+            frames.pop_front();
+        }
+        frames.push_back(msg);
+        drop(frames);
+        self.notify.notify_one();
+    }
+
+    fn try_pop(&self) -> Option<OutgoingMessage> {
+        self.frames.lock().unwrap_or_else(|e| e.into_inner()).pop_front()
+    }
+
+    async fn pop(&self) -> OutgoingMessage {
+        loop {
+            if let Some(msg) = self.try_pop() {
+                return msg;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Sending half, held by the TUI (cheap to clone)
+#[derive(Clone)]
+pub struct OutgoingSender {
+    control: mpsc::Sender<OutgoingMessage>,
+    chat: mpsc::Sender<OutgoingMessage>,
+    bulk: mpsc::Sender<OutgoingMessage>,
+    audio: Arc<AudioQueue>,
+    status_tx: mpsc::UnboundedSender<ClientStatus>,
+}
+
+/// Receiving half, drained by the websocket sender task
+pub struct OutgoingReceiver {
+    control: mpsc::Receiver<OutgoingMessage>,
+    chat: mpsc::Receiver<OutgoingMessage>,
+    bulk: mpsc::Receiver<OutgoingMessage>,
+    audio: Arc<AudioQueue>,
+}
+
+/// Create the outbound queue. Overflow errors are reported on `status_tx`.
+pub fn outbox(status_tx: mpsc::UnboundedSender<ClientStatus>) -> (OutgoingSender, OutgoingReceiver) {
+    let (control_tx, control_rx) = mpsc::channel(CONTROL_QUEUE);
+    let (chat_tx, chat_rx) = mpsc::channel(CHAT_QUEUE);
+    let (bulk_tx, bulk_rx) = mpsc::channel(BULK_QUEUE);
+    let audio = Arc::new(AudioQueue {
+        frames: Mutex::new(VecDeque::with_capacity(AUDIO_QUEUE)),
+        notify: Notify::new(),
+    });
+    (
+        OutgoingSender { control: control_tx, chat: chat_tx, bulk: bulk_tx, audio: audio.clone(), status_tx },
+        OutgoingReceiver { control: control_rx, chat: chat_rx, bulk: bulk_rx, audio },
+    )
+}
+
+impl OutgoingSender {
+    /// Queue a message without waiting. Audio frames never fail (oldest is dropped);
+    /// anything else fails if its queue is full or the client has shut down.
+    pub fn send(&self, msg: OutgoingMessage) -> Result<(), SendError> {
+        let queue = match msg {
+            OutgoingMessage::Audio { .. } => {
+                self.audio.push(msg);
+                return Ok(());
+            }
+            // A call's key has to be in place before its first voice frame
+            OutgoingMessage::Signal(_) | OutgoingMessage::CallKey { .. } => &self.control,
+            _ => &self.chat,
+        };
+        match queue.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                let _ = self.status_tx.send("❌ Outbound queue full, message not sent".into());
+                Err(SendError::Full)
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(SendError::Closed),
+        }
+    }
+
+    /// Queue bulk data (file chunks), waiting for room so a slow socket
+    /// throttles the producer instead of buffering everything in memory.
+    pub async fn send_bulk(&self, msg: OutgoingMessage) -> Result<(), SendError> {
+        self.bulk.send(msg).await.map_err(|_| SendError::Closed)
+    }
+
+    /// Post a status line to the TUI (for producers running off the UI task)
+    pub fn report(&self, status: String) {
+        let _ = self.status_tx.send(ClientStatus::Event(status));
+    }
+}
+
+impl OutgoingReceiver {
+    /// Next message to put on the wire: audio first, then control, chat and bulk.
+    /// Returns `None` once the TUI has dropped its sender.
+    pub async fn recv(&mut self) -> Option<OutgoingMessage> {
+        tokio::select! {
+            biased;
+            msg = self.audio.pop() => Some(msg),
+            Some(msg) = self.control.recv() => Some(msg),
+            msg = self.chat.recv() => msg,
+            Some(msg) = self.bulk.recv() => Some(msg),
+        }
+    }
+
+    /// An audio frame or control message if one is waiting, without waiting for one
+    pub fn try_recv_urgent(&mut self) -> Option<OutgoingMessage> {
+        self.audio.try_pop().or_else(|| self.control.try_recv().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio(n: u8) -> OutgoingMessage {
+        OutgoingMessage::Audio { target_id: "peer".to_string(), data: vec![n] }
+    }
+
+    #[tokio::test]
+    async fn test_audio_drops_oldest() {
+        let (status_tx, _status_rx) = mpsc::unbounded_channel();
+        let (tx, mut rx) = outbox(status_tx);
+        for n in 0..(AUDIO_QUEUE as u8 + 4) {
+            tx.send(audio(n)).unwrap();
+        }
+        match rx.recv().await {
+            Some(OutgoingMessage::Audio { data, .. }) => assert_eq!(data, vec![4]),
+            _ => panic!("expected audio frame"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audio_preempts_chat_and_bulk() {
+        let (status_tx, _status_rx) = mpsc::unbounded_channel();
+        let (tx, mut rx) = outbox(status_tx);
+        tx.send_bulk(OutgoingMessage::JoinRoom { group_id: "bulk".to_string(), join_token: None }).await.unwrap();
+        tx.send(OutgoingMessage::JoinRoom { group_id: "chat".to_string(), join_token: None }).unwrap();
+        tx.send(OutgoingMessage::CallKey { peer_id: "peer".to_string(), salts: None }).unwrap();
+        tx.send(audio(1)).unwrap();
+
+        assert!(matches!(rx.recv().await, Some(OutgoingMessage::Audio { .. })));
+        assert!(matches!(rx.try_recv_urgent(), Some(OutgoingMessage::CallKey { .. })));
+        assert!(rx.try_recv_urgent().is_none());
+        assert!(matches!(rx.recv().await, Some(OutgoingMessage::JoinRoom { group_id, .. }) if group_id == "chat"));
+        assert!(matches!(rx.recv().await, Some(OutgoingMessage::JoinRoom { group_id, .. }) if group_id == "bulk"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_overflow_reported() {
+        let (status_tx, mut status_rx) = mpsc::unbounded_channel();
+        let (tx, _rx) = outbox(status_tx);
+        for _ in 0..CHAT_QUEUE {
+            tx.send(OutgoingMessage::JoinRoom { group_id: "g".to_string(), join_token: None }).unwrap();
+        }
+        assert!(tx.send(OutgoingMessage::JoinRoom { group_id: "g".to_string(), join_token: None }).is_err());
+        assert!(status_rx.try_recv().unwrap().to_string().contains("queue full"));
+    }
+}
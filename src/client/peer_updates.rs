@@ -0,0 +1,160 @@
+//! Peer list updates for the TUI. Whatever touches a peer marks it; at most once per
+//! PEER_UPDATE_INTERVAL the marked peers go out as one batch of deltas, so a burst of
+//! joins and nickname changes is one update and nobody else's key is copied again.
+//! Only a resync (the TUI asks after a reconnect) goes through every peer.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::sleep;
+
+use super::{PeerDisplay, PeerInfo, PeerMap};
+
+/// The most often the TUI is sent a batch
+const PEER_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// One change to the peer list the TUI shows
+#[derive(Clone, Debug)]
+pub enum PeerUpdate {
+    Added(String, PeerDisplay),
+    Changed(String, PeerDisplay),
+    /// Gone for now (see lifecycle.rs): an Added or Changed brings them back
+    Inactive(String, PeerDisplay),
+    Removed(String),
+}
+
+/// Peers touched since the last batch
+#[derive(Default)]
+pub(super) struct PeerChanges {
+    marked: Mutex<Marked>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct Marked {
+    ids: HashSet<String>,
+    /// Go through every peer, not just the marked ones
+    resync: bool,
+}
+
+impl PeerChanges {
+    /// `peer_id` joined, left or changed how they're shown
+    pub fn mark(&self, peer_id: &str) {
+        self.marked.lock().unwrap_or_else(|e| e.into_inner()).ids.insert(peer_id.to_string());
+        self.notify.notify_one();
+    }
+
+    /// Send the TUI everyone again with the next batch
+    pub fn resync(&self) {
+        self.marked.lock().unwrap_or_else(|e| e.into_inner()).resync = true;
+        self.notify.notify_one();
+    }
+
+    fn take(&self) -> Marked {
+        std::mem::take(&mut *self.marked.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// What the TUI gets to see of a peer (no crypto state)
+fn display(peer: &PeerInfo) -> PeerDisplay {
+    PeerDisplay { nickname: peer.nickname.clone(), public_key: peer.public_key.clone() }
+}
+
+/// The updates for what's marked, given the peers now and the ids the TUI has (which
+/// this keeps up to date)
+fn batch(marked: Marked, peers: &HashMap<String, PeerInfo>, shown: &mut HashSet<String>) -> Vec<PeerUpdate> {
+    let mut ids: Vec<String> = if marked.resync {
+        peers.keys().chain(shown.iter()).cloned().collect::<HashSet<_>>().into_iter().collect()
+    } else {
+        marked.ids.into_iter().collect()
+    };
+    ids.sort();
+    ids.into_iter().filter_map(|id| match peers.get(&id) {
+        Some(peer) if !peer.activity.is_active() => {
+            shown.insert(id.clone());
+            Some(PeerUpdate::Inactive(id, display(peer)))
+        }
+        Some(peer) if shown.insert(id.clone()) => Some(PeerUpdate::Added(id, display(peer))),
+        Some(peer) => Some(PeerUpdate::Changed(id, display(peer))),
+        None if shown.remove(&id) => Some(PeerUpdate::Removed(id)),
+        None => None,
+    }).collect()
+}
+
+/// Send batches to the TUI until it goes away
+pub(super) fn spawn_peer_updates(peers: PeerMap, changes: Arc<PeerChanges>, tx: mpsc::UnboundedSender<Vec<PeerUpdate>>) {
+    tokio::spawn(async move {
+        let mut shown = HashSet::new();
+        loop {
+            changes.notify.notified().await;
+            // Whatever else the burst touches comes along in the same batch
+            sleep(PEER_UPDATE_INTERVAL).await;
+            let updates = batch(changes.take(), &*peers.read().await, &mut shown);
+            if !updates.is_empty() && tx.send(updates).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ratchet::RatchetSession;
+    use super::super::rekey::SessionHealth;
+
+    fn peer(nickname: &str) -> PeerInfo {
+        PeerInfo {
+            ratchet: RatchetSession::init(&[1; 32], true),
+            nickname: Some(nickname.to_string()),
+            public_key: vec![1; 32],
+            health: SessionHealth::default(),
+            capabilities: 0,
+            activity: Default::default(),
+        }
+    }
+
+    fn names(updates: &[PeerUpdate]) -> Vec<String> {
+        updates.iter().map(|u| match u {
+            PeerUpdate::Added(id, p) => format!("+{}={}", id, p.nickname.as_deref().unwrap_or("")),
+            PeerUpdate::Changed(id, p) => format!("~{}={}", id, p.nickname.as_deref().unwrap_or("")),
+            PeerUpdate::Inactive(id, _) => format!("…{}", id),
+            PeerUpdate::Removed(id) => format!("-{}", id),
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn test_bursts_become_one_batch_of_deltas() {
+        let peers: PeerMap = Default::default();
+        let changes = Arc::new(PeerChanges::default());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        spawn_peer_updates(peers.clone(), changes.clone(), tx);
+
+        // Two joins and a nickname change in a burst arrive together
+        peers.write().await.insert("a".to_string(), peer("alice"));
+        changes.mark("a");
+        peers.write().await.insert("b".to_string(), peer("bob"));
+        changes.mark("b");
+        peers.write().await.get_mut("a").unwrap().nickname = Some("ally".to_string());
+        changes.mark("a");
+        let first = rx.recv().await.unwrap();
+        assert_eq!(names(&first), vec!["+a=ally", "+b=bob"]);
+
+        // Later changes carry only the peer that changed
+        peers.write().await.get_mut("b").unwrap().nickname = Some("rob".to_string());
+        changes.mark("b");
+        assert_eq!(names(&rx.recv().await.unwrap()), vec!["~b=rob"]);
+        peers.write().await.get_mut("a").unwrap().activity.gone();
+        changes.mark("a");
+        assert_eq!(names(&rx.recv().await.unwrap()), vec!["…a"]);
+        peers.write().await.remove("a");
+        changes.mark("a");
+        assert_eq!(names(&rx.recv().await.unwrap()), vec!["-a"]);
+
+        // A resync goes through everyone
+        changes.resync();
+        assert_eq!(names(&rx.recv().await.unwrap()), vec!["~b=rob"]);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Re-keying a pairwise session that has stopped decrypting. After repeated failures
+//! from one peer we offer them a new ratchet (a KeyExchange with `reset`), and both
+//! sides keep it beside the old one until a message from the other opens under it.
+//! Only then is the old session dropped, so an offer nobody can follow up with a
+//! working message (a forged or replayed one) changes nothing. The new session's keys
+//! take in the DH of the two offers' ratchet keys, so a frame recorded under an earlier
+//! session (which started from the same identity keys) can't stand in for that message.
+
+use anyhow::Result;
+use std::time::{Duration, Instant};
+
+use crate::crypto::ratchet::{RatchetHeader, RatchetSession};
+use crate::crypto::Identity;
+use crate::protocol::{codec, Message, PlainMessage, CAPABILITIES};
+
+use super::{encode_plain, seal_frame, PeerInfo};
+
+/// Decrypt failures from one peer, within FAILURE_WINDOW, that call for a new session
+const FAILURE_THRESHOLD: usize = 3;
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+/// Resets per peer per hour, offered or accepted, so two clients can't re-key in a loop
+const MAX_RESETS: usize = 3;
+const RESET_WINDOW: Duration = Duration::from_secs(3600);
+/// How long a new session waits for its first message before it's given up on
+const PENDING_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How a peer's session has been doing
+#[derive(Default)]
+pub(super) struct SessionHealth {
+    /// Recent frames from the peer that didn't open
+    failures: Vec<Instant>,
+    /// When recent resets started
+    resets: Vec<Instant>,
+    /// The replacement session, until a message opens under it
+    pending: Option<(RatchetSession, Instant)>,
+    /// When a frame from the peer last opened
+    last_opened: Option<Instant>,
+}
+
+impl SessionHealth {
+    /// Whether the session carried a message from the peer within `window`
+    pub(super) fn opened_within(&self, window: Duration) -> bool {
+        self.last_opened.is_some_and(|at| at.elapsed() < window)
+    }
+
+    /// Whether another reset fits in this hour's allowance
+    fn may_reset(&mut self, now: Instant) -> bool {
+        self.resets.retain(|at| now.duration_since(*at) < RESET_WINDOW);
+        self.resets.len() < MAX_RESETS
+    }
+
+    /// Count a frame that didn't open. True when it's time to offer a new session.
+    fn record_failure(&mut self, now: Instant) -> bool {
+        if self.pending.as_ref().is_some_and(|(_, at)| now.duration_since(*at) >= PENDING_TIMEOUT) {
+            self.pending = None;
+        }
+        self.failures.retain(|at| now.duration_since(*at) < FAILURE_WINDOW);
+        self.failures.push(now);
+        self.failures.len() >= FAILURE_THRESHOLD && self.pending.is_none() && self.may_reset(now)
+    }
+
+    /// Keep `session` aside until the peer proves it with a message
+    fn begin(&mut self, session: RatchetSession, now: Instant) {
+        self.resets.push(now);
+        self.failures.clear();
+        self.pending = Some((session, now));
+    }
+}
+
+/// What came of opening a frame from a peer
+pub(super) enum Decrypted {
+    Current(Vec<u8>),
+    /// Opened under the new session, which has now replaced the old one
+    Reset(Vec<u8>),
+    /// Didn't open; `offer_reset` when the failures call for a new session
+    Failed { error: anyhow::Error, offer_reset: bool },
+}
+
+/// Open a frame under the peer's session, or else under a new one waiting to be
+/// confirmed (which then takes over)
+pub(super) fn decrypt(peer: &mut PeerInfo, header: &RatchetHeader, nonce: &[u8], ciphertext: &[u8]) -> Decrypted {
+    let error = match peer.ratchet.decrypt(header, nonce, ciphertext) {
+        Ok(plaintext) => {
+            peer.health.failures.clear();
+            peer.health.last_opened = Some(Instant::now());
+            return Decrypted::Current(plaintext);
+        }
+        Err(e) => e,
+    };
+    // Until the peer's ratchet key is mixed in, the new session's keys are the ones
+    // every session with them started from
+    if let Some((ref mut session, _)) = peer.health.pending.as_mut().filter(|(session, _)| session.has_remote_dh()) {
+        if let Ok(plaintext) = session.decrypt(header, nonce, ciphertext) {
+            if let Some((session, _)) = peer.health.pending.take() {
+                peer.ratchet = session;
+            }
+            peer.health.failures.clear();
+            peer.health.last_opened = Some(Instant::now());
+            return Decrypted::Reset(plaintext);
+        }
+    }
+    let offer_reset = peer.health.record_failure(Instant::now());
+    Decrypted::Failed { error, offer_reset }
+}
+
+/// A fresh session with `peer_id`, from our identity key exchange with them
+fn new_session(identity: &Identity, own_id: &str, peer_id: &str, peer_public_key: &[u8]) -> Result<RatchetSession> {
+    let secret = identity.key_exchange(peer_public_key)?;
+    Ok(RatchetSession::init(&secret, own_id < peer_id))
+}
+
+/// Our reset offer to `peer_id`, carrying the new session's DH key
+fn reset_frame(identity: &Identity, own_id: &str, peer_id: &str, session: &RatchetSession) -> Result<Vec<u8>> {
+    let offer = Message::KeyExchange {
+        from: own_id.to_string(),
+        public_key: identity.public_key_bytes(),
+        dh_ratchet_key: session.public_key().to_vec(),
+        target: peer_id.to_string(),
+        reset: true,
+        capabilities: CAPABILITIES,
+    };
+    Ok(codec::encode(&offer)?)
+}
+
+/// Start re-keying with `peer_id`: set a new session aside and return the offer
+pub(super) fn offer_reset(identity: &Identity, own_id: &str, peer_id: &str, peer: &mut PeerInfo) -> Result<Vec<u8>> {
+    let session = new_session(identity, own_id, peer_id, &peer.public_key)?;
+    let frame = reset_frame(identity, own_id, peer_id, &session)?;
+    peer.health.begin(session, Instant::now());
+    Ok(frame)
+}
+
+/// `peer_id` offered a new session, or answered ours. Returns the frames to send:
+/// our own offer if we didn't have one out, then a first message sealed under the
+/// new session so the peer can confirm it. Nothing if they've reset too often.
+pub(super) fn on_reset_offer(
+    identity: &Identity,
+    own_id: &str,
+    peer_id: &str,
+    peer: &mut PeerInfo,
+    dh_ratchet_key: &[u8],
+) -> Result<Vec<Vec<u8>>> {
+    let Ok(dh_key) = <[u8; 32]>::try_from(dh_ratchet_key) else {
+        anyhow::bail!("reset offer without a ratchet key");
+    };
+    let mut frames = Vec::new();
+    if peer.health.pending.is_none() {
+        if !peer.health.may_reset(Instant::now()) {
+            return Ok(frames);
+        }
+        let session = new_session(identity, own_id, peer_id, &peer.public_key)?;
+        frames.push(reset_frame(identity, own_id, peer_id, &session)?);
+        peer.health.begin(session, Instant::now());
+    }
+    let Some((ref mut session, _)) = peer.health.pending else {
+        return Ok(frames);
+    };
+    // A second offer for the same reset (or a forged one) doesn't re-key it
+    if !session.has_remote_dh() {
+        session.mix_exchanged_dh(dh_key)?;
+    }
+    let confirm = encode_plain(&PlainMessage::session_reset(own_id.to_string()))?;
+    frames.push(seal_frame(session, own_id, peer_id, &confirm, false)?);
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::decode_bincode;
+
+    struct Side {
+        id: String,
+        identity: Identity,
+        peer: PeerInfo,
+    }
+
+    fn side(id: &str, identity: Identity, peer_id: &str, peer_identity: &Identity) -> Side {
+        let ratchet = new_session(&identity, id, peer_id, &peer_identity.public_key_bytes()).unwrap();
+        let peer = PeerInfo {
+            ratchet,
+            nickname: None,
+            public_key: peer_identity.public_key_bytes(),
+            health: SessionHealth::default(),
+            capabilities: 0,
+            activity: Default::default(),
+        };
+        Side { id: id.to_string(), identity, peer }
+    }
+
+    /// Hand a frame one side sent to the other
+    fn deliver(frame: &[u8], to: &mut Side) -> Option<Decrypted> {
+        match codec::decode(frame).unwrap() {
+            Message::Encrypted { header, nonce, ciphertext, .. } => {
+                let header = decode_bincode::<RatchetHeader>(&header).unwrap();
+                Some(decrypt(&mut to.peer, &header, &nonce, &ciphertext))
+            }
+            _ => None,
+        }
+    }
+
+    fn send(from: &mut Side, text: &str) -> Vec<u8> {
+        let plaintext = encode_plain(&PlainMessage::direct(from.id.clone(), text.to_string())).unwrap();
+        seal_frame(&mut from.peer.ratchet, &from.id, "x", &plaintext, false).unwrap()
+    }
+
+    #[test]
+    fn test_failures_lead_to_a_confirmed_new_session() {
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let mut alice = side("alice", a_identity.clone_for_thread(), "bob", &b_identity);
+        let mut bob = side("bob", b_identity, "alice", &a_identity);
+        // Alice has lost step with Bob: she holds a ratchet key he never had
+        alice.peer.ratchet.set_remote_dh([9; 32]);
+
+        let offers: Vec<bool> = (0..3).map(|i| {
+            let frame = send(&mut bob, &format!("m{}", i));
+            match deliver(&frame, &mut alice) {
+                Some(Decrypted::Failed { offer_reset, .. }) => offer_reset,
+                _ => panic!("opened under a broken session"),
+            }
+        }).collect();
+        assert_eq!(offers, vec![false, false, true]);
+
+        // Alice offers, Bob answers and confirms, Alice confirms back
+        let offer = offer_reset(&alice.identity, "alice", "bob", &mut alice.peer).unwrap();
+        let Message::KeyExchange { dh_ratchet_key, reset: true, target, .. } = codec::decode(&offer).unwrap() else { panic!() };
+        assert_eq!(target, "bob");
+        let from_bob = on_reset_offer(&bob.identity, "bob", "alice", &mut bob.peer, &dh_ratchet_key).unwrap();
+        let [answer, confirm] = &from_bob[..] else { panic!("{} frames", from_bob.len()) };
+        let Message::KeyExchange { dh_ratchet_key, .. } = codec::decode(answer).unwrap() else { panic!() };
+        let from_alice = on_reset_offer(&alice.identity, "alice", "bob", &mut alice.peer, &dh_ratchet_key).unwrap();
+        assert_eq!(from_alice.len(), 1);
+        assert!(matches!(deliver(confirm, &mut alice), Some(Decrypted::Reset(_))));
+        assert!(matches!(deliver(&from_alice[0], &mut bob), Some(Decrypted::Reset(_))));
+
+        // Both ways work again on the new session
+        let frame = send(&mut bob, "hi again");
+        assert!(matches!(deliver(&frame, &mut alice), Some(Decrypted::Current(_))));
+        let frame = send(&mut alice, "hello");
+        assert!(matches!(deliver(&frame, &mut bob), Some(Decrypted::Current(_))));
+    }
+
+    #[test]
+    fn test_a_frame_from_an_earlier_session_does_not_confirm_a_reset() {
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let mut alice = side("alice", a_identity.clone_for_thread(), "bob", &b_identity);
+        let mut bob = side("bob", b_identity, "alice", &a_identity);
+        let old = send(&mut bob, "recorded");
+        assert!(matches!(deliver(&old, &mut alice), Some(Decrypted::Current(_))));
+        let Message::Encrypted { header, .. } = codec::decode(&old).unwrap() else { panic!() };
+        let old_dh = decode_bincode::<RatchetHeader>(&header).unwrap().dh_public;
+
+        // Alice offers a reset; the recording doesn't open under it while it waits...
+        offer_reset(&alice.identity, "alice", "bob", &mut alice.peer).unwrap();
+        assert!(matches!(deliver(&old, &mut alice), Some(Decrypted::Failed { .. })));
+
+        // ...nor once a forged answer names the recording's ratchet key
+        on_reset_offer(&alice.identity, "alice", "bob", &mut alice.peer, &old_dh).unwrap();
+        assert!(matches!(deliver(&old, &mut alice), Some(Decrypted::Failed { .. })));
+        assert!(alice.peer.health.pending.is_some());
+    }
+
+    #[test]
+    fn test_resets_are_rate_limited() {
+        let mut health = SessionHealth::default();
+        let start = Instant::now();
+        let shared = [1u8; 32];
+        for round in 0..MAX_RESETS {
+            let now = start + Duration::from_secs(round as u64 * 600);
+            assert!(!health.record_failure(now) && !health.record_failure(now));
+            assert!(health.record_failure(now));
+            health.begin(RatchetSession::init(&shared, true), now);
+            // An unconfirmed session is given up on after a while
+            assert!(!health.record_failure(now + PENDING_TIMEOUT));
+            assert!(health.pending.is_none());
+        }
+        let later = start + Duration::from_secs(1800);
+        assert!(!(0..5).any(|_| health.record_failure(later)));
+        // An hour on, the first reset no longer counts
+        let hour = start + RESET_WINDOW;
+        assert_eq!((0..FAILURE_THRESHOLD).map(|_| health.record_failure(hour)).last(), Some(true));
+    }
+}
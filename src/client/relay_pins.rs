@@ -0,0 +1,179 @@
+//! Knowing the relay again. Every Connect carries a fresh challenge, and the Ack
+//! answers with the relay's key and proof that it holds it (see `crypto::relay_key`).
+//! The first key seen for a relay URL is pinned in `known_relays.json` beside the
+//! identity; after that, a relay answering with another key is refused outright, the
+//! way ssh refuses a changed host key, since that's what a hijacked name or a host
+//! taken over would look like. `--expect-relay` names the key up front instead.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::crypto::relay_key::{relay_fingerprint, same_fingerprint, RelayChallenge};
+
+pub(super) type SharedRelayPins = Arc<Mutex<RelayPins>>;
+
+/// Why we won't use the relay that answered
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum RelayMismatch {
+    #[error("🚨 The relay at {url} claims key {fingerprint} but couldn't prove it holds it")]
+    Unproven { url: String, fingerprint: String },
+    #[error(
+        "🚨 RELAY KEY CHANGED for {url}: it was {pinned}, now it's {fingerprint}. Something may be \
+         posing as the relay. If its operator says the key changed, remove the relay from {file}"
+    )]
+    Changed { url: String, pinned: String, fingerprint: String, file: String },
+    #[error("🚨 The relay at {url} is {fingerprint}, not the {expected} --expect-relay asked for")]
+    Unexpected { url: String, fingerprint: String, expected: String },
+    #[error("🚨 The relay at {url} doesn't say which relay it is (it's an older version), so --expect-relay can't be met")]
+    Anonymous { url: String },
+}
+
+/// How a relay's Ack checked out
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Checked {
+    /// The key we have for it, or the one asked for
+    Known,
+    /// Its first visit: the key is pinned from now on
+    FirstSeen { fingerprint: String },
+    /// A relay from before keys; nothing to check
+    Anonymous,
+}
+
+/// The relay keys we know, by URL
+#[derive(Debug, Default)]
+pub(super) struct RelayPins {
+    /// Where they're kept (None: in memory, for this run only)
+    path: Option<PathBuf>,
+    /// URL to hex public key
+    known: BTreeMap<String, String>,
+    /// `--expect-relay`
+    expect: Option<String>,
+}
+
+impl RelayPins {
+    /// The pins in `path`, if it has any
+    pub fn load(path: Option<PathBuf>, expect: Option<String>) -> Self {
+        let known = path.as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self { path, known, expect }
+    }
+
+    /// Check the Ack from `url` against the `challenge` we sent it as `session_id`
+    pub fn check(
+        &mut self,
+        url: &str,
+        challenge: &RelayChallenge,
+        session_id: &str,
+        relay_key: &[u8],
+        proof: &[u8],
+    ) -> Result<Checked, RelayMismatch> {
+        let url = url.trim_end_matches('/').to_string();
+        if relay_key.is_empty() {
+            // A relay we know by its key doesn't get to stop saying which it is
+            if let Some(pinned) = self.known.get(&url) {
+                return Err(self.changed(url.clone(), pinned, "no key at all".to_string()));
+            }
+            return match self.expect {
+                Some(_) => Err(RelayMismatch::Anonymous { url }),
+                None => Ok(Checked::Anonymous),
+            };
+        }
+        let fingerprint = relay_fingerprint(relay_key);
+        if !challenge.verify(relay_key, session_id, proof) {
+            return Err(RelayMismatch::Unproven { url, fingerprint });
+        }
+        let key = hex::encode(relay_key);
+        if let Some(ref expected) = self.expect {
+            if !same_fingerprint(expected, &fingerprint) {
+                return Err(RelayMismatch::Unexpected { url, fingerprint, expected: expected.clone() });
+            }
+            // Asked for by name, so it's the one to know this relay by from now on
+            if self.known.get(&url) != Some(&key) {
+                self.pin(url, key);
+            }
+            return Ok(Checked::Known);
+        }
+        match self.known.get(&url) {
+            Some(pinned) if *pinned == key => Ok(Checked::Known),
+            Some(pinned) => Err(self.changed(url.clone(), pinned, fingerprint)),
+            None => {
+                self.pin(url, key);
+                Ok(Checked::FirstSeen { fingerprint })
+            }
+        }
+    }
+
+    /// The relay at `url`, pinned as the hex key `pinned`, answered as `fingerprint`
+    fn changed(&self, url: String, pinned: &str, fingerprint: String) -> RelayMismatch {
+        RelayMismatch::Changed {
+            url,
+            pinned: hex::decode(pinned).map(|pinned| relay_fingerprint(&pinned)).unwrap_or_else(|_| pinned.to_string()),
+            fingerprint,
+            file: self.path.as_ref().map_or_else(|| "memory".to_string(), |path| path.display().to_string()),
+        }
+    }
+
+    /// Know `url` by `key`, on disk too if we keep them there (best effort: a pin that
+    /// can't be written still holds for this run)
+    fn pin(&mut self, url: String, key: String) {
+        self.known.insert(url, key);
+        if let Some(ref path) = self.path {
+            if let Ok(json) = serde_json::to_string_pretty(&self.known) {
+                let _ = std::fs::write(path, json + "\n");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::relay_key::RelayKey;
+
+    /// What `relay` answers to a fresh challenge
+    fn ack(relay: &RelayKey) -> (RelayChallenge, Vec<u8>, Vec<u8>) {
+        let challenge = RelayChallenge::default();
+        let proof = relay.prove(&challenge.bytes(), "me").unwrap();
+        (challenge, relay.public_key_bytes(), proof)
+    }
+
+    #[test]
+    fn test_relay_is_pinned_on_first_use_and_a_swap_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_relays.json");
+        let (relay, impostor) = (RelayKey::generate(), RelayKey::generate());
+        let url = "wss://relay.example";
+
+        let mut pins = RelayPins::load(Some(path.clone()), None);
+        let (challenge, key, proof) = ack(&relay);
+        let first = pins.check(url, &challenge, "me", &key, &proof).unwrap();
+        assert_eq!(first, Checked::FirstSeen { fingerprint: relay.fingerprint() });
+        assert_eq!(pins.check(url, &challenge, "me", &key, &proof).unwrap(), Checked::Known);
+
+        // Remembered across runs; another key under the same URL is refused
+        let mut pins = RelayPins::load(Some(path.clone()), None);
+        let (challenge, key, proof) = ack(&impostor);
+        let changed = pins.check(&format!("{}/", url), &challenge, "me", &key, &proof).unwrap_err();
+        assert!(matches!(changed, RelayMismatch::Changed { ref pinned, .. } if *pinned == relay.fingerprint()));
+        assert!(changed.to_string().contains(&path.display().to_string()));
+
+        // Claiming the real key without holding it gets nowhere either
+        let (challenge, _, proof) = ack(&impostor);
+        let unproven = pins.check(url, &challenge, "me", &relay.public_key_bytes(), &proof);
+        assert!(matches!(unproven, Err(RelayMismatch::Unproven { .. })));
+
+        // Nor does answering with no key at all, once the URL has a pin
+        let anonymous = pins.check(url, &challenge, "me", &[], &[]);
+        assert!(matches!(anonymous, Err(RelayMismatch::Changed { ref pinned, .. }) if *pinned == relay.fingerprint()));
+
+        // --expect-relay: only that key will do, and a relay too old to say isn't enough
+        let mut strict = RelayPins::load(None, Some(impostor.fingerprint()));
+        let (challenge, key, proof) = ack(&relay);
+        assert!(matches!(strict.check(url, &challenge, "me", &key, &proof), Err(RelayMismatch::Unexpected { .. })));
+        assert!(matches!(strict.check(url, &challenge, "me", &[], &[]), Err(RelayMismatch::Anonymous { .. })));
+        assert_eq!(RelayPins::load(None, None).check(url, &challenge, "me", &[], &[]).unwrap(), Checked::Anonymous);
+    }
+}
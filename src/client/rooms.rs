@@ -0,0 +1,62 @@
+//! Group rooms we've asked the relay to put us in. The relay forgets a session's rooms
+//! when its connection drops, so each new connection joins them all again (with their
+//! join tokens) right behind Connect, before anything queued for the rooms goes out.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::protocol::Message;
+
+pub(super) type SharedRooms = Arc<Mutex<JoinedRooms>>;
+
+/// Rooms by group id, each with the token it was joined with
+#[derive(Debug, Default)]
+pub(super) struct JoinedRooms {
+    rooms: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+impl JoinedRooms {
+    pub fn joined(&mut self, group_id: &str, join_token: Option<Vec<u8>>) {
+        self.rooms.insert(group_id.to_string(), join_token);
+    }
+
+    pub fn left(&mut self, group_id: &str) {
+        self.rooms.remove(group_id);
+    }
+
+    /// GroupJoin for every room, as `session_id`
+    pub fn rejoin(&self, session_id: &str) -> Vec<Message> {
+        self.rooms.iter()
+            .map(|(group_id, join_token)| Message::GroupJoin {
+                session_id: session_id.to_string(),
+                group_id: group_id.clone(),
+                join_token: join_token.clone(),
+            })
+            .collect()
+    }
+
+    pub fn group_ids(&self) -> Vec<String> {
+        self.rooms.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rooms_are_rejoined_with_their_tokens_until_left() {
+        let mut rooms = JoinedRooms::default();
+        rooms.joined("g1", Some(vec![1; 16]));
+        rooms.joined("g2", None);
+        rooms.joined("g3", None);
+        rooms.left("g2");
+
+        let joins = rooms.rejoin("me");
+        assert!(matches!(&joins[..], [
+            Message::GroupJoin { group_id: g1, join_token: Some(token), .. },
+            Message::GroupJoin { group_id: g3, join_token: None, session_id },
+        ] if g1 == "g1" && *token == vec![1; 16] && g3 == "g3" && session_id == "me"));
+        assert_eq!(rooms.group_ids(), ["g1", "g3"]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Which frame the sender task writes next.
+//!
+//! The outbox hands over one message at a time (voice and control first, then chat,
+//! then bulk), but a message can seal to several frames: a file chunk sent to Global
+//! is one 16KB frame per peer. The sender used to write them all before looking at the
+//! outbox again, so a 20ms voice frame could sit behind a whole fan-out of chunks.
+//! Now each frame is written (and flushed) on its own, and voice and control frames
+//! that turned up in the meantime go out ahead of the rest: voice waits for at most
+//! the one bulk frame already on its way.
+//!
+//! Chat and bulk frames never overtake one another's messages: the sender only takes
+//! the next message once `pop` has run dry, so frames under a ratchet or a sender key
+//! go out in the order they were sealed (a group's new sender key reaches members
+//! before the message that needs it).
+
+use std::collections::VecDeque;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use super::Traffic;
+use crate::protocol::PlainMessage;
+
+/// How urgent a frame is, most urgent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Lane {
+    /// Voice, pings, key exchange replies and plaintext signals
+    Urgent,
+    Chat,
+    /// File chunks
+    Bulk,
+}
+
+impl Lane {
+    /// The lane for the frames carrying `message`
+    pub fn of(message: &PlainMessage) -> Lane {
+        if message.file_chunk.is_some() {
+            Lane::Bulk
+        } else {
+            Lane::Chat
+        }
+    }
+}
+
+/// A sealed frame waiting for the socket
+#[derive(Debug)]
+pub(super) struct Frame {
+    pub data: WsMessage,
+    /// What it counts as in the stats
+    pub kind: Option<Traffic>,
+}
+
+impl Frame {
+    pub fn new(data: Vec<u8>, kind: Option<Traffic>) -> Self {
+        Self { data: WsMessage::Binary(data), kind }
+    }
+}
+
+/// Frames waiting to be written, by lane
+#[derive(Debug, Default)]
+pub(super) struct SendScheduler {
+    lanes: [VecDeque<Frame>; 3],
+}
+
+impl SendScheduler {
+    pub fn push(&mut self, lane: Lane, frame: Frame) {
+        self.lanes[lane as usize].push_back(frame);
+    }
+
+    /// The next frame to write: the oldest of the most urgent lane
+    pub fn pop(&mut self) -> Option<Frame> {
+        self.lanes.iter_mut().find_map(VecDeque::pop_front)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame stamped with the (simulated) millisecond it was queued
+    fn stamped(at: u64, len: usize, kind: Traffic) -> Frame {
+        let mut data = at.to_be_bytes().to_vec();
+        data.resize(len, 0);
+        Frame::new(data, Some(kind))
+    }
+
+    #[test]
+    fn test_voice_waits_for_at_most_one_bulk_frame() {
+        // A 16KB chunk to four peers, on a link that writes one in 8ms; voice every 20ms
+        const WRITE_BULK_MS: u64 = 8;
+        const FANOUT: usize = 4;
+        let mut scheduler = SendScheduler::default();
+        let (mut now, mut next_voice, mut worst, mut voice_sent, mut bulk_sent) = (0u64, 0u64, 0u64, 0, 0);
+
+        while now < 2_000 {
+            while next_voice <= now {
+                scheduler.push(Lane::Urgent, stamped(next_voice, 80, Traffic::Voice));
+                next_voice += 20;
+            }
+            // The transfer never lets up: there's always another chunk in the outbox
+            let Some(frame) = scheduler.pop() else {
+                for _ in 0..FANOUT {
+                    scheduler.push(Lane::Bulk, stamped(now, 16 * 1024, Traffic::Files));
+                }
+                continue;
+            };
+            let WsMessage::Binary(ref data) = frame.data else { unreachable!() };
+            if frame.kind == Some(Traffic::Voice) {
+                let queued = u64::from_be_bytes(data[..8].try_into().unwrap());
+                worst = worst.max(now - queued);
+                voice_sent += 1;
+            } else {
+                bulk_sent += 1;
+                now += WRITE_BULK_MS;
+            }
+        }
+
+        // Every voice frame went out, none later than one chunk's write, and the
+        // transfer still had the rest of the link
+        assert_eq!(voice_sent, 100);
+        assert!(worst <= WRITE_BULK_MS, "voice waited {}ms", worst);
+        assert_eq!(bulk_sent, 250);
+    }
+}
@@ -0,0 +1,245 @@
+//! Connection statistics for /stats: counters bumped by the send and receive tasks
+//! (atomics only, so the hot paths never wait on a lock), read through a handle the
+//! TUI keeps. Traffic is split by what it carried where the send and receive paths
+//! know that; whatever isn't chat, files or voice is protocol overhead. Byte counts
+//! are what crossed the wire; on a deflating connection the frames' own sizes are
+//! kept too, and the split by kind is of those.
+
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::crypto::ratchet::RatchetStats;
+use crate::protocol::PlainMessage;
+
+use super::PeerMap;
+
+/// Counters shared with the connection tasks (persist across reconnects)
+#[derive(Debug, Default)]
+pub(super) struct Counters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    /// The same before deflating and after inflating
+    frame_bytes_sent: AtomicU64,
+    frame_bytes_received: AtomicU64,
+    /// `Compression` of the current connection
+    compression: AtomicU8,
+    frames_sent: AtomicU64,
+    frames_received: AtomicU64,
+    audio_frames_sent: AtomicU64,
+    audio_frames_received: AtomicU64,
+    reconnects: AtomicU64,
+    /// Bytes sent and received per `Traffic` kind
+    kind_sent: [AtomicU64; 3],
+    kind_received: [AtomicU64; 3],
+    /// Unix time of the last frame the relay passed on (0: none yet)
+    last_received: AtomicI64,
+    /// Unix time we last sent chat, which peers answer with read receipts
+    last_chat_sent: AtomicI64,
+}
+
+/// What a frame carried, for splitting the totals
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Traffic {
+    Chat,
+    Files,
+    Voice,
+}
+
+impl Traffic {
+    /// What `msg` counts as (None: protocol overhead, like typing and key updates)
+    pub fn of(msg: &PlainMessage) -> Option<Traffic> {
+        if msg.file_offer.is_some() || msg.file_chunk.is_some() || msg.file_response.is_some() {
+            Some(Traffic::Files)
+        } else if (!msg.system && !msg.content.is_empty()) || msg.history.is_some() {
+            Some(Traffic::Chat)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether frames to and from the relay are deflated (`--compress`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Not asked for
+    #[default]
+    Off,
+    /// Asked for, but the relay doesn't deflate
+    Refused,
+    On,
+}
+
+/// Bytes one way, split by kind
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrafficSplit {
+    pub chat: u64,
+    pub files: u64,
+    pub voice: u64,
+    pub overhead: u64,
+}
+
+impl TrafficSplit {
+    fn of(total: u64, kinds: &[AtomicU64; 3]) -> Self {
+        let [chat, files, voice] = kinds.each_ref().map(|n| n.load(Ordering::Relaxed));
+        TrafficSplit { chat, files, voice, overhead: total.saturating_sub(chat + files + voice) }
+    }
+}
+
+impl Counters {
+    /// A frame of `frame_bytes` went out as `bytes` (fewer if it was deflated)
+    pub fn frame_sent(&self, bytes: usize, frame_bytes: usize) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.frame_bytes_sent.fetch_add(frame_bytes as u64, Ordering::Relaxed);
+    }
+
+    /// `bytes` came in carrying a frame of `frame_bytes` (more if it was deflated)
+    pub fn frame_received(&self, bytes: usize, frame_bytes: usize) {
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.frame_bytes_received.fetch_add(frame_bytes as u64, Ordering::Relaxed);
+        self.last_received.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    /// A frame of `bytes` that went out carrying `kind` (already counted by frame_sent)
+    pub fn kind_sent(&self, kind: Traffic, bytes: usize) {
+        self.kind_sent[kind as usize].fetch_add(bytes as u64, Ordering::Relaxed);
+        if kind == Traffic::Chat {
+            self.last_chat_sent.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+        }
+    }
+
+    /// A frame of `bytes` that came in carrying `kind` (already counted by frame_received)
+    pub fn kind_received(&self, kind: Traffic, bytes: usize) {
+        self.kind_received[kind as usize].fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn audio_sent(&self) {
+        self.audio_frames_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn audio_received(&self) {
+        self.audio_frames_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// What the relay agreed to for the connection just made
+    pub fn set_compression(&self, compression: Compression) {
+        self.compression.store(compression as u8, Ordering::Relaxed);
+    }
+
+    pub fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Seconds (as of `now`) since the relay last passed anything on, if we've sent
+    /// chat after that: a link that should have brought something back by now
+    pub fn unanswered_for(&self, now: i64) -> Option<u64> {
+        let last = self.last_received.load(Ordering::Relaxed);
+        (self.last_chat_sent.load(Ordering::Relaxed) > last).then(|| now.saturating_sub(last).max(0) as u64)
+    }
+
+    /// Whether the relay has passed on nothing for `window` though we sent it chat
+    pub fn gone_quiet(&self, now: i64, window: Duration) -> bool {
+        self.unanswered_for(now).is_some_and(|secs| secs >= window.as_secs())
+    }
+}
+
+/// What the client has done this session, as of one moment
+#[derive(Debug, Clone, Default)]
+pub struct StatsSnapshot {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Frame sizes before deflating / after inflating (the same as the above when off)
+    pub frame_bytes_sent: u64,
+    pub frame_bytes_received: u64,
+    pub compression: Compression,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub audio_frames_sent: u64,
+    pub audio_frames_received: u64,
+    pub reconnects: u64,
+    /// Unix time of the last frame from the relay, if any came
+    pub last_received: Option<i64>,
+    pub sent: TrafficSplit,
+    pub received: TrafficSplit,
+    /// Ratchet state per peer, or None if the peer table was busy
+    pub ratchets: Option<Vec<(String, RatchetStats)>>,
+}
+
+/// Read-only view of a client's statistics, cheap to clone
+#[derive(Clone)]
+pub struct ClientStats {
+    pub(super) counters: Arc<Counters>,
+    pub(super) peers: PeerMap,
+}
+
+impl ClientStats {
+    /// Current totals. Never waits: if the peer table is locked right now the
+    /// ratchet section is left out.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let c = &self.counters;
+        let ratchets = self.peers.try_read().ok().map(|peers| {
+            let mut ratchets: Vec<_> = peers.iter().map(|(id, peer)| (id.clone(), peer.ratchet.stats())).collect();
+            ratchets.sort_by(|a, b| a.0.cmp(&b.0));
+            ratchets
+        });
+        let (bytes_sent, bytes_received) = self.totals();
+        let frame_bytes_sent = c.frame_bytes_sent.load(Ordering::Relaxed);
+        let frame_bytes_received = c.frame_bytes_received.load(Ordering::Relaxed);
+        let compression = match c.compression.load(Ordering::Relaxed) {
+            1 => Compression::Refused,
+            2 => Compression::On,
+            _ => Compression::Off,
+        };
+        StatsSnapshot {
+            bytes_sent,
+            bytes_received,
+            frame_bytes_sent,
+            frame_bytes_received,
+            compression,
+            frames_sent: c.frames_sent.load(Ordering::Relaxed),
+            frames_received: c.frames_received.load(Ordering::Relaxed),
+            audio_frames_sent: c.audio_frames_sent.load(Ordering::Relaxed),
+            audio_frames_received: c.audio_frames_received.load(Ordering::Relaxed),
+            reconnects: c.reconnects.load(Ordering::Relaxed),
+            last_received: Some(c.last_received.load(Ordering::Relaxed)).filter(|at| *at > 0),
+            sent: TrafficSplit::of(frame_bytes_sent, &c.kind_sent),
+            received: TrafficSplit::of(frame_bytes_received, &c.kind_received),
+            ratchets,
+        }
+    }
+
+    /// Seconds the relay has been quiet since we last sent chat (None: it answered)
+    pub fn unanswered_for(&self) -> Option<u64> {
+        self.counters.unanswered_for(chrono::Utc::now().timestamp())
+    }
+
+    /// Bytes sent and received so far (cheap enough to read every second)
+    pub fn totals(&self) -> (u64, u64) {
+        (self.counters.bytes_sent.load(Ordering::Relaxed), self.counters.bytes_received.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quiet_only_counts_after_we_sent_chat() {
+        let counters = Counters::default();
+        let window = Duration::from_secs(120);
+        counters.last_received.store(1_000, Ordering::Relaxed);
+        // Nothing sent, nothing owed: an idle link isn't a stale one
+        assert!(!counters.gone_quiet(5_000, window));
+
+        counters.last_chat_sent.store(1_010, Ordering::Relaxed);
+        assert_eq!(counters.unanswered_for(1_060), Some(60));
+        assert!(!counters.gone_quiet(1_060, window));
+        assert!(counters.gone_quiet(1_120, window));
+
+        // Anything arriving clears it
+        counters.last_received.store(1_130, Ordering::Relaxed);
+        assert_eq!(counters.unanswered_for(1_200), None);
+    }
+}
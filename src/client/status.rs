@@ -0,0 +1,187 @@
+//! Updates on the client's status channel: connection state changes, and free-text
+//! events worth showing the user.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::protocol::{short_id, ErrorCode};
+
+/// Where the connection to the relay stands
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    /// First connection attempt in progress
+    Connecting,
+    /// The relay acknowledged our session
+    Connected { relay: String },
+    /// The connection dropped; attempt `attempt` starts after `next_retry_in`
+    Reconnecting { attempt: u32, next_retry_in: Duration },
+    /// Not connected and not trying
+    Disconnected,
+    /// The relay turned us away for good; `reason` says what to do about it
+    Refused { reason: String },
+    /// The proxy (`--proxy`) failed us before the relay was reached; retried after
+    /// `next_retry_in`, or (None) not until its settings change
+    ProxyFailed { reason: String, next_retry_in: Option<Duration> },
+    /// The relay (or whatever is at its address) stalled connection setup; attempt
+    /// `attempt` starts after `next_retry_in`
+    Unresponsive { timeout: SetupTimeout, attempt: u32, next_retry_in: Duration },
+}
+
+impl ConnectionState {
+    /// Waiting to try the connection again
+    pub fn is_retrying(&self) -> bool {
+        matches!(self, Self::Reconnecting { .. } | Self::Unresponsive { .. } | Self::ProxyFailed { next_retry_in: Some(_), .. })
+    }
+}
+
+/// Connection setup that didn't finish in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum SetupTimeout {
+    /// No websocket handshake: a blackholed host, or something there that isn't a relay
+    #[error("relay did not accept the connection")]
+    Unreachable,
+    /// The websocket is up but our Connect went unanswered (lost, or an overloaded relay)
+    #[error("relay did not respond to handshake")]
+    NoAck,
+}
+
+/// The relay closed our connection and said why
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("relay closed the connection: {message}")]
+pub struct Refusal {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl Refusal {
+    /// What the user can do about a fatal refusal
+    pub fn advice(&self) -> String {
+        let hint = match self.code {
+            ErrorCode::Unauthorized => "check that the address is a wsp relay",
+            ErrorCode::MalformedFrames => "the relay may run a different version; try updating wsp",
+            ErrorCode::SessionReplaced => "another wsp chat with this identity took over; carry on there",
+            _ => "try again later",
+        };
+        format!("{} ({})", self.message, hint)
+    }
+}
+
+/// One update on the status channel
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientStatus {
+    /// The connection changed state
+    Connection(ConnectionState),
+    /// Something happened (key exchange, send failure, dropped message, ...)
+    Event(String),
+    /// The relay says `count` sessions are in a group room we're in
+    RoomPresence { group_id: String, count: u32 },
+    /// A session we asked the relay for (Discover) isn't connected to it
+    PeerNotFound { session_id: String },
+    /// A recipient confirmed one of our messages arrived
+    Delivered { message_id: String },
+    /// A message to a group went out without these members: we have no session with them
+    GroupSkipped { group_id: String, members: Vec<String> },
+    /// After a reconnect, we asked the relay to put us back in these group rooms
+    RoomsRejoined { group_ids: Vec<String> },
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connecting => write!(f, "Connecting..."),
+            Self::Connected { relay } => write!(f, "Connected to {}", relay),
+            Self::Reconnecting { attempt, next_retry_in } => write!(
+                f,
+                "Reconnecting (attempt {}, retry in {}s)",
+                attempt,
+                next_retry_in.as_secs_f32().ceil()
+            ),
+            Self::Disconnected => write!(f, "Disconnected"),
+            Self::Refused { reason } => write!(f, "Refused by relay: {}", reason),
+            Self::ProxyFailed { reason, next_retry_in: Some(retry) } => {
+                write!(f, "Proxy: {} (retry in {}s)", reason, retry.as_secs_f32().ceil())
+            }
+            Self::ProxyFailed { reason, next_retry_in: None } => write!(f, "Proxy: {}", reason),
+            Self::Unresponsive { timeout, attempt, next_retry_in } => write!(
+                f,
+                "Reconnecting: {} (attempt {}, retry in {}s)",
+                timeout,
+                attempt,
+                next_retry_in.as_secs_f32().ceil()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ClientStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connection(state) => state.fmt(f),
+            Self::Event(text) => f.write_str(text),
+            Self::RoomPresence { count, .. } => write!(f, "{} online", count),
+            Self::PeerNotFound { session_id } => write!(f, "{} is not connected to this relay", short_id(session_id)),
+            Self::Delivered { message_id } => write!(f, "Message {} delivered", message_id),
+            Self::GroupSkipped { members, .. } => {
+                let ids: Vec<_> = members.iter().map(|id| short_id(id)).collect();
+                write!(f, "Not sent to {}: no secure session", ids.join(", "))
+            }
+            Self::RoomsRejoined { group_ids } => {
+                let ids: Vec<_> = group_ids.iter().map(|id| short_id(id)).collect();
+                write!(f, "Rejoined group rooms {}", ids.join(", "))
+            }
+        }
+    }
+}
+
+impl From<String> for ClientStatus {
+    fn from(text: String) -> Self {
+        Self::Event(text)
+    }
+}
+
+impl From<&str> for ClientStatus {
+    fn from(text: &str) -> Self {
+        Self::Event(text.to_string())
+    }
+}
+
+impl From<ConnectionState> for ClientStatus {
+    fn from(state: ConnectionState) -> Self {
+        Self::Connection(state)
+    }
+}
+
+/// Host (and port) part of a relay URL, for display: `wss://relay.example:8899/ws` -> `relay.example:8899`
+pub fn relay_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    // Drop any userinfo
+    host.rsplit_once('@').map_or(host, |(_, host)| host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_host() {
+        assert_eq!(relay_host("ws://localhost:8899"), "localhost:8899");
+        assert_eq!(relay_host("wss://user:pw@relay.example/ws?x=1"), "relay.example");
+        assert_eq!(relay_host("relay.example"), "relay.example");
+    }
+
+    #[test]
+    fn test_display() {
+        let state = ConnectionState::Reconnecting { attempt: 2, next_retry_in: Duration::from_millis(1500) };
+        assert_eq!(ClientStatus::from(state).to_string(), "Reconnecting (attempt 2, retry in 2s)");
+        let state = ConnectionState::Unresponsive { timeout: SetupTimeout::NoAck, attempt: 1, next_retry_in: Duration::from_secs(1) };
+        assert!(state.is_retrying());
+        assert_eq!(state.to_string(), "Reconnecting: relay did not respond to handshake (attempt 1, retry in 1s)");
+        assert_eq!(ClientStatus::from("Reconnected").to_string(), "Reconnected");
+        let refusal = Refusal { code: ErrorCode::Unauthorized, message: "connect first".to_string() };
+        assert_eq!(
+            ConnectionState::Refused { reason: refusal.advice() }.to_string(),
+            "Refused by relay: connect first (check that the address is a wsp relay)"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! User settings kept next to the identity (`config.json` in `util::data_dir()`).
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::crypto::ratchet::Padding;
+use crate::tui::SoundSettings;
+
+/// Relay used when neither `--relay` nor the config names one
+pub const DEFAULT_RELAY: &str = "ws://localhost:8899";
+/// Minutes without a keypress before the chat shows you as away
+pub const DEFAULT_AWAY_AFTER_MINS: u64 = 10;
+
+/// Defaults for `wsp chat`; command-line flags take precedence
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub relay: Option<String>,
+    #[serde(default)]
+    pub nickname: Option<String>,
+    /// Cap on a single `/send`, in megabytes (default 1024)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_share_mb: Option<u64>,
+    /// Where a bare `/accept` saves files (default ~/Downloads/wsp, made when first needed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_dir: Option<String>,
+    /// Offers bigger than this many megabytes need a path with `/accept`, as
+    /// confirmation (default 100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_auto_size_mb: Option<u64>,
+    /// Ask group members for missed messages after a reconnect, and answer them (default on)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_sync: Option<bool>,
+    /// Minutes without a keypress before you're shown as away (default 10, 0 = never)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub away_after_mins: Option<u64>,
+    /// Sent once to each peer who DMs you while you're away (off unless set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub away_reply: Option<String>,
+    /// Join groups straight away when a verified peer invites us (default off: /join)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_join_verified: Option<bool>,
+    /// Send files no faster than this many kilobytes a second (default: as fast as the link goes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_rate_kbps: Option<u64>,
+    /// Slow files to this many kilobytes a second while in a call (default 32, 0 = don't)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub call_file_rate_kbps: Option<u64>,
+    /// Warn once a session has sent and received this many megabytes (off unless set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_warn_mb: Option<u64>,
+    /// Reconnect when the relay has passed on nothing for this many seconds after we
+    /// sent chat (default 120, 0 = never)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_silence_secs: Option<u64>,
+    /// Check on peers quiet for this many minutes, and leave those no longer connected
+    /// out of Global chat (default 10, 0 = never)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_idle_mins: Option<u64>,
+    /// Pad chat to a few fixed sizes so its length says less, for peers that can read
+    /// it (`"buckets"`, the default, or `"off"`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<Padding>,
+    /// Reach the relay through this proxy (`socks5://host:port` or `http://host:port`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Ask the relay to deflate frames both ways (default off); /stats shows what it saves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compress: Option<bool>,
+    /// Lobby-less: no Global chat, only DMs and groups (default off)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable_global: Option<bool>,
+    /// Bell, tone or nothing for each kind of event (default: the bell for DMs,
+    /// mentions, calls and file offers)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sounds: Option<SoundSettings>,
+    /// Language for the interface, e.g. `"de"` (default: from `LANG`, else English)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Settings for named profiles (`--profile <name>`), over the ones above
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+}
+
+/// What a profile can set for itself
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+impl Config {
+    /// Read the config at `path`; a missing file is an empty config
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid config file: {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+        }
+    }
+
+    /// The settings `profile` runs with: its own relay, nickname and proxy where it has
+    /// them, the top-level ones otherwise
+    pub fn for_profile(&self, profile: Option<&str>) -> Config {
+        let mut config = self.clone();
+        if let Some(own) = profile.and_then(|name| self.profiles.get(name)) {
+            config.relay = own.relay.clone().or(config.relay);
+            config.nickname = own.nickname.clone().or(config.nickname);
+            config.proxy = own.proxy.clone().or(config.proxy);
+        }
+        config
+    }
+
+    /// Save `relay` as `profile`'s (None: the top-level default)
+    pub fn set_relay(&mut self, profile: Option<&str>, relay: String) {
+        match profile {
+            Some(name) => self.profiles.entry(name.to_string()).or_default().relay = Some(relay),
+            None => self.relay = Some(relay),
+        }
+    }
+
+    /// Save `nickname` as `profile`'s (None: the top-level default)
+    pub fn set_nickname(&mut self, profile: Option<&str>, nickname: String) {
+        match profile {
+            Some(name) => self.profiles.entry(name.to_string()).or_default().nickname = Some(nickname),
+            None => self.nickname = Some(nickname),
+        }
+    }
+
+    /// Write the config to `path`, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, text + "\n").with_context(|| format!("Couldn't write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_config_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Config::load(&dir.path().join("config.json")).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("config.json");
+        let mut config = Config { relay: Some("wss://relay.example".to_string()), nickname: None, max_share_mb: Some(50), download_dir: Some("~/incoming".to_string()), max_auto_size_mb: Some(20), history_sync: Some(false), away_after_mins: Some(0), away_reply: Some("brb".to_string()), auto_join_verified: Some(true), file_rate_kbps: Some(200), call_file_rate_kbps: None, session_warn_mb: None, relay_silence_secs: Some(300), peer_idle_mins: None, padding: Some(Padding::Off), proxy: Some("socks5://127.0.0.1:9050".to_string()), compress: Some(true), disable_global: Some(true), sounds: None, language: Some("de".to_string()), profiles: BTreeMap::new() };
+        config.set_nickname(Some("work"), "alice-at-work".to_string());
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        // A profile takes what it doesn't set from the top level
+        let work = config.for_profile(Some("work"));
+        assert_eq!((work.relay.as_deref(), work.nickname.as_deref()), (Some("wss://relay.example"), Some("alice-at-work")));
+        assert_eq!(config.for_profile(Some("home")).nickname, None);
+
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(Config::load(&path).is_err());
+    }
+}
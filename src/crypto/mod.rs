@@ -1,9 +1,14 @@
+//! Identity keys, symmetric encryption helpers, the Double Ratchet, group sender keys,
+//! safety numbers and the relay's own key.
+
 pub mod ratchet;
+pub mod relay_key;
 pub mod safety_number;
+pub mod sender_key;
 
 use anyhow::Result;
 use chacha20poly1305::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, KeyInit, OsRng, Payload},
     ChaCha20Poly1305, Nonce,
 };
 use rand::RngCore;
@@ -12,6 +17,58 @@ use std::path::Path;
 use x25519_dalek::{PublicKey, StaticSecret};
 use zeroize::Zeroize;
 
+/// Start of an identity file with a checksum (files from before it are a bare nonce
+/// and ciphertext)
+const IDENTITY_MAGIC: &[u8; 8] = b"wspid\0\0\x01";
+/// Header: magic, then the save time (unix seconds, little-endian)
+const IDENTITY_HEADER: usize = IDENTITY_MAGIC.len() + 8;
+/// Footer: blake3 of everything before it
+const IDENTITY_FOOTER: usize = 32;
+const IDENTITY_NONCE: usize = 12;
+
+/// Why an identity file couldn't be opened, told apart so a damaged file isn't
+/// mistaken for a forgotten password
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum IdentityFileError {
+    #[error("identity file appears corrupted (checksum mismatch)")]
+    Corrupted,
+    #[error("Failed to decrypt identity: wrong password")]
+    WrongPassword,
+    /// A file from before checksums: either could be the cause
+    #[error("Failed to decrypt identity (wrong password, or a damaged file)")]
+    Undecryptable,
+}
+
+/// An identity file's nonce and ciphertext, and when it was saved (None for files
+/// from before checksums)
+struct Sealed<'a> {
+    nonce: &'a [u8],
+    ciphertext: &'a [u8],
+    saved_at: Option<i64>,
+}
+
+impl<'a> Sealed<'a> {
+    /// Split `data` up, checking the footer if it has one
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        let Some(body) = data.strip_prefix(IDENTITY_MAGIC) else {
+            anyhow::ensure!(data.len() > IDENTITY_NONCE, "Invalid identity file");
+            let (nonce, ciphertext) = data.split_at(IDENTITY_NONCE);
+            return Ok(Self { nonce, ciphertext, saved_at: None });
+        };
+        if body.len() <= IDENTITY_HEADER - IDENTITY_MAGIC.len() + IDENTITY_NONCE + IDENTITY_FOOTER {
+            return Err(IdentityFileError::Corrupted.into());
+        }
+        let (checked, footer) = data.split_at(data.len() - IDENTITY_FOOTER);
+        if blake3::hash(checked).as_bytes() != footer {
+            return Err(IdentityFileError::Corrupted.into());
+        }
+        let (saved_at, rest) = checked[IDENTITY_MAGIC.len()..].split_at(8);
+        let (nonce, ciphertext) = rest.split_at(IDENTITY_NONCE);
+        let saved_at = i64::from_le_bytes(saved_at.try_into().expect("8 bytes"));
+        Ok(Self { nonce, ciphertext, saved_at: Some(saved_at) })
+    }
+}
+
 /// User's identity keypair
 #[derive(Zeroize, Serialize, Deserialize)]
 #[zeroize(drop)]
@@ -54,7 +111,8 @@ impl Identity {
         Ok(key.as_bytes().to_vec())
     }
 
-    /// Save identity to disk (encrypted with password)
+    /// Save identity to disk (encrypted with password, with a checksum footer), with the
+    /// public key and a backup copy beside it
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P, password: &str) -> Result<()> {
         let serialized = bincode::serialize(self)?;
         
@@ -63,7 +121,7 @@ impl Identity {
         let cipher = ChaCha20Poly1305::new(key_hash.as_bytes().into());
         
         // Generate random nonce
-        let mut nonce_bytes = [0u8; 12];
+        let mut nonce_bytes = [0u8; IDENTITY_NONCE];
         rand::thread_rng().fill_bytes(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes);
         
@@ -71,29 +129,48 @@ impl Identity {
             .encrypt(nonce, serialized.as_ref())
             .map_err(|_| anyhow::anyhow!("Failed to encrypt identity"))?;
         
-        let mut output = nonce_bytes.to_vec();
+        let mut output = IDENTITY_MAGIC.to_vec();
+        output.extend(chrono::Utc::now().timestamp().to_le_bytes());
+        output.extend(nonce_bytes);
         output.extend(ciphertext);
+        let checksum = blake3::hash(&output);
+        output.extend(checksum.as_bytes());
         
-        std::fs::write(path, output)?;
+        std::fs::write(&path, &output)?;
+        std::fs::write(crate::util::identity_backup_path(path.as_ref()), &output)?;
+        self.save_public_key(path.as_ref())
+    }
+
+    /// Write the public key (base64) next to the identity file at `path`, so it can be
+    /// shown without the password
+    pub fn save_public_key(&self, path: &Path) -> Result<()> {
+        std::fs::write(crate::util::public_key_path(path), self.public_key_b64() + "\n")?;
         Ok(())
     }
 
-    /// Load identity from disk (decrypt with password)
+    /// Check the identity file at `path` without decrypting it: when it was saved, or
+    /// None if it's from before checksums. A damaged file is `IdentityFileError::Corrupted`.
+    pub fn check_file<P: AsRef<Path>>(path: P) -> Result<Option<i64>> {
+        let data = std::fs::read(path)?;
+        Ok(Sealed::parse(&data)?.saved_at)
+    }
+
+    /// Load identity from disk (decrypt with password). Failures that are the file's
+    /// fault or the password's come back as `IdentityFileError`.
     pub fn load_from_file<P: AsRef<Path>>(path: P, password: &str) -> Result<Self> {
         let data = std::fs::read(path)?;
-        
-        anyhow::ensure!(data.len() > 12, "Invalid identity file");
-        
-        let nonce = Nonce::from_slice(&data[..12]);
-        let ciphertext = &data[12..];
+        let sealed = Sealed::parse(&data)?;
+        let nonce = Nonce::from_slice(sealed.nonce);
         
         // Derive key from password
         let key_hash = blake3::hash(password.as_bytes());
         let cipher = ChaCha20Poly1305::new(key_hash.as_bytes().into());
         
-        let plaintext = cipher
-            .decrypt(nonce, ciphertext)
-            .map_err(|_| anyhow::anyhow!("Failed to decrypt identity (wrong password?)"))?;
+        // With the checksum intact, the only thing left to be wrong is the password
+        let plaintext = cipher.decrypt(nonce, sealed.ciphertext).map_err(|_| match sealed.saved_at {
+            Some(_) => IdentityFileError::WrongPassword,
+            None => IdentityFileError::Undecryptable,
+        })?;
         
         let identity = bincode::deserialize(&plaintext)?;
         Ok(identity)
@@ -102,6 +179,12 @@ impl Identity {
 
 /// Encrypt a message using ChaCha20Poly1305
 pub fn encrypt_message(key: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    encrypt_message_with_ad(key, plaintext, &[])
+}
+
+/// Encrypt a message using ChaCha20Poly1305, bound to associated data `ad` that
+/// decryption must be given again
+pub fn encrypt_message_with_ad(key: &[u8], plaintext: &[u8], ad: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
     anyhow::ensure!(key.len() == 32, "Key must be 32 bytes");
     
     let cipher = ChaCha20Poly1305::new(key.into());
@@ -112,7 +195,7 @@ pub fn encrypt_message(key: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>
     let nonce = Nonce::from_slice(&nonce_bytes);
     
     let ciphertext = cipher
-        .encrypt(nonce, plaintext)
+        .encrypt(nonce, Payload { msg: plaintext, aad: ad })
         .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
     
     Ok((nonce_bytes.to_vec(), ciphertext))
@@ -120,6 +203,11 @@ pub fn encrypt_message(key: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>
 
 /// Decrypt a message using ChaCha20Poly1305
 pub fn decrypt_message(key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    decrypt_message_with_ad(key, nonce, ciphertext, &[])
+}
+
+/// Decrypt a message sealed by [`encrypt_message_with_ad`] with the same `ad`
+pub fn decrypt_message_with_ad(key: &[u8], nonce: &[u8], ciphertext: &[u8], ad: &[u8]) -> Result<Vec<u8>> {
     anyhow::ensure!(key.len() == 32, "Key must be 32 bytes");
     anyhow::ensure!(nonce.len() == 12, "Nonce must be 12 bytes");
     
@@ -127,7 +215,7 @@ pub fn decrypt_message(key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Ve
     let nonce = Nonce::from_slice(nonce);
     
     let plaintext = cipher
-        .decrypt(nonce, ciphertext)
+        .decrypt(nonce, Payload { msg: ciphertext, aad: ad })
         .map_err(|_| anyhow::anyhow!("Decryption failed (wrong key or corrupted data)"))?;
     
     Ok(plaintext)
@@ -194,6 +282,46 @@ mod tests {
         assert_eq!(alice_shared, bob_shared);
     }
 
+    fn diagnosis(path: &Path, password: &str) -> Option<IdentityFileError> {
+        Identity::load_from_file(path, password).err()?.downcast_ref::<IdentityFileError>().copied()
+    }
+
+    #[test]
+    fn test_identity_file_damage_is_told_from_a_wrong_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity");
+        let identity = Identity::generate();
+        identity.save_to_file(&path, "pass").unwrap();
+        let good = std::fs::read(&path).unwrap();
+        assert_eq!(std::fs::read(crate::util::identity_backup_path(&path)).unwrap(), good);
+        assert!(Identity::check_file(&path).unwrap().is_some());
+        assert_eq!(Identity::load_from_file(&path, "pass").unwrap().public_key_bytes(), identity.public_key_bytes());
+        assert_eq!(diagnosis(&path, "wrong"), Some(IdentityFileError::WrongPassword));
+
+        let body = IDENTITY_HEADER + IDENTITY_NONCE;
+        let footer = good.len() - IDENTITY_FOOTER;
+        // Save time, nonce, ciphertext and footer: a flipped bit anywhere is damage
+        for at in [IDENTITY_MAGIC.len() + 2, IDENTITY_HEADER + 3, body + 5, good.len() - 40, footer + 7] {
+            let mut damaged = good.clone();
+            damaged[at] ^= 0x10;
+            std::fs::write(&path, &damaged).unwrap();
+            assert_eq!(diagnosis(&path, "pass"), Some(IdentityFileError::Corrupted), "byte {}", at);
+            assert!(Identity::check_file(&path).is_err());
+        }
+        // ...and so is a cut-off file, however short
+        for len in [good.len() - 1, footer, body, IDENTITY_MAGIC.len()] {
+            std::fs::write(&path, &good[..len]).unwrap();
+            assert_eq!(diagnosis(&path, "pass"), Some(IdentityFileError::Corrupted), "{} bytes", len);
+        }
+
+        // Files from before checksums still load, and can't tell the two apart
+        let legacy = &good[IDENTITY_HEADER..footer];
+        std::fs::write(&path, legacy).unwrap();
+        assert_eq!(Identity::check_file(&path).unwrap(), None);
+        assert!(Identity::load_from_file(&path, "pass").is_ok());
+        assert_eq!(diagnosis(&path, "wrong"), Some(IdentityFileError::Undecryptable));
+    }
+
     #[test]
     fn test_encryption() {
         let key = vec![0u8; 32];
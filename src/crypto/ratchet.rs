@@ -15,11 +15,27 @@ use hkdf::Hkdf;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use std::collections::VecDeque;
 use x25519_dalek::{PublicKey, StaticSecret};
 use zeroize::Zeroize;
 
-/// Max number of skipped message keys to store (prevents DoS via huge gaps)
+/// Most message keys skipped at once within the current receiving chain
 const MAX_SKIP: u32 = 100;
+/// Most skipped at once from the end of the previous chain, when the header's
+/// `prev_chain_len` says that many were sent there (a peer that kept writing while
+/// we were away)
+const MAX_PREV_CHAIN_SKIP: u32 = 1000;
+/// Most skipped message keys held; past this the oldest go first
+const MAX_STORED_SKIPPED: usize = 1000;
+
+/// First byte of a padded plaintext. Padded and unpadded messages can share a session
+/// as long as unpadded plaintexts never start with it (encoded PlainMessages don't:
+/// msgpack arrays start at 0x90).
+pub const PADDED: u8 = 0;
+/// Sizes padded plaintexts are rounded up to; past the largest, to a multiple of it
+const PAD_BUCKETS: [usize; 4] = [64, 256, 1024, 4096];
+/// The marker byte and the real length (u32 LE) ahead of the plaintext
+const PAD_PREFIX: usize = 5;
 
 /// Info strings for HKDF domain separation
 const KDF_RK_INFO: &[u8] = b"wsp-ratchet-root";
@@ -36,6 +52,31 @@ pub struct RatchetHeader {
     pub msg_num: u32,
 }
 
+/// How far a session's chains have run, for /stats (no key material)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RatchetStats {
+    /// Messages sent on the current sending chain
+    pub send_chain_len: u32,
+    /// Messages received on the current receiving chain
+    pub recv_chain_len: u32,
+    /// Message keys held for frames that haven't arrived yet
+    pub skipped_keys: usize,
+    /// Held keys that a late frame has since used
+    pub skipped_used: u64,
+    /// Held keys dropped to stay under the limit (their frames can't be read now)
+    pub skipped_evicted: u64,
+}
+
+/// Whether plaintexts are padded up to a bucket size before encryption, so their
+/// length says less about what they hold (`"padding"` in the config)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Padding {
+    #[default]
+    Buckets,
+    Off,
+}
+
 /// A skipped message key, indexed by (DH public key, message number)
 #[derive(Hash, Eq, PartialEq, Clone)]
 struct SkippedKey {
@@ -44,6 +85,7 @@ struct SkippedKey {
 }
 
 /// The Double Ratchet session state for one peer
+#[derive(Clone)]
 pub struct RatchetSession {
     // DH ratchet state
     dh_self_secret: [u8; 32],   // Our current ephemeral secret key (raw bytes)
@@ -64,8 +106,11 @@ pub struct RatchetSession {
     // Previous sending chain length (included in headers)
     prev_chain_len: u32,
 
-    // Skipped message keys for out-of-order delivery
-    skipped_keys: std::collections::HashMap<SkippedKey, [u8; 32]>,
+    // Skipped message keys for out-of-order delivery, oldest first: chains in the
+    // order we left them, and message numbers in order within each
+    skipped_keys: VecDeque<(SkippedKey, [u8; 32])>,
+    skipped_used: u64,
+    skipped_evicted: u64,
 
     // Whether we are "Alice" (the party who initiates the first DH ratchet)
     is_alice: bool,
@@ -74,11 +119,9 @@ pub struct RatchetSession {
     initial_ratchet_done: bool,
 
     // Stable voice base key — derived at init from shared secret, never changes
-    // (root_key changes with every ratchet step, so can't use it for voice)
+    // (root_key changes with every ratchet step, so can't use it for voice).
+    // Each call's key is derived from it with that call's salts.
     voice_base_key: [u8; 32],
-
-    // Cached voice key (derived from voice_base_key, stable during call)
-    voice_key: Option<[u8; 32]>,
 }
 
 impl Drop for RatchetSession {
@@ -95,9 +138,6 @@ impl Drop for RatchetSession {
             key.zeroize();
         }
         self.voice_base_key.zeroize();
-        if let Some(ref mut vk) = self.voice_key {
-            vk.zeroize();
-        }
     }
 }
 
@@ -150,11 +190,12 @@ impl RatchetSession {
             send_msg_num: 0,
             recv_msg_num: 0,
             prev_chain_len: 0,
-            skipped_keys: std::collections::HashMap::new(),
+            skipped_keys: VecDeque::new(),
+            skipped_used: 0,
+            skipped_evicted: 0,
             is_alice,
             initial_ratchet_done: false,
             voice_base_key,
-            voice_key: None,
         }
     }
 
@@ -172,6 +213,49 @@ impl RatchetSession {
         }
     }
 
+    /// Whether we know the remote peer's DH key yet
+    pub fn has_remote_dh(&self) -> bool {
+        self.dh_remote.is_some()
+    }
+
+    /// Take the remote peer's first DH key, as `set_remote_dh`, and mix the DH of
+    /// it and ours into the root and first chain keys. Sessions between the same two
+    /// identities then never start from the same keys, so a frame recorded under one
+    /// doesn't open under another. Only before anything has been sent or received.
+    pub fn mix_exchanged_dh(&mut self, remote_dh: [u8; 32]) -> Result<()> {
+        anyhow::ensure!(
+            self.dh_remote.is_none() && self.send_msg_num == 0 && self.recv_msg_num == 0,
+            "Session is already under way"
+        );
+        let self_secret = StaticSecret::from(self.dh_self_secret);
+        let dh_output = self_secret.diffie_hellman(&PublicKey::from(remote_dh));
+        anyhow::ensure!(dh_output.was_contributory(), "Remote DH key is a low-order point");
+
+        let hk = Hkdf::<Sha256>::new(Some(&self.root_key), dh_output.as_bytes());
+        let mut chain_a = [0u8; 32];
+        let mut chain_b = [0u8; 32];
+        hk.expand(KDF_RK_INFO, &mut self.root_key)
+            .expect("HKDF expand failed");
+        hk.expand(b"wsp-chain-alice", &mut chain_a)
+            .expect("HKDF expand failed");
+        hk.expand(b"wsp-chain-bob", &mut chain_b)
+            .expect("HKDF expand failed");
+        hk.expand(b"wsp-voice-base", &mut self.voice_base_key)
+            .expect("HKDF expand failed");
+
+        let (chain_key_send, chain_key_recv) = if self.is_alice {
+            (chain_a, chain_b)
+        } else {
+            (chain_b, chain_a)
+        };
+        self.chain_key_send = Some(chain_key_send);
+        self.chain_key_recv = Some(chain_key_recv);
+        chain_a.zeroize();
+        chain_b.zeroize();
+        self.dh_remote = Some(remote_dh);
+        Ok(())
+    }
+
     /// Encrypt a plaintext message, returning (header, nonce, ciphertext).
     pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<(RatchetHeader, Vec<u8>, Vec<u8>)> {
         // If we're Alice, have received their DH key, and haven't done the initial
@@ -202,20 +286,45 @@ impl RatchetSession {
         Ok((header, nonce, ciphertext))
     }
 
+    /// Encrypt `plaintext` padded to its bucket (see `pad`). Only for peers that said
+    /// they can read padded messages; `unpad` gets the plaintext back.
+    pub fn encrypt_padded(&mut self, plaintext: &[u8]) -> Result<(RatchetHeader, Vec<u8>, Vec<u8>)> {
+        self.encrypt(&pad(plaintext))
+    }
+
     /// Decrypt a message given its header, nonce, and ciphertext.
+    /// A message that doesn't open (forged, or sealed under another session) leaves
+    /// the session as it was.
     pub fn decrypt(
         &mut self,
         header: &RatchetHeader,
         nonce: &[u8],
         ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut next = self.clone();
+        let plaintext = next.step_decrypt(header, nonce, ciphertext)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    /// Decrypt, advancing the chains (and the DH ratchet) as the header asks
+    fn step_decrypt(
+        &mut self,
+        header: &RatchetHeader,
+        nonce: &[u8],
+        ciphertext: &[u8],
     ) -> Result<Vec<u8>> {
         // 1. Try skipped message keys first
         let skip_key = SkippedKey {
             dh_public: header.dh_public,
             msg_num: header.msg_num,
         };
-        if let Some(mk) = self.skipped_keys.remove(&skip_key) {
-            return decrypt_with_key(&mk, nonce, ciphertext);
+        if let Some(pos) = self.skipped_keys.iter().position(|(key, _)| *key == skip_key) {
+            let (_, mut mk) = self.skipped_keys.remove(pos).expect("position is in range");
+            self.skipped_used += 1;
+            let plaintext = decrypt_with_key(&mk, nonce, ciphertext);
+            mk.zeroize();
+            return plaintext;
         }
 
         // 2. Check if we need a DH ratchet step (new DH key from peer)
@@ -229,12 +338,12 @@ impl RatchetSession {
         };
 
         if need_dh_ratchet {
-            self.skip_message_keys(header.prev_chain_len)?;
+            self.skip_message_keys(header.prev_chain_len, MAX_PREV_CHAIN_SKIP)?;
             self.ratchet_recv(&header.dh_public)?;
         }
 
         // 3. Skip ahead if msg_num > recv_msg_num (out-of-order within same chain)
-        self.skip_message_keys(header.msg_num)?;
+        self.skip_message_keys(header.msg_num, MAX_SKIP)?;
 
         // 4. Derive message key from current receiving chain
         let chain_key = self
@@ -248,28 +357,31 @@ impl RatchetSession {
         decrypt_with_key(&message_key, nonce, ciphertext)
     }
 
-    /// Derive (or return cached) voice key for audio encryption.
+    /// Derive the voice key for one call from both callers' salts and the call id.
     /// Uses the stable voice_base_key (derived at init from shared secret),
-    /// NOT the root key (which changes with every DH ratchet step).
-    /// Both sides always derive the same voice key regardless of ratchet state.
-    pub fn derive_voice_key(&mut self) -> [u8; 32] {
-        if let Some(vk) = self.voice_key {
-            return vk;
-        }
-        let hk = Hkdf::<Sha256>::new(None, &self.voice_base_key);
-        let mut vk = [0u8; 32];
-        hk.expand(KDF_VOICE_INFO, &mut vk)
+    /// NOT the root key (which changes with every DH ratchet step), so both sides
+    /// agree regardless of ratchet state — but each call's key is new, and knowing
+    /// one tells nothing about another.
+    pub fn derive_call_key(&self, caller_salt: &[u8], callee_salt: &[u8], call_id: &str) -> [u8; 32] {
+        let mut salt = Vec::with_capacity(caller_salt.len() + callee_salt.len() + call_id.len());
+        salt.extend_from_slice(caller_salt);
+        salt.extend_from_slice(callee_salt);
+        salt.extend_from_slice(call_id.as_bytes());
+        let hk = Hkdf::<Sha256>::new(Some(&salt), &self.voice_base_key);
+        let mut key = [0u8; 32];
+        hk.expand(KDF_VOICE_INFO, &mut key)
             .expect("HKDF expand failed");
-        self.voice_key = Some(vk);
-        vk
+        key
     }
 
-    /// Clear cached voice key (call this when a voice call ends)
-    pub fn clear_voice_key(&mut self) {
-        if let Some(ref mut vk) = self.voice_key {
-            vk.zeroize();
+    pub fn stats(&self) -> RatchetStats {
+        RatchetStats {
+            send_chain_len: self.send_msg_num,
+            recv_chain_len: self.recv_msg_num,
+            skipped_keys: self.skipped_keys.len(),
+            skipped_used: self.skipped_used,
+            skipped_evicted: self.skipped_evicted,
         }
-        self.voice_key = None;
     }
 
     /// Perform a DH ratchet step on the sending side.
@@ -327,17 +439,17 @@ impl RatchetSession {
     }
 
     /// Skip message keys up to `until` in the current receiving chain,
-    /// storing them for out-of-order decryption.
-    fn skip_message_keys(&mut self, until: u32) -> Result<()> {
+    /// storing them for out-of-order decryption. At most `max_gap` at once.
+    fn skip_message_keys(&mut self, until: u32, max_gap: u32) -> Result<()> {
         if until < self.recv_msg_num {
             return Ok(()); // Nothing to skip
         }
         let num_to_skip = until - self.recv_msg_num;
-        if num_to_skip > MAX_SKIP {
+        if num_to_skip > max_gap {
             return Err(anyhow::anyhow!(
                 "Too many skipped messages ({} > {})",
                 num_to_skip,
-                MAX_SKIP
+                max_gap
             ));
         }
 
@@ -358,17 +470,17 @@ impl RatchetSession {
                 dh_public: dh_pub,
                 msg_num: self.recv_msg_num,
             };
-            self.skipped_keys.insert(skip_key, mk);
+            self.skipped_keys.push_back((skip_key, mk));
             ck = new_ck;
             self.recv_msg_num += 1;
         }
         self.chain_key_recv = Some(ck);
 
-        // Evict oldest skipped keys if we're over the limit
-        while self.skipped_keys.len() > MAX_SKIP as usize {
-            // Remove an arbitrary entry (HashMap doesn't have pop_first easily)
-            if let Some(key) = self.skipped_keys.keys().next().cloned() {
-                self.skipped_keys.remove(&key);
+        // Over the limit: the oldest chain's oldest keys are the least likely to be needed
+        while self.skipped_keys.len() > MAX_STORED_SKIPPED {
+            if let Some((_, mut mk)) = self.skipped_keys.pop_front() {
+                mk.zeroize();
+                self.skipped_evicted += 1;
             }
         }
 
@@ -389,7 +501,7 @@ fn kdf_root(root_key: &[u8; 32], dh_output: &[u8]) -> ([u8; 32], [u8; 32]) {
 }
 
 /// KDF for chain key advancement: chain_key → (new_chain_key, message_key)
-fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+pub(super) fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
     // Use BLAKE3 for speed in the hot path (message-level)
     let new_chain = blake3::keyed_hash(chain_key, b"chain");
     let msg_key = blake3::keyed_hash(chain_key, b"message");
@@ -397,7 +509,7 @@ fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
 }
 
 /// Encrypt with a one-time message key
-fn encrypt_with_key(key: &[u8; 32], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+pub(super) fn encrypt_with_key(key: &[u8; 32], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
     let cipher = ChaCha20Poly1305::new(key.into());
     let mut nonce_bytes = [0u8; 12];
     rand::rngs::OsRng.fill_bytes(&mut nonce_bytes);
@@ -409,7 +521,7 @@ fn encrypt_with_key(key: &[u8; 32], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8
 }
 
 /// Decrypt with a one-time message key
-fn decrypt_with_key(key: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+pub(super) fn decrypt_with_key(key: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
     anyhow::ensure!(nonce.len() == 12, "Nonce must be 12 bytes");
     let cipher = ChaCha20Poly1305::new(key.into());
     let nonce = Nonce::from_slice(nonce);
@@ -419,6 +531,37 @@ fn decrypt_with_key(key: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Result<V
     Ok(plaintext)
 }
 
+/// `plaintext` behind the PADDED marker and its length, zero-filled up to the next
+/// bucket size
+pub fn pad(plaintext: &[u8]) -> Vec<u8> {
+    let len = PAD_PREFIX + plaintext.len();
+    let largest = PAD_BUCKETS[PAD_BUCKETS.len() - 1];
+    let size = PAD_BUCKETS.iter().copied().find(|&bucket| bucket >= len)
+        .unwrap_or_else(|| len.div_ceil(largest) * largest);
+    let mut padded = Vec::with_capacity(size);
+    padded.push(PADDED);
+    padded.extend_from_slice(&(plaintext.len() as u32).to_le_bytes());
+    padded.extend_from_slice(plaintext);
+    padded.resize(size, 0);
+    padded
+}
+
+/// Whether `plaintext` came out of `pad`
+pub fn is_padded(plaintext: &[u8]) -> bool {
+    plaintext.first() == Some(&PADDED)
+}
+
+/// The plaintext inside a padded one. Anything malformed is refused rather than
+/// guessed at: a missing marker, a length past the end, or fill that isn't zeros.
+pub fn unpad(padded: &[u8]) -> Result<&[u8]> {
+    anyhow::ensure!(padded.len() >= PAD_PREFIX && is_padded(padded), "Not a padded message");
+    let len = u32::from_le_bytes(padded[1..PAD_PREFIX].try_into().expect("four bytes")) as usize;
+    let (plaintext, fill) = padded[PAD_PREFIX..].split_at_checked(len)
+        .ok_or_else(|| anyhow::anyhow!("Padding length runs past the message"))?;
+    anyhow::ensure!(fill.iter().all(|&b| b == 0), "Padding isn't zeros");
+    Ok(plaintext)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -533,6 +676,99 @@ mod tests {
         assert_eq!(alice.decrypt(&h, &n, &c).unwrap(), b"b2");
     }
 
+    #[test]
+    fn test_frame_that_fails_leaves_session_intact() {
+        let shared = [42u8; 32];
+        let mut alice = RatchetSession::init(&shared, true);
+        let mut bob = RatchetSession::init(&shared, false);
+        alice.set_remote_dh(bob.public_key());
+        bob.set_remote_dh(alice.public_key());
+
+        // A forged frame claiming a new DH key and a gap in the chain
+        let (h, n, mut c) = alice.encrypt(b"a1").unwrap();
+        let forged = RatchetHeader { dh_public: [7; 32], prev_chain_len: 50, msg_num: 50 };
+        assert!(bob.decrypt(&forged, &n, &c).is_err());
+        c[0] ^= 1;
+        assert!(bob.decrypt(&h, &n, &c).is_err());
+        assert_eq!(bob.stats().skipped_keys, 0);
+
+        c[0] ^= 1;
+        assert_eq!(bob.decrypt(&h, &n, &c).unwrap(), b"a1");
+        let (h, n, c) = alice.encrypt(b"a2").unwrap();
+        assert_eq!(bob.decrypt(&h, &n, &c).unwrap(), b"a2");
+    }
+
+    /// A session pair that has swapped initial DH keys
+    fn pair() -> (RatchetSession, RatchetSession) {
+        let shared = [42u8; 32];
+        let mut alice = RatchetSession::init(&shared, true);
+        let mut bob = RatchetSession::init(&shared, false);
+        alice.set_remote_dh(bob.public_key());
+        bob.set_remote_dh(alice.public_key());
+        (alice, bob)
+    }
+
+    #[test]
+    fn test_out_of_order_across_dh_ratchet() {
+        let (mut alice, mut bob) = pair();
+        let sent: Vec<_> = (0..3).map(|i| alice.encrypt(format!("a{}", i).as_bytes()).unwrap()).collect();
+        let (h, n, c) = &sent[0];
+        assert_eq!(bob.decrypt(h, n, c).unwrap(), b"a0");
+
+        // Bob answers, Alice ratchets, and her next chain overtakes the rest of the first
+        let (h, n, c) = bob.encrypt(b"b0").unwrap();
+        alice.decrypt(&h, &n, &c).unwrap();
+        let (h, n, c) = alice.encrypt(b"a3").unwrap();
+        assert_eq!(h.prev_chain_len, 3);
+        assert_eq!(bob.decrypt(&h, &n, &c).unwrap(), b"a3");
+        assert_eq!(bob.stats().skipped_keys, 2);
+
+        for (i, (h, n, c)) in sent.iter().enumerate().skip(1).rev() {
+            assert_eq!(bob.decrypt(h, n, c).unwrap(), format!("a{}", i).as_bytes());
+        }
+        let stats = bob.stats();
+        assert_eq!((stats.skipped_keys, stats.skipped_used, stats.skipped_evicted), (0, 2, 0));
+    }
+
+    #[test]
+    fn test_previous_chain_may_run_long() {
+        let (mut alice, mut bob) = pair();
+        let (h, n, c) = alice.encrypt(b"first").unwrap();
+        bob.decrypt(&h, &n, &c).unwrap();
+        let (reply_h, reply_n, reply_c) = bob.encrypt(b"reply").unwrap();
+
+        // Alice keeps writing while Bob is away; none of it reaches him
+        let gap = MAX_SKIP * 3;
+        let lost: Vec<_> = (0..gap).map(|_| alice.encrypt(b"lost").unwrap()).collect();
+        let (h, n, c) = lost.last().unwrap();
+        let mut too_far = bob.clone();
+        assert!(too_far.decrypt(h, n, c).is_err(), "a gap this long within one chain is refused");
+
+        // ...but once she ratchets, the header vouches for the old chain's length
+        alice.decrypt(&reply_h, &reply_n, &reply_c).unwrap();
+        let (h, n, c) = alice.encrypt(b"back").unwrap();
+        assert_eq!(bob.decrypt(&h, &n, &c).unwrap(), b"back");
+        assert_eq!(bob.stats().skipped_keys, gap as usize);
+    }
+
+    #[test]
+    fn test_sustained_gaps_evict_oldest_keys() {
+        let (mut alice, mut bob) = pair();
+        let sent: Vec<_> = (0..1250).map(|_| alice.encrypt(b"m").unwrap()).collect();
+        for (h, n, c) in sent.iter().step_by(50).skip(1) {
+            bob.decrypt(h, n, c).unwrap();
+        }
+        let stats = bob.stats();
+        assert_eq!(stats.skipped_keys, MAX_STORED_SKIPPED);
+        assert_eq!(stats.skipped_evicted as usize, 1201 - 24 - MAX_STORED_SKIPPED);
+
+        // The oldest are gone, the newest still open
+        let (h, n, c) = &sent[0];
+        assert!(bob.decrypt(h, n, c).is_err());
+        let (h, n, c) = &sent[1199];
+        assert_eq!(bob.decrypt(h, n, c).unwrap(), b"m");
+    }
+
     #[test]
     fn test_no_dh_exchange_symmetric_still_works() {
         // Without DH key exchange (legacy/fallback), symmetric chains work
@@ -559,15 +795,53 @@ mod tests {
         let mut alice = RatchetSession::init(&shared, true);
         let mut bob = RatchetSession::init(&shared, false);
 
-        let vk_a = alice.derive_voice_key();
-        let vk_b = bob.derive_voice_key();
+        let (caller, callee) = ([1u8; 32], [2u8; 32]);
+        let vk_a = alice.derive_call_key(&caller, &callee, "c1");
+        let vk_b = bob.derive_call_key(&caller, &callee, "c1");
         assert_eq!(vk_a, vk_b, "Voice keys should match");
 
-        // Calling again should return cached value
-        assert_eq!(alice.derive_voice_key(), vk_a);
+        // Ratchet steps don't change it
+        let (h, n, c) = alice.encrypt(b"hi").unwrap();
+        bob.decrypt(&h, &n, &c).unwrap();
+        assert_eq!(bob.derive_call_key(&caller, &callee, "c1"), vk_a);
 
-        // Clear and re-derive should still match
-        alice.clear_voice_key();
-        assert_eq!(alice.derive_voice_key(), vk_a);
+        // Another salt, another call id, or the salts swapped: another key
+        assert_ne!(alice.derive_call_key(&caller, &[3u8; 32], "c1"), vk_a);
+        assert_ne!(alice.derive_call_key(&caller, &callee, "c2"), vk_a);
+        assert_ne!(alice.derive_call_key(&callee, &caller, "c1"), vk_a);
+    }
+
+    #[test]
+    fn test_padding_round_trip_and_malformed_padding() {
+        let shared = [42u8; 32];
+        let mut alice = RatchetSession::init(&shared, true);
+        let mut bob = RatchetSession::init(&shared, false);
+        alice.set_remote_dh(bob.public_key());
+        bob.set_remote_dh(alice.public_key());
+
+        // "ok" and a sentence look the same on the wire; past the buckets, whole 4 KB steps
+        let (h, n, short) = alice.encrypt_padded(b"ok").unwrap();
+        let pt = bob.decrypt(&h, &n, &short).unwrap();
+        assert_eq!(unpad(&pt).unwrap(), b"ok");
+        let (h, n, longer) = alice.encrypt_padded(&[b'x'; 50]).unwrap();
+        assert_eq!(unpad(&bob.decrypt(&h, &n, &longer).unwrap()).unwrap(), [b'x'; 50]);
+        assert_eq!(short.len(), longer.len());
+        for (len, size) in [(0, 64), (59, 64), (60, 256), (4091, 4096), (4092, 8192), (10_000, 12_288)] {
+            let padded = pad(&vec![7; len]);
+            assert_eq!(padded.len(), size, "{} bytes", len);
+            assert_eq!(unpad(&padded).unwrap().len(), len);
+        }
+
+        // Unpadded plaintexts still go through encrypt() as they are
+        let (h, n, ct) = alice.encrypt(b"\x92plain").unwrap();
+        assert!(!is_padded(&bob.decrypt(&h, &n, &ct).unwrap()));
+
+        let mut length_too_long = pad(b"hi");
+        length_too_long[1..5].copy_from_slice(&100u32.to_le_bytes());
+        let mut dirty_fill = pad(b"hi");
+        *dirty_fill.last_mut().unwrap() = 1;
+        for malformed in [&b""[..], b"\x00\x01", b"\x92unpadded", &length_too_long[..], &dirty_fill[..]] {
+            assert!(unpad(malformed).is_err(), "{:?}", malformed);
+        }
     }
 }
@@ -0,0 +1,161 @@
+//! The relay's own key, so a client can tell the relay answering is the one it used
+//! before and not something put in its place (a hijacked DNS name, a taken-over host).
+//!
+//! It's an X25519 key like an identity's. Proof works without signatures: the client
+//! sends a fresh X25519 public key as its challenge in Connect, and the relay answers
+//! in Ack with its public key and a MAC over both keys and the session id, keyed from
+//! the Diffie-Hellman of its secret and the challenge. Only the holder of the relay's
+//! secret (or of the client's, which never leaves the client) can work that key out.
+//! The relay keeps its secret in a file of its own, unencrypted so it can start
+//! unattended; `wsp relay --show-id` prints the fingerprint operators publish.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::safety_number::key_fingerprint;
+
+/// Size of a relay key, a challenge and a proof
+pub const RELAY_KEY_LEN: usize = 32;
+
+/// The relay's keypair
+pub struct RelayKey {
+    secret: StaticSecret,
+}
+
+impl RelayKey {
+    /// A new key, for a relay that keeps none (tests, or one run from a read-only place)
+    pub fn generate() -> Self {
+        Self { secret: StaticSecret::random_from_rng(rand::thread_rng()) }
+    }
+
+    /// The key at `path`, or a new one written there (readable by the owner only) if
+    /// there's none yet
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(mut bytes) => {
+                let secret: Option<[u8; RELAY_KEY_LEN]> = bytes.as_slice().try_into().ok();
+                zeroize::Zeroize::zeroize(&mut bytes);
+                let secret = secret.with_context(|| format!("Relay key file {} is damaged", path.display()))?;
+                Ok(Self { secret: StaticSecret::from(secret) })
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let key = Self::generate();
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                let mut options = std::fs::OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                let mut file = options.open(path).with_context(|| format!("Couldn't create {}", path.display()))?;
+                std::io::Write::write_all(&mut file, key.secret.as_bytes())?;
+                Ok(key)
+            }
+            Err(e) => Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+        }
+    }
+
+    pub fn public_key_bytes(&self) -> Vec<u8> {
+        PublicKey::from(&self.secret).as_bytes().to_vec()
+    }
+
+    /// What operators publish and clients pin
+    pub fn fingerprint(&self) -> String {
+        relay_fingerprint(&self.public_key_bytes())
+    }
+
+    /// Answer a client's `challenge` for `session_id`; None if it isn't a usable key
+    pub fn prove(&self, challenge: &[u8], session_id: &str) -> Option<Vec<u8>> {
+        let challenge: [u8; RELAY_KEY_LEN] = challenge.try_into().ok()?;
+        let shared = self.secret.diffie_hellman(&PublicKey::from(challenge));
+        if !shared.was_contributory() {
+            return None;
+        }
+        Some(proof(shared.as_bytes(), &self.public_key_bytes(), &challenge, session_id).as_bytes().to_vec())
+    }
+}
+
+/// A client's challenge for one connection
+pub struct RelayChallenge {
+    secret: StaticSecret,
+}
+
+impl Default for RelayChallenge {
+    fn default() -> Self {
+        Self { secret: StaticSecret::random_from_rng(rand::thread_rng()) }
+    }
+}
+
+impl RelayChallenge {
+    /// What goes in Connect
+    pub fn bytes(&self) -> Vec<u8> {
+        PublicKey::from(&self.secret).as_bytes().to_vec()
+    }
+
+    /// Whether `proof` shows the relay holds the secret for `relay_key`
+    pub fn verify(&self, relay_key: &[u8], session_id: &str, proof_bytes: &[u8]) -> bool {
+        let (Ok(relay_key), Ok(given)) = (<[u8; RELAY_KEY_LEN]>::try_from(relay_key), <[u8; 32]>::try_from(proof_bytes)) else {
+            return false;
+        };
+        let shared = self.secret.diffie_hellman(&PublicKey::from(relay_key));
+        if !shared.was_contributory() {
+            return false;
+        }
+        // blake3::Hash compares in constant time
+        proof(shared.as_bytes(), &relay_key, &self.bytes(), session_id) == blake3::Hash::from(given)
+    }
+}
+
+fn proof(shared: &[u8; 32], relay_key: &[u8], challenge: &[u8], session_id: &str) -> blake3::Hash {
+    let key = blake3::derive_key("wsp relay key proof v1", shared);
+    let mut mac = blake3::Hasher::new_keyed(&key);
+    mac.update(relay_key);
+    mac.update(challenge);
+    mac.update(session_id.as_bytes());
+    mac.finalize()
+}
+
+/// Fingerprint of a relay's public key, in the numeric form of identity fingerprints
+pub fn relay_fingerprint(public_key: &[u8]) -> String {
+    key_fingerprint(public_key).numeric()
+}
+
+/// Whether `given` (as typed for `--expect-relay`, spacing aside) is `fingerprint`
+pub fn same_fingerprint(given: &str, fingerprint: &str) -> bool {
+    let digits = |s: &str| s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    digits(given) == digits(fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_key_holder_can_answer_a_challenge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("relay").join("relay_key");
+        let relay = RelayKey::load_or_create(&path).unwrap();
+        assert_eq!(RelayKey::load_or_create(&path).unwrap().public_key_bytes(), relay.public_key_bytes());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let challenge = RelayChallenge::default();
+        let proof = relay.prove(&challenge.bytes(), "session").unwrap();
+        assert!(challenge.verify(&relay.public_key_bytes(), "session", &proof));
+        assert!(!challenge.verify(&relay.public_key_bytes(), "another", &proof));
+
+        // A stand-in can claim the key but not answer for it, nor replay an old answer
+        let impostor = RelayKey::generate();
+        let forged = impostor.prove(&challenge.bytes(), "session").unwrap();
+        assert!(!challenge.verify(&relay.public_key_bytes(), "session", &forged));
+        assert!(!RelayChallenge::default().verify(&relay.public_key_bytes(), "session", &proof));
+        assert!(relay.prove(&[0; RELAY_KEY_LEN], "session").is_none());
+
+        assert!(same_fingerprint(&relay.fingerprint().replace(' ', ""), &relay.fingerprint()));
+        assert!(!same_fingerprint(&impostor.fingerprint(), &relay.fingerprint()));
+    }
+}
@@ -17,6 +17,21 @@ const EMOJI_ALPHABET: &[&str] = &[
     "🦚", "🌱", "🎷", "💜", "🐧", "🌳", "🎶", "🔶",
 ];
 
+/// Short words for reading a verification challenge aloud (64, so one per 6 bits)
+const CHALLENGE_WORDS: &[&str] = &[
+    "acid", "bark", "bolt", "cave", "clay", "coal", "crow", "dawn",
+    "deer", "dove", "dune", "echo", "fern", "fire", "flax", "foam",
+    "frog", "gale", "gold", "gull", "hail", "hawk", "haze", "iris",
+    "iron", "jade", "kelp", "kite", "lake", "lamp", "lime", "lynx",
+    "mint", "mist", "moss", "moth", "nest", "oak", "opal", "owl",
+    "palm", "pear", "pine", "plum", "pond", "rain", "reef", "rose",
+    "rust", "sage", "salt", "sand", "seal", "silk", "snow", "star",
+    "swan", "tide", "toad", "vine", "wasp", "wave", "wolf", "wren",
+];
+
+/// Length of a mutual verification challenge, in bytes
+pub const CHALLENGE_LEN: usize = 8;
+
 /// Compute a safety number from two public keys.
 ///
 /// The result is deterministic and identical regardless of which side computes it,
@@ -45,6 +60,21 @@ pub fn compute_safety_number(my_pubkey: &[u8], peer_pubkey: &[u8]) -> SafetyNumb
     }
 }
 
+/// Fingerprint of a single identity key, shown in the same formats as a safety number.
+///
+/// Unlike a safety number it doesn't involve a peer, so it can be published (a profile,
+/// an email signature) and checked against `/id` later.
+pub fn key_fingerprint(pubkey: &[u8]) -> SafetyNumber {
+    let mut hasher = Sha256::new();
+    hasher.update(b"WSP-KEY-FINGERPRINT-v1");
+    hasher.update((pubkey.len() as u32).to_le_bytes());
+    hasher.update(pubkey);
+
+    SafetyNumber {
+        hash: hasher.finalize().into(),
+    }
+}
+
 /// A computed safety number that can be displayed in multiple formats.
 #[derive(Clone, Debug)]
 pub struct SafetyNumber {
@@ -93,6 +123,44 @@ impl SafetyNumber {
     }
 }
 
+/// Fresh random challenge for one mutual verification round
+pub fn new_challenge() -> Vec<u8> {
+    (0..CHALLENGE_LEN).map(|_| rand::random::<u8>()).collect()
+}
+
+/// A challenge as four words, shown on both screens so users can tell they're
+/// confirming the same round
+pub fn challenge_words(challenge: &[u8]) -> String {
+    challenge.iter()
+        .take(4)
+        .map(|b| CHALLENGE_WORDS[*b as usize % CHALLENGE_WORDS.len()])
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Confirmation sent once a user has compared the safety number during a challenge.
+///
+/// Like the safety number it's symmetric in the two keys, so the receiver recomputes
+/// it from the keys *it* sees, and it only matches when both users confirmed the same
+/// pair: that catches one of them confirming the wrong peer or key. It's built from
+/// public values alone, so someone relaying the session can compute it for each side;
+/// comparing the safety number itself out of band is what catches them.
+pub fn verification_proof(my_pubkey: &[u8], peer_pubkey: &[u8], challenge: &[u8]) -> Vec<u8> {
+    let (first, second) = if my_pubkey <= peer_pubkey {
+        (my_pubkey, peer_pubkey)
+    } else {
+        (peer_pubkey, my_pubkey)
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"WSP-VERIFY-CONFIRM-v1");
+    for part in [first, second, challenge] {
+        hasher.update((part.len() as u32).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,6 +189,17 @@ mod tests {
         assert_ne!(sn_ab.numeric(), sn_ac.numeric());
     }
 
+    #[test]
+    fn test_key_fingerprint_is_per_key() {
+        let key_a = vec![1u8; 32];
+        let key_b = vec![2u8; 32];
+
+        assert_eq!(key_fingerprint(&key_a).numeric(), key_fingerprint(&key_a).numeric());
+        assert_ne!(key_fingerprint(&key_a).numeric(), key_fingerprint(&key_b).numeric());
+        // Domain-separated from the pairwise safety number
+        assert_ne!(key_fingerprint(&key_a).numeric(), compute_safety_number(&key_a, &key_a).numeric());
+    }
+
     #[test]
     fn test_numeric_format() {
         let key_a = vec![42u8; 32];
@@ -145,4 +224,22 @@ mod tests {
         // Should have 8 emoji characters (though they may be multi-byte)
         assert!(!emoji.is_empty());
     }
+
+    #[test]
+    fn test_verification_proof_binds_keys_and_challenge() {
+        let (key_a, key_b, key_c) = (vec![1u8; 32], vec![2u8; 32], vec![3u8; 32]);
+        let challenge = new_challenge();
+        assert_eq!(challenge.len(), CHALLENGE_LEN);
+
+        let proof = verification_proof(&key_a, &key_b, &challenge);
+        assert_eq!(proof, verification_proof(&key_b, &key_a, &challenge));
+        assert_ne!(proof, verification_proof(&key_a, &key_c, &challenge));
+        assert_ne!(proof, verification_proof(&key_a, &key_b, &[0; CHALLENGE_LEN]));
+    }
+
+    #[test]
+    fn test_challenge_words() {
+        let words = challenge_words(&[0, 1, 63, 64, 9, 9, 9, 9]);
+        assert_eq!(words, "acid bark wren acid");
+    }
 }
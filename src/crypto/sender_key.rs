@@ -0,0 +1,268 @@
+//! Sender keys for group chats.
+//!
+//! Each member encrypts a group message once, under its own symmetric chain for that
+//! group, and hands the chain to the other members over their pairwise ratchets.
+//! Receivers keep one chain per sender and key id. A new key id is started whenever
+//! someone holding the current one leaves the group, so removed members can't read on.
+//!
+//! Sender keys are symmetric: a member holding someone's key could forge frames under it.
+//! The relay only forwards group frames whose `from` is the connection that sent them.
+
+use anyhow::{bail, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zeroize::Zeroize;
+
+use super::ratchet::{decrypt_with_key, encrypt_with_key, kdf_chain};
+
+/// Largest gap a receiver skips ahead over, and the most message keys it keeps for
+/// late frames. Senders can also hand out their chain from this far back.
+const MAX_SKIP: u32 = 100;
+
+/// Header of a sender-key group frame, carried in `Message::GroupEncrypted`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderKeyHeader {
+    /// Which of the sender's keys sealed this frame
+    pub key_id: u32,
+    /// Position in that key's chain
+    pub iteration: u32,
+}
+
+/// A sender's chain from some iteration on, as handed to another member
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderKeyDistribution {
+    pub key_id: u32,
+    /// Iteration `chain_key` belongs to; earlier frames stay unreadable
+    pub iteration: u32,
+    pub chain_key: [u8; 32],
+}
+
+impl std::fmt::Debug for SenderKeyDistribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SenderKeyDistribution")
+            .field("key_id", &self.key_id)
+            .field("iteration", &self.iteration)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for SenderKeyDistribution {
+    fn drop(&mut self) {
+        self.chain_key.zeroize();
+    }
+}
+
+/// Our sending chain for one group
+pub struct SenderChain {
+    key_id: u32,
+    chain_key: [u8; 32],
+    iteration: u32,
+    /// Chain key from up to MAX_SKIP iterations back, so a member who missed the key
+    /// can still be given the recent frames; older keys are gone for good
+    trailing_key: [u8; 32],
+    trailing_iteration: u32,
+}
+
+impl Drop for SenderChain {
+    fn drop(&mut self) {
+        self.chain_key.zeroize();
+        self.trailing_key.zeroize();
+    }
+}
+
+impl SenderChain {
+    /// Start a fresh chain under a random key id
+    pub fn generate() -> Self {
+        let mut chain_key = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut chain_key);
+        Self {
+            key_id: rand::rngs::OsRng.next_u32(),
+            chain_key,
+            iteration: 0,
+            trailing_key: chain_key,
+            trailing_iteration: 0,
+        }
+    }
+
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    /// Iteration the next frame will be sealed at
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    /// Encrypt one group frame, returning (header, nonce, ciphertext)
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<(SenderKeyHeader, Vec<u8>, Vec<u8>)> {
+        if self.iteration == u32::MAX {
+            bail!("Sender key exhausted");
+        }
+        let (next_chain_key, message_key) = kdf_chain(&self.chain_key);
+        let header = SenderKeyHeader { key_id: self.key_id, iteration: self.iteration };
+        let (nonce, ciphertext) = encrypt_with_key(&message_key, plaintext)?;
+
+        self.chain_key = next_chain_key;
+        self.iteration += 1;
+        if self.iteration - self.trailing_iteration > MAX_SKIP {
+            self.trailing_key = kdf_chain(&self.trailing_key).0;
+            self.trailing_iteration += 1;
+        }
+        Ok((header, nonce, ciphertext))
+    }
+
+    /// Our chain from `iteration` on, or from as far back as we still can
+    pub fn distribution_from(&self, iteration: u32) -> SenderKeyDistribution {
+        let iteration = iteration.clamp(self.trailing_iteration, self.iteration);
+        let mut chain_key = self.trailing_key;
+        for _ in self.trailing_iteration..iteration {
+            chain_key = kdf_chain(&chain_key).0;
+        }
+        SenderKeyDistribution { key_id: self.key_id, iteration, chain_key }
+    }
+}
+
+/// Another member's chain, as far as we've read it
+pub struct SenderKeyReceiver {
+    key_id: u32,
+    chain_key: [u8; 32],
+    iteration: u32,
+    /// Message keys for frames skipped over, by iteration
+    skipped: BTreeMap<u32, [u8; 32]>,
+}
+
+impl Drop for SenderKeyReceiver {
+    fn drop(&mut self) {
+        self.chain_key.zeroize();
+        for key in self.skipped.values_mut() {
+            key.zeroize();
+        }
+    }
+}
+
+impl SenderKeyReceiver {
+    pub fn new(distribution: &SenderKeyDistribution) -> Self {
+        Self {
+            key_id: distribution.key_id,
+            chain_key: distribution.chain_key,
+            iteration: distribution.iteration,
+            skipped: BTreeMap::new(),
+        }
+    }
+
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    /// Decrypt a frame sealed under this chain. Nothing is advanced unless it opens,
+    /// so a forged frame can't push the chain past real ones.
+    pub fn decrypt(&mut self, header: &SenderKeyHeader, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        anyhow::ensure!(header.key_id == self.key_id, "Wrong sender key");
+        if header.iteration < self.iteration {
+            let Some(message_key) = self.skipped.get(&header.iteration) else {
+                bail!("Group message already read or too old");
+            };
+            let plaintext = decrypt_with_key(message_key, nonce, ciphertext)?;
+            self.skipped.remove(&header.iteration);
+            return Ok(plaintext);
+        }
+        if header.iteration - self.iteration > MAX_SKIP {
+            bail!("Too many skipped group messages ({} > {})", header.iteration - self.iteration, MAX_SKIP);
+        }
+
+        let mut chain_key = self.chain_key;
+        let mut skipped = Vec::new();
+        for iteration in self.iteration..header.iteration {
+            let (next, message_key) = kdf_chain(&chain_key);
+            skipped.push((iteration, message_key));
+            chain_key = next;
+        }
+        let (next, message_key) = kdf_chain(&chain_key);
+        let plaintext = decrypt_with_key(&message_key, nonce, ciphertext)?;
+
+        self.chain_key = next;
+        self.iteration = header.iteration + 1;
+        self.skipped.extend(skipped);
+        while self.skipped.len() > MAX_SKIP as usize {
+            if let Some(mut oldest) = self.skipped.pop_first() {
+                oldest.1.zeroize();
+            }
+        }
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_with_gaps_and_late_frames() {
+        let mut sender = SenderChain::generate();
+        let mut receiver = SenderKeyReceiver::new(&sender.distribution_from(0));
+        let frames: Vec<_> = (0..5u8).map(|i| sender.encrypt(&[i]).unwrap()).collect();
+
+        // 3 arrives first, then the ones skipped over, then 4
+        for i in [3, 0, 2, 1, 4] {
+            let (header, nonce, ct) = &frames[i];
+            assert_eq!(receiver.decrypt(header, nonce, ct).unwrap(), vec![i as u8]);
+        }
+        // Replays don't open twice
+        let (header, nonce, ct) = &frames[2];
+        assert!(receiver.decrypt(header, nonce, ct).is_err());
+    }
+
+    #[test]
+    fn test_forged_frame_leaves_chain_alone() {
+        let mut sender = SenderChain::generate();
+        let mut receiver = SenderKeyReceiver::new(&sender.distribution_from(0));
+        let (header, nonce, ct) = sender.encrypt(b"real").unwrap();
+
+        let forged = SenderKeyHeader { iteration: 50, ..header };
+        assert!(receiver.decrypt(&forged, &nonce, b"garbage").is_err());
+        assert_eq!(receiver.decrypt(&header, &nonce, &ct).unwrap(), b"real");
+    }
+
+    #[test]
+    fn test_late_distribution_starts_where_asked() {
+        let mut sender = SenderChain::generate();
+        let frames: Vec<_> = (0..3u8).map(|i| sender.encrypt(&[i]).unwrap()).collect();
+
+        // A member given the key from iteration 1 can't read frame 0
+        let mut receiver = SenderKeyReceiver::new(&sender.distribution_from(1));
+        let (header, nonce, ct) = &frames[0];
+        assert!(receiver.decrypt(header, nonce, ct).is_err());
+        for (header, nonce, ct) in &frames[1..] {
+            receiver.decrypt(header, nonce, ct).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_distribution_reaches_back_at_most_max_skip() {
+        let mut sender = SenderChain::generate();
+        let frames: Vec<_> = (0..MAX_SKIP + 20).map(|_| sender.encrypt(b"x").unwrap()).collect();
+
+        let distribution = sender.distribution_from(0);
+        assert_eq!(distribution.iteration, 20);
+        let mut receiver = SenderKeyReceiver::new(&distribution);
+        let (header, nonce, ct) = &frames[20];
+        assert!(receiver.decrypt(header, nonce, ct).is_ok());
+    }
+
+    #[test]
+    fn test_new_key_locks_out_old_holders() {
+        let mut old = SenderChain::generate();
+        let mut removed = SenderKeyReceiver::new(&old.distribution_from(0));
+        let (header, nonce, ct) = old.encrypt(b"before").unwrap();
+        removed.decrypt(&header, &nonce, &ct).unwrap();
+
+        let mut rotated = SenderChain::generate();
+        assert_ne!(rotated.key_id(), old.key_id());
+        let (header, nonce, ct) = rotated.encrypt(b"after").unwrap();
+        assert!(removed.decrypt(&header, &nonce, &ct).is_err());
+        // Even with the key id papered over, the old chain doesn't open it
+        let relabelled = SenderKeyHeader { key_id: old.key_id(), ..header };
+        assert!(removed.decrypt(&relabelled, &nonce, &ct).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+//! `wsp doctor`: goes through what `wsp chat` needs (config, identity, relay, terminal,
+//! audio) one step at a time and prints a PASS/FAIL table, with a hint for anything
+//! that failed. The result is also the exit code, so it can be scripted.
+
+use anyhow::{bail, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{connect_async_with_config, MaybeTlsStream, WebSocketStream};
+
+use crate::audio::{self, AudioSupport};
+use crate::config::{self, Config};
+use crate::onboarding;
+use wsp::client::relay_host;
+use wsp::crypto::relay_key::{relay_fingerprint, RelayChallenge};
+use wsp::protocol::{codec, Message, MAX_MESSAGE_SIZE};
+
+/// How long each step talking to the relay gets
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Round trip above which calls will lag noticeably
+const SLOW_ROUND_TRIP: Duration = Duration::from_millis(400);
+
+type Ws = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    /// Works, but not fully: doesn't fail the run
+    Warn,
+    Fail,
+    /// Couldn't be checked (an earlier step failed, or it's not built in)
+    Skip,
+}
+
+/// One row of the table
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, outcome: Outcome::Pass, detail: detail.into(), hint: None }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: &str) -> Self {
+        Self { name, outcome: Outcome::Warn, detail: detail.into(), hint: Some(hint.to_string()) }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &str) -> Self {
+        Self { name, outcome: Outcome::Fail, detail: detail.into(), hint: Some(hint.to_string()) }
+    }
+
+    fn skip(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, outcome: Outcome::Skip, detail: detail.into(), hint: None }
+    }
+}
+
+/// Run every check and print the table. True if nothing failed.
+pub async fn run(relay: Option<String>, identity_path: &Path, config_path: &Path, profile: Option<&str>) -> Result<bool> {
+    println!("🩺 wsp doctor");
+    println!();
+    let mut checks = Vec::new();
+
+    let (check, config) = check_config(config_path);
+    checks.push(check);
+    let config = config.for_profile(profile);
+    // Same precedence as `wsp chat`: the flag, then the config
+    let relay_url = relay.or(config.relay).unwrap_or_else(|| config::DEFAULT_RELAY.to_string());
+
+    checks.push(check_identity(identity_path));
+    println!("📡 Probing relay {}", relay_url);
+    checks.extend(check_relay(&relay_url).await);
+    checks.extend(check_terminal());
+    checks.push(check_audio());
+
+    println!();
+    print!("{}", render(&checks));
+    println!();
+    let failed = checks.iter().filter(|c| c.outcome == Outcome::Fail).count();
+    if failed == 0 {
+        println!("✅ Everything wsp needs is in place");
+    } else {
+        println!("❌ {} of {} checks failed", failed, checks.len());
+    }
+    Ok(failed == 0)
+}
+
+fn check_config(path: &Path) -> (Check, Config) {
+    if !path.exists() {
+        return (Check::pass("config", format!("none at {}, using defaults", path.display())), Config::default());
+    }
+    match Config::load(path) {
+        Ok(config) => (Check::pass("config", path.display().to_string()), config),
+        Err(e) => (
+            Check::fail("config", format!("{:#}", e), "fix the JSON, or delete the file to go back to defaults"),
+            Config::default(),
+        ),
+    }
+}
+
+fn check_identity(path: &Path) -> Check {
+    if !path.exists() {
+        return Check::fail("identity", format!("none at {}", path.display()), "run `wsp chat` (or `wsp init`) to create one");
+    }
+    println!("🔐 Unlocking identity at {}", path.display());
+    match onboarding::unlock(&mut onboarding::Terminal, path) {
+        Ok(identity) => Check::pass("identity", format!("unlocked, ID {}", identity.public_key_b64())),
+        Err(e) => Check::fail("identity", format!("{:#}", e), "check the password, or pass the right file with --identity (a damaged one is restored from its .bak copy)"),
+    }
+}
+
+/// Resolve, connect, open a throwaway session and ping the relay, stopping at the
+/// first step that fails
+async fn check_relay(url: &str) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let skip_rest = |checks: &mut Vec<Check>, from: usize| {
+        let names = ["relay address", "relay connect", "relay session", "relay latency"];
+        checks.extend(names[from..].iter().map(|name| Check::skip(name, "an earlier step failed")));
+    };
+
+    let target = socket_target(url);
+    let started = Instant::now();
+    match timeout(STEP_TIMEOUT, tokio::net::lookup_host(&target)).await {
+        Ok(Ok(addrs)) => {
+            let addrs: Vec<String> = addrs.map(|a| a.ip().to_string()).collect();
+            checks.push(Check::pass("relay address", format!("{} → {} ({})", target, addrs.join(", "), millis(started.elapsed()))));
+        }
+        Ok(Err(e)) => {
+            checks.push(Check::fail("relay address", format!("{}: {}", target, e), "check the relay URL, and that this machine has DNS"));
+            skip_rest(&mut checks, 1);
+            return checks;
+        }
+        Err(_) => {
+            checks.push(Check::fail("relay address", format!("{}: lookup timed out", target), "check this machine's DNS settings"));
+            skip_rest(&mut checks, 1);
+            return checks;
+        }
+    }
+
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let mut ws = match timeout(STEP_TIMEOUT, connect_async_with_config(url, Some(config), false)).await {
+        Ok(Ok((ws, _))) => {
+            checks.push(Check::pass("relay connect", format!("websocket open in {}", millis(started.elapsed()))));
+            ws
+        }
+        Ok(Err(e)) => {
+            let hint = "is the relay running and reachable from here? For wss://, check its certificate";
+            checks.push(Check::fail("relay connect", e.to_string(), hint));
+            skip_rest(&mut checks, 2);
+            return checks;
+        }
+        Err(_) => {
+            checks.push(Check::fail("relay connect", "timed out", "a firewall or proxy may be dropping the connection"));
+            skip_rest(&mut checks, 2);
+            return checks;
+        }
+    };
+
+    let started = Instant::now();
+    match open_session(&mut ws).await {
+        Ok(Some(fingerprint)) => checks.push(Check::pass(
+            "relay session",
+            format!("acknowledged in {}, relay fingerprint {}", millis(started.elapsed()), fingerprint),
+        )),
+        Ok(None) => checks.push(Check::warn(
+            "relay session",
+            format!("acknowledged in {}", millis(started.elapsed())),
+            "the relay doesn't prove which relay it is, so a swapped one would go unnoticed; it needs updating",
+        )),
+        Err(e) => {
+            checks.push(Check::fail("relay session", format!("{:#}", e), "the server took the websocket but isn't answering as a wsp relay"));
+            skip_rest(&mut checks, 3);
+            return checks;
+        }
+    }
+
+    let started = Instant::now();
+    checks.push(match ping(&mut ws).await {
+        Ok(()) if started.elapsed() > SLOW_ROUND_TRIP => Check::warn(
+            "relay latency",
+            format!("{} round trip", millis(started.elapsed())),
+            "chat will work, but calls will lag; a closer relay would help",
+        ),
+        Ok(()) => Check::pass("relay latency", format!("{} round trip", millis(started.elapsed()))),
+        Err(e) => Check::fail("relay latency", format!("{:#}", e), "the connection is there but not answering; try again"),
+    });
+    let _ = ws.close(None).await;
+    checks
+}
+
+/// Connect under a random session id and wait for the Ack. Returns the relay's
+/// fingerprint if it proved its key (None from relays too old to).
+async fn open_session(ws: &mut Ws) -> Result<Option<String>> {
+    let session_id = hex::encode(rand::random::<[u8; 16]>());
+    let challenge = RelayChallenge::default();
+    let connect = Message::Connect { session_id: session_id.clone(), challenge: challenge.bytes() };
+    ws.send(WsMessage::Binary(codec::encode(&connect)?)).await?;
+    loop {
+        match next_frame(ws).await? {
+            WsMessage::Binary(data) => match codec::decode(&data)? {
+                Message::Ack { relay_key, .. } if relay_key.is_empty() => return Ok(None),
+                Message::Ack { relay_key, proof } => {
+                    let fingerprint = relay_fingerprint(&relay_key);
+                    if !challenge.verify(&relay_key, &session_id, &proof) {
+                        bail!("the relay claims key {} but couldn't prove it holds it", fingerprint);
+                    }
+                    return Ok(Some(fingerprint));
+                }
+                Message::Error { message, .. } => bail!("refused: {}", message),
+                _ => {}
+            },
+            WsMessage::Close(_) => bail!("closed without acknowledging"),
+            _ => {}
+        }
+    }
+}
+
+async fn ping(ws: &mut Ws) -> Result<()> {
+    ws.send(WsMessage::Ping(b"doctor".to_vec())).await?;
+    loop {
+        match next_frame(ws).await? {
+            WsMessage::Pong(_) => return Ok(()),
+            WsMessage::Close(_) => bail!("closed before answering"),
+            _ => {}
+        }
+    }
+}
+
+async fn next_frame(ws: &mut Ws) -> Result<WsMessage> {
+    match timeout(STEP_TIMEOUT, ws.next()).await {
+        Ok(Some(frame)) => Ok(frame?),
+        Ok(None) => bail!("connection closed"),
+        Err(_) => bail!("no answer in {}s", STEP_TIMEOUT.as_secs()),
+    }
+}
+
+/// `host:port` to resolve for a relay URL, with the scheme's port if it names none
+fn socket_target(url: &str) -> String {
+    let host = relay_host(url);
+    // An IPv6 address is bracketed, so its colons don't count
+    let has_port = host.rsplit_once(':')
+        .is_some_and(|(h, port)| port.parse::<u16>().is_ok() && (!h.starts_with('[') || h.ends_with(']')));
+    if has_port {
+        return host.to_string();
+    }
+    let port = if url.starts_with("wss://") { 443 } else { 80 };
+    format!("{}:{}", host, port)
+}
+
+fn millis(d: Duration) -> String {
+    format!("{} ms", d.as_millis())
+}
+
+/// The chat UI needs a real terminal it can put in raw mode and switch screens on
+fn check_terminal() -> Vec<Check> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return vec![
+            Check::fail("terminal", "input or output isn't a terminal", "run wsp straight in a terminal, not through a pipe"),
+            Check::skip("raw mode", "no terminal"),
+            Check::skip("alternate screen", "no terminal"),
+        ];
+    }
+    let term = std::env::var("TERM").unwrap_or_default();
+    let mut checks = vec![if term == "dumb" {
+        Check::warn("terminal", "TERM=dumb", "colours and the sidebar won't show; set TERM (e.g. xterm-256color)")
+    } else {
+        Check::pass("terminal", if term.is_empty() { "interactive".to_string() } else { format!("TERM={}", term) })
+    }];
+    let raw = crossterm::terminal::enable_raw_mode().and_then(|_| crossterm::terminal::disable_raw_mode());
+    checks.push(match raw {
+        Ok(()) => Check::pass("raw mode", "can be switched on and off"),
+        Err(e) => Check::fail("raw mode", e.to_string(), "try another terminal emulator"),
+    });
+    let alternate = crossterm::execute!(
+        std::io::stdout(),
+        crossterm::terminal::EnterAlternateScreen,
+        crossterm::terminal::LeaveAlternateScreen
+    );
+    checks.push(match alternate {
+        Ok(()) => Check::pass("alternate screen", "supported"),
+        Err(e) => Check::fail("alternate screen", e.to_string(), "try another terminal emulator"),
+    });
+    checks
+}
+
+fn check_audio() -> Check {
+    let (inputs, outputs) = audio::device_names();
+    let devices = format!("in: {}; out: {}", list(&inputs), list(&outputs));
+    match audio::detect() {
+        AudioSupport::Full => Check::pass("audio", devices),
+        AudioSupport::ListenOnly => Check::warn("audio", devices, "no microphone: calls are joined muted"),
+        AudioSupport::Unavailable(reason) if cfg!(feature = "audio") => {
+            Check::fail("audio", reason, "check that a sound device is connected and not held by another program")
+        }
+        AudioSupport::Unavailable(reason) => Check::skip("audio", reason),
+    }
+}
+
+fn list(names: &[String]) -> String {
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+fn render(checks: &[Check]) -> String {
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for check in checks {
+        let label = match check.outcome {
+            Outcome::Pass => "✅ PASS",
+            Outcome::Warn => "⚠️  WARN",
+            Outcome::Fail => "❌ FAIL",
+            Outcome::Skip => "➖ SKIP",
+        };
+        out.push_str(&format!("{}  {:<width$}  {}\n", label, check.name, check.detail));
+        if let Some(ref hint) = check.hint {
+            out.push_str(&format!("{:<9}{:<width$}  ↳ {}\n", "", "", hint));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use wsp::relay::RelayServer;
+
+    fn outcomes(checks: &[Check]) -> Vec<Outcome> {
+        checks.iter().map(|c| c.outcome).collect()
+    }
+
+    #[tokio::test]
+    async fn test_relay_checks() {
+        let relay = RelayServer::new("127.0.0.1:0".to_string()).bind().await.unwrap().spawn();
+
+        let checks = check_relay(&format!("ws://{}", relay.local_addr())).await;
+        assert_eq!(outcomes(&checks), vec![Outcome::Pass; 4], "{:?}", checks);
+        assert!(checks[2].detail.contains("relay fingerprint"), "{:?}", checks[2]);
+
+        // Nothing listening any more: the steps after connecting are skipped
+        relay.shutdown().await.unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let checks = check_relay(&format!("ws://{}", closed)).await;
+        assert_eq!(outcomes(&checks), vec![Outcome::Pass, Outcome::Fail, Outcome::Skip, Outcome::Skip]);
+        assert!(render(&checks).contains("↳ is the relay running"));
+    }
+
+    #[test]
+    fn test_socket_target() {
+        assert_eq!(socket_target("ws://localhost:8899"), "localhost:8899");
+        assert_eq!(socket_target("wss://relay.example/ws"), "relay.example:443");
+        assert_eq!(socket_target("ws://[::1]"), "[::1]:80");
+        assert_eq!(socket_target("ws://[::1]:9000"), "[::1]:9000");
+    }
+}
@@ -0,0 +1,22 @@
+//! WSP — zero-knowledge end-to-end encrypted chat.
+//!
+//! The library holds everything a headless tool needs to talk WSP:
+//! - [`crypto`]: identity keys, the Double Ratchet and safety numbers
+//! - [`protocol`]: wire and plaintext message types
+//! - [`client`]: a reconnecting relay client that handles key exchange and encryption
+//! - [`relay`]: the blind-forwarding relay server
+//! - [`storage`]: encrypted local chat history
+//! - [`strings`]: user-facing words by key, in English or a translation
+//! - [`util`]: `~` expansion and default file locations
+//!
+//! The terminal UI and CLI live in the `wsp` binary behind the default `cli` feature,
+//! and voice calls behind the default `audio` one; build with `default-features = false`
+//! to leave out cpal and ratatui.
+
+pub mod client;
+pub mod crypto;
+pub mod protocol;
+pub mod relay;
+pub mod storage;
+pub mod strings;
+pub mod util;
@@ -1,43 +1,139 @@
 mod audio;
 mod cli;
-mod client;
-mod crypto;
-mod protocol;
-mod relay;
-mod storage;
+mod config;
+mod doctor;
+mod onboarding;
 mod tui;
 
 use anyhow::{Context, Result};
 use cli::{Cli, Commands};
+use config::Config;
+use base64::Engine;
+use crypto::safety_number::key_fingerprint;
 use crypto::Identity;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use util::expand_path;
+use wsp::{client, crypto, protocol, relay, strings, util};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse_args();
 
     match cli.command {
-        Commands::Init { path } => {
-            let path = expand_path(&path);
+        Commands::Init { path, profile } => {
+            let path = identity_path(path, profile.as_deref());
             init_identity(&path).await?;
         }
         Commands::Chat {
+            link,
             relay,
+            proxy,
             identity,
+            config,
+            profile,
             save,
+            ephemeral,
+            ephemeral_identity,
+            ephemeral_session,
             name,
+            low_bandwidth,
+            compress,
+            no_global,
+            expect_relay,
+            plain,
         } => {
-            let identity_path = expand_path(&identity);
-            start_chat(&relay, &identity_path, save, name).await?;
+            let identity_path = identity_path(identity, profile.as_deref());
+            let config_path = expand_path(&config);
+            let ephemeral = match (ephemeral, ephemeral_identity) {
+                (_, true) => Ephemeral::WithIdentity,
+                (true, false) => Ephemeral::On,
+                (false, false) => Ephemeral::Off,
+            };
+            let relay = RelayFlags {
+                url: relay,
+                link,
+                proxy,
+                low_bandwidth,
+                compress,
+                no_global,
+                expect_relay,
+                ephemeral_session: ephemeral_session || ephemeral != Ephemeral::Off,
+            };
+            let local = LocalFlags { ephemeral, save_history: save, plain };
+            start_chat(relay, &identity_path, &config_path, profile.as_deref(), local, name).await?;
         }
-        Commands::Relay { addr } => {
-            relay::start_relay(addr).await?;
+        Commands::Doctor { relay, identity, config, profile } => {
+            let identity_path = identity_path(identity, profile.as_deref());
+            let passed = doctor::run(relay, &identity_path, &expand_path(&config), profile.as_deref()).await?;
+            if !passed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Profiles => list_profiles()?,
+        Commands::Relay { addr, max_room_members, max_sessions, status_interval, admin_addr, key, show_id } => {
+            let key_path = key.map(|path| expand_path(&path)).unwrap_or_else(util::relay_key_path);
+            let key = crypto::relay_key::RelayKey::load_or_create(&key_path)?;
+            if show_id {
+                println!("{}", key.fingerprint());
+                return Ok(());
+            }
+            let status_interval = status_interval.map(std::time::Duration::from_secs);
+            relay::start_relay(addr, max_room_members, max_sessions, status_interval, admin_addr, key).await?;
         }
     }
 
     Ok(())
 }
 
+/// What `wsp chat --ephemeral` keeps off the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ephemeral {
+    Off,
+    /// No history or files written by wsp itself; the identity is only read
+    On,
+    /// As On, with an identity made for this session and never saved
+    WithIdentity,
+}
+
+/// The identity file asked for with `--identity`/`--path`, else the profile's
+fn identity_path(given: Option<String>, profile: Option<&str>) -> PathBuf {
+    given.map(|path| expand_path(&path)).unwrap_or_else(|| util::identity_path(profile))
+}
+
+/// `wsp profiles`: the default identity and each named profile, with the short key
+/// fingerprint the chat header shows
+fn list_profiles() -> Result<()> {
+    let mut profiles = vec![("default".to_string(), util::identity_path(None))];
+    if let Ok(entries) = std::fs::read_dir(util::profiles_dir()) {
+        let mut named: Vec<String> = entries
+            .filter_map(|e| e.ok()?.file_name().into_string().ok())
+            .filter(|name| util::valid_profile_name(name))
+            .collect();
+        named.sort();
+        profiles.extend(named.into_iter().map(|name| {
+            let path = util::identity_path(Some(&name));
+            (name, path)
+        }));
+    }
+    profiles.retain(|(_, path)| path.exists());
+    if profiles.is_empty() {
+        println!("No identities yet: `wsp init` makes the default one, `wsp init --profile <name>` a named one.");
+        return Ok(());
+    }
+    let width = profiles.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, path) in profiles {
+        let fingerprint = std::fs::read_to_string(util::public_key_path(&path))
+            .ok()
+            .and_then(|b64| base64::engine::general_purpose::STANDARD.decode(b64.trim()).ok())
+            .map(|key| key_fingerprint(&key).short_numeric());
+        match fingerprint {
+            Some(fingerprint) => println!("  {:<width$}  🔑 {}  {}", name, fingerprint, path.display()),
+            None => println!("  {:<width$}  🔒 (unlock it once with `wsp chat` to show its key)  {}", name, path.display()),
+        }
+    }
+    Ok(())
+}
+
 async fn init_identity(path: &PathBuf) -> Result<()> {
     println!("🔐 Generating new identity...");
 
@@ -83,24 +179,155 @@ async fn init_identity(path: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-async fn start_chat(relay_url: &str, identity_path: &PathBuf, _save_history: bool, nickname: Option<String>) -> Result<()> {
-    // Load identity
-    println!("🔐 Loading identity from: {}", identity_path.display());
-    println!("Enter password:");
-    let password = rpassword::read_password()?;
+/// How `wsp chat` was told to reach the relay, and how hard to lean on the link; the
+/// config fills in what's missing
+struct RelayFlags {
+    url: Option<String>,
+    /// `wsp chat <link>`: the link's relay, and who to open a DM with
+    link: Option<client::InviteLink>,
+    proxy: Option<client::Proxy>,
+    /// `--low-bandwidth`: start with /lowbw on
+    low_bandwidth: bool,
+    /// `--compress`: ask the relay to deflate frames
+    compress: bool,
+    /// `--no-global`: no Global chat, only DMs and groups
+    no_global: bool,
+    /// `--expect-relay`: the relay fingerprint to insist on
+    expect_relay: Option<String>,
+    /// `--ephemeral-session` (or `--ephemeral`): a random session id, not the one
+    /// derived from the identity key
+    ephemeral_session: bool,
+}
+
+/// What `wsp chat` keeps on this machine, and how it shows the chat
+struct LocalFlags {
+    ephemeral: Ephemeral,
+    /// `--save`: keep encrypted history
+    save_history: bool,
+    /// `--plain`: lines on stdout instead of the full-screen UI
+    plain: bool,
+}
+
+async fn start_chat(
+    relay: RelayFlags,
+    identity_path: &Path,
+    config_path: &Path,
+    profile: Option<&str>,
+    local: LocalFlags,
+    nickname: Option<String>,
+) -> Result<()> {
+    let LocalFlags { ephemeral, save_history, plain } = local;
+    let mut config = Config::load(config_path)?;
+    if ephemeral != Ephemeral::Off && save_history {
+        println!("⚠️  --save is ignored with --ephemeral: history stays in memory");
+    }
+
+    // Load identity, or walk a new user through creating one
+    let identity = match ephemeral {
+        Ephemeral::WithIdentity => {
+            let identity = Identity::generate();
+            println!("🕶️  One-time identity for this session only (never saved)");
+            println!("📋 Share this key out-of-band so peers can verify you:");
+            println!("{}", identity.public_key_b64());
+            println!("🔑 Fingerprint: {}", key_fingerprint(&identity.public_key_bytes()).numeric());
+            println!();
+            identity
+        }
+        _ if identity_path.exists() => {
+            println!("🔐 Loading identity from: {}", identity_path.display());
+            let identity = onboarding::unlock(&mut onboarding::Terminal, identity_path)?;
+            // Identities from before profiles had no public key beside them
+            if ephemeral == Ephemeral::Off {
+                let _ = identity.save_public_key(identity_path);
+            }
+            identity
+        }
+        Ephemeral::On => anyhow::bail!(
+            "No identity at {}, and --ephemeral won't create one on disk (add --ephemeral-identity for a one-time one)",
+            identity_path.display()
+        ),
+        Ephemeral::Off => {
+            onboarding::first_run(&mut onboarding::Terminal, identity_path, config_path, &mut config, profile)?
+        }
+    };
+    let config = config.for_profile(profile);
+    let locale = match config.language.as_deref() {
+        Some(tag) => strings::Locale::from_tag(tag).or_else(|| {
+            println!("⚠️  No translation for \"{}\" yet, using English", tag);
+            None
+        }),
+        None => strings::Locale::from_env(),
+    };
+    strings::set_locale(locale.unwrap_or_default());
 
-    let identity = Identity::load_from_file(identity_path, &password)
-        .context("Failed to load identity (wrong password?)")?;
+    // One chat per identity: a second one under the same derived session id would take
+    // the first one's session over on the relay, so ask first
+    let _lock = if relay.ephemeral_session {
+        None
+    } else {
+        Some(onboarding::lock_session(&mut onboarding::Terminal, identity_path)?)
+    };
+
+    // Flags win over the config file
+    let proxy = match relay.proxy {
+        Some(proxy) => Some(proxy),
+        None => config.proxy.as_deref()
+            .map(str::parse::<client::Proxy>)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid proxy in the config: {}", e))?,
+    };
+    let relay_url = relay.url
+        .or(relay.link.as_ref().map(client::InviteLink::relay_url))
+        .or(config.relay)
+        .unwrap_or_else(|| config::DEFAULT_RELAY.to_string());
+    let relay_url = relay_url.as_str();
+    let nickname = match nickname.or(config.nickname) {
+        Some(nick) => Some(protocol::sanitize_nickname(&nick).context("Nickname has no printable characters")?),
+        None => None,
+    };
 
     println!("✅ Identity loaded");
+    if let Some(profile) = profile {
+        println!("🗂️  Profile: {}", profile);
+    }
     println!("📋 Your ID: {}", identity.public_key_b64());
     if let Some(ref nick) = nickname {
         println!("👤 Nickname: {}", nick);
     }
     println!();
-    println!("🔌 Connecting to relay: {}", relay_url);
+    match proxy {
+        Some(ref proxy) => println!("🔌 Connecting to relay: {} (through {})", relay_url, proxy),
+        None => println!("🔌 Connecting to relay: {}", relay_url),
+    }
 
     let mut client = client::ChatClient::new(identity, relay_url.to_string(), nickname.clone());
+    if let Some(proxy) = proxy {
+        client.set_proxy(proxy);
+    }
+    if let Some(secs) = config.relay_silence_secs {
+        client.set_silence_timeout((secs > 0).then(|| std::time::Duration::from_secs(secs)));
+    }
+    if let Some(mins) = config.peer_idle_mins {
+        client.set_peer_idle((mins > 0).then(|| std::time::Duration::from_secs(mins * 60)));
+    }
+    if let Some(padding) = config.padding {
+        client.set_padding(padding);
+    }
+    client.set_compression(relay.compress || config.compress.unwrap_or(false));
+    // Ephemeral runs pin the relay for the session only, writing nothing
+    if ephemeral == Ephemeral::Off {
+        client.set_relay_pins(util::known_relays_path(identity_path));
+    }
+    if let Some(fingerprint) = relay.expect_relay {
+        client.expect_relay(fingerprint);
+    }
+    if relay.ephemeral_session {
+        client.set_ephemeral_session();
+    }
+    let no_global = relay.no_global || config.disable_global.unwrap_or(false);
+    if no_global {
+        client.set_no_global();
+    }
     let _own_id = client.identity_id();
     let own_public_key = client.identity_public_key_bytes();
     let session_id = client.session_id().to_string();
@@ -111,25 +338,65 @@ async fn start_chat(relay_url: &str, identity_path: &PathBuf, _save_history: boo
     let (msg_tx, incoming_rx, status_rx, peer_update_rx, audio_in_rx) = client.connect().await?;
 
     println!("✅ Connected! Share your Session ID with peers to start chatting.");
-    println!("Starting TUI...");
+    if !plain {
+        println!("Starting TUI...");
+    }
     println!();
 
     // Small delay to let connection establish
     tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 
     let mut ui = tui::ChatUI::new(session_id, nickname, own_public_key);
-    ui.run(msg_tx, incoming_rx, status_rx, peer_update_rx, audio_in_rx).await?;
+    ui.set_relay_url(relay_url.to_string());
+    if let Some(link) = relay.link {
+        ui.set_invite(link);
+    }
+    ui.set_profile(profile.map(str::to_string));
+    ui.set_ephemeral(ephemeral != Ephemeral::Off);
+    // A /nick outlasts the session, in the config file if there is one
+    if ephemeral == Ephemeral::Off && config_path.exists() {
+        ui.set_config_path(config_path.to_path_buf());
+    }
+    if let Some(mb) = config.max_share_mb {
+        ui.set_max_share_bytes(mb.saturating_mul(1024 * 1024));
+    }
+    if let Some(ref dir) = config.download_dir {
+        ui.set_download_dir(expand_path(dir));
+    }
+    if let Some(mb) = config.max_auto_size_mb {
+        ui.set_max_auto_size(mb.saturating_mul(1024 * 1024));
+    }
+    ui.set_history_sync(config.history_sync.unwrap_or(true));
+    ui.set_auto_join_verified(config.auto_join_verified.unwrap_or(false));
+    if let Some(kbps) = config.file_rate_kbps {
+        ui.set_file_rate(kbps.saturating_mul(1024));
+    }
+    if let Some(kbps) = config.call_file_rate_kbps {
+        ui.set_call_file_rate(kbps.saturating_mul(1024));
+    }
+    if let Some(mb) = config.session_warn_mb {
+        ui.set_session_warning(mb.saturating_mul(1024 * 1024));
+    }
+    let away_after_mins = config.away_after_mins.unwrap_or(config::DEFAULT_AWAY_AFTER_MINS);
+    ui.set_auto_away((away_after_mins > 0).then(|| std::time::Duration::from_secs(away_after_mins.saturating_mul(60))));
+    ui.set_away_reply(config.away_reply);
+    if let Some(sounds) = config.sounds {
+        ui.set_sounds(sounds);
+    }
+    ui.set_audio_support(audio::detect());
+    ui.set_client_stats(client.stats());
+    ui.set_slow_reconnect(client.slow_reconnect());
+    if relay.low_bandwidth {
+        ui.set_low_bandwidth();
+    }
+    if no_global {
+        ui.set_no_global();
+    }
+    if plain {
+        ui.run_plain(msg_tx, incoming_rx, status_rx, peer_update_rx, audio_in_rx).await?;
+    } else {
+        ui.run(msg_tx, incoming_rx, status_rx, peer_update_rx, audio_in_rx).await?;
+    }
 
     Ok(())
 }
-
-fn expand_path(path: &str) -> PathBuf {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        if let Some(home) = std::env::var_os("HOME") {
-            let mut buf = PathBuf::from(home);
-            buf.push(stripped);
-            return buf;
-        }
-    }
-    PathBuf::from(path)
-}
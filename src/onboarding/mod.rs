@@ -0,0 +1,428 @@
+//! First-run setup, identity unlocking, and the lock that keeps one `wsp chat` per
+//! identity running.
+//!
+//! Everything here talks through [`Prompt`], so the flows can be driven by scripted
+//! answers in tests instead of a terminal.
+
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use wsp::crypto::{Identity, IdentityFileError};
+use wsp::protocol::sanitize_nickname;
+
+/// Password attempts allowed when unlocking an existing identity
+pub const MAX_UNLOCK_ATTEMPTS: u32 = 3;
+
+/// Where the flows ask their questions
+pub trait Prompt {
+    /// Show a line of text
+    fn say(&mut self, text: &str);
+    /// Ask for a line of visible input (trimmed)
+    fn ask(&mut self, question: &str) -> Result<String>;
+    /// Ask for a password without echoing it
+    fn ask_password(&mut self, question: &str) -> Result<String>;
+}
+
+/// The real terminal: stdout, stdin and rpassword
+pub struct Terminal;
+
+impl Prompt for Terminal {
+    fn say(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn ask(&mut self, question: &str) -> Result<String> {
+        println!("{}", question);
+        let mut input = String::new();
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            bail!("No input (stdin closed)");
+        }
+        Ok(input.trim().to_string())
+    }
+
+    fn ask_password(&mut self, question: &str) -> Result<String> {
+        println!("{}", question);
+        Ok(rpassword::read_password()?)
+    }
+}
+
+/// No identity yet: explain, generate one, protect it with a password, and offer to
+/// save a default relay and nickname into `config` (into `profile`'s section if it's a
+/// named profile). Returns the new identity.
+pub fn first_run(
+    prompt: &mut impl Prompt,
+    identity_path: &Path,
+    config_path: &Path,
+    config: &mut Config,
+    profile: Option<&str>,
+) -> Result<Identity> {
+    prompt.say("👋 Welcome to WSP! No identity found, so let's make one.");
+    prompt.say("");
+    prompt.say("Your identity is a keypair generated on this machine. The public half is");
+    prompt.say("how peers recognise you; the private half never leaves this computer and");
+    prompt.say(&format!("is stored encrypted with a password at {}.", identity_path.display()));
+    prompt.say("There's no account and no recovery — lose the password, lose the identity.");
+    prompt.say("");
+
+    let password = choose_password(prompt)?;
+    let identity = Identity::generate();
+    if let Some(parent) = identity_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    identity.save_to_file(identity_path, &password)
+        .with_context(|| format!("Couldn't write {}", identity_path.display()))?;
+
+    prompt.say("");
+    prompt.say(&format!("✅ Identity saved to: {}", identity_path.display()));
+    prompt.say("📋 Your public ID (share this so peers can verify you):");
+    prompt.say(&identity.public_key_b64());
+    prompt.say("");
+
+    let mut changed = false;
+    let relay = prompt.ask(&format!(
+        "Default relay URL? (Enter for {})",
+        config.for_profile(profile).relay.as_deref().unwrap_or(crate::config::DEFAULT_RELAY)
+    ))?;
+    if !relay.is_empty() {
+        if relay.starts_with("ws://") || relay.starts_with("wss://") {
+            config.set_relay(profile, relay);
+            changed = true;
+        } else {
+            prompt.say("⚠️  Relay URLs start with ws:// or wss:// — keeping the default");
+        }
+    }
+    let nickname = prompt.ask("Nickname? (Enter to skip)")?;
+    if !nickname.is_empty() {
+        match sanitize_nickname(&nickname) {
+            Some(nickname) => {
+                config.set_nickname(profile, nickname);
+                changed = true;
+            }
+            None => prompt.say("⚠️  That nickname has no printable characters — skipping"),
+        }
+    }
+    if changed {
+        config.save(config_path)?;
+        prompt.say(&format!("💾 Saved to {}", config_path.display()));
+    }
+    prompt.say("");
+    Ok(identity)
+}
+
+/// Ask for a new password until it's entered the same way twice
+pub fn choose_password(prompt: &mut impl Prompt) -> Result<String> {
+    loop {
+        let password = prompt.ask_password("Choose a password for your identity:")?;
+        if password.is_empty() {
+            prompt.say("⚠️  The password can't be empty");
+            continue;
+        }
+        if let Some(hint) = password_hint(&password) {
+            prompt.say(&format!("💡 {}", hint));
+        }
+        let confirm = prompt.ask_password("Confirm password:")?;
+        if confirm == password {
+            return Ok(password);
+        }
+        prompt.say("❌ Passwords do not match, try again");
+    }
+}
+
+/// A nudge for weak passwords (advice only — the choice is the user's)
+fn password_hint(password: &str) -> Option<&'static str> {
+    let kinds = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ].iter().filter(|&&k| k).count();
+
+    if password.chars().count() < 8 {
+        Some("Weak password: under 8 characters. A few random words make a strong one.")
+    } else if password.chars().count() < 12 && kinds < 3 {
+        Some("Fair password: make it longer or mix in digits and symbols.")
+    } else {
+        None
+    }
+}
+
+/// Decrypt the identity at `path`, asking again after a wrong password. If the file
+/// fails its checksum, say so (it isn't the password) and offer its backup instead,
+/// restoring the file from it once it unlocks.
+pub fn unlock(prompt: &mut impl Prompt, path: &Path) -> Result<Identity> {
+    let mut source = path.to_path_buf();
+    if let Err(e) = Identity::check_file(path) {
+        if e.downcast_ref::<IdentityFileError>() != Some(&IdentityFileError::Corrupted) {
+            return Err(e).with_context(|| format!("Couldn't read {}", path.display()));
+        }
+        source = pick_backup(prompt, path)?;
+    }
+    for attempt in 1..=MAX_UNLOCK_ATTEMPTS {
+        let password = prompt.ask_password("Enter password:")?;
+        match Identity::load_from_file(&source, &password) {
+            Ok(identity) => {
+                if source != path {
+                    std::fs::copy(&source, path).with_context(|| format!("Couldn't restore {}", path.display()))?;
+                    prompt.say(&format!("✅ Restored {} from its backup", path.display()));
+                }
+                return Ok(identity);
+            }
+            Err(e) if attempt < MAX_UNLOCK_ATTEMPTS => {
+                prompt.say(&format!("❌ {} ({} of {} attempts)", e, attempt, MAX_UNLOCK_ATTEMPTS));
+            }
+            Err(e) => return Err(e).context("Failed to load identity (wrong password?)"),
+        }
+    }
+    unreachable!("the last attempt returns")
+}
+
+/// The identity at `path` is damaged: explain, and return its backup if there's a
+/// sound one and the user wants it
+fn pick_backup(prompt: &mut impl Prompt, path: &Path) -> Result<PathBuf> {
+    prompt.say(&format!("❌ {}: identity file appears corrupted (checksum mismatch).", path.display()));
+    prompt.say("   This isn't a wrong password — the file itself has been damaged.");
+    let backup = wsp::util::identity_backup_path(path);
+    let saved_at = match Identity::check_file(&backup) {
+        Ok(saved_at) => saved_at,
+        Err(_) => bail!(
+            "Identity file {} is corrupted (checksum mismatch), and there's no sound backup at {}",
+            path.display(),
+            backup.display()
+        ),
+    };
+    let when = saved_at
+        .and_then(|at| chrono::DateTime::from_timestamp(at, 0))
+        .map(|at| format!(" saved {}", at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")))
+        .unwrap_or_default();
+    let answer = prompt.ask(&format!("Unlock the backup ({}{}) and restore the file from it? [Y/n]", backup.display(), when))?;
+    if answer.eq_ignore_ascii_case("n") || answer.eq_ignore_ascii_case("no") {
+        bail!("Identity file {} is corrupted (checksum mismatch); its backup is {}", path.display(), backup.display());
+    }
+    Ok(backup)
+}
+
+/// The lock a running chat holds beside its identity file (see `lock_session`).
+/// Dropping it removes the file, unless another chat has taken it over since.
+pub struct SessionLock {
+    path: PathBuf,
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        if lock_holder(&self.path) == Some(std::process::id()) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Note that this process is chatting as the identity at `identity_path`. If another
+/// chat still is, ask whether to take over: the relay then closes that chat's session
+/// when this one connects under the same session id. A lock left by a chat that's
+/// gone is simply taken.
+pub fn lock_session(prompt: &mut impl Prompt, identity_path: &Path) -> Result<SessionLock> {
+    let path = wsp::util::identity_lock_path(identity_path);
+    let own = std::process::id();
+    match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(mut file) => {
+            writeln!(file, "{}", own).with_context(|| format!("Couldn't write {}", path.display()))?;
+            return Ok(SessionLock { path });
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e).with_context(|| format!("Couldn't create {}", path.display())),
+    }
+    if let Some(pid) = lock_holder(&path).filter(|pid| *pid != own && is_running(*pid)) {
+        let answer = prompt.ask(&format!("⚠️  Another wsp chat with this identity is already running (pid {}) — take over? (y/N)", pid))?;
+        if !answer.eq_ignore_ascii_case("y") && !answer.eq_ignore_ascii_case("yes") {
+            bail!("wsp chat is already running with {} (pid {})", identity_path.display(), pid);
+        }
+        prompt.say("🔀 Taking over: the other chat is disconnected once this one reaches the same relay");
+    }
+    std::fs::write(&path, format!("{}\n", own)).with_context(|| format!("Couldn't write {}", path.display()))?;
+    Ok(SessionLock { path })
+}
+
+/// The pid in a lock file
+fn lock_holder(path: &Path) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_running(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// No cheap way to ask here, so a lock is taken to be live (the prompt still lets the
+/// user take over)
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Answers questions from a script and records everything said
+    struct Scripted {
+        answers: VecDeque<&'static str>,
+        output: Vec<String>,
+    }
+
+    impl Scripted {
+        fn new(answers: &[&'static str]) -> Self {
+            Self { answers: answers.iter().copied().collect(), output: Vec::new() }
+        }
+
+        fn said(&self, needle: &str) -> bool {
+            self.output.iter().any(|line| line.contains(needle))
+        }
+    }
+
+    impl Prompt for Scripted {
+        fn say(&mut self, text: &str) {
+            self.output.push(text.to_string());
+        }
+
+        fn ask(&mut self, question: &str) -> Result<String> {
+            self.output.push(question.to_string());
+            self.answers.pop_front().map(str::to_string).context("script ran out")
+        }
+
+        fn ask_password(&mut self, question: &str) -> Result<String> {
+            self.ask(question)
+        }
+    }
+
+    #[test]
+    fn test_first_run_creates_identity_and_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let (identity_path, config_path) = (dir.path().join("wsp/identity"), dir.path().join("wsp/config.json"));
+        let mut prompt = Scripted::new(&[
+            "short", "shorter", // mismatch
+            "correct horse battery", "correct horse battery",
+            "wss://relay.example", "  alice  ",
+        ]);
+        let mut config = Config::default();
+
+        let identity = first_run(&mut prompt, &identity_path, &config_path, &mut config, None).unwrap();
+
+        assert!(prompt.said("Weak password"));
+        assert!(prompt.said("do not match"));
+        assert!(prompt.said(&identity.public_key_b64()));
+        let loaded = Identity::load_from_file(&identity_path, "correct horse battery").unwrap();
+        assert_eq!(loaded.public_key_b64(), identity.public_key_b64());
+        let saved = Config::load(&config_path).unwrap();
+        assert_eq!(saved.relay.as_deref(), Some("wss://relay.example"));
+        assert_eq!(saved.nickname.as_deref(), Some("alice"));
+        assert_eq!(saved, config);
+        let public_key = std::fs::read_to_string(wsp::util::public_key_path(&identity_path)).unwrap();
+        assert_eq!(public_key.trim(), identity.public_key_b64());
+    }
+
+    #[test]
+    fn test_first_run_skips_config_when_answers_are_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let mut prompt = Scripted::new(&["", "long enough pass", "long enough pass", "", ""]);
+
+        first_run(&mut prompt, &dir.path().join("identity"), &config_path, &mut Config::default(), None).unwrap();
+
+        assert!(prompt.said("can't be empty"));
+        assert!(!config_path.exists());
+    }
+
+    #[test]
+    fn test_unlock_retries_wrong_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity");
+        Identity::generate().save_to_file(&path, "right").unwrap();
+
+        assert!(unlock(&mut Scripted::new(&["wrong", "right"]), &path).is_ok());
+
+        let mut prompt = Scripted::new(&["a", "b", "c", "right"]);
+        assert!(unlock(&mut prompt, &path).is_err());
+        assert_eq!(prompt.answers.len(), 1, "stopped after three attempts");
+    }
+
+    #[test]
+    fn test_unlock_falls_back_to_the_backup_of_a_damaged_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity");
+        let identity = Identity::generate();
+        identity.save_to_file(&path, "right").unwrap();
+        let good = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &good[..good.len() - 5]).unwrap();
+
+        // Declining the backup names both files
+        let err = unlock(&mut Scripted::new(&["n"]), &path).err().unwrap();
+        assert!(format!("{:#}", err).contains("identity.bak"), "{:#}", err);
+
+        let mut prompt = Scripted::new(&["", "right"]);
+        let unlocked = unlock(&mut prompt, &path).unwrap();
+        assert!(prompt.said("checksum mismatch") && prompt.said("isn't a wrong password"));
+        assert!(prompt.said("Restored"));
+        assert_eq!(unlocked.public_key_b64(), identity.public_key_b64());
+        assert_eq!(std::fs::read(&path).unwrap(), good);
+
+        // Without a sound backup there's nothing to offer
+        std::fs::write(&path, &good[..20]).unwrap();
+        std::fs::write(wsp::util::identity_backup_path(&path), b"junk").unwrap();
+        let err = unlock(&mut Scripted::new(&[]), &path).err().unwrap();
+        assert!(format!("{:#}", err).contains("no sound backup"), "{:#}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_session_lock_asks_before_taking_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let identity_path = dir.path().join("identity");
+        let lock_path = wsp::util::identity_lock_path(&identity_path);
+        let own = std::process::id();
+
+        // Free: taken without asking, and removed again
+        let lock = lock_session(&mut Scripted::new(&[]), &identity_path).unwrap();
+        assert_eq!(lock_holder(&lock_path), Some(own));
+        drop(lock);
+        assert!(!lock_path.exists());
+
+        // Left by a chat that's gone: taken without asking
+        std::fs::write(&lock_path, format!("{}\n", u32::MAX)).unwrap();
+        drop(lock_session(&mut Scripted::new(&[]), &identity_path).unwrap());
+
+        // Held by a running process (our parent will do): asked, and only "y" takes over
+        let other = std::os::unix::process::parent_id();
+        std::fs::write(&lock_path, format!("{}\n", other)).unwrap();
+        let mut prompt = Scripted::new(&[""]);
+        let err = lock_session(&mut prompt, &identity_path).err().unwrap();
+        assert!(prompt.said(&format!("already running (pid {}) — take over? (y/N)", other)));
+        assert!(err.to_string().contains("already running"), "{}", err);
+        assert_eq!(lock_holder(&lock_path), Some(other));
+
+        let lock = lock_session(&mut Scripted::new(&["y"]), &identity_path).unwrap();
+        assert_eq!(lock_holder(&lock_path), Some(own));
+        // Taken over in turn: our drop leaves the other chat's lock alone
+        std::fs::write(&lock_path, format!("{}\n", other)).unwrap();
+        drop(lock);
+        assert_eq!(lock_holder(&lock_path), Some(other));
+    }
+
+    #[test]
+    fn test_password_hint() {
+        assert!(password_hint("abc123").unwrap().starts_with("Weak"));
+        assert!(password_hint("abcdefghij").unwrap().starts_with("Fair"));
+        assert_eq!(password_hint("Abcdefgh1!"), None);
+        assert_eq!(password_hint("four random words here"), None);
+    }
+}
@@ -0,0 +1,337 @@
+//! Framing for `Message` on the websocket.
+//!
+//! Each frame is a versioned envelope: one byte of wire version, one byte of message
+//! type, then the variant's fields in bincode (the same bytes the raw format carried
+//! after its 4-byte variant index). Type ids are fixed per variant rather than taken
+//! from its position in the enum, so variants can be added without renumbering the
+//! others, and a peer that meets a type it doesn't know can skip the frame instead of
+//! misreading it. Trailing payload bytes are ignored, so a variant can grow new fields
+//! at its end; a frame from a peer that predates such a field reads it as zero (false,
+//! empty or None).
+//!
+//! Frames in the old raw format (a bincode `Message`, variant index first) are still
+//! accepted while clients move over. They're told apart by the second byte: it's always
+//! 0 in a raw frame (the high bytes of a small little-endian index) and never 0 in an
+//! envelope.
+//!
+//! A connection can also carry deflated frames, if the client asked for them in the
+//! websocket handshake (`x-wsp-compress: deflate`) and the relay said yes in its
+//! answer. tungstenite has no permessage-deflate, so it's done here: a deflated frame
+//! is the byte `DEFLATED` (never the first byte of either framing) and then the
+//! frame, raw-deflated. Either side may still send any frame as it is, so audio, and
+//! frames that don't shrink, aren't deflated. Frames are compressed after they're
+//! encrypted, so this never mixes secrets with anything an observer controls; the
+//! savings are in the framing, ids and keys around the ciphertexts.
+
+use bincode::Options;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use super::{Message, MAX_MESSAGE_SIZE};
+
+/// Envelope version written by `encode`
+pub const WIRE_VERSION: u8 = 1;
+
+/// How a frame was framed on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+    /// Version + type byte + payload
+    Envelope,
+    /// A bare bincode `Message` (clients from before the envelope)
+    Legacy,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CodecError {
+    #[error("frame too short")]
+    Truncated,
+    #[error("unsupported wire version {0}")]
+    UnsupportedVersion(u8),
+    #[error("unknown message type {0}")]
+    UnknownType(u8),
+    #[error("bad payload: {0}")]
+    Payload(#[from] bincode::Error),
+    #[error("bad deflated frame")]
+    Deflate,
+}
+
+/// Stable wire id of each variant. Never renumber or reuse one; a new variant takes
+/// the next free id. 0 is reserved (it marks raw legacy frames).
+fn type_id(message: &Message) -> u8 {
+    match message {
+        Message::Connect { .. } => 1,
+        Message::Discover { .. } => 2,
+        Message::KeyExchange { .. } => 3,
+        Message::Encrypted { .. } => 4,
+        Message::Ack { .. } => 5,
+        Message::Error { .. } => 6,
+        Message::GroupJoin { .. } => 7,
+        Message::GroupLeave { .. } => 8,
+        Message::GroupEncrypted { .. } => 9,
+        Message::AudioFrame { .. } => 10,
+        Message::Typing { .. } => 11,
+        Message::ReadReceipt { .. } => 12,
+        Message::RoomPresence { .. } => 13,
+        Message::Delivered { .. } => 14,
+    }
+}
+
+/// Position in `Message` (what bincode tags variants with) of the variant `type_id`
+/// names. Kept in step with the enum by the round-trip tests below.
+fn variant_index(type_id: u8) -> Option<u32> {
+    match type_id {
+        1..=14 => Some(type_id as u32 - 1),
+        _ => None,
+    }
+}
+
+/// Read after every payload, so fields missing from an older peer's frame decode as
+/// zeros. Covers a few trailing fields; anything past it still fails as truncated.
+const MISSING_FIELDS: [u8; 16] = [0; 16];
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE as u64)
+}
+
+/// Frame a message in the current envelope
+pub fn encode(message: &Message) -> bincode::Result<Vec<u8>> {
+    let raw = bincode::serialize(message)?;
+    let mut frame = Vec::with_capacity(raw.len() - 2);
+    frame.push(WIRE_VERSION);
+    frame.push(type_id(message));
+    // Drop the 4-byte variant index; the type byte stands in for it
+    frame.extend_from_slice(&raw[4..]);
+    Ok(frame)
+}
+
+/// Frame a message the way `format` does (the relay answers old clients in kind)
+pub fn encode_as(message: &Message, format: WireFormat) -> bincode::Result<Vec<u8>> {
+    match format {
+        WireFormat::Envelope => encode(message),
+        WireFormat::Legacy => bincode::serialize(message),
+    }
+}
+
+/// Which framing `data` uses, judging by its first bytes
+pub fn wire_format(data: &[u8]) -> Option<WireFormat> {
+    match data {
+        [_, 0, 0, 0, ..] => Some(WireFormat::Legacy),
+        [_, ty, ..] if *ty != 0 => Some(WireFormat::Envelope),
+        _ => None,
+    }
+}
+
+/// Decode a frame from an untrusted source, in either framing. Length prefixes can't
+/// make it allocate more than MAX_MESSAGE_SIZE.
+pub fn decode(data: &[u8]) -> Result<Message, CodecError> {
+    match wire_format(data).ok_or(CodecError::Truncated)? {
+        WireFormat::Legacy => Ok(options().deserialize_from(data.chain(&MISSING_FIELDS[..]))?),
+        WireFormat::Envelope => {
+            let (version, ty, payload) = (data[0], data[1], &data[2..]);
+            if version != WIRE_VERSION {
+                return Err(CodecError::UnsupportedVersion(version));
+            }
+            let index = variant_index(ty).ok_or(CodecError::UnknownType(ty))?;
+            let tag = index.to_le_bytes();
+            Ok(options().deserialize_from(tag.chain(payload).chain(&MISSING_FIELDS[..]))?)
+        }
+    }
+}
+
+/// Handshake header asking for (and, echoed in the answer, agreeing to) deflated frames
+pub const COMPRESS_HEADER: &str = "x-wsp-compress";
+/// The only value of COMPRESS_HEADER so far
+pub const COMPRESS_DEFLATE: &str = "deflate";
+/// First byte of a deflated frame
+pub const DEFLATED: u8 = 0xdf;
+/// Frames shorter than this go as they are: there's nothing to gain
+const MIN_DEFLATE: usize = 64;
+
+/// Whether `frame` is sent as it is even on a compressing connection: Opus audio
+/// doesn't deflate, and it's the traffic that can least spare the time
+fn skips_deflate(frame: &[u8]) -> bool {
+    frame.len() < MIN_DEFLATE
+        || match wire_format(frame) {
+            Some(WireFormat::Envelope) => frame[1] == 10,
+            Some(WireFormat::Legacy) => frame[0] == 9,
+            None => true,
+        }
+}
+
+/// `frame` deflated and marked, or as it is if that wouldn't make it smaller
+pub fn deflate(frame: Vec<u8>) -> Vec<u8> {
+    if skips_deflate(&frame) {
+        return frame;
+    }
+    let mut out = Vec::with_capacity(frame.len() / 2);
+    out.push(DEFLATED);
+    let mut encoder = flate2::write::DeflateEncoder::new(out, flate2::Compression::fast());
+    match encoder.write_all(&frame).and_then(|_| encoder.finish()) {
+        Ok(deflated) if deflated.len() < frame.len() => deflated,
+        _ => frame,
+    }
+}
+
+/// The frame `data` carries: inflated if it's marked deflated, otherwise `data`
+/// itself. Inflating stops at MAX_MESSAGE_SIZE, so a small frame can't expand into
+/// a huge one.
+pub fn inflate(data: &[u8]) -> Result<Cow<'_, [u8]>, CodecError> {
+    let Some((&DEFLATED, deflated)) = data.split_first() else {
+        return Ok(Cow::Borrowed(data));
+    };
+    let mut frame = Vec::new();
+    flate2::read::DeflateDecoder::new(deflated)
+        .take(MAX_MESSAGE_SIZE as u64 + 1)
+        .read_to_end(&mut frame)
+        .map_err(|_| CodecError::Deflate)?;
+    if frame.len() > MAX_MESSAGE_SIZE {
+        return Err(CodecError::Deflate);
+    }
+    Ok(Cow::Owned(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{ErrorCode, CAPABILITIES};
+    use rand::{Rng, SeedableRng};
+
+    /// One of every variant
+    fn samples() -> Vec<Message> {
+        let id = "a".repeat(32);
+        vec![
+            Message::Connect { session_id: id.clone(), challenge: vec![] },
+            Message::Discover { target_session: id.clone(), from: id.clone() },
+            Message::KeyExchange { from: id.clone(), public_key: vec![1; 32], dh_ratchet_key: vec![2; 32], target: id.clone(), reset: true, capabilities: CAPABILITIES },
+            Message::Encrypted { from: id.clone(), target: id.clone(), header: vec![3; 40], nonce: vec![4; 12], ciphertext: vec![5; 64] },
+            Message::Ack { relay_key: vec![12; 32], proof: vec![13; 32] },
+            Message::Error { message: "nope".to_string(), code: ErrorCode::PeerNotFound, session: Some(id.clone()) },
+            Message::GroupJoin { session_id: id.clone(), group_id: "g".to_string(), join_token: Some(vec![6; 32]) },
+            Message::GroupLeave { session_id: id.clone(), group_id: "g".to_string() },
+            Message::GroupEncrypted { from: id.clone(), group_id: "g".to_string(), header: vec![7; 8], nonce: vec![8; 12], ciphertext: vec![9; 16] },
+            Message::AudioFrame { from: id.clone(), nonce: vec![10; 12], ciphertext: vec![11; 80], call_id: "c1".to_string() },
+            Message::Typing { from: id.clone(), target: String::new(), is_typing: true },
+            Message::ReadReceipt { from: id.clone(), target: id.clone(), message_id: "m1".to_string() },
+            Message::RoomPresence { group_id: "g".to_string(), count: 3 },
+            Message::Delivered { from: id.clone(), target: id.clone(), message_id: "m1".to_string() },
+        ]
+    }
+
+    #[test]
+    fn test_every_variant_round_trips_in_both_formats() {
+        let samples = samples();
+        for (i, message) in samples.iter().enumerate() {
+            // Type ids are 1-based and unique, and map back to the right variant
+            assert_eq!(type_id(message) as usize, i + 1);
+            for format in [WireFormat::Envelope, WireFormat::Legacy] {
+                let frame = encode_as(message, format).unwrap();
+                assert_eq!(wire_format(&frame), Some(format), "{:?}", message);
+                let decoded = decode(&frame).unwrap();
+                assert_eq!(bincode::serialize(&decoded).unwrap(), bincode::serialize(message).unwrap());
+            }
+        }
+        assert_eq!(variant_index(samples.len() as u8 + 1), None);
+    }
+
+    #[test]
+    fn test_envelope_layout() {
+        // An Ack from a relay without a key: two empty fields, each an 8-byte length
+        let frame = encode(&Message::Ack { relay_key: vec![], proof: vec![] }).unwrap();
+        assert_eq!(frame, [&[WIRE_VERSION, 5][..], &[0; 16]].concat());
+        let frame = encode(&Message::RoomPresence { group_id: "g".to_string(), count: 2 }).unwrap();
+        assert_eq!(&frame[..2], &[WIRE_VERSION, 13]);
+    }
+
+    #[test]
+    fn test_unknown_and_future_frames() {
+        assert!(matches!(decode(&[WIRE_VERSION, 200, 1, 2, 3]), Err(CodecError::UnknownType(200))));
+        assert!(matches!(decode(&[WIRE_VERSION + 1, 5]), Err(CodecError::UnsupportedVersion(_))));
+        assert!(matches!(decode(&[]), Err(CodecError::Truncated)));
+        assert!(matches!(decode(&[WIRE_VERSION]), Err(CodecError::Truncated)));
+        // Fields appended by a newer peer are ignored
+        let mut frame = encode(&Message::Connect { session_id: "abc".to_string(), challenge: vec![] }).unwrap();
+        frame.extend_from_slice(b"new field");
+        assert!(matches!(decode(&frame), Ok(Message::Connect { session_id, .. }) if session_id == "abc"));
+        // ...and fields the sender doesn't know yet come out zeroed
+        let kx = Message::KeyExchange { from: "abc".to_string(), public_key: vec![1; 32], dh_ratchet_key: vec![], target: String::new(), reset: false, capabilities: CAPABILITIES };
+        for format in [WireFormat::Envelope, WireFormat::Legacy] {
+            let frame = encode_as(&kx, format).unwrap();
+            let old = &frame[..frame.len() - 13];
+            assert!(matches!(decode(old), Ok(Message::KeyExchange { target, reset: false, capabilities: 0, .. }) if target.is_empty()));
+        }
+        // An error from an older relay has no code; a code from a newer one isn't known
+        let error = encode(&Message::Error { message: "no".to_string(), code: ErrorCode::SessionLimit, session: None }).unwrap();
+        let (old, code) = error.split_at(error.len() - 3);
+        assert_eq!(code, &[3, 0, 0]);
+        assert!(matches!(decode(old), Ok(Message::Error { code: ErrorCode::Unspecified, session: None, .. })));
+        let newer = [old, &[99, 0]].concat();
+        assert!(matches!(decode(&newer), Ok(Message::Error { code: ErrorCode::Unspecified, .. })));
+    }
+
+    #[test]
+    fn test_peer_not_found_names_the_session_apart_from_the_message() {
+        let id = "b".repeat(32);
+        let error = Message::Error { message: "gone, sorry".to_string(), code: ErrorCode::PeerNotFound, session: Some(id.clone()) };
+        for format in [WireFormat::Envelope, WireFormat::Legacy] {
+            let frame = encode_as(&error, format).unwrap();
+            assert!(matches!(
+                decode(&frame),
+                Ok(Message::Error { code: ErrorCode::PeerNotFound, session: Some(session), message }) if session == id && message == "gone, sorry"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_deflate_round_trips_and_skips_audio() {
+        for message in samples() {
+            let frame = encode(&message).unwrap();
+            assert_eq!(inflate(&deflate(frame.clone())).unwrap(), frame);
+        }
+        let id = "a".repeat(64);
+        let chat = encode(&Message::Encrypted { from: id.clone(), target: id.clone(), header: vec![3; 40], nonce: vec![4; 12], ciphertext: vec![5; 64] }).unwrap();
+        let deflated = deflate(chat.clone());
+        assert!(deflated[0] == DEFLATED && deflated.len() < chat.len());
+        let audio = encode(&Message::AudioFrame { from: id, nonce: vec![10; 12], ciphertext: vec![0; 200], call_id: "c1".to_string() }).unwrap();
+        assert_eq!(deflate(audio.clone()), audio);
+
+        // A bomb stops at the size limit
+        let bomb = deflate(vec![WIRE_VERSION; MAX_MESSAGE_SIZE + 1]);
+        assert!(bomb.len() < MAX_MESSAGE_SIZE / 100);
+        assert!(matches!(inflate(&bomb), Err(CodecError::Deflate)));
+        assert!(matches!(inflate(&[DEFLATED, 0xff, 0xff]), Err(CodecError::Deflate)));
+    }
+
+    #[test]
+    fn test_huge_length_prefix_is_refused() {
+        let mut frame = vec![WIRE_VERSION, 1];
+        frame.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decode(&frame).is_err());
+    }
+
+    #[test]
+    fn test_truncated_and_garbled_frames_never_panic() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for message in samples() {
+            for format in [WireFormat::Envelope, WireFormat::Legacy] {
+                let frame = encode_as(&message, format).unwrap();
+                for len in 0..frame.len() {
+                    let _ = decode(&frame[..len]);
+                }
+                for _ in 0..200 {
+                    let mut garbled = frame.clone();
+                    let i = rng.gen_range(0..garbled.len());
+                    garbled[i] = rng.gen();
+                    let _ = decode(&garbled);
+                }
+            }
+        }
+        for _ in 0..1000 {
+            let len = rng.gen_range(0..64);
+            let junk: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            let _ = decode(&junk);
+        }
+    }
+}
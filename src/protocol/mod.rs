@@ -1,12 +1,84 @@
+//! Wire messages seen by the relay, and the plaintext format carried inside them.
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::crypto::sender_key::SenderKeyDistribution;
+use crate::strings::Key;
+
+pub mod codec;
+
+/// Largest websocket message either side accepts (file chunks are 16KB, so this is generous)
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// Size of one file transfer chunk
+pub const FILE_CHUNK_SIZE: usize = 16384;
+/// Largest file a peer may offer — incoming transfers are assembled in memory
+pub const MAX_FILE_SIZE: u64 = 1024 * 1024 * 1024;
+/// Longest chat message shown, in characters; longer content is truncated
+pub const MAX_CONTENT_CHARS: usize = 8192;
+/// Chat messages longer than this many bytes are sent as several parts
+pub const MAX_PART_BYTES: usize = 8 * 1024;
+/// Most parts one message may be split into (so at most 512KB of text)
+pub const MAX_MESSAGE_PARTS: u32 = 64;
+/// Longest nickname (or group name) accepted from a peer, in characters
+pub const MAX_NICKNAME_CHARS: usize = 32;
+/// Most group messages in one history catch-up batch
+pub const MAX_HISTORY_BATCH: usize = 50;
+/// Most recent group messages a sender keeps to send again, and most one request asks for
+pub const MAX_GROUP_RESEND: usize = 50;
+/// Longest away message accepted from a peer, in characters
+pub const MAX_AWAY_MESSAGE_CHARS: usize = 100;
+/// Length of a room join token (shared inside the E2EE group invite)
+pub const JOIN_TOKEN_LEN: usize = 32;
+/// Most blanks a notice can fill
+pub const MAX_NOTICE_PARAMS: usize = 4;
+/// Length of the random salt each caller contributes to a call's voice key
+pub const CALL_SALT_LEN: usize = 32;
+/// Most member names a group invite carries
+pub const MAX_INVITE_HINTS: usize = 256;
+/// Key exchange capability bit: padded plaintexts can be read (see `crypto::ratchet::pad`)
+pub const CAP_PADDING: u32 = 1 << 0;
+/// Every capability this build announces in its key exchanges
+pub const CAPABILITIES: u32 = CAP_PADDING;
+/// Combining marks kept on one base character ("zalgo" text stacks hundreds)
+const MAX_COMBINING_RUN: usize = 4;
+
+/// Deserialize bincode from an untrusted source. Same wire format as `bincode::deserialize`,
+/// but a crafted length prefix can't make it allocate more than MAX_MESSAGE_SIZE.
+pub fn decode_bincode<T: DeserializeOwned>(data: &[u8]) -> bincode::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE as u64)
+        .deserialize(data)
+}
+
+/// First 12 characters of a session id, for display (ids from the wire may be short or odd)
+pub fn short_id(id: &str) -> &str {
+    id.char_indices().nth(12).map_or(id, |(end, _)| &id[..end])
+}
 
 /// Message types sent over the wire
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     /// Initial handshake with relay
-    Connect { session_id: String },
-    /// Peer discovery
-    Discover { target_session: String },
+    Connect {
+        session_id: String,
+        /// A fresh X25519 public key the relay proves its own key against in the Ack
+        /// (empty from clients that predate it)
+        #[serde(default)]
+        challenge: Vec<u8>,
+    },
+    /// Ask the session `target_session` to start a key exchange with us, for a peer
+    /// whose broadcast we never saw. The relay answers PeerNotFound if it's not there.
+    Discover {
+        target_session: String,
+        /// Session asking (empty from clients that predate it)
+        #[serde(default)]
+        from: String,
+    },
     /// Key exchange message (contains identity public key + ephemeral DH ratchet key)
     KeyExchange {
         from: String,
@@ -16,6 +88,17 @@ pub enum Message {
         /// steps (where the sender generates a NEW key) are detectable.
         #[serde(default)]
         dh_ratchet_key: Vec<u8>,
+        /// Session ID the exchange is meant for; empty = every peer
+        #[serde(default)]
+        target: String,
+        /// Offer to replace a session that has stopped decrypting (sent to `target` only).
+        /// On a broadcast without a ratchet key: the sender holds no sessions (it has
+        /// restarted under the same session id), so any we have with it is stale.
+        #[serde(default)]
+        reset: bool,
+        /// What the sender can handle (`CAP_*` bits); zero from clients that predate it
+        #[serde(default)]
+        capabilities: u32,
     },
     /// Encrypted message payload
     Encrypted {
@@ -30,14 +113,33 @@ pub enum Message {
         nonce: Vec<u8>,
         ciphertext: Vec<u8>,
     },
-    /// Acknowledgment
-    Ack,
-    /// Error
-    Error { message: String },
+    /// Acknowledgment of Connect
+    Ack {
+        /// The relay's public key (empty from relays that predate it)
+        #[serde(default)]
+        relay_key: Vec<u8>,
+        /// Its answer to the Connect's challenge (see `crypto::relay_key`)
+        #[serde(default)]
+        proof: Vec<u8>,
+    },
+    /// The relay refused something; `message` says what, for people
+    Error {
+        message: String,
+        /// What kind of refusal, for clients to act on (zero from relays that predate it)
+        #[serde(default)]
+        code: ErrorCode,
+        /// The session a PeerNotFound is about
+        #[serde(default)]
+        session: Option<String>,
+    },
     /// Join a group room on the relay (relay tracks room membership)
     GroupJoin {
         session_id: String,
         group_id: String,
+        /// Room password: the first joiner sets it, later joins must match.
+        /// The relay keeps only its hash.
+        #[serde(default)]
+        join_token: Option<Vec<u8>>,
     },
     /// Leave a group room on the relay
     GroupLeave {
@@ -59,6 +161,9 @@ pub enum Message {
         from: String,
         nonce: Vec<u8>,
         ciphertext: Vec<u8>,
+        /// The call it belongs to (`CallSalt::call_id`); empty from clients that predate it
+        #[serde(default)]
+        call_id: String,
     },
     /// Lightweight typing indicator — NOT encrypted, doesn't touch the ratchet
     Typing {
@@ -73,6 +178,19 @@ pub enum Message {
         target: String,
         message_id: String,
     },
+    /// How many sessions are in a group room — relay to room members only, whenever
+    /// someone joins or leaves. Never says who.
+    RoomPresence {
+        group_id: String,
+        count: u32,
+    },
+    /// A chat message reached `from` — NOT encrypted, doesn't touch the ratchet. Lets
+    /// the sender stop holding it for a resend.
+    Delivered {
+        from: String,
+        target: String,
+        message_id: String,
+    },
 }
 
 /// File offer metadata
@@ -83,6 +201,14 @@ pub struct FileOffer {
     pub size: u64,            // Total size in bytes
     pub checksum: String,     // Blake3 hash of full file
     pub total_chunks: u32,    // Number of chunks
+    /// Content type the sender claims (the receiver checks it against the data)
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    /// A shared directory, sent as a tar archive of `entry_count` files and folders
+    #[serde(default)]
+    pub is_archive: bool,
+    #[serde(default)]
+    pub entry_count: u32,
 }
 
 /// File chunk data
@@ -98,6 +224,208 @@ pub struct FileChunk {
 pub struct GroupInvite {
     pub group_id: String,
     pub group_name: String,
+    /// Token the relay demands before letting us into the room
+    #[serde(default)]
+    pub join_token: Option<Vec<u8>>,
+    /// Every member the inviter holds an identity key for, and the name they know them
+    /// by. The invitee sets up a session with each and shows the name until the
+    /// member's own nickname arrives over it.
+    #[serde(default)]
+    pub members: Vec<MemberHint>,
+}
+
+/// A group member's nickname as the inviter knows it; the identity key lets the
+/// invitee check it is still talking about the same person
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberHint {
+    pub session_id: String,
+    pub nickname: String,
+    pub public_key: Vec<u8>,
+}
+
+/// Who a member believes is in a group, sent to each member when membership changes
+/// or a session with one comes up. The digest is over the sorted identity keys, so two
+/// members agree on the group exactly when their digests match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupRoster {
+    pub digest: Vec<u8>,
+    /// Every member the sender holds an identity key for, the sender included
+    pub members: Vec<MemberHint>,
+    /// An answer to a roster whose digest didn't match; never answered itself
+    #[serde(default)]
+    pub reply: bool,
+}
+
+/// One caller's share of a call's voice key: which call, and a fresh random salt.
+/// Sent with the call request and with accepting it; each pair of callers derives
+/// their key from both salts, so every call gets a key of its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallSalt {
+    pub call_id: String,
+    pub salt: Vec<u8>,
+}
+
+impl CallSalt {
+    /// A new random salt for `call_id`
+    pub fn generate(call_id: String) -> Self {
+        Self { call_id, salt: rand::random::<[u8; CALL_SALT_LEN]>().to_vec() }
+    }
+}
+
+/// Mutual safety-number verification, carried in a DM
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Verification {
+    /// Compare safety numbers with me; the challenge is shown as words on both screens
+    Request { challenge: Vec<u8> },
+    /// I compared them and they match; `proof` binds both identity keys to the challenge
+    Confirm { challenge: Vec<u8>, proof: Vec<u8> },
+}
+
+/// Group sender-key housekeeping between two members, carried in a DM and never shown
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SenderKeyUpdate {
+    /// My sending chain for `group_id`
+    Distribution { group_id: String, distribution: SenderKeyDistribution },
+    /// A group frame of yours arrived under a key I don't have; `iteration` is where it sat
+    Request { group_id: String, key_id: u32, iteration: u32 },
+}
+
+/// Asks a group member to send some of their messages again, by `group_seq`, after
+/// they went missing on the way. Carried in a DM.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupResend {
+    pub group_id: String,
+    pub seqs: Vec<u64>,
+}
+
+/// Group history catch-up between two members, carried in a DM
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HistorySync {
+    /// Send me the messages you have from `group_id` since this unix time
+    Request { group_id: String, since_timestamp: i64 },
+    /// Some of them, oldest first; `more` is false on the last batch
+    Batch { group_id: String, messages: Vec<PlainMessage>, more: bool },
+}
+
+/// A file transfer or call milestone recorded as a chat entry. Kept with the entry so
+/// exports (and anything reading stored messages) can tell it from other notices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatEvent {
+    FileOffered,
+    FileAccepted,
+    FileRejected,
+    FileCompleted,
+    FileFailed,
+    CallStarted,
+    CallEnded,
+    CallMissed,
+}
+
+/// A system notice sent as what happened rather than in words, so the receiver can put
+/// it in its own language: `kind` names a message in `strings` and `params` fill its
+/// blanks. The kind is a string, not an enum, so one added later falls back to the
+/// message's `content` on clients that don't know it instead of failing to decode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notice {
+    pub kind: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+}
+
+/// Whether someone is at their keyboard, carried as a string in `PlainMessage::presence`:
+/// "active", "away", or "away:<message>"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Presence {
+    Active,
+    Away(Option<String>),
+}
+
+impl Presence {
+    pub fn to_wire(&self) -> String {
+        match self {
+            Presence::Active => "active".to_string(),
+            Presence::Away(None) => "away".to_string(),
+            Presence::Away(Some(message)) => format!("away:{}", message),
+        }
+    }
+
+    /// None for anything that isn't one of the forms above
+    pub fn parse(wire: &str) -> Option<Self> {
+        match wire.split_once(':') {
+            None if wire == "active" => Some(Presence::Active),
+            None if wire == "away" => Some(Presence::Away(None)),
+            Some(("away", "")) => Some(Presence::Away(None)),
+            Some(("away", message)) => Some(Presence::Away(Some(message.to_string()))),
+            _ => None,
+        }
+    }
+}
+
+/// Why the relay sent `Message::Error`. Goes over the wire as a number, so a code from
+/// a newer relay reads as `Unspecified` instead of spoiling the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(from = "u16", into = "u16")]
+pub enum ErrorCode {
+    #[default]
+    Unspecified,
+    /// Too many frames the relay couldn't use; it closed the connection
+    MalformedFrames,
+    /// Something other than Connect came first; it closed the connection
+    Unauthorized,
+    /// The relay holds as many sessions as it will take; it closed the connection
+    SessionLimit,
+    /// A group join was refused: wrong join token
+    JoinRefused,
+    /// A group join was refused: the room is full
+    RoomFull,
+    /// We're sending faster than the relay allows
+    RateLimited,
+    /// A frame over the size limit; it closed the connection
+    FrameTooLarge,
+    /// A Discover named a session the relay doesn't have; the error's `session` says which
+    PeerNotFound,
+    /// Another connection came in under our session id (the same identity started
+    /// elsewhere) and took the session over; it closed the connection
+    SessionReplaced,
+}
+
+impl ErrorCode {
+    /// Reconnecting would only be refused again the same way
+    pub fn is_fatal(self) -> bool {
+        matches!(self, Self::MalformedFrames | Self::Unauthorized | Self::SessionReplaced)
+    }
+
+    /// The relay closes the connection after sending this
+    pub fn closes_connection(self) -> bool {
+        matches!(
+            self,
+            Self::MalformedFrames | Self::Unauthorized | Self::SessionLimit | Self::FrameTooLarge | Self::SessionReplaced
+        )
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1 => Self::MalformedFrames,
+            2 => Self::Unauthorized,
+            3 => Self::SessionLimit,
+            4 => Self::JoinRefused,
+            5 => Self::RoomFull,
+            6 => Self::RateLimited,
+            7 => Self::FrameTooLarge,
+            8 => Self::PeerNotFound,
+            9 => Self::SessionReplaced,
+            _ => Self::Unspecified,
+        }
+    }
+}
+
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        code as u16
+    }
 }
 
 /// Plaintext message format (before encryption)
@@ -149,6 +477,82 @@ pub struct PlainMessage {
     /// Read receipt — contains the message_id that was read
     #[serde(default)]
     pub read_receipt: Option<String>,
+    /// Safety-number verification handshake
+    #[serde(default)]
+    pub verification: Option<Verification>,
+    /// Seconds after `timestamp` when this message disappears from both sides
+    #[serde(default)]
+    pub expire_after: Option<u64>,
+    /// New disappearing-message setting for the conversation, in seconds (0 = off)
+    #[serde(default)]
+    pub expire_policy: Option<u64>,
+    /// Position of this part in a split message (0-based)
+    #[serde(default)]
+    pub part_index: Option<u32>,
+    /// How many parts the split message has
+    #[serde(default)]
+    pub part_total: Option<u32>,
+    /// Shared by every part of one split message
+    #[serde(default)]
+    pub part_group_id: Option<String>,
+    /// Group sender-key distribution or request (handled by the client, not the TUI)
+    #[serde(default)]
+    pub sender_key: Option<SenderKeyUpdate>,
+    /// Group history catch-up request or reply
+    #[serde(default)]
+    pub history: Option<HistorySync>,
+    /// Away/active announcement (`Presence` in wire form)
+    #[serde(default)]
+    pub presence: Option<String>,
+    /// Group id of an invite the sender turned down
+    #[serde(default)]
+    pub invite_declined: Option<String>,
+    /// Sealed under a re-keyed session to confirm it (handled by the client, not the TUI)
+    #[serde(default)]
+    pub session_reset: bool,
+    /// Our salt for a call's voice key, with `call_request` and an accepting `call_accept`
+    #[serde(default)]
+    pub call_salt: Option<CallSalt>,
+    /// With a declining `call_accept`: not a no, just already in another call
+    #[serde(default)]
+    pub call_busy: bool,
+    /// We muted (true) or unmuted our microphone in a group call
+    #[serde(default)]
+    pub call_muted: Option<bool>,
+    /// Group call keepalive: true asks "still there?", false answers it
+    #[serde(default)]
+    pub call_keepalive: Option<bool>,
+    /// We put the call on hold (true) or took it off again
+    #[serde(default)]
+    pub call_hold: Option<bool>,
+    /// Set on our own transfer and call entries; never sent
+    #[serde(default)]
+    pub event: Option<ChatEvent>,
+    /// An action (`/me waves`), shown as "· alice waves" instead of "alice: waves"
+    #[serde(default)]
+    pub action: bool,
+    /// Our view of the membership of `group_id`, to be checked against the recipient's
+    #[serde(default)]
+    pub group_roster: Option<GroupRoster>,
+    /// The sender's running count of its chat in `group_id`, so members can put the
+    /// group's messages in order and notice any that went missing
+    #[serde(default)]
+    pub group_seq: Option<u64>,
+    /// Asks the recipient to send some of their group messages again
+    #[serde(default)]
+    pub group_resend: Option<GroupResend>,
+    /// What a system notice says, for the receiver to word (`content` has the English)
+    #[serde(default)]
+    pub notice: Option<Notice>,
+    /// Set locally on messages that came from another member's history; never sent
+    #[serde(skip)]
+    pub synced: bool,
+    /// Set locally when the message mentions us; never sent
+    #[serde(skip)]
+    pub mentions_me: bool,
+    /// Set locally when the message arrived well after newer ones; never sent
+    #[serde(skip)]
+    pub delayed: bool,
 }
 
 impl PlainMessage {
@@ -161,41 +565,54 @@ impl PlainMessage {
         }
     }
 
+    /// A global chat message, with a fresh id
     pub fn new(sender: String, content: String) -> Self {
-        Self { content, ..Self::base(sender) }
+        Self { content, message_id: Some(Self::generate_id()), ..Self::base(sender) }
     }
 
+    /// A direct (one-to-one) chat message, with a fresh id
     pub fn direct(sender: String, content: String) -> Self {
-        Self { content, direct: true, ..Self::base(sender) }
+        Self { content, direct: true, message_id: Some(Self::generate_id()), ..Self::base(sender) }
     }
 
+    /// A system notice shown in the chat (joins, leaves, call events)
     pub fn system(sender: String, content: String) -> Self {
         Self { content, system: true, ..Self::base(sender) }
     }
 
+    /// A transfer or call milestone, shown like a system notice
+    pub fn event(sender: String, event: ChatEvent, content: String) -> Self {
+        Self { content, system: true, event: Some(event), ..Self::base(sender) }
+    }
+
+    /// Nickname announcement
     pub fn nickname(sender: String, nickname: String) -> Self {
         Self { system: true, nickname: Some(nickname), ..Self::base(sender) }
     }
 
+    /// Ask the peer to open a DM tab with us
     pub fn dm_request(sender: String) -> Self {
         Self { system: true, direct: true, dm_request: true, ..Self::base(sender) }
     }
 
+    /// Offer a file for transfer
     pub fn file_offer(sender: String, offer: FileOffer, direct: bool) -> Self {
         Self { direct, file_offer: Some(offer), ..Self::base(sender) }
     }
 
+    /// One chunk of an accepted file
     pub fn file_chunk(sender: String, chunk: FileChunk, direct: bool) -> Self {
         Self { direct, file_chunk: Some(chunk), ..Self::base(sender) }
     }
 
+    /// Accept or reject a file offer
     pub fn file_response(sender: String, file_id: String, accept: bool, direct: bool) -> Self {
         Self { content: file_id, direct, file_response: Some(accept), ..Self::base(sender) }
     }
 
-    /// A group chat message
+    /// A group chat message, with a fresh id
     pub fn group(sender: String, content: String, group_id: String) -> Self {
-        Self { content, group_id: Some(group_id), ..Self::base(sender) }
+        Self { content, group_id: Some(group_id), message_id: Some(Self::generate_id()), ..Self::base(sender) }
     }
 
     /// A group invite sent via DM
@@ -203,9 +620,39 @@ impl PlainMessage {
         Self { system: true, direct: true, group_invite: Some(invite), ..Self::base(sender) }
     }
 
+    /// Turn down an invite to `group_id`, so the inviter stops counting us as a member
+    pub fn invite_declined(sender: String, group_id: String) -> Self {
+        Self { system: true, direct: true, invite_declined: Some(group_id), ..Self::base(sender) }
+    }
+
+    /// Our member list for `group_id`, sent pairwise to one member
+    pub fn group_roster(sender: String, group_id: String, roster: GroupRoster) -> Self {
+        Self { system: true, direct: true, group_id: Some(group_id), group_roster: Some(roster), ..Self::base(sender) }
+    }
+
+    /// First message under a re-keyed pairwise session, proving it works
+    pub fn session_reset(sender: String) -> Self {
+        Self { system: true, direct: true, session_reset: true, ..Self::base(sender) }
+    }
+
+    /// Sender-key distribution or request for one group member
+    pub fn sender_key(sender: String, update: SenderKeyUpdate) -> Self {
+        Self { system: true, direct: true, sender_key: Some(update), ..Self::base(sender) }
+    }
+
+    /// Ask a member to send some of their group messages again
+    pub fn group_resend(sender: String, request: GroupResend) -> Self {
+        Self { system: true, direct: true, group_resend: Some(request), ..Self::base(sender) }
+    }
+
+    /// Group history catch-up request or batch
+    pub fn history(sender: String, sync: HistorySync) -> Self {
+        Self { system: true, direct: true, history: Some(sync), ..Self::base(sender) }
+    }
+
     /// Voice call request
-    pub fn call_request(sender: String) -> Self {
-        Self { system: true, direct: true, call_request: Some(true), ..Self::base(sender) }
+    pub fn call_request(sender: String, salt: CallSalt) -> Self {
+        Self { system: true, direct: true, call_request: Some(true), call_salt: Some(salt), ..Self::base(sender) }
     }
 
     /// Voice call accept/reject
@@ -213,6 +660,26 @@ impl PlainMessage {
         Self { system: true, direct: true, call_accept: Some(accept), ..Self::base(sender) }
     }
 
+    /// Turn a call away because we're already in one
+    pub fn call_busy(sender: String) -> Self {
+        Self { call_busy: true, ..Self::call_accept(sender, false) }
+    }
+
+    /// Our mute state, for the others in a group call
+    pub fn call_muted(sender: String, muted: bool) -> Self {
+        Self { system: true, direct: true, call_muted: Some(muted), ..Self::base(sender) }
+    }
+
+    /// Ask a quiet group call member whether they're still there, or answer that
+    pub fn call_keepalive(sender: String, ping: bool) -> Self {
+        Self { system: true, direct: true, call_keepalive: Some(ping), ..Self::base(sender) }
+    }
+
+    /// Tell the others in a call we put it on hold, or took it off
+    pub fn call_hold(sender: String, on_hold: bool) -> Self {
+        Self { system: true, direct: true, call_hold: Some(on_hold), ..Self::base(sender) }
+    }
+
     /// Voice call hangup
     pub fn call_hangup(sender: String) -> Self {
         Self { system: true, direct: true, call_hangup: Some(true), ..Self::base(sender) }
@@ -228,6 +695,220 @@ impl PlainMessage {
         Self { system: true, direct, read_receipt: Some(message_id), ..Self::base(sender) }
     }
 
+    /// Away/active announcement
+    pub fn presence(sender: String, presence: &Presence) -> Self {
+        Self { system: true, presence: Some(presence.to_wire()), ..Self::base(sender) }
+    }
+
+    /// Decode a decrypted payload (MessagePack, falling back to legacy bincode)
+    pub fn decode(plaintext: &[u8]) -> Option<Self> {
+        rmp_serde::from_slice(plaintext)
+            .or_else(|_| decode_bincode(plaintext))
+            .ok()
+    }
+
+    /// Bring a message from a peer within sane bounds before anything displays or
+    /// stores it. Text is sanitized for the terminal and capped in place, with the
+    /// repairs reported in `Ok`; a bogus file offer, chunk or name rejects the message.
+    pub fn sanitize(&mut self) -> Result<Vec<&'static str>, &'static str> {
+        if let Some(ref offer) = self.file_offer {
+            if offer.size > MAX_FILE_SIZE {
+                return Err("file offer too large");
+            }
+            if offer.total_chunks as u64 != offer.size.div_ceil(FILE_CHUNK_SIZE as u64) {
+                return Err("file offer chunk count doesn't match its size");
+            }
+        }
+        if let Some(ref chunk) = self.file_chunk {
+            if chunk.data.len() > FILE_CHUNK_SIZE {
+                return Err("oversized file chunk");
+            }
+        }
+        match (self.part_index, self.part_total, &self.part_group_id) {
+            (None, None, None) => {}
+            (Some(index), Some(total), Some(_)) if index < total && (2..=MAX_MESSAGE_PARTS).contains(&total) => {
+                if self.content.len() > MAX_PART_BYTES {
+                    return Err("oversized message part");
+                }
+            }
+            _ => return Err("malformed message part"),
+        }
+
+        if self.group_resend.as_ref().is_some_and(|r| r.seqs.len() > MAX_GROUP_RESEND) {
+            return Err("oversized resend request");
+        }
+        if self.notice.as_ref().is_some_and(|n| n.kind.len() > 32 || n.params.len() > MAX_NOTICE_PARAMS) {
+            return Err("malformed notice");
+        }
+
+        let mut repairs = Vec::new();
+        if let Some(HistorySync::Batch { ref group_id, ref mut messages, .. }) = self.history {
+            if messages.len() > MAX_HISTORY_BATCH {
+                return Err("oversized history batch");
+            }
+            for msg in messages.iter_mut() {
+                if msg.history.is_some() || msg.group_id.as_ref() != Some(group_id) {
+                    return Err("malformed history batch");
+                }
+                if !msg.sanitize()?.is_empty() {
+                    repairs.push("history message cleaned or truncated");
+                }
+            }
+        }
+        let mut content = sanitize_text(&self.content, true);
+        if let Some((end, _)) = content.char_indices().nth(MAX_CONTENT_CHARS) {
+            content.truncate(end);
+            content.push_str(" … [truncated]");
+        }
+        if content != self.content {
+            self.content = content;
+            repairs.push("message cleaned or truncated");
+        }
+        if let Some(ref mut nickname) = self.nickname {
+            let cleaned = sanitize_nickname(nickname).ok_or("unusable nickname")?;
+            if cleaned != *nickname {
+                *nickname = cleaned;
+                repairs.push("nickname cleaned");
+            }
+        }
+        if let Some(ref mut invite) = self.group_invite {
+            let cleaned = sanitize_group_name(&invite.group_name).ok_or("unusable group name")?;
+            if cleaned != invite.group_name {
+                invite.group_name = cleaned;
+                repairs.push("group name cleaned");
+            }
+            if invite.join_token.as_ref().is_some_and(|t| t.len() != JOIN_TOKEN_LEN) {
+                return Err("malformed join token");
+            }
+            if !sanitize_member_hints(&mut invite.members) {
+                repairs.push("unusable member names dropped");
+            }
+        }
+        if let Some(ref mut notice) = self.notice {
+            let mut cleaned = false;
+            for value in notice.params.values_mut() {
+                let clean: String = sanitize_text(value, false).chars().take(MAX_NICKNAME_CHARS).collect();
+                if clean != *value {
+                    *value = clean;
+                    cleaned = true;
+                }
+            }
+            if cleaned {
+                repairs.push("notice cleaned");
+            }
+        }
+        if let Some(ref mut roster) = self.group_roster {
+            if roster.digest.len() != 32 || self.group_id.is_none() {
+                return Err("malformed group roster");
+            }
+            if !sanitize_member_hints(&mut roster.members) {
+                repairs.push("unusable roster entries dropped");
+            }
+        }
+        if let Some(ref call) = self.call_salt {
+            let id_ok = (1..=64).contains(&call.call_id.len()) && call.call_id.bytes().all(|b| b.is_ascii_alphanumeric());
+            if !id_ok || call.salt.len() != CALL_SALT_LEN {
+                return Err("malformed call salt");
+            }
+        }
+        if let Some(ref mut presence) = self.presence {
+            let cleaned = match Presence::parse(presence).ok_or("malformed presence")? {
+                Presence::Away(Some(message)) => {
+                    let message: String = sanitize_text(&message, false).chars().take(MAX_AWAY_MESSAGE_CHARS).collect();
+                    let message = message.trim().to_string();
+                    Presence::Away((!message.is_empty()).then_some(message))
+                }
+                other => other,
+            }.to_wire();
+            if cleaned != *presence {
+                *presence = cleaned;
+                repairs.push("away message cleaned");
+            }
+        }
+        if let Some(ref mut offer) = self.file_offer {
+            let mime_ok = |m: &String| m.len() <= 127 && m.bytes().all(|b| b.is_ascii_graphic());
+            if offer.mime_type.as_ref().is_some_and(|m| !mime_ok(m)) {
+                offer.mime_type = None;
+                repairs.push("bogus content type dropped");
+            }
+            let cleaned = sanitize_text(&offer.filename, false);
+            if cleaned != offer.filename {
+                offer.filename = cleaned;
+                repairs.push("file name cleaned");
+            }
+        }
+        Ok(repairs)
+    }
+
+    /// Verification request or confirmation for a DM peer
+    pub fn verification(sender: String, verification: Verification) -> Self {
+        Self { system: true, direct: true, verification: Some(verification), ..Self::base(sender) }
+    }
+
+    /// Announce a conversation's disappearing-message setting (0 = off)
+    pub fn expire_policy(sender: String, ttl_secs: u64, direct: bool) -> Self {
+        Self { system: true, direct, expire_policy: Some(ttl_secs), ..Self::base(sender) }
+    }
+
+    /// Unix time this message disappears, if it has a TTL
+    pub fn expires_at(&self) -> Option<i64> {
+        let ttl = self.expire_after?;
+        Some(self.timestamp.saturating_add(i64::try_from(ttl).unwrap_or(i64::MAX)))
+    }
+
+    /// Chat for everyone on the relay (the Global tab): not a DM, not a group's, and
+    /// not housekeeping
+    pub fn is_global_chat(&self) -> bool {
+        !self.system && !self.direct && self.group_id.is_none()
+    }
+
+    /// A peer's "… has left" as they quit (in words only, from clients before notices)
+    pub fn is_goodbye(&self) -> bool {
+        let said = match self.notice {
+            Some(_) => self.is_notice(Key::Left),
+            None => self.content.ends_with(" has left"),
+        };
+        said && self.system && !self.direct && self.group_id.is_none()
+    }
+
+    /// Whether the message's TTL has run out at unix time `now`
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at().is_some_and(|at| at <= now)
+    }
+
+    /// Split a long chat message into parts of at most MAX_PART_BYTES each, cut on
+    /// character boundaries. Short messages come back as they are.
+    pub fn into_parts(self) -> Vec<PlainMessage> {
+        if self.content.len() <= MAX_PART_BYTES {
+            return vec![self];
+        }
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for (i, c) in self.content.char_indices() {
+            if i + c.len_utf8() - start > MAX_PART_BYTES {
+                chunks.push(&self.content[start..i]);
+                start = i;
+            }
+        }
+        chunks.push(&self.content[start..]);
+
+        let part_group_id = Self::generate_id();
+        let total = chunks.len() as u32;
+        chunks.iter().enumerate().map(|(index, chunk)| PlainMessage {
+            content: chunk.to_string(),
+            part_index: Some(index as u32),
+            part_total: Some(total),
+            part_group_id: Some(part_group_id.clone()),
+            ..self.clone()
+        }).collect()
+    }
+
+    /// Whether a recipient confirms this with `Message::Delivered`: a DM or group
+    /// message with an id (broadcasts aren't confirmed)
+    pub fn awaits_delivery(&self) -> bool {
+        self.message_id.is_some() && !self.system && (self.direct || self.group_id.is_some())
+    }
+
     /// Generate a unique message ID
     pub fn generate_id() -> String {
         use rand::Rng;
@@ -236,20 +917,311 @@ impl PlainMessage {
     }
 }
 
-/// Session state
-#[derive(Debug, Clone)]
-pub struct Session {
-    pub session_id: String,
-    pub peer_id: Option<String>,
-    pub shared_secret: Option<Vec<u8>>,
+/// Make untrusted text safe to draw on a terminal. Control characters become visible
+/// symbols (␛, ␍, …) instead of reaching the terminal, bidi overrides and zero-width
+/// characters are dropped so text can't be visually reordered or disguised, and runs of
+/// combining marks are cut short. Newlines and tabs survive only if `multiline`.
+pub fn sanitize_text(text: &str, multiline: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut combining = 0;
+    for c in text.chars() {
+        if is_combining_mark(c) {
+            combining += 1;
+            if combining > MAX_COMBINING_RUN {
+                continue;
+            }
+        } else {
+            combining = 0;
+        }
+        match c {
+            '\n' | '\t' if multiline => out.push(c),
+            // C0 controls map onto the Control Pictures block: \x1b -> ␛, \r -> ␍
+            '\0'..='\x1f' => out.push(char::from_u32(0x2400 + c as u32).unwrap_or('�')),
+            '\x7f' => out.push('␡'),
+            '\u{80}'..='\u{9f}' => out.push('�'),
+            c if is_invisible_format(c) => {}
+            c => out.push(c),
+        }
+    }
+    out
 }
 
-impl Session {
-    pub fn new(session_id: String) -> Self {
-        Self {
-            session_id,
-            peer_id: None,
-            shared_secret: None,
+/// Clean a nickname for display: printable characters only, whitespace collapsed to
+/// single spaces, at most MAX_NICKNAME_CHARS. `#` is dropped too — it's reserved for the
+/// `alex#1a2b` form that tells apart peers sharing a name. None if nothing is left.
+pub fn sanitize_nickname(name: &str) -> Option<String> {
+    sanitize_group_name(&name.replace('#', ""))
+}
+
+/// Clean a group name for display, by the nickname rules except that `#` is allowed
+pub fn sanitize_group_name(name: &str) -> Option<String> {
+    let spaced: String = name.chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|c| !c.is_control())
+        .collect();
+    let cleaned = sanitize_text(&spaced, false);
+    let nickname: String = cleaned.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_NICKNAME_CHARS)
+        .collect();
+    let nickname = nickname.trim_end().to_string();
+    (!nickname.is_empty()).then_some(nickname)
+}
+
+/// Keep the first MAX_INVITE_HINTS member hints, dropping any with a bad session id,
+/// key or nickname. False if anything was dropped.
+fn sanitize_member_hints(hints: &mut Vec<MemberHint>) -> bool {
+    let count = hints.len();
+    hints.truncate(MAX_INVITE_HINTS);
+    hints.retain_mut(|hint| {
+        let id_ok = (1..=64).contains(&hint.session_id.len()) && hint.session_id.bytes().all(|b| b.is_ascii_alphanumeric());
+        match sanitize_nickname(&hint.nickname) {
+            Some(cleaned) if id_ok && hint.public_key.len() == 32 => {
+                hint.nickname = cleaned;
+                true
+            }
+            _ => false,
+        }
+    });
+    hints.len() == count
+}
+
+/// Bidi overrides/isolates and zero-width characters: invisible, but they change how
+/// the surrounding text renders
+fn is_invisible_format(c: char) -> bool {
+    matches!(c,
+        '\u{061c}' | '\u{180e}' | '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}'
+        | '\u{2060}'..='\u{2069}' | '\u{feff}')
+}
+
+/// Combining diacritical mark blocks (enough to catch stacked "zalgo" text)
+fn is_combining_mark(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036f}' | '\u{0483}'..='\u{0489}' | '\u{1ab0}'..='\u{1aff}'
+        | '\u{1dc0}'..='\u{1dff}' | '\u{20d0}'..='\u{20ff}' | '\u{fe20}'..='\u{fe2f}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(size: u64, total_chunks: u32) -> FileOffer {
+        FileOffer {
+            file_id: "f1".to_string(),
+            filename: "notes.txt".to_string(),
+            size,
+            checksum: String::new(),
+            total_chunks,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_decode_bincode_rejects_huge_length_prefix() {
+        // Connect whose session_id claims to be u64::MAX bytes long
+        let mut data = 0u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decode_bincode::<Message>(&data).is_err());
+
+        let valid = bincode::serialize(&Message::Connect { session_id: "abc".to_string(), challenge: vec![] }).unwrap();
+        assert!(matches!(decode_bincode(&valid), Ok(Message::Connect { .. })));
+    }
+
+    #[test]
+    fn test_plain_message_decode_formats() {
+        let msg = PlainMessage::new("alice".to_string(), "hi".to_string());
+        let from_rmp = PlainMessage::decode(&rmp_serde::to_vec(&msg).unwrap()).unwrap();
+        let from_bincode = PlainMessage::decode(&bincode::serialize(&msg).unwrap()).unwrap();
+        assert_eq!(from_rmp.content, "hi");
+        assert_eq!(from_bincode.content, "hi");
+        assert!(PlainMessage::decode(b"\xff\x00junk").is_none());
+    }
+
+    #[test]
+    fn test_sanitize_truncates_content_and_nickname() {
+        let mut msg = PlainMessage::new("alice".to_string(), "x".repeat(MAX_CONTENT_CHARS + 10));
+        msg.nickname = Some("n".repeat(100));
+        assert_eq!(msg.sanitize().unwrap().len(), 2);
+        assert!(msg.content.ends_with("[truncated]"));
+        assert!(msg.content.starts_with(&"x".repeat(MAX_CONTENT_CHARS)));
+        assert_eq!(msg.nickname.unwrap().chars().count(), MAX_NICKNAME_CHARS);
+    }
+
+    #[test]
+    fn test_sanitize_text_escapes_control_characters() {
+        // ANSI colour codes and a carriage return that would overwrite the line prefix
+        assert_eq!(sanitize_text("red\x1b[31m\nline two\r<bob> fake", true), "red␛[31m\nline two␍<bob> fake");
+        assert_eq!(sanitize_text("a\nb\tc", false), "a␊b␉c");
+        // 8-bit CSI and DEL
+        assert_eq!(sanitize_text("x\u{9b}2Jy\x7f", true), "x�2Jy␡");
+        // Legitimate text is untouched
+        assert_eq!(sanitize_text("tab\tand\nnewline é 日本語 👋", true), "tab\tand\nnewline é 日本語 👋");
+    }
+
+    #[test]
+    fn test_sanitize_text_drops_bidi_and_zero_width() {
+        // Right-to-left override makes "txt.exe" display as "exe.txt"
+        assert_eq!(sanitize_text("file\u{202e}txt.exe", false), "filetxt.exe");
+        assert_eq!(sanitize_text("\u{2066}a\u{2069}d\u{200b}m\u{200d}i\u{feff}n", false), "admin");
+    }
+
+    #[test]
+    fn test_sanitize_text_collapses_combining_runs() {
+        let zalgo = format!("z{}a", "\u{0301}".repeat(200));
+        assert_eq!(sanitize_text(&zalgo, true), format!("z{}a", "\u{0301}".repeat(MAX_COMBINING_RUN)));
+        // A couple of stacked accents (Vietnamese) are fine
+        assert_eq!(sanitize_text("ệ", true), "ệ");
+    }
+
+    #[test]
+    fn test_sanitize_nickname() {
+        assert_eq!(sanitize_nickname("  bob  ").as_deref(), Some("bob"));
+        assert_eq!(sanitize_nickname("evil\nnick\x1b[2J").as_deref(), Some("evil nick[2J"));
+        assert_eq!(sanitize_nickname("a\u{202e}dmin\u{200b}").as_deref(), Some("admin"));
+        assert_eq!(sanitize_nickname("\u{200b}\u{202e} \t"), None);
+        assert_eq!(sanitize_nickname(&"n".repeat(100)).unwrap().len(), MAX_NICKNAME_CHARS);
+        assert_eq!(sanitize_nickname("alex#1a2b").as_deref(), Some("alex1a2b"));
+        assert_eq!(sanitize_nickname("#"), None);
+        assert_eq!(sanitize_group_name("#general").as_deref(), Some("#general"));
+    }
+
+    #[test]
+    fn test_sanitize_rejects_unusable_nickname() {
+        let mut msg = PlainMessage::nickname("alice".to_string(), "\u{202e}\u{200b}".to_string());
+        assert!(msg.sanitize().is_err());
+        let mut msg = PlainMessage::nickname("alice".to_string(), "ally\r".to_string());
+        assert_eq!(msg.sanitize().unwrap(), vec!["nickname cleaned"]);
+        assert_eq!(msg.nickname.as_deref(), Some("ally"));
+    }
+
+    #[test]
+    fn test_sanitize_drops_unusable_member_hints() {
+        let hint = |id: &str, nick: &str, key_len| MemberHint {
+            session_id: id.to_string(),
+            nickname: nick.to_string(),
+            public_key: vec![1; key_len],
+        };
+        let invite = GroupInvite {
+            group_id: "g1".to_string(),
+            group_name: "team".to_string(),
+            join_token: None,
+            members: vec![hint("bob1", "bob\r", 32), hint("carol1", "carol", 5), hint("../x", "dan", 32), hint("erin1", "\u{200b}", 32)],
+        };
+        let mut msg = PlainMessage::group_invite_msg("alice".to_string(), invite);
+        assert_eq!(msg.sanitize().unwrap(), vec!["unusable member names dropped"]);
+        assert_eq!(msg.group_invite.unwrap().members, vec![hint("bob1", "bob", 32)]);
+    }
+
+    #[test]
+    fn test_sanitize_rejects_bogus_file_offers() {
+        let chunks = |size: u64| size.div_ceil(FILE_CHUNK_SIZE as u64) as u32;
+        let ok = |size, total| PlainMessage::file_offer("alice".to_string(), offer(size, total), true).sanitize();
+
+        assert!(ok(100_000, chunks(100_000)).is_ok());
+        assert!(ok(0, 0).is_ok());
+        assert!(ok(MAX_FILE_SIZE + 1, chunks(MAX_FILE_SIZE + 1)).is_err());
+        assert!(ok(100_000, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_rejects_oversized_chunk() {
+        let chunk = |len| FileChunk { file_id: "f1".to_string(), index: 0, data: vec![0; len] };
+        let mut fits = PlainMessage::file_chunk("alice".to_string(), chunk(FILE_CHUNK_SIZE), true);
+        let mut too_big = PlainMessage::file_chunk("alice".to_string(), chunk(FILE_CHUNK_SIZE + 1), true);
+        assert!(fits.sanitize().is_ok());
+        assert!(too_big.sanitize().is_err());
+    }
+
+    #[test]
+    fn test_into_parts_splits_on_char_boundaries() {
+        let short = PlainMessage::direct("alice".to_string(), "hi".to_string());
+        assert_eq!(short.into_parts().len(), 1);
+
+        // Three-byte characters don't divide MAX_PART_BYTES evenly
+        let content = "€".repeat(MAX_PART_BYTES);
+        let parts = PlainMessage::direct("alice".to_string(), content.clone()).into_parts();
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|p| p.content.len() <= MAX_PART_BYTES && p.direct));
+        assert!(parts.iter().all(|p| p.part_total == Some(4) && p.part_group_id == parts[0].part_group_id));
+        assert_eq!(parts.iter().map(|p| p.content.as_str()).collect::<String>(), content);
+
+        for mut part in parts {
+            assert!(part.sanitize().is_ok());
         }
     }
+
+    #[test]
+    fn test_sanitize_rejects_malformed_parts() {
+        let part = |index, total| PlainMessage {
+            part_index: Some(index),
+            part_total: Some(total),
+            part_group_id: Some("p1".to_string()),
+            ..PlainMessage::direct("alice".to_string(), "x".to_string())
+        };
+        assert!(part(1, 2).sanitize().is_ok());
+        assert!(part(2, 2).sanitize().is_err());
+        assert!(part(0, 1).sanitize().is_err());
+        assert!(part(0, MAX_MESSAGE_PARTS + 1).sanitize().is_err());
+        assert!(PlainMessage { part_group_id: None, ..part(0, 2) }.sanitize().is_err());
+    }
+
+    #[test]
+    fn test_sanitize_checks_history_batches() {
+        let batch = |messages: Vec<PlainMessage>| {
+            let sync = HistorySync::Batch { group_id: "g1".to_string(), messages, more: false };
+            PlainMessage::history("alice".to_string(), sync)
+        };
+        let in_group = |content: &str| PlainMessage::group("bob".to_string(), content.to_string(), "g1".to_string());
+
+        let mut ok = batch(vec![in_group("hi\x1b[2J")]);
+        assert_eq!(ok.sanitize(), Ok(vec!["history message cleaned or truncated"]));
+        let Some(HistorySync::Batch { ref messages, .. }) = ok.history else { unreachable!() };
+        assert_eq!(messages[0].content, "hi␛[2J");
+
+        let other_group = PlainMessage::group("bob".to_string(), "x".to_string(), "g2".to_string());
+        assert!(batch(vec![other_group]).sanitize().is_err());
+        assert!(batch(vec![in_group("x"); MAX_HISTORY_BATCH + 1]).sanitize().is_err());
+        let nested = PlainMessage { history: batch(vec![]).history, ..in_group("x") };
+        assert!(batch(vec![nested]).sanitize().is_err());
+    }
+
+    #[test]
+    fn test_presence_wire_forms() {
+        for presence in [Presence::Active, Presence::Away(None), Presence::Away(Some("lunch: back at 2".to_string()))] {
+            assert_eq!(Presence::parse(&presence.to_wire()), Some(presence));
+        }
+        assert_eq!(Presence::parse("away:"), Some(Presence::Away(None)));
+        assert_eq!(Presence::parse("busy"), None);
+
+        let mut msg = PlainMessage::presence("alice".to_string(), &Presence::Away(Some(format!("\x1b[2J{}", "z".repeat(500)))));
+        assert_eq!(msg.sanitize().unwrap(), vec!["away message cleaned"]);
+        let Some(Presence::Away(Some(message))) = Presence::parse(msg.presence.as_deref().unwrap()) else { unreachable!() };
+        assert!(message.starts_with("␛[2J") && message.chars().count() == MAX_AWAY_MESSAGE_CHARS);
+        assert!(PlainMessage { presence: Some("asleep".to_string()), ..msg }.sanitize().is_err());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut msg = PlainMessage::direct("alice".to_string(), "secret".to_string());
+        assert!(!msg.is_expired(i64::MAX));
+
+        msg.expire_after = Some(60);
+        assert_eq!(msg.expires_at(), Some(msg.timestamp + 60));
+        assert!(!msg.is_expired(msg.timestamp + 59));
+        assert!(msg.is_expired(msg.timestamp + 60));
+
+        msg.expire_after = Some(u64::MAX);
+        assert!(!msg.is_expired(i64::MAX - 1));
+    }
+
+    #[test]
+    fn test_short_id() {
+        assert_eq!(short_id(&"a".repeat(32)), "a".repeat(12));
+        assert_eq!(short_id("abc"), "abc");
+        assert_eq!(short_id("ééééééééééééé"), "éééééééééééé");
+    }
 }
@@ -0,0 +1,275 @@
+//! Operator console (`wsp relay --admin-addr`): line commands from the relay's own
+//! machine, one reply line each. It sees only what routing already knows — session
+//! ids, room ids and member counts — and it has no password, so it only listens on a
+//! loopback address or a unix socket.
+//!
+//! ```text
+//! sessions               how many are connected, and their ids (shortened)
+//! rooms                  room ids (shortened) and member counts
+//! kick <session prefix>  close that session's connection
+//! limits                 current limits and per-connection buffers
+//! set <param> <value>    change max_sessions or max_room_members while running
+//! ```
+
+use anyhow::{bail, Context, Result};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::protocol::short_id;
+
+use super::{Limits, PeerMap, RoomMap, FORWARD_TIMEOUT, MAX_FRAME_SIZE, MAX_INVALID_FRAMES, PEER_QUEUE};
+
+const HELP: &str = "commands: sessions | rooms | kick <session prefix> | limits | set <max_sessions|max_room_members> <n> | quit";
+
+/// A console connection, whichever kind of socket it came in on
+trait Console: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Console for T {}
+
+/// Where the console listens
+pub(super) enum AdminListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, SocketFile),
+}
+
+/// A unix socket's path, removed when the relay stops
+#[cfg(unix)]
+pub(super) struct SocketFile(std::path::PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl AdminListener {
+    /// Listen on `addr`: a loopback `host:port`, or else a unix socket path
+    pub(super) async fn bind(addr: &str) -> Result<Self> {
+        let Ok(socket_addr) = addr.parse::<SocketAddr>() else {
+            return Self::bind_unix(addr);
+        };
+        if !socket_addr.ip().is_loopback() {
+            bail!(
+                "--admin-addr {} isn't a loopback address: the console has no password, so use 127.0.0.1, [::1] or a unix socket path",
+                addr
+            );
+        }
+        let listener = TcpListener::bind(socket_addr).await
+            .with_context(|| format!("Failed to bind the admin console on {}", addr))?;
+        Ok(Self::Tcp(listener))
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(path: &str) -> Result<Self> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        let path = std::path::PathBuf::from(path);
+        // A socket left behind by a relay that didn't stop cleanly is taken over;
+        // one another relay still answers on is not
+        if std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                bail!("Admin socket {} is in use by another relay", path.display());
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = tokio::net::UnixListener::bind(&path)
+            .with_context(|| format!("Failed to bind the admin console on {}", path.display()))?;
+        let file = SocketFile(path);
+        std::fs::set_permissions(&file.0, std::fs::Permissions::from_mode(0o600))?;
+        Ok(Self::Unix(listener, file))
+    }
+
+    #[cfg(not(unix))]
+    fn bind_unix(path: &str) -> Result<Self> {
+        bail!("--admin-addr {}: not a host:port, and unix sockets aren't available here", path)
+    }
+
+    /// The TCP address listened on, any port 0 resolved (None for a unix socket)
+    pub(super) fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok(),
+            #[cfg(unix)]
+            Self::Unix(..) => None,
+        }
+    }
+
+    /// Where to point `nc` at, for the startup banner
+    pub(super) fn describe(&self) -> String {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().map(|a| a.to_string()).unwrap_or_default(),
+            #[cfg(unix)]
+            Self::Unix(_, file) => file.0.display().to_string(),
+        }
+    }
+
+    async fn accept(&self) -> std::io::Result<Box<dyn Console>> {
+        match self {
+            Self::Tcp(listener) => Ok(Box::new(listener.accept().await?.0)),
+            #[cfg(unix)]
+            Self::Unix(listener, _) => Ok(Box::new(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// The relay state the console reads and changes, shared with the connections
+#[derive(Clone)]
+pub(super) struct Admin {
+    pub peers: PeerMap,
+    pub rooms: RoomMap,
+    pub limits: Arc<Limits>,
+}
+
+impl Admin {
+    /// Take console connections on `listener` until `stop` fires
+    pub(super) async fn serve(self, listener: AdminListener, stop: CancellationToken) {
+        let mut consoles = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(console) => {
+                        let admin = self.clone();
+                        consoles.spawn(async move { admin.converse(console).await });
+                    }
+                    Err(e) => eprintln!("❌ Admin console: {}", e),
+                },
+                Some(_) = consoles.join_next() => {}
+                _ = stop.cancelled() => break,
+            }
+        }
+    }
+
+    /// Answer one console's commands until it hangs up or says quit
+    async fn converse(&self, console: Box<dyn Console>) -> std::io::Result<()> {
+        let (read, mut write) = tokio::io::split(console);
+        let mut lines = BufReader::new(read).lines();
+        while let Some(line) = lines.next_line().await? {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "quit" {
+                break;
+            }
+            let reply = self.command(line).await;
+            write.write_all(format!("{}\n", reply).as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Run one command line and return its reply
+    pub(super) async fn command(&self, line: &str) -> String {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts[..] {
+            ["sessions"] => self.sessions().await,
+            ["rooms"] => self.rooms().await,
+            ["kick", prefix] => self.kick(prefix).await,
+            ["kick", ..] => "error: usage: kick <session prefix>".to_string(),
+            ["limits"] => self.limits(),
+            ["set", param, value] => self.set(param, value),
+            ["set", ..] => "error: usage: set <max_sessions|max_room_members> <n>".to_string(),
+            ["help"] => HELP.to_string(),
+            _ => format!("error: unknown command {:?} — {}", line, HELP),
+        }
+    }
+
+    async fn sessions(&self) -> String {
+        let mut ids: Vec<String> = self.peers.read().await.keys().map(|id| short_id(id).to_string()).collect();
+        ids.sort();
+        match ids.len() {
+            0 => "0 sessions".to_string(),
+            n => format!("{} session{}: {}", n, if n == 1 { "" } else { "s" }, ids.join(" ")),
+        }
+    }
+
+    async fn rooms(&self) -> String {
+        let mut rooms: Vec<(String, usize)> = self.rooms.read().await.iter()
+            .map(|(id, room)| (short_id(id).to_string(), room.members.len()))
+            .collect();
+        rooms.sort();
+        let listed: Vec<String> = rooms.iter().map(|(id, members)| format!("{} ({})", id, members)).collect();
+        match rooms.len() {
+            0 => "0 rooms".to_string(),
+            n => format!("{} room{}: {}", n, if n == 1 { "" } else { "s" }, listed.join(" ")),
+        }
+    }
+
+    async fn kick(&self, prefix: &str) -> String {
+        let peers = self.peers.read().await;
+        let matching: Vec<(&String, _)> = peers.iter().filter(|(id, _)| id.starts_with(prefix)).collect();
+        match matching[..] {
+            [] => format!("error: no session starts with {}", prefix),
+            [(id, peer)] => {
+                peer.kick.cancel();
+                format!("kicked {}", short_id(id))
+            }
+            _ => format!("error: {} sessions start with {} — give more of the id", matching.len(), prefix),
+        }
+    }
+
+    fn limits(&self) -> String {
+        format!(
+            "max_sessions={} max_room_members={} peer_queue={} forward_timeout_secs={} max_frame_bytes={} max_invalid_frames={}",
+            self.limits.sessions(),
+            self.limits.room_members(),
+            PEER_QUEUE,
+            FORWARD_TIMEOUT.as_secs(),
+            MAX_FRAME_SIZE,
+            MAX_INVALID_FRAMES
+        )
+    }
+
+    fn set(&self, param: &str, value: &str) -> String {
+        let limit = match param {
+            "max_sessions" => &self.limits.sessions,
+            "max_room_members" => &self.limits.room_members,
+            _ => return format!("error: {} can't be changed while running (only max_sessions and max_room_members)", param),
+        };
+        match value.parse::<usize>() {
+            Ok(n) if n > 0 => {
+                limit.store(n, Ordering::Relaxed);
+                format!("{}={}", param, n)
+            }
+            _ => format!("error: {} needs a whole number above 0", param),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_console_only_listens_locally_and_checks_its_input() {
+        let err = AdminListener::bind("0.0.0.0:0").await.err().unwrap();
+        assert!(err.to_string().contains("isn't a loopback address"), "{}", err);
+
+        let admin = Admin { peers: PeerMap::default(), rooms: RoomMap::default(), limits: Arc::default() };
+        assert_eq!(admin.command("sessions").await, "0 sessions");
+        assert_eq!(admin.command("kick").await, "error: usage: kick <session prefix>");
+        assert_eq!(admin.command("kick ab").await, "error: no session starts with ab");
+        assert_eq!(admin.command("set max_sessions 0").await, "error: max_sessions needs a whole number above 0");
+        assert!(admin.command("set peer_queue 9").await.starts_with("error: peer_queue can't be changed"));
+        assert_eq!(admin.command("set max_room_members 8").await, "max_room_members=8");
+        assert!(admin.command("limits").await.starts_with("max_sessions=10000 max_room_members=8 peer_queue=256"));
+        assert!(admin.command("reboot").await.starts_with("error: unknown command \"reboot\""));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_is_private_and_removed_on_stop() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("admin.sock");
+        let listener = AdminListener::bind(path.to_str().unwrap()).await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
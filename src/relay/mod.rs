@@ -1,51 +1,288 @@
-use anyhow::Result;
+//! Relay server: routes opaque frames between sessions and group rooms.
+
+use anyhow::{Context, Result};
 use futures_util::{SinkExt, StreamExt};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::RwLock;
-use tokio_tungstenite::{accept_async, tungstenite::Message as WsMessage};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_tungstenite::accept_hdr_async_with_config;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::{protocol::WebSocketConfig, Message as WsMessage};
+use tokio_util::sync::CancellationToken;
+
+use crate::crypto::relay_key::RelayKey;
+use crate::protocol::codec::{self, WireFormat};
+use crate::protocol::{short_id, ErrorCode, Message, JOIN_TOKEN_LEN, MAX_MESSAGE_SIZE};
+
+mod admin;
+mod reap;
+mod stats;
+
+use admin::{Admin, AdminListener};
+use reap::{Liveness, REAP_INTERVAL};
+use stats::{FrameKind, RelayStats};
+
+/// Largest single websocket frame (clients send each message as one frame)
+const MAX_FRAME_SIZE: usize = MAX_MESSAGE_SIZE;
+/// Accepted length range for session and group ids (clients generate 32 hex chars)
+const MIN_ID_LEN: usize = 12;
+const MAX_ID_LEN: usize = 128;
+/// Invalid frames tolerated from one connection before it's dropped
+const MAX_INVALID_FRAMES: u32 = 8;
+/// Default cap on sessions in one group room (`--max-room-members`)
+pub const DEFAULT_MAX_ROOM_MEMBERS: usize = 256;
+/// Default cap on sessions connected at once (`--max-sessions`)
+pub const DEFAULT_MAX_SESSIONS: usize = 10_000;
+/// How long a closing connection gets to deliver what's queued for it (a parting error)
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+/// How long a stopping relay waits for its connections to close before cutting them off
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+/// Max frames queued for one connected peer before forwarding applies backpressure
+const PEER_QUEUE: usize = 256;
+/// How long a forward waits for room in a peer's queue before the peer is cut off for
+/// not reading
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+type PeerTx = tokio::sync::mpsc::Sender<Vec<u8>>;
+type PeerMap = Arc<RwLock<HashMap<String, Peer>>>;
+type RoomMap = Arc<RwLock<HashMap<String, Room>>>; // group_id -> room
+
+/// A connected session: where its frames go, a way to cut its connection, and whether
+/// it still answers pings and keeps up
+#[derive(Debug, Clone)]
+struct Peer {
+    tx: PeerTx,
+    kick: CancellationToken,
+    liveness: Arc<Liveness>,
+}
 
-use crate::protocol::Message;
+/// One group room. The relay learns nothing about the group beyond its id, who is
+/// in it, and a hash of its join token.
+#[derive(Debug, Default)]
+struct Room {
+    members: HashSet<String>,
+    /// blake3 of the join token presented by the room's first member, if any
+    token_hash: Option<blake3::Hash>,
+}
 
-type PeerMap = Arc<RwLock<HashMap<String, tokio::sync::mpsc::UnboundedSender<Vec<u8>>>>>;
-type RoomMap = Arc<RwLock<HashMap<String, HashSet<String>>>>; // group_id -> set of session_ids
+/// Why a GroupJoin was refused
+#[derive(Debug, PartialEq)]
+enum JoinError {
+    WrongToken,
+    Full,
+}
+
+/// How much one relay takes on; the admin console can change these while it runs
+#[derive(Debug)]
+struct Limits {
+    room_members: AtomicUsize,
+    sessions: AtomicUsize,
+}
+
+impl Limits {
+    fn new(room_members: usize, sessions: usize) -> Self {
+        Self { room_members: AtomicUsize::new(room_members), sessions: AtomicUsize::new(sessions) }
+    }
+
+    fn room_members(&self) -> usize {
+        self.room_members.load(Ordering::Relaxed)
+    }
+
+    fn sessions(&self) -> usize {
+        self.sessions.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ROOM_MEMBERS, DEFAULT_MAX_SESSIONS)
+    }
+}
 
 /// Zero-knowledge relay server
-/// - Stores nothing to disk
+/// - Stores nothing to disk (`start_relay` keeps the relay's key there)
 /// - No logging of message content
 /// - Only forwards encrypted blobs
 /// - Session IDs are ephemeral and in-memory only
 /// - Group rooms are tracked by ID only — relay never sees names or content
 pub struct RelayServer {
-    addr: String,
+    addrs: Vec<String>,
     peers: PeerMap,
     rooms: RoomMap,
+    limits: Arc<Limits>,
+    stats: Arc<RelayStats>,
+    /// Print a one-line heartbeat this often (`--status-interval`)
+    status_interval: Option<Duration>,
+    /// Where the operator console listens (`--admin-addr`)
+    admin_addr: Option<String>,
+    /// Ping sessions and sweep rooms this often
+    reap_interval: Duration,
+    /// What clients know this relay by
+    key: Arc<RelayKey>,
 }
 
 impl RelayServer {
+    /// Relay that will listen on `addr` (e.g. `0.0.0.0:8080`, or port 0 for any free
+    /// port) when `run()` or `bind()` is called
     pub fn new(addr: String) -> Self {
+        Self::with_addrs(vec![addr])
+    }
+
+    /// Relay that will listen on every one of `addrs` (e.g. `0.0.0.0:8899` and
+    /// `[::]:8899`) when `run()` is called
+    pub fn with_addrs(addrs: Vec<String>) -> Self {
         Self {
-            addr,
+            addrs,
             peers: Arc::new(RwLock::new(HashMap::new())),
             rooms: Arc::new(RwLock::new(HashMap::new())),
+            limits: Arc::default(),
+            stats: Arc::default(),
+            status_interval: None,
+            reap_interval: REAP_INTERVAL,
+            admin_addr: None,
+            key: Arc::new(RelayKey::generate()),
         }
     }
 
-    pub async fn run(&self) -> Result<()> {
-        let listener = TcpListener::bind(&self.addr).await?;
+    /// Prove to clients that we hold `key`, so they can tell us from a relay put in our
+    /// place (a throwaway key unless set, which they'll see change every restart)
+    pub fn set_key(&mut self, key: RelayKey) {
+        self.key = Arc::new(key);
+    }
+
+    /// The fingerprint of our key, for operators to publish
+    pub fn fingerprint(&self) -> String {
+        self.key.fingerprint()
+    }
+
+    /// Refuse joins once a room holds `max` sessions
+    pub fn set_max_room_members(&mut self, max: usize) {
+        self.limits.room_members.store(max, Ordering::Relaxed);
+    }
+
+    /// Turn away new sessions once `max` are connected
+    pub fn set_max_sessions(&mut self, max: usize) {
+        self.limits.sessions.store(max, Ordering::Relaxed);
+    }
+
+    /// Take operator commands on `addr`: a loopback `host:port`, or a unix socket path
+    pub fn set_admin_addr(&mut self, addr: String) {
+        self.admin_addr = Some(addr);
+    }
+
+    /// Ping sessions, drop the ones that stopped answering and sweep rooms every
+    /// `interval` (REAP_INTERVAL unless set)
+    pub fn set_reap_interval(&mut self, interval: Duration) {
+        self.reap_interval = interval;
+    }
+
+    /// Print sessions, rooms and traffic rates every `interval` while serving
+    pub fn set_status_interval(&mut self, interval: Duration) {
+        self.status_interval = Some(interval);
+    }
+
+    /// Bind every address and serve until Ctrl+C, then print a summary
+    pub async fn run(self) -> Result<()> {
+        let relay = self.bind().await?;
         println!("🔒 WSP Relay Server");
-        println!("📡 Listening on: {}", self.addr);
+        for addr in relay.local_addrs() {
+            println!("📡 Listening on: {}", addr);
+        }
+        for addr in reachable_addrs(&relay.listeners) {
+            println!("🌐 Likely reachable at: ws://{}", addr);
+        }
+        if let Some(ref admin) = relay.admin {
+            println!("🛠️  Admin console on: {} (local only; type help)", admin.describe());
+        }
+        println!("🪪 Relay fingerprint: {}", relay.server.fingerprint());
         println!("🚫 Zero-knowledge mode: No logging, no storage, RAM only");
         println!();
 
-        loop {
-            let (stream, _) = listener.accept().await?;
-            
+        let stats = relay.server.stats.clone();
+        relay.run_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+        }).await?;
+        println!();
+        println!("🛑 Shutting down");
+        println!("{}", stats.summary());
+        Ok(())
+    }
+
+    /// Bind every address without serving yet, so the ports picked for `:0` can be
+    /// read back before anyone is told where to connect
+    pub async fn bind(self) -> Result<BoundRelay> {
+        let listeners = bind_all(&self.addrs).await?;
+        let admin = match self.admin_addr {
+            Some(ref addr) => Some(AdminListener::bind(addr).await?),
+            None => None,
+        };
+        Ok(BoundRelay { server: self, listeners, admin })
+    }
+
+    /// Accept connections on an already-bound listener until `shutdown` resolves.
+    /// Open connections are closed when this returns.
+    pub async fn serve(&self, listener: TcpListener, shutdown: impl Future<Output = ()>) -> Result<()> {
+        self.serve_all(vec![listener], shutdown).await
+    }
+
+    /// Accept connections on every listener until `shutdown` resolves; sessions and
+    /// rooms are shared, whichever address a client came in on
+    pub async fn serve_all(&self, listeners: Vec<TcpListener>, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let mut connections = JoinSet::new();
+        let closing = CancellationToken::new();
+        tokio::pin!(shutdown);
+        let mut status_tick = self.status_interval
+            .map(|every| tokio::time::interval_at(tokio::time::Instant::now() + every, every));
+        let mut last_status = (Instant::now(), self.stats.totals());
+        let mut reap_tick = tokio::time::interval_at(tokio::time::Instant::now() + self.reap_interval, self.reap_interval);
+
+        let served = loop {
+            let (stream, _) = tokio::select! {
+                accepted = accept_any(&listeners) => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => break Err(e.into()),
+                },
+                _ = &mut shutdown => break Ok(()),
+                // Reap finished connection tasks so the set doesn't grow forever
+                Some(_) = connections.join_next() => continue,
+                _ = async { status_tick.as_mut().expect("checked").tick().await }, if status_tick.is_some() => {
+                    let (sessions, rooms) = (self.peers.read().await.len(), self.rooms.read().await.len());
+                    let totals = self.stats.totals();
+                    println!("{}", self.stats.status_line(sessions, rooms, &last_status.1, &totals, last_status.0.elapsed()));
+                    last_status = (Instant::now(), totals);
+                    continue;
+                }
+                _ = reap_tick.tick() => {
+                    let reaped = reap::reap(&self.stats, &self.peers, &self.rooms).await;
+                    if reaped.sessions + reaped.members > 0 {
+                        println!(
+                            "🧹 Reaped {} quiet sessions, {} stale room members and {} empty rooms",
+                            reaped.sessions, reaped.members, reaped.rooms
+                        );
+                    }
+                    continue;
+                }
+            };
+
             let peers = self.peers.clone();
             let rooms = self.rooms.clone();
-            tokio::spawn(async move {
-                match handle_connection(stream, peers, rooms).await {
+            let stats = self.stats.clone();
+            let limits = self.limits.clone();
+            let key = self.key.clone();
+            let closing = closing.clone();
+            connections.spawn(async move {
+                match handle_connection(stream, peers, rooms, stats, limits, key, closing).await {
                     Ok(_) => {}
                     Err(e) => {
                         let err_str = e.to_string();
@@ -57,181 +294,1066 @@ impl RelayServer {
                     }
                 }
             });
+        };
+
+        // Stop taking connections (freeing the ports), then close the open ones: each
+        // client gets a Close frame and the connection cleans up after itself
+        drop(listeners);
+        closing.cancel();
+        let _ = tokio::time::timeout(SHUTDOWN_GRACE, async {
+            while connections.join_next().await.is_some() {}
+        }).await;
+        served
+    }
+}
+
+/// A relay with its listeners bound, not yet serving
+pub struct BoundRelay {
+    server: RelayServer,
+    listeners: Vec<TcpListener>,
+    admin: Option<AdminListener>,
+}
+
+impl BoundRelay {
+    /// The addresses actually listened on, with any port 0 resolved
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners.iter().filter_map(|listener| listener.local_addr().ok()).collect()
+    }
+
+    /// The admin console's TCP address, if it has one (not for a unix socket)
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin.as_ref().and_then(AdminListener::local_addr)
+    }
+
+    /// Serve until `shutdown` resolves, then close every connection and unbind
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let stop_admin = CancellationToken::new();
+        if let Some(listener) = self.admin {
+            let admin = Admin {
+                peers: self.server.peers.clone(),
+                rooms: self.server.rooms.clone(),
+                limits: self.server.limits.clone(),
+            };
+            tokio::spawn(admin.serve(listener, stop_admin.clone()));
         }
+        let served = self.server.serve_all(self.listeners, shutdown).await;
+        stop_admin.cancel();
+        served
     }
+
+    /// Serve in the background until the returned handle is shut down or dropped
+    pub fn spawn(self) -> RelayHandle {
+        let addrs = self.local_addrs();
+        let admin_addr = self.admin_addr();
+        let stop = CancellationToken::new();
+        let stopped = stop.clone();
+        let task = tokio::spawn(self.run_until(async move { stopped.cancelled().await }));
+        RelayHandle { addrs, admin_addr, stop, task }
+    }
+}
+
+/// A relay serving in the background (see `BoundRelay::spawn`). Dropping it stops the
+/// relay too, without waiting.
+pub struct RelayHandle {
+    addrs: Vec<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    stop: CancellationToken,
+    task: JoinHandle<Result<()>>,
 }
 
-async fn handle_connection(stream: TcpStream, peers: PeerMap, rooms: RoomMap) -> Result<()> {
-    let ws_stream = accept_async(stream).await?;
+impl RelayHandle {
+    /// The first address listened on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addrs[0]
+    }
+
+    /// Every address listened on
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// The admin console's TCP address, if it has one
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+
+    /// Stop the relay and wait until its connections are closed and its ports free
+    pub async fn shutdown(mut self) -> Result<()> {
+        self.stop.cancel();
+        (&mut self.task).await.context("Relay task failed")?
+    }
+}
+
+impl Drop for RelayHandle {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+async fn handle_connection<S>(
+    stream: S,
+    peers: PeerMap,
+    rooms: RoomMap,
+    stats: Arc<RelayStats>,
+    limits: Arc<Limits>,
+    key: Arc<RelayKey>,
+    closing: CancellationToken,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let config = WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_FRAME_SIZE),
+        ..Default::default()
+    };
+    // Deflate this connection's frames if the client asks
+    let mut deflating = false;
+    // The error type is tungstenite's, for refusing the handshake (which this never does)
+    #[allow(clippy::result_large_err)]
+    let negotiate = |request: &Request, mut response: Response| {
+        deflating = wants_deflate(request);
+        if deflating {
+            response.headers_mut().insert(codec::COMPRESS_HEADER, HeaderValue::from_static(codec::COMPRESS_DEFLATE));
+        }
+        Ok(response)
+    };
+    let ws_stream = accept_hdr_async_with_config(stream, negotiate, Some(config)).await?;
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(PEER_QUEUE);
+    let kick = CancellationToken::new();
+    let liveness = Arc::new(Liveness::default());
     let mut session_id: Option<String> = None;
+    // Framing this client speaks; our own replies use the same
+    let mut wire = WireFormat::Envelope;
+    let mut invalid_frames = 0u32;
 
-    // Spawn task to send messages to this client
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if ws_sender.send(WsMessage::Binary(msg)).await.is_err() {
+    // Spawn task to send messages to this client, and the reaper's pings
+    let pinged = liveness.clone();
+    let mut send_task = tokio::spawn(async move {
+        loop {
+            let frame = tokio::select! {
+                msg = rx.recv() => match msg {
+                    Some(msg) => WsMessage::Binary(if deflating { codec::deflate(msg) } else { msg }),
+                    None => break,
+                },
+                _ = pinged.ping_due() => WsMessage::Ping(Vec::new()),
+            };
+            if ws_sender.send(frame).await.is_err() {
                 break;
             }
         }
+        // A proper goodbye rather than a dropped socket
+        let _ = ws_sender.close().await;
     });
 
-    // Handle incoming messages
-    while let Some(msg) = ws_receiver.next().await {
+    // Handle incoming messages until the client goes or the relay stops
+    loop {
+        let msg = tokio::select! {
+            msg = ws_receiver.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = closing.cancelled() => break,
+            _ = kick.cancelled() => {
+                // The reaper says so itself
+                if liveness.lagging() {
+                    println!("🐢 Session cut off: no room in its queue for {}s", FORWARD_TIMEOUT.as_secs());
+                } else if !liveness.gone_quiet() {
+                    println!("👢 Session kicked by the operator, or taken over");
+                }
+                break;
+            }
+        };
         match msg {
             Ok(WsMessage::Binary(data)) => {
-                // Deserialize message
-                let message: Message = match bincode::deserialize(&data) {
-                    Ok(m) => m,
-                    Err(_) => continue,
+                // Only a connection that agreed to deflate may send deflated frames;
+                // they're forwarded inflated, and deflated again for whoever wants that
+                // (anything else is left empty, to count as an invalid frame below)
+                let data = match codec::inflate(&data) {
+                    Ok(Cow::Owned(inflated)) if deflating => inflated,
+                    Ok(Cow::Borrowed(_)) => data,
+                    _ => Vec::new(),
                 };
+                // Deserialize and sanity-check before routing anything
+                let message = match codec::decode(&data) {
+                    Ok(m) if frame_is_valid(&m, session_id.as_deref()) => m,
+                    // Nothing but Connect opens a session
+                    Ok(m) if session_id.is_none() && !matches!(m, Message::Connect { .. }) => {
+                        let wire = codec::wire_format(&data).unwrap_or(wire);
+                        refuse(&tx, wire, ErrorCode::Unauthorized, "connect before sending anything else").await;
+                        break;
+                    }
+                    _ => {
+                        invalid_frames += 1;
+                        if invalid_frames >= MAX_INVALID_FRAMES {
+                            println!("🚫 Dropping connection after {} invalid frames", invalid_frames);
+                            refuse(&tx, wire, ErrorCode::MalformedFrames, "too many invalid frames").await;
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                stats.frame_in(frame_kind(&message), data.len());
 
                 match message {
-                    Message::Connect { session_id: sid } => {
+                    Message::Connect { session_id: sid, challenge } => {
                         // Register or update this peer (session resumption)
                         let mut peers_write = peers.write().await;
                         let is_resumption = peers_write.contains_key(&sid);
+                        if !is_resumption && peers_write.len() >= limits.sessions() {
+                            drop(peers_write);
+                            println!("⛔ Turned away a session: {} connected", limits.sessions());
+                            let wire = codec::wire_format(&data).unwrap_or(WireFormat::Envelope);
+                            refuse(&tx, wire, ErrorCode::SessionLimit, "the relay is full, try again later").await;
+                            break;
+                        }
                         
                         if is_resumption {
-                            println!("🔄 Session resumption: {}", &sid[..12]);
+                            println!("🔄 Session resumption: {}", short_id(&sid));
                         } else {
-                            println!("🆕 New session: {}", &sid[..12]);
+                            println!("🆕 New session: {}", short_id(&sid));
                         }
                         
                         // Insert/replace the sender channel
-                        peers_write.insert(sid.clone(), tx.clone());
+                        let replaced = peers_write.insert(sid.clone(), Peer { tx: tx.clone(), kick: kick.clone(), liveness: liveness.clone() });
+                        stats.sessions(peers_write.len());
                         drop(peers_write);
+
+                        // Another live connection held this session (the same identity started
+                        // elsewhere): it's told why, without waiting on a full queue, and closed
+                        if let Some(old) = replaced.filter(|old| !old.tx.same_channel(&tx)) {
+                            println!("🔀 Session {} taken over by a new connection", short_id(&sid));
+                            let error = Message::Error {
+                                message: "another connection took over this session".to_string(),
+                                code: ErrorCode::SessionReplaced,
+                                session: None,
+                            };
+                            if let Ok(frame) = codec::encode_as(&error, WireFormat::Envelope) {
+                                let _ = old.tx.try_send(frame);
+                            }
+                            old.kick.cancel();
+                        }
                         
+                        // Send ACK, with our key and, if asked, proof that it's ours
+                        let ack = Message::Ack {
+                            relay_key: key.public_key_bytes(),
+                            proof: key.prove(&challenge, &sid).unwrap_or_default(),
+                        };
                         session_id = Some(sid);
+                        wire = codec::wire_format(&data).unwrap_or(WireFormat::Envelope);
                         
-                        // Send ACK
-                        let ack = bincode::serialize(&Message::Ack)?;
-                        tx.send(ack)?;
+                        let ack = codec::encode_as(&ack, wire)?;
+                        tx.send(ack).await?;
                     }
-                    Message::Discover { target_session } => {
-                        // Forward discovery to target if online
-                        let peers_read = peers.read().await;
-                        if let Some(target_tx) = peers_read.get(&target_session) {
-                            target_tx.send(data)?;
+                    Message::Discover { target_session, .. } => {
+                        // Forward discovery to target if online, else say it isn't
+                        match connected_peer(&peers, &target_session).await {
+                            Some(target) => forward(&stats, &target, data, false).await,
+                            None => not_found(&tx, wire, &target_session).await,
                         }
                     }
-                    Message::KeyExchange { .. } | Message::AudioFrame { .. } => {
-                        // Forward key exchanges and audio to all peers (blind forwarding)
-                        let peers_read = peers.read().await;
-                        for (sid, peer_tx) in peers_read.iter() {
-                            if Some(sid) != session_id.as_ref() {
-                                let _ = peer_tx.send(data.clone());
-                            }
+                    Message::KeyExchange { ref target, .. } if target.is_empty() => {
+                        // Forward key exchanges to all peers (blind forwarding)
+                        for peer in peers_except(&peers, session_id.as_ref()).await {
+                            forward(&stats, &peer, data.clone(), false).await;
+                        }
+                    }
+                    Message::AudioFrame { .. } => {
+                        // Audio goes to all peers too, but is dropped for peers that can't keep up
+                        for peer in peers_except(&peers, session_id.as_ref()).await {
+                            forward(&stats, &peer, data.clone(), true).await;
                         }
                     }
-                    Message::Encrypted { ref target, .. } => {
+                    Message::Encrypted { ref target, .. }
+                    | Message::KeyExchange { ref target, .. }
+                    | Message::Typing { ref target, .. }
+                    | Message::ReadReceipt { ref target, .. }
+                    | Message::Delivered { ref target, .. } => {
                         if !target.is_empty() {
                             // Targeted: forward only to the specified peer
-                            let peers_read = peers.read().await;
-                            if let Some(peer_tx) = peers_read.get(target) {
-                                let _ = peer_tx.send(data.clone());
+                            if let Some(peer) = connected_peer(&peers, target).await {
+                                forward(&stats, &peer, data.clone(), false).await;
                             }
                         } else {
                             // Broadcast (legacy): forward to all peers
-                            let peers_read = peers.read().await;
-                            for (sid, peer_tx) in peers_read.iter() {
-                                if Some(sid) != session_id.as_ref() {
-                                    let _ = peer_tx.send(data.clone());
-                                }
+                            for peer in peers_except(&peers, session_id.as_ref()).await {
+                                forward(&stats, &peer, data.clone(), false).await;
                             }
                         }
                     }
-                    Message::Typing { ref target, .. } | Message::ReadReceipt { ref target, .. } => {
-                        // Forward lightweight signals to target peer (no encryption)
-                        if !target.is_empty() {
-                            let peers_read = peers.read().await;
-                            if let Some(peer_tx) = peers_read.get(target) {
-                                let _ = peer_tx.send(data.clone());
-                            }
-                        } else {
-                            // Broadcast to all
-                            let peers_read = peers.read().await;
-                            for (sid, peer_tx) in peers_read.iter() {
-                                if Some(sid) != session_id.as_ref() {
-                                    let _ = peer_tx.send(data.clone());
+                    Message::GroupJoin { session_id: sid, group_id, join_token } => {
+                        // Add session to the group room if it holds the right token and there's space
+                        let (joined, rejoined) = {
+                            let mut rooms_write = rooms.write().await;
+                            let rejoined = rooms_write.get(&group_id).is_some_and(|room| room.members.contains(&sid));
+                            let joined = join_room(&mut rooms_write, &sid, &group_id, join_token.as_deref(), limits.room_members());
+                            (joined, rejoined)
+                        };
+                        match joined {
+                            Ok(members) => {
+                                println!("📥 Session {}.. joined room {}.. ({} members)", 
+                                    short_id(&sid), 
+                                    short_id(&group_id),
+                                    members);
+                                // A resumed session re-joining doesn't change the count;
+                                // only the joiner needs telling
+                                if rejoined {
+                                    let presence = Message::RoomPresence { group_id, count: members as u32 };
+                                    tx.send(codec::encode_as(&presence, wire)?).await?;
+                                } else {
+                                    announce_presence(&stats, &peers, &rooms, &group_id).await;
                                 }
                             }
+                            Err(e) => {
+                                let (code, reason) = match e {
+                                    JoinError::WrongToken => (ErrorCode::JoinRefused, "wrong join token"),
+                                    JoinError::Full => (ErrorCode::RoomFull, "room is full"),
+                                };
+                                println!("⛔ Session {}.. refused from room {}.. ({})",
+                                    short_id(&sid),
+                                    short_id(&group_id),
+                                    reason);
+                                let message = format!("Can't join room {}: {}", short_id(&group_id), reason);
+                                refuse(&tx, wire, code, &message).await;
+                            }
                         }
                     }
-                    Message::GroupJoin { session_id: sid, group_id } => {
-                        // Add session to the group room
-                        let mut rooms_write = rooms.write().await;
-                        let room = rooms_write.entry(group_id.clone()).or_insert_with(HashSet::new);
-                        room.insert(sid.clone());
-                        println!("📥 Session {}.. joined room {}.. ({} members)", 
-                            &sid[..12.min(sid.len())], 
-                            &group_id[..12.min(group_id.len())],
-                            room.len());
-                    }
                     Message::GroupLeave { session_id: sid, group_id } => {
                         // Remove session from the group room
                         let mut rooms_write = rooms.write().await;
                         if let Some(room) = rooms_write.get_mut(&group_id) {
-                            room.remove(&sid);
-                            let remaining = room.len();
+                            let left = room.members.remove(&sid);
+                            let remaining = room.members.len();
                             println!("📤 Session {}.. left room {}.. ({} remaining)", 
-                                &sid[..12.min(sid.len())], 
-                                &group_id[..12.min(group_id.len())],
+                                short_id(&sid), 
+                                short_id(&group_id),
                                 remaining);
                             // Clean up empty rooms
                             if remaining == 0 {
                                 rooms_write.remove(&group_id);
                             }
+                            drop(rooms_write);
+                            if left && remaining > 0 {
+                                announce_presence(&stats, &peers, &rooms, &group_id).await;
+                            }
                         }
                     }
                     Message::GroupEncrypted { from, group_id, .. } => {
-                        // Forward to all members of the group room except sender
-                        let rooms_read = rooms.read().await;
-                        if let Some(members) = rooms_read.get(&group_id) {
+                        // Forward to all members of the group room except sender (members only —
+                        // outsiders who learn a group id can't inject frames)
+                        let members: Vec<Peer> = {
+                            let rooms_read = rooms.read().await;
                             let peers_read = peers.read().await;
-                            for member_sid in members {
-                                if member_sid != &from {
-                                    if let Some(peer_tx) = peers_read.get(member_sid) {
-                                        let _ = peer_tx.send(data.clone());
-                                    }
-                                }
-                            }
+                            rooms_read.get(&group_id)
+                                .filter(|room| room.members.contains(&from))
+                                .map(|room| room.members.iter()
+                                    .filter(|sid| **sid != from)
+                                    .filter_map(|sid| peers_read.get(sid).cloned())
+                                    .collect())
+                                .unwrap_or_default()
+                        };
+                        for peer in members {
+                            forward(&stats, &peer, data.clone(), false).await;
                         }
                     }
                     _ => {}
                 }
             }
+            Err(tokio_tungstenite::tungstenite::Error::Capacity(_)) => {
+                refuse(&tx, wire, ErrorCode::FrameTooLarge, "frame over the size limit").await;
+                break;
+            }
+            Ok(WsMessage::Pong(_)) => liveness.answered(),
             Ok(WsMessage::Close(_)) | Err(_) => break,
+            Ok(WsMessage::Text(_)) => {
+                // The protocol is binary-only
+                invalid_frames += 1;
+                if invalid_frames >= MAX_INVALID_FRAMES {
+                    refuse(&tx, wire, ErrorCode::MalformedFrames, "too many invalid frames").await;
+                    break;
+                }
+            }
             _ => {}
         }
     }
 
-    // Cleanup on disconnect
+    // Cleanup on disconnect — unless the session was resumed on another connection,
+    // in which case the entry (and its room memberships) belong to that one now
     if let Some(sid) = session_id {
-        peers.write().await.remove(&sid);
-        
-        // Remove from all rooms
-        let mut rooms_write = rooms.write().await;
-        let mut empty_rooms = Vec::new();
-        for (group_id, members) in rooms_write.iter_mut() {
-            members.remove(&sid);
-            if members.is_empty() {
-                empty_rooms.push(group_id.clone());
+        let mut peers_write = peers.write().await;
+        let still_ours = peers_write.get(&sid).is_some_and(|peer| peer.tx.same_channel(&tx));
+        if still_ours {
+            peers_write.remove(&sid);
+            drop(peers_write);
+
+            // Remove from all rooms, and tell whoever is left
+            let mut rooms_write = rooms.write().await;
+            let mut empty_rooms = Vec::new();
+            let mut shrunk_rooms = Vec::new();
+            for (group_id, room) in rooms_write.iter_mut() {
+                if !room.members.remove(&sid) {
+                    continue;
+                }
+                if room.members.is_empty() {
+                    empty_rooms.push(group_id.clone());
+                } else {
+                    shrunk_rooms.push(group_id.clone());
+                }
             }
+            for group_id in empty_rooms {
+                rooms_write.remove(&group_id);
+            }
+            drop(rooms_write);
+            // When the whole relay is stopping there's nobody to tell
+            if !closing.is_cancelled() {
+                for group_id in shrunk_rooms {
+                    announce_presence(&stats, &peers, &rooms, &group_id).await;
+                }
+            }
+
+            println!("🔌 Session disconnected");
         }
-        for group_id in empty_rooms {
-            rooms_write.remove(&group_id);
-        }
-        
-        println!("🔌 Session disconnected");
     }
 
-    send_task.abort();
+    // Let a parting error reach the client before the connection goes
+    drop(tx);
+    if tokio::time::timeout(CLOSE_GRACE, &mut send_task).await.is_err() {
+        send_task.abort();
+    }
     Ok(())
 }
 
-pub async fn start_relay(addr: String) -> Result<()> {
-    let server = RelayServer::new(addr);
+/// Tell this connection's client what was refused and why
+async fn refuse(tx: &PeerTx, wire: WireFormat, code: ErrorCode, message: &str) {
+    send_error(tx, wire, Message::Error { message: message.to_string(), code, session: None }).await;
+}
+
+/// Tell the client that `sid` isn't connected here
+async fn not_found(tx: &PeerTx, wire: WireFormat, sid: &str) {
+    let message = format!("{} is not connected to this relay", sid);
+    send_error(tx, wire, Message::Error { message, code: ErrorCode::PeerNotFound, session: Some(sid.to_string()) }).await;
+}
+
+async fn send_error(tx: &PeerTx, wire: WireFormat, error: Message) {
+    if let Ok(frame) = codec::encode_as(&error, wire) {
+        let _ = tx.send(frame).await;
+    }
+}
+
+/// Add `sid` to a room, creating it if needed. The first member's token (if any) becomes
+/// the room's password; later joins must present the same token. Returns the member count.
+fn join_room(
+    rooms: &mut HashMap<String, Room>,
+    sid: &str,
+    group_id: &str,
+    join_token: Option<&[u8]>,
+    max_members: usize,
+) -> Result<usize, JoinError> {
+    let room = rooms.entry(group_id.to_string()).or_insert_with(|| Room {
+        members: HashSet::new(),
+        token_hash: join_token.map(blake3::hash),
+    });
+    // blake3::Hash compares in constant time
+    if room.token_hash != join_token.map(blake3::hash) {
+        return Err(JoinError::WrongToken);
+    }
+    if !room.members.contains(sid) && room.members.len() >= max_members {
+        return Err(JoinError::Full);
+    }
+    room.members.insert(sid.to_string());
+    Ok(room.members.len())
+}
+
+fn valid_id(id: &str) -> bool {
+    (MIN_ID_LEN..=MAX_ID_LEN).contains(&id.len())
+}
+
+fn valid_group_id(id: &str) -> bool {
+    (1..=MAX_ID_LEN).contains(&id.len())
+}
+
+/// Whether a client's handshake asks for deflated frames
+fn wants_deflate(request: &Request) -> bool {
+    request.headers().get(codec::COMPRESS_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|v| v.trim().eq_ignore_ascii_case(codec::COMPRESS_DEFLATE)))
+}
+
+/// Check a decoded frame against the connection's state before routing it.
+/// Everything but Connect needs a registered session, and sessions can only
+/// speak (and join rooms) as themselves.
+fn frame_is_valid(message: &Message, session_id: Option<&str>) -> bool {
+    let Some(own) = session_id else {
+        return matches!(message, Message::Connect { session_id, .. } if valid_id(session_id));
+    };
+    let targets_ok = |target: &str| target.is_empty() || valid_id(target);
+    match message {
+        // Re-sending Connect is allowed (keepalive/resync) but can't switch sessions
+        Message::Connect { session_id, .. } => session_id == own,
+        Message::Discover { target_session, from } => valid_id(target_session) && (from.is_empty() || from == own),
+        Message::AudioFrame { from, .. } => from == own,
+        Message::Encrypted { from, target, .. }
+        | Message::KeyExchange { from, target, .. }
+        | Message::Typing { from, target, .. }
+        | Message::ReadReceipt { from, target, .. }
+        | Message::Delivered { from, target, .. } => from == own && targets_ok(target),
+        Message::GroupJoin { session_id, group_id, join_token } => {
+            session_id == own
+                && valid_group_id(group_id)
+                && join_token.as_ref().is_none_or(|t| t.len() == JOIN_TOKEN_LEN)
+        }
+        Message::GroupLeave { session_id, group_id } => session_id == own && valid_group_id(group_id),
+        Message::GroupEncrypted { from, group_id, .. } => from == own && valid_group_id(group_id),
+        // Relay-to-client only
+        Message::Ack { .. } | Message::Error { .. } | Message::RoomPresence { .. } => false,
+    }
+}
+
+/// One connected peer (cloned so no lock is held while forwarding)
+async fn connected_peer(peers: &PeerMap, sid: &str) -> Option<Peer> {
+    peers.read().await.get(sid).cloned()
+}
+
+/// Every connected peer except `exclude`
+async fn peers_except(peers: &PeerMap, exclude: Option<&String>) -> Vec<Peer> {
+    peers.read().await.iter()
+        .filter(|(sid, _)| Some(*sid) != exclude)
+        .map(|(_, peer)| peer.clone())
+        .collect()
+}
+
+/// Send every connected member of `group_id` the room's member count. Only members
+/// hear it, and only the number — which sessions are in the room never leaves the relay.
+async fn announce_presence(stats: &RelayStats, peers: &PeerMap, rooms: &RoomMap, group_id: &str) {
+    let (count, members): (usize, Vec<Peer>) = {
+        let rooms_read = rooms.read().await;
+        let peers_read = peers.read().await;
+        let Some(room) = rooms_read.get(group_id) else {
+            return;
+        };
+        let members = room.members.iter().filter_map(|sid| peers_read.get(sid).cloned()).collect();
+        (room.members.len(), members)
+    };
+    let presence = Message::RoomPresence { group_id: group_id.to_string(), count: count as u32 };
+    let Ok(data) = codec::encode(&presence) else {
+        return;
+    };
+    for peer in members {
+        forward(stats, &peer, data.clone(), false).await;
+    }
+}
+
+/// Forward a frame to one peer. Droppable frames (audio) are discarded if the peer's
+/// queue is full — stale voice is worthless. Everything else waits for room, which
+/// pushes back on the sending client's socket instead of growing relay memory, so a
+/// file goes at the pace of the slower end. A peer that makes no room for
+/// FORWARD_TIMEOUT has stopped reading: it's cut off, so it holds the sender up only
+/// once rather than for every frame after.
+async fn forward(stats: &RelayStats, peer: &Peer, data: Vec<u8>, droppable: bool) {
+    if peer.kick.is_cancelled() {
+        return;
+    }
+    let len = data.len();
+    let sent = if droppable {
+        peer.tx.try_send(data).is_ok()
+    } else {
+        match tokio::time::timeout(FORWARD_TIMEOUT, peer.tx.send(data)).await {
+            Ok(sent) => sent.is_ok(),
+            Err(_) => {
+                peer.liveness.fell_behind();
+                peer.kick.cancel();
+                false
+            }
+        }
+    };
+    if sent {
+        stats.frame_out(len);
+    }
+}
+
+/// Which rate a client frame counts towards
+fn frame_kind(message: &Message) -> FrameKind {
+    match message {
+        Message::Encrypted { .. } => FrameKind::Direct,
+        Message::GroupEncrypted { .. } => FrameKind::Group,
+        Message::AudioFrame { .. } => FrameKind::Audio,
+        Message::KeyExchange { .. } => FrameKind::KeyExchange,
+        Message::Typing { .. } | Message::ReadReceipt { .. } | Message::Delivered { .. } => FrameKind::Signal,
+        _ => FrameKind::Control,
+    }
+}
+
+/// Bind a listener on every address `addrs` resolve to. IPv6 listeners take IPv6
+/// only, so `0.0.0.0` and `[::]` on the same port can both be bound.
+pub async fn bind_all(addrs: &[String]) -> Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    let mut bound = HashSet::new();
+    for addr in addrs {
+        let resolved = tokio::net::lookup_host(addr.as_str()).await.with_context(|| format!("Can't resolve {}", addr))?;
+        for addr in resolved {
+            if bound.insert(addr) {
+                listeners.push(bind(addr).with_context(|| format!("Can't listen on {}", addr))?);
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // As TcpListener::bind does: a restarted relay gets its port straight back
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// The next connection on any of `listeners`
+async fn accept_any(listeners: &[TcpListener]) -> std::io::Result<(TcpStream, SocketAddr)> {
+    std::future::poll_fn(|cx| {
+        listeners.iter()
+            .find_map(|listener| match listener.poll_accept(cx) {
+                Poll::Ready(accepted) => Some(Poll::Ready(accepted)),
+                Poll::Pending => None,
+            })
+            .unwrap_or(Poll::Pending)
+    }).await
+}
+
+/// Where other machines can likely reach the wildcard listeners: the address this
+/// machine would send from towards the internet (worked out locally, nothing is sent).
+/// Behind NAT that's the LAN address.
+fn reachable_addrs(listeners: &[TcpListener]) -> Vec<SocketAddr> {
+    listeners.iter()
+        .filter_map(|listener| listener.local_addr().ok())
+        .filter(|addr| addr.ip().is_unspecified())
+        .filter_map(|addr| {
+            let probe = if addr.is_ipv4() { "192.0.2.1:9" } else { "[2001:db8::1]:9" };
+            let socket = std::net::UdpSocket::bind(SocketAddr::new(addr.ip(), 0)).ok()?;
+            socket.connect(probe).ok()?;
+            Some(SocketAddr::new(socket.local_addr().ok()?.ip(), addr.port()))
+        })
+        .collect()
+}
+
+/// Run a relay on `addrs` until Ctrl+C, printing a status line every `status_interval` if set
+pub async fn start_relay(
+    addrs: Vec<String>,
+    max_room_members: usize,
+    max_sessions: usize,
+    status_interval: Option<Duration>,
+    admin_addr: Option<String>,
+    key: RelayKey,
+) -> Result<()> {
+    let mut server = RelayServer::with_addrs(addrs);
+    server.set_key(key);
+    server.set_max_room_members(max_room_members);
+    server.set_max_sessions(max_sessions);
+    if let Some(interval) = status_interval {
+        server.set_status_interval(interval);
+    }
+    if let Some(addr) = admin_addr {
+        server.set_admin_addr(addr);
+    }
     server.run().await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::FILE_CHUNK_SIZE;
+    use tokio::io::DuplexStream;
+    use tokio_tungstenite::{client_async, WebSocketStream};
+
+    type Ws = WebSocketStream<DuplexStream>;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// One relay connection over an in-memory pipe, plus the shared maps it writes to
+    async fn open() -> (Ws, RoomMap) {
+        let rooms: RoomMap = Arc::new(RwLock::new(HashMap::new()));
+        (open_on(Arc::new(RwLock::new(HashMap::new())), rooms.clone()).await, rooms)
+    }
+
+    /// Another connection to the same relay
+    async fn open_on(peers: PeerMap, rooms: RoomMap) -> Ws {
+        open_limited(peers, rooms, Arc::default()).await
+    }
+
+    async fn open_limited(peers: PeerMap, rooms: RoomMap, limits: Arc<Limits>) -> Ws {
+        open_piped(peers, rooms, limits, 4 * MAX_MESSAGE_SIZE).await
+    }
+
+    /// A connection whose pipe holds only `pipe` bytes the client hasn't read
+    async fn open_piped(peers: PeerMap, rooms: RoomMap, limits: Arc<Limits>, pipe: usize) -> Ws {
+        let (client_io, server_io) = tokio::io::duplex(pipe);
+        tokio::spawn(handle_connection(server_io, peers, rooms, Arc::default(), limits, Arc::new(RelayKey::generate()), CancellationToken::new()));
+        client_async("ws://relay/", client_io).await.unwrap().0
+    }
+
+    fn error_code(msg: Option<Message>) -> Option<ErrorCode> {
+        match msg {
+            Some(Message::Error { code, .. }) => Some(code),
+            _ => None,
+        }
+    }
+
+    async fn send(ws: &mut Ws, msg: &Message) {
+        ws.send(WsMessage::Binary(codec::encode(msg).unwrap())).await.unwrap();
+    }
+
+    /// Next relay frame, or None once the connection is closed
+    async fn recv(ws: &mut Ws) -> Option<Message> {
+        recv_within(ws, TIMEOUT).await
+    }
+
+    async fn recv_within(ws: &mut Ws, wait: Duration) -> Option<Message> {
+        loop {
+            match tokio::time::timeout(wait, ws.next()).await.expect("timed out") {
+                Some(Ok(WsMessage::Binary(data))) => return Some(codec::decode(&data).unwrap()),
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return None,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    fn connect_msg(sid: &str) -> Message {
+        Message::Connect { session_id: sid.to_string(), challenge: vec![] }
+    }
+
+    /// Read until the relay pings, and make sure it has the pong: the Ack to a
+    /// Connect sent after it
+    async fn answer_ping(ws: &mut Ws, sid: &str) {
+        while !matches!(tokio::time::timeout(TIMEOUT, ws.next()).await.expect("timed out"), Some(Ok(WsMessage::Ping(_)))) {}
+        ws.flush().await.unwrap();
+        send(ws, &connect_msg(sid)).await;
+        while !matches!(recv(ws).await, Some(Message::Ack { .. })) {}
+    }
+
+    #[test]
+    fn test_frames_must_come_from_own_session() {
+        let own = "a".repeat(32);
+        let other = "b".repeat(32);
+        let discover = |from: &str| Message::Discover { target_session: other.clone(), from: from.to_string() };
+        assert!(!frame_is_valid(&discover(""), None));
+        assert!(frame_is_valid(&discover(""), Some(&own)));
+        assert!(frame_is_valid(&discover(&own), Some(&own)));
+        assert!(!frame_is_valid(&discover(&other), Some(&own)));
+        assert!(!frame_is_valid(&connect_msg(&other), Some(&own)));
+        assert!(!frame_is_valid(
+            &Message::Typing { from: other.clone(), target: String::new(), is_typing: true },
+            Some(&own),
+        ));
+        assert!(!frame_is_valid(
+            &Message::Typing { from: own.clone(), target: "short".to_string(), is_typing: true },
+            Some(&own),
+        ));
+        assert!(!frame_is_valid(&Message::Ack { relay_key: vec![], proof: vec![] }, Some(&own)));
+    }
+
+    #[tokio::test]
+    async fn test_short_session_id_is_rejected() {
+        let (mut ws, _) = open().await;
+        send(&mut ws, &connect_msg("abc")).await;
+        send(&mut ws, &connect_msg(&"a".repeat(32))).await;
+        // Only the valid Connect is acknowledged
+        assert!(matches!(recv(&mut ws).await, Some(Message::Ack { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_garbage_frames_close_connection() {
+        let (mut ws, _) = open().await;
+        for _ in 0..MAX_INVALID_FRAMES {
+            ws.send(WsMessage::Binary(vec![0xff; 7])).await.unwrap();
+        }
+        assert_eq!(error_code(recv(&mut ws).await), Some(ErrorCode::MalformedFrames));
+        assert!(recv(&mut ws).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_message_closes_connection() {
+        let (mut ws, _) = open().await;
+        send(&mut ws, &connect_msg(&"a".repeat(32))).await;
+        assert!(matches!(recv(&mut ws).await, Some(Message::Ack { .. })));
+
+        let _ = ws.send(WsMessage::Binary(vec![0; MAX_MESSAGE_SIZE + 1])).await;
+        assert_eq!(error_code(recv(&mut ws).await), Some(ErrorCode::FrameTooLarge));
+        assert!(recv(&mut ws).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_frames_before_connect_are_unauthorized() {
+        let (mut ws, _) = open().await;
+        send(&mut ws, &Message::Discover { target_session: "b".repeat(32), from: String::new() }).await;
+        assert_eq!(error_code(recv(&mut ws).await), Some(ErrorCode::Unauthorized));
+        assert!(recv(&mut ws).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_discover_reaches_the_target_or_says_it_is_not_here() {
+        let (alice_id, bob_id, gone_id) = ("a".repeat(32), "b".repeat(32), "c".repeat(32));
+        let peers: PeerMap = Arc::new(RwLock::new(HashMap::new()));
+        let rooms: RoomMap = Arc::new(RwLock::new(HashMap::new()));
+        let mut alice = open_on(peers.clone(), rooms.clone()).await;
+        let mut bob = open_on(peers, rooms).await;
+        for (ws, sid) in [(&mut alice, &alice_id), (&mut bob, &bob_id)] {
+            send(ws, &connect_msg(sid)).await;
+            assert!(matches!(recv(ws).await, Some(Message::Ack { .. })));
+        }
+
+        send(&mut alice, &Message::Discover { target_session: bob_id.clone(), from: alice_id.clone() }).await;
+        assert!(matches!(recv(&mut bob).await, Some(Message::Discover { from, .. }) if from == alice_id));
+
+        send(&mut alice, &Message::Discover { target_session: gone_id.clone(), from: alice_id.clone() }).await;
+        assert!(matches!(
+            recv(&mut alice).await,
+            Some(Message::Error { code: ErrorCode::PeerNotFound, session: Some(session), .. }) if session == gone_id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_past_the_limit_are_turned_away() {
+        let peers: PeerMap = Arc::new(RwLock::new(HashMap::new()));
+        let rooms: RoomMap = Arc::new(RwLock::new(HashMap::new()));
+        let limits = Arc::new(Limits::new(DEFAULT_MAX_ROOM_MEMBERS, 1));
+        let mut first = open_limited(peers.clone(), rooms.clone(), limits.clone()).await;
+        send(&mut first, &connect_msg(&"a".repeat(32))).await;
+        assert!(matches!(recv(&mut first).await, Some(Message::Ack { .. })));
+
+        let mut second = open_limited(peers.clone(), rooms.clone(), limits.clone()).await;
+        send(&mut second, &connect_msg(&"b".repeat(32))).await;
+        assert_eq!(error_code(recv(&mut second).await), Some(ErrorCode::SessionLimit));
+        assert!(recv(&mut second).await.is_none());
+
+        // Resuming a session that's already counted still works
+        let mut resumed = open_limited(peers, rooms, limits).await;
+        send(&mut resumed, &connect_msg(&"a".repeat(32))).await;
+        assert!(matches!(recv(&mut resumed).await, Some(Message::Ack { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_cannot_join_room_as_another_session() {
+        let (mut ws, rooms) = open().await;
+        let own = "a".repeat(32);
+        send(&mut ws, &connect_msg(&own)).await;
+        assert!(matches!(recv(&mut ws).await, Some(Message::Ack { .. })));
+
+        let join = |sid: &str| Message::GroupJoin {
+            session_id: sid.to_string(),
+            group_id: "room".to_string(),
+            join_token: None,
+        };
+        send(&mut ws, &join(&"b".repeat(32))).await;
+        send(&mut ws, &join(&own)).await;
+        assert!(matches!(recv(&mut ws).await, Some(Message::RoomPresence { count: 1, .. })));
+
+        let rooms = rooms.read().await;
+        assert_eq!(rooms.get("room").map(|r| &r.members), Some(&HashSet::from([own])));
+    }
+
+    #[test]
+    fn test_join_token_is_checked() {
+        let mut rooms = HashMap::new();
+        let (a, b, c) = ("a".repeat(32), "b".repeat(32), "c".repeat(32));
+        let token = [7u8; JOIN_TOKEN_LEN];
+
+        assert_eq!(join_room(&mut rooms, &a, "room", Some(&token), 8), Ok(1));
+        assert_eq!(join_room(&mut rooms, &b, "room", None, 8), Err(JoinError::WrongToken));
+        assert_eq!(join_room(&mut rooms, &b, "room", Some(&[8u8; JOIN_TOKEN_LEN]), 8), Err(JoinError::WrongToken));
+        assert_eq!(join_room(&mut rooms, &b, "room", Some(&token), 8), Ok(2));
+        // Only the hash is kept
+        assert_eq!(rooms["room"].token_hash, Some(blake3::hash(&token)));
+
+        // A room opened without a token stays open, and can't be claimed afterwards
+        assert_eq!(join_room(&mut rooms, &a, "open", None, 8), Ok(1));
+        assert_eq!(join_room(&mut rooms, &c, "open", Some(&token), 8), Err(JoinError::WrongToken));
+        assert_eq!(join_room(&mut rooms, &c, "open", None, 8), Ok(2));
+    }
+
+    #[test]
+    fn test_room_member_cap() {
+        let mut rooms = HashMap::new();
+        let (a, b, c) = ("a".repeat(32), "b".repeat(32), "c".repeat(32));
+        assert_eq!(join_room(&mut rooms, &a, "room", None, 2), Ok(1));
+        assert_eq!(join_room(&mut rooms, &b, "room", None, 2), Ok(2));
+        assert_eq!(join_room(&mut rooms, &c, "room", None, 2), Err(JoinError::Full));
+        // Members re-joining (e.g. after a reconnect) don't count twice
+        assert_eq!(join_room(&mut rooms, &b, "room", None, 2), Ok(2));
+    }
+
+    #[tokio::test]
+    async fn test_refused_join_gets_error() {
+        let (mut ws, rooms) = open().await;
+        let own = "a".repeat(32);
+        send(&mut ws, &connect_msg(&own)).await;
+        assert!(matches!(recv(&mut ws).await, Some(Message::Ack { .. })));
+        join_room(&mut *rooms.write().await, &"b".repeat(32), "room", Some(&[1; JOIN_TOKEN_LEN]), 8).unwrap();
+
+        send(&mut ws, &Message::GroupJoin {
+            session_id: own.clone(),
+            group_id: "room".to_string(),
+            join_token: Some(vec![2; JOIN_TOKEN_LEN]),
+        }).await;
+        assert!(matches!(recv(&mut ws).await, Some(Message::Error { message, code: ErrorCode::JoinRefused, .. }) if message.contains("wrong join token")));
+        assert!(!rooms.read().await["room"].members.contains(&own));
+    }
+
+    #[tokio::test]
+    async fn test_room_presence_goes_to_members_only() {
+        let peers: PeerMap = Arc::new(RwLock::new(HashMap::new()));
+        let rooms: RoomMap = Arc::new(RwLock::new(HashMap::new()));
+        let mut clients = Vec::new();
+        for sid in ["a", "b", "c"].map(|c| c.repeat(32)) {
+            let mut ws = open_on(peers.clone(), rooms.clone()).await;
+            send(&mut ws, &connect_msg(&sid)).await;
+            assert!(matches!(recv(&mut ws).await, Some(Message::Ack { .. })));
+            clients.push((sid, ws));
+        }
+        let join = |sid: &str| Message::GroupJoin { session_id: sid.to_string(), group_id: "room".to_string(), join_token: None };
+        let [(a, ws_a), (b, ws_b), (c, ws_c)] = &mut clients[..] else { unreachable!() };
+
+        send(ws_a, &join(a)).await;
+        assert!(matches!(recv(ws_a).await, Some(Message::RoomPresence { group_id, count: 1 }) if group_id == "room"));
+        send(ws_b, &join(b)).await;
+        assert!(matches!(recv(ws_a).await, Some(Message::RoomPresence { count: 2, .. })));
+        assert!(matches!(recv(ws_b).await, Some(Message::RoomPresence { count: 2, .. })));
+
+        // A re-join (resumed session) counts once and only the joiner hears it
+        send(ws_b, &join(b)).await;
+        assert!(matches!(recv(ws_b).await, Some(Message::RoomPresence { count: 2, .. })));
+
+        // Disconnecting shrinks the room for those left
+        ws_b.close(None).await.unwrap();
+        assert!(matches!(recv(ws_a).await, Some(Message::RoomPresence { count: 1, .. })));
+
+        // The outsider heard nothing: its next frame is the Ack to this Connect
+        send(ws_c, &connect_msg(c)).await;
+        assert!(matches!(recv(ws_c).await, Some(Message::Ack { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_reaper_drops_quiet_sessions_and_sweeps_rooms() {
+        let peers: PeerMap = Arc::new(RwLock::new(HashMap::new()));
+        let rooms: RoomMap = Arc::new(RwLock::new(HashMap::new()));
+        let stats = RelayStats::default();
+        let (a, b, gone) = ("a".repeat(32), "b".repeat(32), "c".repeat(32));
+        let mut alive = open_on(peers.clone(), rooms.clone()).await;
+        let mut quiet = open_on(peers.clone(), rooms.clone()).await;
+        for (ws, sid) in [(&mut alive, &a), (&mut quiet, &b)] {
+            send(ws, &connect_msg(sid)).await;
+            assert!(matches!(recv(ws).await, Some(Message::Ack { .. })));
+        }
+        {
+            // Left behind by members that never disconnected cleanly
+            let mut rooms = rooms.write().await;
+            for sid in [&a, &b, &gone] {
+                join_room(&mut rooms, sid, "room", None, 8).unwrap();
+            }
+            join_room(&mut rooms, &gone, "abandoned", None, 8).unwrap();
+        }
+
+        let first = reap::reap(&stats, &peers, &rooms).await;
+        assert_eq!(first, reap::Reaped { sessions: 0, members: 2, rooms: 1 });
+        assert!(!rooms.read().await.contains_key("abandoned"));
+        answer_ping(&mut alive, &a).await;
+
+        // Missing one pong is forgiven; missing a second isn't
+        assert_eq!(reap::reap(&stats, &peers, &rooms).await, reap::Reaped::default());
+        answer_ping(&mut alive, &a).await;
+        assert_eq!(reap::reap(&stats, &peers, &rooms).await, reap::Reaped { sessions: 1, members: 1, rooms: 0 });
+        // Its connection is closed behind what was still queued for it
+        while recv(&mut quiet).await.is_some() {}
+        assert_eq!(peers.read().await.keys().collect::<Vec<_>>(), [&a]);
+        assert_eq!(rooms.read().await["room"].members, HashSet::from([a.clone()]));
+        assert!(matches!(recv(&mut alive).await, Some(Message::RoomPresence { count: 1, .. })));
+        assert!(stats.summary().ends_with(" · reaped 1 sessions, 1 rooms"), "{}", stats.summary());
+    }
+
+    #[tokio::test]
+    async fn test_a_peer_that_stops_reading_doesnt_hold_up_the_others() {
+        let peers: PeerMap = Arc::new(RwLock::new(HashMap::new()));
+        let rooms: RoomMap = Arc::new(RwLock::new(HashMap::new()));
+        let (a, b, stuck_id) = ("a".repeat(32), "b".repeat(32), "c".repeat(32));
+        let mut alice = open_on(peers.clone(), rooms.clone()).await;
+        let mut bob = open_on(peers.clone(), rooms.clone()).await;
+        let mut stuck = open_piped(peers.clone(), rooms.clone(), Arc::default(), 64 * 1024).await;
+        for (ws, sid) in [(&mut alice, &a), (&mut bob, &b), (&mut stuck, &stuck_id)] {
+            send(ws, &connect_msg(sid)).await;
+            assert!(matches!(recv(ws).await, Some(Message::Ack { .. })));
+        }
+
+        // Enough broadcasts to fill the stuck peer's pipe and queue twice over
+        const FRAMES: usize = 2 * PEER_QUEUE;
+        send_numbered(alice, &a, "", FRAMES, 4096);
+
+        // Bob waits on the stuck peer once, for FORWARD_TIMEOUT, not for every frame after
+        let all = tokio::time::timeout(2 * FORWARD_TIMEOUT, async {
+            for i in 0..FRAMES {
+                let Some(Message::Encrypted { nonce, .. }) = recv_within(&mut bob, FORWARD_TIMEOUT + TIMEOUT).await else {
+                    panic!("expected broadcast {}", i);
+                };
+                assert_eq!(nonce, (i as u32).to_le_bytes());
+            }
+        });
+        all.await.expect("held up by the stuck peer more than once");
+        // The stuck peer was cut off behind what was already on its way
+        while recv(&mut stuck).await.is_some() {}
+        assert!(!peers.read().await.contains_key(&stuck_id));
+    }
+
+    #[tokio::test]
+    async fn test_a_slow_reader_gets_a_whole_transfer_and_stays_connected() {
+        let peers: PeerMap = Arc::new(RwLock::new(HashMap::new()));
+        let rooms: RoomMap = Arc::new(RwLock::new(HashMap::new()));
+        let (a, b) = ("a".repeat(32), "b".repeat(32));
+        let mut alice = open_on(peers.clone(), rooms.clone()).await;
+        let mut bob = open_piped(peers.clone(), rooms.clone(), Arc::default(), 64 * 1024).await;
+        for (ws, sid) in [(&mut alice, &a), (&mut bob, &b)] {
+            send(ws, &connect_msg(sid)).await;
+            assert!(matches!(recv(ws).await, Some(Message::Ack { .. })));
+        }
+
+        // 8 MB of chunks, twice what Bob's pipe and queue hold, sent as fast as Alice
+        // can: the relay holds her back to his pace instead of cutting him off
+        const FRAMES: usize = 2 * PEER_QUEUE;
+        send_numbered(alice, &a, &b, FRAMES, FILE_CHUNK_SIZE);
+        for i in 0..FRAMES {
+            if i % 16 == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            let Some(Message::Encrypted { nonce, .. }) = recv(&mut bob).await else {
+                panic!("expected chunk {}", i);
+            };
+            assert_eq!(nonce, (i as u32).to_le_bytes());
+        }
+        assert!(peers.read().await.contains_key(&b));
+    }
+
+    /// Send `count` frames from `from` to `target` (empty for everyone) in the
+    /// background, each numbered in its nonce
+    fn send_numbered(mut ws: Ws, from: &str, target: &str, count: usize, size: usize) {
+        let (from, target) = (from.to_string(), target.to_string());
+        tokio::spawn(async move {
+            for i in 0..count {
+                let frame = Message::Encrypted {
+                    from: from.clone(),
+                    target: target.clone(),
+                    header: vec![],
+                    nonce: (i as u32).to_le_bytes().to_vec(),
+                    ciphertext: vec![0; size],
+                };
+                send(&mut ws, &frame).await;
+            }
+            ws
+        });
+    }
+
+}
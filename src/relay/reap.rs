@@ -0,0 +1,126 @@
+//! Clearing out what the disconnect path misses. A connection cleans up after itself
+//! when it ends, but a half-dead socket can sit open until TCP gives up, still getting
+//! copies of every broadcast, and a member that went without a clean disconnect can
+//! linger in a room. Every REAP_INTERVAL the relay pings each session, cuts off those
+//! that left MISSED_PONGS pings in a row unanswered, and then drops room members that
+//! aren't connected any more (and rooms nobody is left in).
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::protocol::short_id;
+
+use super::stats::RelayStats;
+use super::{announce_presence, Peer, PeerMap, Room, RoomMap};
+
+/// How often sessions are pinged and rooms swept
+pub(super) const REAP_INTERVAL: Duration = Duration::from_secs(60);
+/// Unanswered pings in a row before a session is dropped
+const MISSED_PONGS: u32 = 2;
+
+/// Whether a session still answers: the reaper asks for a ping, the connection's send
+/// task sends it, and the pong coming back clears the count. Also whether it stopped
+/// reading, so that forwarding cut it off.
+#[derive(Debug, Default)]
+pub(super) struct Liveness {
+    ping: Notify,
+    /// Pings sent since the client last answered one
+    unanswered: AtomicU32,
+    lagging: AtomicBool,
+}
+
+impl Liveness {
+    /// Wait until the reaper wants this session pinged
+    pub async fn ping_due(&self) {
+        self.ping.notified().await
+    }
+
+    /// A pong came back
+    pub fn answered(&self) {
+        self.unanswered.store(0, Ordering::Relaxed);
+    }
+
+    /// Whether the session stopped answering and is being dropped for it
+    pub fn gone_quiet(&self) -> bool {
+        self.unanswered.load(Ordering::Relaxed) >= MISSED_PONGS
+    }
+
+    /// Its queue stayed full: it's being dropped for not reading
+    pub fn fell_behind(&self) {
+        self.lagging.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether it's being dropped for not reading
+    pub fn lagging(&self) -> bool {
+        self.lagging.load(Ordering::Relaxed)
+    }
+
+    /// Ask for another ping
+    fn ping(&self) {
+        self.unanswered.fetch_add(1, Ordering::Relaxed);
+        self.ping.notify_one();
+    }
+}
+
+/// What one sweep cleared out
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(super) struct Reaped {
+    pub sessions: usize,
+    pub members: usize,
+    pub rooms: usize,
+}
+
+/// Ping every session and drop the ones gone quiet, then sweep the rooms
+pub(super) async fn reap(stats: &RelayStats, peers: &PeerMap, rooms: &RoomMap) -> Reaped {
+    let mut reaped = Reaped::default();
+    {
+        let mut peers_write = peers.write().await;
+        peers_write.retain(|sid, peer| {
+            if !peer.liveness.gone_quiet() {
+                peer.liveness.ping();
+                return true;
+            }
+            println!("💤 Session {} stopped answering pings", short_id(sid));
+            peer.kick.cancel();
+            reaped.sessions += 1;
+            false
+        });
+    }
+
+    let shrunk = {
+        let peers_read = peers.read().await;
+        let mut rooms_write = rooms.write().await;
+        let (members, emptied, shrunk) = sweep_rooms(&mut rooms_write, &peers_read);
+        reaped.members = members;
+        reaped.rooms = emptied;
+        shrunk
+    };
+    for group_id in shrunk {
+        announce_presence(stats, peers, rooms, &group_id).await;
+    }
+    stats.reaped(reaped.sessions, reaped.rooms);
+    reaped
+}
+
+/// Drop room members that aren't connected, and rooms left empty. Returns how many of
+/// each went, and the rooms that still have someone in them to tell.
+fn sweep_rooms(rooms: &mut HashMap<String, Room>, peers: &HashMap<String, Peer>) -> (usize, usize, Vec<String>) {
+    let mut members = 0;
+    let mut shrunk = Vec::new();
+    for (group_id, room) in rooms.iter_mut() {
+        let before = room.members.len();
+        room.members.retain(|sid| peers.contains_key(sid));
+        if room.members.len() < before {
+            members += before - room.members.len();
+            if !room.members.is_empty() {
+                shrunk.push(group_id.clone());
+            }
+        }
+    }
+    let before = rooms.len();
+    rooms.retain(|_, room| !room.members.is_empty());
+    (members, before - rooms.len(), shrunk)
+}
@@ -0,0 +1,194 @@
+//! Relay counters for the `--status-interval` heartbeat and the summary printed on
+//! Ctrl+C. Connection tasks bump atomics; nothing here is ever locked. Only frame
+//! kinds and sizes are counted — never who sent what to whom.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// What a client frame was, for the per-type rates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FrameKind {
+    /// Encrypted pairwise messages
+    Direct,
+    Group,
+    Audio,
+    KeyExchange,
+    /// Typing indicators and read receipts
+    Signal,
+    /// Connect, discovery and room joins/leaves
+    Control,
+}
+
+impl FrameKind {
+    const ALL: [FrameKind; 6] = [
+        FrameKind::Direct,
+        FrameKind::Group,
+        FrameKind::Audio,
+        FrameKind::KeyExchange,
+        FrameKind::Signal,
+        FrameKind::Control,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            FrameKind::Direct => "dm",
+            FrameKind::Group => "group",
+            FrameKind::Audio => "audio",
+            FrameKind::KeyExchange => "kx",
+            FrameKind::Signal => "signal",
+            FrameKind::Control => "ctl",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct RelayStats {
+    started: Instant,
+    peak_sessions: AtomicU64,
+    /// Frames received from clients, by `FrameKind`
+    frames_in: [AtomicU64; 6],
+    bytes_in: AtomicU64,
+    /// Frames handed to a recipient's queue (one frame to five room members is five)
+    frames_out: AtomicU64,
+    bytes_out: AtomicU64,
+    /// Sessions dropped for not answering pings, and rooms swept away once empty
+    sessions_reaped: AtomicU64,
+    rooms_reaped: AtomicU64,
+}
+
+impl Default for RelayStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            peak_sessions: AtomicU64::new(0),
+            frames_in: Default::default(),
+            bytes_in: AtomicU64::new(0),
+            frames_out: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            sessions_reaped: AtomicU64::new(0),
+            rooms_reaped: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Totals at one moment, so two of them give the rates over an interval
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct Totals {
+    frames_in: [u64; 6],
+    bytes_in: u64,
+    frames_out: u64,
+    bytes_out: u64,
+}
+
+impl RelayStats {
+    pub fn frame_in(&self, kind: FrameKind, bytes: usize) {
+        self.frames_in[kind as usize].fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn frame_out(&self, bytes: usize) {
+        self.frames_out.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Note how many sessions are connected now
+    pub fn sessions(&self, connected: usize) {
+        self.peak_sessions.fetch_max(connected as u64, Ordering::Relaxed);
+    }
+
+    /// Note what a reaper sweep cleared out
+    pub fn reaped(&self, sessions: usize, rooms: usize) {
+        self.sessions_reaped.fetch_add(sessions as u64, Ordering::Relaxed);
+        self.rooms_reaped.fetch_add(rooms as u64, Ordering::Relaxed);
+    }
+
+    /// " · reaped 3 sessions, 1 rooms" once the reaper has cleared anything out
+    fn reaped_text(&self) -> String {
+        let (sessions, rooms) = (self.sessions_reaped.load(Ordering::Relaxed), self.rooms_reaped.load(Ordering::Relaxed));
+        if sessions + rooms == 0 {
+            return String::new();
+        }
+        format!(" · reaped {} sessions, {} rooms", sessions, rooms)
+    }
+
+    pub fn totals(&self) -> Totals {
+        Totals {
+            frames_in: std::array::from_fn(|i| self.frames_in[i].load(Ordering::Relaxed)),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            frames_out: self.frames_out.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+
+    /// One heartbeat line: sessions, rooms, and rates since `previous` (taken `elapsed` ago)
+    pub fn status_line(&self, sessions: usize, rooms: usize, previous: &Totals, now: &Totals, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64().max(0.001);
+        let rate = |now: u64, then: u64| now.saturating_sub(then) as f64 / secs;
+        let by_kind: Vec<String> = FrameKind::ALL.iter()
+            .map(|&kind| format!("{} {:.1}", kind.label(), rate(now.frames_in[kind as usize], previous.frames_in[kind as usize])))
+            .collect();
+        format!(
+            "[{}] {} sessions · {} rooms · msgs/s: {} · in {}/s · out {}/s{}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            sessions,
+            rooms,
+            by_kind.join(" "),
+            format_bytes(rate(now.bytes_in, previous.bytes_in)),
+            format_bytes(rate(now.bytes_out, previous.bytes_out)),
+            self.reaped_text(),
+        )
+    }
+
+    /// What to print on the way out
+    pub fn summary(&self) -> String {
+        let totals = self.totals();
+        let uptime = self.started.elapsed().as_secs();
+        format!(
+            "📊 Up {}h {:02}m {:02}s · peak {} sessions · {} messages forwarded ({}){}",
+            uptime / 3600,
+            uptime % 3600 / 60,
+            uptime % 60,
+            self.peak_sessions.load(Ordering::Relaxed),
+            totals.frames_out,
+            format_bytes(totals.bytes_out as f64),
+            self.reaped_text(),
+        )
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    if bytes >= 1024.0 * 1024.0 {
+        format!("{:.1} MB", bytes / (1024.0 * 1024.0))
+    } else if bytes >= 1024.0 {
+        format!("{:.1} KB", bytes / 1024.0)
+    } else {
+        format!("{:.0} B", bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_line_rates() {
+        let stats = RelayStats::default();
+        let before = stats.totals();
+        for _ in 0..20 {
+            stats.frame_in(FrameKind::Audio, 100);
+        }
+        stats.frame_in(FrameKind::Direct, 48);
+        stats.frame_out(2048);
+        stats.sessions(3);
+        stats.sessions(2);
+
+        let line = stats.status_line(2, 1, &before, &stats.totals(), Duration::from_secs(2));
+        assert!(line.contains("2 sessions · 1 rooms"), "{}", line);
+        assert!(line.contains("dm 0.5 group 0.0 audio 10.0"), "{}", line);
+        assert!(line.contains("in 1.0 KB/s · out 1.0 KB/s"), "{}", line);
+        assert!(stats.summary().contains("peak 3 sessions · 1 messages forwarded"));
+        assert!(!line.contains("reaped"));
+        stats.reaped(2, 1);
+        assert!(stats.summary().ends_with(" · reaped 2 sessions, 1 rooms"), "{}", stats.summary());
+    }
+}
@@ -1,85 +1,403 @@
+//! Encrypted on-disk chat history.
+//!
+//! Records are numbered as they're saved and sealed with their number (see
+//! `records`), so loading can put them back in order, drop a second copy, say which
+//! are missing, and step over one cut short by a crash without losing what follows.
+//! A file from before the numbering is rewritten in the new layout the first time
+//! it's loaded.
+
+mod records;
+
 use anyhow::Result;
+use std::collections::HashSet;
 use std::path::Path;
+use std::sync::Mutex;
 
 use crate::crypto::{decrypt_message, encrypt_message};
 use crate::protocol::PlainMessage;
 
+use records::{Header, Scanned, HEADER_LEN, MAGIC, VERSION};
+
+/// Something wrong with the history file that loading worked around
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum HistoryProblem {
+    #[error("the last record was cut short at byte {offset} (a crash while saving?)")]
+    TornTail { offset: u64 },
+    #[error("{len} unreadable bytes at byte {offset}, with intact records after them")]
+    Damaged { offset: u64, len: u64 },
+    #[error("records {first} to {last} are missing")]
+    Missing { first: u64, last: u64 },
+    #[error("record {seq} is in the file twice; the second copy was skipped")]
+    Duplicate { seq: u64 },
+    #[error("record {seq} was out of place and has been put back in order")]
+    OutOfOrder { seq: u64 },
+}
+
+/// What loading the history found
+#[derive(Debug, Default)]
+pub struct LoadedHistory {
+    /// In the order they were saved, without disappearing messages that ran out
+    pub messages: Vec<PlainMessage>,
+    pub problems: Vec<HistoryProblem>,
+    /// The file was in the old layout and has been rewritten
+    pub migrated: bool,
+}
+
+/// Where the next save goes
+#[derive(Debug)]
+struct Appender {
+    header: Header,
+    next_seq: u64,
+    /// There's no file yet (or only part of a header): write the header first
+    fresh: bool,
+    /// Cut a torn last record off before appending
+    torn_tail: Option<u64>,
+}
+
 /// Encrypted chat history storage
 pub struct HistoryStorage {
     path: std::path::PathBuf,
     key: Vec<u8>,
+    /// Known once the file has been read
+    appender: Mutex<Option<Appender>>,
 }
 
 impl HistoryStorage {
+    /// History file at `path`, encrypted with a 32-byte key
     pub fn new<P: AsRef<Path>>(path: P, encryption_key: &[u8]) -> Self {
         Self {
             path: path.as_ref().to_path_buf(),
             key: encryption_key.to_vec(),
+            appender: Mutex::new(None),
         }
     }
 
     /// Save a message to encrypted storage
     pub fn save_message(&self, msg: &PlainMessage) -> Result<()> {
-        let serialized = rmp_serde::to_vec(msg)?;
-        let (nonce, ciphertext) = encrypt_message(&self.key, &serialized)?;
-        
-        let mut data = nonce;
-        data.extend(ciphertext);
-        
-        // Append to file
+        let mut slot = self.appender.lock().unwrap_or_else(|e| e.into_inner());
+        // Taken out while we write: after a failed save the file is read afresh
+        let mut appender = match slot.take() {
+            Some(appender) => appender,
+            None => self.read()?.1,
+        };
+
         use std::io::Write;
         let mut file = std::fs::OpenOptions::new()
             .create(true)
             .append(true)
             .open(&self.path)?;
-        
-        // Write length prefix
-        file.write_all(&(data.len() as u32).to_le_bytes())?;
-        file.write_all(&data)?;
-        
+        if appender.fresh {
+            file.set_len(0)?;
+            file.write_all(&appender.header)?;
+            appender.fresh = false;
+        }
+        if let Some(offset) = appender.torn_tail.take() {
+            file.set_len(offset)?;
+        }
+        file.write_all(&records::seal(&self.key, &appender.header, appender.next_seq, msg)?)?;
+        appender.next_seq += 1;
+
+        *slot = Some(appender);
         Ok(())
     }
 
-    /// Load all messages from encrypted storage
-    pub fn load_messages(&self) -> Result<Vec<PlainMessage>> {
-        if !self.path.exists() {
-            return Ok(Vec::new());
-        }
+    /// Where the starred message ids live: a sidecar next to the history file
+    fn stars_path(&self) -> std::path::PathBuf {
+        self.path.with_extension("stars")
+    }
 
-        use std::io::Read;
-        let mut file = std::fs::File::open(&self.path)?;
-        let mut messages = Vec::new();
+    /// Replace the saved set of starred message ids
+    pub fn save_stars(&self, ids: &std::collections::HashSet<String>) -> Result<()> {
+        let serialized = rmp_serde::to_vec(ids)?;
+        let (nonce, ciphertext) = encrypt_message(&self.key, &serialized)?;
 
-        loop {
-            let mut len_bytes = [0u8; 4];
-            match file.read_exact(&mut len_bytes) {
-                Ok(_) => {}
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e.into()),
-            }
+        let mut data = nonce;
+        data.extend(ciphertext);
+        std::fs::write(self.stars_path(), data)?;
+        Ok(())
+    }
 
-            let len = u32::from_le_bytes(len_bytes) as usize;
-            let mut data = vec![0u8; len];
-            file.read_exact(&mut data)?;
+    /// Load the starred message ids, empty if none were saved
+    pub fn load_stars(&self) -> Result<std::collections::HashSet<String>> {
+        let path = self.stars_path();
+        if !path.exists() {
+            return Ok(Default::default());
+        }
+        let data = std::fs::read(path)?;
+        if data.len() < 12 {
+            anyhow::bail!("Stars file is truncated");
+        }
+        let plaintext = decrypt_message(&self.key, &data[..12], &data[12..])?;
+        Ok(rmp_serde::from_slice(&plaintext)?)
+    }
 
-            if data.len() < 12 {
-                continue; // Invalid entry
-            }
+    /// Load all messages from encrypted storage, skipping expired disappearing messages
+    pub fn load_messages(&self) -> Result<Vec<PlainMessage>> {
+        Ok(self.load_history()?.messages)
+    }
 
-            let nonce = &data[..12];
-            let ciphertext = &data[12..];
+    /// Load all messages, with whatever was wrong with the file. An old-layout file is
+    /// rewritten in the current one.
+    pub fn load_history(&self) -> Result<LoadedHistory> {
+        let mut slot = self.appender.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut loaded, appender) = self.read()?;
+        *slot = Some(appender);
 
-            match decrypt_message(&self.key, nonce, ciphertext) {
-                Ok(plaintext) => {
-                    if let Ok(msg) = rmp_serde::from_slice::<PlainMessage>(&plaintext)
-                        .or_else(|_| bincode::deserialize::<PlainMessage>(&plaintext)) {
-                        messages.push(msg);
+        // Disappearing messages that ran out while we were away stay gone
+        let now = chrono::Utc::now().timestamp();
+        loaded.messages.retain(|msg| !msg.is_expired(now));
+        Ok(loaded)
+    }
+
+    /// Everything in the file, and where the next save goes
+    fn read(&self) -> Result<(LoadedHistory, Appender)> {
+        let file = match std::fs::read(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        // Nothing saved yet, or a crash while writing the header
+        if file.len() < HEADER_LEN && MAGIC.starts_with(&file[..file.len().min(MAGIC.len())]) {
+            let appender = Appender { header: records::header(chrono::Utc::now().timestamp()), next_seq: 0, fresh: true, torn_tail: None };
+            return Ok((LoadedHistory::default(), appender));
+        }
+        if !file.starts_with(MAGIC) {
+            return self.migrate(&file);
+        }
+        let version = file[MAGIC.len()];
+        anyhow::ensure!(
+            version <= VERSION,
+            "History file {} is format v{} from a newer wsp; leaving it alone",
+            self.path.display(),
+            version
+        );
+        let header: Header = file[..HEADER_LEN].try_into()?;
+
+        let mut loaded = LoadedHistory::default();
+        let mut torn_tail = None;
+        let mut seen = HashSet::new();
+        let mut highest = None;
+        let mut numbered = Vec::new();
+        for found in records::scan(&self.key, &header, &file) {
+            match found {
+                Scanned::Unreadable { offset, len } if offset + len == file.len() => {
+                    // Nothing opening at all is a wrong key (or not our file), not a
+                    // crash: the next save would cut the whole history off
+                    anyhow::ensure!(
+                        !seen.is_empty() || records::cut_short(&file[offset..]),
+                        "History file {} doesn't open with this key; leaving it alone",
+                        self.path.display()
+                    );
+                    torn_tail = Some(offset as u64);
+                    loaded.problems.push(HistoryProblem::TornTail { offset: offset as u64 });
+                }
+                Scanned::Unreadable { offset, len } => {
+                    loaded.problems.push(HistoryProblem::Damaged { offset: offset as u64, len: len as u64 });
+                }
+                Scanned::Record { seq, .. } if !seen.insert(seq) => {
+                    loaded.problems.push(HistoryProblem::Duplicate { seq });
+                }
+                Scanned::Record { seq, msg } => {
+                    if highest.is_some_and(|highest| seq < highest) {
+                        loaded.problems.push(HistoryProblem::OutOfOrder { seq });
                     }
+                    highest = highest.max(Some(seq));
+                    numbered.push((seq, msg));
                 }
-                Err(_) => continue, // Skip corrupted messages
             }
         }
 
-        Ok(messages)
+        numbered.sort_by_key(|(seq, _)| *seq);
+        let mut next_seq = 0;
+        for (seq, msg) in numbered {
+            if seq > next_seq {
+                loaded.problems.push(HistoryProblem::Missing { first: next_seq, last: seq - 1 });
+            }
+            next_seq = seq + 1;
+            loaded.messages.push(*msg);
+        }
+        Ok((loaded, Appender { header, next_seq, fresh: false, torn_tail }))
+    }
+
+    /// Rewrite a file from before the numbering in the current layout, keeping the
+    /// original beside it as `.bak`
+    fn migrate(&self, file: &[u8]) -> Result<(LoadedHistory, Appender)> {
+        let (messages, unreadable) = records::legacy_messages(&self.key, file);
+        anyhow::ensure!(
+            unreadable == 0 && !messages.is_empty(),
+            "History file {} isn't one this key can read; leaving it alone",
+            self.path.display()
+        );
+        let mut backup = self.path.clone().into_os_string();
+        backup.push(".bak");
+        std::fs::copy(&self.path, &backup)?;
+
+        let header = records::header(chrono::Utc::now().timestamp());
+        let mut rewritten = header.to_vec();
+        for (seq, msg) in messages.iter().enumerate() {
+            rewritten.extend(records::seal(&self.key, &header, seq as u64, msg)?);
+        }
+        // Written beside it and renamed over, so a crash leaves one whole file or the other
+        let staging = self.path.with_extension("migrating");
+        std::fs::write(&staging, rewritten)?;
+        std::fs::rename(&staging, &self.path)?;
+
+        let appender = Appender { header, next_seq: messages.len() as u64, fresh: false, torn_tail: None };
+        Ok((LoadedHistory { messages, problems: Vec::new(), migrated: true }, appender))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(loaded: &LoadedHistory) -> Vec<&str> {
+        loaded.messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    fn save(storage: &HistoryStorage, text: &str) {
+        storage.save_message(&PlainMessage::new("me".to_string(), text.to_string())).unwrap();
+    }
+
+    #[test]
+    fn test_a_torn_last_record_loses_only_itself_at_every_cut() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.enc");
+        let storage = HistoryStorage::new(&path, &[7; 32]);
+        save(&storage, "one");
+        save(&storage, "two");
+        let last_start = std::fs::metadata(&path).unwrap().len() as usize;
+        save(&storage, "three");
+        let full = std::fs::read(&path).unwrap();
+
+        for cut in last_start..full.len() {
+            // Cut anywhere in the last record: the rest load, and the cut is reported
+            std::fs::write(&path, &full[..cut]).unwrap();
+            let loaded = HistoryStorage::new(&path, &[7; 32]).load_history().unwrap();
+            assert_eq!(texts(&loaded), ["one", "two"], "cut at {}", cut);
+            let torn = (cut > last_start).then_some(HistoryProblem::TornTail { offset: last_start as u64 });
+            assert_eq!(loaded.problems, torn.into_iter().collect::<Vec<_>>(), "cut at {}", cut);
+
+            // Intact records after the torn one still load
+            let mut appended = full[..cut].to_vec();
+            appended.extend_from_slice(&full[last_start..]);
+            std::fs::write(&path, &appended).unwrap();
+            let loaded = HistoryStorage::new(&path, &[7; 32]).load_history().unwrap();
+            assert_eq!(texts(&loaded), ["one", "two", "three"], "cut at {}", cut);
+            let damaged = (cut > last_start).then_some(HistoryProblem::Damaged { offset: last_start as u64, len: (cut - last_start) as u64 });
+            assert_eq!(loaded.problems, damaged.into_iter().collect::<Vec<_>>(), "cut at {}", cut);
+        }
+
+        // Saving after a torn record cuts it off and carries on the numbering
+        std::fs::write(&path, &full[..full.len() - 1]).unwrap();
+        let storage = HistoryStorage::new(&path, &[7; 32]);
+        save(&storage, "four");
+        let loaded = storage.load_history().unwrap();
+        assert_eq!(texts(&loaded), ["one", "two", "four"]);
+        assert!(loaded.problems.is_empty(), "{:?}", loaded.problems);
+    }
+
+    #[test]
+    fn test_reordered_duplicated_and_missing_records_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.enc");
+        let storage = HistoryStorage::new(&path, &[7; 32]);
+        let mut ends = vec![HEADER_LEN];
+        for text in ["r0", "r1", "r2", "r3"] {
+            save(&storage, text);
+            ends.push(std::fs::metadata(&path).unwrap().len() as usize);
+        }
+        let full = std::fs::read(&path).unwrap();
+        let record = |n: usize| &full[ends[n]..ends[n + 1]];
+
+        // A sync tool shuffled the file: r2, r0, r0 again, r3, and r1 lost
+        let mut shuffled = full[..HEADER_LEN].to_vec();
+        for n in [2, 0, 0, 3] {
+            shuffled.extend_from_slice(record(n));
+        }
+        std::fs::write(&path, &shuffled).unwrap();
+        let loaded = HistoryStorage::new(&path, &[7; 32]).load_history().unwrap();
+        assert_eq!(texts(&loaded), ["r0", "r2", "r3"]);
+        assert_eq!(loaded.problems, [
+            HistoryProblem::OutOfOrder { seq: 0 },
+            HistoryProblem::Duplicate { seq: 0 },
+            HistoryProblem::Missing { first: 1, last: 1 },
+        ]);
+
+        // A record from another history file doesn't open here
+        let other = dir.path().join("other.enc");
+        let other_storage = HistoryStorage::new(&other, &[7; 32]);
+        save(&other_storage, "elsewhere");
+        let other_bytes = std::fs::read(&other).unwrap();
+        let mut spliced = full.clone();
+        spliced.extend_from_slice(&other_bytes[HEADER_LEN..]);
+        std::fs::write(&path, &spliced).unwrap();
+        let loaded = HistoryStorage::new(&path, &[7; 32]).load_history().unwrap();
+        assert_eq!(texts(&loaded), ["r0", "r1", "r2", "r3"]);
+        assert_eq!(loaded.problems, [HistoryProblem::TornTail { offset: full.len() as u64 }]);
+    }
+
+    #[test]
+    fn test_old_history_file_is_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.enc");
+        let key = [7; 32];
+        let mut old = Vec::new();
+        for text in ["before", "the upgrade"] {
+            let plaintext = rmp_serde::to_vec(&PlainMessage::new("me".to_string(), text.to_string())).unwrap();
+            let (nonce, ciphertext) = encrypt_message(&key, &plaintext).unwrap();
+            old.extend_from_slice(&((nonce.len() + ciphertext.len()) as u32).to_le_bytes());
+            old.extend(nonce);
+            old.extend(ciphertext);
+        }
+        std::fs::write(&path, &old).unwrap();
+
+        let storage = HistoryStorage::new(&path, &key);
+        let loaded = storage.load_history().unwrap();
+        assert!(loaded.migrated);
+        assert_eq!(texts(&loaded), ["before", "the upgrade"]);
+        assert!(std::fs::read(&path).unwrap().starts_with(MAGIC));
+        assert_eq!(std::fs::read(dir.path().join("history.enc.bak")).unwrap(), old);
+
+        save(&storage, "after");
+        let loaded = HistoryStorage::new(&path, &key).load_history().unwrap();
+        assert!(!loaded.migrated);
+        assert_eq!(texts(&loaded), ["before", "the upgrade", "after"]);
+        assert!(loaded.problems.is_empty());
+    }
+
+    #[test]
+    fn test_a_history_file_the_key_cant_open_is_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.enc");
+        let storage = HistoryStorage::new(&path, &[7; 32]);
+        save(&storage, "one");
+        save(&storage, "two");
+        let before = std::fs::read(&path).unwrap();
+
+        // Every record failing to open isn't a torn tail to cut off
+        let wrong = HistoryStorage::new(&path, &[8; 32]);
+        assert!(wrong.load_history().is_err());
+        assert!(wrong.save_message(&PlainMessage::new("me".to_string(), "three".to_string())).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        assert_eq!(texts(&storage.load_history().unwrap()), ["one", "two"]);
+    }
+
+    #[test]
+    fn test_an_old_file_the_key_cant_open_isnt_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.enc");
+        let plaintext = rmp_serde::to_vec(&PlainMessage::new("me".to_string(), "old".to_string())).unwrap();
+        let (nonce, ciphertext) = encrypt_message(&[7; 32], &plaintext).unwrap();
+        let mut old = ((nonce.len() + ciphertext.len()) as u32).to_le_bytes().to_vec();
+        old.extend(nonce);
+        old.extend(ciphertext);
+        std::fs::write(&path, &old).unwrap();
+
+        let wrong = HistoryStorage::new(&path, &[8; 32]);
+        assert!(wrong.load_history().is_err());
+        assert!(wrong.save_message(&PlainMessage::new("me".to_string(), "new".to_string())).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), old);
+        assert!(!dir.path().join("history.enc.bak").exists());
     }
 }
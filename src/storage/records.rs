@@ -0,0 +1,158 @@
+//! The history file's layout.
+//!
+//! A header (magic, format version, creation time, a random file id), then records,
+//! each:
+//! `mark | length | sequence | nonce | ciphertext`. The plaintext is the sequence
+//! number again followed by the message, and the header and sequence number are the
+//! AEAD's associated data, so a record can't be moved to another place in the file,
+//! or into another file, without failing to open. The sequence also rides in the clear
+//! so each record can be opened without knowing where it falls; the mark lets a reader
+//! find the next record after bytes it can't make sense of (a record torn by a crash,
+//! with later appends behind it).
+
+use anyhow::Result;
+
+use crate::crypto::{decrypt_message_with_ad, encrypt_message_with_ad};
+use crate::protocol::PlainMessage;
+
+/// Start of a history file (files from before it are bare records)
+pub(super) const MAGIC: &[u8; 8] = b"wsphist\0";
+pub(super) const VERSION: u8 = 1;
+/// Header: magic, version, the creation time (unix seconds, little-endian), then
+/// random bytes that tell this file from one created the same second
+pub(super) const HEADER_LEN: usize = MAGIC.len() + 1 + 8 + 8;
+/// Start of every record
+const MARK: &[u8; 4] = b"wspR";
+const NONCE_LEN: usize = 12;
+/// Sequence, nonce and the AEAD tag around an empty plaintext
+const MIN_BODY: usize = 8 + NONCE_LEN + 16;
+/// No message comes near this; a length past it is damage
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+pub(super) type Header = [u8; HEADER_LEN];
+
+pub(super) fn header(created_at: i64) -> Header {
+    let mut header = [0u8; HEADER_LEN];
+    header[..MAGIC.len()].copy_from_slice(MAGIC);
+    header[MAGIC.len()] = VERSION;
+    header[MAGIC.len() + 1..MAGIC.len() + 9].copy_from_slice(&created_at.to_le_bytes());
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut header[MAGIC.len() + 9..]);
+    header
+}
+
+fn associated_data(header: &Header, seq: u64) -> Vec<u8> {
+    let mut ad = header.to_vec();
+    ad.extend_from_slice(&seq.to_le_bytes());
+    ad
+}
+
+/// `msg` sealed as record number `seq` of the file with `header`
+pub(super) fn seal(key: &[u8], header: &Header, seq: u64, msg: &PlainMessage) -> Result<Vec<u8>> {
+    let mut plaintext = seq.to_le_bytes().to_vec();
+    plaintext.extend(rmp_serde::to_vec(msg)?);
+    let (nonce, ciphertext) = encrypt_message_with_ad(key, &plaintext, &associated_data(header, seq))?;
+
+    let body_len = 8 + nonce.len() + ciphertext.len();
+    let mut record = Vec::with_capacity(MARK.len() + 4 + body_len);
+    record.extend_from_slice(MARK);
+    record.extend_from_slice(&(body_len as u32).to_le_bytes());
+    record.extend_from_slice(&seq.to_le_bytes());
+    record.extend(nonce);
+    record.extend(ciphertext);
+    Ok(record)
+}
+
+/// The record at the start of `bytes`, and how many bytes it took; None if there
+/// isn't a whole one there that opens
+fn open(key: &[u8], header: &Header, bytes: &[u8]) -> Option<(u64, PlainMessage, usize)> {
+    let rest = bytes.strip_prefix(MARK)?;
+    let len = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+    if !(MIN_BODY..=MAX_BODY).contains(&len) {
+        return None;
+    }
+    let body = rest.get(4..4 + len)?;
+    let seq = u64::from_le_bytes(body[..8].try_into().ok()?);
+    let (nonce, ciphertext) = body[8..].split_at(NONCE_LEN);
+    let plaintext = decrypt_message_with_ad(key, nonce, ciphertext, &associated_data(header, seq)).ok()?;
+    if plaintext.get(..8)? != seq.to_le_bytes() {
+        return None;
+    }
+    let msg = rmp_serde::from_slice(&plaintext[8..]).ok()?;
+    Some((seq, msg, MARK.len() + 4 + len))
+}
+
+/// Whether `bytes` can only be the start of a record: too short for a whole one, or
+/// a mark whose length runs past the end
+pub(super) fn cut_short(bytes: &[u8]) -> bool {
+    if bytes.len() < MARK.len() + 4 + MIN_BODY {
+        return true;
+    }
+    let Some(rest) = bytes.strip_prefix(MARK) else {
+        return false;
+    };
+    let len = u32::from_le_bytes(rest[..4].try_into().unwrap_or_default()) as usize;
+    4 + len > rest.len()
+}
+
+/// What reading the records found, in file order
+#[derive(Debug)]
+pub(super) enum Scanned {
+    Record { seq: u64, msg: Box<PlainMessage> },
+    /// Bytes from `offset` that aren't a record we could open (to the end of the file
+    /// if `len` reaches it)
+    Unreadable { offset: usize, len: usize },
+}
+
+/// Every record in `file` (header included), stepping over anything unreadable to
+/// the next record that opens
+pub(super) fn scan(key: &[u8], header: &Header, file: &[u8]) -> Vec<Scanned> {
+    let mut found = Vec::new();
+    let mut pos = HEADER_LEN;
+    let mut unreadable_from = None;
+    while pos < file.len() {
+        if let Some((seq, msg, used)) = open(key, header, &file[pos..]) {
+            if let Some(offset) = unreadable_from.take() {
+                found.push(Scanned::Unreadable { offset, len: pos - offset });
+            }
+            found.push(Scanned::Record { seq, msg: Box::new(msg) });
+            pos += used;
+            continue;
+        }
+        unreadable_from.get_or_insert(pos);
+        pos = file[pos + 1..].windows(MARK.len())
+            .position(|w| w == MARK)
+            .map_or(file.len(), |at| pos + 1 + at);
+    }
+    if let Some(offset) = unreadable_from {
+        found.push(Scanned::Unreadable { offset, len: file.len() - offset });
+    }
+    found
+}
+
+/// Messages in a file from before the header: bare `length | nonce | ciphertext`
+/// records, up to the first that's cut short. Also how many records wouldn't open.
+pub(super) fn legacy_messages(key: &[u8], file: &[u8]) -> (Vec<PlainMessage>, usize) {
+    let mut messages = Vec::new();
+    let mut unreadable = 0;
+    let mut pos = 0;
+    while let Some(len_bytes) = file.get(pos..pos + 4).and_then(|b| <[u8; 4]>::try_from(b).ok()) {
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        let Some(data) = file.get(pos + 4..pos + 4 + len) else {
+            break;
+        };
+        pos += 4 + len;
+        let msg = (data.len() >= NONCE_LEN)
+            .then(|| crate::crypto::decrypt_message(key, &data[..NONCE_LEN], &data[NONCE_LEN..]).ok())
+            .flatten()
+            .and_then(|plaintext| {
+                rmp_serde::from_slice::<PlainMessage>(&plaintext)
+                    .or_else(|_| bincode::deserialize::<PlainMessage>(&plaintext))
+                    .ok()
+            });
+        match msg {
+            Some(msg) => messages.push(msg),
+            None => unreadable += 1,
+        }
+    }
+    (messages, unreadable)
+}
@@ -0,0 +1,17 @@
+//! German
+
+use super::Key;
+
+pub(super) static WORDS: &[(Key, &str)] = &[
+    (Key::Joined, "{name} ist beigetreten"),
+    (Key::Left, "{name} ist gegangen"),
+    (Key::LeftGroup, "{name} hat die Gruppe verlassen"),
+    (Key::InDnd, "{name} möchte nicht gestört werden"),
+    (Key::InLowBandwidth, "{name} ist im Modus für geringe Bandbreite"),
+    (Key::WhyDnd, "nicht stören"),
+    (Key::WhyLowBandwidth, "geringe Bandbreite"),
+    (Key::MissedCall, "📞 Verpasster Anruf von {name} ({why}) — /callback zum Zurückrufen"),
+    (Key::GroupCallNotJoined, "📞 {name} hat einen Gruppenanruf gestartet (nicht beigetreten: {why})"),
+    (Key::UnknownCommand, "Unbekannter Befehl: /{command}"),
+    (Key::NotInGroupTab, "Wechsle zuerst in einen Gruppen-Tab"),
+];
@@ -0,0 +1,235 @@
+//! Words the user reads, by key, in their language. English is the full set; each
+//! translation is a table of templates (de.rs) and any key it lacks reads in English.
+//! Templates have named blanks (`{name}`) filled in by `text`.
+//!
+//! Notices a peer sends us ("alice has left", "bob is in do-not-disturb") travel as a
+//! [`Notice`]: a kind, which is a key here, and its blanks. The receiver words them in
+//! its own language; `content` still carries the English for clients from before.
+//!
+//! The language is picked once at start (`set_locale`, from the config or `LANG`) and
+//! everything asks `locale()` after that.
+
+mod de;
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::protocol::{HistorySync, Notice, PlainMessage};
+
+/// A language we have words for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+}
+
+impl Locale {
+    /// The language in a tag like `de`, `de-AT` or `de_DE.UTF-8` (`C` and `POSIX` are
+    /// English). None for one we have no words for.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.split(['_', '-', '.', '@']).next().unwrap_or_default().to_ascii_lowercase();
+        match language.as_str() {
+            "en" | "c" | "posix" => Some(Locale::En),
+            "de" => Some(Locale::De),
+            _ => None,
+        }
+    }
+
+    /// The language the environment asks for, by the usual precedence: `LC_ALL`,
+    /// then `LC_MESSAGES`, then `LANG`
+    pub fn from_env() -> Option<Self> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|tag| !tag.is_empty())
+            .and_then(|tag| Self::from_tag(&tag))
+    }
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// Use `locale` from now on. Only the first call counts.
+pub fn set_locale(locale: Locale) {
+    let _ = LOCALE.set(locale);
+}
+
+/// The language in use (English until `set_locale`)
+pub fn locale() -> Locale {
+    LOCALE.get().copied().unwrap_or_default()
+}
+
+/// Something to say. The ones with a `kind` can be sent to peers as a [`Notice`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// {name}: a peer our client just exchanged keys with
+    Joined,
+    /// {name}: a peer quitting
+    Left,
+    /// {name}: a member leaving a group
+    LeftGroup,
+    /// {name}: a peer who turned our call down while in do-not-disturb
+    InDnd,
+    /// {name}: a peer who turned our call down while in low-bandwidth mode
+    InLowBandwidth,
+    /// Why we didn't take a call
+    WhyDnd,
+    WhyLowBandwidth,
+    /// {name}, {why}
+    MissedCall,
+    /// {name}, {why}
+    GroupCallNotJoined,
+    /// {command}
+    UnknownCommand,
+    NotInGroupTab,
+}
+
+impl Key {
+    /// Every key, for checking translations
+    pub const ALL: [Key; 11] = [
+        Key::Joined, Key::Left, Key::LeftGroup, Key::InDnd, Key::InLowBandwidth, Key::WhyDnd,
+        Key::WhyLowBandwidth, Key::MissedCall, Key::GroupCallNotJoined, Key::UnknownCommand, Key::NotInGroupTab,
+    ];
+
+    /// What a notice of this key is called on the wire, for the ones peers send
+    pub fn kind(self) -> Option<&'static str> {
+        match self {
+            Key::Joined => Some("joined"),
+            Key::Left => Some("left"),
+            Key::LeftGroup => Some("left_group"),
+            Key::InDnd => Some("in_dnd"),
+            Key::InLowBandwidth => Some("in_low_bandwidth"),
+            _ => None,
+        }
+    }
+
+    /// The key for a notice's kind; None for kinds from newer clients
+    pub fn from_kind(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.kind() == Some(kind))
+    }
+
+    fn english(self) -> &'static str {
+        match self {
+            Key::Joined => "{name} has joined",
+            Key::Left => "{name} has left",
+            Key::LeftGroup => "{name} has left the group",
+            Key::InDnd => "{name} is in do-not-disturb",
+            Key::InLowBandwidth => "{name} is in low-bandwidth mode",
+            Key::WhyDnd => "do not disturb",
+            Key::WhyLowBandwidth => "low-bandwidth mode",
+            Key::MissedCall => "📞 Missed call from {name} ({why}) — /callback to ring back",
+            Key::GroupCallNotJoined => "📞 {name} started a group call (not joined: {why})",
+            Key::UnknownCommand => "Unknown command: /{command}",
+            Key::NotInGroupTab => "Switch to a group tab first",
+        }
+    }
+
+    /// This key's template in `locale`, or in English if that has none
+    fn template(self, locale: Locale) -> &'static str {
+        let translated = match locale {
+            Locale::En => None,
+            Locale::De => de::WORDS.iter().find(|(key, _)| *key == self).map(|(_, words)| *words),
+        };
+        translated.unwrap_or_else(|| self.english())
+    }
+}
+
+/// `key` in the language in use, its blanks filled from `params`
+pub fn text(key: Key, params: &[(&str, &str)]) -> String {
+    text_in(locale(), key, params)
+}
+
+/// `key` in `locale`. A blank with nothing to fill it is left as it is.
+pub fn text_in(locale: Locale, key: Key, params: &[(&str, &str)]) -> String {
+    let template = key.template(locale);
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    // One pass, so a parameter that happens to contain "{name}" stays as it is
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let blank = after.find('}').map(|close| &after[..close]);
+        match blank.and_then(|blank| params.iter().find(|(name, _)| *name == blank)) {
+            Some((blank, value)) => {
+                out.push_str(value);
+                rest = &after[blank.len() + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// How `notice` reads here; None if it's of a kind we don't know
+pub fn notice_text(notice: &Notice) -> Option<String> {
+    let key = Key::from_kind(&notice.kind)?;
+    let params: Vec<(&str, &str)> = notice.params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    Some(text(key, &params))
+}
+
+/// Put the notice in a message from a peer into our own words, in place. Messages
+/// without one, or with a kind we don't know, keep the content they came with.
+pub fn localize(msg: &mut PlainMessage) {
+    if let Some(text) = msg.notice.as_ref().and_then(notice_text) {
+        msg.content = text;
+    }
+    if let Some(HistorySync::Batch { ref mut messages, .. }) = msg.history {
+        messages.iter_mut().for_each(localize);
+    }
+}
+
+impl PlainMessage {
+    /// A system notice to send: `key` as a [`Notice`] for the receiver to word, with
+    /// the English for clients that can't
+    pub fn notice(sender: String, key: Key, params: &[(&str, &str)]) -> Self {
+        let notice = Notice {
+            kind: key.kind().expect("only notice keys are sent").to_string(),
+            params: params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<BTreeMap<_, _>>(),
+        };
+        Self { notice: Some(notice), ..Self::system(sender, text_in(Locale::En, key, params)) }
+    }
+
+    /// Whether this is a notice of `key`
+    pub fn is_notice(&self, key: Key) -> bool {
+        self.notice.as_ref().is_some_and(|n| Some(n.kind.as_str()) == key.kind())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notices_read_in_the_receivers_language() {
+        assert_eq!(Locale::from_tag("de_DE.UTF-8"), Some(Locale::De));
+        assert_eq!(Locale::from_tag("C"), Some(Locale::En));
+        assert_eq!(Locale::from_tag("fr_FR"), None);
+
+        // German is complete, with the same blanks as the English
+        let blanks = |s: &str| s.match_indices('{').map(|(at, _)| s[at..].split('}').next().unwrap().to_string()).collect::<Vec<_>>();
+        for key in Key::ALL {
+            let (en, de) = (key.template(Locale::En), key.template(Locale::De));
+            assert!(de::WORDS.iter().any(|(k, _)| *k == key), "no German for {:?}", key);
+            assert_eq!(blanks(en), blanks(de), "{:?}", key);
+        }
+
+        // Sent as a kind with blanks plus English; a German client words it for itself
+        let mut msg = PlainMessage::notice("a".repeat(32), Key::Left, &[("name", "{why} alice")]);
+        assert_eq!(msg.content, "{why} alice has left");
+        let notice = msg.notice.clone().unwrap();
+        assert_eq!(notice.kind, "left");
+        assert_eq!(Key::from_kind(&notice.kind), Some(Key::Left));
+        assert!(msg.is_notice(Key::Left) && !msg.is_notice(Key::Joined));
+        let params: Vec<(&str, &str)> = notice.params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(text_in(Locale::De, Key::Left, &params), "{why} alice ist gegangen");
+
+        // Kinds from newer clients keep the words they came with
+        msg.notice = Some(Notice { kind: "waved".to_string(), params: BTreeMap::new() });
+        localize(&mut msg);
+        assert_eq!(msg.content, "{why} alice has left");
+        assert_eq!(text_in(Locale::En, Key::UnknownCommand, &[]), "Unknown command: /{command}");
+    }
+}
@@ -0,0 +1,72 @@
+//! IRC-style actions: `/me waves` goes out as a chat message flagged `action`, and
+//! everywhere a message is shown with its sender it reads "· alice waves".
+
+use crate::protocol::PlainMessage;
+
+use super::state::{ChatState, Effect};
+
+/// `name: text`, or `· name text` for an action
+pub(crate) fn attributed(name: &str, text: &str, action: bool) -> String {
+    if action {
+        format!("· {} {}", name, text)
+    } else {
+        format!("{}: {}", name, text)
+    }
+}
+
+impl ChatState {
+    /// Handle /me <text> in whichever tab is open. `text` is everything after the
+    /// command, spacing kept.
+    pub(crate) fn handle_me_command(&mut self, text: &str, fx: &mut Vec<Effect>) {
+        if text.is_empty() {
+            self.status = "Usage: /me <action>, e.g. /me waves".to_string();
+            return;
+        }
+        self.send_chat(text.to_string(), true, fx);
+    }
+
+    /// The sender's name and `text` from `m`, the way the chat shows them
+    pub(crate) fn attributed_text(&self, m: &PlainMessage, text: &str) -> String {
+        let name = if m.sender == self.own_id {
+            self.display_name()
+        } else {
+            self.get_peer_display_name(&m.sender)
+        };
+        attributed(&name, text, m.action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::OutgoingMessage;
+    use crate::tui::types::{GroupInfo, Tab};
+
+    #[test]
+    fn test_me_sends_an_action_in_every_kind_of_tab() {
+        let peer = "ab".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), Some("ada".to_string()), vec![0; 32]);
+        state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members: vec![peer.clone()], join_token: None });
+        state.tabs.push(Tab::DirectMessage(peer.clone()));
+        state.tabs.push(Tab::Group("g1".to_string()));
+
+        for (index, tab) in state.tabs.clone().into_iter().enumerate() {
+            state.active_tab = index;
+            let fx = state.handle_command("/me waves  hello");
+            let sent = fx.iter().find_map(|e| match e {
+                Effect::Send(OutgoingMessage::Global(m)) => Some(m),
+                Effect::Send(OutgoingMessage::Direct { message, .. } | OutgoingMessage::Group { message, .. }) => Some(message),
+                _ => None,
+            }).unwrap();
+            assert!(sent.action && sent.content == "waves  hello", "{:?}", tab);
+            let shown = state.messages[&tab].last().unwrap();
+            assert_eq!(state.attributed_text(shown, &shown.content), "· ada waves  hello");
+        }
+
+        let fx = state.handle_command("/me");
+        assert!(fx.is_empty() && state.status.starts_with("Usage"));
+        // Plain chat keeps the colon
+        let said = PlainMessage::new(peer.clone(), "hi".to_string());
+        assert_eq!(state.attributed_text(&said, "hi"), format!("{}: hi", &peer[..12]));
+    }
+}
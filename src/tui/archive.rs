@@ -0,0 +1,188 @@
+//! Directory shares: a folder goes over the wire as one tar archive, which the receiver
+//! can keep as-is or unpack with `/accept --extract`.
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+
+/// Largest share `/send` will offer unless the config says otherwise
+pub(crate) const DEFAULT_MAX_SHARE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// What a pre-scan of a shared directory found
+#[derive(Debug, Default)]
+pub(crate) struct DirScan {
+    /// (on-disk path, path inside the archive)
+    pub files: Vec<(PathBuf, PathBuf)>,
+    /// Directories, as paths inside the archive
+    pub dirs: Vec<PathBuf>,
+    pub total_bytes: u64,
+    /// Symlinks left out of the archive
+    pub skipped_symlinks: Vec<PathBuf>,
+}
+
+impl DirScan {
+    pub fn entry_count(&self) -> u32 {
+        (self.files.len() + self.dirs.len()) as u32
+    }
+}
+
+/// Walk `root`, refusing to go past `max_bytes` of file content. Symlinks are never
+/// followed; anything that isn't a plain file or directory is skipped too.
+pub(crate) fn scan(root: &Path, max_bytes: u64) -> Result<DirScan> {
+    let mut found = DirScan::default();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        let dir = root.join(&rel);
+        let mut entries: Vec<_> = std::fs::read_dir(&dir)
+            .with_context(|| format!("Couldn't read {}", dir.display()))?
+            .collect::<std::io::Result<_>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let rel_path = rel.join(entry.file_name());
+            let kind = entry.file_type()?;
+            if kind.is_symlink() {
+                found.skipped_symlinks.push(rel_path);
+            } else if kind.is_dir() {
+                found.dirs.push(rel_path.clone());
+                pending.push(rel_path);
+            } else if kind.is_file() {
+                found.total_bytes += entry.metadata()?.len();
+                if found.total_bytes > max_bytes {
+                    bail!("{} is over the {} share limit", root.display(), super::ChatState::format_size(max_bytes));
+                }
+                found.files.push((entry.path(), rel_path));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Pack a scanned directory into a tar archive
+pub(crate) fn build(scan: &DirScan) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    builder.mode(tar::HeaderMode::Deterministic);
+    builder.follow_symlinks(false);
+    for dir in &scan.dirs {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        builder.append_data(&mut header, dir, std::io::empty())?;
+    }
+    for (path, rel) in &scan.files {
+        let mut file = File::open(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        builder.append_file(rel, &mut file)?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// An entry name as a path under the extraction directory, or None if it could land
+/// anywhere else (absolute, `..`, drive prefixes)
+fn contained(name: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => out.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!out.as_os_str().is_empty()).then_some(out)
+}
+
+/// Unpack `data` into `dest` (created if needed). Only plain files and directories are
+/// written; links, devices and entries that would escape `dest` are skipped.
+/// Returns (entries written, entries skipped).
+pub(crate) fn extract(data: &[u8], dest: &Path) -> Result<(usize, usize)> {
+    std::fs::create_dir_all(dest).with_context(|| format!("Couldn't create {}", dest.display()))?;
+    let mut archive = tar::Archive::new(data);
+    let (mut written, mut skipped) = (0, 0);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(rel) = contained(&entry.path()?) else {
+            skipped += 1;
+            continue;
+        };
+        let target = dest.join(rel);
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => std::fs::create_dir_all(&target)?,
+            tar::EntryType::Regular => {
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let mut file = File::create(&target).with_context(|| format!("Couldn't write {}", target.display()))?;
+                std::io::copy(&mut entry, &mut file)?;
+            }
+            _ => {
+                skipped += 1;
+                continue;
+            }
+        }
+        written += 1;
+    }
+    Ok((written, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub/deeper")).unwrap();
+        std::fs::write(dir.path().join("top.txt"), b"top").unwrap();
+        std::fs::write(dir.path().join("sub/deeper/leaf.txt"), b"leaf").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/etc/passwd", dir.path().join("sub/link")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_round_trip_skips_symlinks() {
+        let src = sample_tree();
+        let found = scan(src.path(), DEFAULT_MAX_SHARE_BYTES).unwrap();
+        assert_eq!(found.total_bytes, 7);
+        assert_eq!(found.entry_count(), 4);
+        #[cfg(unix)]
+        assert_eq!(found.skipped_symlinks, vec![PathBuf::from("sub/link")]);
+
+        let data = build(&found).unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        assert_eq!(extract(&data, dest.path()).unwrap(), (4, 0));
+        assert_eq!(std::fs::read(dest.path().join("top.txt")).unwrap(), b"top");
+        assert_eq!(std::fs::read(dest.path().join("sub/deeper/leaf.txt")).unwrap(), b"leaf");
+        assert!(!dest.path().join("sub/link").exists());
+    }
+
+    #[test]
+    fn test_scan_enforces_size_cap() {
+        let src = sample_tree();
+        assert!(scan(src.path(), 5).unwrap_err().to_string().contains("share limit"));
+    }
+
+    #[test]
+    fn test_extract_refuses_escaping_entries() {
+        // tar::Builder won't write these names, so the headers are made by hand
+        let mut builder = tar::Builder::new(Vec::new());
+        for name in ["../escape.txt", "/abs.txt", "ok/../../escape2.txt", "fine.txt"] {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(2);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, &b"hi"[..]).unwrap();
+        }
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_size(0);
+        builder.append_link(&mut link, "evil", "/etc").unwrap();
+        let data = builder.into_inner().unwrap();
+
+        let outer = tempfile::tempdir().unwrap();
+        let dest = outer.path().join("dest");
+        assert_eq!(extract(&data, &dest).unwrap(), (1, 4));
+        assert!(dest.join("fine.txt").exists());
+        assert!(!outer.path().join("escape.txt").exists() && !outer.path().join("escape2.txt").exists());
+        assert!(std::fs::symlink_metadata(dest.join("evil")).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! Presence: /away and /back, going away by itself after a while without a keypress,
+//! the optional auto-reply, and which peers are away.
+
+use std::time::Instant;
+
+use crate::client::OutgoingMessage;
+use crate::protocol::{PlainMessage, Presence, MAX_AWAY_MESSAGE_CHARS};
+
+use super::state::{ChatState, Effect};
+use super::types::Tab;
+
+/// We're away from the keyboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Away {
+    pub message: Option<String>,
+    /// Set by the idle timer rather than /away
+    pub auto: bool,
+}
+
+impl ChatState {
+    /// Handle /away [message]
+    pub(crate) fn handle_away_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        let message: String = args.join(" ").chars().take(MAX_AWAY_MESSAGE_CHARS).collect();
+        let message = (!message.is_empty()).then_some(message);
+        self.status = match message {
+            Some(ref m) => format!("💤 You're away: {} (any key brings you back)", m),
+            None => "💤 You're away (any key brings you back)".to_string(),
+        };
+        self.set_away(Some(Away { message, auto: false }), fx);
+    }
+
+    /// Handle /back
+    pub(crate) fn handle_back_command(&mut self, fx: &mut Vec<Effect>) {
+        if self.away.is_none() {
+            self.status = "You're not away".to_string();
+            return;
+        }
+        self.set_away(None, fx);
+        self.status = "👋 You're back".to_string();
+    }
+
+    /// Change our presence and tell every peer
+    fn set_away(&mut self, away: Option<Away>, fx: &mut Vec<Effect>) {
+        let presence = match away {
+            Some(ref a) => Presence::Away(a.message.clone()),
+            None => Presence::Active,
+        };
+        self.away = away;
+        self.away_replied.clear();
+        fx.push(Effect::Send(OutgoingMessage::Global(PlainMessage::presence(self.own_id.clone(), &presence))));
+    }
+
+    /// A key was pressed: restart the idle clock, and come back if we were away
+    pub(crate) fn note_activity(&mut self) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        self.last_input = Instant::now();
+        if self.away.is_some() {
+            self.set_away(None, &mut fx);
+            self.status = "👋 Welcome back".to_string();
+        }
+        fx
+    }
+
+    /// Go away once nothing has been typed for `away_after` (run from housekeeping)
+    pub(crate) fn check_idle(&mut self) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        let idle = self.away_after.is_some_and(|after| self.last_input.elapsed() >= after);
+        if idle && self.away.is_none() {
+            self.set_away(Some(Away { message: None, auto: true }), &mut fx);
+            self.status = "💤 Away (idle) — any key brings you back".to_string();
+        }
+        fx
+    }
+
+    /// A peer said they're away or back
+    pub(crate) fn handle_presence(&mut self, sender: &str, presence: &str) {
+        match Presence::parse(presence) {
+            Some(Presence::Away(message)) => {
+                self.peer_away.insert(sender.to_string(), message);
+            }
+            Some(Presence::Active) => {
+                self.peer_away.remove(sender);
+            }
+            None => {}
+        }
+    }
+
+    /// Peers who connected after we went away haven't heard; everyone else has
+    pub(crate) fn announce_away_to(&self, new_peers: &[String], fx: &mut Vec<Effect>) {
+        let Some(ref away) = self.away else {
+            return;
+        };
+        let presence = Presence::Away(away.message.clone());
+        for peer_id in new_peers {
+            fx.push(Effect::Send(OutgoingMessage::Direct {
+                target_id: peer_id.clone(),
+                message: PlainMessage::presence(self.own_id.clone(), &presence),
+            }));
+        }
+    }
+
+    /// Answer a DM that arrived while we're away with the configured reply, once per
+    /// peer per absence (so two away users can't bounce replies forever)
+    pub(crate) fn auto_reply(&mut self, from: &str, fx: &mut Vec<Effect>) {
+        let Some(ref text) = self.away_reply else {
+            return;
+        };
+        if self.away.is_none() || !self.away_replied.insert(from.to_string()) {
+            return;
+        }
+        let reply = PlainMessage::direct(self.own_id.clone(), text.clone());
+        let tab = Tab::DirectMessage(from.to_string());
+        self.push_message(tab, reply.clone());
+        fx.push(Effect::Send(OutgoingMessage::Direct { target_id: from.to_string(), message: reply }));
+    }
+
+    /// "alice is away: grabbing lunch", while that peer's DM tab is open
+    pub(crate) fn away_line(&self) -> Option<String> {
+        let Tab::DirectMessage(peer_id) = &self.tabs[self.active_tab] else {
+            return None;
+        };
+        let message = self.peer_away.get(peer_id)?;
+        let name = self.get_peer_display_name(peer_id);
+        Some(match message {
+            Some(m) => format!("{} is away: {}", name, m),
+            None => format!("{} is away", name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use std::time::Duration;
+
+    fn presence_sent(fx: &[Effect]) -> Vec<String> {
+        fx.iter().filter_map(|e| match e {
+            Effect::Send(OutgoingMessage::Global(m) | OutgoingMessage::Direct { message: m, .. }) => m.presence.clone(),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_away_back_and_idle() {
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let fx = state.handle_command("/away grabbing lunch");
+        assert_eq!(presence_sent(&fx), vec!["away:grabbing lunch"]);
+        assert_eq!(state.away.as_ref().and_then(|a| a.message.as_deref()), Some("grabbing lunch"));
+
+        // Any keypress brings us back
+        assert_eq!(presence_sent(&state.note_activity()), vec!["active"]);
+        assert!(state.note_activity().is_empty());
+
+        state.away_after = Some(Duration::from_secs(600));
+        assert!(state.check_idle().is_empty());
+        state.away_after = Some(Duration::ZERO);
+        assert_eq!(presence_sent(&state.check_idle()), vec!["away"]);
+        assert!(state.away.as_ref().is_some_and(|a| a.auto));
+        assert!(state.check_idle().is_empty());
+    }
+
+    #[test]
+    fn test_peer_presence_and_auto_reply() {
+        let peer = "peer".repeat(8);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.apply_peer_updates(vec![PeerUpdate::Added(peer.clone(), PeerDisplay { nickname: Some("pat".to_string()), public_key: vec![1; 32] })]);
+        state.ingest_message(PlainMessage::presence(peer.clone(), &Presence::Away(Some("lunch".to_string()))));
+        state.handle_command(&format!("/dm {}", peer));
+        assert_eq!(state.away_line().as_deref(), Some("pat is away: lunch"));
+        state.ingest_message(PlainMessage::presence(peer.clone(), &Presence::Active));
+        assert_eq!(state.away_line(), None);
+
+        // Auto-reply only while away, and once per peer
+        state.away_reply = Some("back soon".to_string());
+        let dm = || PlainMessage::direct(peer.clone(), "you there?".to_string());
+        assert!(state.ingest_message(dm()).is_empty());
+        state.handle_command("/away");
+        let fx = state.ingest_message(dm());
+        assert!(matches!(&fx[..], [Effect::Send(OutgoingMessage::Direct { message, .. })] if message.content == "back soon"));
+        assert!(state.ingest_message(dm()).is_empty());
+    }
+}
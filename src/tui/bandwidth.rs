@@ -0,0 +1,137 @@
+//! Bandwidth for metered connections: the live ↑/↓ rate in the header while a call or
+//! file transfer runs, a cap on how fast files go out (`file_rate_kbps` in the config),
+//! and a warning once the session has moved more than `session_warn_mb`. Voice is only
+//! ever counted, never slowed down.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::state::ChatState;
+
+/// Token bucket pacing outgoing file chunks, shared by every transfer
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+    bytes_per_sec: u64,
+    /// Bytes we may send now (negative: owed), and when that was worked out
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimit {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        Self { bytes_per_sec, bucket: Mutex::new((bytes_per_sec as f64, Instant::now())) }
+    }
+
+    /// Spend `bytes`, returning how long to wait before sending them
+    fn reserve(&self, bytes: usize) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let (ref mut tokens, ref mut at) = *bucket;
+        let rate = self.bytes_per_sec as f64;
+        let now = Instant::now();
+        // At most a second's worth saves up while nothing is sent
+        *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * rate).min(rate) - bytes as f64;
+        *at = now;
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / rate)
+        }
+    }
+
+    /// Wait until `bytes` more may go out
+    pub async fn take(&self, bytes: usize) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Bandwidth {
+    /// Bytes sent and received at the last reading, and when
+    last: Option<(u64, u64, Instant)>,
+    /// Bytes per second up and down since the reading before
+    pub rate: (u64, u64),
+    /// Warn once the session has moved this many bytes, both ways together
+    pub warn_after: Option<u64>,
+    warned: bool,
+}
+
+impl ChatState {
+    /// Take a reading of the client's byte totals (run from housekeeping).
+    /// Returns true if the rate shown changed.
+    pub(crate) fn check_bandwidth(&mut self, (sent, received): (u64, u64)) -> bool {
+        let now = Instant::now();
+        let before = self.bandwidth.rate;
+        if let Some((last_sent, last_received, at)) = self.bandwidth.last {
+            let secs = now.duration_since(at).as_secs_f64().max(0.001);
+            let per_sec = |bytes: u64| (bytes as f64 / secs) as u64;
+            self.bandwidth.rate = (per_sec(sent.saturating_sub(last_sent)), per_sec(received.saturating_sub(last_received)));
+        }
+        self.bandwidth.last = Some((sent, received, now));
+
+        let total = sent + received;
+        if let Some(limit) = self.bandwidth.warn_after.filter(|&limit| !self.bandwidth.warned && total >= limit) {
+            self.bandwidth.warned = true;
+            let text = format!(
+                "⚠️  This session has used {} of data (warning set at {})",
+                Self::format_size(total),
+                Self::format_size(limit)
+            );
+            let tab = self.tabs[self.active_tab].clone();
+            self.add_system_message(&tab, text.clone());
+            self.status = text;
+        }
+        self.bandwidth.rate != before
+    }
+
+    /// "↑12KB/s ↓340KB/s" for the header, while a call or file transfer runs
+    pub(crate) fn bandwidth_label(&self, streaming: bool) -> Option<String> {
+        if self.active_call.is_none() && self.active_transfers.is_empty() && !streaming {
+            return None;
+        }
+        let (up, down) = self.bandwidth.rate;
+        Some(format!("↑{}/s ↓{}/s", compact_size(up), compact_size(down)))
+    }
+}
+
+/// A byte count in as few characters as reads well
+fn compact_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{}B", bytes),
+        1024..=1_048_575 => format!("{}KB", bytes / 1024),
+        _ => format!("{:.1}MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tui::types::Tab;
+
+    #[test]
+    fn test_rate_limit_paces_chunks() {
+        let limit = RateLimit::new(1000);
+        // A second's worth goes straight out, then the rest waits its turn
+        assert_eq!(limit.reserve(1000), Duration::ZERO);
+        let wait = limit.reserve(500);
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500), "{:?}", wait);
+    }
+
+    #[test]
+    fn test_session_warning_fires_once() {
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.bandwidth.warn_after = Some(1000);
+        state.check_bandwidth((100, 100));
+        assert!(!state.bandwidth.warned);
+        assert_eq!(state.bandwidth_label(false), None);
+        assert!(state.bandwidth_label(true).is_some_and(|l| l.starts_with('↑')));
+
+        state.check_bandwidth((600, 500));
+        assert!(state.status.starts_with("⚠️  This session has used"), "{}", state.status);
+        state.check_bandwidth((6000, 5000));
+        let warnings = state.messages[&Tab::Global].iter().filter(|m| m.content.contains("has used")).count();
+        assert_eq!(warnings, 1);
+    }
+}
@@ -0,0 +1,194 @@
+//! Per-call voice keys. Whoever places a call sends a fresh salt with the request and
+//! everyone who accepts answers with one of their own; each pair of callers has the
+//! client derive their key from both salts, and hanging up wipes it.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::client::OutgoingMessage;
+use crate::protocol::{CallSalt, PlainMessage};
+
+use super::state::{ChatState, Effect};
+
+/// Key agreement for the call we're placing, being offered, or in
+#[derive(Debug, Clone)]
+pub(crate) struct CallKeys {
+    pub call_id: String,
+    /// Who placed the call: their salt comes first in every key they share
+    pub initiator: String,
+    /// Our salt, once we've placed or accepted the call
+    pub own: Option<CallSalt>,
+    /// Salts from the others in the call
+    pub peers: HashMap<String, CallSalt>,
+    /// Peers the client holds a key for
+    pub keyed: HashSet<String>,
+}
+
+impl CallKeys {
+    /// The keys for the call `msg` offers us; None if the request came without a salt
+    pub(crate) fn offered(msg: &PlainMessage) -> Option<Self> {
+        let salt = msg.call_salt.as_ref()?;
+        Some(Self {
+            call_id: salt.call_id.clone(),
+            initiator: msg.sender.clone(),
+            own: None,
+            peers: HashMap::from([(msg.sender.clone(), salt.clone())]),
+            keyed: HashSet::new(),
+        })
+    }
+}
+
+impl ChatState {
+    /// We're placing a call: our salt for the request
+    pub(crate) fn place_call_keys(&mut self) -> CallSalt {
+        let own = CallSalt::generate(PlainMessage::generate_id());
+        self.call_keys = Some(CallKeys {
+            call_id: own.call_id.clone(),
+            initiator: self.own_id.clone(),
+            own: Some(own.clone()),
+            peers: HashMap::new(),
+            keyed: HashSet::new(),
+        });
+        own
+    }
+
+    /// A call is being offered to us. False if the request came without a salt.
+    pub(crate) fn offered_call_keys(&mut self, msg: &PlainMessage) -> bool {
+        self.call_keys = CallKeys::offered(msg);
+        self.call_keys.is_some()
+    }
+
+    /// We're accepting the offered call: key it with everyone already in, and return
+    /// our salt for the accept
+    pub(crate) fn accept_call_keys(&mut self, fx: &mut Vec<Effect>) -> Option<CallSalt> {
+        let keys = self.call_keys.as_mut()?;
+        let own = CallSalt::generate(keys.call_id.clone());
+        keys.own = Some(own.clone());
+        let peers: Vec<String> = keys.peers.keys().cloned().collect();
+        for peer_id in peers {
+            self.key_call_peer(&peer_id, fx);
+        }
+        Some(own)
+    }
+
+    /// Someone accepted the call, with their salt. Keyed right away if we're in it;
+    /// otherwise kept for when we accept. False if it isn't for our call.
+    pub(crate) fn note_call_salt(&mut self, msg: &PlainMessage, fx: &mut Vec<Effect>) -> bool {
+        let (Some(keys), Some(salt)) = (self.call_keys.as_mut(), msg.call_salt.as_ref()) else {
+            return false;
+        };
+        if salt.call_id != keys.call_id {
+            return false;
+        }
+        keys.peers.insert(msg.sender.clone(), salt.clone());
+        if keys.own.is_some() {
+            self.key_call_peer(&msg.sender, fx);
+        }
+        true
+    }
+
+    /// Have the client derive the key we share with `peer_id` for this call
+    fn key_call_peer(&mut self, peer_id: &str, fx: &mut Vec<Effect>) {
+        let Some(keys) = self.call_keys.as_mut() else {
+            return;
+        };
+        let (Some(own), Some(theirs)) = (keys.own.clone(), keys.peers.get(peer_id).cloned()) else {
+            return;
+        };
+        // The caller's salt goes first; between two who joined, the lower session id's
+        let we_lead = keys.initiator == self.own_id || (keys.initiator != peer_id && *self.own_id < *peer_id);
+        let salts = if we_lead { (own, theirs) } else { (theirs, own) };
+        keys.keyed.insert(peer_id.to_string());
+        fx.push(Effect::Send(OutgoingMessage::CallKey { peer_id: peer_id.to_string(), salts: Some(salts) }));
+    }
+
+    /// `peer_id` left the call: wipe the key we had with them
+    pub(crate) fn drop_call_key(&mut self, peer_id: &str, fx: &mut Vec<Effect>) {
+        let Some(keys) = self.call_keys.as_mut() else {
+            return;
+        };
+        keys.peers.remove(peer_id);
+        if keys.keyed.remove(peer_id) {
+            fx.push(Effect::Send(OutgoingMessage::CallKey { peer_id: peer_id.to_string(), salts: None }));
+        }
+    }
+
+    /// Whether `call_id` is the call we're in now
+    pub(crate) fn is_current_call(&self, call_id: &str) -> bool {
+        self.active_call.is_some() && self.call_keys.as_ref().is_some_and(|keys| keys.call_id == call_id)
+    }
+
+    /// The call is over (or was never taken): wipe every key it had
+    pub(crate) fn end_call_keys(&mut self, fx: &mut Vec<Effect>) {
+        let Some(keys) = self.call_keys.take() else {
+            return;
+        };
+        let mut keyed: Vec<String> = keys.keyed.into_iter().collect();
+        keyed.sort();
+        for peer_id in keyed {
+            fx.push(Effect::Send(OutgoingMessage::CallKey { peer_id, salts: None }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_keys(fx: &[Effect]) -> Vec<(&str, Option<&(CallSalt, CallSalt)>)> {
+        fx.iter().filter_map(|e| match e {
+            Effect::Send(OutgoingMessage::CallKey { peer_id, salts }) => Some((peer_id.as_str(), salts.as_ref())),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_group_call_keys_every_pair() {
+        let (alice, bob, me) = ("a".repeat(32), "b".repeat(32), "c".repeat(32));
+        let mut state = ChatState::new(me.clone(), None, vec![0; 32]);
+        let group = |mut msg: PlainMessage| {
+            msg.group_id = Some("g1".to_string());
+            msg
+        };
+
+        // Alice calls the group, Bob answers before we do
+        let alice_salt = CallSalt::generate("call1".to_string());
+        state.ingest_message(group(PlainMessage::call_request(alice.clone(), alice_salt.clone())));
+        let bob_salt = CallSalt::generate("call1".to_string());
+        let accept = |from: &str, salt: &CallSalt| group(PlainMessage {
+            call_salt: Some(salt.clone()),
+            ..PlainMessage::call_accept(from.to_string(), true)
+        });
+        assert!(call_keys(&state.ingest_message(accept(&bob, &bob_salt))).is_empty());
+
+        // Joining keys us with both: Alice's salt first, then the lower id of Bob and us
+        let fx = state.handle_command("/accept-call");
+        let keys = call_keys(&fx);
+        let own = state.call_keys.as_ref().unwrap().own.clone().unwrap();
+        assert_eq!(own.call_id, "call1");
+        let mut keys: Vec<_> = keys.iter().map(|(p, s)| (*p, s.cloned().unwrap())).collect();
+        keys.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(keys, vec![
+            (alice.as_str(), (alice_salt, own.clone())),
+            (bob.as_str(), (bob_salt, own)),
+        ]);
+
+        // A salt for some other call is ignored; Bob leaving wipes only his key
+        let stray = CallSalt::generate("call2".to_string());
+        assert!(call_keys(&state.ingest_message(accept(&"d".repeat(32), &stray))).is_empty());
+        let fx = state.ingest_message(group(PlainMessage::call_hangup(bob.clone())));
+        assert_eq!(call_keys(&fx), vec![(bob.as_str(), None)]);
+
+        assert!(state.is_current_call("call1"));
+        let fx = state.handle_command("/hangup");
+        assert_eq!(call_keys(&fx), vec![(alice.as_str(), None)]);
+        assert!(state.call_keys.is_none());
+        assert!(!state.is_current_call("call1"));
+
+        // Straight into the next call: audio still queued from the last one isn't played
+        state.ingest_message(group(PlainMessage::call_request(alice.clone(), CallSalt::generate("call2".to_string()))));
+        assert!(!state.is_current_call("call2"));
+        state.handle_command("/accept-call");
+        assert!(state.is_current_call("call2"));
+        assert!(!state.is_current_call("call1"));
+    }
+}
@@ -1,30 +1,39 @@
-use tokio::sync::mpsc;
+use std::collections::HashMap;
 
-use crate::audio::AudioPipeline;
+use crate::audio::AudioSupport;
 use crate::client::OutgoingMessage;
-use crate::protocol::PlainMessage;
+use crate::protocol::{ChatEvent, PlainMessage};
+use crate::strings::{self, Key};
 
 use super::helpers::format_duration;
+use super::mic::MicControls;
+use super::participants::Participant;
+use super::sounds::SoundEvent;
 use super::types::{CallState, CallType, Tab};
+use super::state::{ChatState, Effect};
 use super::ChatUI;
 
-impl ChatUI {
-    pub(crate) fn handle_call_command(&mut self, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+impl ChatState {
+    pub(crate) fn handle_call_command(&mut self, fx: &mut Vec<Effect>) {
         let current_tab = self.tabs[self.active_tab].clone();
 
         if self.active_call.is_some() {
             self.status = "Already in a call. Use /hangup first.".to_string();
             return;
         }
+        if !self.calls_available() {
+            return;
+        }
 
         match &current_tab {
             Tab::DirectMessage(peer_id) => {
                 let peer_id = peer_id.clone();
-                let call_req = PlainMessage::call_request(self.own_id.clone());
-                let _ = msg_tx.send(OutgoingMessage::Direct {
+                let salt = self.place_call_keys();
+                let call_req = PlainMessage::call_request(self.own_id.clone(), salt);
+                fx.push(Effect::Send(OutgoingMessage::Direct {
                     target_id: peer_id.clone(),
                     message: call_req,
-                });
+                }));
 
                 let peer_name = self.get_peer_display_name(&peer_id);
                 self.status = format!("📞 Calling {}...", peer_name);
@@ -41,18 +50,19 @@ impl ChatUI {
                     let group_name = group.name.clone();
                     let member_ids = group.members.clone();
 
-                    let mut call_req = PlainMessage::call_request(self.own_id.clone());
+                    let salt = self.place_call_keys();
+                    let mut call_req = PlainMessage::call_request(self.own_id.clone(), salt);
                     call_req.group_id = Some(group_id.clone());
-                    let _ = msg_tx.send(OutgoingMessage::Group {
+                    fx.push(Effect::Send(OutgoingMessage::Group {
                         group_id: group_id.clone(),
                         member_ids,
                         message: call_req,
-                    });
+                    }));
 
-                    self.start_audio_call_group(group_id.clone());
+                    self.start_audio_call_group(group_id.clone(), fx);
 
                     self.status = format!("📞 Starting group call in {}...", group_name);
-                    self.add_system_message(&current_tab, format!("📞 Starting group call in {}", group_name));
+                    self.add_event_message(&current_tab, ChatEvent::CallStarted, format!("📞 Starting group call in {}", group_name));
                 } else {
                     self.status = "Group not found".to_string();
                 }
@@ -63,54 +73,102 @@ impl ChatUI {
         }
     }
 
-    pub(crate) fn handle_accept_call_command(&mut self, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+    pub(crate) fn handle_accept_call_command(&mut self, fx: &mut Vec<Effect>) {
         if self.active_call.is_some() {
             self.status = "Already in a call. Use /hangup first.".to_string();
             return;
         }
+        if !self.calls_available() {
+            return;
+        }
 
-        // Check for pending group call first, then DM call
-        if let Some((group_id, _initiator_id)) = self.pending_group_call.take() {
+        // A call left waiting when the one before it ended, then a pending group call,
+        // then a DM call. Keys follow the accept out, then audio starts.
+        let mut key_fx = Vec::new();
+        if let Some(keys) = self.call_waiting.take() {
+            let caller = keys.initiator.clone();
+            self.call_keys = Some(keys);
+            self.answer_direct_call(caller, fx);
+        } else if let Some((group_id, _initiator_id)) = self.pending_group_call.take() {
+            let salt = self.accept_call_keys(&mut key_fx);
             if let Some(group) = self.groups.get(&group_id) {
                 let member_ids = group.members.clone();
                 let mut accept_msg = PlainMessage::call_accept(self.own_id.clone(), true);
                 accept_msg.group_id = Some(group_id.clone());
-                let _ = msg_tx.send(OutgoingMessage::Group {
+                accept_msg.call_salt = salt;
+                fx.push(Effect::Send(OutgoingMessage::Group {
                     group_id: group_id.clone(),
                     member_ids,
                     message: accept_msg,
-                });
+                }));
             }
+            fx.append(&mut key_fx);
 
-            self.start_audio_call_group(group_id.clone());
+            self.start_audio_call_group(group_id.clone(), fx);
 
             let group_name = self.group_name(&group_id);
             let group_tab = Tab::Group(group_id);
-            self.add_system_message(&group_tab, format!("🔊 Joined group call in {}", group_name));
+            self.add_event_message(&group_tab, ChatEvent::CallStarted, format!("🔊 Joined group call in {}", group_name));
         } else if let Some(peer_id) = self.pending_call_from.take() {
-            let accept_msg = PlainMessage::call_accept(self.own_id.clone(), true);
-            let _ = msg_tx.send(OutgoingMessage::Direct {
-                target_id: peer_id.clone(),
-                message: accept_msg,
-            });
-
-            self.start_audio_call(peer_id.clone());
+            self.answer_direct_call(peer_id, fx);
         } else {
             self.status = "No incoming call to accept.".to_string();
         }
     }
 
-    pub(crate) fn handle_reject_call_command(&mut self, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+    /// Accept the direct call `peer_id` is offering (its keys are in `call_keys`)
+    pub(crate) fn answer_direct_call(&mut self, peer_id: String, fx: &mut Vec<Effect>) {
+        let mut key_fx = Vec::new();
+        let mut accept_msg = PlainMessage::call_accept(self.own_id.clone(), true);
+        accept_msg.call_salt = self.accept_call_keys(&mut key_fx);
+        fx.push(Effect::Send(OutgoingMessage::Direct {
+            target_id: peer_id.clone(),
+            message: accept_msg,
+        }));
+        fx.append(&mut key_fx);
+
+        self.start_audio_call(peer_id, fx);
+    }
+
+    /// Handle /callback: call whoever we last missed a call from, in their DM tab
+    pub(crate) fn handle_callback_command(&mut self, fx: &mut Vec<Effect>) {
+        let Some(peer_id) = self.missed_call_from.clone() else {
+            self.status = "No missed call to return".to_string();
+            return;
+        };
+        if self.active_call.is_some() {
+            self.status = "Already in a call. Use /hangup first.".to_string();
+            return;
+        }
+        if !self.calls_available() {
+            return;
+        }
+        self.missed_call_from = None;
+        let dm_tab = Tab::DirectMessage(peer_id);
+        self.ensure_tab(&dm_tab);
+        if let Some(idx) = self.tabs.iter().position(|t| *t == dm_tab) {
+            self.set_active_tab(idx);
+        }
+        self.handle_call_command(fx);
+    }
+
+    pub(crate) fn handle_reject_call_command(&mut self, fx: &mut Vec<Effect>) {
+        if self.reject_waiting_call(fx) {
+            return;
+        }
+        if self.pending_group_call.is_some() || self.pending_call_from.is_some() {
+            self.end_call_keys(fx);
+        }
         if let Some((group_id, _initiator_id)) = self.pending_group_call.take() {
             if let Some(group) = self.groups.get(&group_id) {
                 let member_ids = group.members.clone();
                 let mut reject_msg = PlainMessage::call_accept(self.own_id.clone(), false);
                 reject_msg.group_id = Some(group_id.clone());
-                let _ = msg_tx.send(OutgoingMessage::Group {
+                fx.push(Effect::Send(OutgoingMessage::Group {
                     group_id: group_id.clone(),
                     member_ids,
                     message: reject_msg,
-                });
+                }));
             }
 
             let group_name = self.group_name(&group_id);
@@ -120,10 +178,10 @@ impl ChatUI {
             self.add_system_message(&group_tab, format!("Declined group call in {}", group_name));
         } else if let Some(peer_id) = self.pending_call_from.take() {
             let reject_msg = PlainMessage::call_accept(self.own_id.clone(), false);
-            let _ = msg_tx.send(OutgoingMessage::Direct {
+            fx.push(Effect::Send(OutgoingMessage::Direct {
                 target_id: peer_id.clone(),
                 message: reject_msg,
-            });
+            }));
 
             let peer_name = self.get_peer_display_name(&peer_id);
             self.status = format!("Rejected call from {}", peer_name);
@@ -135,7 +193,7 @@ impl ChatUI {
         }
     }
 
-    pub(crate) fn handle_hangup_command(&mut self, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+    pub(crate) fn handle_hangup_command(&mut self, fx: &mut Vec<Effect>) {
         let call = match self.active_call.take() {
             Some(c) => c,
             None => {
@@ -147,36 +205,55 @@ impl ChatUI {
         match &call.call_type {
             CallType::Direct(peer_id) => {
                 let hangup_msg = PlainMessage::call_hangup(self.own_id.clone());
-                let _ = msg_tx.send(OutgoingMessage::Direct {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
                     target_id: peer_id.clone(),
                     message: hangup_msg,
-                });
+                }));
             }
             CallType::Group { group_id } => {
                 if let Some(group) = self.groups.get(group_id) {
                     let member_ids = group.members.clone();
                     let mut hangup_msg = PlainMessage::call_hangup(self.own_id.clone());
                     hangup_msg.group_id = Some(group_id.clone());
-                    let _ = msg_tx.send(OutgoingMessage::Group {
+                    fx.push(Effect::Send(OutgoingMessage::Group {
                         group_id: group_id.clone(),
                         member_ids,
                         message: hangup_msg,
-                    });
+                    }));
                 }
             }
         }
 
-        self.stop_audio_call(&call);
+        self.stop_audio_call(&call, fx);
     }
 
-    pub(crate) fn handle_incoming_call_request(&mut self, msg: &PlainMessage, _msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+    pub(crate) fn handle_incoming_call_request(&mut self, msg: &PlainMessage, fx: &mut Vec<Effect>) {
         let peer_name = self.get_peer_display_name(&msg.sender);
 
         if self.active_call.is_some() {
+            if self.offer_call_waiting(msg) {
+                return;
+            }
             self.status = format!("📞 Missed call from {} (already in a call)", peer_name);
+            // A direct caller hears we're busy instead of ringing on
+            if msg.group_id.is_none() {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: msg.sender.clone(),
+                    message: PlainMessage::call_busy(self.own_id.clone()),
+                }));
+                self.missed_call_from = Some(msg.sender.clone());
+                let dm_tab = Tab::DirectMessage(msg.sender.clone());
+                self.add_event_message(&dm_tab, ChatEvent::CallMissed, format!("📞 Missed call from {} (you were in another call) — /callback to ring back", peer_name));
+            }
             return;
         }
 
+        if !self.offered_call_keys(msg) {
+            self.status = format!("📞 {} called without a call key (their wsp may be too old) — ignored", peer_name);
+            return;
+        }
+
+        self.chime(SoundEvent::Call);
         if let Some(ref group_id) = msg.group_id {
             let group_name = self.group_name(group_id);
 
@@ -191,23 +268,25 @@ impl ChatUI {
                 msg.sender.clone(),
                 format!("📞 {} started a group call — /accept-call or /reject-call", peer_name),
             );
-            self.messages.entry(group_tab).or_insert_with(Vec::new).push(sys_msg);
+            self.push_message(group_tab, sys_msg);
         } else {
             self.pending_call_from = Some(msg.sender.clone());
-            self.status = format!("📞 Incoming call from {} — /accept-call or /reject-call", peer_name);
+            let text = if self.schedule_auto_answer(&msg.sender) {
+                format!("📞 Incoming call from {} — answering after two rings (contact policy), /reject-call to stop", peer_name)
+            } else {
+                format!("📞 Incoming call from {} — /accept-call or /reject-call", peer_name)
+            };
+            self.status = text.clone();
 
             let dm_tab = Tab::DirectMessage(msg.sender.clone());
             self.ensure_tab(&dm_tab);
 
-            let sys_msg = PlainMessage::system(
-                msg.sender.clone(),
-                format!("📞 Incoming call from {} — /accept-call or /reject-call", peer_name),
-            );
-            self.messages.entry(dm_tab).or_insert_with(Vec::new).push(sys_msg);
+            let sys_msg = PlainMessage::system(msg.sender.clone(), text);
+            self.push_message(dm_tab, sys_msg);
         }
     }
 
-    pub(crate) fn handle_call_response(&mut self, msg: &PlainMessage, accept: bool, _msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+    pub(crate) fn handle_call_response(&mut self, msg: &PlainMessage, accept: bool, fx: &mut Vec<Effect>) {
         let peer_name = self.get_peer_display_name(&msg.sender);
 
         if let Some(ref group_id) = msg.group_id {
@@ -215,51 +294,67 @@ impl ChatUI {
             let group_tab = Tab::Group(group_id.clone());
 
             if accept {
+                self.note_call_salt(msg, fx);
+                if self.active_call.as_ref().is_some_and(|c| matches!(&c.call_type, CallType::Group { group_id: g } if g == group_id)) {
+                    self.add_call_participant(&msg.sender, fx);
+                }
                 let sys_msg = PlainMessage::system(
                     msg.sender.clone(),
                     format!("🔊 {} joined the group call", peer_name),
                 );
-                self.messages.entry(group_tab).or_insert_with(Vec::new).push(sys_msg);
+                self.push_message(group_tab, sys_msg);
                 self.status = format!("{} joined the call in {}", peer_name, group_name);
             } else {
                 let sys_msg = PlainMessage::system(
                     msg.sender.clone(),
                     format!("{} declined the group call", peer_name),
                 );
-                self.messages.entry(group_tab).or_insert_with(Vec::new).push(sys_msg);
+                self.push_message(group_tab, sys_msg);
             }
         } else {
             let dm_tab = Tab::DirectMessage(msg.sender.clone());
 
-            if accept {
-                self.start_audio_call(msg.sender.clone());
+            if accept && self.note_call_salt(msg, fx) {
+                self.start_audio_call(msg.sender.clone(), fx);
+            } else if accept {
+                // Not an answer to the call we placed: don't leave them talking to nobody
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: msg.sender.clone(),
+                    message: PlainMessage::call_hangup(self.own_id.clone()),
+                }));
+                self.status = format!("📞 {} answered a call we didn't place — hung up", peer_name);
             } else {
-                self.status = format!("{} rejected the call", peer_name);
-                let sys_msg = PlainMessage::system(
-                    msg.sender.clone(),
-                    format!("{} rejected the call", peer_name),
-                );
-                self.messages.entry(dm_tab).or_insert_with(Vec::new).push(sys_msg);
+                self.end_call_keys(fx);
+                let text = if msg.call_busy {
+                    format!("📞 {} is in another call", peer_name)
+                } else {
+                    format!("{} rejected the call", peer_name)
+                };
+                self.status = text.clone();
+                let sys_msg = PlainMessage::system(msg.sender.clone(), text);
+                self.push_message(dm_tab, sys_msg);
             }
         }
     }
 
-    pub(crate) fn handle_remote_hangup(&mut self, msg: &PlainMessage, _msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+    pub(crate) fn handle_remote_hangup(&mut self, msg: &PlainMessage, fx: &mut Vec<Effect>) {
         let peer_name = self.get_peer_display_name(&msg.sender);
 
         if let Some(ref group_id) = msg.group_id {
+            self.drop_call_key(&msg.sender, fx);
+            self.remove_call_participant(&msg.sender);
             let group_tab = Tab::Group(group_id.clone());
             let sys_msg = PlainMessage::system(
                 msg.sender.clone(),
                 format!("📵 {} left the group call", peer_name),
             );
-            self.messages.entry(group_tab).or_insert_with(Vec::new).push(sys_msg);
+            self.push_message(group_tab, sys_msg);
         } else {
             if let Some(ref call) = self.active_call {
                 match &call.call_type {
                     CallType::Direct(peer_id) if *peer_id == msg.sender => {
                         let call = self.active_call.take().unwrap();
-                        self.stop_audio_call(&call);
+                        self.stop_audio_call(&call, fx);
                     }
                     _ => {}
                 }
@@ -267,40 +362,35 @@ impl ChatUI {
         }
     }
 
-    pub(crate) fn start_audio_call(&mut self, peer_id: String) {
+    pub(crate) fn start_audio_call(&mut self, peer_id: String, fx: &mut Vec<Effect>) {
         let peer_name = self.get_peer_display_name(&peer_id);
 
-        match AudioPipeline::start() {
-            Ok(mut pipeline) => {
-                self.audio_capture_rx = pipeline.take_capture_rx();
-                self.audio_pipeline = Some(pipeline);
-                self.active_call = Some(CallState {
-                    call_type: CallType::Direct(peer_id.clone()),
-                    start_time: chrono::Utc::now(),
-                    muted: false,
-                });
-                self.status = format!("🔊 In call with {} | /mute to toggle mic | /hangup to end", peer_name);
-
-                let dm_tab = Tab::DirectMessage(peer_id);
-                self.add_system_message(&dm_tab, format!("🔊 Voice call started with {}", peer_name));
-            }
-            Err(e) => {
-                self.status = format!("❌ Failed to start audio: {}", e);
-                let dm_tab = Tab::DirectMessage(peer_id);
-                self.add_system_message(&dm_tab, format!("❌ Failed to start audio: {}", e));
-            }
-        }
+        self.active_call = Some(CallState {
+            call_type: CallType::Direct(peer_id.clone()),
+            start_time: chrono::Utc::now(),
+            mic: self.call_mic(vec![peer_id.clone()]),
+            participants: HashMap::new(),
+            held_by_peer: false,
+        });
+        self.status = format!("🔊 In call with {} | {} | /hangup to end", peer_name, self.mute_hint());
+        self.pace_for_call();
+
+        let dm_tab = Tab::DirectMessage(peer_id);
+        self.add_event_message(&dm_tab, ChatEvent::CallStarted, format!("🔊 Voice call started with {}", peer_name));
+        self.start_audio(fx);
     }
 
-    pub(crate) fn stop_audio_call(&mut self, call: &CallState) {
+    pub(crate) fn stop_audio_call(&mut self, call: &CallState, fx: &mut Vec<Effect>) {
         let duration = chrono::Utc::now() - call.start_time;
         let duration_str = format_duration(duration);
+        self.tally.call_time += duration;
 
-        if let Some(ref pipeline) = self.audio_pipeline {
-            pipeline.stop();
+        // The pipeline and the microphone carry on for a call still on hold
+        if self.held_call.is_none() {
+            call.mic.hang_up();
+            fx.push(Effect::StopAudio);
         }
-        self.audio_pipeline = None;
-        self.audio_capture_rx = None;
+        self.end_call_keys(fx);
 
         match &call.call_type {
             CallType::Direct(peer_id) => {
@@ -308,36 +398,178 @@ impl ChatUI {
                 self.status = format!("Call with {} ended ({})", peer_name, duration_str);
 
                 let dm_tab = Tab::DirectMessage(peer_id.clone());
-                self.add_system_message(&dm_tab, format!("📵 Call ended with {} ({})", peer_name, duration_str));
+                self.add_event_message(&dm_tab, ChatEvent::CallEnded, format!("📵 Call ended with {} ({})", peer_name, duration_str));
             }
             CallType::Group { group_id } => {
                 let group_name = self.group_name(group_id);
                 self.status = format!("Left group call in {} ({})", group_name, duration_str);
 
                 let group_tab = Tab::Group(group_id.clone());
-                self.add_system_message(&group_tab, format!("📵 Left group call in {} ({})", group_name, duration_str));
+                self.add_event_message(&group_tab, ChatEvent::CallEnded, format!("📵 Left group call in {} ({})", group_name, duration_str));
             }
         }
+        self.pace_for_call();
     }
 
-    pub(crate) fn start_audio_call_group(&mut self, group_id: String) {
+    pub(crate) fn start_audio_call_group(&mut self, group_id: String, fx: &mut Vec<Effect>) {
         let group_name = self.group_name(&group_id);
 
-        match AudioPipeline::start() {
-            Ok(mut pipeline) => {
-                self.audio_capture_rx = pipeline.take_capture_rx();
-                self.audio_pipeline = Some(pipeline);
-                self.active_call = Some(CallState {
-                    call_type: CallType::Group { group_id: group_id.clone() },
-                    start_time: chrono::Utc::now(),
-                    muted: false,
-                });
-                self.status = format!("🔊 In group call: {} | /mute to toggle mic | /hangup to leave", group_name);
+        let members = self.groups.get(&group_id).map(|g| g.members.clone()).unwrap_or_default();
+        // Whoever we saw join before we did (just the caller, when we placed it: nobody)
+        let joined: Vec<String> = self.call_keys.as_ref().map(|k| k.peers.keys().cloned().collect()).unwrap_or_default();
+        self.active_call = Some(CallState {
+            call_type: CallType::Group { group_id },
+            start_time: chrono::Utc::now(),
+            mic: self.call_mic(members),
+            participants: joined.into_iter().map(|id| (id, Participant::new())).collect(),
+            held_by_peer: false,
+        });
+        self.status = format!("🔊 In group call: {} | {} | /hangup to leave", group_name, self.mute_hint());
+        self.pace_for_call();
+        if self.audio_support == AudioSupport::ListenOnly {
+            self.announce_mute(fx);
+        }
+        self.start_audio(fx);
+    }
+
+    /// Turn down a call we won't take (do not disturb, low-bandwidth mode). A direct
+    /// caller gets the `notice` saying why and the call shows as missed, `why`; a group
+    /// call is just noted.
+    pub(crate) fn decline_call(&mut self, msg: &PlainMessage, notice: Key, why: Key, fx: &mut Vec<Effect>) {
+        let peer_name = self.get_peer_display_name(&msg.sender);
+        let why = strings::text(why, &[]);
+        let params = [("name", peer_name.as_str()), ("why", why.as_str())];
+        if let Some(ref group_id) = msg.group_id {
+            let tab = Tab::Group(group_id.clone());
+            self.add_event_message(&tab, ChatEvent::CallMissed, strings::text(Key::GroupCallNotJoined, &params));
+            return;
+        }
+        fx.push(Effect::Send(OutgoingMessage::Direct {
+            target_id: msg.sender.clone(),
+            message: PlainMessage::call_accept(self.own_id.clone(), false),
+        }));
+        let notice = PlainMessage {
+            direct: true,
+            message_id: Some(PlainMessage::generate_id()),
+            ..PlainMessage::notice(self.own_id.clone(), notice, &[("name", &self.display_name())])
+        };
+        fx.push(Effect::Send(OutgoingMessage::Direct { target_id: msg.sender.clone(), message: notice }));
+        self.missed_call_from = Some(msg.sender.clone());
+        let tab = Tab::DirectMessage(msg.sender.clone());
+        self.add_event_message(&tab, ChatEvent::CallMissed, strings::text(Key::MissedCall, &params));
+    }
+
+    /// False, with the reason on the status line, when this machine can't do calls
+    fn calls_available(&mut self) -> bool {
+        if let AudioSupport::Unavailable(ref reason) = self.audio_support {
+            self.status = format!("📵 Voice calls unavailable: {}", reason);
+            return false;
+        }
+        if !self.traffic.allows_calls() {
+            self.status = "📶 No calls in low-bandwidth mode — /lowbw off first".to_string();
+            return false;
+        }
+        true
+    }
+
+    /// Our microphone's controls for a new call; muted for good without a microphone.
+    /// With a call on hold the new one takes over its microphone.
+    fn call_mic(&self, targets: Vec<String>) -> MicControls {
+        if let Some(ref held) = self.held_call {
+            held.call.mic.set_targets(targets);
+            return held.call.mic.clone();
+        }
+        let mic = MicControls::new(targets);
+        mic.set_muted(self.audio_support == AudioSupport::ListenOnly);
+        mic
+    }
+
+    fn mute_hint(&self) -> &'static str {
+        match self.audio_support {
+            AudioSupport::ListenOnly => "🎧 listen-only (no microphone)",
+            _ => "/mute to toggle mic",
+        }
+    }
+
+    /// Handle /mute
+    pub(crate) fn handle_mute_command(&mut self, fx: &mut Vec<Effect>) {
+        let Some(ref call) = self.active_call else {
+            self.status = "Not in a call".to_string();
+            return;
+        };
+        self.status = if self.audio_support == AudioSupport::ListenOnly {
+            "🎧 Listen-only: there's no microphone to unmute".to_string()
+        } else if call.mic.toggle_mute() {
+            "🔇 Microphone muted".to_string()
+        } else {
+            "🔊 Microphone unmuted".to_string()
+        };
+        self.announce_mute(fx);
+    }
+
+    /// Who our microphone goes to: the peer (unless they have us on hold), or the
+    /// group's current members. Nobody, while the only call is on hold.
+    pub(crate) fn sync_call_targets(&self) {
+        let Some(ref call) = self.active_call else {
+            if let Some(ref held) = self.held_call {
+                held.call.mic.set_targets(Vec::new());
+            }
+            return;
+        };
+        match call.call_type {
+            CallType::Direct(_) if call.held_by_peer => call.mic.set_targets(Vec::new()),
+            CallType::Direct(ref peer_id) => call.mic.set_targets(vec![peer_id.clone()]),
+            CallType::Group { ref group_id } => {
+                call.mic.set_targets(self.groups.get(group_id).map(|g| g.members.clone()).unwrap_or_default());
+            }
+        }
+    }
+
+    /// The audio device couldn't be opened for the call we just entered — back out of it
+    pub(crate) fn audio_failed(&mut self, err: String) {
+        let Some(call) = self.active_call.take() else {
+            return;
+        };
+        let tab = match call.call_type {
+            CallType::Direct(peer_id) => Tab::DirectMessage(peer_id),
+            CallType::Group { group_id } => Tab::Group(group_id),
+        };
+        self.status = format!("❌ Failed to start audio: {}", err);
+        self.add_system_message(&tab, format!("❌ Failed to start audio: {}", err));
+        self.pace_for_call();
+    }
+}
+
+impl ChatUI {
+    /// Decode and play an incoming audio frame if it belongs to the active call.
+    /// Returns true when the call's participant list needs redrawing.
+    pub(super) fn play_audio_frame(&mut self, from: &str, call_id: &str, opus_data: &[u8]) -> bool {
+        let Some(ref call) = self.state.active_call else {
+            return false;
+        };
+        // Frames still queued from a call we've just left aren't this one's
+        if !self.state.is_current_call(call_id) {
+            return false;
+        }
+        let accept = match &call.call_type {
+            CallType::Direct(peer_id) => peer_id == from,
+            CallType::Group { group_id } => {
+                self.state.groups.get(group_id)
+                    .map(|g| g.members.iter().any(|m| m == from))
+                    .unwrap_or(false)
             }
-            Err(e) => {
-                self.status = format!("❌ Failed to start audio: {}", e);
-                let group_tab = Tab::Group(group_id);
-                self.add_system_message(&group_tab, format!("❌ Failed to start audio: {}", e));
+        };
+        if !accept {
+            return false;
+        }
+        let Some(ref mut pipeline) = self.audio_pipeline else {
+            return false;
+        };
+        match pipeline.play(opus_data) {
+            Ok(level) => self.state.note_call_audio(from, level),
+            Err(_) => {
+                self.audio_stats.dropped();
+                false
             }
         }
     }
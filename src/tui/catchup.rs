@@ -0,0 +1,179 @@
+//! Group history catch-up: after a reconnect we ask one online member of each group
+//! for what we missed, and answer the same question from other members.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crate::client::OutgoingMessage;
+use crate::protocol::{HistorySync, PlainMessage, MAX_HISTORY_BATCH};
+
+use super::state::{ChatState, Effect};
+use super::types::Tab;
+
+/// Most messages one catch-up reply carries (the newest ones)
+const MAX_HISTORY_REPLY: usize = 200;
+/// Content bytes per batch, well under the relay's frame limit
+const HISTORY_BATCH_BYTES: usize = 256 * 1024;
+/// How often one peer's requests for the same group are answered
+const HISTORY_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Identifies a message across copies: its id, or what it says and when if it has none
+fn dedup_key(m: &PlainMessage) -> (String, i64, String) {
+    match m.message_id {
+        Some(ref id) => (id.clone(), 0, String::new()),
+        None => (m.sender.clone(), m.timestamp, m.content.clone()),
+    }
+}
+
+impl ChatState {
+    /// Ask for what every group said while we were away (after a reconnect)
+    pub(crate) fn request_group_history(&mut self) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        if self.history_sync {
+            let group_ids: Vec<String> = self.groups.keys().cloned().collect();
+            for group_id in group_ids {
+                self.request_history(&group_id, &mut fx);
+            }
+        }
+        fx
+    }
+
+    /// Handle /group sync — catch up on the current group now
+    pub(crate) fn handle_group_sync_command(&mut self, group_id: &str, fx: &mut Vec<Effect>) {
+        if !self.history_sync {
+            self.status = "History sync is off (history_sync in config.json)".to_string();
+            return;
+        }
+        match self.request_history(group_id, fx) {
+            Some(member) => self.status = format!("🔄 Asking {} for missed messages...", self.get_peer_display_name(&member)),
+            None => self.status = "No group member online to sync from".to_string(),
+        }
+    }
+
+    /// Ask one online member of `group_id` for everything after our latest message.
+    /// Returns who was asked.
+    fn request_history(&mut self, group_id: &str, fx: &mut Vec<Effect>) -> Option<String> {
+        let member = self.groups.get(group_id)?
+            .members.iter()
+            .find(|id| self.peers.contains_key(*id))?
+            .clone();
+        let since_timestamp = self.messages.get(&Tab::Group(group_id.to_string()))
+            .and_then(|msgs| msgs.iter().rev().find(|m| !m.system))
+            .map_or(0, |m| m.timestamp);
+
+        self.history_pending.insert(group_id.to_string(), (member.clone(), 0));
+        let request = HistorySync::Request { group_id: group_id.to_string(), since_timestamp };
+        fx.push(Effect::Send(OutgoingMessage::Direct {
+            target_id: member.clone(),
+            message: PlainMessage::history(self.own_id.clone(), request),
+        }));
+        Some(member)
+    }
+
+    /// A catch-up request or batch from `sender`
+    pub(crate) fn handle_history(&mut self, sender: &str, sync: HistorySync, fx: &mut Vec<Effect>) {
+        match sync {
+            HistorySync::Request { group_id, since_timestamp } => self.answer_history(sender, &group_id, since_timestamp, fx),
+            HistorySync::Batch { group_id, messages, more } => self.merge_history(sender, &group_id, messages, more),
+        }
+    }
+
+    /// Send `sender` our copy of `group_id` since `since` — only if sharing is on, they're
+    /// a member of a group we're in, and they haven't just asked
+    fn answer_history(&mut self, sender: &str, group_id: &str, since: i64, fx: &mut Vec<Effect>) {
+        let is_member = self.groups.get(group_id).is_some_and(|g| g.members.iter().any(|id| id == sender));
+        if !self.history_sync || !is_member {
+            return;
+        }
+        let key = (sender.to_string(), group_id.to_string());
+        if self.history_served.get(&key).is_some_and(|at| at.elapsed() < HISTORY_COOLDOWN) {
+            return;
+        }
+        self.history_served.insert(key, Instant::now());
+
+        let now = chrono::Utc::now().timestamp();
+        let mut shared: Vec<PlainMessage> = self.messages.get(&Tab::Group(group_id.to_string()))
+            .map(|msgs| msgs.iter()
+                .filter(|m| !m.system && m.file_offer.is_none() && m.timestamp >= since && !m.is_expired(now))
+                .map(|m| PlainMessage {
+                    timestamp: m.timestamp,
+                    message_id: m.message_id.clone(),
+                    expire_after: m.expire_after,
+                    ..PlainMessage::group(m.sender.clone(), m.content.clone(), group_id.to_string())
+                })
+                .collect())
+            .unwrap_or_default();
+        let excess = shared.len().saturating_sub(MAX_HISTORY_REPLY);
+        shared.drain(..excess);
+
+        let mut batches: Vec<Vec<PlainMessage>> = vec![Vec::new()];
+        let mut bytes = 0;
+        for m in shared {
+            let last = batches.last_mut().expect("never empty");
+            if !last.is_empty() && (last.len() == MAX_HISTORY_BATCH || bytes + m.content.len() > HISTORY_BATCH_BYTES) {
+                batches.push(Vec::new());
+                bytes = 0;
+            }
+            bytes += m.content.len();
+            batches.last_mut().expect("never empty").push(m);
+        }
+        let count = batches.len();
+        for (i, messages) in batches.into_iter().enumerate() {
+            let batch = HistorySync::Batch { group_id: group_id.to_string(), messages, more: i + 1 < count };
+            fx.push(Effect::Send(OutgoingMessage::Direct {
+                target_id: sender.to_string(),
+                message: PlainMessage::history(self.own_id.clone(), batch),
+            }));
+        }
+    }
+
+    /// Fold a batch into the group tab, if it's from the member we asked. Messages we
+    /// already have are skipped; the rest are marked as synced and put in time order.
+    fn merge_history(&mut self, sender: &str, group_id: &str, messages: Vec<PlainMessage>, more: bool) {
+        let Some((asked, merged)) = self.history_pending.get(group_id).cloned() else {
+            return;
+        };
+        if asked != sender || !self.groups.contains_key(group_id) {
+            return;
+        }
+
+        let tab = Tab::Group(group_id.to_string());
+        let mut seen: HashSet<_> = self.messages.get(&tab).map(|msgs| msgs.iter().map(dedup_key).collect()).unwrap_or_default();
+        let now = chrono::Utc::now().timestamp();
+        let mut added = 0;
+        for m in messages {
+            if m.system || m.is_expired(now) || !seen.insert(dedup_key(&m)) {
+                continue;
+            }
+            // Only the chat message itself is kept: no offers, invites or call signals
+            let mut m = PlainMessage {
+                timestamp: m.timestamp,
+                message_id: m.message_id,
+                expire_after: m.expire_after,
+                synced: true,
+                ..PlainMessage::group(m.sender, m.content, group_id.to_string())
+            };
+            self.mark_mention(&mut m);
+            self.push_message(tab.clone(), m);
+            added += 1;
+        }
+        if added > 0 {
+            if let Some(msgs) = self.messages.get_mut(&tab) {
+                msgs.sort_by_key(|m| m.timestamp);
+            }
+        }
+
+        let merged = merged + added;
+        if more {
+            self.history_pending.insert(group_id.to_string(), (asked, merged));
+            return;
+        }
+        self.history_pending.remove(group_id);
+        let group_name = self.group_name(group_id);
+        self.status = if merged == 0 {
+            format!("🔄 Nothing missed in {}", group_name)
+        } else {
+            format!("🔄 Caught up on {} message(s) in {} from {}", merged, group_name, self.get_peer_display_name(sender))
+        };
+    }
+}
@@ -0,0 +1,176 @@
+//! Joins and leaves when the relay churns. After a relay restart or a network blip
+//! everyone comes back at once, so join/leave notices that land within CHURN_WINDOW of
+//! each other are held and then shown as one line ("7 peers reconnected") once things
+//! go quiet. Leaves always wait out the window, so a peer who drops and comes straight
+//! back never gets a leave/join pair. (The client already skips the join for a peer
+//! whose session was in use in the last few minutes.)
+
+use std::time::{Duration, Instant};
+
+use crate::protocol::PlainMessage;
+use crate::strings::Key;
+
+use super::state::ChatState;
+use super::types::Tab;
+
+/// Join/leave notices this close together are summed up in one line
+const CHURN_WINDOW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Presence {
+    Joined,
+    Left,
+    /// Left and came back within the window
+    Reconnected,
+}
+
+/// Join/leave notices waiting for the relay to settle
+#[derive(Debug, Default)]
+pub(crate) struct Churn {
+    /// When the latest join or leave came in
+    last: Option<Instant>,
+    /// Held notices, one per peer, in the order they came
+    held: Vec<(String, Presence, PlainMessage)>,
+}
+
+/// Whether `msg` is a peer joining (a notice from our client) or saying goodbye
+fn presence_of(msg: &PlainMessage) -> Option<Presence> {
+    if msg.is_goodbye() {
+        return Some(Presence::Left);
+    }
+    if !msg.system || msg.direct || msg.group_id.is_some() {
+        return None;
+    }
+    msg.is_notice(Key::Joined).then_some(Presence::Joined)
+}
+
+/// "7 peers reconnected, 2 joined, 1 left"
+fn summary(held: &[(String, Presence, PlainMessage)]) -> String {
+    let count = |kind: Presence| held.iter().filter(|(_, p, _)| *p == kind).count();
+    let parts: Vec<String> = [(Presence::Reconnected, "reconnected"), (Presence::Joined, "joined"), (Presence::Left, "left")]
+        .into_iter()
+        .map(|(kind, verb)| (count(kind), verb))
+        .filter(|(n, _)| *n > 0)
+        .enumerate()
+        .map(|(i, (n, verb))| match i {
+            0 => format!("{} peer{} {}", n, if n == 1 { "" } else { "s" }, verb),
+            _ => format!("{} {}", n, verb),
+        })
+        .collect();
+    parts.join(", ")
+}
+
+impl ChatState {
+    /// Take a join or leave notice to show later, if now isn't the time. Anything
+    /// else (and a join with nothing else going on) is handed back to show now.
+    pub(crate) fn hold_presence(&mut self, msg: PlainMessage, now: Instant) -> Option<PlainMessage> {
+        let Some(kind) = presence_of(&msg) else {
+            return Some(msg);
+        };
+        let churn = &mut self.churn;
+        let busy = churn.last.is_some_and(|at| now.duration_since(at) < CHURN_WINDOW);
+        churn.last = Some(now);
+
+        if let Some(at) = churn.held.iter().position(|(id, _, _)| *id == msg.sender) {
+            let (_, held, _) = churn.held[at];
+            match (held, kind) {
+                // Came straight back: neither notice is worth showing
+                (Presence::Left, Presence::Joined) => churn.held[at].1 = Presence::Reconnected,
+                (Presence::Reconnected, Presence::Joined) => {}
+                // Joined and gone again before it was shown
+                (Presence::Joined, Presence::Left) => {
+                    churn.held.remove(at);
+                }
+                (_, kind) => churn.held[at] = (msg.sender.clone(), kind, msg),
+            }
+            return None;
+        }
+        if kind == Presence::Joined && !busy {
+            return Some(msg);
+        }
+        churn.held.push((msg.sender.clone(), kind, msg));
+        None
+    }
+
+    /// Show what was held once no join or leave has come in for CHURN_WINDOW: one
+    /// notice as it was, several as a summary line. True if anything was shown.
+    pub(crate) fn release_churn(&mut self, now: Instant) -> bool {
+        let settled = self.churn.last.is_some_and(|at| now.duration_since(at) >= CHURN_WINDOW);
+        if !settled || self.churn.held.is_empty() {
+            return false;
+        }
+        let mut held = std::mem::take(&mut self.churn.held);
+        match held.len() {
+            1 => match held.pop() {
+                Some((_, Presence::Reconnected, _)) | None => return false,
+                Some((_, _, msg)) => self.push_message(Tab::Global, msg),
+            },
+            _ => {
+                let line = summary(&held);
+                self.add_system_message(&Tab::Global, line);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The notice our client makes when a key exchange brings in a new peer
+    fn joined(id: &str) -> PlainMessage {
+        PlainMessage::notice(id.to_string(), Key::Joined, &[("name", &id[..8])])
+    }
+
+    /// A goodbye in words only, as clients from before notices send it
+    fn left(id: &str) -> PlainMessage {
+        PlainMessage::system(id.to_string(), format!("{} has left", &id[..8]))
+    }
+
+    fn shown(state: &ChatState) -> Vec<String> {
+        state.messages[&Tab::Global].iter().map(|m| m.content.clone()).collect()
+    }
+
+    #[test]
+    fn test_burst_of_key_exchanges_becomes_one_line() {
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let peers: Vec<String> = (0..8).map(|i| format!("{:02x}", i).repeat(16)).collect();
+        let start = Instant::now();
+
+        // The relay restarts: seven peers drop, then all come back, and one new one joins
+        assert!(state.hold_presence(left(&peers[0]), start).is_none());
+        for peer in &peers[1..7] {
+            assert!(state.hold_presence(left(peer), start + Duration::from_secs(1)).is_none());
+        }
+        for (i, peer) in peers.iter().enumerate() {
+            let at = start + Duration::from_secs(5 + i as u64);
+            assert!(state.hold_presence(joined(peer), at).is_none());
+        }
+        let last = start + Duration::from_secs(12);
+        assert!(!state.release_churn(last + CHURN_WINDOW - Duration::from_secs(1)));
+        assert!(state.release_churn(last + CHURN_WINDOW));
+        assert_eq!(shown(&state), ["7 peers reconnected, 1 joined"]);
+        assert!(!state.release_churn(last + CHURN_WINDOW * 2));
+
+        // Once it's quiet, a join shows straight away; a blip shows nothing at all
+        let later = last + CHURN_WINDOW * 3;
+        let newcomer = "ee".repeat(16);
+        let msg = state.hold_presence(joined(&newcomer), later).unwrap();
+        assert_eq!(msg.content, "eeeeeeee has joined");
+        let later = later + CHURN_WINDOW * 2;
+        state.hold_presence(left(&newcomer), later);
+        state.hold_presence(joined(&newcomer), later + Duration::from_secs(3));
+        assert!(!state.release_churn(later + Duration::from_secs(3) + CHURN_WINDOW));
+
+        // A leave on its own is shown once the window has passed
+        let later = later + CHURN_WINDOW * 3;
+        state.hold_presence(left(&newcomer), later);
+        assert!(state.release_churn(later + CHURN_WINDOW));
+        assert_eq!(state.messages[&Tab::Global].last().unwrap().content, "eeeeeeee has left");
+
+        // Other notices are never held
+        let notice = PlainMessage::system(newcomer.clone(), "something else".to_string());
+        assert!(state.hold_presence(notice, later).is_some());
+    }
+}
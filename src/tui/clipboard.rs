@@ -0,0 +1,146 @@
+//! System clipboard access. Ctrl+Shift+V and /paste-image offer an image as a PNG file
+//! (anything else falls back to an ordinary text paste); /id copy puts our key on it.
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+
+use super::state::{ChatState, Effect};
+
+/// What the system clipboard was holding
+#[cfg_attr(not(feature = "clipboard"), allow(dead_code))]
+pub(crate) enum Clipboard {
+    /// An image, already encoded as PNG
+    Image(Vec<u8>),
+    Text(String),
+}
+
+#[cfg(feature = "clipboard")]
+thread_local! {
+    /// Kept open for the life of the app: on X11 and Wayland the text we copy is served
+    /// by this process and disappears with the handle that set it
+    static SYSTEM_CLIPBOARD: std::cell::RefCell<Option<arboard::Clipboard>> = const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(feature = "clipboard")]
+fn with_clipboard<T>(f: impl FnOnce(&mut arboard::Clipboard) -> Result<T>) -> Result<T> {
+    SYSTEM_CLIPBOARD.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        let clipboard = match slot.as_mut() {
+            Some(clipboard) => clipboard,
+            None => slot.insert(arboard::Clipboard::new().map_err(explain)?),
+        };
+        f(clipboard)
+    })
+}
+
+/// Read the clipboard, preferring an image over text
+#[cfg(feature = "clipboard")]
+pub(crate) fn read() -> Result<Clipboard> {
+    with_clipboard(read_from)
+}
+
+#[cfg(feature = "clipboard")]
+fn read_from(clipboard: &mut arboard::Clipboard) -> Result<Clipboard> {
+    use anyhow::{anyhow, Context};
+
+    match clipboard.get_image() {
+        Ok(image) => {
+            let png = encode_png(image.width as u32, image.height as u32, &image.bytes)
+                .context("Couldn't encode the clipboard image")?;
+            Ok(Clipboard::Image(png))
+        }
+        Err(arboard::Error::ContentNotAvailable) => match clipboard.get_text() {
+            Ok(text) => Ok(Clipboard::Text(text)),
+            Err(arboard::Error::ContentNotAvailable) => Err(anyhow!("The clipboard is empty")),
+            Err(e) => Err(explain(e)),
+        },
+        Err(e) => Err(explain(e)),
+    }
+}
+
+/// Put `text` on the clipboard
+#[cfg(feature = "clipboard")]
+pub(crate) fn copy_text(text: &str) -> Result<()> {
+    with_clipboard(|clipboard| clipboard.set_text(text).map_err(explain))
+}
+
+#[cfg(not(feature = "clipboard"))]
+const NO_CLIPBOARD: &str = "This wsp was built without clipboard support (rebuild with --features clipboard)";
+
+#[cfg(not(feature = "clipboard"))]
+pub(crate) fn read() -> Result<Clipboard> {
+    anyhow::bail!(NO_CLIPBOARD)
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub(crate) fn copy_text(_text: &str) -> Result<()> {
+    anyhow::bail!(NO_CLIPBOARD)
+}
+
+/// Turn a clipboard failure into something actionable — on Linux it's usually a missing
+/// display server connection (SSH sessions, or Wayland without XWayland)
+#[cfg(feature = "clipboard")]
+fn explain(e: arboard::Error) -> anyhow::Error {
+    match e {
+        arboard::Error::ClipboardNotSupported => {
+            anyhow::anyhow!("No clipboard available here (is DISPLAY or WAYLAND_DISPLAY set?)")
+        }
+        arboard::Error::ClipboardOccupied => anyhow::anyhow!("Another program is holding the clipboard, try again"),
+        e => anyhow::anyhow!("Clipboard access failed: {}", e),
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    Ok(out)
+}
+
+/// Name for a pasted image, e.g. `pasted-20240301-140211.png`
+pub(crate) fn pasted_filename(now: DateTime<Local>) -> String {
+    format!("pasted-{}.png", now.format("%Y%m%d-%H%M%S"))
+}
+
+impl ChatState {
+    /// Handle /paste-image — offer the clipboard image to the current tab
+    pub(crate) fn handle_paste_image_command(&mut self, fx: &mut Vec<Effect>) {
+        match read() {
+            Ok(Clipboard::Image(png)) => self.share_pasted_image(png, fx),
+            Ok(Clipboard::Text(_)) => {
+                self.status = "📋 The clipboard holds text, not an image".to_string();
+            }
+            Err(e) => self.status = format!("📋 {:#}", e),
+        }
+    }
+
+    /// Offer PNG bytes from the clipboard as a file in the current tab
+    pub(crate) fn share_pasted_image(&mut self, png: Vec<u8>, fx: &mut Vec<Effect>) {
+        let filename = pasted_filename(Local::now());
+        self.share_data(filename, png, None, fx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_pasted_filename() {
+        let when = Local.with_ymd_and_hms(2024, 3, 1, 14, 2, 11).unwrap();
+        assert_eq!(pasted_filename(when), "pasted-20240301-140211.png");
+    }
+
+    #[cfg(feature = "clipboard")]
+    #[test]
+    fn test_encode_png() {
+        let png = encode_png(2, 1, &[255, 0, 0, 255, 0, 0, 255, 255]).unwrap();
+        assert_eq!(super::super::mime::sniff(&png), Some("image/png"));
+    }
+}
@@ -1,14 +1,82 @@
-use tokio::sync::mpsc;
-
+use crate::audio::AudioSupport;
 use crate::client::OutgoingMessage;
-use crate::crypto::safety_number::compute_safety_number;
-use crate::protocol::PlainMessage;
+use crate::protocol::{PlainMessage, MAX_MESSAGE_PARTS, MAX_PART_BYTES};
+use crate::strings::{self, Key};
+
+use super::parts::send_in_parts;
+use super::types::{CommandEntry, Tab};
+use super::state::{ChatState, Effect};
 
-use super::types::Tab;
-use super::ChatUI;
+impl ChatState {
+    /// Get all available commands for autocomplete
+    pub(crate) fn get_all_commands(&self) -> Vec<CommandEntry> {
+        let mut commands = vec![
+            CommandEntry { name: "help".to_string(), description: "Show this command list".to_string() },
+            CommandEntry { name: "keys".to_string(), description: "Show the key bindings (also F1)".to_string() },
+            CommandEntry { name: "dm".to_string(), description: "Open DM with a peer: /dm <nick|id>".to_string() },
+            CommandEntry { name: "tab".to_string(), description: "List open conversations, or switch: /tab [n|name]".to_string() },
+            CommandEntry { name: "nick".to_string(), description: "Change nickname: /nick <name>".to_string() },
+            CommandEntry { name: "me".to_string(), description: "Say what you're doing: /me waves".to_string() },
+            CommandEntry { name: "away".to_string(), description: "Show peers you're away: /away [message]".to_string() },
+            CommandEntry { name: "back".to_string(), description: "Show peers you're back (any key does too)".to_string() },
+            CommandEntry { name: "dnd".to_string(), description: "Do not disturb: /dnd on|off|<duration> (@urgent DMs get through)".to_string() },
+            CommandEntry { name: "lowbw".to_string(), description: "Low-bandwidth mode, text only: /lowbw on|off".to_string() },
+            CommandEntry { name: "sounds".to_string(), description: "Bell and tones for DMs, mentions, calls and offers: /sounds on|off".to_string() },
+            CommandEntry { name: "contact".to_string(), description: "Auto-accept from a verified contact: /contact policy <name> files=auto:<dir> calls=auto".to_string() },
+            CommandEntry { name: "group".to_string(), description: "Group commands: create/invite/leave/members/sync".to_string() },
+            CommandEntry { name: "join".to_string(), description: "Accept a group invite: /join [n|group name]".to_string() },
+            CommandEntry { name: "decline".to_string(), description: "Turn down a group invite: /decline [n|group name]".to_string() },
+            CommandEntry { name: "call".to_string(), description: "Start a voice call in current tab".to_string() },
+            CommandEntry { name: "accept-call".to_string(), description: "Accept incoming call".to_string() },
+            CommandEntry { name: "reject-call".to_string(), description: "Reject incoming call".to_string() },
+            CommandEntry { name: "callback".to_string(), description: "Call back whoever's call you last missed".to_string() },
+            CommandEntry { name: "hangup".to_string(), description: "End current call".to_string() },
+            CommandEntry { name: "mute".to_string(), description: "Toggle microphone mute".to_string() },
+            CommandEntry { name: "hold".to_string(), description: "Put the current call on hold".to_string() },
+            CommandEntry { name: "unhold".to_string(), description: "Resume the call on hold".to_string() },
+            CommandEntry { name: "swap".to_string(), description: "Answer a waiting call (holding this one), or switch to the call on hold".to_string() },
+            CommandEntry { name: "expire".to_string(), description: "Disappearing messages here: /expire <5m|1h|off>".to_string() },
+            CommandEntry { name: "id".to_string(), description: "Show your full identity key and fingerprint: /id [copy]".to_string() },
+            CommandEntry { name: "invite-link".to_string(), description: "Your relay and key as a wsp:// link and QR code: /invite-link [copy]".to_string() },
+            CommandEntry { name: "verify".to_string(), description: "Show safety number and ask peer to verify".to_string() },
+            CommandEntry { name: "verified".to_string(), description: "Mark peer as verified".to_string() },
+            CommandEntry { name: "whois".to_string(), description: "Everything known about a peer: /whois <nickname|id>".to_string() },
+            CommandEntry { name: "recent".to_string(), description: "Show or fold the peers who have gone, under recently seen in the sidebar".to_string() },
+            CommandEntry { name: "stats".to_string(), description: "Show traffic, ratchet, transfer and call statistics".to_string() },
+            CommandEntry { name: "mentions".to_string(), description: "List messages that mention you: /mentions [n]".to_string() },
+            CommandEntry { name: "star".to_string(), description: "Star or unstar a message, kept on this machine only: /star [n back from the last]".to_string() },
+            CommandEntry { name: "starred".to_string(), description: "List starred messages in all tabs: /starred [n] jumps to one".to_string() },
+            CommandEntry { name: "copy".to_string(), description: "Copy a message, or just its code, to the clipboard: /copy [code] [n]".to_string() },
+            CommandEntry { name: "expand".to_string(), description: "Show a collapsed long message: /expand [n]".to_string() },
+            CommandEntry { name: "export".to_string(), description: "Save this tab to a file: /export [path] [--format txt|json]".to_string() },
+            CommandEntry { name: "pipe".to_string(), description: "Post lines from a file or named pipe here as they come: /pipe <path> [label] | stop".to_string() },
+            CommandEntry { name: "send".to_string(), description: "Share a file or folder: /send <path>".to_string() },
+            CommandEntry { name: "paste-image".to_string(), description: "Share the image on the clipboard (also Ctrl+Shift+V)".to_string() },
+            CommandEntry { name: "offers".to_string(), description: "List pending file offers in this tab".to_string() },
+            CommandEntry { name: "accept".to_string(), description: "Accept file offer: /accept [n|filename] [path] [--force] [--extract]".to_string() },
+            CommandEntry { name: "reject".to_string(), description: "Reject file offer: /reject [n|filename]".to_string() },
+            CommandEntry { name: "pause".to_string(), description: "Pause a file you're sending: /pause [file]".to_string() },
+            CommandEntry { name: "resume".to_string(), description: "Carry on sending a paused file: /resume [file]".to_string() },
+            CommandEntry { name: "limit".to_string(), description: "Cap how fast a file goes out: /limit [file] <KB/s|off>".to_string() },
+            CommandEntry { name: "downloads".to_string(), description: "List files received this session and where they were saved".to_string() },
+        ];
+        // Say up front what the audio devices won't allow
+        let (call_note, mic_note) = match self.audio_support {
+            AudioSupport::Full => return commands,
+            AudioSupport::ListenOnly => ("listen-only: no microphone".to_string(), "no microphone"),
+            AudioSupport::Unavailable(ref reason) => (format!("unavailable: {}", reason), "calls unavailable"),
+        };
+        for cmd in commands.iter_mut() {
+            match cmd.name.as_str() {
+                "call" | "accept-call" | "callback" => cmd.description = format!("{} ({})", cmd.description, call_note),
+                "mute" => cmd.description = format!("{} ({})", cmd.description, mic_note),
+                _ => {}
+            }
+        }
+        commands
+    }
 
-impl ChatUI {
-    pub(crate) fn handle_input(&mut self, text: String, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+    pub(crate) fn handle_input(&mut self, text: String, fx: &mut Vec<Effect>) {
         // Handle commands
         let trimmed = text.trim();
         if trimmed.starts_with('/') {
@@ -22,14 +90,14 @@ impl ChatUI {
                 "help" => {
                     // Show help as a system message in current tab
                     let tab = self.tabs[self.active_tab].clone();
-                    let commands = Self::get_all_commands();
+                    let commands = self.get_all_commands();
                     let mut help_text = String::from("Available commands:\n");
                     for cmd in &commands {
                         help_text.push_str(&format!("  /{:<16} {}\n", cmd.name, cmd.description));
                     }
                     help_text.push_str("\nTip: Type / to see interactive autocomplete!");
                     let msg = PlainMessage::system("system".to_string(), help_text);
-                    self.messages.entry(tab).or_insert_with(Vec::new).push(msg);
+                    self.push_message(tab, msg);
                     self.status = "Showing help".to_string();
                     return;
                 }
@@ -39,130 +107,219 @@ impl ChatUI {
                         return;
                     }
                     let target = parts[1];
-                    self.open_dm_tab(target, Some(msg_tx));
+                    self.open_dm_tab(target, Some(fx));
+                }
+                "tab" => {
+                    self.handle_tab_command(&parts[1..]);
                 }
                 "nick" => {
-                    if parts.len() < 2 {
-                        self.status = "Usage: /nick <new_nickname>".to_string();
-                        return;
-                    }
-                    let new_nick = parts[1..].join(" ");
-                    self.own_nickname = Some(new_nick.clone());
-
-                    // Send nickname update to all peers
-                    for peer_id in self.peers.keys() {
-                        let nickname_msg = PlainMessage::nickname(
-                            self.own_id.clone(),
-                            new_nick.clone(),
-                        );
-                        let _ = msg_tx.send(OutgoingMessage::Direct {
-                            target_id: peer_id.clone(),
-                            message: nickname_msg,
-                        });
-                    }
-
-                    self.status = format!("Nickname changed to: {}", new_nick);
+                    self.handle_nick_command(&parts[1..], fx);
+                }
+                "me" => {
+                    self.handle_me_command(trimmed["/me".len()..].trim_start(), fx);
                 }
                 "group" => {
-                    self.handle_group_command(&parts[1..], msg_tx);
+                    self.handle_group_command(&parts[1..], fx);
+                }
+                "join" => {
+                    self.handle_join_command(&parts[1..], fx);
+                }
+                "decline" => {
+                    self.handle_decline_command(&parts[1..], fx);
+                }
+                "away" => {
+                    self.handle_away_command(&parts[1..], fx);
+                }
+                "back" => {
+                    self.handle_back_command(fx);
+                }
+                "dnd" => {
+                    self.handle_dnd_command(&parts[1..]);
+                }
+                "lowbw" => {
+                    self.handle_lowbw_command(&parts[1..], fx);
+                }
+                "contact" => {
+                    self.handle_contact_command(&parts[1..]);
+                }
+                "sounds" => {
+                    self.handle_sounds_command(&parts[1..]);
                 }
                 "call" => {
-                    self.handle_call_command(msg_tx);
+                    self.handle_call_command(fx);
                 }
                 "accept-call" => {
-                    self.handle_accept_call_command(msg_tx);
+                    self.handle_accept_call_command(fx);
                 }
                 "reject-call" => {
-                    self.handle_reject_call_command(msg_tx);
+                    self.handle_reject_call_command(fx);
+                }
+                "callback" => {
+                    self.handle_callback_command(fx);
                 }
                 "hangup" | "end-call" => {
-                    self.handle_hangup_command(msg_tx);
-                }
-                "mute" => {
-                    if let Some(ref mut call) = self.active_call {
-                        call.muted = !call.muted;
-                        if call.muted {
-                            self.status = "🔇 Microphone muted".to_string();
-                        } else {
-                            self.status = "🔊 Microphone unmuted".to_string();
-                        }
-                    } else {
-                        self.status = "Not in a call".to_string();
-                    }
+                    self.handle_hangup_command(fx);
+                }
+                "mute" => self.handle_mute_command(fx),
+                "hold" => self.handle_hold_command(fx),
+                "unhold" => self.handle_unhold_command(fx),
+                "swap" => self.handle_swap_command(fx),
+                "id" => {
+                    self.handle_id_command(&parts[1..]);
+                    return;
+                }
+                "invite-link" => {
+                    self.handle_invite_link_command(&parts[1..], fx);
+                    return;
                 }
                 "verify" => {
-                    self.handle_verify_command(&parts[1..], msg_tx);
+                    self.handle_verify_command(&parts[1..], fx);
                     return;
                 }
                 "verified" => {
-                    // Mark current verify target as verified
-                    self.handle_mark_verified(&parts[1..]);
+                    self.handle_mark_verified(&parts[1..], fx);
+                    return;
+                }
+                "whois" => {
+                    self.handle_whois_command(&parts[1..]);
                     return;
                 }
+                "recent" => {
+                    self.handle_recent_command();
+                    return;
+                }
+                "expand" => {
+                    self.handle_expand_command(&parts[1..]);
+                }
+                "mentions" => {
+                    self.handle_mentions_command(&parts[1..]);
+                }
+                "star" => {
+                    self.handle_star_command(&parts[1..]);
+                }
+                "starred" => {
+                    self.handle_starred_command(&parts[1..]);
+                }
+                "stats" => {
+                    fx.push(Effect::ShowStats);
+                }
+                "keys" => {
+                    fx.push(Effect::ShowKeys);
+                }
+                "export" => {
+                    self.handle_export_command(&parts[1..]);
+                }
+                "expire" => {
+                    self.handle_expire_command(&parts[1..], fx);
+                }
+                "pipe" => {
+                    self.handle_pipe_command(&parts[1..], fx);
+                }
                 "send" | "share" => {
                     if parts.len() < 2 {
                         self.status = "Usage: /send <filepath>".to_string();
                         return;
                     }
                     let filepath = parts[1..].join(" ");
-                    self.handle_share_command(&filepath, msg_tx);
+                    self.handle_share_command(&filepath, fx);
+                }
+                "pause" | "resume" => {
+                    self.handle_pause_command(&parts[1..], parts[0] == "pause");
+                }
+                "limit" => {
+                    self.handle_limit_command(&parts[1..]);
                 }
                 "accept" => {
-                    let save_path = if parts.len() >= 2 {
-                        parts[1..].join(" ")
-                    } else {
-                        ".".to_string()
-                    };
-                    self.handle_accept_command(&save_path, msg_tx);
+                    let force = parts.contains(&"--force");
+                    let extract = parts.contains(&"--extract");
+                    let args: Vec<&str> = parts[1..].iter().copied()
+                        .filter(|p| !matches!(*p, "--force" | "--extract"))
+                        .collect();
+                    self.handle_accept_command(&args, force, extract, fx);
                 }
                 "reject" => {
-                    self.handle_reject_command(msg_tx);
+                    self.handle_reject_command(parts.get(1).copied(), fx);
+                }
+                "copy" => {
+                    self.handle_copy_command(&parts[1..]);
+                }
+                "paste-image" => {
+                    self.handle_paste_image_command(fx);
+                }
+                "offers" => {
+                    self.handle_offers_command();
+                }
+                "downloads" => {
+                    self.handle_downloads_command();
                 }
                 _ => {
-                    self.status = format!("Unknown command: /{}", parts[0]);
+                    self.status = strings::text(Key::UnknownCommand, &[("command", parts[0])]);
                 }
             }
             return;
         }
 
         // Regular message (falls through from command handling above)
-        let current_tab = &self.tabs[self.active_tab].clone();
+        self.send_chat(text, false, fx);
+    }
+
+    /// Send `text` to the open tab as chat, or as an action (`/me`)
+    pub(crate) fn send_chat(&mut self, text: String, action: bool, fx: &mut Vec<Effect>) {
+        let tab = self.tabs[self.active_tab].clone();
+        if self.hold_global_send(&tab) {
+            self.held_input = Some(if action { format!("/me {}", text) } else { text });
+            return;
+        }
+        self.send_chat_to(&tab, text, action, fx);
+    }
+
+    /// Send `text` to `current_tab` as chat, or as an action, whether or not it's open
+    pub(crate) fn send_chat_to(&mut self, current_tab: &Tab, text: String, action: bool, fx: &mut Vec<Effect>) {
+
+        if text.len() > MAX_PART_BYTES * MAX_MESSAGE_PARTS as usize {
+            self.status = format!("Message too long ({} KB max) — try /send for big pastes", MAX_PART_BYTES * MAX_MESSAGE_PARTS as usize / 1024);
+            return;
+        }
+
+        if let Some(reason) = self.send_blocked(current_tab) {
+            self.status = reason;
+            return;
+        }
 
         // Reset scroll to bottom when sending a message
         self.scroll_offset.insert(current_tab.clone(), 0);
 
         match current_tab {
             Tab::Global => {
-                let mut msg = PlainMessage::new(self.own_id.clone(), text);
-                let msg_id = PlainMessage::generate_id();
-                msg.message_id = Some(msg_id.clone());
+                let msg = PlainMessage { action, ..PlainMessage::new(self.own_id.clone(), text) };
+                let msg_id = msg.message_id.clone().unwrap_or_default();
                 self.read_status.insert(msg_id, super::types::ReadStatus::Sent);
-                self.messages.entry(Tab::Global).or_insert_with(Vec::new).push(msg.clone());
-                let _ = msg_tx.send(OutgoingMessage::Global(msg));
+                self.push_message(Tab::Global, msg.clone());
+                send_in_parts(msg, fx, OutgoingMessage::Global);
             }
             Tab::DirectMessage(peer_id) => {
-                let mut msg = PlainMessage::direct(self.own_id.clone(), text);
-                let msg_id = PlainMessage::generate_id();
-                msg.message_id = Some(msg_id.clone());
-                self.read_status.insert(msg_id, super::types::ReadStatus::Sent);
-                self.messages.entry(current_tab.clone()).or_insert_with(Vec::new).push(msg.clone());
-                let _ = msg_tx.send(OutgoingMessage::Direct {
+                let mut msg = PlainMessage { action, ..PlainMessage::direct(self.own_id.clone(), text) };
+                msg.expire_after = self.expiry.get(current_tab).copied();
+                let msg_id = msg.message_id.clone().unwrap_or_default();
+                self.read_status.insert(msg_id, super::types::ReadStatus::Pending);
+                self.push_message(current_tab.clone(), msg.clone());
+                send_in_parts(msg, fx, |message| OutgoingMessage::Direct {
                     target_id: peer_id.clone(),
-                    message: msg,
+                    message,
                 });
             }
             Tab::Group(group_id) => {
-                if let Some(group) = self.groups.get(group_id) {
-                    let mut msg = PlainMessage::group(self.own_id.clone(), text, group_id.clone());
-                    let msg_id = PlainMessage::generate_id();
-                    msg.message_id = Some(msg_id.clone());
-                    self.read_status.insert(msg_id, super::types::ReadStatus::Sent);
-                    self.messages.entry(current_tab.clone()).or_insert_with(Vec::new).push(msg.clone());
-                    let member_ids: Vec<String> = group.members.clone();
-                    let _ = msg_tx.send(OutgoingMessage::Group {
+                if let Some(member_ids) = self.groups.get(group_id).map(|g| g.members.clone()) {
+                    let mut msg = PlainMessage { action, ..PlainMessage::group(self.own_id.clone(), text, group_id.clone()) };
+                    msg.expire_after = self.expiry.get(current_tab).copied();
+                    self.number_group_message(group_id, &mut msg);
+                    let msg_id = msg.message_id.clone().unwrap_or_default();
+                    self.read_status.insert(msg_id, super::types::ReadStatus::Pending);
+                    self.push_message(current_tab.clone(), msg.clone());
+                    send_in_parts(msg, fx, |message| OutgoingMessage::Group {
                         group_id: group_id.clone(),
-                        member_ids,
-                        message: msg,
+                        member_ids: member_ids.clone(),
+                        message,
                     });
                 } else {
                     self.status = "Group not found".to_string();
@@ -170,99 +327,4 @@ impl ChatUI {
             }
         }
     }
-
-    /// Handle /verify [nickname|peer_id] — show safety number for a peer
-    /// If no argument, try to use the current DM tab's peer
-    fn handle_verify_command(&mut self, args: &[&str], _msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
-        let peer_id = if args.is_empty() {
-            // Try current tab
-            match &self.tabs[self.active_tab] {
-                Tab::DirectMessage(id) => Some(id.clone()),
-                _ => None,
-            }
-        } else {
-            self.find_peer_by_name_or_id(args[0])
-        };
-
-        let peer_id = match peer_id {
-            Some(id) => id,
-            None => {
-                if args.is_empty() {
-                    self.status = "Usage: /verify <nickname|peer_id> (or use in a DM tab)".to_string();
-                } else {
-                    self.status = format!("Peer not found: {}", args[0]);
-                }
-                return;
-            }
-        };
-
-        let peer = match self.peers.get(&peer_id) {
-            Some(p) => p,
-            None => {
-                self.status = "Peer not connected".to_string();
-                return;
-            }
-        };
-
-        if peer.public_key.is_empty() {
-            self.status = "No public key available for this peer yet".to_string();
-            return;
-        }
-
-        let safety_number = compute_safety_number(&self.own_public_key, &peer.public_key);
-        let peer_name = self.get_peer_display_name(&peer_id);
-        let verified = if self.verified_peers.contains(&peer_id) { " ✅" } else { "" };
-
-        // Show as a system message in the current tab
-        let tab = self.tabs[self.active_tab].clone();
-        let msg = PlainMessage::system(
-            "system".to_string(),
-            format!(
-                "🔐 Safety Number with {}{}\n  Numbers: {}\n  Emoji:   {}\n\nBoth sides should see the same code.\nIf they match, run /verified {} to mark as verified.",
-                peer_name,
-                verified,
-                safety_number.numeric(),
-                safety_number.emoji(),
-                args.first().copied().unwrap_or(&peer_id[..12.min(peer_id.len())]),
-            ),
-        );
-        self.messages.entry(tab).or_insert_with(Vec::new).push(msg);
-        self.status = format!("Safety number shown for {}", peer_name);
-    }
-
-    /// Handle /verified <nickname|peer_id> — mark a peer as verified
-    fn handle_mark_verified(&mut self, args: &[&str]) {
-        let peer_id = if args.is_empty() {
-            match &self.tabs[self.active_tab] {
-                Tab::DirectMessage(id) => Some(id.clone()),
-                _ => None,
-            }
-        } else {
-            self.find_peer_by_name_or_id(args[0])
-        };
-
-        let peer_id = match peer_id {
-            Some(id) => id,
-            None => {
-                if args.is_empty() {
-                    self.status = "Usage: /verified <nickname|peer_id> (or use in a DM tab)".to_string();
-                } else {
-                    self.status = format!("Peer not found: {}", args[0]);
-                }
-                return;
-            }
-        };
-
-        let peer_name = self.get_peer_display_name(&peer_id);
-        self.verified_peers.insert(peer_id);
-        self.status = format!("✅ {} marked as verified", peer_name);
-
-        // Show confirmation in current tab
-        let tab = self.tabs[self.active_tab].clone();
-        let msg = PlainMessage::system(
-            "system".to_string(),
-            format!("✅ {} is now verified — identity confirmed!", peer_name),
-        );
-        self.messages.entry(tab).or_insert_with(Vec::new).push(msg);
-    }
 }
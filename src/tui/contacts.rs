@@ -0,0 +1,225 @@
+//! Contact policies (`/contact policy`), for your own devices talking to each other:
+//! file offers from a contact can start downloading straight away, and their calls can
+//! answer themselves after two rings. A policy belongs to an identity key and only acts
+//! while that key is verified; a contact turning up with another key loses it. Whatever
+//! a policy accepts still shows up in the chat and on the status line.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::util::expand_path;
+
+use super::state::{ChatState, Effect};
+use super::types::Tab;
+
+/// Largest offer a policy accepts without asking
+const AUTO_ACCEPT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+/// How long an auto-answered call rings first (two rings)
+const AUTO_ANSWER_AFTER: Duration = Duration::from_secs(4);
+
+const USAGE: &str = "Usage: /contact policy <name> [files=auto:<dir>|files=ask] [calls=auto|calls=ask] [off]";
+
+/// What we accept from one contact without asking
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContactPolicy {
+    /// Download their file offers into this directory
+    pub files_dir: Option<PathBuf>,
+    /// Answer their direct calls after two rings
+    pub auto_calls: bool,
+}
+
+impl ChatState {
+    /// Handle /contact policy <name> [rules...] — show or change what a verified contact
+    /// gets without asking
+    pub(crate) fn handle_contact_command(&mut self, args: &[&str]) {
+        let (Some(&"policy"), Some(target)) = (args.first(), args.get(1)) else {
+            self.status = USAGE.to_string();
+            return;
+        };
+        let peer_id = match self.find_peer_by_name_or_id(target) {
+            Ok(id) => id,
+            Err(e) => {
+                self.status = e;
+                return;
+            }
+        };
+        let name = self.get_peer_display_name(&peer_id);
+        let Some(key) = self.peers.get(&peer_id).map(|p| p.public_key.clone()) else {
+            return;
+        };
+        if self.verification_of(&peer_id).is_none() {
+            self.status = format!("🔒 Policies are only for verified contacts — /verify {} first", name);
+            return;
+        }
+
+        let mut policy = self.contact_policies.get(&key).cloned().unwrap_or_default();
+        for rule in &args[2..] {
+            match *rule {
+                "off" => policy = ContactPolicy::default(),
+                "files=ask" => policy.files_dir = None,
+                "calls=auto" => policy.auto_calls = true,
+                "calls=ask" => policy.auto_calls = false,
+                _ => match rule.strip_prefix("files=auto:") {
+                    Some(dir) if expand_path(dir).is_dir() => policy.files_dir = Some(expand_path(dir)),
+                    Some(dir) => {
+                        self.status = format!("No such directory: {}", dir);
+                        return;
+                    }
+                    None => {
+                        self.status = format!("Unknown rule {:?} — {}", rule, USAGE);
+                        return;
+                    }
+                },
+            }
+        }
+        if args.len() > 2 {
+            if policy == ContactPolicy::default() {
+                self.contact_policies.remove(&key);
+            } else {
+                self.contact_policies.insert(key, policy.clone());
+            }
+        }
+        self.status = format!("📇 {}: {}", name, describe(&policy));
+    }
+
+    /// `peer_id`'s policy, while their key is verified
+    fn policy_for(&self, peer_id: &str) -> Option<&ContactPolicy> {
+        self.verification_of(peer_id)?;
+        self.contact_policies.get(&self.peers.get(peer_id)?.public_key)
+    }
+
+    /// A peer's identity key changed: whatever their old key was allowed is gone
+    pub(crate) fn forget_policy(&mut self, peer_id: &str, old_key: &[u8]) {
+        if self.contact_policies.remove(old_key).is_some() {
+            let name = self.get_peer_display_name(peer_id);
+            let text = format!("⚠️  {}'s identity key changed — their contact policy was removed", name);
+            self.add_system_message(&Tab::Global, text.clone());
+            self.status = text;
+        }
+    }
+
+    /// Start downloading offer `file_id` if its sender's policy says to. True if it did.
+    pub(crate) fn auto_accept_offer(&mut self, file_id: &str, fx: &mut Vec<Effect>) -> bool {
+        let Some(pending) = self.pending_offers.get(file_id).cloned() else {
+            return false;
+        };
+        let Some(dir) = self.policy_for(&pending.from_peer).and_then(|p| p.files_dir.clone()) else {
+            return false;
+        };
+        let (name, filename, tab) = (self.get_peer_display_name(&pending.from_peer), pending.offer.filename.clone(), pending.tab.clone());
+        if pending.offer.size > AUTO_ACCEPT_MAX_BYTES {
+            self.add_system_message(&tab, format!(
+                "📇 {} is over the {} auto-accept limit — /accept or /reject it yourself",
+                filename,
+                Self::format_size(AUTO_ACCEPT_MAX_BYTES)
+            ));
+            return false;
+        }
+        // The trailing slash keeps it a directory even if it's gone since
+        let path = self.accept_offer(file_id.to_string(), pending, &format!("{}/", dir.display()), false, false, fx);
+        self.add_system_message(&tab, format!("🤖 Auto-accepted {} from {} (contact policy) → {}", filename, name, path.display()));
+        self.status = format!("🤖 Auto-accepted {} from {} → {}", filename, name, path.display());
+        true
+    }
+
+    /// Let a call from `peer_id` ring twice and then answer it, if their policy says
+    /// to. True if it will be.
+    pub(crate) fn schedule_auto_answer(&mut self, peer_id: &str) -> bool {
+        if !self.policy_for(peer_id).is_some_and(|p| p.auto_calls) {
+            return false;
+        }
+        self.auto_answer = Some((peer_id.to_string(), Instant::now() + AUTO_ANSWER_AFTER));
+        true
+    }
+
+    /// Answer a call whose two rings are up (run from housekeeping)
+    pub(crate) fn check_auto_answer(&mut self) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        let Some((peer_id, at)) = self.auto_answer.clone() else {
+            return fx;
+        };
+        if Instant::now() < at {
+            return fx;
+        }
+        self.auto_answer = None;
+        // Only if it's still ringing, and still theirs to answer
+        if self.pending_call_from.as_deref() != Some(peer_id.as_str()) || !self.policy_for(&peer_id).is_some_and(|p| p.auto_calls) {
+            return fx;
+        }
+        self.handle_accept_call_command(&mut fx);
+        if self.active_call.is_some() {
+            let name = self.get_peer_display_name(&peer_id);
+            self.add_system_message(&Tab::DirectMessage(peer_id), format!("🤖 Auto-answered the call from {} (contact policy)", name));
+            self.status = format!("🤖 Auto-answered the call from {}", name);
+        }
+        fx
+    }
+}
+
+/// What `policy` lets through, in a few words
+pub(super) fn describe(policy: &ContactPolicy) -> String {
+    let files = match policy.files_dir {
+        Some(ref dir) => format!("files download to {} (up to {})", dir.display(), ChatState::format_size(AUTO_ACCEPT_MAX_BYTES)),
+        None => "files ask".to_string(),
+    };
+    let calls = if policy.auto_calls { "calls answer after two rings" } else { "calls ask" };
+    format!("{}, {}", files, calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{OutgoingMessage, PeerDisplay, PeerUpdate};
+    use crate::protocol::{CallSalt, FileOffer, PlainMessage};
+    use crate::tui::types::Verified;
+
+    fn offer(file_id: &str) -> PlainMessage {
+        let offer = FileOffer {
+            file_id: file_id.to_string(),
+            filename: "notes.txt".to_string(),
+            size: 5,
+            checksum: blake3::hash(b"notes").to_hex().to_string(),
+            total_chunks: 1,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        };
+        PlainMessage::file_offer("a".repeat(32), offer, true)
+    }
+
+    #[test]
+    fn test_policies_for_verified_contacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let alice = "a".repeat(32);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let display = |key: u8| PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![key; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display(1))]);
+
+        let rules = format!("/contact policy alice files=auto:{} calls=auto", dir.path().display());
+        state.handle_command(&rules);
+        assert!(state.contact_policies.is_empty(), "{}", state.status);
+        state.verified_peers.insert(vec![1; 32], Verified::Local { at: 0 });
+        state.handle_command(&rules);
+        assert!(state.status.contains("calls answer after two rings"), "{}", state.status);
+
+        // Offers start downloading, and say so
+        let fx = state.ingest_message(offer("f1"));
+        assert!(fx.iter().any(|e| matches!(e, Effect::Send(OutgoingMessage::Direct { message, .. }) if message.file_response == Some(true))));
+        assert_eq!(state.active_transfers["f1"].save_path, dir.path().join("notes.txt"));
+        assert!(state.status.starts_with("🤖 Auto-accepted notes.txt"));
+
+        // Calls ring twice, then answer
+        state.ingest_message(PlainMessage::call_request(alice.clone(), CallSalt::generate("c1".to_string())));
+        assert!(state.check_auto_answer().is_empty());
+        state.auto_answer.as_mut().unwrap().1 = Instant::now();
+        let fx = state.check_auto_answer();
+        assert!(fx.iter().any(|e| matches!(e, Effect::Send(OutgoingMessage::Direct { message, .. }) if message.call_accept == Some(true))));
+        assert!(state.active_call.is_some());
+
+        // A new identity key loses the policy
+        state.apply_peer_updates(vec![PeerUpdate::Changed(alice.clone(), display(2))]);
+        assert!(state.contact_policies.is_empty());
+        state.ingest_message(offer("f2"));
+        assert!(state.pending_offers.contains_key("f2"));
+    }
+}
@@ -0,0 +1,267 @@
+//! Control messages: whatever a peer sends that isn't there to be read — typing,
+//! nicknames, presence, receipts, call signalling, file control, group housekeeping.
+//! Every incoming message is sorted before anything is added to a tab: control goes to
+//! its handler (which may leave a note of its own, like "alice is now known as al"),
+//! and only chat is ever stored, so unread counts, exports, expiry and history sharing
+//! never see control traffic.
+
+use std::time::Instant;
+
+use crate::protocol::PlainMessage;
+use crate::strings::Key;
+
+use super::state::{ChatState, Effect};
+use super::types::ReadStatus;
+
+/// What a control message is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Control {
+    Typing,
+    Nickname,
+    Presence,
+    ReadReceipt,
+    CallRequest,
+    CallAccept,
+    CallHangup,
+    CallMuted,
+    CallKeepalive,
+    CallHold,
+    ExpirePolicy,
+    Verification,
+    History,
+    GroupResend,
+    InviteDeclined,
+    GroupRoster,
+    GroupInvite,
+    FileOffer,
+    FileChunk,
+    FileResponse,
+    DmRequest,
+    /// Session and group key housekeeping, which the client deals with itself
+    Session,
+}
+
+/// What `msg` is for, if it isn't chat. A message with several control fields counts
+/// as the first here, as it always has.
+pub(crate) fn control_of(msg: &PlainMessage) -> Option<Control> {
+    let control = if msg.typing.is_some() {
+        Control::Typing
+    } else if msg.nickname.is_some() {
+        Control::Nickname
+    } else if msg.presence.is_some() {
+        Control::Presence
+    } else if msg.read_receipt.is_some() {
+        Control::ReadReceipt
+    } else if msg.call_request == Some(true) {
+        Control::CallRequest
+    } else if msg.call_accept.is_some() {
+        Control::CallAccept
+    } else if msg.call_hangup == Some(true) {
+        Control::CallHangup
+    } else if msg.call_muted.is_some() {
+        Control::CallMuted
+    } else if msg.call_keepalive.is_some() {
+        Control::CallKeepalive
+    } else if msg.call_hold.is_some() {
+        Control::CallHold
+    } else if msg.expire_policy.is_some() {
+        Control::ExpirePolicy
+    } else if msg.verification.is_some() {
+        Control::Verification
+    } else if msg.history.is_some() {
+        Control::History
+    } else if msg.group_resend.is_some() {
+        Control::GroupResend
+    } else if msg.invite_declined.is_some() {
+        Control::InviteDeclined
+    } else if msg.group_roster.is_some() {
+        Control::GroupRoster
+    } else if msg.group_invite.is_some() {
+        Control::GroupInvite
+    } else if msg.file_offer.is_some() {
+        Control::FileOffer
+    } else if msg.file_chunk.is_some() {
+        Control::FileChunk
+    } else if msg.file_response.is_some() {
+        Control::FileResponse
+    } else if msg.dm_request {
+        Control::DmRequest
+    } else if msg.session_reset || msg.sender_key.is_some() {
+        Control::Session
+    } else {
+        return None;
+    };
+    Some(control)
+}
+
+impl ChatState {
+    /// Act on a control message. Nothing here adds `msg` itself to a tab.
+    pub(crate) fn handle_control(&mut self, control: Control, mut msg: PlainMessage, fx: &mut Vec<Effect>) {
+        // Signaling for the call on hold is handled with that call brought forward
+        let call_signal = matches!(control, Control::CallAccept | Control::CallHangup | Control::CallMuted | Control::CallKeepalive | Control::CallHold);
+        if call_signal && self.about_held_call(&msg) {
+            return self.on_held_call(|state| state.handle_control(control, msg, fx));
+        }
+        match control {
+            Control::Typing => {
+                if msg.typing == Some(true) {
+                    self.typing_peers.insert(msg.sender.clone(), Instant::now());
+                } else {
+                    self.typing_peers.remove(&msg.sender);
+                }
+            }
+            // A member's new name, announced to the group. The pairwise one is the
+            // client's: it updates the peer and sends its own notice.
+            Control::Nickname => self.group_nickname(&msg),
+            Control::Presence => {
+                if let Some(ref presence) = msg.presence {
+                    self.handle_presence(&msg.sender, presence);
+                }
+            }
+            Control::ReadReceipt => {
+                if let Some(id) = msg.read_receipt {
+                    self.read_status.insert(id, ReadStatus::Read);
+                }
+            }
+            Control::CallRequest => {
+                if self.dnd.is_some() {
+                    self.decline_call(&msg, Key::InDnd, Key::WhyDnd, fx);
+                } else if !self.traffic.allows_calls() {
+                    self.decline_call(&msg, Key::InLowBandwidth, Key::WhyLowBandwidth, fx);
+                } else {
+                    self.handle_incoming_call_request(&msg, fx);
+                }
+            }
+            Control::CallAccept => {
+                let accept = msg.call_accept == Some(true);
+                self.handle_call_response(&msg, accept, fx);
+            }
+            Control::CallHangup => self.handle_remote_hangup(&msg, fx),
+            Control::CallMuted => {
+                let muted = msg.call_muted == Some(true);
+                self.handle_call_muted(&msg, muted);
+            }
+            Control::CallKeepalive => {
+                let ping = msg.call_keepalive == Some(true);
+                self.handle_call_keepalive(&msg, ping, fx);
+            }
+            Control::CallHold => {
+                let on_hold = msg.call_hold == Some(true);
+                self.handle_call_hold(&msg, on_hold);
+            }
+            Control::ExpirePolicy => {
+                let ttl = msg.expire_policy.unwrap_or_default();
+                self.handle_expire_policy(&msg, ttl);
+            }
+            Control::Verification => {
+                if let Some(verification) = msg.verification.take() {
+                    self.handle_verification(&msg, verification);
+                }
+            }
+            Control::History => {
+                if let Some(sync) = msg.history.take() {
+                    self.handle_history(&msg.sender, sync, fx);
+                }
+            }
+            Control::GroupResend => {
+                if let Some(request) = msg.group_resend.take() {
+                    self.handle_group_resend(&msg.sender, request, fx);
+                }
+            }
+            Control::InviteDeclined => {
+                if let Some(ref group_id) = msg.invite_declined {
+                    self.handle_invite_declined(&msg.sender, group_id);
+                }
+            }
+            // Another member's view of a group's membership
+            Control::GroupRoster => {
+                if let (Some(roster), Some(group_id)) = (msg.group_roster.take(), msg.group_id.clone()) {
+                    self.handle_group_roster(&msg.sender, &group_id, roster, fx);
+                }
+            }
+            Control::GroupInvite => {
+                if let Some(invite) = msg.group_invite.clone() {
+                    self.handle_group_invite(msg, invite, fx);
+                }
+            }
+            Control::FileOffer => self.handle_file_offer(msg, fx),
+            Control::FileChunk => self.handle_file_chunk(msg),
+            Control::FileResponse => {
+                let accept = msg.file_response == Some(true);
+                self.handle_file_response(msg, accept, fx);
+            }
+            Control::DmRequest => self.dm_requested(&msg.sender),
+            Control::Session => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::protocol::{CallSalt, FileOffer, GroupRoster, Presence};
+    use crate::tui::types::{GroupInfo, Tab};
+
+    const ME: &str = "me000000000000000000";
+    const ALICE: &str = "alice000000000000000";
+
+    fn stored(state: &ChatState) -> Vec<&PlainMessage> {
+        state.messages.values().flatten().collect()
+    }
+
+    #[test]
+    fn test_control_messages_never_reach_a_tab() {
+        let mut state = ChatState::new(ME.to_string(), None, vec![0; 32]);
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(ALICE.to_string(), display)]);
+        state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members: vec![ALICE.to_string()], join_token: None });
+        state.ensure_tab(&Tab::Group("g1".to_string()));
+        let alice = || ALICE.to_string();
+        state.messages.clear();
+        state.unread.clear();
+
+        // The quiet ones do their thing and leave nothing behind
+        state.ingest_message(PlainMessage::typing(alice(), true, true));
+        assert!(state.typing_peers.contains_key(ALICE));
+        state.ingest_message(PlainMessage::presence(alice(), &Presence::Away(Some("lunch".to_string()))));
+        assert_eq!(state.peer_away.get(ALICE), Some(&Some("lunch".to_string())));
+        state.ingest_message(PlainMessage::read_receipt(alice(), "m1".to_string(), true));
+        assert_eq!(state.read_status.get("m1"), Some(&ReadStatus::Read));
+        state.ingest_message(PlainMessage::dm_request(alice()));
+        assert!(state.tabs.contains(&Tab::DirectMessage(alice())));
+        // The client already dealt with these
+        state.ingest_message(PlainMessage::nickname(alice(), "al".to_string()));
+        state.ingest_message(PlainMessage::session_reset(alice()));
+        // Even with text on them, or missing what their handler needs
+        state.ingest_message(PlainMessage { content: "psst".to_string(), ..PlainMessage::nickname(alice(), "al".to_string()) });
+        state.ingest_message(PlainMessage::system(alice(), String::new()));
+        state.ingest_message(PlainMessage { group_id: None, ..PlainMessage::group_roster(alice(), "g1".to_string(), GroupRoster { digest: Vec::new(), members: Vec::new(), reply: false }) });
+        state.ingest_message(PlainMessage::typing(alice(), false, true));
+        assert!(state.typing_peers.is_empty());
+        assert!(stored(&state).is_empty(), "{:?}", stored(&state));
+        assert!(state.unread.values().all(|n| *n == 0));
+
+        // The rest may leave a note or a row of our own, but never the message itself
+        let renamed = PlainMessage { group_id: Some("g1".to_string()), ..PlainMessage::nickname(alice(), "al".to_string()) };
+        state.ingest_message(renamed);
+        state.ingest_message(PlainMessage::expire_policy(alice(), 60, true));
+        state.ingest_message(PlainMessage::call_request(alice(), CallSalt::generate("c1".to_string())));
+        state.ingest_message(PlainMessage::call_hangup(alice()));
+        let offer = FileOffer {
+            file_id: "f1".to_string(),
+            filename: "a.txt".to_string(),
+            size: 1,
+            checksum: String::new(),
+            total_chunks: 1,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        };
+        state.ingest_message(PlainMessage::file_offer(alice(), offer, true));
+        let notes: Vec<&str> = stored(&state).iter().map(|m| m.content.as_str()).collect();
+        assert!(notes.iter().any(|n| n.contains("is now known as al")), "{:?}", notes);
+        assert!(notes.iter().any(|n| n.starts_with("📎 a.txt")), "{:?}", notes);
+        assert!(stored(&state).iter().all(|m| m.event.is_some() || control_of(m).is_none()));
+    }
+}
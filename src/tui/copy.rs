@@ -0,0 +1,85 @@
+//! `/copy [n]` puts a message's text on the system clipboard, without the time and
+//! sender the screen shows in front of it, and `/copy code [n]` just the inside of a
+//! ``` block (found as the renderer finds them, see fences.rs). Selecting with the mouse
+//! can't do either once lines wrap. Needs the `clipboard` feature, like /paste-image.
+
+use crate::protocol::PlainMessage;
+
+use super::clipboard;
+use super::fences::code_blocks;
+use super::state::ChatState;
+
+impl ChatState {
+    /// What /copy [code] [n] would copy from this tab, and whose it is
+    fn copy_target(&self, args: &[&str]) -> Result<(String, String), String> {
+        let (code, n) = match args {
+            ["code", rest @ ..] => (true, rest.first()),
+            rest => (false, rest.first()),
+        };
+        let n = match n.map(|n| n.parse::<usize>()) {
+            None => 1,
+            Some(Ok(n)) if n > 0 => n,
+            Some(_) => return Err("Usage: /copy [n] or /copy code [n] — n counts back from the last (1 is the last)".to_string()),
+        };
+        let tab = &self.tabs[self.active_tab];
+        let mut chat = self.messages.get(tab).into_iter().flatten().rev().filter(|m| !m.system);
+        if !code {
+            let m = chat.nth(n - 1).ok_or_else(|| format!("No message {} back here to copy", n))?;
+            return Ok((m.content.clone(), format!("{} message", self.whose(m))));
+        }
+        chat.flat_map(|m| code_blocks(&m.content).into_iter().rev().map(move |block| (m, block)))
+            .nth(n - 1)
+            .map(|(m, block)| (block, format!("{} code block", self.whose(m))))
+            .ok_or_else(|| format!("No code block {} back here to copy", n))
+    }
+
+    /// "your" or "alice's"
+    fn whose(&self, m: &PlainMessage) -> String {
+        if m.sender == self.own_id {
+            "your".to_string()
+        } else {
+            format!("{}'s", self.get_peer_display_name(&m.sender))
+        }
+    }
+
+    /// Handle /copy [n] and /copy code [n]
+    pub(crate) fn handle_copy_command(&mut self, args: &[&str]) {
+        let (text, what) = match self.copy_target(args) {
+            Ok(found) => found,
+            Err(e) => {
+                self.status = e;
+                return;
+            }
+        };
+        self.status = match clipboard::copy_text(&text) {
+            Ok(()) => format!("📋 Copied {} ({})", what, Self::format_size(text.len() as u64)),
+            Err(e) => format!("📋 {:#}", e),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+
+    #[test]
+    fn test_copy_finds_messages_and_code_blocks() {
+        let text = "try this:\n```rust\nfn main() {\n    println!(\"hi\");\n}\n```\nor\n```\n  ls -la\n";
+        let alice = "aa".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+        state.ingest_message(PlainMessage::new(alice.clone(), text.to_string()));
+        state.ingest_message(PlainMessage::new(alice.clone(), "no code here".to_string()));
+        state.add_system_message(&crate::tui::types::Tab::Global, "a notice".to_string());
+
+        // Notices don't count; code is found scanning back through the messages
+        assert_eq!(state.copy_target(&[]), Ok(("no code here".to_string(), "alice's message".to_string())));
+        assert_eq!(state.copy_target(&["2"]).unwrap().0, text);
+        assert_eq!(state.copy_target(&["code"]).unwrap(), ("  ls -la\n".to_string(), "alice's code block".to_string()));
+        assert!(state.copy_target(&["code", "2"]).unwrap().0.starts_with("fn main()"));
+        assert_eq!(state.copy_target(&["code", "3"]), Err("No code block 3 back here to copy".to_string()));
+        assert!(state.copy_target(&["0"]).unwrap_err().starts_with("Usage"));
+    }
+}
@@ -0,0 +1,116 @@
+//! Finding a peer whose key exchange broadcast we never saw: `/dm <session id>` asks the
+//! relay to pass a Discover to that session, which answers with a key exchange of its
+//! own. The DM tab opens once the session is up; a relay that doesn't have the session
+//! says so, and one that never answers times out.
+
+use std::time::{Duration, Instant};
+
+use crate::client::OutgoingMessage;
+use crate::protocol::{short_id, Message};
+
+use super::state::{ChatState, Effect};
+
+/// How long to wait for a discovered peer's key exchange
+const DISCOVER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A full session id: 32 hex digits
+fn is_session_id(text: &str) -> bool {
+    text.len() == 32 && text.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+impl ChatState {
+    /// Ask the relay for `target` if it's a session id we don't know yet. False if it
+    /// isn't one, so the caller can say no such peer.
+    pub(crate) fn discover_peer(&mut self, target: &str, fx: &mut Vec<Effect>) -> bool {
+        let target = target.to_ascii_lowercase();
+        if !is_session_id(&target) || target == self.own_id {
+            return false;
+        }
+        if self.discovering.contains_key(&target) {
+            self.status = format!("🔎 Still looking for {}...", short_id(&target));
+            return true;
+        }
+        self.discovering.insert(target.clone(), Instant::now());
+        self.status = format!("🔎 Looking for {} on the relay...", short_id(&target));
+        fx.push(Effect::Send(OutgoingMessage::Signal(Message::Discover {
+            target_session: target,
+            from: self.own_id.clone(),
+        })));
+        true
+    }
+
+    /// Ask the relay for `target` without opening anything: if it's there, it turns up
+    /// as a new peer
+    pub(crate) fn discover_quietly(&self, target: &str, fx: &mut Vec<Effect>) {
+        if is_session_id(target) && target != self.own_id && !self.peers.contains_key(target) {
+            fx.push(Effect::Send(OutgoingMessage::Signal(Message::Discover {
+                target_session: target.to_string(),
+                from: self.own_id.clone(),
+            })));
+        }
+    }
+
+    /// Open the DM we were waiting on, now that `peer_id`'s session is up
+    pub(crate) fn discovered(&mut self, peer_id: &str, fx: &mut Vec<Effect>) {
+        if self.discovering.remove(peer_id).is_some() {
+            self.open_dm_tab(peer_id, Some(fx));
+        }
+    }
+
+    /// The relay says `session_id` isn't connected
+    pub(crate) fn peer_not_found(&mut self, session_id: &str) {
+        if self.invite_not_found(session_id) {
+            return;
+        }
+        if self.discovering.remove(session_id).is_some() {
+            self.status = format!("❌ {} is not connected to this relay", short_id(session_id));
+        }
+    }
+
+    /// Give up on lookups nobody answered (run from housekeeping)
+    pub(crate) fn expire_discoveries(&mut self) {
+        let expired: Vec<String> = self.discovering.iter()
+            .filter(|(_, asked)| asked.elapsed() >= DISCOVER_TIMEOUT)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            self.discovering.remove(&id);
+            self.status = format!("❌ No answer from {}: peer not connected to this relay", short_id(&id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::tui::types::Tab;
+
+    #[test]
+    fn test_dm_by_session_id_discovers_the_peer() {
+        let peer = "ab".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let fx = state.handle_command(&format!("/dm {}", peer.to_uppercase()));
+        assert!(matches!(
+            &fx[..],
+            [Effect::Send(OutgoingMessage::Signal(Message::Discover { target_session, .. }))] if *target_session == peer
+        ));
+        // A second /dm while the first is out doesn't ask again
+        assert!(state.handle_command(&format!("/dm {}", peer)).is_empty());
+
+        let found = PeerUpdate::Added(peer.clone(), PeerDisplay { nickname: None, public_key: vec![1; 32] });
+        let fx = state.apply_peer_updates(vec![found]);
+        assert_eq!(state.tabs[state.active_tab], Tab::DirectMessage(peer.clone()));
+        assert!(fx.iter().any(|e| matches!(e, Effect::Send(OutgoingMessage::Direct { message, .. }) if message.dm_request)));
+
+        // Not on the relay, or no answer in time
+        let gone = "cd".repeat(16);
+        state.handle_command(&format!("/dm {}", gone));
+        state.peer_not_found(&gone);
+        assert!(state.status.contains("not connected"), "{}", state.status);
+        state.handle_command(&format!("/dm {}", gone));
+        state.discovering.insert(gone.clone(), Instant::now() - DISCOVER_TIMEOUT);
+        state.expire_discoveries();
+        assert!(state.discovering.is_empty());
+    }
+}
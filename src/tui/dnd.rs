@@ -0,0 +1,168 @@
+//! Do not disturb: `/dnd on|off|<duration>`. Calls are declined for us, new file offers
+//! and DM tabs wait without touching the status line, and mentions don't escalate in
+//! the tab bar. A DM containing `@urgent` still gets through, once per peer per hour.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::protocol::PlainMessage;
+
+use super::helpers::{format_ttl, parse_ttl};
+use super::state::ChatState;
+use super::types::Tab;
+
+/// How often one peer's `@urgent` may break through
+const URGENT_COOLDOWN: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Default)]
+pub(crate) struct Dnd {
+    /// When it turns itself off (None = until /dnd off)
+    pub until: Option<Instant>,
+    /// Messages that came in meanwhile, per tab
+    pub missed: HashMap<Tab, u64>,
+}
+
+impl ChatState {
+    /// Handle /dnd on|off|<duration>
+    pub(crate) fn handle_dnd_command(&mut self, args: &[&str]) {
+        match args.first().copied() {
+            Some("on") => {
+                self.dnd = Some(Dnd::default());
+                self.status = "🔕 Do not disturb — /dnd off to end it".to_string();
+            }
+            Some("off") => {
+                if self.dnd.is_none() {
+                    self.status = "Do not disturb is already off".to_string();
+                    return;
+                }
+                self.end_dnd();
+            }
+            Some(arg) => match parse_ttl(arg) {
+                Some(secs) => {
+                    self.dnd = Some(Dnd { until: Some(Instant::now() + Duration::from_secs(secs)), ..Default::default() });
+                    self.status = format!("🔕 Do not disturb for {}", format_ttl(secs));
+                }
+                None => self.status = format!("Invalid duration: {} (try on, off, 30m or 2h)", arg),
+            },
+            None => {
+                self.status = match self.dnd_label() {
+                    Some(label) => format!("{} — /dnd off to end it", label),
+                    None => "Usage: /dnd on|off|<duration>".to_string(),
+                };
+            }
+        }
+    }
+
+    /// "🔕 DND" for the header, with the time left if it ends by itself
+    pub(crate) fn dnd_label(&self) -> Option<String> {
+        let dnd = self.dnd.as_ref()?;
+        Some(match dnd.until {
+            Some(until) => {
+                let left = until.saturating_duration_since(Instant::now()).as_secs().max(1);
+                format!("🔕 DND ({} left)", format_ttl(left))
+            }
+            None => "🔕 DND".to_string(),
+        })
+    }
+
+    /// Turn do not disturb off and say what came in while it was on
+    fn end_dnd(&mut self) {
+        let Some(dnd) = self.dnd.take() else {
+            return;
+        };
+        let mut missed: Vec<(String, u64)> = dnd.missed.iter().map(|(tab, n)| (self.get_tab_name(tab), *n)).collect();
+        missed.sort();
+        let summary = if missed.is_empty() {
+            "🔔 Do not disturb is off — nothing came in".to_string()
+        } else {
+            let per_tab: Vec<String> = missed.iter().map(|(name, n)| format!("{}: {}", name, n)).collect();
+            format!("🔔 Do not disturb is off — while it was on: {}", per_tab.join(", "))
+        };
+        self.status = "🔔 Do not disturb is off".to_string();
+        let tab = self.tabs[self.active_tab].clone();
+        self.add_system_message(&tab, summary);
+    }
+
+    /// End a timed do not disturb once its time is up (run from housekeeping).
+    /// Returns true if the header needs redrawing.
+    pub(crate) fn check_dnd(&mut self) -> bool {
+        let Some(until) = self.dnd.as_ref().map(|d| d.until) else {
+            return false;
+        };
+        if until.is_some_and(|until| Instant::now() >= until) {
+            self.end_dnd();
+        }
+        until.is_some()
+    }
+
+    /// Put `text` on the status line, unless we're not to be disturbed
+    pub(crate) fn flash_status(&mut self, text: String) {
+        if self.dnd.is_none() {
+            self.status = text;
+        }
+    }
+
+    /// Note a message for the summary shown when do not disturb ends
+    pub(crate) fn count_missed(&mut self, tab: &Tab, msg: &PlainMessage) {
+        if let Some(ref mut dnd) = self.dnd {
+            if !msg.system && !msg.synced && msg.sender != self.own_id {
+                *dnd.missed.entry(tab.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    /// Let an `@urgent` DM through do not disturb, if its sender hasn't used that
+    /// in the last hour
+    pub(crate) fn check_urgent(&mut self, msg: &PlainMessage) {
+        if self.dnd.is_none() || !msg.content.to_lowercase().contains("@urgent") {
+            return;
+        }
+        if self.urgent_allowed.get(&msg.sender).is_some_and(|at| at.elapsed() < URGENT_COOLDOWN) {
+            return;
+        }
+        self.urgent_allowed.insert(msg.sender.clone(), Instant::now());
+        self.status = format!("🚨 Urgent from {} (through do not disturb)", self.get_peer_display_name(&msg.sender));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::OutgoingMessage;
+    use crate::protocol::CallSalt;
+    use crate::tui::state::Effect;
+
+    #[test]
+    fn test_dnd_declines_calls_and_summarises() {
+        let peer = "peer".repeat(8);
+        let mut state = ChatState::new("me".repeat(16), Some("me".to_string()), vec![0; 32]);
+        state.handle_command("/dnd 30m");
+        assert!(state.dnd.as_ref().is_some_and(|d| d.until.is_some()));
+
+        let fx = state.ingest_message(PlainMessage::call_request(peer.clone(), CallSalt::generate("c1".to_string())));
+        assert!(state.pending_call_from.is_none());
+        let sent: Vec<&PlainMessage> = fx.iter().filter_map(|e| match e {
+            Effect::Send(OutgoingMessage::Direct { message, .. }) => Some(message),
+            _ => None,
+        }).collect();
+        assert!(matches!(sent[..], [reject, notice] if reject.call_accept == Some(false) && notice.content == "me is in do-not-disturb"));
+
+        let status = state.status.clone();
+        state.ingest_message(PlainMessage::direct(peer.clone(), "hi".to_string()));
+        state.ingest_message(PlainMessage::new(peer.clone(), "all".to_string()));
+        assert_eq!(state.status, status);
+
+        // One urgent ping per hour gets through
+        state.ingest_message(PlainMessage::direct(peer.clone(), "@URGENT prod is down".to_string()));
+        assert!(state.status.starts_with("🚨"));
+        state.status.clear();
+        state.ingest_message(PlainMessage::direct(peer.clone(), "@urgent again".to_string()));
+        assert!(state.status.is_empty());
+
+        state.dnd.as_mut().unwrap().until = Some(Instant::now());
+        assert!(state.check_dnd());
+        assert!(state.dnd.is_none());
+        let summary = &state.messages[&Tab::Global].last().unwrap().content;
+        assert!(summary.contains("#global: 1") && summary.contains(": 3"), "{}", summary);
+    }
+}
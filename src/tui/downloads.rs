@@ -0,0 +1,143 @@
+//! Where accepted files go. A bare /accept saves into the download directory
+//! (`"download_dir"` in config.json, else ~/Downloads/wsp, made when first needed), and
+//! offer rows say where that will be before anything is accepted. Offers over
+//! `"max_auto_size_mb"` need a path typed out as confirmation. /downloads lists what
+//! arrived this session and where it ended up.
+
+use std::path::PathBuf;
+
+use crate::protocol::FileOffer;
+
+use super::files::{safe_filename, unique_path};
+use super::state::ChatState;
+use super::types::ActiveTransfer;
+
+/// Largest offer a bare /accept takes, unless the config says otherwise
+pub(crate) const DEFAULT_MAX_AUTO_SIZE: u64 = 100 * 1024 * 1024;
+
+/// A file (or unpacked folder) saved this session
+#[derive(Debug, Clone)]
+pub(crate) struct Download {
+    pub filename: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub from_peer: String,
+    pub at: chrono::DateTime<chrono::Local>,
+}
+
+impl ChatState {
+    /// Where a bare /accept would save `offer` right now, or None if it's too big to
+    /// take without a path
+    pub(crate) fn default_destination(&self, offer: &FileOffer) -> Option<PathBuf> {
+        (offer.size <= self.max_auto_size)
+            .then(|| unique_path(self.download_dir.join(safe_filename(&offer.filename, &offer.file_id))))
+    }
+
+    /// The save path for a bare /accept of `offer`: the download directory, made if it
+    /// isn't there yet. The error says why it has to be accepted with a path instead.
+    pub(crate) fn download_dir_for(&self, offer: &FileOffer) -> Result<String, String> {
+        if offer.size > self.max_auto_size {
+            return Err(format!(
+                "{} is {}, over the {} a bare /accept takes — name where to save it to confirm: /accept [n] <path>",
+                offer.filename,
+                Self::format_size(offer.size),
+                Self::format_size(self.max_auto_size)
+            ));
+        }
+        std::fs::create_dir_all(&self.download_dir)
+            .map_err(|e| format!("Couldn't create {}: {}", self.download_dir.display(), e))?;
+        // The trailing slash keeps it a directory
+        Ok(format!("{}/", self.download_dir.display()))
+    }
+
+    /// Note a transfer that was just saved, for /downloads
+    pub(crate) fn record_download(&mut self, transfer: &ActiveTransfer) {
+        self.downloads.push(Download {
+            filename: transfer.offer.filename.clone(),
+            path: transfer.save_path.clone(),
+            size: transfer.offer.size,
+            from_peer: transfer.from_peer.clone(),
+            at: chrono::Local::now(),
+        });
+    }
+
+    /// Handle /downloads: files received this session, with where they are
+    pub(crate) fn handle_downloads_command(&mut self) {
+        if self.downloads.is_empty() {
+            self.status = format!("Nothing received this session — a bare /accept saves to {}", self.download_dir.display());
+            return;
+        }
+        let mut lines = vec![format!("📥 Received this session (a bare /accept saves to {}):", self.download_dir.display())];
+        for (i, download) in self.downloads.iter().enumerate() {
+            lines.push(format!(
+                "  {}. {} ({}) from {} at {} → {}",
+                i + 1,
+                download.filename,
+                Self::format_size(download.size),
+                self.get_peer_display_name(&download.from_peer),
+                download.at.format("%H:%M"),
+                download.path.display()
+            ));
+        }
+        let tab = self.tabs[self.active_tab].clone();
+        self.add_system_message(&tab, lines.join("\n"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::protocol::{FileChunk, PlainMessage};
+    use crate::tui::types::{Tab, FILE_CHUNK_SIZE};
+
+    fn offer(file_id: &str, name: &str, data: &[u8]) -> FileOffer {
+        FileOffer {
+            file_id: file_id.to_string(),
+            filename: name.to_string(),
+            size: data.len() as u64,
+            checksum: blake3::hash(data).to_hex().to_string(),
+            total_chunks: data.len().div_ceil(FILE_CHUNK_SIZE).max(1) as u32,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_bare_accept_saves_to_the_download_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let alice = "aa".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.download_dir = dir.path().join("downloads");
+        state.max_auto_size = 8;
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+
+        // Too big for a bare /accept: it says so, and nothing is accepted
+        let big = offer("f1", "big.bin", b"0123456789");
+        state.ingest_message(PlainMessage::file_offer(alice.clone(), big, false));
+        assert!(state.handle_command("/accept").is_empty());
+        assert!(state.status.contains("over the 8 bytes a bare /accept takes"), "{}", state.status);
+        assert!(state.pending_offers.contains_key("f1"));
+        state.handle_command("/reject");
+
+        // The row says where it will go; the directory is made on accepting
+        let data = b"notes";
+        state.ingest_message(PlainMessage::file_offer(alice.clone(), offer("f2", "../notes.txt", data), false));
+        let dest = state.download_dir.join("notes.txt");
+        let row = state.messages[&Tab::Global].last().unwrap().clone();
+        assert!(state.event_text(&row).contains(&format!("saves to {}", dest.display())), "{}", state.event_text(&row));
+        assert!(!state.download_dir.exists());
+        state.handle_command("/accept");
+        assert!(state.download_dir.is_dir());
+        let chunk = FileChunk { file_id: "f2".to_string(), index: 0, data: data.to_vec() };
+        state.ingest_message(PlainMessage::file_chunk(alice.clone(), chunk, false));
+        assert_eq!(std::fs::read(&dest).unwrap(), data);
+
+        state.handle_command("/downloads");
+        let listed = state.messages[&Tab::Global].last().unwrap().content.clone();
+        assert!(listed.contains("1. ../notes.txt (5 bytes) from alice at "), "{}", listed);
+        assert!(listed.ends_with(&format!("→ {}", dest.display())), "{}", listed);
+    }
+}
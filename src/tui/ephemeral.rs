@@ -0,0 +1,89 @@
+//! Paranoid mode (`wsp chat --ephemeral`): commands that would write to disk refuse,
+//! or warn loudly where the write is the point (saving a file someone sent). Message
+//! text is overwritten in memory when it's dropped, in any mode: tabs closed, messages
+//! expired, and everything on exit.
+
+use zeroize::Zeroize;
+
+use crate::protocol::PlainMessage;
+
+use super::state::ChatState;
+use super::types::Tab;
+
+/// Overwrite what a message said before it's dropped
+pub(crate) fn wipe(mut msg: PlainMessage) {
+    msg.content.zeroize();
+    msg.nickname.zeroize();
+    if let Some(ref mut chunk) = msg.file_chunk {
+        chunk.data.zeroize();
+    }
+}
+
+impl ChatState {
+    /// In ephemeral mode, refuse `command` (which would write to disk) and say so.
+    /// True if it was refused.
+    pub(crate) fn refuse_disk_write(&mut self, command: &str) -> bool {
+        if self.ephemeral {
+            self.status = format!("🚫 EPHEMERAL: {} would write to disk, so it's off in this mode", command);
+        }
+        self.ephemeral
+    }
+
+    /// Drop a tab's messages, wiping them
+    pub(crate) fn drop_tab_messages(&mut self, tab: &Tab) {
+        for msg in self.messages.remove(tab).unwrap_or_default() {
+            wipe(msg);
+        }
+    }
+
+    /// Wipe every message and half-received file (on exit)
+    pub(crate) fn wipe_all(&mut self) {
+        for msg in self.messages.drain().flat_map(|(_, messages)| messages) {
+            wipe(msg);
+        }
+        for mut transfer in self.active_transfers.drain().map(|(_, t)| t) {
+            transfer.chunks_received.iter_mut().flatten().for_each(|chunk| chunk.zeroize());
+        }
+        self.offer_rows.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::FileOffer;
+    use crate::tui::state::Effect;
+
+    #[test]
+    fn test_ephemeral_mode_keeps_off_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.ephemeral = true;
+        state.ingest_message(PlainMessage::new("peer".repeat(8), "secret".to_string()));
+
+        let path = dir.path().join("out.txt");
+        state.handle_command(&format!("/export {}", path.display()));
+        assert!(!path.exists());
+        assert!(state.status.contains("EPHEMERAL"), "{}", state.status);
+
+        // Accepting a file still works, with a warning in the chat
+        let offer = FileOffer {
+            file_id: "f1".to_string(),
+            filename: "a.txt".to_string(),
+            size: 3,
+            checksum: blake3::hash(b"abc").to_hex().to_string(),
+            total_chunks: 1,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        };
+        state.ingest_message(PlainMessage::file_offer("peer".repeat(8), offer, false));
+        let fx = state.handle_command(&format!("/accept {}/", dir.path().display()));
+        assert!(fx.iter().any(|e| matches!(e, Effect::Send(_))));
+        let warned = state.messages[&Tab::Global].iter().any(|m| m.content.contains("EPHEMERAL"));
+        assert!(warned);
+
+        state.wipe_all();
+        assert!(state.messages.is_empty() && state.active_transfers.is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::client::OutgoingMessage;
+use crate::protocol::PlainMessage;
+
+use super::ephemeral::wipe;
+use super::helpers::{format_ttl, parse_ttl};
+use super::types::Tab;
+use super::state::{ChatState, Effect};
+
+impl ChatState {
+    /// Handle /expire <duration|off> — set the current conversation's disappearing-message
+    /// TTL and announce it to the other side(s)
+    pub(crate) fn handle_expire_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        let tab = self.tabs[self.active_tab].clone();
+        if tab == Tab::Global {
+            self.status = "Disappearing messages work in DM and group tabs".to_string();
+            return;
+        }
+        let ttl = match args.first().copied() {
+            Some("off") => 0,
+            Some(arg) => match parse_ttl(arg) {
+                Some(ttl) => ttl,
+                None => {
+                    self.status = format!("Invalid duration: {} (try 30s, 5m, 2h, 1d or off)", arg);
+                    return;
+                }
+            },
+            None => {
+                self.status = match self.expiry.get(&tab) {
+                    Some(ttl) => format!("⏳ Messages here disappear after {} — /expire off to stop", format_ttl(*ttl)),
+                    None => "Usage: /expire <duration|off>".to_string(),
+                };
+                return;
+            }
+        };
+
+        let announcement = PlainMessage::expire_policy(self.own_id.clone(), ttl, true);
+        match &tab {
+            Tab::DirectMessage(peer_id) => {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: peer_id.clone(),
+                    message: announcement,
+                }));
+            }
+            Tab::Group(group_id) => {
+                let Some(member_ids) = self.groups.get(group_id).map(|g| g.members.clone()) else {
+                    self.status = "Group not found".to_string();
+                    return;
+                };
+                let mut announcement = announcement;
+                announcement.direct = false;
+                announcement.group_id = Some(group_id.clone());
+                fx.push(Effect::Send(OutgoingMessage::Group {
+                    group_id: group_id.clone(),
+                    member_ids,
+                    message: announcement,
+                }));
+            }
+            Tab::Global => unreachable!("rejected above"),
+        }
+
+        let notice = self.set_expiry(&tab, ttl, "You");
+        self.status = notice;
+    }
+
+    /// A peer changed a conversation's disappearing-message setting
+    pub(crate) fn handle_expire_policy(&mut self, msg: &PlainMessage, ttl: u64) {
+        let tab = match msg.group_id {
+            Some(ref group_id) => Tab::Group(group_id.clone()),
+            None => Tab::DirectMessage(msg.sender.clone()),
+        };
+        let peer_name = self.get_peer_display_name(&msg.sender);
+        self.set_expiry(&tab, ttl, &peer_name);
+    }
+
+    /// Record a TTL (0 = off) for a tab and note the change in it, so both sides see the policy
+    fn set_expiry(&mut self, tab: &Tab, ttl: u64, who: &str) -> String {
+        let notice = if ttl == 0 {
+            self.expiry.remove(tab);
+            format!("⏳ {} turned off disappearing messages", who)
+        } else {
+            self.expiry.insert(tab.clone(), ttl);
+            format!("⏳ {} set messages to disappear after {}", who, format_ttl(ttl))
+        };
+        self.add_system_message(tab, notice.clone());
+        notice
+    }
+
+    /// Drop messages whose TTL has passed at unix time `now`. Returns true if any were removed.
+    pub(crate) fn sweep_expired(&mut self, now: i64) -> bool {
+        let mut removed = false;
+        for messages in self.messages.values_mut() {
+            let (expired, kept): (Vec<_>, Vec<_>) = std::mem::take(messages).into_iter().partition(|m| m.is_expired(now));
+            *messages = kept;
+            removed |= !expired.is_empty();
+            expired.into_iter().for_each(wipe);
+        }
+        removed
+    }
+
+    /// Whether the focused tab shows any countdowns (so the clock needs redrawing)
+    pub(crate) fn has_expiring_messages(&self) -> bool {
+        self.messages.get(&self.tabs[self.active_tab])
+            .is_some_and(|messages| messages.iter().any(|m| m.expire_after.is_some()))
+    }
+}
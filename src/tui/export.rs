@@ -0,0 +1,323 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::protocol::{ChatEvent, PlainMessage};
+use crate::util::expand_path;
+
+use super::action::attributed;
+use super::state::ChatState;
+
+/// Output format for /export
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ExportFormat {
+    Txt,
+    Json,
+}
+
+/// Parsed /export arguments
+#[derive(Debug, PartialEq)]
+struct ExportOptions {
+    path: Option<String>,
+    format: Option<ExportFormat>,
+    /// Unix time; earlier messages are left out
+    since: Option<i64>,
+    force: bool,
+}
+
+/// One exported message: PlainMessage's user-facing fields, without signaling flags
+#[derive(Debug, Serialize)]
+struct ExportedMessage {
+    timestamp: i64,
+    sender: String,
+    /// Display name at export time (nickname or short id)
+    sender_name: String,
+    content: String,
+    direct: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expire_after: Option<u64>,
+    /// Set on file transfer and call entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<ChatEvent>,
+    /// A `/me` action
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    action: bool,
+}
+
+impl ChatState {
+    /// Handle /export [path] [--format txt|json] [--since <date>] [--force] — write the
+    /// current tab's messages to a plaintext file
+    pub(crate) fn handle_export_command(&mut self, args: &[&str]) {
+        if self.refuse_disk_write("/export") {
+            return;
+        }
+        let options = match parse_export_args(args) {
+            Ok(options) => options,
+            Err(e) => {
+                self.status = format!("{} — usage: /export [path] [--format txt|json] [--since YYYY-MM-DD] [--force]", e);
+                return;
+            }
+        };
+
+        let tab = self.tabs[self.active_tab].clone();
+        let format = options.format
+            .or_else(|| options.path.as_deref().and_then(format_from_extension))
+            .unwrap_or(ExportFormat::Txt);
+        let path = match options.path {
+            Some(ref path) => expand_path(path),
+            None => PathBuf::from(default_export_name(&self.get_tab_name(&tab), format)),
+        };
+
+        let entries: Vec<ExportedMessage> = self.messages.get(&tab)
+            .map(|messages| messages.iter()
+                .filter(|m| !m.system || m.event.is_some())
+                .filter(|m| options.since.is_none_or(|since| m.timestamp >= since))
+                .map(|m| self.exported(m))
+                .collect())
+            .unwrap_or_default();
+
+        let rendered = match format {
+            ExportFormat::Txt => Ok(format_txt(&entries)),
+            ExportFormat::Json => format_json(&entries),
+        };
+        match rendered.and_then(|text| write_export(&path, &text, options.force)) {
+            Ok(()) => {
+                self.status = format!(
+                    "📤 Exported {} messages to {} — ⚠️ this file is NOT encrypted",
+                    entries.len(),
+                    path.display()
+                );
+            }
+            Err(e) => {
+                self.status = format!("Export failed: {:#}", e);
+            }
+        }
+    }
+
+    fn exported(&self, m: &PlainMessage) -> ExportedMessage {
+        let sender_name = if m.sender == self.own_id {
+            self.display_name()
+        } else {
+            self.get_peer_display_name(&m.sender)
+        };
+        ExportedMessage {
+            timestamp: m.timestamp,
+            sender: m.sender.clone(),
+            sender_name,
+            content: m.content.clone(),
+            direct: m.direct,
+            group_id: m.group_id.clone(),
+            message_id: m.message_id.clone(),
+            expire_after: m.expire_after,
+            event: m.event,
+            action: m.action,
+        }
+    }
+}
+
+fn parse_export_args(args: &[&str]) -> Result<ExportOptions> {
+    let mut options = ExportOptions { path: None, format: None, since: None, force: false };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--force" => options.force = true,
+            "--format" => {
+                options.format = Some(match args.next().copied() {
+                    Some("txt") => ExportFormat::Txt,
+                    Some("json") => ExportFormat::Json,
+                    Some(other) => bail!("Unknown format: {}", other),
+                    None => bail!("--format needs txt or json"),
+                });
+            }
+            "--since" => {
+                let date = args.next().context("--since needs a date")?;
+                options.since = Some(parse_since(date).with_context(|| format!("Invalid date: {}", date))?);
+            }
+            flag if flag.starts_with("--") => bail!("Unknown option: {}", flag),
+            path if options.path.is_none() => options.path = Some(path.to_string()),
+            extra => bail!("Unexpected argument: {}", extra),
+        }
+    }
+    Ok(options)
+}
+
+/// `YYYY-MM-DD` (start of day) or `YYYY-MM-DDTHH:MM[:SS]`, in UTC like the chat timestamps
+fn parse_since(date: &str) -> Option<i64> {
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Some(day.and_hms_opt(0, 0, 0)?.and_utc().timestamp());
+    }
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"].iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(date, fmt).ok())
+        .map(|time| time.and_utc().timestamp())
+}
+
+fn format_from_extension(path: &str) -> Option<ExportFormat> {
+    match Path::new(path).extension()?.to_str()? {
+        "json" => Some(ExportFormat::Json),
+        "txt" => Some(ExportFormat::Txt),
+        _ => None,
+    }
+}
+
+fn default_export_name(tab_name: &str, format: ExportFormat) -> String {
+    let safe: String = tab_name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+    let ext = match format {
+        ExportFormat::Txt => "txt",
+        ExportFormat::Json => "json",
+    };
+    format!("wsp-{}-{}.{}", safe.trim_matches('_'), stamp, ext)
+}
+
+/// `[2024-03-01 14:02:11] alice: message`, one per line; continuation lines of a
+/// multi-line message are indented so every entry still starts with a timestamp.
+/// Transfer and call events read `[2024-03-01 14:02:11] * event`, and actions
+/// `[2024-03-01 14:02:11] · alice waves`.
+fn format_txt(entries: &[ExportedMessage]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let time = DateTime::from_timestamp(entry.timestamp, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "????-??-?? ??:??:??".to_string());
+        let content = entry.content.replace('\n', "\n    ");
+        match entry.event {
+            Some(_) => out.push_str(&format!("[{}] * {}\n", time, content)),
+            None => out.push_str(&format!("[{}] {}\n", time, attributed(&entry.sender_name, &content, entry.action))),
+        }
+    }
+    out
+}
+
+fn format_json(entries: &[ExportedMessage]) -> Result<String> {
+    let mut json = serde_json::to_string_pretty(entries).context("failed to encode export")?;
+    json.push('\n');
+    Ok(json)
+}
+
+fn write_export(path: &Path, text: &str, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!("{} already exists (add --force to overwrite)", path.display());
+    }
+    std::fs::write(path, text).with_context(|| format!("couldn't write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::tui::types::Tab;
+
+    const ME: &str = "me000000000000000000";
+    const ALICE: &str = "alice000000000000000";
+
+    fn entry(timestamp: i64, sender_name: &str, content: &str) -> ExportedMessage {
+        ExportedMessage {
+            timestamp,
+            sender: ALICE.to_string(),
+            sender_name: sender_name.to_string(),
+            content: content.to_string(),
+            direct: true,
+            group_id: None,
+            message_id: None,
+            expire_after: None,
+            event: None,
+            action: false,
+        }
+    }
+
+    /// A DM with alice holding a join notice and two chat messages
+    fn state_with_dm() -> ChatState {
+        let mut state = ChatState::new(ME.to_string(), Some("me".to_string()), vec![0; 32]);
+        state.apply_peer_updates(vec![PeerUpdate::Added(ALICE.to_string(), PeerDisplay {
+            nickname: Some("alice".to_string()),
+            public_key: vec![1; 32],
+        })]);
+        let tab = Tab::DirectMessage(ALICE.to_string());
+        state.add_system_message(&tab, "alice has joined".to_string());
+        for (timestamp, sender, content) in [(1_709_301_731, ALICE, "hi"), (1_709_388_000, ME, "hey")] {
+            let mut msg = PlainMessage::direct(sender.to_string(), content.to_string());
+            msg.timestamp = timestamp;
+            state.push_message(tab.clone(), msg);
+        }
+        state.handle_command("/dm alice");
+        state
+    }
+
+    #[test]
+    fn test_format_txt() {
+        let waves = ExportedMessage { action: true, ..entry(1_709_301_800, "bob", "waves") };
+        let text = format_txt(&[entry(1_709_301_731, "alice", "hi"), entry(1_709_301_790, "bob", "two\nlines"), waves]);
+        assert_eq!(text, "[2024-03-01 14:02:11] alice: hi\n[2024-03-01 14:03:10] bob: two\n    lines\n[2024-03-01 14:03:20] · bob waves\n");
+    }
+
+    #[test]
+    fn test_format_json_leaves_out_internal_fields() {
+        let json = format_json(&[entry(1_709_301_731, "alice", "hi")]).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let object = value[0].as_object().unwrap();
+        assert_eq!(object["sender_name"], "alice");
+        assert_eq!(object["content"], "hi");
+        assert!(!object.contains_key("system"));
+        assert!(!object.contains_key("group_id"));
+    }
+
+    #[test]
+    fn test_export_keeps_transfer_and_call_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dm.json");
+        let mut state = state_with_dm();
+        let tab = Tab::DirectMessage(ALICE.to_string());
+        state.add_event_message(&tab, ChatEvent::CallEnded, "📵 Call ended with alice (0:42)".to_string());
+        let event = state.messages[&tab].last().unwrap();
+        assert!(format_txt(&[state.exported(event)]).ends_with("] * 📵 Call ended with alice (0:42)\n"));
+
+        state.handle_command(&format!("/export {}", path.display()));
+        assert!(state.status.contains("Exported 3 messages"));
+        let value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(value[2]["event"], "call_ended");
+        assert!(value[0].get("event").is_none());
+    }
+
+    #[test]
+    fn test_parse_export_args() {
+        let options = parse_export_args(&["out.json", "--since", "2024-03-01", "--force"]).unwrap();
+        assert_eq!(options.path.as_deref(), Some("out.json"));
+        assert_eq!(options.since, Some(1_709_251_200));
+        assert!(options.force);
+        assert_eq!(parse_export_args(&["--format", "json"]).unwrap().format, Some(ExportFormat::Json));
+        assert_eq!(parse_since("2024-03-01T14:02"), Some(1_709_301_720));
+
+        assert!(parse_export_args(&["--format", "pdf"]).is_err());
+        assert!(parse_export_args(&["--since", "yesterday"]).is_err());
+        assert!(parse_export_args(&["a.txt", "b.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_export_writes_chat_messages_and_refuses_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dm.txt");
+        let mut state = state_with_dm();
+
+        state.handle_command(&format!("/export {}", path.display()));
+        assert!(state.status.contains("Exported 2 messages"));
+        assert!(state.status.contains("NOT encrypted"));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "[2024-03-01 14:02:11] alice: hi\n[2024-03-02 14:00:00] me: hey\n"
+        );
+
+        state.handle_command(&format!("/export {} --since 2024-03-02", path.display()));
+        assert!(state.status.contains("--force"));
+        state.handle_command(&format!("/export {} --since 2024-03-02 --force", path.display()));
+        assert!(state.status.contains("Exported 1 messages"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[2024-03-02 14:00:00] me: hey\n");
+    }
+}
@@ -0,0 +1,187 @@
+//! Fenced ``` blocks in chat. The renderer draws each one as a panel, titled with its
+//! language if it has one, whose lines keep their indentation and are cut off at the
+//! edge (…) instead of wrapped; blocks over FOLDED_CODE_LINES lines fold until /expand.
+//! `/copy code` finds blocks the same way.
+
+/// Code blocks show this many lines until the message is expanded
+pub(crate) const FOLDED_CODE_LINES: usize = 20;
+
+/// How one line of a message reads: prose, or part of a fenced ``` block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LineKind {
+    Prose,
+    /// The ``` (or ```lang) that opens a block
+    Open,
+    Code,
+    /// The ``` that closes it; a block left open runs to the end of the message
+    Close,
+}
+
+fn is_fence(line: &str) -> bool {
+    line.trim_start().starts_with("```")
+}
+
+/// `text`'s lines, each with what it is
+pub(crate) fn line_kinds(text: &str) -> Vec<(LineKind, &str)> {
+    let mut in_block = false;
+    text.split('\n')
+        .map(|line| {
+            let kind = match (in_block, is_fence(line)) {
+                (false, false) => LineKind::Prose,
+                (false, true) => LineKind::Open,
+                (true, false) => LineKind::Code,
+                (true, true) => LineKind::Close,
+            };
+            if is_fence(line) {
+                in_block = !in_block;
+            }
+            (kind, line)
+        })
+        .collect()
+}
+
+/// The inside of each fenced block in `text`, in order
+pub(crate) fn code_blocks(text: &str) -> Vec<String> {
+    let mut blocks: Vec<Vec<&str>> = Vec::new();
+    for (kind, line) in line_kinds(text) {
+        match kind {
+            LineKind::Open => blocks.push(Vec::new()),
+            LineKind::Code => blocks.last_mut().expect("a block is open").push(line),
+            LineKind::Prose | LineKind::Close => {}
+        }
+    }
+    blocks.into_iter().map(|lines| lines.join("\n")).collect()
+}
+
+/// Whether some block in `text` is long enough to fold
+pub(crate) fn has_folded_code(text: &str) -> bool {
+    code_blocks(text).iter().any(|block| block.split('\n').count() > FOLDED_CODE_LINES)
+}
+
+/// How many fewer lines `text` takes with its long blocks folded
+pub(crate) fn folded_away(text: &str) -> usize {
+    code_blocks(text).iter()
+        .map(|block| block.split('\n').count())
+        .filter(|lines| *lines > FOLDED_CODE_LINES)
+        .map(|lines| lines - FOLDED_CODE_LINES - 1)
+        .sum()
+}
+
+/// One row of a message on screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Row {
+    /// A line of prose, still to be wrapped
+    Prose(String),
+    /// The top or bottom of a block's panel
+    Border(String),
+    /// A line of code, fitted to the panel
+    Code(String),
+    /// "│ … 43 more lines (/expand 3) │"
+    Folded(String),
+}
+
+/// The language after a block's opening ```, if any
+fn language(open: &str) -> Option<&str> {
+    open.trim_start().trim_start_matches('`').split_whitespace().next()
+}
+
+/// `text` as rows `width` characters wide, its blocks drawn as panels. With `fold`
+/// (the message's number, for the /expand hint) long blocks show only their start.
+pub(crate) fn layout(text: &str, width: usize, fold: Option<usize>) -> Vec<Row> {
+    // "│ " and " │" either side of the code
+    let inner = width.saturating_sub(4).max(1);
+    let rule = |n: usize| "─".repeat(n);
+    let fit = |line: &str| {
+        let line = line.replace('\t', "    ");
+        let mut chars: Vec<char> = line.chars().collect();
+        if chars.len() > inner {
+            chars.truncate(inner - 1);
+            chars.push('…');
+        }
+        let shown: String = chars.iter().collect();
+        format!("│ {}{} │", shown, " ".repeat(inner - chars.len()))
+    };
+
+    let mut rows = Vec::new();
+    let mut in_block = 0;
+    let kinds = line_kinds(text);
+    for (at, (kind, line)) in kinds.iter().enumerate() {
+        match kind {
+            LineKind::Prose => rows.push(Row::Prose(line.to_string())),
+            LineKind::Open => {
+                in_block = 0;
+                let top = match language(line) {
+                    Some(lang) => {
+                        let title = format!("┌─ {} ", lang);
+                        let used = title.chars().count();
+                        format!("{}{}┐", title, rule(width.saturating_sub(used + 1)))
+                    }
+                    None => format!("┌{}┐", rule(width.saturating_sub(2))),
+                };
+                rows.push(Row::Border(top));
+            }
+            LineKind::Code => {
+                in_block += 1;
+                match fold {
+                    Some(_) if in_block > FOLDED_CODE_LINES => {}
+                    _ => rows.push(Row::Code(fit(line))),
+                }
+            }
+            LineKind::Close => {}
+        }
+        // The end of a block (or of the message, for one left open) closes its panel
+        let block_ends = match kind {
+            LineKind::Close => true,
+            LineKind::Open | LineKind::Code => at + 1 == kinds.len(),
+            LineKind::Prose => false,
+        };
+        if block_ends {
+            if let Some(n) = fold.filter(|_| in_block > FOLDED_CODE_LINES) {
+                let hint = format!("… {} more lines (/expand {})", in_block - FOLDED_CODE_LINES, n);
+                rows.push(Row::Folded(fit(&hint)));
+            }
+            rows.push(Row::Border(format!("└{}┘", rule(width.saturating_sub(2)))));
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_become_panels() {
+        let text = "see:\n```rust\nfn main() {\n\tlet long_name = 1;\n}\n```\ndone";
+        let kinds: Vec<LineKind> = line_kinds(text).into_iter().map(|(kind, _)| kind).collect();
+        use LineKind::*;
+        assert_eq!(kinds, [Prose, Open, Code, Code, Code, Close, Prose]);
+        assert_eq!(code_blocks(text), ["fn main() {\n\tlet long_name = 1;\n}"]);
+
+        // Indentation kept, long lines cut off at the edge
+        let rows = layout(text, 20, Some(1));
+        let drawn: Vec<String> = rows.iter().map(|row| match row {
+            Row::Prose(s) | Row::Border(s) | Row::Code(s) | Row::Folded(s) => s.clone(),
+        }).collect();
+        assert_eq!(drawn, [
+            "see:",
+            "┌─ rust ───────────┐",
+            "│ fn main() {      │",
+            "│     let long_na… │",
+            "│ }                │",
+            "└──────────────────┘",
+            "done",
+        ]);
+        assert!(drawn[1..6].iter().all(|row| row.chars().count() == 20));
+
+        // Long blocks fold until expanded; one left open still gets its panel closed
+        let long = format!("```\n{}", (1..=63).map(|i| i.to_string()).collect::<Vec<_>>().join("\n"));
+        let folded = layout(&long, 40, Some(4));
+        assert_eq!(folded.len(), 1 + FOLDED_CODE_LINES + 2);
+        assert!(matches!(&folded[FOLDED_CODE_LINES + 1], Row::Folded(s) if s.contains("… 43 more lines (/expand 4)")));
+        assert!(matches!(folded.last(), Some(Row::Border(s)) if s.starts_with('└')));
+        assert_eq!(layout(&long, 40, None).len(), 1 + 63 + 1);
+        assert!(has_folded_code(&long) && !has_folded_code(text));
+        assert_eq!(folded_away(&long), 63 - FOLDED_CODE_LINES - 1);
+    }
+}
@@ -1,15 +1,21 @@
-use std::path::PathBuf;
-use tokio::sync::mpsc;
-
-use crate::client::OutgoingMessage;
-use crate::protocol::{FileChunk, FileOffer, PlainMessage};
-
-use super::helpers::expand_path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::client::{OutgoingMessage, OutgoingSender};
+use crate::protocol::{ChatEvent, FileChunk, FileOffer, PlainMessage};
+use crate::util::expand_path;
+
+use super::archive;
+use super::bandwidth::RateLimit;
+use super::mime;
+use super::sounds::SoundEvent;
 use super::types::{ActiveTransfer, OutgoingTransfer, PendingFileOffer, Tab, FILE_CHUNK_SIZE};
-use super::ChatUI;
+use super::state::{ChatState, Effect};
 
-impl ChatUI {
-    pub(crate) fn handle_share_command(&mut self, filepath: &str, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+impl ChatState {
+    pub(crate) fn handle_share_command(&mut self, filepath: &str, fx: &mut Vec<Effect>) {
         self.status = format!("Reading file: {}...", filepath);
 
         if self.peers.is_empty() {
@@ -19,21 +25,39 @@ impl ChatUI {
 
         let path = expand_path(filepath);
 
-        let file_data = match std::fs::read(&path) {
-            Ok(data) => data,
+        let Share { data: file_data, filename, entry_count, skipped_symlinks } = match read_share(&path, self.max_share_bytes) {
+            Ok(share) => share,
             Err(e) => {
-                self.status = format!("Failed to read file: {}", e);
+                self.status = format!("Failed to share {}: {:#}", filepath, e);
                 return;
             }
         };
 
-        let filename = match path.file_name() {
-            Some(name) => name.to_string_lossy().to_string(),
-            None => {
-                self.status = "Invalid file path".to_string();
-                return;
-            }
-        };
+        self.share_data(filename, file_data, entry_count, fx);
+        if skipped_symlinks > 0 {
+            self.status.push_str(&format!(" — ⚠️ skipped {} symlink(s)", skipped_symlinks));
+        }
+    }
+
+    /// Offer `file_data` under `filename` to the current tab. `entry_count` marks a folder
+    /// share's tar archive.
+    pub(crate) fn share_data(&mut self, filename: String, file_data: Vec<u8>, entry_count: Option<u32>, fx: &mut Vec<Effect>) {
+        if self.peers.is_empty() {
+            self.status = "No peers connected to share with".to_string();
+            return;
+        }
+        let tab = self.tabs[self.active_tab].clone();
+        if let Some(reason) = self.global_refused(&tab) {
+            self.status = reason;
+            return;
+        }
+        if self.hold_global_send(&tab) {
+            return;
+        }
+        if file_data.len() as u64 > self.max_share_bytes {
+            self.status = format!("Failed to share {}: over the {} share limit", filename, Self::format_size(self.max_share_bytes));
+            return;
+        }
 
         let checksum = blake3::hash(&file_data).to_hex().to_string();
         let total_chunks = ((file_data.len() + FILE_CHUNK_SIZE - 1) / FILE_CHUNK_SIZE) as u32;
@@ -45,13 +69,16 @@ impl ChatUI {
             size: file_data.len() as u64,
             checksum,
             total_chunks,
+            mime_type: mime::guess_from_name(&filename).map(str::to_string),
+            is_archive: entry_count.is_some(),
+            entry_count: entry_count.unwrap_or(0),
         };
 
         let current_tab = &self.tabs[self.active_tab];
-        let (is_direct, target_peer) = match current_tab {
-            Tab::Global => (false, String::new()),
-            Tab::DirectMessage(peer_id) => (true, peer_id.clone()),
-            Tab::Group(_group_id) => (false, String::new()),
+        let (is_direct, target_peer, group_id) = match current_tab {
+            Tab::Global => (false, String::new(), None),
+            Tab::DirectMessage(peer_id) => (true, peer_id.clone(), None),
+            Tab::Group(group_id) => (false, String::new(), Some(group_id.clone())),
         };
 
         let offer_msg = PlainMessage::file_offer(self.own_id.clone(), offer.clone(), is_direct);
@@ -62,21 +89,21 @@ impl ChatUI {
                     let member_ids = group.members.clone();
                     let mut group_offer = offer_msg.clone();
                     group_offer.group_id = Some(group_id.clone());
-                    let _ = msg_tx.send(OutgoingMessage::Group {
+                    fx.push(Effect::Send(OutgoingMessage::Group {
                         group_id: group_id.clone(),
                         member_ids,
                         message: group_offer,
-                    });
+                    }));
                 }
             }
             Tab::DirectMessage(_) => {
-                let _ = msg_tx.send(OutgoingMessage::Direct {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
                     target_id: target_peer.clone(),
                     message: offer_msg,
-                });
+                }));
             }
             Tab::Global => {
-                let _ = msg_tx.send(OutgoingMessage::Global(offer_msg));
+                fx.push(Effect::Send(OutgoingMessage::Global(offer_msg)));
             }
         }
 
@@ -86,108 +113,232 @@ impl ChatUI {
             target_peer,
             chunks_sent: 0,
             is_direct,
+            group_id,
+            answered: Vec::new(),
+            control: Arc::default(),
         });
+        let tab = self.tabs[self.active_tab].clone();
+        self.add_offer_row(&tab, &offer, None);
 
-        self.status = format!("Offering file: {} ({})", filename, Self::format_size(offer.size));
+        self.status = match entry_count {
+            Some(entries) => format!("Offering folder: {} ({} entries, {})", filename, entries, Self::format_size(offer.size)),
+            None => format!("Offering file: {} ({})", filename, Self::format_size(offer.size)),
+        };
     }
 
-    pub(crate) fn handle_accept_command(&mut self, save_path: &str, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
-        let current_tab = &self.tabs[self.active_tab];
+    /// Pending offers made in the current tab, in the order of their rows — the order
+    /// /offers numbers them in
+    fn offers_in_tab(&self) -> Vec<(String, PendingFileOffer)> {
+        self.pending_rows_in(&self.tabs[self.active_tab])
+            .into_iter()
+            .filter_map(|id| Some((id.clone(), self.pending_offers.get(&id)?.clone())))
+            .collect()
+    }
 
-        let offer_to_accept = self.pending_offers.iter()
-            .find(|(_, pending)| &pending.tab == current_tab)
-            .map(|(id, pending)| (id.clone(), pending.clone()));
+    /// Resolve `selector` (a /offers index or a filename) to an offer in this tab. Without
+    /// a selector the tab must have exactly one pending offer.
+    fn select_offer(&self, selector: Option<&str>) -> Result<(String, PendingFileOffer), String> {
+        let mut offers = self.offers_in_tab();
+        match selector {
+            None => match offers.len() {
+                0 => Err("No pending file offer in this tab".to_string()),
+                1 => Ok(offers.remove(0)),
+                n => Err(format!("{} file offers pending — pick one from /offers", n)),
+            },
+            Some(selector) => {
+                let by_index = selector.parse::<usize>().ok()
+                    .filter(|n| (1..=offers.len()).contains(n))
+                    .map(|n| n - 1);
+                by_index
+                    .or_else(|| offers.iter().position(|(_, p)| p.offer.filename == selector))
+                    .map(|i| offers.remove(i))
+                    .ok_or_else(|| format!("No offer {:?} here — see /offers", selector))
+            }
+        }
+    }
 
-        if let Some((file_id, pending)) = offer_to_accept {
-            let save_dir = expand_path(save_path);
+    /// Handle /offers — list this tab's pending offers with the indexes /accept and /reject take
+    pub(crate) fn handle_offers_command(&mut self) {
+        let offers = self.offers_in_tab();
+        if offers.is_empty() {
+            self.status = "No pending file offers in this tab".to_string();
+            return;
+        }
+        let mut lines = vec![format!("📎 {} pending file offer(s) — /accept <n> [path] or /reject <n>:", offers.len())];
+        for (i, (_, pending)) in offers.iter().enumerate() {
+            lines.push(format!(
+                "  {}. {} ({}) from {}",
+                i + 1,
+                pending.offer.filename,
+                Self::format_size(pending.offer.size),
+                self.get_peer_display_name(&pending.from_peer)
+            ));
+        }
+        let tab = self.tabs[self.active_tab].clone();
+        self.add_system_message(&tab, lines.join("\n"));
+    }
 
-            let full_path = if save_dir.is_dir() || save_path.ends_with('/') || save_path == "." {
-                save_dir.join(&pending.offer.filename)
-            } else {
-                save_dir
-            };
+    /// Handle /accept [n|filename] [path] [--force] [--extract]. Without a path it goes to
+    /// the download directory. The offered name is never trusted as a path, and an
+    /// existing file is only overwritten with --force. A folder share is saved as its
+    /// .tar unless --extract asks for it to be unpacked.
+    pub(crate) fn handle_accept_command(&mut self, args: &[&str], force: bool, extract: bool, fx: &mut Vec<Effect>) {
+        // The first argument names an offer if it matches one; otherwise it's the save path
+        let (selected, path_args) = match args.split_first() {
+            Some((first, rest)) => match self.select_offer(Some(first)) {
+                Ok(offer) => (Ok(offer), rest),
+                Err(_) => (self.select_offer(None), args),
+            },
+            None => (self.select_offer(None), args),
+        };
+        let (file_id, pending) = match selected {
+            Ok(offer) => offer,
+            Err(e) => {
+                self.status = e;
+                return;
+            }
+        };
+        if extract && !pending.offer.is_archive {
+            self.status = format!("{} isn't a folder share — accept it without --extract", pending.offer.filename);
+            return;
+        }
+        let save_path = if path_args.is_empty() {
+            match self.download_dir_for(&pending.offer) {
+                Ok(dir) => dir,
+                Err(e) => {
+                    self.status = e;
+                    return;
+                }
+            }
+        } else {
+            path_args.join(" ")
+        };
+        self.accept_offer(file_id, pending, &save_path, force, extract, fx);
+    }
 
-            let response_msg = PlainMessage::file_response(
-                self.own_id.clone(),
-                file_id.clone(),
-                true,
-                pending.tab != Tab::Global,
-            );
+    /// Accept `pending`, saving it under `save_path` (a directory, or the file to write).
+    /// Returns where it will be saved.
+    pub(crate) fn accept_offer(
+        &mut self,
+        file_id: String,
+        pending: PendingFileOffer,
+        save_path: &str,
+        force: bool,
+        extract: bool,
+        fx: &mut Vec<Effect>,
+    ) -> PathBuf {
+
+        let save_dir = expand_path(save_path);
+
+        let safe_name = safe_filename(&pending.offer.filename, &file_id);
+        // Extracting goes into a folder named after the archive
+        let safe_name = match safe_name.strip_suffix(".tar") {
+            Some(stem) if extract && !stem.is_empty() => stem.to_string(),
+            _ => safe_name,
+        };
+        let full_path = if save_dir.is_dir() || save_path.ends_with('/') {
+            save_dir.join(&safe_name)
+        } else {
+            save_dir
+        };
+        let full_path = if force { full_path } else { unique_path(full_path) };
 
-            match &pending.tab {
-                Tab::Global => {
-                    let _ = msg_tx.send(OutgoingMessage::Global(response_msg));
-                }
-                Tab::DirectMessage(peer_id) => {
-                    let _ = msg_tx.send(OutgoingMessage::Direct {
-                        target_id: peer_id.clone(),
-                        message: response_msg,
-                    });
-                }
-                Tab::Group(_group_id) => {
-                    let _ = msg_tx.send(OutgoingMessage::Direct {
-                        target_id: pending.from_peer.clone(),
-                        message: response_msg,
-                    });
-                }
+        let response_msg = PlainMessage::file_response(
+            self.own_id.clone(),
+            file_id.clone(),
+            true,
+            pending.tab != Tab::Global,
+        );
+
+        match &pending.tab {
+            Tab::Global => {
+                fx.push(Effect::Send(OutgoingMessage::Global(response_msg)));
+            }
+            Tab::DirectMessage(peer_id) => {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: peer_id.clone(),
+                    message: response_msg,
+                }));
+            }
+            Tab::Group(_group_id) => {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: pending.from_peer.clone(),
+                    message: response_msg,
+                }));
             }
+        }
 
-            let chunks_vec = vec![None; pending.offer.total_chunks as usize];
-            self.active_transfers.insert(file_id.clone(), ActiveTransfer {
-                offer: pending.offer.clone(),
-                chunks_received: chunks_vec,
-                save_path: full_path.clone(),
-                chunks_done: 0,
-            });
+        let chunks_vec = vec![None; pending.offer.total_chunks as usize];
+        self.active_transfers.insert(file_id.clone(), ActiveTransfer {
+            offer: pending.offer.clone(),
+            chunks_received: chunks_vec,
+            save_path: full_path.clone(),
+            chunks_done: 0,
+            from_peer: pending.from_peer.clone(),
+            tab: pending.tab.clone(),
+            extract,
+            started: Instant::now(),
+        });
 
-            self.pending_offers.remove(&file_id);
-            self.status = format!("Accepting {}, saving to {}", pending.offer.filename, full_path.display());
-        } else {
-            self.status = "No pending file offer in this tab".to_string();
+        self.pending_offers.remove(&file_id);
+        let action = if extract { "extracting into" } else { "saving to" };
+        self.update_offer_row(&file_id, ChatEvent::FileAccepted, &format!("📥 {} {}", action, full_path.display()));
+        if self.ephemeral {
+            self.add_system_message(&pending.tab, format!(
+                "⚠️  EPHEMERAL: {} will be written to disk at {} — delete it yourself when done",
+                pending.offer.filename,
+                full_path.display()
+            ));
         }
+        self.status = if safe_name == pending.offer.filename {
+            format!("Accepting {}, saving to {}", pending.offer.filename, full_path.display())
+        } else {
+            format!("Accepting {:?}, saving to {} (renamed for safety)", pending.offer.filename, full_path.display())
+        };
+        full_path
     }
 
-    pub(crate) fn handle_reject_command(&mut self, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
-        let current_tab = &self.tabs[self.active_tab];
-
-        let offer_to_reject = self.pending_offers.iter()
-            .find(|(_, pending)| &pending.tab == current_tab)
-            .map(|(id, pending)| (id.clone(), pending.clone()));
-
-        if let Some((file_id, pending)) = offer_to_reject {
-            let response_msg = PlainMessage::file_response(
-                self.own_id.clone(),
-                file_id.clone(),
-                false,
-                pending.tab != Tab::Global,
-            );
-
-            match &pending.tab {
-                Tab::Global => {
-                    let _ = msg_tx.send(OutgoingMessage::Global(response_msg));
-                }
-                Tab::DirectMessage(peer_id) => {
-                    let _ = msg_tx.send(OutgoingMessage::Direct {
-                        target_id: peer_id.clone(),
-                        message: response_msg,
-                    });
-                }
-                Tab::Group(_group_id) => {
-                    let _ = msg_tx.send(OutgoingMessage::Direct {
-                        target_id: pending.from_peer.clone(),
-                        message: response_msg,
-                    });
-                }
+    /// Handle /reject [n|filename]
+    pub(crate) fn handle_reject_command(&mut self, selector: Option<&str>, fx: &mut Vec<Effect>) {
+        let (file_id, pending) = match self.select_offer(selector) {
+            Ok(offer) => offer,
+            Err(e) => {
+                self.status = e;
+                return;
             }
+        };
 
-            self.pending_offers.remove(&file_id);
-            self.status = format!("Rejected file: {}", pending.offer.filename);
-        } else {
-            self.status = "No pending file offer in this tab".to_string();
+        let response_msg = PlainMessage::file_response(
+            self.own_id.clone(),
+            file_id.clone(),
+            false,
+            pending.tab != Tab::Global,
+        );
+
+        match &pending.tab {
+            Tab::Global => {
+                fx.push(Effect::Send(OutgoingMessage::Global(response_msg)));
+            }
+            Tab::DirectMessage(peer_id) => {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: peer_id.clone(),
+                    message: response_msg,
+                }));
+            }
+            Tab::Group(_group_id) => {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: pending.from_peer.clone(),
+                    message: response_msg,
+                }));
+            }
         }
+
+        self.pending_offers.remove(&file_id);
+        self.status = format!("Rejected file: {}", pending.offer.filename);
+        self.update_offer_row(&file_id, ChatEvent::FileRejected, "🚫 rejected");
     }
 
-    pub(crate) fn handle_file_offer(&mut self, msg: PlainMessage) {
+    pub(crate) fn handle_file_offer(&mut self, msg: PlainMessage, fx: &mut Vec<Effect>) {
         if let Some(offer) = msg.file_offer {
             let file_id = offer.file_id.clone();
             let sender_name = self.get_peer_display_name(&msg.sender);
@@ -200,42 +351,79 @@ impl ChatUI {
                 Tab::Global
             };
 
-            self.pending_offers.insert(file_id, PendingFileOffer {
+            self.pending_offers.insert(file_id.clone(), PendingFileOffer {
                 offer: offer.clone(),
-                from_peer: msg.sender,
-                tab,
+                from_peer: msg.sender.clone(),
+                tab: tab.clone(),
+                received: Instant::now(),
             });
+            self.add_offer_row(&tab, &offer, Some(&msg.sender));
 
-            self.status = format!(
-                "{} wants to share {} ({}) — /accept [path] or /reject",
+            let kind = if offer.is_archive {
+                format!(", folder of {} entries", offer.entry_count)
+            } else {
+                offer.mime_type.as_deref().map(|m| format!(", {}", m)).unwrap_or_default()
+            };
+            if self.auto_accept_offer(&file_id, fx) {
+                return;
+            }
+            self.chime(SoundEvent::FileOffer);
+            let waiting = self.pending_offers.values().filter(|p| p.tab == tab).count();
+            let how = if waiting > 1 {
+                format!("{} offers waiting here, see /offers", waiting)
+            } else if offer.is_archive {
+                "/accept [path] [--extract] or /reject".to_string()
+            } else {
+                "/accept [path] or /reject".to_string()
+            };
+            self.flash_status(format!(
+                "{} wants to share {} ({}{}) — {}",
                 sender_name,
                 offer.filename,
-                Self::format_size(offer.size)
-            );
+                Self::format_size(offer.size),
+                kind,
+                how
+            ));
         }
     }
 
     pub(crate) fn handle_file_chunk(&mut self, msg: PlainMessage) {
         if let Some(chunk) = msg.file_chunk {
             let file_id = &chunk.file_id;
+            let sender_name = self.get_peer_display_name(&msg.sender);
 
-            if let Some(transfer) = self.active_transfers.get_mut(file_id) {
+            // The first chunk's magic bytes tell us what the file really is
+            let content_warning = self.active_transfers.get(file_id)
+                .filter(|t| chunk.index == 0 && t.chunks_received.first().is_some_and(Option::is_none))
+                .and_then(|t| {
+                    let warning = mime::mismatch_warning(&t.offer.filename, t.offer.mime_type.as_deref(), &chunk.data)?;
+                    Some((t.tab.clone(), format!("⚠️ {} from {} {} — be careful opening it", t.offer.filename, sender_name, warning)))
+                });
+            if let Some((tab, text)) = content_warning {
+                self.add_system_message(&tab, text);
+            }
+
+            // Only whoever made the offer sends its chunks (a group offer's come in a DM)
+            if let Some(transfer) = self.active_transfers.get_mut(file_id).filter(|t| t.from_peer == msg.sender) {
+                if chunk.index >= transfer.offer.total_chunks {
+                    self.status = format!(
+                        "⚠️ Ignored chunk {} of {} from {}: offer only has {} chunks",
+                        chunk.index,
+                        transfer.offer.filename,
+                        sender_name,
+                        transfer.offer.total_chunks
+                    );
+                    return;
+                }
                 if (chunk.index as usize) < transfer.chunks_received.len() {
                     if transfer.chunks_received[chunk.index as usize].is_none() {
                         transfer.chunks_received[chunk.index as usize] = Some(chunk.data);
                         transfer.chunks_done += 1;
 
-                        let progress = (transfer.chunks_done as f64 / transfer.offer.total_chunks as f64) * 100.0;
-                        self.status = format!(
-                            "Receiving {}: {:.0}% ({}/{})",
-                            transfer.offer.filename,
-                            progress,
-                            transfer.chunks_done,
-                            transfer.offer.total_chunks
-                        );
-
                         if transfer.chunks_done == transfer.offer.total_chunks {
                             self.finalize_transfer(file_id);
+                        } else {
+                            self.status = self.transfer_progress();
                         }
                     }
                 }
@@ -243,85 +431,144 @@ impl ChatUI {
         }
     }
 
-    pub(crate) fn handle_file_response(&mut self, msg: PlainMessage, accept: bool, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+    /// One status line covering every download in flight, so concurrent transfers don't
+    /// overwrite each other's progress
+    fn transfer_progress(&self) -> String {
+        let percent = |t: &ActiveTransfer| (t.chunks_done as f64 / t.offer.total_chunks as f64) * 100.0;
+        let mut transfers: Vec<&ActiveTransfer> = self.active_transfers.values().collect();
+        transfers.sort_by(|a, b| a.offer.filename.cmp(&b.offer.filename));
+        match transfers[..] {
+            [] => String::new(),
+            [t] => format!(
+                "Receiving {}: {:.0}% ({}/{})",
+                t.offer.filename,
+                percent(t),
+                t.chunks_done,
+                t.offer.total_chunks
+            ),
+            _ => {
+                let each: Vec<String> = transfers.iter()
+                    .map(|t| format!("{} {:.0}%", t.offer.filename, percent(t)))
+                    .collect();
+                format!("Receiving {} files: {}", transfers.len(), each.join(" · "))
+            }
+        }
+    }
+
+    pub(crate) fn handle_file_response(&mut self, msg: PlainMessage, accept: bool, fx: &mut Vec<Effect>) {
         let file_id = &msg.content;
 
+        let sender_name = self.get_peer_display_name(&msg.sender);
+        if self.outgoing_transfers.get(file_id).is_some_and(|t| t.group_id.is_some()) {
+            self.handle_group_file_response(msg, accept, fx);
+            return;
+        }
         if !accept {
             if let Some(transfer) = self.outgoing_transfers.remove(file_id) {
                 self.status = format!("File rejected: {}", transfer.offer.filename);
             }
+            self.answer_offer_row(file_id, &msg.sender, "🚫 rejected");
             return;
         }
 
-        let sender_name = self.get_peer_display_name(&msg.sender);
-        if let Some(transfer) = self.outgoing_transfers.get_mut(file_id) {
-            self.status = format!("{} accepted {}. Sending...", sender_name, transfer.offer.filename);
-
-            let total_chunks = transfer.offer.total_chunks as usize;
-            for i in 0..total_chunks {
-                let start = i * FILE_CHUNK_SIZE;
-                let end = ((i + 1) * FILE_CHUNK_SIZE).min(transfer.file_data.len());
-                let chunk_data = transfer.file_data[start..end].to_vec();
-
-                let chunk = FileChunk {
-                    file_id: file_id.clone(),
-                    index: i as u32,
-                    data: chunk_data,
-                };
-
-                let chunk_msg = PlainMessage::file_chunk(
-                    self.own_id.clone(),
-                    chunk,
-                    transfer.is_direct,
-                );
-
-                if transfer.is_direct {
-                    let _ = msg_tx.send(OutgoingMessage::Direct {
-                        target_id: transfer.target_peer.clone(),
-                        message: chunk_msg,
-                    });
-                } else {
-                    let _ = msg_tx.send(OutgoingMessage::Global(chunk_msg));
-                }
+        let Some(transfer) = self.outgoing_transfers.remove(file_id) else {
+            // Only the first to accept gets it
+            self.answer_offer_row(file_id, &msg.sender, "✅ accepted, too late to send");
+            return;
+        };
+        if !self.traffic.allows_file(transfer.offer.size) {
+            self.defer_file(file_id, transfer, &msg.sender);
+            return;
+        }
+        self.status = format!("{} accepted {}. Sending...", sender_name, transfer.offer.filename);
+        self.answer_offer_row(file_id, &msg.sender, "✅ accepted, sending");
+        self.stream_accepted(file_id.clone(), transfer, fx);
+    }
 
-                transfer.chunks_sent += 1;
-            }
+    /// A member's answer to a group offer. Each member who accepts gets their own copy
+    /// of the chunks, sent to them alone; the offer stays open until every member has
+    /// answered.
+    fn handle_group_file_response(&mut self, msg: PlainMessage, accept: bool, fx: &mut Vec<Effect>) {
+        let file_id = msg.content.clone();
+        let Some(transfer) = self.outgoing_transfers.get_mut(&file_id) else {
+            return;
+        };
+        let Some(members) = transfer.group_id.as_ref().and_then(|g| self.groups.get(g)).map(|g| g.members.clone()) else {
+            return;
+        };
+        // Only members get it, and only once
+        if !members.contains(&msg.sender) || transfer.answered.contains(&msg.sender) {
+            return;
+        }
+        transfer.answered.push(msg.sender.clone());
+        let everyone_answered = members.iter().all(|m| *m == self.own_id || transfer.answered.contains(m));
+        let for_member = accept.then(|| OutgoingTransfer { target_peer: msg.sender.clone(), control: Arc::default(), ..transfer.clone() });
+        let filename = transfer.offer.filename.clone();
+        if everyone_answered {
+            self.outgoing_transfers.remove(&file_id);
+        }
 
-            let filename = transfer.offer.filename.clone();
-            self.outgoing_transfers.remove(file_id);
-            self.status = format!("Sent {} successfully", filename);
+        let Some(for_member) = for_member else {
+            self.status = format!("{} turned down {}", self.get_peer_display_name(&msg.sender), filename);
+            self.answer_offer_row(&file_id, &msg.sender, "🚫 rejected");
+            return;
+        };
+        if !self.traffic.allows_file(for_member.offer.size) {
+            self.defer_file(&file_id, for_member, &msg.sender);
+            return;
         }
+        self.status = format!("{} accepted {}. Sending...", self.get_peer_display_name(&msg.sender), filename);
+        self.answer_offer_row(&file_id, &msg.sender, "✅ accepted, sending");
+        self.stream_accepted(file_id, for_member, fx);
     }
 
-    pub(crate) fn finalize_transfer(&mut self, file_id: &str) {
-        if let Some(transfer) = self.active_transfers.remove(file_id) {
-            let mut file_data = Vec::new();
-            for chunk_opt in &transfer.chunks_received {
-                if let Some(chunk) = chunk_opt {
-                    file_data.extend_from_slice(chunk);
-                } else {
-                    self.status = format!("Error: Missing chunks for {}", transfer.offer.filename);
-                    return;
-                }
-            }
-
-            let actual_checksum = blake3::hash(&file_data).to_hex().to_string();
-            if actual_checksum != transfer.offer.checksum {
-                self.status = format!("Error: Checksum mismatch for {}", transfer.offer.filename);
-                return;
-            }
+    /// Start sending an accepted file
+    pub(crate) fn stream_accepted(&mut self, file_id: String, transfer: OutgoingTransfer, fx: &mut Vec<Effect>) {
+        self.tally.files_sent += 1;
+        self.tally.file_bytes_sent += transfer.offer.size;
+        self.track_sending(&file_id, &transfer);
+        // Chunks are streamed by the UI task so the transfer is paced by the outbound queue
+        fx.push(Effect::StreamFile { file_id, transfer });
+    }
 
-            if let Err(e) = std::fs::write(&transfer.save_path, &file_data) {
-                self.status = format!("Error saving file: {}", e);
+    pub(crate) fn finalize_transfer(&mut self, file_id: &str) {
+        let Some(transfer) = self.active_transfers.remove(file_id) else {
+            return;
+        };
+        let saved = match save_transfer(&transfer) {
+            Ok(saved) => saved,
+            Err(reason) => {
+                self.status = format!("Error: {} — {}", transfer.offer.filename, reason);
+                self.update_offer_row(file_id, ChatEvent::FileFailed, &format!("❌ failed: {}", reason));
                 return;
             }
-
-            self.status = format!(
-                "File saved: {} ✓ ({})",
-                transfer.save_path.display(),
-                Self::format_size(transfer.offer.size)
-            );
-        }
+        };
+        self.tally.files_received += 1;
+        self.tally.file_bytes_received += transfer.offer.size;
+        self.record_download(&transfer);
+
+        let path = transfer.save_path.display();
+        let (status, done) = match saved {
+            Saved::File => (
+                format!("File saved: {} ✓ ({})", path, Self::format_size(transfer.offer.size)),
+                format!("✅ saved to {}", path),
+            ),
+            Saved::Extracted { written, skipped: 0 } => (
+                format!("Folder extracted: {} ✓ ({} entries)", path, written),
+                format!("✅ extracted into {} ({} entries)", path, written),
+            ),
+            Saved::Extracted { written, skipped } => (
+                format!("Folder extracted: {} ✓ ({} entries) — ⚠️ skipped {} unsafe entries", path, written, skipped),
+                format!("✅ extracted into {} ({} entries, ⚠️ skipped {} unsafe)", path, written, skipped),
+            ),
+        };
+        self.status = status;
+        self.update_offer_row(file_id, ChatEvent::FileCompleted, &format!(
+            "{} — {}, blake3 {}",
+            done,
+            transfer_rate(transfer.offer.size, transfer.started.elapsed()),
+            transfer.offer.checksum
+        ));
     }
 
     pub(crate) fn format_size(bytes: u64) -> String {
@@ -340,3 +587,245 @@ impl ChatUI {
         }
     }
 }
+
+/// How a finished download ended up on disk
+enum Saved {
+    File,
+    Extracted { written: usize, skipped: usize },
+}
+
+/// Reassemble a finished download, check it against the offer's checksum, and save or
+/// unpack it. The error is the reason, for the transfer's chat entry.
+fn save_transfer(transfer: &ActiveTransfer) -> Result<Saved, String> {
+    let mut file_data = Vec::with_capacity(transfer.offer.size as usize);
+    for chunk in &transfer.chunks_received {
+        file_data.extend_from_slice(chunk.as_deref().ok_or("missing chunks")?);
+    }
+    if blake3::hash(&file_data).to_hex().as_str() != transfer.offer.checksum {
+        return Err("checksum mismatch".to_string());
+    }
+    if transfer.extract {
+        let (written, skipped) = archive::extract(&file_data, &transfer.save_path)
+            .map_err(|e| format!("couldn't extract: {:#}", e))?;
+        return Ok(Saved::Extracted { written, skipped });
+    }
+    std::fs::write(&transfer.save_path, &file_data).map_err(|e| format!("couldn't save: {}", e))?;
+    Ok(Saved::File)
+}
+
+/// "1.20 MB in 3.2s (384.00 KB/s)"
+fn transfer_rate(size: u64, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64().max(0.001);
+    format!(
+        "{} in {:.1}s ({}/s)",
+        ChatState::format_size(size),
+        secs,
+        ChatState::format_size((size as f64 / secs) as u64)
+    )
+}
+
+/// What `/send` offers for a path
+struct Share {
+    data: Vec<u8>,
+    filename: String,
+    /// Entries in the archive when a directory is shared
+    entry_count: Option<u32>,
+    skipped_symlinks: usize,
+}
+
+/// Read a file, or pack a directory into `<name>.tar`, refusing anything over `max_bytes`
+fn read_share(path: &Path, max_bytes: u64) -> anyhow::Result<Share> {
+    // "." and ".." have no file name of their own
+    let name = path.file_name().map(|n| n.to_os_string())
+        .or_else(|| path.canonicalize().ok()?.file_name().map(|n| n.to_os_string()))
+        .map(|n| n.to_string_lossy().to_string());
+    if path.is_dir() {
+        let found = archive::scan(path, max_bytes)?;
+        let name = name.unwrap_or_else(|| "folder".to_string());
+        Ok(Share {
+            data: archive::build(&found)?,
+            filename: format!("{}.tar", name),
+            entry_count: Some(found.entry_count()),
+            skipped_symlinks: found.skipped_symlinks.len(),
+        })
+    } else {
+        let size = std::fs::metadata(path)?.len();
+        if size > max_bytes {
+            anyhow::bail!("over the {} share limit", ChatState::format_size(max_bytes));
+        }
+        Ok(Share {
+            data: std::fs::read(path)?,
+            filename: name.ok_or_else(|| anyhow::anyhow!("Invalid file path"))?,
+            entry_count: None,
+            skipped_symlinks: 0,
+        })
+    }
+}
+
+/// Characters Windows won't have in a file name; `:` would also name a drive or a
+/// stream
+const WINDOWS_RESERVED_CHARS: &[char] = &[':', '<', '>', '"', '|', '?', '*'];
+
+/// Reduce an offered file name to a single harmless path component, on any platform: no
+/// directories, no control characters, no leading dots, nothing Windows reads as a drive,
+/// a stream or a device. Falls back to a generated name if nothing is left.
+pub(crate) fn safe_filename(offered: &str, file_id: &str) -> String {
+    let last = offered.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = last.chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if WINDOWS_RESERVED_CHARS.contains(&c) { '_' } else { c })
+        .collect();
+    // Windows drops trailing dots and spaces, so "a.txt." would land on "a.txt"
+    let cleaned = cleaned.trim().trim_start_matches('.').trim_end_matches(['.', ' ']).trim();
+    if cleaned.is_empty() {
+        let id: String = file_id.chars().filter(char::is_ascii_alphanumeric).take(16).collect();
+        format!("received-{}", id)
+    } else if is_windows_device(cleaned) {
+        format!("_{}", cleaned)
+    } else {
+        cleaned.to_string()
+    }
+}
+
+/// Whether Windows opens a device for `name`, whatever its extension ("nul.txt" too)
+fn is_windows_device(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end().to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$" => true,
+        _ => ["COM", "LPT"].iter().any(|prefix| {
+            stem.strip_prefix(prefix)
+                .is_some_and(|n| matches!(n, "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "¹" | "²" | "³"))
+        }),
+    }
+}
+
+/// `path`, or the first free "name (n).ext" next to it
+pub(crate) fn unique_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    (1..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .expect("unbounded range")
+}
+
+/// Stream an accepted file's chunks from a task: send_bulk waits for room in the
+/// outbound queue, so a slow link paces the transfer instead of buffering the whole file.
+/// The rate limit, if any, paces it further; the counter says a file is going out.
+pub(super) fn stream_file(
+    tx: OutgoingSender,
+    own_id: String,
+    file_id: String,
+    mut transfer: OutgoingTransfer,
+    (limit, streaming): (Option<Arc<RateLimit>>, Arc<AtomicUsize>),
+) {
+    tokio::spawn(async move {
+        streaming.fetch_add(1, Ordering::Relaxed);
+        let sent = send_chunks(&tx, &own_id, &file_id, &mut transfer, limit.as_deref()).await;
+        streaming.fetch_sub(1, Ordering::Relaxed);
+        transfer.control.finish();
+        if sent {
+            tx.report(format!("Sent {} successfully ({} chunks)", transfer.offer.filename, transfer.chunks_sent));
+        }
+    });
+}
+
+/// Chunk `index` of `transfer`, addressed: to the peer for a DM offer, to the member
+/// who accepted for a group offer (never the whole relay), to Global for a Global one
+pub(super) fn chunk_message(own_id: &str, file_id: &str, transfer: &OutgoingTransfer, index: u32, data: &[u8]) -> OutgoingMessage {
+    let chunk = FileChunk {
+        file_id: file_id.to_string(),
+        index,
+        data: data.to_vec(),
+    };
+    let direct = transfer.is_direct || transfer.group_id.is_some();
+    let chunk_msg = PlainMessage::file_chunk(own_id.to_string(), chunk, direct);
+    if direct {
+        OutgoingMessage::Direct {
+            target_id: transfer.target_peer.clone(),
+            message: chunk_msg,
+        }
+    } else {
+        OutgoingMessage::Global(chunk_msg)
+    }
+}
+
+/// Send every chunk of `transfer`. False if the connection went away first.
+async fn send_chunks(tx: &OutgoingSender, own_id: &str, file_id: &str, transfer: &mut OutgoingTransfer, limit: Option<&RateLimit>) -> bool {
+    for (i, chunk_data) in transfer.file_data.chunks(FILE_CHUNK_SIZE).enumerate() {
+        let outgoing = chunk_message(own_id, file_id, transfer, i as u32, chunk_data);
+        transfer.control.ready(chunk_data.len()).await;
+        if let Some(limit) = limit {
+            limit.take(chunk_data.len()).await;
+        }
+        if tx.send_bulk(outgoing).await.is_err() {
+            return false;
+        }
+
+        transfer.chunks_sent += 1;
+        transfer.control.chunk_sent();
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safe_filename_strips_paths_and_dots() {
+        assert_eq!(safe_filename("../../.bashrc", "f1"), "bashrc");
+        assert_eq!(safe_filename("/etc/passwd", "f1"), "passwd");
+        assert_eq!(safe_filename("..\\..\\win.ini", "f1"), "win.ini");
+        assert_eq!(safe_filename("photo.jpg", "f1"), "photo.jpg");
+    }
+
+    #[test]
+    fn test_safe_filename_strips_control_characters() {
+        assert_eq!(safe_filename("evil\nname\r.txt", "f1"), "evilname.txt");
+        assert_eq!(safe_filename("\x1b[31mred\x1b[0m.txt", "f1"), "[31mred[0m.txt");
+    }
+
+    #[test]
+    fn test_safe_filename_falls_back_when_nothing_is_left() {
+        assert_eq!(safe_filename("..", "ab12"), "received-ab12");
+        assert_eq!(safe_filename("", "ab12"), "received-ab12");
+        assert_eq!(safe_filename("dir/", "ab12"), "received-ab12");
+        assert_eq!(safe_filename("\u{7}", "../x"), "received-x");
+        assert_eq!(safe_filename(". . .", "ab12"), "received-ab12");
+    }
+
+    #[test]
+    fn test_safe_filename_is_safe_on_windows() {
+        // A drive-relative path, and an alternate data stream
+        assert_eq!(safe_filename("C:evil.exe", "f1"), "C_evil.exe");
+        assert_eq!(safe_filename("notes.txt:hidden", "f1"), "notes.txt_hidden");
+        assert_eq!(safe_filename("a<b>\"c\"|d?e*.txt", "f1"), "a_b__c__d_e_.txt");
+        // Windows would drop these and write a different file
+        assert_eq!(safe_filename("report.pdf. . ", "f1"), "report.pdf");
+        // Device names, with or without an extension, in any case
+        assert_eq!(safe_filename("CON", "f1"), "_CON");
+        assert_eq!(safe_filename("nul.txt", "f1"), "_nul.txt");
+        assert_eq!(safe_filename("Com1.log", "f1"), "_Com1.log");
+        assert_eq!(safe_filename("lpt9", "f1"), "_lpt9");
+        assert_eq!(safe_filename("aux .tar.gz", "f1"), "_aux .tar.gz");
+        assert_eq!(safe_filename("console.txt", "f1"), "console.txt");
+        assert_eq!(safe_filename("com10", "f1"), "com10");
+    }
+
+    #[test]
+    fn test_unique_path_appends_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("note.txt");
+        assert_eq!(unique_path(path.clone()), path);
+
+        std::fs::write(&path, b"one").unwrap();
+        assert_eq!(unique_path(path.clone()), dir.path().join("note (1).txt"));
+        std::fs::write(dir.path().join("note (1).txt"), b"two").unwrap();
+        assert_eq!(unique_path(path), dir.path().join("note (2).txt"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Ordered group delivery. Each member numbers its chat in a group (`group_seq`, inside
+//! the encrypted message), and the relay may hand members the frames in any order.
+//! A message that arrives ahead of its turn waits up to REORDER_WAIT for the ones
+//! before it; if they still haven't come, a placeholder marks the gap and the sender
+//! is asked (in a DM) to send them again from the last MAX_GROUP_RESEND it keeps.
+//! A message that fills a gap later replaces the placeholder.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::client::OutgoingMessage;
+use crate::protocol::{GroupResend, PlainMessage, MAX_GROUP_RESEND};
+
+use super::parts::send_in_parts;
+use super::state::{ChatState, Effect};
+use super::types::Tab;
+
+/// How long a message that came early waits for the ones before it
+const REORDER_WAIT: Duration = Duration::from_millis(200);
+
+/// Sequence numbers in and out, and our recent group messages
+#[derive(Debug, Default)]
+pub(crate) struct GroupOrder {
+    /// Our next number in each group
+    next_out: HashMap<String, u64>,
+    /// Our latest messages in each group, oldest first, to send again when asked
+    sent: HashMap<String, VecDeque<PlainMessage>>,
+    /// Where each (group, sender) is up to
+    senders: HashMap<(String, String), SenderSeq>,
+}
+
+/// What we've had from one member in one group
+#[derive(Debug)]
+struct SenderSeq {
+    /// The number we show next
+    next: u64,
+    /// Messages that came early, with when they arrived
+    held: BTreeMap<u64, (PlainMessage, Instant)>,
+    /// Numbers given up on, each with the id of the placeholder standing in for it
+    missing: BTreeMap<u64, String>,
+}
+
+impl SenderSeq {
+    fn starting_at(seq: u64) -> Self {
+        Self { next: seq, held: BTreeMap::new(), missing: BTreeMap::new() }
+    }
+
+    /// Held messages that are now next in line
+    fn take_ready(&mut self) -> Vec<PlainMessage> {
+        let mut ready = Vec::new();
+        while let Some((msg, _)) = self.held.remove(&self.next) {
+            ready.push(msg);
+            self.next += 1;
+        }
+        ready
+    }
+
+    /// When the earliest held message has waited long enough
+    fn deadline(&self) -> Option<Instant> {
+        self.held.values().map(|(_, at)| *at + REORDER_WAIT).min()
+    }
+
+    /// Give up waiting for what comes before the first held message; returns the
+    /// numbers skipped
+    fn skip_gap(&mut self) -> Vec<u64> {
+        let Some(&first) = self.held.keys().next() else {
+            return Vec::new();
+        };
+        let skipped = (self.next..first).collect();
+        self.next = first;
+        skipped
+    }
+}
+
+/// The placeholder text for `n` missing messages from `name`
+fn missing_text(name: &str, n: usize) -> String {
+    match n {
+        1 => format!("(a message from {} may be missing)", name),
+        n => format!("({} messages from {} may be missing)", n, name),
+    }
+}
+
+impl ChatState {
+    /// Number our message `msg` in `group_id` and keep it for resending
+    pub(crate) fn number_group_message(&mut self, group_id: &str, msg: &mut PlainMessage) {
+        let next = self.group_order.next_out.entry(group_id.to_string()).or_default();
+        msg.group_seq = Some(*next);
+        *next += 1;
+        let sent = self.group_order.sent.entry(group_id.to_string()).or_default();
+        sent.push_back(msg.clone());
+        if sent.len() > MAX_GROUP_RESEND {
+            sent.pop_front();
+        }
+    }
+
+    /// Show a numbered group message from a member in its turn, holding it if it came early
+    pub(crate) fn order_group_message(&mut self, msg: PlainMessage) {
+        let (Some(group_id), Some(seq)) = (msg.group_id.clone(), msg.group_seq) else {
+            return;
+        };
+        let key = (group_id.clone(), msg.sender.clone());
+        let track = self.group_order.senders.entry(key.clone()).or_insert_with(|| SenderSeq::starting_at(seq));
+        let tab = Tab::Group(group_id);
+
+        if seq > track.next {
+            track.held.insert(seq, (msg, Instant::now()));
+        } else if seq == track.next {
+            track.next += 1;
+            let ready = track.take_ready();
+            self.tally.group_reordered += ready.len() as u64;
+            self.push_message(tab.clone(), msg);
+            for msg in ready {
+                self.push_message(tab.clone(), msg);
+            }
+        } else if let Some(placeholder) = track.missing.remove(&seq) {
+            // Sent again, or just very late: it takes the placeholder's place
+            let still_missing = track.missing.values().filter(|id| **id == placeholder).count();
+            self.tally.group_recovered += 1;
+            let name = self.get_peer_display_name(&key.1);
+            let id = msg.message_id.clone();
+            self.push_message(tab.clone(), msg);
+            let Some(messages) = self.messages.get_mut(&tab) else {
+                return;
+            };
+            let Some(at) = messages.iter().position(|m| m.message_id.as_ref() == Some(&placeholder)) else {
+                return;
+            };
+            if let Some(from) = messages.iter().position(|m| id.is_some() && m.message_id == id) {
+                let recovered = messages.remove(from);
+                messages.insert(if from < at { at - 1 } else { at }, recovered);
+            }
+            let at = messages.iter().position(|m| m.message_id.as_ref() == Some(&placeholder)).unwrap_or(at);
+            if still_missing == 0 {
+                messages.remove(at);
+            } else {
+                messages[at].content = missing_text(&name, still_missing);
+            }
+        } else {
+            // Numbering started over: they restarted
+            let stale: Vec<PlainMessage> = std::mem::take(&mut track.held).into_values().map(|(m, _)| m).collect();
+            *track = SenderSeq::starting_at(seq + 1);
+            for msg in stale.into_iter().chain([msg]) {
+                self.push_message(tab.clone(), msg);
+            }
+        }
+    }
+
+    /// When the next held group message stops waiting, if any is held
+    pub(crate) fn reorder_deadline(&self) -> Option<Instant> {
+        self.group_order.senders.values().filter_map(SenderSeq::deadline).min()
+    }
+
+    /// Stop waiting for messages that haven't turned up in time: mark the gaps, ask
+    /// their senders to send them again, and show what was held
+    pub(crate) fn release_overdue(&mut self, now: Instant) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        let overdue: Vec<(String, String)> = self.group_order.senders.iter()
+            .filter(|(_, track)| track.deadline().is_some_and(|at| at <= now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in overdue {
+            let (group_id, sender) = key.clone();
+            let tab = Tab::Group(group_id.clone());
+            let name = self.get_peer_display_name(&sender);
+            let mut requested = Vec::new();
+            while let Some(track) = self.group_order.senders.get_mut(&key) {
+                if track.deadline().is_none_or(|at| at > now) {
+                    break;
+                }
+                let skipped = track.skip_gap();
+                let ready = track.take_ready();
+                let placeholder = PlainMessage::generate_id();
+                for seq in &skipped {
+                    track.missing.insert(*seq, placeholder.clone());
+                }
+                self.tally.group_gaps += 1;
+                self.push_message(tab.clone(), PlainMessage {
+                    message_id: Some(placeholder),
+                    ..PlainMessage::system(self.own_id.clone(), missing_text(&name, skipped.len()))
+                });
+                for msg in ready {
+                    self.push_message(tab.clone(), msg);
+                }
+                requested.extend(skipped);
+            }
+            // The sender only keeps its latest few, so ask for the newest of them
+            let seqs = requested.split_off(requested.len().saturating_sub(MAX_GROUP_RESEND));
+            let request = PlainMessage::group_resend(self.own_id.clone(), GroupResend { group_id, seqs });
+            fx.push(Effect::Send(OutgoingMessage::Direct { target_id: sender, message: request }));
+        }
+        fx
+    }
+
+    /// A member missed some of our group messages: send them the ones we still have
+    pub(crate) fn handle_group_resend(&mut self, from: &str, request: GroupResend, fx: &mut Vec<Effect>) {
+        let is_member = self.groups.get(&request.group_id).is_some_and(|g| g.members.iter().any(|id| id == from));
+        let Some(sent) = self.group_order.sent.get(&request.group_id).filter(|_| is_member) else {
+            return;
+        };
+        let again: Vec<PlainMessage> = sent.iter()
+            .filter(|m| m.group_seq.is_some_and(|seq| request.seqs.contains(&seq)))
+            .cloned()
+            .collect();
+        self.tally.group_resent += again.len() as u64;
+        for msg in again {
+            send_in_parts(msg, fx, |message| OutgoingMessage::Direct { target_id: from.to_string(), message });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::tui::types::GroupInfo;
+
+    fn shown(state: &ChatState, tab: &Tab) -> Vec<String> {
+        state.messages[tab].iter().map(|m| m.content.clone()).collect()
+    }
+
+    #[test]
+    fn test_group_messages_reordered_and_gaps_filled() {
+        let (alice, bob) = ("aa".repeat(16), "bb".repeat(16));
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+        let members = vec!["me".repeat(16), alice.clone()];
+        state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members, join_token: None });
+        let tab = Tab::Group("g1".to_string());
+
+        // Alice's messages numbered as her client would
+        let mut alice_state = ChatState::new(alice.clone(), None, vec![1; 32]);
+        let said: Vec<PlainMessage> = (0..6).map(|i| {
+            let mut msg = PlainMessage::group(alice.clone(), format!("m{}", i), "g1".to_string());
+            alice_state.number_group_message("g1", &mut msg);
+            msg
+        }).collect();
+
+        // An adjacent swap is put right without waiting
+        state.ingest_message(said[0].clone());
+        state.ingest_message(said[2].clone());
+        assert!(state.reorder_deadline().is_some());
+        state.ingest_message(said[1].clone());
+        assert_eq!(shown(&state, &tab), ["m0", "m1", "m2"]);
+        assert_eq!((state.reorder_deadline(), state.tally.group_reordered), (None, 1));
+
+        // m3 and m4 never come: after the wait, a placeholder and a resend request
+        state.ingest_message(said[5].clone());
+        let at = state.reorder_deadline().unwrap();
+        assert!(state.release_overdue(at - Duration::from_millis(1)).is_empty());
+        let fx = state.release_overdue(at);
+        assert_eq!(shown(&state, &tab), ["m0", "m1", "m2", "(2 messages from alice may be missing)", "m5"]);
+        let request = match &fx[..] {
+            [Effect::Send(OutgoingMessage::Direct { target_id, message })] if *target_id == alice => message.clone(),
+            _ => panic!("expected a resend request to alice"),
+        };
+        assert_eq!(request.group_resend, Some(GroupResend { group_id: "g1".to_string(), seqs: vec![3, 4] }));
+
+        // Alice sends them again, to members only
+        alice_state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members: vec!["me".repeat(16)], join_token: None });
+        let mut fx = Vec::new();
+        alice_state.handle_group_resend(&bob, request.group_resend.clone().unwrap(), &mut fx);
+        assert!(fx.is_empty());
+        let fx = alice_state.ingest_message(PlainMessage { sender: "me".repeat(16), ..request });
+        let again: Vec<PlainMessage> = fx.into_iter().filter_map(|e| match e {
+            Effect::Send(OutgoingMessage::Direct { message, .. }) => Some(message),
+            _ => None,
+        }).collect();
+        assert_eq!(again.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), ["m3", "m4"]);
+        assert_eq!(alice_state.tally.group_resent, 2);
+
+        // The placeholder counts down, then goes
+        state.ingest_message(again[0].clone());
+        assert!(shown(&state, &tab).contains(&"(a message from alice may be missing)".to_string()));
+        state.ingest_message(again[1].clone());
+        assert_eq!(shown(&state, &tab), ["m0", "m1", "m2", "m3", "m4", "m5"]);
+        assert_eq!((state.tally.group_gaps, state.tally.group_recovered), (1, 2));
+    }
+}
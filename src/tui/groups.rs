@@ -1,16 +1,15 @@
-use tokio::sync::mpsc;
-
 use crate::client::OutgoingMessage;
-use crate::protocol::{GroupInvite, PlainMessage};
+use crate::protocol::{GroupInvite, MemberHint, PlainMessage, MAX_INVITE_HINTS};
+use crate::strings::{self, Key};
 
-use super::helpers::generate_group_id;
+use super::helpers::{generate_group_id, generate_join_token};
 use super::types::{GroupInfo, Tab};
-use super::ChatUI;
+use super::state::{ChatState, Effect};
 
-impl ChatUI {
-    pub(crate) fn handle_group_command(&mut self, parts: &[&str], msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
+impl ChatState {
+    pub(crate) fn handle_group_command(&mut self, parts: &[&str], fx: &mut Vec<Effect>) {
         if parts.is_empty() {
-            self.status = "Usage: /group create <name> | invite <peer> | leave | members".to_string();
+            self.status = "Usage: /group create <name> | invite <peer> | leave | members | sync".to_string();
             return;
         }
 
@@ -22,10 +21,12 @@ impl ChatUI {
                 }
                 let group_name = parts[1..].join(" ");
                 let group_id = generate_group_id();
+                let join_token = generate_join_token();
 
                 self.groups.insert(group_id.clone(), GroupInfo {
                     name: group_name.clone(),
                     members: Vec::new(),
+                    join_token: Some(join_token.clone()),
                 });
 
                 let group_tab = Tab::Group(group_id.clone());
@@ -33,7 +34,10 @@ impl ChatUI {
                 self.messages.insert(group_tab.clone(), Vec::new());
                 self.active_tab = self.tabs.len() - 1;
 
-                let _ = msg_tx.send(OutgoingMessage::JoinRoom { group_id: group_id.clone() });
+                fx.push(Effect::Send(OutgoingMessage::JoinRoom {
+                    group_id: group_id.clone(),
+                    join_token: Some(join_token),
+                }));
 
                 self.add_system_message(
                     &group_tab,
@@ -53,15 +57,15 @@ impl ChatUI {
                 let group_id = match &current_tab {
                     Tab::Group(id) => id.clone(),
                     _ => {
-                        self.status = "Switch to a group tab first".to_string();
+                        self.status = strings::text(Key::NotInGroupTab, &[]);
                         return;
                     }
                 };
 
                 let peer_id = match self.find_peer_by_name_or_id(target) {
-                    Some(id) => id,
-                    None => {
-                        self.status = format!("Peer not found: {}", target);
+                    Ok(id) => id,
+                    Err(e) => {
+                        self.status = e;
                         return;
                     }
                 };
@@ -78,12 +82,14 @@ impl ChatUI {
                 let invite = GroupInvite {
                     group_id: group_id.clone(),
                     group_name: group_name.clone(),
+                    join_token: self.groups.get(&group_id).and_then(|g| g.join_token.clone()),
+                    members: self.member_hints(&group_id),
                 };
                 let invite_msg = PlainMessage::group_invite_msg(self.own_id.clone(), invite);
-                let _ = msg_tx.send(OutgoingMessage::Direct {
+                fx.push(Effect::Send(OutgoingMessage::Direct {
                     target_id: peer_id.clone(),
                     message: invite_msg,
-                });
+                }));
 
                 if let Some(group) = self.groups.get_mut(&group_id) {
                     group.members.push(peer_id.clone());
@@ -98,32 +104,34 @@ impl ChatUI {
                 let group_id = match &current_tab {
                     Tab::Group(id) => id.clone(),
                     _ => {
-                        self.status = "Switch to a group tab first".to_string();
+                        self.status = strings::text(Key::NotInGroupTab, &[]);
                         return;
                     }
                 };
 
-                let _ = msg_tx.send(OutgoingMessage::LeaveRoom { group_id: group_id.clone() });
+                fx.push(Effect::Send(OutgoingMessage::LeaveRoom { group_id: group_id.clone() }));
 
                 if let Some(group) = self.groups.get(&group_id) {
-                    let leave_msg = PlainMessage::group(
-                        self.own_id.clone(),
-                        format!("{} has left the group", self.display_name()),
-                        group_id.clone(),
-                    );
-                    let mut sys_leave = leave_msg.clone();
-                    sys_leave.system = true;
+                    let sys_leave = PlainMessage {
+                        group_id: Some(group_id.clone()),
+                        message_id: Some(PlainMessage::generate_id()),
+                        ..PlainMessage::notice(self.own_id.clone(), Key::LeftGroup, &[("name", &self.display_name())])
+                    };
                     let member_ids: Vec<String> = group.members.clone();
-                    let _ = msg_tx.send(OutgoingMessage::Group {
+                    fx.push(Effect::Send(OutgoingMessage::Group {
                         group_id: group_id.clone(),
                         member_ids,
                         message: sys_leave,
-                    });
+                    }));
                 }
 
                 let group_name = self.group_name(&group_id);
                 self.groups.remove(&group_id);
-                self.messages.remove(&current_tab);
+                self.member_keys.remove(&group_id);
+                self.room_presence.remove(&group_id);
+                self.drop_tab_messages(&current_tab);
+                self.unread.remove(&current_tab);
+                self.mention_unread.remove(&current_tab);
                 if let Some(idx) = self.tabs.iter().position(|t| t == &current_tab) {
                     self.tabs.remove(idx);
                     if self.active_tab >= self.tabs.len() {
@@ -138,7 +146,7 @@ impl ChatUI {
                 let group_id = match &current_tab {
                     Tab::Group(id) => id.clone(),
                     _ => {
-                        self.status = "Switch to a group tab first".to_string();
+                        self.status = strings::text(Key::NotInGroupTab, &[]);
                         return;
                     }
                 };
@@ -159,34 +167,33 @@ impl ChatUI {
                     self.status = "Group not found".to_string();
                 }
             }
+            "sync" => match &self.tabs[self.active_tab] {
+                Tab::Group(id) => {
+                    let group_id = id.clone();
+                    self.handle_group_sync_command(&group_id, fx);
+                }
+                _ => self.status = strings::text(Key::NotInGroupTab, &[]),
+            },
             _ => {
-                self.status = "Usage: /group create <name> | invite <peer> | leave | members".to_string();
+                self.status = "Usage: /group create <name> | invite <peer> | leave | members | sync".to_string();
             }
         }
     }
 
-    pub(crate) fn handle_group_invite(&mut self, msg: PlainMessage, invite: GroupInvite, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
-        let sender_name = self.get_peer_display_name(&msg.sender);
-        let group_id = invite.group_id.clone();
-        let group_name = invite.group_name.clone();
-
-        self.groups.insert(group_id.clone(), GroupInfo {
-            name: group_name.clone(),
-            members: vec![msg.sender.clone()],
-        });
-
-        let group_tab = Tab::Group(group_id.clone());
-        self.ensure_tab(&group_tab);
-
-        let _ = msg_tx.send(OutgoingMessage::JoinRoom { group_id: group_id.clone() });
-
-        // Use sender's ID for system message attribution (not our own)
-        let sys_msg = PlainMessage::system(
-            msg.sender.clone(),
-            format!("{} invited you to \"{}\"", sender_name, group_name),
-        );
-        self.messages.entry(group_tab).or_insert_with(Vec::new).push(sys_msg);
+    /// Members of `group_id` we hold an identity key for, for an invite
+    fn member_hints(&self, group_id: &str) -> Vec<MemberHint> {
+        let Some(group) = self.groups.get(group_id) else {
+            return Vec::new();
+        };
+        group.members.iter()
+            .filter_map(|id| self.member_hint(group_id, id))
+            .take(MAX_INVITE_HINTS)
+            .collect()
+    }
 
-        self.status = format!("Joined group: {} (invited by {})", group_name, sender_name);
+    /// The client put us back in these rooms after a reconnect
+    pub(crate) fn rooms_rejoined(&mut self, group_ids: &[String]) {
+        let names: Vec<String> = group_ids.iter().map(|id| self.group_name(id)).collect();
+        self.status = format!("↻ Back in {}", names.join(", "));
     }
 }
@@ -1,13 +1,14 @@
-use std::path::PathBuf;
-use tokio::sync::mpsc;
-
 use crate::client::OutgoingMessage;
-use crate::protocol::PlainMessage;
+use crate::protocol::{short_id, ChatEvent, PlainMessage, JOIN_TOKEN_LEN};
 
+use super::ordering::insert_in_order;
 use super::types::Tab;
-use super::ChatUI;
+use super::state::{ChatState, Effect};
+
+/// Fewest id characters after the `#` when two peers share a nickname
+const NICK_SUFFIX_CHARS: usize = 4;
 
-impl ChatUI {
+impl ChatState {
     /// Ensure a tab exists; create it if missing
     pub(crate) fn ensure_tab(&mut self, tab: &Tab) {
         if !self.tabs.contains(tab) {
@@ -16,11 +17,52 @@ impl ChatUI {
         }
     }
 
+    /// Append a message to a tab (creating it if needed), counting it as unread
+    /// unless the tab is currently focused
+    pub(crate) fn push_message(&mut self, tab: Tab, msg: PlainMessage) {
+        self.ensure_tab(&tab);
+        self.chime_for_message(&tab, &msg);
+        if self.tabs[self.active_tab] != tab {
+            *self.unread.entry(tab.clone()).or_insert(0) += 1;
+            if msg.mentions_me {
+                *self.mention_unread.entry(tab.clone()).or_insert(0) += 1;
+            }
+        }
+        self.tally.count_message(&tab, &msg, &self.own_id);
+        self.count_missed(&tab, &msg);
+        if let Some(ref mut transcript) = self.transcript {
+            transcript.push((tab.clone(), msg.clone()));
+        }
+        // Ours and catch-up copies go at the end; a peer's may have been held up
+        let messages = self.messages.entry(tab).or_default();
+        if msg.sender == self.own_id || msg.synced {
+            messages.push(msg);
+        } else {
+            insert_in_order(messages, msg);
+        }
+    }
+
+    /// Focus a tab and clear its unread count
+    pub(crate) fn set_active_tab(&mut self, idx: usize) {
+        self.active_tab = idx;
+        if let Some(tab) = self.tabs.get(idx) {
+            self.unread.remove(tab);
+            self.mention_unread.remove(tab);
+        }
+    }
+
     /// Add a system message to a tab, ensuring the tab exists first
     pub(crate) fn add_system_message(&mut self, tab: &Tab, text: String) {
         self.ensure_tab(tab);
         let sys_msg = PlainMessage::system(self.own_id.clone(), text);
-        self.messages.entry(tab.clone()).or_insert_with(Vec::new).push(sys_msg);
+        self.push_message(tab.clone(), sys_msg);
+    }
+
+    /// Add a transfer or call milestone to a tab, marked with what happened
+    pub(crate) fn add_event_message(&mut self, tab: &Tab, event: ChatEvent, text: String) {
+        self.ensure_tab(tab);
+        let msg = PlainMessage::event(self.own_id.clone(), event, text);
+        self.push_message(tab.clone(), msg);
     }
 
     /// Get the display name for a group, falling back to a default
@@ -30,63 +72,119 @@ impl ChatUI {
             .unwrap_or_else(|| "Group".to_string())
     }
 
-    pub(crate) fn open_dm_tab(&mut self, target: &str, msg_tx: Option<&mpsc::UnboundedSender<OutgoingMessage>>) {
-        let peer_id = self.find_peer_by_name_or_id(target);
-
-        if let Some(id) = peer_id {
-            let dm_tab = Tab::DirectMessage(id.clone());
-
-            if let Some(idx) = self.tabs.iter().position(|t| t == &dm_tab) {
-                self.active_tab = idx;
-            } else {
-                self.tabs.push(dm_tab.clone());
-                self.messages.insert(dm_tab, Vec::new());
-                self.active_tab = self.tabs.len() - 1;
-
-                // Send DM request to peer so they open a tab too
-                if let Some(tx) = msg_tx {
-                    let dm_req = PlainMessage::dm_request(self.own_id.clone());
-                    let _ = tx.send(OutgoingMessage::Direct {
-                        target_id: id.clone(),
-                        message: dm_req,
-                    });
+    pub(crate) fn open_dm_tab(&mut self, target: &str, mut fx: Option<&mut Vec<Effect>>) {
+        let id = match self.find_peer_by_name_or_id(target) {
+            Ok(id) => id,
+            Err(e) => {
+                // A full session id we haven't met can still be asked for
+                if !fx.as_deref_mut().is_some_and(|fx| self.discover_peer(target, fx)) {
+                    self.status = e;
                 }
+                return;
             }
+        };
+        let dm_tab = Tab::DirectMessage(id.clone());
 
-            let peer_name = self.get_peer_display_name(&id);
-            self.status = format!("Opened DM with {}", peer_name);
+        if let Some(idx) = self.tabs.iter().position(|t| t == &dm_tab) {
+            self.set_active_tab(idx);
         } else {
-            self.status = format!("Peer not found: {}", target);
+            self.tabs.push(dm_tab.clone());
+            self.messages.insert(dm_tab, Vec::new());
+            self.active_tab = self.tabs.len() - 1;
+
+            // Send DM request to peer so they open a tab too
+            if let Some(fx) = fx {
+                let dm_req = PlainMessage::dm_request(self.own_id.clone());
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: id.clone(),
+                    message: dm_req,
+                }));
+            }
         }
+
+        let peer_name = self.get_peer_display_name(&id);
+        self.status = format!("Opened DM with {}", peer_name);
     }
 
-    pub(crate) fn find_peer_by_name_or_id(&self, target: &str) -> Option<String> {
-        // First try exact nickname match
-        for (id, info) in &self.peers {
-            if let Some(ref nick) = info.nickname {
-                if nick.eq_ignore_ascii_case(target) {
-                    return Some(id.clone());
-                }
-            }
+    /// Resolve a peer from a command argument: a nickname, `nick#suffix` (how clashing
+    /// nicknames are shown), or a session id prefix. Err is the status line to show.
+    pub(crate) fn find_peer_by_name_or_id(&self, target: &str) -> Result<String, String> {
+        let named = |nick: &str| -> Vec<&String> {
+            self.peers.iter()
+                .filter(|(_, info)| info.nickname.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(nick)))
+                .map(|(id, _)| id)
+                .collect()
+        };
+
+        // `alex#1a2b` — nicknames never contain '#', so this can't be a plain name
+        if let Some((nick, suffix)) = target.rsplit_once('#') {
+            let ids = named(nick);
+            let suffix = suffix.to_ascii_lowercase();
+            let matching: Vec<&&String> = ids.iter().filter(|id| !suffix.is_empty() && id.starts_with(&suffix)).collect();
+            return match matching.as_slice() {
+                [id] => Ok(id.to_string()),
+                [] => Err(format!("Peer not found: {}", target)),
+                _ => Err(self.ambiguous(nick, &ids)),
+            };
+        }
+
+        match named(target).as_slice() {
+            [id] => return Ok(id.to_string()),
+            [] => {}
+            ids => return Err(self.ambiguous(target, ids)),
         }
 
-        // Then try ID prefix match
-        for id in self.peers.keys() {
-            if id.starts_with(target) {
-                return Some(id.clone());
+        let mut ids: Vec<&String> = self.peers.keys().filter(|id| id.starts_with(target)).collect();
+        match ids.as_slice() {
+            [id] => Ok(id.to_string()),
+            [] => Err(format!("Peer not found: {}", target)),
+            _ => {
+                ids.sort();
+                let ids: Vec<&str> = ids.iter().map(|id| short_id(id)).collect();
+                Err(format!("Several peers match {}: {} — give more of the id", target, ids.join(", ")))
             }
         }
+    }
 
-        None
+    /// "Multiple peers named alex: alex#1a2b, alex#9f3c — use the suffixed form"
+    fn ambiguous(&self, nick: &str, ids: &[&String]) -> String {
+        let mut names: Vec<String> = ids.iter().map(|id| self.get_peer_display_name(id)).collect();
+        names.sort();
+        format!("Multiple peers named {}: {} — use the suffixed form", nick, names.join(", "))
     }
 
+    /// A peer's nickname, with `#` and the shortest distinguishing start of its id when
+    /// another peer goes by the same name; `~name` while we only have a group invite's
+    /// word for it; the short id if it has no nickname
     pub(crate) fn get_peer_display_name(&self, peer_id: &str) -> String {
-        if let Some(info) = self.peers.get(peer_id) {
-            if let Some(ref nick) = info.nickname {
-                return nick.clone();
-            }
+        let Some(nick) = self.peers.get(peer_id).and_then(|info| info.nickname.as_ref()) else {
+            return match self.unconfirmed_name(peer_id) {
+                Some(hint) => format!("~{}", hint),
+                None => short_id(peer_id).to_string(),
+            };
+        };
+        let namesakes: Vec<&String> = self.peers.iter()
+            .filter(|(id, info)| *id != peer_id && info.nickname.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(nick)))
+            .map(|(id, _)| id)
+            .collect();
+        if namesakes.is_empty() {
+            return nick.clone();
+        }
+        let suffix = (NICK_SUFFIX_CHARS..peer_id.len())
+            .filter_map(|len| peer_id.get(..len))
+            .find(|prefix| namesakes.iter().all(|id| !id.starts_with(prefix)))
+            .unwrap_or(peer_id);
+        format!("{}#{}", nick, suffix)
+    }
+
+    /// The name a group invite gave for a peer who hasn't sent us their own, as long as
+    /// the identity key it came with is the one the peer holds
+    pub(crate) fn unconfirmed_name(&self, peer_id: &str) -> Option<&str> {
+        let hint = self.name_hints.get(peer_id)?;
+        match self.peers.get(peer_id) {
+            Some(peer) if peer.nickname.is_some() || peer.public_key != hint.public_key => None,
+            _ => Some(&hint.nickname),
         }
-        peer_id[..12.min(peer_id.len())].to_string()
     }
 
     pub(crate) fn display_name(&self) -> String {
@@ -110,22 +208,32 @@ impl ChatUI {
 
     pub(crate) fn next_tab(&mut self) {
         if !self.tabs.is_empty() {
-            self.active_tab = (self.active_tab + 1) % self.tabs.len();
+            self.set_active_tab((self.active_tab + 1) % self.tabs.len());
         }
     }
 
     pub(crate) fn prev_tab(&mut self) {
         if !self.tabs.is_empty() {
-            self.active_tab = if self.active_tab == 0 {
+            let idx = if self.active_tab == 0 {
                 self.tabs.len() - 1
             } else {
                 self.active_tab - 1
             };
+            self.set_active_tab(idx);
         }
     }
 
+    /// "(3 online)" for a group tab whose room count the relay has told us
+    pub(crate) fn presence_label(&self, tab: &Tab) -> Option<String> {
+        let Tab::Group(group_id) = tab else {
+            return None;
+        };
+        self.room_presence.get(group_id).map(|count| format!("({} online)", count))
+    }
+
     pub(crate) fn get_tab_name(&self, tab: &Tab) -> String {
         match tab {
+            Tab::Global if self.no_global => "notices".to_string(),
             Tab::Global => "#global".to_string(),
             Tab::DirectMessage(peer_id) => {
                 self.get_peer_display_name(peer_id)
@@ -148,6 +256,12 @@ pub fn generate_group_id() -> String {
     hex::encode(random_bytes)
 }
 
+/// Random room password for a new group (only its hash ever reaches the relay)
+pub fn generate_join_token() -> Vec<u8> {
+    use rand::Rng;
+    (0..JOIN_TOKEN_LEN).map(|_| rand::thread_rng().gen()).collect()
+}
+
 /// Format a duration smartly: "1:23" for under an hour, "2:45:03" for hours, "1d 3:20:15" for days
 pub fn format_duration(duration: chrono::Duration) -> String {
     let total_secs = duration.num_seconds();
@@ -165,14 +279,30 @@ pub fn format_duration(duration: chrono::Duration) -> String {
     }
 }
 
-/// Expand ~ in paths to the user's home directory
-pub fn expand_path(path: &str) -> PathBuf {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        if let Some(home) = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
-            let mut buf = PathBuf::from(home);
-            buf.push(stripped);
-            return buf;
-        }
+/// Parse a TTL like "30s", "5m", "2h" or "1d" (bare numbers are seconds)
+pub fn parse_ttl(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (digits, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => text.split_at(idx),
+        None => (text, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    let value: u64 = digits.parse().ok()?;
+    value.checked_mul(scale).filter(|secs| *secs > 0)
+}
+
+/// Compact TTL for display: the largest whole unit, e.g. "4m" or "2d"
+pub fn format_ttl(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
     }
-    PathBuf::from(path)
 }
@@ -0,0 +1,268 @@
+//! Call hold and call waiting. A call on hold keeps its keys, and the audio pipeline
+//! keeps running for it (our microphone just goes nowhere), so /unhold or /swap picks
+//! it straight back up; only one call is ever live. A direct call that rings while
+//! we're in one waits for /swap instead of being turned away, as long as nothing is
+//! on hold already.
+
+use crate::client::OutgoingMessage;
+use crate::protocol::PlainMessage;
+
+use super::call_keys::CallKeys;
+use super::sounds::SoundEvent;
+use super::state::{ChatState, Effect};
+use super::types::{CallState, CallType, Tab};
+
+/// A call we put on hold, with the keys it was in
+#[derive(Debug)]
+pub(crate) struct HeldCall {
+    pub call: CallState,
+    pub keys: Option<CallKeys>,
+}
+
+impl ChatState {
+    /// Who a call is with: the peer, or the group
+    pub(crate) fn call_label(&self, call: &CallState) -> String {
+        match call.call_type {
+            CallType::Direct(ref peer_id) => self.get_peer_display_name(peer_id),
+            CallType::Group { ref group_id } => self.group_name(group_id),
+        }
+    }
+
+    /// Open the audio devices for the call we just entered, unless they're open for
+    /// a call on hold already
+    pub(crate) fn start_audio(&self, fx: &mut Vec<Effect>) {
+        if self.held_call.is_none() {
+            fx.push(Effect::StartAudio);
+        }
+    }
+
+    /// Handle /hold
+    pub(crate) fn handle_hold_command(&mut self, fx: &mut Vec<Effect>) {
+        if self.held_call.is_some() {
+            self.status = "A call is already on hold — /swap to switch to it".to_string();
+            return;
+        }
+        match self.hold_active_call(fx) {
+            Some(label) => self.status = format!("⏸ {} on hold — /unhold to resume", label),
+            None => self.status = "Not in a call".to_string(),
+        }
+        self.pace_for_call();
+    }
+
+    /// Handle /unhold
+    pub(crate) fn handle_unhold_command(&mut self, fx: &mut Vec<Effect>) {
+        if self.active_call.is_some() && self.held_call.is_some() {
+            self.status = "Already in a call — /swap to switch to the one on hold".to_string();
+            return;
+        }
+        let Some(held) = self.held_call.take() else {
+            self.status = "No call on hold".to_string();
+            return;
+        };
+        let label = self.resume_call(held, fx);
+        self.status = format!("▶ Back in the call with {}", label);
+        self.pace_for_call();
+    }
+
+    /// Handle /swap: answer the waiting call, putting ours on hold, or trade places
+    /// with the call on hold
+    pub(crate) fn handle_swap_command(&mut self, fx: &mut Vec<Effect>) {
+        if let Some(keys) = self.call_waiting.take() {
+            self.hold_active_call(fx);
+            let caller = keys.initiator.clone();
+            self.call_keys = Some(keys);
+            self.answer_direct_call(caller, fx);
+            return;
+        }
+        let Some(held) = self.held_call.take() else {
+            self.status = "No call on hold or waiting".to_string();
+            return;
+        };
+        let on_hold = self.hold_active_call(fx);
+        let label = self.resume_call(held, fx);
+        self.status = match on_hold {
+            Some(other) => format!("⇄ Back with {} — {} on hold", label, other),
+            None => format!("▶ Back in the call with {}", label),
+        };
+        self.pace_for_call();
+    }
+
+    /// Put the live call on hold; who it's with, if there was one
+    fn hold_active_call(&mut self, fx: &mut Vec<Effect>) -> Option<String> {
+        let call = self.active_call.take()?;
+        self.send_hold(&call, true, fx);
+        let label = self.call_label(&call);
+        self.held_call = Some(HeldCall { call, keys: self.call_keys.take() });
+        Some(label)
+    }
+
+    /// Make `held` the live call again; who it's with
+    fn resume_call(&mut self, held: HeldCall, fx: &mut Vec<Effect>) -> String {
+        self.send_hold(&held.call, false, fx);
+        let label = self.call_label(&held.call);
+        self.active_call = Some(held.call);
+        self.call_keys = held.keys;
+        label
+    }
+
+    /// Tell the others in `call` we put it on hold, or took it off
+    fn send_hold(&self, call: &CallState, on_hold: bool, fx: &mut Vec<Effect>) {
+        let msg = PlainMessage::call_hold(self.own_id.clone(), on_hold);
+        match call.call_type {
+            CallType::Direct(ref peer_id) => {
+                fx.push(Effect::Send(OutgoingMessage::Direct { target_id: peer_id.clone(), message: msg }));
+            }
+            CallType::Group { ref group_id } => {
+                let member_ids = self.groups.get(group_id).map(|g| g.members.clone()).unwrap_or_default();
+                fx.push(Effect::Send(OutgoingMessage::Group {
+                    group_id: group_id.clone(),
+                    member_ids,
+                    message: PlainMessage { group_id: Some(group_id.clone()), ..msg },
+                }));
+            }
+        }
+    }
+
+    /// A direct call came in while we're in one: let it wait, if there's room for it.
+    /// False to turn it away as busy.
+    pub(crate) fn offer_call_waiting(&mut self, msg: &PlainMessage) -> bool {
+        if msg.group_id.is_some() || self.held_call.is_some() || self.call_waiting.is_some() {
+            return false;
+        }
+        let in_call_with_them = self.active_call.as_ref()
+            .is_some_and(|c| matches!(c.call_type, CallType::Direct(ref p) if *p == msg.sender));
+        if in_call_with_them {
+            return false;
+        }
+        let Some(keys) = CallKeys::offered(msg) else {
+            return false;
+        };
+        self.call_waiting = Some(keys);
+        self.chime(SoundEvent::Call);
+
+        let peer_name = self.get_peer_display_name(&msg.sender);
+        let text = format!("📞 Call waiting from {} — /swap to hold the current call and answer, /reject-call to decline", peer_name);
+        self.status = text.clone();
+        let dm_tab = Tab::DirectMessage(msg.sender.clone());
+        self.ensure_tab(&dm_tab);
+        self.push_message(dm_tab, PlainMessage::system(msg.sender.clone(), text));
+        true
+    }
+
+    /// /reject-call with a call waiting: turn it down. False if none is waiting.
+    pub(crate) fn reject_waiting_call(&mut self, fx: &mut Vec<Effect>) -> bool {
+        let Some(keys) = self.call_waiting.take() else {
+            return false;
+        };
+        fx.push(Effect::Send(OutgoingMessage::Direct {
+            target_id: keys.initiator.clone(),
+            message: PlainMessage::call_accept(self.own_id.clone(), false),
+        }));
+        let peer_name = self.get_peer_display_name(&keys.initiator);
+        self.status = format!("Declined the waiting call from {}", peer_name);
+        self.add_system_message(&Tab::DirectMessage(keys.initiator), format!("Declined the waiting call from {}", peer_name));
+        true
+    }
+
+    /// The peer of our direct call, or a member of our group call, put it on hold or
+    /// came back
+    pub(crate) fn handle_call_hold(&mut self, msg: &PlainMessage, on_hold: bool) {
+        let peer_name = self.get_peer_display_name(&msg.sender);
+        let Some(ref mut call) = self.active_call else {
+            return;
+        };
+        match call.call_type {
+            CallType::Direct(ref peer_id) if *peer_id == msg.sender && msg.group_id.is_none() => {
+                call.held_by_peer = on_hold;
+                self.status = if on_hold {
+                    format!("⏸ {} put the call on hold", peer_name)
+                } else {
+                    format!("▶ {} is back on the call", peer_name)
+                };
+            }
+            CallType::Group { ref group_id } if msg.group_id.as_deref() == Some(group_id) => {
+                if let Some(p) = call.participants.get_mut(&msg.sender) {
+                    p.set_on_hold(on_hold);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `msg` is signaling for the call on hold
+    pub(crate) fn about_held_call(&self, msg: &PlainMessage) -> bool {
+        let Some(ref held) = self.held_call else {
+            return false;
+        };
+        match held.call.call_type {
+            CallType::Direct(ref peer_id) => msg.group_id.is_none() && msg.sender == *peer_id,
+            CallType::Group { ref group_id } => msg.group_id.as_deref() == Some(group_id),
+        }
+    }
+
+    /// Run `f` with the call on hold standing in for the live one, so what handles
+    /// the live call handles it too. The live call sits in the hold slot meanwhile
+    /// (which keeps a hangup from stopping the audio it still needs).
+    pub(crate) fn on_held_call(&mut self, f: impl FnOnce(&mut Self)) {
+        let Some(held) = self.held_call.take() else {
+            return;
+        };
+        let live_keys = self.call_keys.take();
+        self.held_call = self.active_call.take().map(|call| HeldCall { call, keys: None });
+        self.active_call = Some(held.call);
+        self.call_keys = held.keys;
+
+        f(self);
+
+        let live = self.held_call.take();
+        self.held_call = self.active_call.take().map(|call| HeldCall { call, keys: self.call_keys.take() });
+        self.active_call = live.map(|l| l.call);
+        self.call_keys = live_keys;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::CallSalt;
+
+    #[test]
+    fn test_call_waiting_swaps_and_the_held_call_can_end() {
+        let (alice, bob) = ("a".repeat(32), "b".repeat(32));
+        let mut state = ChatState::new("c".repeat(32), None, vec![0; 32]);
+        state.ingest_message(PlainMessage::call_request(alice.clone(), CallSalt::generate("call1".to_string())));
+        state.handle_command("/accept-call");
+
+        // Bob rings mid-call: he waits rather than hearing we're busy
+        let fx = state.ingest_message(PlainMessage::call_request(bob.clone(), CallSalt::generate("call2".to_string())));
+        assert!(!fx.iter().any(|e| matches!(e, Effect::Send(_))));
+        assert!(state.status.contains("Call waiting"), "{}", state.status);
+        assert!(state.is_current_call("call1"));
+
+        // /swap: Alice is told she's on hold, Bob is answered, and the audio carries on
+        let fx = state.handle_command("/swap");
+        assert!(matches!(&fx[0], Effect::Send(OutgoingMessage::Direct { target_id, message }) if *target_id == alice && message.call_hold == Some(true)));
+        assert!(matches!(&fx[1], Effect::Send(OutgoingMessage::Direct { target_id, message }) if *target_id == bob && message.call_accept == Some(true)));
+        assert!(!fx.iter().any(|e| matches!(e, Effect::StartAudio)));
+        assert!(state.is_current_call("call2"));
+        assert!(state.held_call.as_ref().is_some_and(|h| h.keys.as_ref().is_some_and(|k| k.call_id == "call1")));
+
+        // Alice puts us on hold too, then hangs up: only her call and key go
+        state.ingest_message(PlainMessage::call_hold(alice.clone(), true));
+        assert!(state.active_call.as_ref().is_some_and(|c| !c.held_by_peer));
+        let fx = state.ingest_message(PlainMessage::call_hangup(alice.clone()));
+        assert!(matches!(&fx[..], [Effect::Send(OutgoingMessage::CallKey { peer_id, salts: None })] if *peer_id == alice));
+        assert!(state.held_call.is_none());
+        assert!(state.is_current_call("call2"));
+
+        // Bob holds us; with nothing left on hold, /hold and /unhold go both ways
+        state.ingest_message(PlainMessage::call_hold(bob.clone(), true));
+        assert!(state.active_call.as_ref().is_some_and(|c| c.held_by_peer));
+        state.handle_command("/hold");
+        assert!(state.active_call.is_none() && !state.is_current_call("call2"));
+        let fx = state.handle_command("/unhold");
+        assert!(matches!(&fx[..], [Effect::Send(OutgoingMessage::Direct { message, .. })] if message.call_hold == Some(false)));
+        let fx = state.handle_command("/hangup");
+        assert!(fx.iter().any(|e| matches!(e, Effect::StopAudio)));
+    }
+}
@@ -0,0 +1,191 @@
+//! Keyboard input: editing the input line, key bindings, and the command
+//! autocomplete popup.
+
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::client::OutgoingSender;
+
+use super::clipboard;
+use super::types::{AutocompleteState, KeyAction, KeyBinding, KeyContext};
+use super::ChatUI;
+
+impl ChatUI {
+    /// Handle a key press. Returns true when the user asked to quit.
+    pub(super) async fn handle_key(&mut self, key: KeyEvent, msg_tx: &mut OutgoingSender) -> bool {
+        let effects = self.state.note_activity();
+        self.apply_effects(effects, msg_tx);
+
+        // The key and QR overlays close on any key
+        if self.show_keys || self.qr.is_some() {
+            self.show_keys = false;
+            self.qr = None;
+            return false;
+        }
+
+        // Handle autocomplete navigation first; other keys fall through and update it
+        if self.autocomplete.is_some() {
+            if let Some(action) = KeyBinding::lookup(&[KeyContext::Autocomplete], &key) {
+                if let Some(ref mut ac) = self.autocomplete {
+                    let last = ac.filtered.len().saturating_sub(1);
+                    match action {
+                        KeyAction::CompletePrev => ac.selected = if ac.selected > 0 { ac.selected - 1 } else { last },
+                        KeyAction::CompleteNext => ac.selected = if ac.selected < last { ac.selected + 1 } else { 0 },
+                        KeyAction::Complete => {
+                            if let Some(&cmd_idx) = ac.filtered.get(ac.selected) {
+                                self.input = format!("/{} ", ac.commands[cmd_idx].name).chars().collect();
+                                self.cursor = self.input.len();
+                            }
+                            self.autocomplete = None;
+                        }
+                        _ => self.autocomplete = None,
+                    }
+                }
+                return false;
+            }
+        }
+
+        let Some(action) = KeyBinding::lookup(&[KeyContext::Global, KeyContext::Input], &key) else {
+            if let KeyCode::Char(c) = key.code {
+                self.input.insert(self.cursor, c);
+                self.cursor += 1;
+                self.update_autocomplete();
+                // Send typing indicator for non-command input
+                if !self.input.starts_with(&['/']) {
+                    let effects = self.state.typing_indicator();
+                    self.apply_effects(effects, msg_tx);
+                }
+            }
+            return false;
+        };
+        match action {
+            KeyAction::Quit => return true,
+            KeyAction::ShowKeys => self.show_keys = true,
+            KeyAction::NextTab => self.state.next_tab(),
+            KeyAction::PrevTab => self.state.prev_tab(),
+            KeyAction::ScrollUp => self.state.scroll_up(1),
+            KeyAction::ScrollDown => self.state.scroll_down(1),
+            KeyAction::PageUp => self.state.scroll_up(10),
+            KeyAction::PageDown => self.state.scroll_down(10),
+            // Paste: an image is offered as a file, text goes into the input
+            KeyAction::Paste => {
+                match clipboard::read() {
+                    Ok(clipboard::Clipboard::Image(png)) => {
+                        let mut effects = Vec::new();
+                        self.state.share_pasted_image(png, &mut effects);
+                        self.apply_effects(effects, msg_tx);
+                    }
+                    Ok(clipboard::Clipboard::Text(text)) => {
+                        for c in text.chars().filter(|c| *c == '\n' || !c.is_control()) {
+                            self.input.insert(self.cursor, c);
+                            self.cursor += 1;
+                        }
+                        self.update_autocomplete();
+                    }
+                    Err(e) => self.state.status = format!("📋 {:#}", e),
+                }
+            }
+            KeyAction::DeleteBack => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.input.remove(self.cursor);
+                    self.update_autocomplete();
+                }
+            }
+            KeyAction::DeleteForward => {
+                if self.cursor < self.input.len() {
+                    self.input.remove(self.cursor);
+                    self.update_autocomplete();
+                }
+            }
+            KeyAction::CursorLeft => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                }
+            }
+            KeyAction::CursorRight => {
+                if self.cursor < self.input.len() {
+                    self.cursor += 1;
+                }
+            }
+            KeyAction::LineStart => {
+                self.cursor = 0;
+            }
+            KeyAction::LineEnd => {
+                self.cursor = self.input.len();
+            }
+            KeyAction::Newline => {
+                self.input.insert(self.cursor, '\n');
+                self.cursor += 1;
+            }
+            KeyAction::Send => {
+                if !self.input.is_empty() {
+                    let text: String = self.input.iter().collect();
+                    let effects = self.state.handle_command(&text);
+                    self.apply_effects(effects, msg_tx);
+                    self.input.clear();
+                    if let Some(held) = self.state.held_input.take() {
+                        self.input = held.chars().collect();
+                    }
+                    self.cursor = self.input.len();
+                    self.autocomplete = None;
+                    self.state.last_typing_sent = None;
+                }
+            }
+            KeyAction::CompletePrev | KeyAction::CompleteNext | KeyAction::Complete | KeyAction::CompleteDismiss => {}
+        }
+        false
+    }
+
+    /// Update autocomplete state based on current input
+    fn update_autocomplete(&mut self) {
+        let input_str: String = self.input.iter().collect();
+        if input_str.starts_with('/') && !input_str.contains(' ') {
+            let filter = input_str[1..].to_lowercase();
+            let commands = self.state.get_all_commands();
+            let filtered: Vec<usize> = commands.iter().enumerate()
+                .filter(|(_, cmd)| cmd.name.starts_with(&filter))
+                .map(|(i, _)| i)
+                .collect();
+
+            if !filtered.is_empty() {
+                let selected = if let Some(ref ac) = self.autocomplete {
+                    ac.selected.min(filtered.len().saturating_sub(1))
+                } else {
+                    0
+                };
+                self.autocomplete = Some(AutocompleteState {
+                    commands,
+                    filtered,
+                    selected,
+                });
+            } else {
+                self.autocomplete = None;
+            }
+        } else {
+            self.autocomplete = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(ui: &mut ChatUI, text: &str) -> Option<Vec<String>> {
+        ui.input = text.chars().collect();
+        ui.update_autocomplete();
+        let ac = ui.autocomplete.as_ref()?;
+        Some(ac.filtered.iter().map(|&i| ac.commands[i].name.clone()).collect())
+    }
+
+    #[test]
+    fn test_autocomplete_follows_input() {
+        let mut ui = ChatUI::new("me".repeat(16), None, vec![0; 32]);
+        let names = typed(&mut ui, "/gr").unwrap();
+        assert!(names.iter().all(|n| n.starts_with("gr")) && names.iter().any(|n| n == "group"), "{:?}", names);
+        assert_eq!(typed(&mut ui, "/zzz"), None);
+        // Once arguments start, the popup goes away
+        assert_eq!(typed(&mut ui, "/group "), None);
+        assert_eq!(typed(&mut ui, "hello"), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! `/invite-link`: our relay and identity key as one `wsp://` link, shown as text and
+//! as a QR code to scan off the screen. `wsp chat <link>` on the other end connects to
+//! that relay and looks for us; if we're not online yet it keeps waiting, and the DM
+//! opens as soon as a peer with the link's key turns up.
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+
+use crate::client::InviteLink;
+use crate::protocol::short_id;
+
+use super::clipboard;
+use super::state::{ChatState, Effect};
+use super::types::Tab;
+
+/// `text` as a QR code, two modules to a character cell. Light and dark are swapped
+/// so it reads as dark-on-light on a light-on-dark terminal.
+pub(crate) fn qr_lines(text: &str) -> Vec<String> {
+    match QrCode::new(text.as_bytes()) {
+        Ok(code) => code
+            .render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .quiet_zone(true)
+            .build()
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        Err(e) => vec![format!("(no QR code: {})", e)],
+    }
+}
+
+impl ChatState {
+    /// Our invite link on the relay we're connected to
+    fn own_invite_link(&self) -> Result<InviteLink, String> {
+        let relay_url = self.relay_url.as_deref().ok_or("No relay to link to")?;
+        InviteLink::new(relay_url, &self.own_public_key)
+    }
+
+    /// Handle /invite-link [copy]: show our link and its QR code, or copy the link
+    pub(crate) fn handle_invite_link_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        let link = match self.own_invite_link() {
+            Ok(link) => link,
+            Err(e) => {
+                self.status = format!("❌ {}", e);
+                return;
+            }
+        };
+        match args.first().copied() {
+            Some("copy") => {
+                self.status = match clipboard::copy_text(&link.to_string()) {
+                    Ok(()) => "📋 Invite link copied to the clipboard".to_string(),
+                    Err(e) => format!("📋 {:#}", e),
+                };
+            }
+            Some(other) => self.status = format!("Unknown /invite-link option {:?} — usage: /invite-link [copy]", other),
+            None => {
+                let text = format!(
+                    "🔗 Your invite link\n  {}\n\
+                     Whoever opens it with `wsp chat <link>` connects to this relay and gets a DM with you, \
+                     once you're both online. It holds your identity key, not a secret: verify them as usual.",
+                    link
+                );
+                let tab = self.tabs[self.active_tab].clone();
+                self.add_system_message(&tab, text);
+                fx.push(Effect::ShowQr(link.to_string()));
+            }
+        }
+    }
+
+    /// Wait for whoever made `link` (`wsp chat <link>`) and open a DM when they show up
+    pub(crate) fn await_invite(&mut self, link: InviteLink) {
+        if link.public_key()[..] == self.own_public_key[..] {
+            self.status = "That's your own invite link — hand it to someone else".to_string();
+            return;
+        }
+        self.awaiting_invite = Some(link);
+    }
+
+    /// Ask the relay for the link's owner (on connecting, and again after a reconnect)
+    pub(crate) fn seek_invite(&mut self, fx: &mut Vec<Effect>) {
+        let Some(link) = self.awaiting_invite.clone() else {
+            return;
+        };
+        let found = self.peers.iter().find(|(_, p)| p.public_key[..] == link.public_key()[..]).map(|(id, _)| id.clone());
+        if let Some(id) = found {
+            self.invite_peer_up(&id, fx);
+            return;
+        }
+        self.status = format!("🔎 Looking for {} from the invite link...", short_id(&link.session_id()));
+        self.discover_quietly(&link.session_id(), fx);
+    }
+
+    /// The relay says `session_id` isn't connected: if it's the link's owner, keep waiting.
+    /// True if it was.
+    pub(crate) fn invite_not_found(&mut self, session_id: &str) -> bool {
+        let Some(ref link) = self.awaiting_invite else {
+            return false;
+        };
+        if link.session_id() != session_id {
+            return false;
+        }
+        self.status = format!("⏳ {} isn't online yet — the DM opens when they connect", short_id(session_id));
+        true
+    }
+
+    /// A new peer: if it has the invite link's key, open the DM with them
+    pub(crate) fn invite_peer_up(&mut self, peer_id: &str, fx: &mut Vec<Effect>) {
+        let matches = self.awaiting_invite.as_ref().is_some_and(|link| {
+            self.peers.get(peer_id).is_some_and(|p| p.public_key[..] == link.public_key()[..])
+        });
+        if !matches {
+            return;
+        }
+        self.awaiting_invite = None;
+        self.open_dm_tab(peer_id, Some(fx));
+        let note = format!(
+            "🔗 {} has the identity key from the invite link. It came with the link, so verify them as usual.",
+            self.get_peer_display_name(peer_id)
+        );
+        self.add_system_message(&Tab::DirectMessage(peer_id.to_string()), note);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{OutgoingMessage, PeerDisplay, PeerUpdate};
+    use crate::protocol::Message;
+
+    #[test]
+    fn test_invite_link_shown_and_awaited() {
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.handle_command("/invite-link");
+        assert_eq!(state.status, "❌ No relay to link to");
+        state.relay_url = Some("ws://relay.example:8899".to_string());
+        let fx = state.handle_command("/invite-link");
+        let link = InviteLink::new("ws://relay.example:8899", &[0; 32]).unwrap().to_string();
+        assert!(matches!(&fx[..], [Effect::ShowQr(text)] if *text == link));
+        assert!(state.messages[&Tab::Global].last().unwrap().content.contains(&link));
+        assert!(qr_lines(&link).len() > 10);
+
+        // The other end: nobody with the key yet, so it waits
+        let friend: InviteLink = link.parse().unwrap();
+        let mut state = ChatState::new("bb".repeat(16), None, vec![1; 32]);
+        state.await_invite(friend.clone());
+        let mut fx = Vec::new();
+        state.seek_invite(&mut fx);
+        assert!(matches!(
+            &fx[..],
+            [Effect::Send(OutgoingMessage::Signal(Message::Discover { target_session, .. }))] if *target_session == friend.session_id()
+        ));
+        assert!(state.invite_not_found(&friend.session_id()));
+        assert!(state.status.contains("isn't online yet"), "{}", state.status);
+
+        // A stranger doesn't resolve it; the key's owner, under any session id, does
+        let stranger = PeerDisplay { nickname: None, public_key: vec![2; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added("cc".repeat(16), stranger)]);
+        assert!(state.awaiting_invite.is_some());
+        let owner = PeerDisplay { nickname: Some("me".to_string()), public_key: vec![0; 32] };
+        let fx = state.apply_peer_updates(vec![PeerUpdate::Added("dd".repeat(16), owner)]);
+        assert!(state.awaiting_invite.is_none());
+        assert_eq!(state.tabs[state.active_tab], Tab::DirectMessage("dd".repeat(16)));
+        assert!(fx.iter().any(|e| matches!(e, Effect::Send(OutgoingMessage::Direct { message, .. }) if message.dm_request)));
+    }
+}
@@ -0,0 +1,238 @@
+//! Group invites wait for /join or /decline instead of joining on arrival, so a peer
+//! can't fill the tab bar with groups or put us in relay rooms we never chose.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::client::OutgoingMessage;
+use crate::protocol::{short_id, GroupInvite, PlainMessage};
+
+use super::state::{ChatState, Effect};
+use super::types::{GroupInfo, PendingInvite, Tab};
+
+/// Most invites kept waiting; a new one pushes out the oldest
+const MAX_PENDING_INVITES: usize = 10;
+/// How long an invite waits for an answer
+const INVITE_TIMEOUT: Duration = Duration::from_secs(600);
+
+impl ChatState {
+    /// An invite arrived: join right away if it's from a peer we've verified and the
+    /// config allows that, otherwise hold it for /join
+    pub(crate) fn handle_group_invite(&mut self, msg: PlainMessage, invite: GroupInvite, fx: &mut Vec<Effect>) {
+        if self.groups.contains_key(&invite.group_id) {
+            return;
+        }
+        let sender_name = self.get_peer_display_name(&msg.sender);
+        if self.auto_join_verified && self.verification_of(&msg.sender).is_some() {
+            let group_name = invite.group_name.clone();
+            self.join_invited_group(&msg.sender, invite, fx);
+            self.status = format!("Joined group: {} (invited by {})", group_name, sender_name);
+            return;
+        }
+
+        let notice = format!("{} invited you to \"{}\" — /join or /decline", sender_name, invite.group_name);
+        self.pending_invites.retain(|p| p.invite.group_id != invite.group_id);
+        if self.pending_invites.len() >= MAX_PENDING_INVITES {
+            self.pending_invites.remove(0);
+        }
+        self.pending_invites.push(PendingInvite { invite, from_peer: msg.sender.clone(), received: Instant::now() });
+
+        let sys_msg = PlainMessage::system(msg.sender.clone(), notice.clone());
+        self.push_message(Tab::Global, sys_msg);
+        self.flash_status(notice);
+    }
+
+    /// Become a member of an invited group: its tab, the relay room, and a session with
+    /// each member the invite names
+    fn join_invited_group(&mut self, inviter: &str, invite: GroupInvite, fx: &mut Vec<Effect>) {
+        let sender_name = self.get_peer_display_name(inviter);
+        let group_id = invite.group_id.clone();
+        let mut members = vec![inviter.to_string()];
+        let mut keys = HashMap::new();
+        let mut clashes = Vec::new();
+        for hint in invite.members {
+            let id = hint.session_id.clone();
+            if id == self.own_id || members.contains(&id) {
+                continue;
+            }
+            match self.peers.get(&id) {
+                Some(peer) if peer.public_key != hint.public_key => clashes.push(self.get_peer_display_name(&id)),
+                Some(_) => {}
+                None => {
+                    keys.insert(id.clone(), hint.public_key.clone());
+                    self.discover_quietly(&id, fx);
+                }
+            }
+            let known = self.peers.get(&id).and_then(|p| p.nickname.as_ref()).is_some();
+            if !known && hint.nickname != short_id(&id) {
+                self.name_hints.insert(id.clone(), hint);
+            }
+            members.push(id);
+        }
+        self.groups.insert(group_id.clone(), GroupInfo {
+            name: invite.group_name.clone(),
+            members,
+            join_token: invite.join_token.clone(),
+        });
+        self.member_keys.insert(group_id.clone(), keys);
+
+        let group_tab = Tab::Group(group_id.clone());
+        self.ensure_tab(&group_tab);
+
+        fx.push(Effect::Send(OutgoingMessage::JoinRoom {
+            group_id: group_id.clone(),
+            join_token: invite.join_token,
+        }));
+
+        // Use sender's ID for system message attribution (not our own)
+        let sys_msg = PlainMessage::system(
+            inviter.to_string(),
+            format!("{} invited you to \"{}\"", sender_name, invite.group_name),
+        );
+        self.push_message(group_tab, sys_msg);
+        if !clashes.is_empty() {
+            self.membership_inconsistent(
+                &group_id,
+                &format!("{}'s invite has a different identity key for {}", sender_name, clashes.join(", ")),
+            );
+        }
+        self.announce_roster(&group_id, fx);
+    }
+
+    /// The invite `arg` names: its number in the list or the group's name. With no
+    /// argument, the only pending invite.
+    fn select_invite(&self, arg: Option<&str>) -> Result<usize, String> {
+        match arg {
+            _ if self.pending_invites.is_empty() => Err("No pending group invites".to_string()),
+            None if self.pending_invites.len() == 1 => Ok(0),
+            None => Err(format!("{} invites pending — name one: {}", self.pending_invites.len(), self.list_invites())),
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(n) if (1..=self.pending_invites.len()).contains(&n) => Ok(n - 1),
+                Ok(_) => Err(format!("No invite #{} — pending: {}", arg, self.list_invites())),
+                Err(_) => self.pending_invites.iter()
+                    .position(|p| p.invite.group_name.eq_ignore_ascii_case(arg))
+                    .ok_or_else(|| format!("No invite to \"{}\" — pending: {}", arg, self.list_invites())),
+            },
+        }
+    }
+
+    /// "1. offtopic (alice), 2. ..."
+    fn list_invites(&self) -> String {
+        self.pending_invites.iter().enumerate()
+            .map(|(i, p)| format!("{}. {} ({})", i + 1, p.invite.group_name, self.get_peer_display_name(&p.from_peer)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Handle /join [n|group name]
+    pub(crate) fn handle_join_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        let name = (!args.is_empty()).then(|| args.join(" "));
+        let idx = match self.select_invite(name.as_deref()) {
+            Ok(idx) => idx,
+            Err(e) => {
+                self.status = e;
+                return;
+            }
+        };
+        let pending = self.pending_invites.remove(idx);
+        let group_tab = Tab::Group(pending.invite.group_id.clone());
+        let group_name = pending.invite.group_name.clone();
+        let sender_name = self.get_peer_display_name(&pending.from_peer);
+        self.join_invited_group(&pending.from_peer, pending.invite, fx);
+        if let Some(idx) = self.tabs.iter().position(|t| *t == group_tab) {
+            self.set_active_tab(idx);
+        }
+        self.status = format!("Joined group: {} (invited by {})", group_name, sender_name);
+    }
+
+    /// Handle /decline [n|group name] — the inviter is told, so they drop us from the group
+    pub(crate) fn handle_decline_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        let name = (!args.is_empty()).then(|| args.join(" "));
+        let idx = match self.select_invite(name.as_deref()) {
+            Ok(idx) => idx,
+            Err(e) => {
+                self.status = e;
+                return;
+            }
+        };
+        let pending = self.pending_invites.remove(idx);
+        fx.push(Effect::Send(OutgoingMessage::Direct {
+            target_id: pending.from_peer.clone(),
+            message: PlainMessage::invite_declined(self.own_id.clone(), pending.invite.group_id),
+        }));
+        self.status = format!("Declined invite to \"{}\"", pending.invite.group_name);
+    }
+
+    /// A peer we invited said no: they're not a member after all
+    pub(crate) fn handle_invite_declined(&mut self, sender: &str, group_id: &str) {
+        let Some(group) = self.groups.get_mut(group_id) else {
+            return;
+        };
+        let before = group.members.len();
+        group.members.retain(|id| id != sender);
+        if group.members.len() == before {
+            return;
+        }
+        if let Some(keys) = self.member_keys.get_mut(group_id) {
+            keys.remove(sender);
+        }
+        let text = format!("{} declined the invite", self.get_peer_display_name(sender));
+        self.add_system_message(&Tab::Group(group_id.to_string()), text);
+    }
+
+    /// Drop invites nobody answered in time. Returns true if any were dropped.
+    pub(crate) fn expire_invites(&mut self) -> bool {
+        let before = self.pending_invites.len();
+        self.pending_invites.retain(|p| p.received.elapsed() < INVITE_TIMEOUT);
+        self.pending_invites.len() != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invite(from: &str, group_id: &str, name: &str) -> PlainMessage {
+        PlainMessage::group_invite_msg(from.to_string(), GroupInvite {
+            group_id: group_id.to_string(),
+            group_name: name.to_string(),
+            join_token: None,
+            members: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_invites_wait_for_an_answer() {
+        let alice = "alice".repeat(6);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        for i in 0..MAX_PENDING_INVITES + 2 {
+            assert!(state.ingest_message(invite(&alice, &format!("g{}", i), &format!("spam{}", i))).is_empty());
+        }
+        assert!(state.groups.is_empty());
+        assert_eq!(state.pending_invites.len(), MAX_PENDING_INVITES);
+        assert_eq!(state.pending_invites[0].invite.group_name, "spam2");
+        assert!(!state.tabs.iter().any(|t| matches!(t, Tab::Group(_))));
+
+        // More than one pending: /join needs to be told which
+        assert!(state.handle_command("/join").is_empty());
+        let fx = state.handle_command("/join SPAM5");
+        assert!(matches!(&fx[..], [Effect::Send(OutgoingMessage::JoinRoom { group_id, .. })] if group_id == "g5"));
+        assert_eq!(state.tabs[state.active_tab], Tab::Group("g5".to_string()));
+
+        let fx = state.handle_command("/decline 1");
+        assert!(matches!(
+            &fx[..],
+            [Effect::Send(OutgoingMessage::Direct { target_id, message })] if *target_id == alice && message.invite_declined.as_deref() == Some("g2")
+        ));
+        assert_eq!(state.pending_invites.len(), MAX_PENDING_INVITES - 2);
+    }
+
+    #[test]
+    fn test_declined_invitee_leaves_member_list() {
+        let bob = "bob".repeat(10);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.groups.insert("g1".to_string(), GroupInfo { name: "g".to_string(), members: vec![bob.clone()], join_token: None });
+        state.ingest_message(PlainMessage::invite_declined(bob.clone(), "g1".to_string()));
+        assert!(state.groups["g1"].members.is_empty());
+    }
+}
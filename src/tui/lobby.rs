@@ -0,0 +1,112 @@
+//! Global is the relay's lobby, not a group of friends: on a shared relay it reaches
+//! every stranger connected. While anyone there is unverified the Global tab says so
+//! under the header, and the session's first Global send is held back (put back in
+//! the input box) until it's sent again. Lobby-less mode (`--no-global`, or
+//! `"disable_global": true` in config.json) keeps the first tab only for notices —
+//! joins, leaves and command output — and the client refuses Global chat both ways.
+
+use super::state::ChatState;
+use super::types::Tab;
+
+impl ChatState {
+    /// Peers on the relay we haven't verified
+    pub(crate) fn strangers(&self) -> usize {
+        self.peers.keys().filter(|id| self.verification_of(id).is_none()).count()
+    }
+
+    /// The note beside the Global tab's banner while strangers can read it
+    pub(crate) fn global_exposure(&self) -> Option<String> {
+        if self.no_global || self.strangers() == 0 {
+            return None;
+        }
+        let peers = self.peers.len();
+        Some(format!("👁 Global is visible to all {} peer{} on this relay", peers, if peers == 1 { "" } else { "s" }))
+    }
+
+    /// Why nothing can be sent in `tab` in lobby-less mode, if that's the case
+    pub(crate) fn global_refused(&self, tab: &Tab) -> Option<String> {
+        (self.no_global && *tab == Tab::Global)
+            .then(|| "Global is off (--no-global) — open a DM or a group to chat".to_string())
+    }
+
+    /// Whether to hold back a send in `tab`: the session's first one to Global while
+    /// strangers are there. Sending it again goes through.
+    pub(crate) fn hold_global_send(&mut self, tab: &Tab) -> bool {
+        let strangers = self.strangers();
+        if *tab != Tab::Global || self.no_global || self.global_confirmed || strangers == 0 {
+            return false;
+        }
+        self.global_confirmed = true;
+        self.status = format!(
+            "⚠️ Not sent yet: Global reaches all {} peers on this relay, {} of them unverified — send it again to post it there",
+            self.peers.len(),
+            strangers
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{OutgoingMessage, PeerDisplay, PeerUpdate};
+    use crate::tui::state::Effect;
+
+    fn chat_sent(fx: &[Effect]) -> bool {
+        fx.iter().any(|e| matches!(e, Effect::Send(OutgoingMessage::Global(m)) if !m.system))
+    }
+
+    #[test]
+    fn test_first_global_send_waits_while_strangers_listen() {
+        let (alice, bob) = ("aa".repeat(16), "bb".repeat(16));
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let peer = |name: &str, key: u8| PeerDisplay { nickname: Some(name.to_string()), public_key: vec![key; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), peer("alice", 1))]);
+
+        // Everyone here is verified: no note, nothing held
+        state.handle_command("/verified alice");
+        assert_eq!(state.global_exposure(), None);
+        assert!(chat_sent(&state.handle_command("hi alice")));
+
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.apply_peer_updates(vec![
+            PeerUpdate::Added(alice.clone(), peer("alice", 1)),
+            PeerUpdate::Added(bob, peer("bob", 2)),
+        ]);
+        state.handle_command("/verified alice");
+        assert_eq!(state.global_exposure().as_deref(), Some("👁 Global is visible to all 2 peers on this relay"));
+
+        // Held once, with the text handed back; sending it again goes
+        assert!(!chat_sent(&state.handle_command("/me waves")));
+        assert!(state.status.contains("all 2 peers on this relay, 1 of them unverified"), "{}", state.status);
+        assert_eq!(state.held_input.as_deref(), Some("/me waves"));
+        assert!(state.messages[&Tab::Global].iter().all(|m| m.system));
+        assert!(chat_sent(&state.handle_command("/me waves")));
+        assert!(chat_sent(&state.handle_command("and again")));
+    }
+
+    #[test]
+    fn test_no_global_keeps_the_tab_for_notices() {
+        let dir = tempfile::tempdir().unwrap();
+        let alice = "aa".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.no_global = true;
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+        assert_eq!(state.get_tab_name(&Tab::Global), "notices");
+        assert_eq!(state.global_exposure(), None);
+
+        // Neither chat nor files go out from it
+        assert!(!chat_sent(&state.handle_command("hello?")));
+        assert_eq!(state.status, "Global is off (--no-global) — open a DM or a group to chat");
+        let file = dir.path().join("notes.txt");
+        std::fs::write(&file, b"notes").unwrap();
+        assert!(state.handle_command(&format!("/send {}", file.display())).is_empty());
+        assert!(state.status.starts_with("Global is off"), "{}", state.status);
+        assert!(state.typing_indicator().is_empty());
+
+        // Joins and leaves still land there
+        state.ingest_message(crate::protocol::PlainMessage::system(alice.clone(), "alice has joined".to_string()));
+        assert_eq!(state.messages[&Tab::Global].last().unwrap().content, "alice has joined");
+    }
+}
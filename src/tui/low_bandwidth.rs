@@ -0,0 +1,193 @@
+//! Low-bandwidth mode (`/lowbw on|off`, or `--low-bandwidth` from the start) for a bad
+//! connection: only small payloads go out. Calls are refused both ways, typing
+//! indicators and read receipts aren't sent, accepted files over 1 MB wait until it's
+//! off, and the client backs off longer between reconnects. Each of those paths asks
+//! `TrafficPolicy` rather than checking the mode itself.
+
+use crate::protocol::ChatEvent;
+
+use super::state::{ChatState, Effect};
+use super::types::OutgoingTransfer;
+
+/// Largest file sent while low-bandwidth mode is on; bigger ones wait for /lowbw off
+pub(crate) const LOW_BANDWIDTH_MAX_FILE: u64 = 1024 * 1024;
+
+/// What may go out right now
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum TrafficPolicy {
+    #[default]
+    Normal,
+    /// Text only
+    LowBandwidth,
+}
+
+impl TrafficPolicy {
+    pub(crate) fn allows_calls(self) -> bool {
+        self == Self::Normal
+    }
+
+    pub(crate) fn allows_typing(self) -> bool {
+        self == Self::Normal
+    }
+
+    pub(crate) fn allows_receipts(self) -> bool {
+        self == Self::Normal
+    }
+
+    /// Whether a file of `size` bytes may be streamed now
+    pub(crate) fn allows_file(self, size: u64) -> bool {
+        self == Self::Normal || size <= LOW_BANDWIDTH_MAX_FILE
+    }
+}
+
+/// An accepted file held back by low-bandwidth mode
+#[derive(Debug, Clone)]
+pub(crate) struct DeferredFile {
+    pub file_id: String,
+    pub transfer: OutgoingTransfer,
+    /// Who accepted it
+    pub peer: String,
+}
+
+impl ChatState {
+    /// Handle /lowbw on|off
+    pub(crate) fn handle_lowbw_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        match args.first().copied() {
+            Some("on") if self.traffic == TrafficPolicy::LowBandwidth => {
+                self.status = "Low-bandwidth mode is already on".to_string();
+            }
+            Some("on") => {
+                self.set_low_bandwidth(true, fx);
+                let call = if self.active_call.is_some() { " (this call goes on until /hangup)" } else { "" };
+                self.status = format!(
+                    "📶 Low-bandwidth mode: text only — no calls, typing indicators or read receipts; files over {} wait{}",
+                    Self::format_size(LOW_BANDWIDTH_MAX_FILE),
+                    call
+                );
+            }
+            Some("off") if self.traffic == TrafficPolicy::Normal => {
+                self.status = "Low-bandwidth mode is already off".to_string();
+            }
+            Some("off") => self.set_low_bandwidth(false, fx),
+            _ => {
+                let mode = if self.traffic == TrafficPolicy::Normal { "off" } else { "on" };
+                self.status = format!("Low-bandwidth mode is {} — usage: /lowbw on|off", mode);
+            }
+        }
+    }
+
+    /// Switch low-bandwidth mode; turning it off sends the files it held back
+    pub(crate) fn set_low_bandwidth(&mut self, on: bool, fx: &mut Vec<Effect>) {
+        self.traffic = if on { TrafficPolicy::LowBandwidth } else { TrafficPolicy::Normal };
+        fx.push(Effect::LowBandwidth(on));
+        if on {
+            return;
+        }
+        let deferred = std::mem::take(&mut self.deferred_files);
+        for file in &deferred {
+            let name = self.get_peer_display_name(&file.peer);
+            self.update_offer_row(&file.file_id, ChatEvent::FileAccepted, &format!("{} ✅ accepted, sending", name));
+        }
+        self.status = match deferred.len() {
+            0 => "📶 Low-bandwidth mode is off".to_string(),
+            n => format!("📶 Low-bandwidth mode is off — sending {} held-back file{}", n, if n == 1 { "" } else { "s" }),
+        };
+        for file in deferred {
+            self.stream_accepted(file.file_id, file.transfer, fx);
+        }
+    }
+
+    /// Hold an accepted file back until low-bandwidth mode is off
+    pub(crate) fn defer_file(&mut self, file_id: &str, transfer: OutgoingTransfer, peer: &str) {
+        self.status = format!(
+            "📶 {} ({}) waits for /lowbw off: low-bandwidth mode only sends files up to {}",
+            transfer.offer.filename,
+            Self::format_size(transfer.offer.size),
+            Self::format_size(LOW_BANDWIDTH_MAX_FILE)
+        );
+        self.answer_offer_row(file_id, peer, "✅ accepted, waiting for /lowbw off");
+        self.deferred_files.push(DeferredFile { file_id: file_id.to_string(), transfer, peer: peer.to_string() });
+    }
+
+    /// "📶 LOW BW" for the header while the mode is on
+    pub(crate) fn low_bandwidth_label(&self) -> Option<String> {
+        (self.traffic == TrafficPolicy::LowBandwidth).then(|| {
+            match self.deferred_files.len() {
+                0 => "📶 LOW BW".to_string(),
+                n => format!("📶 LOW BW ({} held)", n),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{OutgoingMessage, PeerDisplay, PeerUpdate};
+    use crate::protocol::{CallSalt, PlainMessage};
+    use crate::tui::types::Tab;
+
+    fn sent(fx: &[Effect]) -> Vec<&PlainMessage> {
+        fx.iter().filter_map(|e| match e {
+            Effect::Send(OutgoingMessage::Direct { message, .. }) => Some(message),
+            _ => None,
+        }).collect()
+    }
+
+    fn offered(fx: &[Effect]) -> String {
+        fx.iter().find_map(|e| match e {
+            Effect::Send(OutgoingMessage::Direct { message, .. }) => message.file_offer.as_ref().map(|o| o.file_id.clone()),
+            _ => None,
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_every_path_consults_the_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let alice = "aa".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), Some("me".to_string()), vec![0; 32]);
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+        state.ingest_message(PlainMessage::direct(alice.clone(), "hi".to_string()));
+        state.set_active_tab(state.tabs.iter().position(|t| *t == Tab::DirectMessage(alice.clone())).unwrap());
+
+        let fx = state.handle_command("/lowbw on");
+        assert!(matches!(fx[..], [Effect::LowBandwidth(true)]));
+        assert_eq!(state.low_bandwidth_label().as_deref(), Some("📶 LOW BW"));
+
+        // Typing and receipts stay home
+        assert!(state.typing_indicator().is_empty());
+        assert!(state.read_receipts().is_empty());
+
+        // Calls: ours refused, theirs turned down with a reason
+        assert!(state.handle_command("/call").is_empty());
+        assert!(state.status.contains("low-bandwidth"), "{}", state.status);
+        let fx = state.ingest_message(PlainMessage::call_request(alice.clone(), CallSalt::generate("c1".to_string())));
+        assert!(matches!(
+            sent(&fx)[..],
+            [reject, notice] if reject.call_accept == Some(false) && notice.content == "me is in low-bandwidth mode"
+        ));
+        assert!(state.pending_call_from.is_none());
+
+        // Small files go, big ones wait for /lowbw off
+        let small = dir.path().join("small.txt");
+        let big = dir.path().join("big.bin");
+        std::fs::write(&small, b"notes").unwrap();
+        std::fs::write(&big, vec![0; LOW_BANDWIDTH_MAX_FILE as usize + 1]).unwrap();
+        let small_id = offered(&state.handle_command(&format!("/send {}", small.display())));
+        let big_id = offered(&state.handle_command(&format!("/send {}", big.display())));
+        let fx = state.ingest_message(PlainMessage::file_response(alice.clone(), small_id, true, true));
+        assert!(matches!(fx[..], [Effect::StreamFile { .. }]));
+        assert!(state.ingest_message(PlainMessage::file_response(alice.clone(), big_id.clone(), true, true)).is_empty());
+        assert!(state.status.contains("waits for /lowbw off"), "{}", state.status);
+        assert_eq!(state.low_bandwidth_label().as_deref(), Some("📶 LOW BW (1 held)"));
+
+        let fx = state.handle_command("/lowbw off");
+        assert!(matches!(
+            &fx[..],
+            [Effect::LowBandwidth(false), Effect::StreamFile { file_id, .. }] if *file_id == big_id
+        ));
+        assert!(state.low_bandwidth_label().is_none());
+        assert!(!state.read_receipts().is_empty());
+    }
+}
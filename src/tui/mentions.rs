@@ -0,0 +1,129 @@
+use crate::protocol::PlainMessage;
+
+use super::fences::folded_away;
+use super::parts::{is_long, COLLAPSED_LINES};
+use super::types::Tab;
+use super::state::ChatState;
+
+/// How many mentions /mentions lists
+const MENTIONS_LISTED: usize = 20;
+/// Shortest `@id-prefix` that counts as a mention
+const MIN_ID_PREFIX: usize = 8;
+
+impl ChatState {
+    /// Whether `content` mentions us: `@nickname` (any case, not followed by more of a
+    /// word) or `@` and at least 8 leading characters of our session id
+    pub(crate) fn mentions_me(&self, content: &str) -> bool {
+        let nickname = self.own_nickname.as_deref().map(str::to_lowercase);
+        let content = content.to_lowercase();
+        content.match_indices('@').any(|(at, _)| {
+            let rest = &content[at + 1..];
+            let by_nick = nickname.as_deref().is_some_and(|nick| {
+                rest.strip_prefix(nick)
+                    .is_some_and(|after| !after.starts_with(|c: char| c.is_alphanumeric() || c == '_' || c == '-'))
+            });
+            let id_prefix: String = rest.chars().take_while(char::is_ascii_hexdigit).collect();
+            by_nick || (id_prefix.len() >= MIN_ID_PREFIX && self.own_id.starts_with(&id_prefix))
+        })
+    }
+
+    /// Flag an incoming chat message that mentions us, once, before it is stored
+    pub(crate) fn mark_mention(&self, msg: &mut PlainMessage) {
+        msg.mentions_me = !msg.system && msg.sender != self.own_id && self.mentions_me(&msg.content);
+    }
+
+    /// Handle /mentions [n] — list our latest mentions across all tabs, or jump to one
+    pub(crate) fn handle_mentions_command(&mut self, args: &[&str]) {
+        match args.first() {
+            None => self.list_mentions(),
+            Some(arg) => match arg.parse::<usize>().ok().and_then(|n| self.mention_list.get(n.wrapping_sub(1)).cloned()) {
+                Some((tab, timestamp, sender)) => self.jump_to_mention(&tab, timestamp, &sender),
+                None => self.status = "No such mention — run /mentions to list them".to_string(),
+            },
+        }
+    }
+
+    fn list_mentions(&mut self) {
+        let mut found: Vec<(&Tab, &PlainMessage)> = self.messages.iter()
+            .flat_map(|(tab, msgs)| msgs.iter().filter(|m| m.mentions_me).map(move |m| (tab, m)))
+            .collect();
+        found.sort_by_key(|(_, m)| m.timestamp);
+        let found = &found[found.len().saturating_sub(MENTIONS_LISTED)..];
+        if found.is_empty() {
+            self.status = "Nobody has mentioned you yet".to_string();
+            return;
+        }
+
+        let mut lines = vec![format!("📣 Your last {} mention(s) — /mentions <n> to jump:", found.len())];
+        for (i, (tab, m)) in found.iter().enumerate() {
+            let time = chrono::DateTime::from_timestamp(m.timestamp, 0)
+                .map(|dt| dt.with_timezone(&chrono::Local).format("%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            let snippet: String = m.content.lines().next().unwrap_or_default().chars().take(60).collect();
+            lines.push(format!(
+                "  {}. [{}] {} — {}",
+                i + 1,
+                time,
+                self.get_tab_name(tab),
+                self.attributed_text(m, &snippet)
+            ));
+        }
+        self.mention_list = found.iter().map(|(tab, m)| ((*tab).clone(), m.timestamp, m.sender.clone())).collect();
+
+        let tab = self.tabs[self.active_tab].clone();
+        self.add_system_message(&tab, lines.join("\n"));
+    }
+
+    /// Focus `tab` and scroll so the mention sits at the bottom of the view. Line counts
+    /// are estimated without wrapping, which can leave it a little higher than that.
+    fn jump_to_mention(&mut self, tab: &Tab, timestamp: i64, sender: &str) {
+        let Some(msgs) = self.messages.get(tab) else {
+            self.status = "That conversation is gone".to_string();
+            return;
+        };
+        let Some(position) = msgs.iter().position(|m| m.mentions_me && m.timestamp == timestamp && m.sender == sender) else {
+            self.status = "That message is gone (expired or cleared)".to_string();
+            return;
+        };
+        let lines_below: usize = msgs[position + 1..].iter().map(estimated_lines).sum();
+
+        if let Some(idx) = self.tabs.iter().position(|t| t == tab) {
+            self.set_active_tab(idx);
+        }
+        self.scroll_offset.insert(tab.clone(), lines_below);
+        self.status = format!("Jumped to {}'s mention in {}", self.get_peer_display_name(sender), self.get_tab_name(tab));
+    }
+}
+
+/// Roughly how many lines the renderer gives a message
+pub(super) fn estimated_lines(m: &PlainMessage) -> usize {
+    let lines = m.content.lines().count().saturating_sub(folded_away(&m.content)).max(1);
+    if is_long(&m.content) && lines > COLLAPSED_LINES {
+        COLLAPSED_LINES + 1
+    } else {
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ME: &str = "abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789";
+
+    fn state() -> ChatState {
+        ChatState::new(ME.to_string(), Some("Ally B".to_string()), vec![0; 32])
+    }
+
+    #[test]
+    fn test_mention_detection() {
+        let state = state();
+        assert!(state.mentions_me("hey @ally b, look"));
+        assert!(state.mentions_me("@ALLY B"));
+        assert!(state.mentions_me("ping @abcdef01"));
+        assert!(!state.mentions_me("@ally bee is someone else"));
+        assert!(!state.mentions_me("ally b without the at"));
+        assert!(!state.mentions_me("@abcdef0 too short"));
+        assert!(!state.mentions_me("@abcdef02 wrong id"));
+    }
+}
@@ -0,0 +1,117 @@
+//! The microphone pump: a task per call that forwards captured opus frames to the
+//! network the moment they're encoded, so voice keeps its 20ms pacing however busy
+//! the UI loop is. The TUI only flips its controls: mute, hang up, and who to send to.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::client::{OutgoingMessage, OutgoingSender};
+
+/// Shared between the call's state and its pump
+#[derive(Debug, Clone, Default)]
+pub struct MicControls {
+    muted: Arc<AtomicBool>,
+    /// Cancelled when the call ends; the pump stops and drops the capture channel
+    hangup: CancellationToken,
+    /// Who each frame goes to: the peer, or the group's members
+    targets: Arc<RwLock<Vec<String>>>,
+}
+
+impl MicControls {
+    pub fn new(targets: Vec<String>) -> Self {
+        Self { targets: Arc::new(RwLock::new(targets)), ..Default::default() }
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Flip mute; returns whether we're now muted
+    pub fn toggle_mute(&self) -> bool {
+        !self.muted.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    pub fn hang_up(&self) {
+        self.hangup.cancel();
+    }
+
+    /// Point the pump at a new set of peers
+    pub fn set_targets(&self, targets: Vec<String>) {
+        *self.targets.write().unwrap_or_else(|e| e.into_inner()) = targets;
+    }
+
+    fn targets(&self) -> Vec<String> {
+        self.targets.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Forward frames from `capture_rx` until the call hangs up or capture stops
+pub(crate) fn spawn_mic_pump(capture_rx: mpsc::UnboundedReceiver<Vec<u8>>, msg_tx: OutgoingSender, controls: MicControls) {
+    tokio::spawn(pump(capture_rx, controls, move |msg| {
+        let _ = msg_tx.send(msg);
+    }));
+}
+
+async fn pump(mut capture_rx: mpsc::UnboundedReceiver<Vec<u8>>, controls: MicControls, send: impl Fn(OutgoingMessage)) {
+    loop {
+        let frame = tokio::select! {
+            _ = controls.hangup.cancelled() => return,
+            frame = capture_rx.recv() => match frame {
+                Some(frame) => frame,
+                None => return,
+            },
+        };
+        if controls.is_muted() {
+            continue;
+        }
+        for target_id in controls.targets() {
+            send(OutgoingMessage::Audio { target_id, data: frame.clone() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn next(out_rx: &mut mpsc::UnboundedReceiver<OutgoingMessage>) -> (String, Vec<u8>) {
+        match tokio::time::timeout(Duration::from_secs(1), out_rx.recv()).await {
+            Ok(Some(OutgoingMessage::Audio { target_id, data })) => (target_id, data),
+            _ => panic!("no frame sent"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pump_forwards_until_hangup() {
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let (capture_tx, capture_rx) = mpsc::unbounded_channel();
+        let controls = MicControls::new(vec!["alice".to_string(), "bob".to_string()]);
+        tokio::spawn(pump(capture_rx, controls.clone(), move |msg| {
+            let _ = out_tx.send(msg);
+        }));
+        // Each frame goes out as it's captured, to every target
+        capture_tx.send(vec![1]).unwrap();
+        assert_eq!(next(&mut out_rx).await, ("alice".to_string(), vec![1]));
+        assert_eq!(next(&mut out_rx).await, ("bob".to_string(), vec![1]));
+
+        // Muted frames are dropped; a group member leaving stops theirs
+        assert!(controls.toggle_mute());
+        capture_tx.send(vec![2]).unwrap();
+        tokio::task::yield_now().await;
+        assert!(!controls.toggle_mute());
+        controls.set_targets(vec!["bob".to_string()]);
+        capture_tx.send(vec![3]).unwrap();
+        assert_eq!(next(&mut out_rx).await, ("bob".to_string(), vec![3]));
+
+        controls.hang_up();
+        tokio::time::timeout(Duration::from_secs(1), capture_tx.closed()).await.expect("pump still running");
+    }
+}
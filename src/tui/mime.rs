@@ -0,0 +1,119 @@
+//! Content types for file transfers: guessed from the name on the sending side, sniffed
+//! from the first bytes on the receiving side so a disguised file can be flagged.
+
+use std::path::Path;
+
+const ZIP: &str = "application/zip";
+
+/// Extension -> MIME type for the formats `sniff` can recognise (plus plain text)
+const BY_EXTENSION: &[(&[&str], &str)] = &[
+    (&["png"], "image/png"),
+    (&["jpg", "jpeg"], "image/jpeg"),
+    (&["gif"], "image/gif"),
+    (&["webp"], "image/webp"),
+    (&["pdf"], "application/pdf"),
+    (&["zip"], ZIP),
+    (&["gz", "tgz"], "application/gzip"),
+    (&["tar"], "application/x-tar"),
+    (&["7z"], "application/x-7z-compressed"),
+    (&["rar"], "application/vnd.rar"),
+    (&["mp3"], "audio/mpeg"),
+    (&["ogg", "opus"], "audio/ogg"),
+    (&["mp4", "m4a", "mov"], "video/mp4"),
+    (&["wasm"], "application/wasm"),
+    (&["exe", "dll"], "application/x-msdownload"),
+    (&["sh"], "text/x-shellscript"),
+    (&["txt", "md", "log", "csv"], "text/plain"),
+];
+
+/// Formats that are zip archives underneath
+const ZIP_CONTAINERS: &[&str] = &["docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "jar", "apk"];
+
+/// Types that run code when opened
+const EXECUTABLE: &[&str] = &[
+    "application/x-executable",
+    "application/x-mach-binary",
+    "application/x-msdownload",
+    "text/x-shellscript",
+];
+
+fn extension(filename: &str) -> Option<String> {
+    Path::new(filename).extension().map(|e| e.to_string_lossy().to_lowercase())
+}
+
+/// MIME type implied by a file name's extension
+pub(crate) fn guess_from_name(filename: &str) -> Option<&'static str> {
+    let ext = extension(filename)?;
+    if ZIP_CONTAINERS.contains(&ext.as_str()) {
+        return Some(ZIP);
+    }
+    BY_EXTENSION.iter()
+        .find(|(exts, _)| exts.contains(&ext.as_str()))
+        .map(|(_, mime)| *mime)
+}
+
+/// MIME type recognised from a file's leading magic bytes
+pub(crate) fn sniff(data: &[u8]) -> Option<&'static str> {
+    let starts = |magic: &[u8]| data.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+    Some(match () {
+        _ if starts(b"\x89PNG\r\n\x1a\n") => "image/png",
+        _ if starts(b"\xff\xd8\xff") => "image/jpeg",
+        _ if starts(b"GIF87a") || starts(b"GIF89a") => "image/gif",
+        _ if starts(b"RIFF") && at(8, b"WEBP") => "image/webp",
+        _ if starts(b"%PDF-") => "application/pdf",
+        _ if starts(b"PK\x03\x04") || starts(b"PK\x05\x06") => ZIP,
+        _ if starts(b"\x1f\x8b") => "application/gzip",
+        _ if at(257, b"ustar") => "application/x-tar",
+        _ if starts(b"7z\xbc\xaf\x27\x1c") => "application/x-7z-compressed",
+        _ if starts(b"Rar!\x1a\x07") => "application/vnd.rar",
+        _ if starts(b"ID3") => "audio/mpeg",
+        _ if starts(b"OggS") => "audio/ogg",
+        _ if at(4, b"ftyp") => "video/mp4",
+        _ if starts(b"\0asm") => "application/wasm",
+        _ if starts(b"\x7fELF") => "application/x-executable",
+        _ if starts(b"\xcf\xfa\xed\xfe") || starts(b"\xce\xfa\xed\xfe") || starts(b"\xca\xfe\xba\xbe") => "application/x-mach-binary",
+        _ if starts(b"MZ") => "application/x-msdownload",
+        _ if starts(b"#!") => "text/x-shellscript",
+        _ => return None,
+    })
+}
+
+/// A warning if the first bytes of `filename` don't look like what it claims to be
+/// (`claimed` from the offer, else the extension). Unknown content is given the benefit
+/// of the doubt, except that anything executable is always called out.
+pub(crate) fn mismatch_warning(filename: &str, claimed: Option<&str>, first_chunk: &[u8]) -> Option<String> {
+    let sniffed = sniff(first_chunk)?;
+    let claimed = claimed.or_else(|| guess_from_name(filename));
+    match claimed {
+        Some(claimed) if claimed == sniffed => None,
+        Some(claimed) => Some(format!("claims to be {} but looks like {}", claimed, sniffed)),
+        None if EXECUTABLE.contains(&sniffed) => Some(format!("looks like an executable ({})", sniffed)),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guess_from_name() {
+        assert_eq!(guess_from_name("cat.PNG"), Some("image/png"));
+        assert_eq!(guess_from_name("report.docx"), Some(ZIP));
+        assert_eq!(guess_from_name("Makefile"), None);
+    }
+
+    #[test]
+    fn test_mismatch_warning() {
+        let png = b"\x89PNG\r\n\x1a\n....";
+        assert_eq!(mismatch_warning("cat.png", None, png), None);
+        assert_eq!(mismatch_warning("cat.png", Some("image/png"), png), None);
+        assert_eq!(mismatch_warning("notes.xyz", None, b"hello"), None);
+        assert_eq!(mismatch_warning("report.docx", None, b"PK\x03\x04..."), None);
+
+        let warning = mismatch_warning("cat.jpg", None, b"\x7fELF\x02\x01").unwrap();
+        assert!(warning.contains("image/jpeg") && warning.contains("application/x-executable"));
+        assert!(mismatch_warning("notes", None, b"#!/bin/sh\nrm -rf ~").unwrap().contains("executable"));
+    }
+}
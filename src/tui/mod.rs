@@ -1,107 +1,257 @@
+mod action;
+mod archive;
+mod away;
+mod bandwidth;
+mod call_keys;
 mod calls;
+mod catchup;
+mod churn;
+mod clipboard;
 mod commands;
+mod contacts;
+mod control;
+mod copy;
+mod discover;
+mod dnd;
+mod downloads;
+mod ephemeral;
+mod expiry;
+mod fences;
+mod export;
+mod participants;
+mod parts;
 mod files;
+mod group_seq;
 mod groups;
 mod helpers;
+mod hold;
+mod input;
+mod invite_link;
+mod lobby;
+mod low_bandwidth;
+mod invites;
+mod mentions;
+mod mic;
+mod mime;
+mod nick;
+mod offer_rows;
+mod ordering;
+mod pipe;
+mod plain;
+mod recent;
 mod render;
+mod roster;
+mod security;
+mod sounds;
+mod stars;
+mod state;
+mod stats;
+mod transfer_control;
 mod types;
+mod verify;
+mod whois;
 
 use anyhow::Result;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    event::{Event, EventStream, KeyEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use futures_util::StreamExt;
 use ratatui::{backend::CrosstermBackend, Terminal};
-use std::collections::HashMap;
 use std::io;
-use tokio::sync::mpsc;
-
-use crate::audio::AudioPipeline;
-use crate::client::{OutgoingMessage, PeerDisplay};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::audio::{AudioPipeline, AudioStats, AudioSupport};
+use crate::client::{ClientStats, ClientStatus, ConnectionState, InviteLink, OutgoingMessage, OutgoingSender, PeerUpdate};
 use crate::protocol::PlainMessage;
 
-use types::{
-    ActiveTransfer, AutocompleteState, CallState, CallType, CommandEntry, GroupInfo,
-    OutgoingTransfer, PendingFileOffer, ReadStatus, Tab,
-};
-
+pub use sounds::SoundSettings;
+pub use state::{ChatState, Effect};
+use bandwidth::RateLimit;
+use low_bandwidth::TrafficPolicy;
+use pipe::{PipeEvent, PipeReader};
+use types::AutocompleteState;
+
+/// Minimum time between redraws (caps the frame rate during calls and bursts)
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+/// Longest we wait on quit for the goodbye messages to reach the relay
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+/// How often typing indicators and disappearing messages expire, read receipts go out
+/// and the call clock ticks
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Terminal front-end: owns the input line and audio devices, renders `ChatState`
 pub struct ChatUI {
-    pub(crate) tabs: Vec<Tab>,
-    pub(crate) active_tab: usize,
-    pub(crate) messages: HashMap<Tab, Vec<PlainMessage>>,
+    pub(crate) state: ChatState,
     pub(crate) input: Vec<char>,
     pub(crate) cursor: usize,
-    pub(crate) status: String,
-    pub(crate) peers: HashMap<String, PeerDisplay>,
-    pub(crate) own_id: String,
-    pub(crate) own_nickname: Option<String>,
-    /// Our own identity public key (for safety number computation)
-    pub(crate) own_public_key: Vec<u8>,
-    /// Peers we've manually verified via /verify
-    pub(crate) verified_peers: std::collections::HashSet<String>,
-    pub(crate) pending_offers: HashMap<String, PendingFileOffer>,
-    pub(crate) active_transfers: HashMap<String, ActiveTransfer>,
-    pub(crate) outgoing_transfers: HashMap<String, OutgoingTransfer>,
-    pub(crate) groups: HashMap<String, GroupInfo>,
-    // Voice call state
-    pub(crate) active_call: Option<CallState>,
-    pub(crate) pending_call_from: Option<String>,
-    pub(crate) pending_group_call: Option<(String, String)>,
     pub(crate) audio_pipeline: Option<AudioPipeline>,
-    pub(crate) audio_capture_rx: Option<mpsc::UnboundedReceiver<Vec<u8>>>,
-    // Scroll state per tab (0 = at bottom)
-    pub(crate) scroll_offset: HashMap<Tab, usize>,
-    // Typing indicators: peer_id -> last typing timestamp
-    pub(crate) typing_peers: HashMap<String, std::time::Instant>,
-    pub(crate) last_typing_sent: Option<std::time::Instant>,
-    // Read receipts: message_id -> ReadStatus
-    pub(crate) read_status: HashMap<String, ReadStatus>,
     // Command autocomplete state
     pub(crate) autocomplete: Option<AutocompleteState>,
+    /// Relay connection as last reported by the client (`state.status` holds the last event)
+    pub(crate) connection: ConnectionState,
+    /// When `connection` last changed (for the reconnect countdown)
+    pub(crate) connection_since: Instant,
+    /// The client's traffic and ratchet counters, for /stats
+    pub(crate) client_stats: Option<ClientStats>,
+    /// Audio frame counters, kept across calls
+    pub(crate) audio_stats: Arc<AudioStats>,
+    /// The key binding overlay (F1 or /keys) is up
+    pub(crate) show_keys: bool,
+    /// The QR code /invite-link put up, a line of text per row
+    pub(crate) qr: Option<Vec<String>>,
+    /// Paces outgoing file chunks, if the config caps them
+    pub(crate) file_rate: Option<Arc<RateLimit>>,
+    /// Files being streamed out right now
+    pub(crate) streaming: Arc<AtomicUsize>,
+    /// Speakers opened for notification tones outside a call, and when one last played
+    pub(crate) speakers: Option<(AudioPipeline, Instant)>,
+    /// Config file a /nick is saved to (None: nothing is written)
+    pub(crate) config_path: Option<PathBuf>,
+    /// The task following /pipe's file
+    pub(crate) pipe_reader: Option<PipeReader>,
+    /// The client's switch for longer reconnect backoff, flipped with low-bandwidth mode
+    pub(crate) slow_reconnect: Option<Arc<AtomicBool>>,
 }
 
 impl ChatUI {
     pub fn new(own_id: String, nickname: Option<String>, own_public_key: Vec<u8>) -> Self {
-        let mut messages = HashMap::new();
-        messages.insert(Tab::Global, Vec::new());
-
         Self {
-            tabs: vec![Tab::Global],
-            active_tab: 0,
-            messages,
+            state: ChatState::new(own_id, nickname, own_public_key),
             input: Vec::new(),
             cursor: 0,
-            status: "Connecting...".to_string(),
-            peers: HashMap::new(),
-            own_id,
-            own_nickname: nickname,
-            own_public_key,
-            verified_peers: std::collections::HashSet::new(),
-            pending_offers: HashMap::new(),
-            active_transfers: HashMap::new(),
-            outgoing_transfers: HashMap::new(),
-            groups: HashMap::new(),
-            active_call: None,
-            pending_call_from: None,
-            pending_group_call: None,
             audio_pipeline: None,
-            audio_capture_rx: None,
-            scroll_offset: HashMap::new(),
-            typing_peers: HashMap::new(),
-            last_typing_sent: None,
-            read_status: HashMap::new(),
             autocomplete: None,
+            connection: ConnectionState::Disconnected,
+            connection_since: Instant::now(),
+            client_stats: None,
+            audio_stats: Arc::default(),
+            show_keys: false,
+            qr: None,
+            file_rate: None,
+            streaming: Arc::default(),
+            speakers: None,
+            config_path: None,
+            pipe_reader: None,
+            slow_reconnect: None,
         }
     }
 
+    /// Hand over the client's reconnect backoff switch, for low-bandwidth mode
+    pub fn set_slow_reconnect(&mut self, switch: Arc<AtomicBool>) {
+        self.slow_reconnect = Some(switch);
+    }
+
+    /// The relay we're connected to, for /invite-link
+    pub fn set_relay_url(&mut self, relay_url: String) {
+        self.state.relay_url = Some(relay_url);
+    }
+
+    /// Open a DM with whoever made `link` (`wsp chat <link>`) once they're online
+    pub fn set_invite(&mut self, link: InviteLink) {
+        self.state.await_invite(link);
+    }
+
+    /// Lobby-less mode (`--no-global`): the first tab only shows notices
+    pub fn set_no_global(&mut self) {
+        self.state.no_global = true;
+    }
+
+    /// Start in low-bandwidth mode (`--low-bandwidth`)
+    pub fn set_low_bandwidth(&mut self) {
+        self.state.traffic = TrafficPolicy::LowBandwidth;
+        if let Some(ref switch) = self.slow_reconnect {
+            switch.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Cap what `/send` will offer (files and whole folders alike)
+    pub fn set_max_share_bytes(&mut self, bytes: u64) {
+        self.state.max_share_bytes = bytes;
+    }
+
+    /// Save files a bare /accept takes into `dir` (made when first needed)
+    pub fn set_download_dir(&mut self, dir: PathBuf) {
+        self.state.download_dir = dir;
+    }
+
+    /// Make /accept take a path for offers over `bytes`
+    pub fn set_max_auto_size(&mut self, bytes: u64) {
+        self.state.max_auto_size = bytes;
+    }
+
+    /// Send files no faster than this many bytes a second (voice is never capped)
+    pub fn set_file_rate(&mut self, bytes_per_sec: u64) {
+        self.file_rate = Some(Arc::new(RateLimit::new(bytes_per_sec)));
+    }
+
+    /// Slow files to this many bytes a second during calls (0: leave them be)
+    pub fn set_call_file_rate(&mut self, bytes_per_sec: u64) {
+        self.state.call_file_rate = bytes_per_sec;
+    }
+
+    /// Warn once the session has sent and received this many bytes between them
+    pub fn set_session_warning(&mut self, bytes: u64) {
+        self.state.bandwidth.warn_after = Some(bytes);
+    }
+
+    /// Where /stats reads the connection's counters from
+    pub fn set_client_stats(&mut self, stats: ClientStats) {
+        self.client_stats = Some(stats);
+    }
+
+    /// Whether to take part in group history catch-up (on unless the config says no)
+    pub fn set_history_sync(&mut self, enabled: bool) {
+        self.state.history_sync = enabled;
+    }
+
+    /// Join groups on invite from peers we've verified, without waiting for /join
+    pub fn set_auto_join_verified(&mut self, enabled: bool) {
+        self.state.auto_join_verified = enabled;
+    }
+
+    /// Go away after this long without a keypress (None = only on /away)
+    pub fn set_auto_away(&mut self, after: Option<Duration>) {
+        self.state.away_after = after;
+    }
+
+    /// What the audio devices allow (calls are refused, or joined muted, without them)
+    pub fn set_audio_support(&mut self, support: AudioSupport) {
+        self.state.audio_support = support;
+    }
+
+    /// Paranoid mode: refuse or warn about anything that would write to disk
+    pub fn set_ephemeral(&mut self, ephemeral: bool) {
+        self.state.ephemeral = ephemeral;
+    }
+
+    /// Save nickname changes to this config file, under our profile
+    pub fn set_config_path(&mut self, path: PathBuf) {
+        self.config_path = Some(path);
+    }
+
+    /// The named profile we're running as, for the header
+    pub fn set_profile(&mut self, profile: Option<String>) {
+        self.state.profile = profile;
+    }
+
+    /// Reply once with `text` to each peer who DMs us while we're away
+    pub fn set_away_reply(&mut self, text: Option<String>) {
+        self.state.away_reply = text;
+    }
+
     pub async fn run(
         &mut self,
-        mut msg_tx: mpsc::UnboundedSender<OutgoingMessage>,
+        mut msg_tx: OutgoingSender,
         mut incoming_rx: mpsc::UnboundedReceiver<PlainMessage>,
-        mut status_rx: mpsc::UnboundedReceiver<String>,
-        mut peer_update_rx: mpsc::UnboundedReceiver<HashMap<String, PeerDisplay>>,
-        mut audio_in_rx: mpsc::UnboundedReceiver<(String, Vec<u8>)>,
+        mut status_rx: mpsc::UnboundedReceiver<ClientStatus>,
+        mut peer_update_rx: mpsc::UnboundedReceiver<Vec<PeerUpdate>>,
+        mut audio_in_rx: mpsc::UnboundedReceiver<(String, String, Vec<u8>)>,
     ) -> Result<()> {
         // Setup terminal - no mouse capture so native text selection works
         enable_raw_mode()?;
@@ -123,483 +273,278 @@ impl ChatUI {
         result
     }
 
-    /// Get all available commands for autocomplete
-    pub(crate) fn get_all_commands() -> Vec<CommandEntry> {
-        vec![
-            CommandEntry { name: "help".to_string(), description: "Show this command list".to_string() },
-            CommandEntry { name: "dm".to_string(), description: "Open DM with a peer: /dm <nick|id>".to_string() },
-            CommandEntry { name: "nick".to_string(), description: "Change nickname: /nick <name>".to_string() },
-            CommandEntry { name: "group".to_string(), description: "Group commands: create/invite/leave/members".to_string() },
-            CommandEntry { name: "call".to_string(), description: "Start a voice call in current tab".to_string() },
-            CommandEntry { name: "accept-call".to_string(), description: "Accept incoming call".to_string() },
-            CommandEntry { name: "reject-call".to_string(), description: "Reject incoming call".to_string() },
-            CommandEntry { name: "hangup".to_string(), description: "End current call".to_string() },
-            CommandEntry { name: "mute".to_string(), description: "Toggle microphone mute".to_string() },
-            CommandEntry { name: "verify".to_string(), description: "Show safety number for peer".to_string() },
-            CommandEntry { name: "verified".to_string(), description: "Mark peer as verified".to_string() },
-            CommandEntry { name: "send".to_string(), description: "Share a file: /send <filepath>".to_string() },
-            CommandEntry { name: "accept".to_string(), description: "Accept file offer: /accept [path]".to_string() },
-            CommandEntry { name: "reject".to_string(), description: "Reject file offer".to_string() },
-        ]
-    }
-
-    /// Update autocomplete state based on current input
-    fn update_autocomplete(&mut self) {
-        let input_str: String = self.input.iter().collect();
-        if input_str.starts_with('/') && !input_str.contains(' ') {
-            let filter = input_str[1..].to_lowercase();
-            let commands = Self::get_all_commands();
-            let filtered: Vec<usize> = commands.iter().enumerate()
-                .filter(|(_, cmd)| cmd.name.starts_with(&filter))
-                .map(|(i, _)| i)
-                .collect();
-
-            if !filtered.is_empty() {
-                let selected = if let Some(ref ac) = self.autocomplete {
-                    ac.selected.min(filtered.len().saturating_sub(1))
-                } else {
-                    0
-                };
-                self.autocomplete = Some(AutocompleteState {
-                    commands,
-                    filtered,
-                    selected,
-                    filter,
-                });
-            } else {
-                self.autocomplete = None;
-            }
-        } else {
-            self.autocomplete = None;
+    /// Say goodbye and close the connection, waiting (briefly) until the goodbyes are
+    /// actually on the wire
+    async fn shutdown(&mut self, msg_tx: &OutgoingSender) {
+        self.pipe_reader = None;
+        let effects = self.state.farewell();
+        self.apply_effects(effects, msg_tx);
+        let (done_tx, done_rx) = oneshot::channel();
+        if msg_tx.send(OutgoingMessage::Shutdown { done: done_tx }).is_ok() {
+            let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, done_rx).await;
         }
+        self.state.wipe_all();
     }
 
-        /// Send typing indicator to current tab's peers (debounced, bypasses ratchet)
-    fn send_typing_indicator(&mut self, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
-        use crate::protocol::Message;
-        
-        let now = std::time::Instant::now();
-        // Debounce: only send every 3 seconds
-        if let Some(last) = self.last_typing_sent {
-            if now.duration_since(last).as_secs() < 3 {
-                return;
-            }
-        }
-        self.last_typing_sent = Some(now);
-
-        let current_tab = &self.tabs[self.active_tab].clone();
-        match current_tab {
-            Tab::DirectMessage(peer_id) => {
-                let _ = msg_tx.send(OutgoingMessage::Signal(Message::Typing {
-                    from: self.own_id.clone(),
-                    target: peer_id.clone(),
-                    is_typing: true,
-                }));
-            }
-            Tab::Group(group_id) => {
-                if let Some(group) = self.groups.get(group_id) {
-                    for member_id in &group.members {
-                        let _ = msg_tx.send(OutgoingMessage::Signal(Message::Typing {
-                            from: self.own_id.clone(),
-                            target: member_id.clone(),
-                            is_typing: true,
-                        }));
+    /// Carry out the side effects requested by a state transition
+    fn apply_effects(&mut self, effects: Vec<Effect>, msg_tx: &OutgoingSender) {
+        for effect in effects {
+            match effect {
+                Effect::Send(msg) => {
+                    let _ = msg_tx.send(msg);
+                }
+                Effect::StreamFile { file_id, transfer } => {
+                    let streaming = (self.file_rate.clone(), self.streaming.clone());
+                    files::stream_file(msg_tx.clone(), self.state.own_id.clone(), file_id, transfer, streaming);
+                }
+                Effect::StartAudio => match AudioPipeline::start(self.audio_stats.clone(), self.state.audio_support == AudioSupport::Full) {
+                    Ok(mut pipeline) => {
+                        if let (Some(capture_rx), Some(call)) = (pipeline.take_capture_rx(), self.state.active_call.as_ref()) {
+                            mic::spawn_mic_pump(capture_rx, msg_tx.clone(), call.mic.clone());
+                        }
+                        self.audio_pipeline = Some(pipeline);
+                    }
+                    Err(e) => self.state.audio_failed(e.to_string()),
+                },
+                Effect::StopAudio => {
+                    if let Some(ref pipeline) = self.audio_pipeline {
+                        pipeline.stop();
                     }
+                    self.audio_pipeline = None;
                 }
-            }
-            Tab::Global => {
-                // Send to all peers
-                for peer_id in self.peers.keys() {
-                    let _ = msg_tx.send(OutgoingMessage::Signal(Message::Typing {
-                        from: self.own_id.clone(),
-                        target: peer_id.clone(),
-                        is_typing: true,
-                    }));
+                Effect::ShowKeys => self.show_keys = true,
+                Effect::ShowQr(text) => self.qr = Some(invite_link::qr_lines(&text)),
+                Effect::Sound(sound) => self.play_sound(sound),
+                Effect::SaveNickname(nickname) => self.save_nickname(&nickname),
+                Effect::StartPipe(path) => self.pipe_reader = Some(pipe::spawn_reader(path)),
+                Effect::StopPipe => self.pipe_reader = None,
+                Effect::LowBandwidth(on) => {
+                    if let Some(ref switch) = self.slow_reconnect {
+                        switch.store(on, Ordering::Relaxed);
+                    }
+                }
+                Effect::ShowStats => {
+                    let snapshot = self.client_stats.as_ref().map(ClientStats::snapshot);
+                    self.state.show_stats(snapshot.as_ref(), self.audio_stats.snapshot());
                 }
             }
         }
+        // Group members may have come or gone
+        self.state.sync_call_targets();
     }
 
-    /// Send read receipts for visible messages in the current tab (bypasses ratchet)
-    fn send_read_receipts(&mut self, msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>) {
-        use crate::protocol::Message;
-        
-        let current_tab = self.tabs[self.active_tab].clone();
-        let messages = match self.messages.get(&current_tab) {
-            Some(msgs) => msgs.clone(),
-            None => return,
-        };
-
-        for msg in &messages {
-            if msg.sender == self.own_id || msg.system {
-                continue;
+    /// Take in a report from the client: connection changes and what came of our sends
+    fn handle_status(&mut self, status: ClientStatus, msg_tx: &OutgoingSender) {
+        match status {
+            ClientStatus::Connection(state) => {
+                let connected = matches!(state, ConnectionState::Connected { .. });
+                let reconnected = self.connection.is_retrying() && connected;
+                // Room counts are stale until the relay sends fresh ones
+                if !connected {
+                    self.state.room_presence.clear();
+                }
+                self.connection = state;
+                self.connection_since = Instant::now();
+                // Groups kept talking while we were gone, and peers came and went
+                if reconnected {
+                    let mut effects = self.state.request_group_history();
+                    effects.push(Effect::Send(OutgoingMessage::ResyncPeers));
+                    self.apply_effects(effects, msg_tx);
+                }
+                // Whoever we're waiting on from an invite link may have come online meanwhile
+                if connected {
+                    let mut effects = Vec::new();
+                    self.state.seek_invite(&mut effects);
+                    self.apply_effects(effects, msg_tx);
+                }
             }
-            if let Some(ref msg_id) = msg.message_id {
-                if self.read_status.get(msg_id) == Some(&ReadStatus::Read) {
-                    continue; // Already sent read receipt
+            ClientStatus::Event(text) => self.state.status = text,
+            ClientStatus::RoomPresence { group_id, count } => {
+                if self.state.groups.contains_key(&group_id) {
+                    self.state.room_presence.insert(group_id, count);
                 }
-                // Mark as read and send receipt via signal (no ratchet)
-                self.read_status.insert(msg_id.clone(), ReadStatus::Read);
-
-                let _ = msg_tx.send(OutgoingMessage::Signal(Message::ReadReceipt {
-                    from: self.own_id.clone(),
-                    target: msg.sender.clone(),
-                    message_id: msg_id.clone(),
-                }));
             }
+            ClientStatus::PeerNotFound { session_id } => self.state.peer_not_found(&session_id),
+            ClientStatus::Delivered { message_id } => self.state.mark_delivered(&message_id),
+            ClientStatus::GroupSkipped { group_id, members } => self.state.group_skipped(&group_id, &members),
+            ClientStatus::RoomsRejoined { group_ids } => self.state.rooms_rejoined(&group_ids),
         }
     }
 
-    /// Clean up expired typing indicators (>5 seconds old)
-    fn cleanup_typing_indicators(&mut self) {
-        let now = std::time::Instant::now();
-        self.typing_peers.retain(|_, instant| {
-            now.duration_since(*instant).as_secs() < 5
-        });
+    /// The once-a-second chores: expire typing indicators, disappearing messages,
+    /// invites and half-arrived messages, go away when idle, ring and auto-answer, and
+    /// send read receipts. True if anything on screen changed.
+    fn housekeeping(&mut self, read_receipt_timer: &mut Instant, msg_tx: &OutgoingSender) -> bool {
+        let mut changed = self.state.cleanup_typing_indicators();
+        // Disappearing messages: drop the expired, keep the countdowns moving
+        if self.state.sweep_expired(chrono::Utc::now().timestamp()) || self.state.has_expiring_messages() {
+            changed = true;
+        }
+        let effects = self.state.check_idle();
+        if !effects.is_empty() {
+            self.apply_effects(effects, msg_tx);
+            changed = true;
+        }
+        self.state.sweep_sending();
+        let effects = self.state.check_call_participants();
+        self.apply_effects(effects, msg_tx);
+        let effects: Vec<Effect> = self.state.check_ringing().into_iter().collect();
+        self.apply_effects(effects, msg_tx);
+        self.close_idle_speakers();
+        let effects = self.state.check_auto_answer();
+        if !effects.is_empty() {
+            self.apply_effects(effects, msg_tx);
+            changed = true;
+        }
+        if self.state.check_dnd() {
+            changed = true;
+        }
+        if let Some(totals) = self.client_stats.as_ref().map(ClientStats::totals) {
+            if self.state.check_bandwidth(totals) {
+                changed = true;
+            }
+        }
+        let effects = self.state.check_pipe(Instant::now());
+        if !effects.is_empty() {
+            self.apply_effects(effects, msg_tx);
+            changed = true;
+        }
+        if self.state.release_churn(Instant::now()) {
+            changed = true;
+        }
+        self.state.expire_invites();
+        self.state.expire_discoveries();
+        if self.state.expire_partial_messages() {
+            self.state.status = "⚠️  Gave up on a long message that never finished arriving".to_string();
+            changed = true;
+        }
+        if read_receipt_timer.elapsed().as_secs() >= 2 {
+            let effects = self.state.read_receipts();
+            self.apply_effects(effects, msg_tx);
+            *read_receipt_timer = Instant::now();
+        }
+        changed
     }
 
     async fn run_loop(
         &mut self,
         terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-        msg_tx: &mut mpsc::UnboundedSender<OutgoingMessage>,
+        msg_tx: &mut OutgoingSender,
         incoming_rx: &mut mpsc::UnboundedReceiver<PlainMessage>,
-        status_rx: &mut mpsc::UnboundedReceiver<String>,
-        peer_update_rx: &mut mpsc::UnboundedReceiver<HashMap<String, PeerDisplay>>,
-        audio_in_rx: &mut mpsc::UnboundedReceiver<(String, Vec<u8>)>,
+        status_rx: &mut mpsc::UnboundedReceiver<ClientStatus>,
+        peer_update_rx: &mut mpsc::UnboundedReceiver<Vec<PeerUpdate>>,
+        audio_in_rx: &mut mpsc::UnboundedReceiver<(String, String, Vec<u8>)>,
     ) -> Result<()> {
-        let mut opus_decoder: Option<audiopus::coder::Decoder> = None;
-        let mut read_receipt_timer = std::time::Instant::now();
-        loop {
-            terminal.draw(|f| self.ui(f))?;
+        let mut events = EventStream::new();
+        let mut read_receipt_timer = Instant::now();
+        let mut housekeeping = tokio::time::interval(HOUSEKEEPING_INTERVAL);
+        // Redraw only when something visible changed, at most once per FRAME_INTERVAL
+        let mut dirty = true;
+        let mut last_draw = tokio::time::Instant::now() - FRAME_INTERVAL;
+        // Closing the terminal window (SIGHUP) or `kill` (SIGTERM) quits like Ctrl+C
+        let terminated = termination_signal();
+        tokio::pin!(terminated);
 
-            // Periodically clean up typing indicators and send read receipts
-            self.cleanup_typing_indicators();
-            if read_receipt_timer.elapsed().as_secs() >= 2 {
-                self.send_read_receipts(msg_tx);
-                read_receipt_timer = std::time::Instant::now();
+        loop {
+            if dirty && last_draw.elapsed() >= FRAME_INTERVAL {
+                terminal.draw(|f| self.ui(f))?;
+                dirty = false;
+                last_draw = tokio::time::Instant::now();
             }
-
-            // Handle events with timeout
-            if event::poll(std::time::Duration::from_millis(100))? {
-                if let Event::Key(key) = event::read()? {
-                    if key.kind == KeyEventKind::Press {
-                        // Handle autocomplete navigation first
-                        if self.autocomplete.is_some() {
-                            match key.code {
-                                KeyCode::Up => {
-                                    if let Some(ref mut ac) = self.autocomplete {
-                                        if ac.selected > 0 {
-                                            ac.selected -= 1;
-                                        } else {
-                                            ac.selected = ac.filtered.len().saturating_sub(1);
-                                        }
-                                    }
-                                    continue;
-                                }
-                                KeyCode::Down => {
-                                    if let Some(ref mut ac) = self.autocomplete {
-                                        if ac.selected < ac.filtered.len().saturating_sub(1) {
-                                            ac.selected += 1;
-                                        } else {
-                                            ac.selected = 0;
-                                        }
-                                    }
-                                    continue;
-                                }
-                                KeyCode::Enter => {
-                                    if let Some(ref ac) = self.autocomplete {
-                                        if let Some(&cmd_idx) = ac.filtered.get(ac.selected) {
-                                            let cmd_name = ac.commands[cmd_idx].name.clone();
-                                            self.input = format!("/{} ", cmd_name).chars().collect();
-                                            self.cursor = self.input.len();
-                                        }
-                                    }
-                                    self.autocomplete = None;
-                                    continue;
-                                }
-                                KeyCode::Esc => {
-                                    self.autocomplete = None;
-                                    continue;
-                                }
-                                KeyCode::Tab => {
-                                    // Tab-complete the selected command
-                                    if let Some(ref ac) = self.autocomplete {
-                                        if let Some(&cmd_idx) = ac.filtered.get(ac.selected) {
-                                            let cmd_name = ac.commands[cmd_idx].name.clone();
-                                            self.input = format!("/{} ", cmd_name).chars().collect();
-                                            self.cursor = self.input.len();
-                                        }
-                                    }
-                                    self.autocomplete = None;
-                                    continue;
-                                }
-                                _ => {
-                                    // Fall through to normal handling, autocomplete will update
-                                }
-                            }
-                        }
-
-                        match key.code {
-                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                let nick = self.display_name();
-                                let leave_msg = PlainMessage::system(
-                                    self.own_id.clone(),
-                                    format!("{} has left", nick),
-                                );
-                                let _ = msg_tx.send(OutgoingMessage::Global(leave_msg));
-                                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            let next_frame = last_draw + FRAME_INTERVAL;
+            // Group messages that came early are shown once the ones before them give up
+            let reorder_due = self.state.reorder_deadline().map(tokio::time::Instant::from_std);
+
+            tokio::select! {
+                event = events.next() => {
+                    match event {
+                        Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                            if self.handle_key(key, msg_tx).await {
+                                self.shutdown(msg_tx).await;
                                 return Ok(());
                             }
-                            KeyCode::Tab => {
-                                self.next_tab();
-                            }
-                            KeyCode::Left if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                self.prev_tab();
-                            }
-                            KeyCode::Right if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                self.next_tab();
-                            }
-                            // Scroll: Up/Down with Alt, PgUp/PgDown
-                            KeyCode::Up if key.modifiers.contains(KeyModifiers::ALT) => {
-                                self.scroll_up(1);
-                            }
-                            KeyCode::Down if key.modifiers.contains(KeyModifiers::ALT) => {
-                                self.scroll_down(1);
-                            }
-                            KeyCode::PageUp => {
-                                self.scroll_up(10);
-                            }
-                            KeyCode::PageDown => {
-                                self.scroll_down(10);
-                            }
-                            KeyCode::Char(c) => {
-                                self.input.insert(self.cursor, c);
-                                self.cursor += 1;
-                                self.update_autocomplete();
-                                // Send typing indicator for non-command input
-                                if !self.input.starts_with(&['/']) {
-                                    self.send_typing_indicator(msg_tx);
-                                }
-                            }
-                            KeyCode::Backspace => {
-                                if self.cursor > 0 {
-                                    self.cursor -= 1;
-                                    self.input.remove(self.cursor);
-                                    self.update_autocomplete();
-                                }
-                            }
-                            KeyCode::Delete => {
-                                if self.cursor < self.input.len() {
-                                    self.input.remove(self.cursor);
-                                    self.update_autocomplete();
-                                }
-                            }
-                            KeyCode::Left => {
-                                if self.cursor > 0 {
-                                    self.cursor -= 1;
-                                }
-                            }
-                            KeyCode::Right => {
-                                if self.cursor < self.input.len() {
-                                    self.cursor += 1;
-                                }
-                            }
-                            KeyCode::Home => {
-                                self.cursor = 0;
-                            }
-                            KeyCode::End => {
-                                self.cursor = self.input.len();
-                            }
-                            KeyCode::Enter if key.modifiers.contains(event::KeyModifiers::SHIFT) => {
-                                self.input.insert(self.cursor, '\n');
-                                self.cursor += 1;
-                            }
-                            KeyCode::Enter => {
-                                if !self.input.is_empty() {
-                                    let text: String = self.input.iter().collect();
-                                    self.handle_input(text, msg_tx);
-                                    self.input.clear();
-                                    self.cursor = 0;
-                                    self.autocomplete = None;
-                                    self.last_typing_sent = None;
-                                }
-                            }
-                            _ => {}
+                            dirty = true;
                         }
+                        Some(Ok(Event::Resize(..))) => dirty = true,
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => return Err(e.into()),
+                        None => return Ok(()),
                     }
                 }
-            }
-
-            // Check for incoming messages
-            while let Ok(msg) = incoming_rx.try_recv() {
-                // Handle typing indicators
-                if let Some(is_typing) = msg.typing {
-                    if is_typing {
-                        self.typing_peers.insert(msg.sender.clone(), std::time::Instant::now());
-                    } else {
-                        self.typing_peers.remove(&msg.sender);
-                    }
-                    continue;
+                _ = &mut terminated => {
+                    self.shutdown(msg_tx).await;
+                    return Ok(());
                 }
-
-                // Handle read receipts
-                if let Some(ref receipt_msg_id) = msg.read_receipt {
-                    self.read_status.insert(receipt_msg_id.clone(), ReadStatus::Read);
-                    continue;
+                Some(msg) = incoming_rx.recv() => {
+                    let effects = self.state.ingest_message(msg);
+                    self.apply_effects(effects, msg_tx);
+                    dirty = true;
                 }
-
-                // Handle voice call signaling
-                if msg.call_request == Some(true) {
-                    self.handle_incoming_call_request(&msg, msg_tx);
-                    continue;
+                Some(status) = status_rx.recv() => {
+                    self.handle_status(status, msg_tx);
+                    dirty = true;
                 }
-                if let Some(accept) = msg.call_accept {
-                    self.handle_call_response(&msg, accept, msg_tx);
-                    continue;
+                Some(updates) = peer_update_rx.recv() => {
+                    let effects = self.state.apply_peer_updates(updates);
+                    self.apply_effects(effects, msg_tx);
+                    dirty = true;
                 }
-                if msg.call_hangup == Some(true) {
-                    self.handle_remote_hangup(&msg, msg_tx);
-                    continue;
-                }
-
-                // Handle group invites
-                if let Some(ref invite) = msg.group_invite {
-                    self.handle_group_invite(msg.clone(), invite.clone(), msg_tx);
-                    continue;
-                }
-
-                // Handle file-related messages
-                if msg.file_offer.is_some() {
-                    self.handle_file_offer(msg.clone());
-                    continue;
-                } else if msg.file_chunk.is_some() {
-                    self.handle_file_chunk(msg.clone());
-                    continue;
-                } else if let Some(accept) = msg.file_response {
-                    self.handle_file_response(msg.clone(), accept, msg_tx);
-                    continue;
-                }
-
-                // Clear typing indicator for sender (they sent a real message)
-                self.typing_peers.remove(&msg.sender);
-
-                // Track read status for own messages
-                if msg.sender == self.own_id {
-                    if let Some(ref msg_id) = msg.message_id {
-                        self.read_status.entry(msg_id.clone()).or_insert(ReadStatus::Sent);
+                // Incoming audio frames (decrypt → decode → playback)
+                event = pipe::next_event(&mut self.pipe_reader) => {
+                    match event {
+                        PipeEvent::Lines(lines) => {
+                            let effects = self.state.pipe_lines(lines, Instant::now());
+                            self.apply_effects(effects, msg_tx);
+                        }
+                        PipeEvent::Failed(reason) => {
+                            self.pipe_reader = None;
+                            self.state.pipe_failed(&reason);
+                        }
                     }
+                    dirty = true;
                 }
-
-                // Auto-scroll to bottom on new messages if at bottom
-                let target_tab = if let Some(ref group_id) = msg.group_id {
-                    Tab::Group(group_id.clone())
-                } else if msg.direct {
-                    Tab::DirectMessage(msg.sender.clone())
-                } else {
-                    Tab::Global
-                };
-                let scroll = self.scroll_offset.get(&target_tab).copied().unwrap_or(0);
-                if scroll == 0 {
-                    // Already at bottom, stay there (default behavior)
-                }
-
-                // Handle group messages
-                if let Some(ref group_id) = msg.group_id {
-                    let group_tab = Tab::Group(group_id.clone());
-                    self.ensure_tab(&group_tab);
-                    self.messages.entry(group_tab).or_insert_with(Vec::new).push(msg);
-                    continue;
+                Some((from, call_id, opus_data)) = audio_in_rx.recv() => {
+                    if self.play_audio_frame(&from, &call_id, &opus_data) {
+                        dirty = true;
+                    }
                 }
-
-                if msg.dm_request {
-                    let sender_id = msg.sender.clone();
-                    let dm_tab = Tab::DirectMessage(sender_id.clone());
-                    if !self.tabs.contains(&dm_tab) {
-                        self.tabs.push(dm_tab.clone());
-                        self.messages.insert(dm_tab, Vec::new());
-                        let peer_name = self.get_peer_display_name(&sender_id);
-                        self.status = format!("{} opened a DM with you", peer_name);
+                _ = housekeeping.tick() => {
+                    if self.housekeeping(&mut read_receipt_timer, msg_tx) {
+                        dirty = true;
                     }
-                } else if msg.system && !msg.content.is_empty() {
-                    self.messages.entry(Tab::Global).or_insert_with(Vec::new).push(msg);
-                } else if !msg.system {
-                    let sender_id = msg.sender.clone();
-
-                    if msg.direct {
-                        let dm_tab = Tab::DirectMessage(sender_id.clone());
-                        self.ensure_tab(&dm_tab);
-                        self.messages.entry(dm_tab).or_insert_with(Vec::new).push(msg);
-                    } else {
-                        self.messages.entry(Tab::Global).or_insert_with(Vec::new).push(msg);
+                    // Keep the call duration clock and reconnect countdown ticking
+                    if self.state.active_call.is_some() || self.state.held_call.is_some() || self.connection.is_retrying() {
+                        dirty = true;
                     }
                 }
-            }
-
-            // Check for status updates
-            while let Ok(status) = status_rx.try_recv() {
-                self.status = status;
-            }
-
-            // Check for peer updates
-            while let Ok(peers) = peer_update_rx.try_recv() {
-                self.peers = peers;
-            }
-
-            // Handle incoming audio frames (decrypt → decode → playback)
-            while let Ok((from, opus_data)) = audio_in_rx.try_recv() {
-                if let Some(ref call) = self.active_call {
-                    let accept = match &call.call_type {
-                        CallType::Direct(peer_id) => *peer_id == from,
-                        CallType::Group { group_id } => {
-                            self.groups.get(group_id)
-                                .map(|g| g.members.contains(&from))
-                                .unwrap_or(false)
-                        }
-                    };
-                    if accept {
-                        if opus_decoder.is_none() {
-                            opus_decoder = audiopus::coder::Decoder::new(
-                                audiopus::SampleRate::Hz48000,
-                                audiopus::Channels::Mono,
-                            ).ok();
-                        }
-                        if let Some(ref mut decoder) = opus_decoder {
-                            if let Ok(pcm) = AudioPipeline::decode_opus_frame(decoder, &opus_data) {
-                                if let Some(ref pipeline) = self.audio_pipeline {
-                                    if let Some(tx) = pipeline.playback_tx() {
-                                        let _ = tx.send(pcm);
-                                    }
-                                }
-                            }
-                        }
-                    }
+                _ = tokio::time::sleep_until(reorder_due.unwrap_or(next_frame)), if reorder_due.is_some() => {
+                    let effects = self.state.release_overdue(Instant::now());
+                    self.apply_effects(effects, msg_tx);
+                    dirty = true;
                 }
+                // A change arrived inside the frame cap — draw it once the cap elapses
+                _ = tokio::time::sleep_until(next_frame), if dirty => {}
             }
+        }
+    }
+}
 
-            // Send captured audio frames to peer(s) (unless muted)
-            if let Some(ref call) = self.active_call {
-                let is_muted = call.muted;
-                let target_ids: Vec<String> = match &call.call_type {
-                    CallType::Direct(peer_id) => vec![peer_id.clone()],
-                    CallType::Group { group_id } => {
-                        self.groups.get(group_id)
-                            .map(|g| g.members.clone())
-                            .unwrap_or_default()
-                    }
-                };
-                if let Some(ref mut capture_rx) = self.audio_capture_rx {
-                    while let Ok(opus_frame) = capture_rx.try_recv() {
-                        if !is_muted {
-                            for target_id in &target_ids {
-                                let _ = msg_tx.send(OutgoingMessage::Audio {
-                                    target_id: target_id.clone(),
-                                    data: opus_frame.clone(),
-                                });
-                            }
-                        }
-                    }
+/// Resolves when the process is asked to terminate (SIGTERM, or SIGHUP when the
+/// terminal goes away). Never resolves where those signals don't exist.
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match (signal(SignalKind::terminate()), signal(SignalKind::hangup())) {
+            (Ok(mut term), Ok(mut hup)) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = hup.recv() => {}
                 }
             }
+            _ => std::future::pending().await,
         }
     }
+    #[cfg(not(unix))]
+    std::future::pending::<()>().await
 }
@@ -0,0 +1,121 @@
+//! Changing our nickname with /nick: every peer hears it pairwise, every group we're in
+//! hears it through the group as well (members we never exchanged keys with included),
+//! and the config file keeps it for next time.
+
+use crate::client::OutgoingMessage;
+use crate::config::Config;
+use crate::protocol::{sanitize_nickname, short_id, MemberHint, PlainMessage, MAX_NICKNAME_CHARS};
+
+use super::types::Tab;
+use super::state::{ChatState, Effect};
+use super::ChatUI;
+
+impl ChatState {
+    /// Handle /nick <name>
+    pub(crate) fn handle_nick_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        if args.is_empty() {
+            self.status = format!("Usage: /nick <new_nickname> (up to {} characters, no #)", MAX_NICKNAME_CHARS);
+            return;
+        }
+        // Peers would clean it up anyway; apply the same rules so everyone sees one name
+        let requested = args.join(" ");
+        let Some(new_nick) = sanitize_nickname(&requested) else {
+            self.status = "Nickname has no printable characters".to_string();
+            return;
+        };
+        self.own_nickname = Some(new_nick.clone());
+
+        // The client announces it to every peer in one batch (and to later peers on join)
+        fx.push(Effect::Send(OutgoingMessage::Nickname(new_nick.clone())));
+        let mut group_ids: Vec<&String> = self.groups.keys().collect();
+        group_ids.sort();
+        for group_id in group_ids {
+            fx.push(Effect::Send(OutgoingMessage::Group {
+                group_id: group_id.clone(),
+                member_ids: self.groups[group_id].members.clone(),
+                message: PlainMessage {
+                    group_id: Some(group_id.clone()),
+                    ..PlainMessage::nickname(self.own_id.clone(), new_nick.clone())
+                },
+            }));
+        }
+        fx.push(Effect::SaveNickname(new_nick.clone()));
+
+        self.status = if new_nick == requested {
+            format!("Nickname changed to: {}", new_nick)
+        } else {
+            format!("Nickname changed to: {} (cleaned up: at most {} characters, no #)", new_nick, MAX_NICKNAME_CHARS)
+        };
+    }
+
+    /// A member announced a new nickname to one of our groups. Say so in the group's tab,
+    /// and go by it until their pairwise announcement (if any) confirms it.
+    pub(crate) fn group_nickname(&mut self, msg: &PlainMessage) {
+        let (Some(nick), Some(group_id)) = (msg.nickname.clone(), msg.group_id.clone()) else {
+            return;
+        };
+        if msg.sender == self.own_id || !self.groups.contains_key(&group_id) {
+            return;
+        }
+        let peer = self.peers.get(&msg.sender);
+        let hint = self.name_hints.get(&msg.sender);
+        // The pairwise announcement may have beaten this one here
+        let old = peer.and_then(|p| p.nickname.clone())
+            .or_else(|| hint.map(|h| h.nickname.clone()))
+            .filter(|old| *old != nick)
+            .unwrap_or_else(|| short_id(&msg.sender).to_string());
+        if peer.and_then(|p| p.nickname.as_ref()).is_none() {
+            let public_key = peer.map(|p| p.public_key.clone())
+                .or_else(|| hint.map(|h| h.public_key.clone()))
+                .unwrap_or_default();
+            self.name_hints.insert(msg.sender.clone(), MemberHint { session_id: msg.sender.clone(), nickname: nick.clone(), public_key });
+        }
+        self.add_system_message(&Tab::Group(group_id), format!("{} is now known as {}", old, nick));
+    }
+}
+
+impl ChatUI {
+    /// Keep a /nick for the next start, in the config file we were started with (if any)
+    pub(super) fn save_nickname(&mut self, nickname: &str) {
+        let Some(ref path) = self.config_path else {
+            return;
+        };
+        let saved = Config::load(path).and_then(|mut config| {
+            config.set_nickname(self.state.profile.as_deref(), nickname.to_string());
+            config.save(path)
+        });
+        if let Err(e) = saved {
+            self.state.status = format!("⚠️  Nickname changed, but not saved: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tui::types::GroupInfo;
+
+    #[test]
+    fn test_nick_reaches_groups_and_is_saved() {
+        let peer = "ab".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members: vec![peer.clone()], join_token: None });
+        state.tabs.push(Tab::Group("g1".to_string()));
+
+        let fx = state.handle_command("/nick ada#1");
+        assert_eq!(state.own_nickname.as_deref(), Some("ada1"));
+        assert!(fx.iter().any(|e| matches!(e, Effect::Send(OutgoingMessage::Nickname(n)) if n == "ada1")));
+        assert!(fx.iter().any(|e| matches!(
+            e,
+            Effect::Send(OutgoingMessage::Group { group_id, message, .. }) if group_id == "g1" && message.nickname.as_deref() == Some("ada1")
+        )));
+        assert!(fx.iter().any(|e| matches!(e, Effect::SaveNickname(n) if n == "ada1")));
+
+        // A member we never exchanged keys with renames themselves in the group
+        let renamed = PlainMessage { group_id: Some("g1".to_string()), ..PlainMessage::nickname(peer.clone(), "bea".to_string()) };
+        state.ingest_message(renamed);
+        let last = state.messages[&Tab::Group("g1".to_string())].last().unwrap();
+        assert_eq!(last.content, format!("{} is now known as bea", short_id(&peer)));
+        assert_eq!(state.unconfirmed_name(&peer), Some("bea"));
+    }
+}
@@ -0,0 +1,205 @@
+//! File offers as rows in the conversation. Each offer, ours or a peer's, gets one
+//! chat entry that is rewritten as it goes: waiting for /accept, downloading (with a
+//! progress bar while chunks arrive), then saved, failed or rejected. Our own offers
+//! list who accepted and who turned them down. /accept and /reject without a number
+//! pick from the rows in the open tab.
+
+use std::time::Duration;
+
+use crate::protocol::{ChatEvent, FileOffer, PlainMessage};
+
+use super::state::ChatState;
+use super::types::{ActiveTransfer, Tab, FILE_CHUNK_SIZE};
+
+/// Cells in a download's progress bar
+const BAR_WIDTH: usize = 20;
+
+/// The unchanging start of an offer's row, and for our own offers the answers so far
+#[derive(Clone, Debug)]
+pub(crate) struct OfferRow {
+    head: String,
+    answers: Vec<String>,
+}
+
+impl ChatState {
+    /// Add the row for `offer` to `tab`: a peer's offer if `from` is given, ours otherwise
+    pub(crate) fn add_offer_row(&mut self, tab: &Tab, offer: &FileOffer, from: Option<&str>) {
+        let kind = if offer.is_archive {
+            format!(", folder of {} entries", offer.entry_count)
+        } else {
+            offer.mime_type.as_deref().map(|m| format!(", {}", m)).unwrap_or_default()
+        };
+        let mut head = format!("📎 {} ({}{})", offer.filename, Self::format_size(offer.size), kind);
+        let content = match from {
+            Some(peer) => {
+                head.push_str(&format!(" from {}", self.get_peer_display_name(peer)));
+                head.clone()
+            }
+            None => format!("{} — waiting for an answer", head),
+        };
+        self.offer_rows.insert(offer.file_id.clone(), OfferRow { head, answers: Vec::new() });
+        self.ensure_tab(tab);
+        let row = PlainMessage {
+            file_offer: Some(offer.clone()),
+            ..PlainMessage::event(self.own_id.clone(), ChatEvent::FileOffered, content)
+        };
+        self.push_message(tab.clone(), row);
+    }
+
+    /// Rewrite `file_id`'s row to say where it's got to
+    pub(crate) fn update_offer_row(&mut self, file_id: &str, event: ChatEvent, outcome: &str) {
+        let Some(row) = self.offer_rows.get(file_id) else {
+            return;
+        };
+        let content = format!("{} — {}", row.head, outcome);
+        if let Some(m) = self.offer_row_mut(file_id) {
+            m.event = Some(event);
+            m.content = content;
+        }
+    }
+
+    /// Note `peer`'s answer to one of our offers on its row
+    pub(crate) fn answer_offer_row(&mut self, file_id: &str, peer: &str, answer: &str) {
+        let name = self.get_peer_display_name(peer);
+        let Some(row) = self.offer_rows.get_mut(file_id) else {
+            return;
+        };
+        row.answers.push(format!("{} {}", name, answer));
+        let content = format!("{} — {}", row.head, row.answers.join(" · "));
+        if let Some(m) = self.offer_row_mut(file_id) {
+            // Accepted by anyone beats rejected by someone
+            if m.event != Some(ChatEvent::FileAccepted) {
+                m.event = Some(if answer.starts_with('✅') { ChatEvent::FileAccepted } else { ChatEvent::FileRejected });
+            }
+            m.content = content;
+        }
+    }
+
+    fn offer_row_mut(&mut self, file_id: &str) -> Option<&mut PlainMessage> {
+        self.messages.values_mut()
+            .flat_map(|messages| messages.iter_mut())
+            .find(|m| m.event.is_some() && m.file_offer.as_ref().is_some_and(|o| o.file_id == file_id))
+    }
+
+    /// Ids of the offers waiting for an answer in `tab`, in the order their rows appear.
+    /// Offers whose row has gone (wiped, or expired) come last, oldest first.
+    pub(crate) fn pending_rows_in(&self, tab: &Tab) -> Vec<String> {
+        let mut ids: Vec<String> = self.messages.get(tab).into_iter()
+            .flatten()
+            .filter(|m| m.event.is_some())
+            .filter_map(|m| m.file_offer.as_ref())
+            .filter(|o| self.pending_offers.get(&o.file_id).is_some_and(|p| &p.tab == tab))
+            .map(|o| o.file_id.clone())
+            .collect();
+        let mut rowless: Vec<_> = self.pending_offers.iter()
+            .filter(|(id, p)| &p.tab == tab && !ids.contains(id))
+            .collect();
+        rowless.sort_by(|a, b| a.1.received.cmp(&b.1.received).then_with(|| a.0.cmp(b.0)));
+        ids.extend(rowless.into_iter().map(|(id, _)| id.clone()));
+        ids
+    }
+
+    /// An event entry as shown: offer rows get where they'd be saved and what to type
+    /// while they wait, and a progress bar while they download
+    pub(crate) fn event_text(&self, m: &PlainMessage) -> String {
+        let Some(offer) = m.file_offer.as_ref() else {
+            return m.content.clone();
+        };
+        if let Some(pending) = self.pending_offers.get(&offer.file_id) {
+            let waiting = self.pending_rows_in(&pending.tab);
+            let pick = match waiting.iter().position(|id| *id == offer.file_id) {
+                Some(i) if waiting.len() > 1 => format!(" {}", i + 1),
+                _ => String::new(),
+            };
+            return match self.default_destination(offer) {
+                Some(dest) => format!("{} — saves to {} [accept: /accept{}] [reject: /reject{}]", m.content, dest.display(), pick, pick),
+                None => format!("{} — too big to take without a path [accept: /accept{} <path>] [reject: /reject{}]", m.content, pick, pick),
+            };
+        }
+        match self.active_transfers.get(&offer.file_id) {
+            Some(transfer) => format!("{} {}", m.content, progress(transfer)),
+            None => m.content.clone(),
+        }
+    }
+}
+
+/// "█████░░░░░░░░░░░░░░░ 25% · 1.20 MB/s"
+fn progress(transfer: &ActiveTransfer) -> String {
+    let total = transfer.offer.total_chunks.max(1) as usize;
+    let done = transfer.chunks_done as usize;
+    let filled = done * BAR_WIDTH / total;
+    let bytes = (done as u64 * FILE_CHUNK_SIZE as u64).min(transfer.offer.size);
+    let secs = transfer.started.elapsed().max(Duration::from_millis(1)).as_secs_f64();
+    format!(
+        "{}{} {}% · {}/s",
+        "█".repeat(filled),
+        "░".repeat(BAR_WIDTH - filled),
+        done * 100 / total,
+        ChatState::format_size((bytes as f64 / secs) as u64)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+
+    fn offer(file_id: &str, name: &str) -> FileOffer {
+        FileOffer {
+            file_id: file_id.to_string(),
+            filename: name.to_string(),
+            size: 4 * FILE_CHUNK_SIZE as u64,
+            checksum: String::new(),
+            total_chunks: 4,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        }
+    }
+
+    fn row<'a>(state: &'a ChatState, file_id: &str) -> &'a PlainMessage {
+        state.messages[&Tab::Global].iter()
+            .find(|m| m.file_offer.as_ref().is_some_and(|o| o.file_id == file_id))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_offer_rows_follow_the_transfer() {
+        let dir = tempfile::tempdir().unwrap();
+        let alice = "aa".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.download_dir = dir.path().to_path_buf();
+        state.global_confirmed = true;
+        let alice_display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), alice_display)]);
+        state.ingest_message(PlainMessage::file_offer(alice.clone(), offer("f1", "report.pdf"), false));
+        state.ingest_message(PlainMessage::file_offer(alice.clone(), offer("f2", "notes.txt"), false));
+
+        let shown = state.event_text(row(&state, "f1"));
+        let dest = dir.path().join("report.pdf");
+        assert_eq!(shown, format!("📎 report.pdf (64.00 KB) from alice — saves to {} [accept: /accept 1] [reject: /reject 1]", dest.display()));
+        state.handle_command("/reject 1");
+        assert_eq!(row(&state, "f1").content, "📎 report.pdf (64.00 KB) from alice — 🚫 rejected");
+        assert_eq!(row(&state, "f1").event, Some(ChatEvent::FileRejected));
+        // One left: the bare commands pick it
+        assert!(state.event_text(row(&state, "f2")).ends_with("[accept: /accept] [reject: /reject]"));
+
+        state.handle_command(&format!("/accept {}", dir.path().display()));
+        let chunk = crate::protocol::FileChunk { file_id: "f2".to_string(), index: 0, data: vec![0; FILE_CHUNK_SIZE] };
+        state.ingest_message(PlainMessage::file_chunk(alice.clone(), chunk, false));
+        let shown = state.event_text(row(&state, "f2"));
+        assert!(shown.contains("📥 saving to") && shown.contains("█████░░░░░░░░░░░░░░░ 25% · "), "{}", shown);
+
+        // Our own offers list the answers
+        let path = dir.path().join("out.bin");
+        std::fs::write(&path, b"data").unwrap();
+        let fx = state.handle_command(&format!("/send {}", path.display()));
+        let file_id = fx.iter().find_map(|e| match e {
+            crate::tui::state::Effect::Send(crate::client::OutgoingMessage::Global(m)) => m.file_offer.as_ref().map(|o| o.file_id.clone()),
+            _ => None,
+        }).unwrap();
+        assert!(row(&state, &file_id).content.ends_with("waiting for an answer"));
+        state.ingest_message(PlainMessage::file_response(alice.clone(), file_id.clone(), true, false));
+        assert_eq!(row(&state, &file_id).content, "📎 out.bin (4 bytes) — alice ✅ accepted, sending");
+    }
+}
@@ -0,0 +1,91 @@
+//! Keeping a tab in the order things were said: a message the relay delivers twice
+//! is shown once, and one that turns up after newer ones is slotted in where its
+//! timestamp puts it rather than at the bottom.
+
+use crate::protocol::PlainMessage;
+
+use super::state::ChatState;
+
+/// Message ids remembered per peer for spotting redeliveries
+const SEEN_PER_PEER: usize = 256;
+/// A message this many seconds older than the newest one in its tab is placed by timestamp
+const REORDER_SLACK_SECS: i64 = 2;
+/// Further behind than this, it's also marked "(delayed)"
+const DELAYED_AFTER_SECS: i64 = 30;
+
+/// Put `msg` into `messages` by timestamp: at the end, unless it's clearly older than
+/// the last one, in which case it goes after the latest message not newer than it
+pub(crate) fn insert_in_order(messages: &mut Vec<PlainMessage>, mut msg: PlainMessage) {
+    let Some(tail) = messages.last().map(|m| m.timestamp) else {
+        messages.push(msg);
+        return;
+    };
+    if tail - msg.timestamp <= REORDER_SLACK_SECS {
+        messages.push(msg);
+        return;
+    }
+    msg.delayed = tail - msg.timestamp > DELAYED_AFTER_SECS;
+    let at = messages.iter().rposition(|m| m.timestamp <= msg.timestamp).map_or(0, |i| i + 1);
+    messages.insert(at, msg);
+}
+
+impl ChatState {
+    /// Whether we've already shown `msg` from its sender. Remembers its id otherwise,
+    /// keeping the most recent few hundred per peer.
+    pub(crate) fn seen_before(&mut self, msg: &PlainMessage) -> bool {
+        let Some(ref id) = msg.message_id else {
+            return false;
+        };
+        let seen = self.seen_ids.entry(msg.sender.clone()).or_default();
+        if let Some(at) = seen.iter().position(|s| s == id) {
+            // Still being redelivered: keep it around longer
+            let id = seen.remove(at).unwrap_or_default();
+            seen.push_back(id);
+            return true;
+        }
+        seen.push_back(id.clone());
+        if seen.len() > SEEN_PER_PEER {
+            seen.pop_front();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tui::types::Tab;
+
+    fn said(sender: &str, content: &str, timestamp: i64) -> PlainMessage {
+        PlainMessage { timestamp, ..PlainMessage::new(sender.to_string(), content.to_string()) }
+    }
+
+    #[test]
+    fn test_duplicates_dropped_and_late_messages_slotted_in() {
+        let peer = "ab".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let now = chrono::Utc::now().timestamp();
+
+        let first = said(&peer, "first", now - 120);
+        state.ingest_message(first.clone());
+        state.ingest_message(said(&peer, "third", now));
+        // Redelivered by the relay: shown once
+        state.ingest_message(first.clone());
+        // Held up somewhere for a minute and a half
+        state.ingest_message(said(&peer, "second", now - 90));
+        // A second or so of skew isn't worth reordering
+        state.ingest_message(said(&peer, "fourth", now - 1));
+
+        let shown: Vec<(&str, bool)> = state.messages[&Tab::Global].iter()
+            .map(|m| (m.content.as_str(), m.delayed))
+            .collect();
+        assert_eq!(shown, [("first", false), ("second", true), ("third", false), ("fourth", false)]);
+
+        // The same id from someone else isn't a duplicate; only the latest ids are kept
+        assert!(!state.seen_before(&PlainMessage { sender: "cd".repeat(16), ..first.clone() }));
+        for i in 0..SEEN_PER_PEER {
+            state.seen_before(&PlainMessage { message_id: Some(i.to_string()), ..first.clone() });
+        }
+        assert!(!state.seen_before(&first));
+    }
+}
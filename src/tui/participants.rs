@@ -0,0 +1,236 @@
+//! Who's in a group call with us: joins and leaves come from the call signaling, the
+//! speaking indicator from how loud each member's frames are, and mute from their
+//! announcements. A member we stop hearing from is pinged, and shown as possibly gone
+//! if nothing comes back (unless they said they put the call on hold).
+
+use std::time::{Duration, Instant};
+
+use crate::client::OutgoingMessage;
+use crate::protocol::PlainMessage;
+
+use super::state::{ChatState, Effect};
+use super::types::CallType;
+
+/// Frame loudness (RMS) above which a member counts as speaking
+const SPEAKING_LEVEL: f32 = 0.02;
+/// How long the speaking indicator stays on after the last loud frame
+const SPEAKING_HOLD: Duration = Duration::from_millis(300);
+/// Silence after which we ask a member whether they're still there
+const PING_AFTER: Duration = Duration::from_secs(5);
+/// Silence after which a member is shown as "(connection lost?)"
+const LOST_AFTER: Duration = Duration::from_secs(15);
+
+/// Someone else in the group call
+#[derive(Debug, Clone)]
+pub struct Participant {
+    pub muted: bool,
+    pub on_hold: bool,
+    last_loud: Option<Instant>,
+    /// Last audio frame, announcement or keepalive from them
+    last_heard: Instant,
+    last_ping: Option<Instant>,
+}
+
+impl Participant {
+    pub(crate) fn new() -> Self {
+        Self { muted: false, on_hold: false, last_loud: None, last_heard: Instant::now(), last_ping: None }
+    }
+
+    pub fn speaking(&self) -> bool {
+        !self.muted && self.last_loud.is_some_and(|at| at.elapsed() < SPEAKING_HOLD)
+    }
+
+    pub fn lost(&self) -> bool {
+        !self.on_hold && self.last_heard.elapsed() >= LOST_AFTER
+    }
+
+    /// They put the call on hold, or came back to it
+    pub(crate) fn set_on_hold(&mut self, on_hold: bool) {
+        self.on_hold = on_hold;
+        self.last_heard = Instant::now();
+    }
+}
+
+impl ChatState {
+    /// The group of the call we're in, if it's a group call
+    fn call_group(&self) -> Option<&str> {
+        match self.active_call.as_ref()?.call_type {
+            CallType::Group { ref group_id } => Some(group_id),
+            CallType::Direct(_) => None,
+        }
+    }
+
+    /// `msg` is about the group call we're in
+    fn in_our_call(&self, msg: &PlainMessage) -> bool {
+        self.call_group().is_some_and(|g| msg.group_id.as_deref() == Some(g))
+    }
+
+    /// Someone joined our group call. If we're muted they hear about it.
+    pub(crate) fn add_call_participant(&mut self, peer_id: &str, fx: &mut Vec<Effect>) {
+        let Some(ref mut call) = self.active_call else {
+            return;
+        };
+        call.participants.insert(peer_id.to_string(), Participant::new());
+        if call.mic.is_muted() {
+            let msg = self.call_msg(PlainMessage::call_muted(self.own_id.clone(), true));
+            fx.push(Effect::Send(OutgoingMessage::Direct { target_id: peer_id.to_string(), message: msg }));
+        }
+    }
+
+    pub(crate) fn remove_call_participant(&mut self, peer_id: &str) {
+        if let Some(ref mut call) = self.active_call {
+            call.participants.remove(peer_id);
+        }
+    }
+
+    /// Tag a call payload with our call's group
+    fn call_msg(&self, mut msg: PlainMessage) -> PlainMessage {
+        msg.group_id = self.call_group().map(str::to_string);
+        msg
+    }
+
+    /// Tell the group call whether we're muted
+    pub(crate) fn announce_mute(&self, fx: &mut Vec<Effect>) {
+        let (Some(call), Some(group_id)) = (self.active_call.as_ref(), self.call_group()) else {
+            return;
+        };
+        let member_ids = self.groups.get(group_id).map(|g| g.members.clone()).unwrap_or_default();
+        fx.push(Effect::Send(OutgoingMessage::Group {
+            group_id: group_id.to_string(),
+            member_ids,
+            message: self.call_msg(PlainMessage::call_muted(self.own_id.clone(), call.mic.is_muted())),
+        }));
+    }
+
+    /// A frame from group member `from` played at `level` (someone sending audio is in
+    /// the call, even if we missed them joining). True when the list needs redrawing.
+    pub(crate) fn note_call_audio(&mut self, from: &str, level: f32) -> bool {
+        if self.call_group().is_none() {
+            return false;
+        }
+        let Some(ref mut call) = self.active_call else {
+            return false;
+        };
+        let joined = !call.participants.contains_key(from);
+        let p = call.participants.entry(from.to_string()).or_insert_with(Participant::new);
+        let was = p.speaking();
+        p.last_heard = Instant::now();
+        if level >= SPEAKING_LEVEL {
+            p.last_loud = Some(Instant::now());
+        }
+        joined || p.speaking() != was
+    }
+
+    /// A member said they muted or unmuted
+    pub(crate) fn handle_call_muted(&mut self, msg: &PlainMessage, muted: bool) {
+        if !self.in_our_call(msg) {
+            return;
+        }
+        if let Some(p) = self.active_call.as_mut().and_then(|c| c.participants.get_mut(&msg.sender)) {
+            p.muted = muted;
+            p.last_heard = Instant::now();
+        }
+    }
+
+    /// A keepalive from a member: answer their ping, or note their answer to ours
+    pub(crate) fn handle_call_keepalive(&mut self, msg: &PlainMessage, ping: bool, fx: &mut Vec<Effect>) {
+        if !self.in_our_call(msg) {
+            return;
+        }
+        if let Some(p) = self.active_call.as_mut().and_then(|c| c.participants.get_mut(&msg.sender)) {
+            p.last_heard = Instant::now();
+        }
+        if ping {
+            let pong = self.call_msg(PlainMessage::call_keepalive(self.own_id.clone(), false));
+            fx.push(Effect::Send(OutgoingMessage::Direct { target_id: msg.sender.clone(), message: pong }));
+        }
+    }
+
+    /// Ping members who've gone quiet (run from housekeeping)
+    pub(crate) fn check_call_participants(&mut self) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        let Some(group_id) = self.call_group().map(str::to_string) else {
+            return fx;
+        };
+        let own_id = self.own_id.clone();
+        let Some(ref mut call) = self.active_call else {
+            return fx;
+        };
+        let mut quiet: Vec<String> = Vec::new();
+        for (peer_id, p) in call.participants.iter_mut().filter(|(_, p)| !p.on_hold) {
+            let due = p.last_ping.is_none_or(|at| at.elapsed() >= PING_AFTER);
+            if p.last_heard.elapsed() >= PING_AFTER && due {
+                p.last_ping = Some(Instant::now());
+                quiet.push(peer_id.clone());
+            }
+        }
+        quiet.sort();
+        for peer_id in quiet {
+            let ping = PlainMessage { group_id: Some(group_id.clone()), ..PlainMessage::call_keepalive(own_id.clone(), true) };
+            fx.push(Effect::Send(OutgoingMessage::Direct { target_id: peer_id, message: ping }));
+        }
+        fx
+    }
+
+    /// Members of the group call by name, for the sidebar
+    pub(crate) fn call_participants(&self) -> Vec<(String, &Participant)> {
+        let Some(ref call) = self.active_call else {
+            return Vec::new();
+        };
+        let mut members: Vec<(String, &Participant)> = call.participants.iter()
+            .map(|(id, p)| (self.get_peer_display_name(id), p))
+            .collect();
+        members.sort_by(|a, b| a.0.cmp(&b.0));
+        members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::CallSalt;
+    use crate::tui::types::GroupInfo;
+
+    #[test]
+    fn test_group_call_participants() {
+        let (alice, bob) = ("a".repeat(32), "b".repeat(32));
+        let mut state = ChatState::new("c".repeat(32), None, vec![0; 32]);
+        state.groups.insert("g1".to_string(), GroupInfo { name: "g".to_string(), members: vec![alice.clone(), bob.clone()], join_token: None });
+        let group = |mut msg: PlainMessage| {
+            msg.group_id = Some("g1".to_string());
+            msg
+        };
+
+        // Alice calls and we join, then mute: Bob hears that when he joins after us
+        state.ingest_message(group(PlainMessage::call_request(alice.clone(), CallSalt::generate("call1".to_string()))));
+        state.handle_command("/accept-call");
+        assert_eq!(state.call_participants().len(), 1);
+        assert!(matches!(&state.handle_command("/mute")[..], [Effect::Send(OutgoingMessage::Group { message, .. })] if message.call_muted == Some(true)));
+        let accept = group(PlainMessage { call_salt: Some(CallSalt::generate("call1".to_string())), ..PlainMessage::call_accept(bob.clone(), true) });
+        let fx = state.ingest_message(accept);
+        assert!(fx.iter().any(|e| matches!(e, Effect::Send(OutgoingMessage::Direct { target_id, message }) if *target_id == bob && message.call_muted == Some(true))));
+
+        // Loud frames light Alice up; Bob says he's muted
+        assert!(state.note_call_audio(&alice, 0.3));
+        assert!(!state.note_call_audio(&alice, 0.3));
+        state.ingest_message(group(PlainMessage::call_muted(bob.clone(), true)));
+        let call = state.active_call.as_ref().unwrap();
+        assert!(call.participants[&alice].speaking() && call.participants[&bob].muted);
+
+        // Bob goes quiet: pinged once, then shown as lost; his answer brings him back
+        let p = state.active_call.as_mut().unwrap().participants.get_mut(&bob).unwrap();
+        p.last_heard -= LOST_AFTER;
+        let pings = state.check_call_participants();
+        assert!(matches!(&pings[..], [Effect::Send(OutgoingMessage::Direct { target_id, message })] if *target_id == bob && message.call_keepalive == Some(true)));
+        assert!(state.check_call_participants().is_empty());
+        assert!(state.active_call.as_ref().unwrap().participants[&bob].lost());
+        state.ingest_message(group(PlainMessage::call_keepalive(bob.clone(), false)));
+        assert!(!state.active_call.as_ref().unwrap().participants[&bob].lost());
+
+        // Alice answers our pings, and leaving takes her off the list
+        let fx = state.ingest_message(group(PlainMessage::call_keepalive(alice.clone(), true)));
+        assert!(matches!(&fx[..], [Effect::Send(OutgoingMessage::Direct { message, .. })] if message.call_keepalive == Some(false)));
+        state.ingest_message(group(PlainMessage::call_hangup(alice.clone())));
+        assert_eq!(state.call_participants().len(), 1);
+    }
+}
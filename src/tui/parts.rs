@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+use crate::client::OutgoingMessage;
+use crate::protocol::PlainMessage;
+
+use super::fences::has_folded_code;
+use super::types::PartialMessage;
+use super::state::{ChatState, Effect};
+
+/// A split message whose remaining parts haven't arrived by then is dropped
+const PART_TIMEOUT: Duration = Duration::from_secs(60);
+/// Split messages being reassembled at once; parts of any further ones are dropped
+const MAX_PARTIAL_MESSAGES: usize = 32;
+/// Long messages render as their first this-many lines until /expand
+pub(crate) const COLLAPSED_LINES: usize = 40;
+/// A message with more characters than this counts as long even on few lines
+const COLLAPSED_CHARS: usize = 4000;
+
+/// Whether a message is long enough to render collapsed
+pub(crate) fn is_long(content: &str) -> bool {
+    content.lines().count() > COLLAPSED_LINES || content.chars().count() > COLLAPSED_CHARS
+}
+
+/// Queue a chat message, split into parts first if it's too long to send as one
+pub(crate) fn send_in_parts(msg: PlainMessage, fx: &mut Vec<Effect>, wrap: impl Fn(PlainMessage) -> OutgoingMessage) {
+    for part in msg.into_parts() {
+        fx.push(Effect::Send(wrap(part)));
+    }
+}
+
+impl ChatState {
+    /// Store one part of a split message. Returns the whole message once its last part is in.
+    pub(crate) fn collect_part(&mut self, msg: PlainMessage) -> Option<PlainMessage> {
+        let (Some(index), Some(total), Some(group_id)) = (msg.part_index, msg.part_total, msg.part_group_id.clone()) else {
+            return Some(msg);
+        };
+        let key = (msg.sender.clone(), group_id);
+        if !self.partial_messages.contains_key(&key) && self.partial_messages.len() >= MAX_PARTIAL_MESSAGES {
+            self.status = format!("⚠️  Dropped part of a long message from {}", self.get_peer_display_name(&msg.sender));
+            return None;
+        }
+
+        let partial = self.partial_messages.entry(key.clone()).or_insert_with(|| PartialMessage {
+            parts: vec![None; total as usize],
+            started: Instant::now(),
+        });
+        // Sanitized already, so index < total; a total that changes mid-message is bogus
+        if partial.parts.len() != total as usize {
+            self.partial_messages.remove(&key);
+            return None;
+        }
+        partial.parts[index as usize] = Some(msg.content.clone());
+        if partial.parts.iter().any(Option::is_none) {
+            return None;
+        }
+
+        let partial = self.partial_messages.remove(&key)?;
+        Some(PlainMessage {
+            content: partial.parts.into_iter().flatten().collect(),
+            part_index: None,
+            part_total: None,
+            part_group_id: None,
+            ..msg
+        })
+    }
+
+    /// Give up on split messages that stopped arriving. Returns true if any were dropped.
+    pub(crate) fn expire_partial_messages(&mut self) -> bool {
+        let before = self.partial_messages.len();
+        self.partial_messages.retain(|_, partial| partial.started.elapsed() < PART_TIMEOUT);
+        self.partial_messages.len() != before
+    }
+
+    /// Handle /expand [n] — show a collapsed message in full (or fold it again).
+    /// `n` is the number shown under the message; without it, the latest collapsed one.
+    pub(crate) fn handle_expand_command(&mut self, args: &[&str]) {
+        let tab = self.tabs[self.active_tab].clone();
+        let Some(messages) = self.messages.get_mut(&tab) else {
+            return;
+        };
+        let index = match args.first() {
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(n) if (1..=messages.len()).contains(&n) => n - 1,
+                _ => {
+                    self.status = format!("No message {} here", arg);
+                    return;
+                }
+            },
+            None => match messages.iter().rposition(|m| !m.system && (is_long(&m.content) || has_folded_code(&m.content))) {
+                Some(index) => index,
+                None => {
+                    self.status = "Nothing to expand".to_string();
+                    return;
+                }
+            },
+        };
+
+        // Expansion is tracked by message id; notices without one get a local id
+        let msg = &mut messages[index];
+        let id = msg.message_id.get_or_insert_with(PlainMessage::generate_id).clone();
+        if self.expanded.remove(&id) {
+            self.status = format!("Collapsed message {}", index + 1);
+        } else {
+            self.expanded.insert(id);
+            self.status = format!("Expanded message {} — /expand {} to fold it again", index + 1, index + 1);
+        }
+    }
+}
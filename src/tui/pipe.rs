@@ -0,0 +1,373 @@
+//! /pipe: follow a file or named pipe and post what's written to it into a tab, so
+//! `make 2>&1 > /tmp/build.fifo` or a log shows up live for the whole group. Lines
+//! are read on a task, gathered into batches and sent as ordinary messages from us.
+//! A chatty source is capped at PIPE_MAX_PER_MINUTE messages; the lines dropped past
+//! that are summed up in one "…suppressed" message once there's room again.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::util::expand_path;
+
+use super::state::{ChatState, Effect};
+use super::types::Tab;
+
+/// A batch goes out once it has this many lines...
+const BATCH_LINES: usize = 10;
+/// ...or this long after its first line arrived
+const BATCH_WINDOW: Duration = Duration::from_secs(1);
+/// Most messages a pipe posts in any minute
+const PIPE_MAX_PER_MINUTE: usize = 20;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Longer lines are cut here
+const MAX_LINE_CHARS: usize = 1000;
+/// How often the end of a file (or a pipe with no writer) is checked for more
+const FOLLOW_POLL: Duration = Duration::from_millis(250);
+
+/// A /pipe in progress, as the chat sees it
+pub(crate) struct Pipe {
+    pub path: PathBuf,
+    /// Where its lines are posted
+    pub tab: Tab,
+    pub label: Option<String>,
+    /// When each recent message went out, oldest first
+    sent: VecDeque<Instant>,
+    /// Lines dropped by the rate limit since the last summary
+    suppressed: usize,
+}
+
+/// What the reader task reports
+pub(crate) enum PipeEvent {
+    Lines(Vec<String>),
+    /// The source couldn't be opened or read
+    Failed(String),
+}
+
+/// The reader task: dropping this stops it, even if it's still waiting for a writer
+pub(crate) struct PipeReader {
+    stop: CancellationToken,
+    events: mpsc::Receiver<PipeEvent>,
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+impl ChatState {
+    /// Handle /pipe <path> [label] and /pipe stop
+    pub(crate) fn handle_pipe_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        match args {
+            [] => {
+                self.status = match self.pipe {
+                    Some(ref pipe) => format!("Piping {} into {} — /pipe stop to end it", pipe.path.display(), self.get_tab_name(&pipe.tab)),
+                    None => "Usage: /pipe <path> [label], /pipe stop".to_string(),
+                };
+            }
+            ["stop"] => match self.pipe.take() {
+                Some(pipe) => {
+                    fx.push(Effect::StopPipe);
+                    self.status = format!("Stopped piping {}", pipe.path.display());
+                }
+                None => self.status = "Nothing is being piped".to_string(),
+            },
+            [path, label @ ..] => {
+                let path = expand_path(path);
+                let label = (!label.is_empty()).then(|| label.join(" "));
+                let tab = self.tabs[self.active_tab].clone();
+                self.status = format!("Piping {} into {} — /pipe stop to end it", path.display(), self.get_tab_name(&tab));
+                fx.push(Effect::StartPipe(path.clone()));
+                self.pipe = Some(Pipe { path, tab, label, sent: VecDeque::new(), suppressed: 0 });
+            }
+        }
+    }
+
+    /// Post a batch from the pipe, unless the rate limit says to count it instead
+    pub(crate) fn pipe_lines(&mut self, lines: Vec<String>, now: Instant) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        let Some(mut pipe) = self.pipe.take() else {
+            return fx;
+        };
+        // Its tab was closed: nowhere left to post
+        if !self.tabs.contains(&pipe.tab) {
+            fx.push(Effect::StopPipe);
+            self.status = format!("Stopped piping {}: its tab was closed", pipe.path.display());
+            return fx;
+        }
+        let lines: Vec<String> = lines.iter().map(|line| clean_line(line)).collect();
+        if lines.iter().any(|line| !line.trim().is_empty()) {
+            self.flush_suppressed(&mut pipe, now, &mut fx);
+            if take_slot(&mut pipe.sent, now) {
+                let text = labelled(pipe.label.as_deref(), &lines.join("\n"));
+                self.send_chat_to(&pipe.tab, text, false, &mut fx);
+            } else {
+                pipe.suppressed += lines.len();
+            }
+        }
+        self.pipe = Some(pipe);
+        fx
+    }
+
+    /// Post the "…suppressed" summary if lines were dropped and there's room for it now
+    pub(crate) fn check_pipe(&mut self, now: Instant) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        if let Some(mut pipe) = self.pipe.take() {
+            if self.tabs.contains(&pipe.tab) {
+                self.flush_suppressed(&mut pipe, now, &mut fx);
+            }
+            self.pipe = Some(pipe);
+        }
+        fx
+    }
+
+    fn flush_suppressed(&mut self, pipe: &mut Pipe, now: Instant, fx: &mut Vec<Effect>) {
+        if pipe.suppressed > 0 && take_slot(&mut pipe.sent, now) {
+            let text = labelled(pipe.label.as_deref(), &format!("…suppressed {} lines", pipe.suppressed));
+            self.send_chat_to(&pipe.tab, text, false, fx);
+            pipe.suppressed = 0;
+        }
+    }
+
+    /// The reader gave up: the pipe is over
+    pub(crate) fn pipe_failed(&mut self, reason: &str) {
+        if let Some(pipe) = self.pipe.take() {
+            self.status = format!("⚠️  /pipe {}: {}", pipe.path.display(), reason);
+        }
+    }
+}
+
+/// Room for one more message in the last minute? Takes it if so.
+fn take_slot(sent: &mut VecDeque<Instant>, now: Instant) -> bool {
+    while sent.front().is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW) {
+        sent.pop_front();
+    }
+    if sent.len() >= PIPE_MAX_PER_MINUTE {
+        return false;
+    }
+    sent.push_back(now);
+    true
+}
+
+fn labelled(label: Option<&str>, text: &str) -> String {
+    match label {
+        Some(label) => format!("[{}] {}", label, text),
+        None => text.to_string(),
+    }
+}
+
+/// A line as it should appear in chat: colours and other escape sequences removed,
+/// tabs kept, other control characters dropped, and cut at MAX_LINE_CHARS
+pub(crate) fn clean_line(line: &str) -> String {
+    let mut out = String::new();
+    let mut kept = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates up to a final byte in @..~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC (window titles, hyperlinks): up to BEL or ESC \
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Two-character escapes: drop the second one too
+                _ => {}
+            },
+            c if c.is_control() && c != '\t' => {}
+            c => {
+                out.push(c);
+                kept += 1;
+            }
+        }
+        if kept >= MAX_LINE_CHARS {
+            break;
+        }
+    }
+    out
+}
+
+/// Start following `path` on a task
+pub(crate) fn spawn_reader(path: PathBuf) -> PipeReader {
+    let stop = CancellationToken::new();
+    let (tx, events) = mpsc::channel(16);
+    let stopped = stop.clone();
+    tokio::spawn(async move {
+        let result = tokio::select! {
+            _ = stopped.cancelled() => Ok(()),
+            result = follow(&path, &tx) => result,
+        };
+        if let Err(e) = result {
+            let _ = tx.send(PipeEvent::Failed(format!("{:#}", e))).await;
+        }
+    });
+    PipeReader { stop, events }
+}
+
+/// The next thing the reader has to say; never resolves while nothing is piped
+pub(crate) async fn next_event(pipe: &mut Option<PipeReader>) -> PipeEvent {
+    match pipe {
+        Some(reader) => reader.events.recv().await.unwrap_or_else(|| PipeEvent::Failed("reader stopped".to_string())),
+        None => std::future::pending().await,
+    }
+}
+
+/// Read `path` from the start and keep following it, sending batches of lines until
+/// the chat stops listening
+async fn follow(path: &Path, tx: &mpsc::Sender<PipeEvent>) -> Result<()> {
+    let mut reader = BufReader::new(open(path).await?);
+    let mut buf = Vec::new();
+    let mut batch = Vec::new();
+    let mut deadline = None;
+    loop {
+        tokio::select! {
+            line = next_line(&mut reader, &mut buf) => {
+                if batch.is_empty() {
+                    deadline = Some(tokio::time::Instant::now() + BATCH_WINDOW);
+                }
+                batch.push(line.with_context(|| format!("couldn't read {}", path.display()))?);
+                if batch.len() < BATCH_LINES {
+                    continue;
+                }
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {}
+        }
+        deadline = None;
+        if tx.send(PipeEvent::Lines(std::mem::take(&mut batch))).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Opened without blocking: a named pipe nobody writes to yet mustn't hold up the task
+/// (or quitting)
+async fn open(path: &Path) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+    let metadata = tokio::fs::metadata(path).await.with_context(|| format!("can't open {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if metadata.file_type().is_fifo() {
+            let receiver = tokio::net::unix::pipe::OpenOptions::new()
+                .open_receiver(path)
+                .with_context(|| format!("can't open {}", path.display()))?;
+            return Ok(Box::new(receiver));
+        }
+    }
+    if !metadata.is_file() {
+        bail!("{} is not a file or named pipe", path.display());
+    }
+    let file = tokio::fs::File::open(path).await.with_context(|| format!("can't open {}", path.display()))?;
+    Ok(Box::new(file))
+}
+
+/// The next whole line. At the end of the file, or while a pipe has no writer, waits
+/// for more like `tail -f`. Safe to cancel: a partly read line stays in `buf`.
+async fn next_line(reader: &mut BufReader<Box<dyn AsyncRead + Unpin + Send>>, buf: &mut Vec<u8>) -> std::io::Result<String> {
+    loop {
+        let read = reader.read_until(b'\n', buf).await?;
+        // A line that never ends is sent in pieces
+        if buf.ends_with(b"\n") || buf.len() >= MAX_LINE_CHARS * 4 {
+            let line = String::from_utf8_lossy(buf).trim_end_matches(['\n', '\r']).to_string();
+            buf.clear();
+            return Ok(line);
+        }
+        if read == 0 {
+            tokio::time::sleep(FOLLOW_POLL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::OutgoingMessage;
+
+    fn posted(fx: &[Effect]) -> Vec<String> {
+        fx.iter().filter_map(|e| match e {
+            Effect::Send(OutgoingMessage::Global(m)) => Some(m.content.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_clean_line_strips_escapes() {
+        assert_eq!(clean_line("\x1b[1;31merror\x1b[0m: boom"), "error: boom");
+        assert_eq!(clean_line("\x1b]8;;http://x\x1b\\link\x1b]8;;\x07 done\r"), "link done");
+        assert_eq!(clean_line("a\tb\x07c"), "a\tbc");
+        assert_eq!(clean_line(&"x".repeat(5000)).len(), MAX_LINE_CHARS);
+    }
+
+    #[test]
+    fn test_pipe_posts_batches_and_summarises_a_flood() {
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let mut fx = Vec::new();
+        state.handle_pipe_command(&["/tmp/build.fifo", "build"], &mut fx);
+        assert!(matches!(&fx[..], [Effect::StartPipe(_)]));
+
+        let start = Instant::now();
+        let fx = state.pipe_lines(vec!["\x1b[32mok\x1b[0m".to_string(), "next".to_string()], start);
+        assert_eq!(posted(&fx), ["[build] ok\nnext"]);
+        for _ in 1..PIPE_MAX_PER_MINUTE {
+            state.pipe_lines(vec!["line".to_string()], start);
+        }
+        // Over the limit: counted, not sent
+        for _ in 0..12 {
+            assert!(posted(&state.pipe_lines(vec!["spam".to_string(); 10], start)).is_empty());
+        }
+        assert!(state.check_pipe(start + Duration::from_secs(30)).is_empty());
+        let fx = state.check_pipe(start + RATE_WINDOW);
+        assert_eq!(posted(&fx), ["[build] …suppressed 120 lines"]);
+
+        let mut fx = Vec::new();
+        state.handle_pipe_command(&["stop"], &mut fx);
+        assert!(matches!(&fx[..], [Effect::StopPipe]) && state.pipe.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reader_follows_a_file_and_stops_on_an_unwritten_fifo() {
+        use std::io::Write;
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("build.log");
+        std::fs::write(&log, "one\ntwo\n").unwrap();
+        let mut reader = Some(spawn_reader(log.clone()));
+        let PipeEvent::Lines(lines) = next_event(&mut reader).await else { panic!("read failed") };
+        assert_eq!(lines, ["one", "two"]);
+        // Appended later, like tail -f
+        std::fs::OpenOptions::new().append(true).open(&log).unwrap().write_all(b"three\n").unwrap();
+        let PipeEvent::Lines(lines) = next_event(&mut reader).await else { panic!("read failed") };
+        assert_eq!(lines, ["three"]);
+
+        let PipeEvent::Failed(reason) = next_event(&mut Some(spawn_reader(dir.path().join("missing")))).await else {
+            panic!("opened a missing file")
+        };
+        assert!(reason.contains("can't open"), "{}", reason);
+
+        // Nobody ever writes to it: stopping mustn't wait on a writer
+        #[cfg(unix)]
+        {
+            let fifo = dir.path().join("quiet.fifo");
+            assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+            let mut reader = spawn_reader(fifo);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            reader.stop.cancel();
+            let ended = tokio::time::timeout(Duration::from_secs(2), reader.events.recv()).await;
+            assert!(matches!(ended, Ok(None)), "the reader task is still running");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! `--plain`: a line-at-a-time front end for screen readers and terminals that can't
+//! take a full-screen UI. Nothing is redrawn: each message is printed once as it
+//! arrives ("[14:02] alice: hi"), status and system lines come out in brackets, and
+//! stdin is read a line at a time through the same commands as the full-screen UI.
+//! /tab moves between conversations. Calls need the full-screen UI.
+
+use std::io::{self, Write};
+use std::time::Instant;
+
+use anyhow::Result;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+
+use crate::audio::AudioSupport;
+use crate::client::{ClientStatus, OutgoingSender, PeerUpdate};
+use crate::protocol::PlainMessage;
+
+use super::pipe::{self, PipeEvent};
+use super::state::ChatState;
+use super::types::Tab;
+use super::{termination_signal, ChatUI, HOUSEKEEPING_INTERVAL};
+
+/// Why calls are refused in this mode
+const NO_CALLS: &str = "not in --plain mode — start wsp chat without --plain for calls";
+
+impl ChatState {
+    /// How `m`, just added to `tab`, reads as a line of its own. None for our own chat,
+    /// which was typed right above it.
+    pub(crate) fn plain_text(&self, tab: &Tab, m: &PlainMessage) -> Option<String> {
+        if m.sender == self.own_id && !m.system {
+            return None;
+        }
+        let time = chrono::DateTime::from_timestamp(m.timestamp, 0)
+            .map(|dt| dt.with_timezone(&chrono::Local).format("%H:%M").to_string())
+            .unwrap_or_default();
+        let place = match tab {
+            Tab::Global => String::new(),
+            Tab::DirectMessage(_) => format!("(DM {}) ", self.get_tab_name(tab)),
+            Tab::Group(_) => format!("({}) ", self.get_tab_name(tab)),
+        };
+        if m.system {
+            // Lists (/help, /whois, ...) keep their own lines under the bracketed first one
+            let text = self.event_text(m);
+            let (first, rest) = text.split_once('\n').unwrap_or((&text, ""));
+            let mut line = format!("[{}] {}[{}]", time, place, first);
+            for more in rest.lines() {
+                line.push('\n');
+                line.push_str(more);
+            }
+            return Some(line);
+        }
+        let delayed = if m.delayed { " (delayed)" } else { "" };
+        Some(format!("[{}] {}{}{}", time, place, self.attributed_text(m, &m.content), delayed))
+    }
+
+    /// Handle /tab [n|name]: list the open conversations, or switch to one
+    pub(crate) fn handle_tab_command(&mut self, args: &[&str]) {
+        let Some(target) = args.first() else {
+            let mut lines = vec!["Open conversations — /tab <n|name> to switch:".to_string()];
+            for (i, tab) in self.tabs.iter().enumerate() {
+                let here = if i == self.active_tab { " (current)" } else { "" };
+                let unread = match self.unread.get(tab) {
+                    Some(n) if *n > 0 => format!(", {} unread", n),
+                    _ => String::new(),
+                };
+                lines.push(format!("  {}. {}{}{}", i + 1, self.get_tab_name(tab), here, unread));
+            }
+            let tab = self.tabs[self.active_tab].clone();
+            self.add_system_message(&tab, lines.join("\n"));
+            return;
+        };
+        let wanted = target.trim_start_matches('#');
+        let found = match target.parse::<usize>() {
+            Ok(n) => (n >= 1 && n <= self.tabs.len()).then(|| n - 1),
+            Err(_) => self.tabs.iter().position(|tab| self.get_tab_name(tab).trim_start_matches('#').eq_ignore_ascii_case(wanted)),
+        };
+        match found {
+            Some(idx) => {
+                self.set_active_tab(idx);
+                self.status = format!("Now in {}", self.get_tab_name(&self.tabs[idx]));
+            }
+            None => self.status = format!("No conversation {} — /tab lists them", target),
+        }
+    }
+}
+
+/// What `--plain` has already printed about the connection and status line
+#[derive(Default)]
+struct Shown {
+    connection: String,
+    status: String,
+}
+
+impl ChatUI {
+    /// Run the line-at-a-time front end (`--plain`) over the same channels as `run`
+    pub async fn run_plain(
+        &mut self,
+        msg_tx: OutgoingSender,
+        mut incoming_rx: mpsc::UnboundedReceiver<PlainMessage>,
+        mut status_rx: mpsc::UnboundedReceiver<ClientStatus>,
+        mut peer_update_rx: mpsc::UnboundedReceiver<Vec<PeerUpdate>>,
+        mut audio_in_rx: mpsc::UnboundedReceiver<(String, String, Vec<u8>)>,
+    ) -> Result<()> {
+        self.state.audio_support = AudioSupport::Unavailable(NO_CALLS.to_string());
+        self.state.transcript = Some(Vec::new());
+        let mut out = io::stdout();
+        writeln!(
+            out,
+            "[Plain mode: you're in {} — /help lists commands, /tab switches conversation, /quit or Ctrl+D leaves]",
+            self.state.get_tab_name(&self.state.tabs[self.state.active_tab])
+        )?;
+
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        let mut read_receipt_timer = Instant::now();
+        let mut housekeeping = tokio::time::interval(HOUSEKEEPING_INTERVAL);
+        let mut shown = Shown::default();
+        // Without raw mode, Ctrl+C is a signal rather than a key
+        let terminated = termination_signal();
+        tokio::pin!(terminated);
+        let interrupted = tokio::signal::ctrl_c();
+        tokio::pin!(interrupted);
+
+        loop {
+            self.print_news(&mut out, &mut shown)?;
+            let reorder_due = self.state.reorder_deadline().map(tokio::time::Instant::from_std);
+
+            tokio::select! {
+                line = lines.next_line() => {
+                    match line? {
+                        Some(line) if line.trim() == "/quit" => break,
+                        Some(line) => self.plain_input(&line, &msg_tx),
+                        None => break,
+                    }
+                }
+                _ = &mut terminated => break,
+                _ = &mut interrupted => break,
+                Some(msg) = incoming_rx.recv() => {
+                    let effects = self.state.ingest_message(msg);
+                    self.apply_effects(effects, &msg_tx);
+                }
+                Some(status) = status_rx.recv() => self.handle_status(status, &msg_tx),
+                Some(updates) = peer_update_rx.recv() => {
+                    let effects = self.state.apply_peer_updates(updates);
+                    self.apply_effects(effects, &msg_tx);
+                }
+                event = pipe::next_event(&mut self.pipe_reader) => {
+                    match event {
+                        PipeEvent::Lines(lines) => {
+                            let effects = self.state.pipe_lines(lines, Instant::now());
+                            self.apply_effects(effects, &msg_tx);
+                        }
+                        PipeEvent::Failed(reason) => {
+                            self.pipe_reader = None;
+                            self.state.pipe_failed(&reason);
+                        }
+                    }
+                }
+                // No calls here, so no call audio to play
+                Some(_) = audio_in_rx.recv() => {}
+                _ = housekeeping.tick() => {
+                    self.housekeeping(&mut read_receipt_timer, &msg_tx);
+                }
+                _ = tokio::time::sleep_until(reorder_due.unwrap_or_else(tokio::time::Instant::now)), if reorder_due.is_some() => {
+                    let effects = self.state.release_overdue(Instant::now());
+                    self.apply_effects(effects, &msg_tx);
+                }
+            }
+        }
+
+        self.shutdown(&msg_tx).await;
+        self.print_news(&mut out, &mut shown)?;
+        Ok(())
+    }
+
+    /// One line typed at the prompt: a command or a message for the current conversation
+    fn plain_input(&mut self, line: &str, msg_tx: &OutgoingSender) {
+        let effects = self.state.note_activity();
+        self.apply_effects(effects, msg_tx);
+        if line.trim().is_empty() {
+            return;
+        }
+        // Whatever the command says is news, even if it said the same last time
+        self.state.status.clear();
+        let effects = self.state.handle_command(line);
+        self.apply_effects(effects, msg_tx);
+        // A held Global send goes out when it's typed again; there's no input box to refill
+        self.state.held_input = None;
+        if std::mem::take(&mut self.show_keys) {
+            self.state.status = "No key bindings in plain mode: everything is a /command (/help lists them)".to_string();
+        }
+    }
+
+    /// Print what's happened since last time: connection changes, new messages, and
+    /// the status line if it changed
+    fn print_news(&mut self, out: &mut impl Write, shown: &mut Shown) -> io::Result<()> {
+        let connection = self.connection.to_string();
+        if connection != shown.connection {
+            writeln!(out, "[{}]", connection)?;
+            shown.connection = connection;
+        }
+        let news = self.state.transcript.as_mut().map(std::mem::take).unwrap_or_default();
+        for (tab, m) in news {
+            if let Some(line) = self.state.plain_text(&tab, &m) {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        for line in self.qr.take().unwrap_or_default() {
+            writeln!(out, "{}", line)?;
+        }
+        if self.state.status != shown.status {
+            if !self.state.status.is_empty() {
+                writeln!(out, "[{}]", self.state.status)?;
+            }
+            shown.status = self.state.status.clone();
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::PeerDisplay;
+    use crate::tui::types::GroupInfo;
+
+    #[test]
+    fn test_plain_lines_and_tab_switching() {
+        let alice = "aa".repeat(16);
+        let mut ui = ChatUI::new("me".repeat(16), None, vec![0; 32]);
+        ui.state.transcript = Some(Vec::new());
+        ui.state.audio_support = AudioSupport::Unavailable(NO_CALLS.to_string());
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        ui.state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+        ui.state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members: vec![alice.clone()], join_token: None });
+        ui.state.ensure_tab(&Tab::Group("g1".to_string()));
+        ui.state.ingest_message(PlainMessage::new(alice.clone(), "hi".to_string()));
+        ui.state.ingest_message(PlainMessage::group(alice.clone(), "standup?".to_string(), "g1".to_string()));
+
+        let mut out = Vec::new();
+        let mut shown = Shown { connection: ui.connection.to_string(), ..Shown::default() };
+        ui.print_news(&mut out, &mut shown).unwrap();
+        let printed = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = printed.lines().filter(|l| !l.contains("[alice has joined")).collect();
+        let time = chrono::Local::now().format("%H:%M").to_string();
+        assert!(lines.contains(&format!("[{}] alice: hi", time).as_str()), "{}", printed);
+        assert!(lines.contains(&format!("[{}] (#team) alice: standup?", time).as_str()), "{}", printed);
+
+        // Commands work as in the full-screen UI, and print in brackets
+        ui.state.handle_command("/tab team");
+        assert_eq!(ui.state.tabs[ui.state.active_tab], Tab::Group("g1".to_string()));
+        ui.state.handle_command("/tab 9");
+        assert_eq!(ui.state.status, "No conversation 9 — /tab lists them");
+        ui.state.handle_command("/call");
+        let mut out = Vec::new();
+        ui.print_news(&mut out, &mut shown).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("[📵 Voice calls unavailable: {}]\n", NO_CALLS));
+
+        // What we send isn't echoed back
+        ui.state.handle_command("on my way");
+        assert!(ui.state.transcript.as_ref().unwrap().iter().all(|(tab, m)| ui.state.plain_text(tab, m).is_none()));
+    }
+}
@@ -0,0 +1,59 @@
+//! Peers the client counts as gone for now: they said goodbye, or went quiet and the
+//! relay says they aren't connected. They keep their names, verification and DM tabs,
+//! but the sidebar lists them apart under "recently seen", folded to a count until
+//! /recent opens it. Anything from them brings them back to the list above.
+
+use super::state::ChatState;
+
+impl ChatState {
+    /// Recently seen peers, by display name
+    pub(crate) fn recently_seen_peers(&self) -> Vec<String> {
+        let mut peers: Vec<(String, &String)> = self.recently_seen.iter()
+            .map(|id| (self.get_peer_display_name(id).to_lowercase(), id))
+            .collect();
+        peers.sort();
+        peers.into_iter().map(|(_, id)| id.clone()).collect()
+    }
+
+    /// Handle /recent: open or fold the sidebar's recently seen peers
+    pub(crate) fn handle_recent_command(&mut self) {
+        self.show_recently_seen = !self.show_recently_seen;
+        self.status = match (self.recently_seen.len(), self.show_recently_seen) {
+            (0, _) => "Nobody has gone since you connected".to_string(),
+            (n, true) => format!("Listing {} recently seen peer{} in the sidebar", n, if n == 1 { "" } else { "s" }),
+            (_, false) => "Recently seen peers folded away".to_string(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+
+    #[test]
+    fn test_gone_peers_move_to_recently_seen_and_back() {
+        let (alice, bob) = ("aa".repeat(16), "bb".repeat(16));
+        let display = |name: &str, key: u8| PeerDisplay { nickname: Some(name.to_string()), public_key: vec![key; 32] };
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.apply_peer_updates(vec![
+            PeerUpdate::Added(alice.clone(), display("alice", 1)),
+            PeerUpdate::Added(bob.clone(), display("bob", 2)),
+        ]);
+
+        // Gone for now: still known by name, just not online
+        let fx = state.apply_peer_updates(vec![PeerUpdate::Inactive(bob.clone(), display("bob", 2))]);
+        assert!(fx.is_empty());
+        assert_eq!(state.sidebar_peers(), [alice.as_str()]);
+        assert_eq!(state.recently_seen_peers(), [bob.as_str()]);
+        assert_eq!(state.get_peer_display_name(&bob), "bob");
+        state.handle_command("/recent");
+        assert_eq!(state.status, "Listing 1 recently seen peer in the sidebar");
+
+        // Back as soon as they're heard from, or forgotten
+        state.apply_peer_updates(vec![PeerUpdate::Changed(bob.clone(), display("bob", 2))]);
+        assert_eq!(state.sidebar_peers(), [alice.clone(), bob.clone()]);
+        state.apply_peer_updates(vec![PeerUpdate::Inactive(alice.clone(), display("alice", 1)), PeerUpdate::Removed(alice.clone())]);
+        assert!(state.recently_seen_peers().is_empty() && !state.peers.contains_key(&alice));
+    }
+}
@@ -6,10 +6,29 @@ use ratatui::{
     Frame,
 };
 
-use super::helpers::format_duration;
-use super::types::{CallType, ReadStatus, Tab};
+use crate::client::ConnectionState;
+use crate::protocol::ChatEvent;
+
+use super::fences::{layout, Row};
+use super::helpers::{format_duration, format_ttl};
+use super::participants::Participant;
+use super::parts::{is_long, COLLAPSED_LINES};
+use super::security::Security;
+use super::types::{CallType, KeyContext, ReadStatus, Tab, Verified, KEY_BINDINGS};
 use super::ChatUI;
 
+/// Seconds without a frame after we sent chat before the header says the link is quiet
+const QUIET_AFTER_SECS: u64 = 30;
+
+/// Background for messages that mention us
+const MENTION_STYLE: Style = Style::new().bg(Color::Indexed(58)).add_modifier(Modifier::BOLD);
+
+/// `inline code`
+const CODE_STYLE: Style = Style::new().fg(Color::Yellow).bg(Color::DarkGray);
+
+/// The lines of a ``` block
+const CODE_PANEL_STYLE: Style = Style::new().bg(Color::Indexed(236));
+
 impl ChatUI {
     /// Count display lines for input text (accounting for newlines and wrapping)
     pub(crate) fn count_input_lines(input: &[char], inner_width: usize) -> usize {
@@ -134,7 +153,7 @@ impl ChatUI {
                 }
                 if let Some(end_pos) = end {
                     let code_text: String = chars[start..end_pos].iter().collect();
-                    spans.push(Span::styled(code_text, Style::default().fg(Color::Yellow).bg(Color::DarkGray)));
+                    spans.push(Span::styled(code_text, CODE_STYLE));
                     i = end_pos + 1;
                 } else {
                     current.push(chars[i]);
@@ -157,6 +176,27 @@ impl ChatUI {
         spans
     }
 
+    /// A row of a message, styled: markdown-lite for prose, code as it is on a dim panel
+    fn styled_row(row: &Row) -> Vec<Span<'static>> {
+        match row {
+            Row::Prose(line) => Self::parse_markdown(line),
+            Row::Code(line) => vec![Span::styled(line.clone(), CODE_PANEL_STYLE)],
+            Row::Border(line) => vec![Span::styled(line.clone(), Style::default().fg(Color::DarkGray))],
+            Row::Folded(line) => vec![Span::styled(line.clone(), CODE_PANEL_STYLE.fg(Color::DarkGray).add_modifier(Modifier::ITALIC))],
+        }
+    }
+
+    /// A message's rows at `max_width`: prose word-wrapped, ``` blocks as panels
+    /// (folded unless `fold` is None)
+    fn wrap_message(text: &str, max_width: usize, fold: Option<usize>) -> Vec<Row> {
+        layout(text, max_width, fold).into_iter()
+            .flat_map(|row| match row {
+                Row::Prose(line) => Self::word_wrap(&line, max_width).into_iter().map(Row::Prose).collect(),
+                row => vec![row],
+            })
+            .collect()
+    }
+
     /// Word-wrap text to fit within a given width, returning wrapped lines
     fn word_wrap(text: &str, max_width: usize) -> Vec<String> {
         if max_width == 0 {
@@ -223,20 +263,20 @@ impl ChatUI {
 
     /// Get typing indicator text for the current tab
     fn get_typing_text(&self) -> Option<String> {
-        let current_tab = &self.tabs[self.active_tab];
-        let typing_names: Vec<String> = self.typing_peers.iter()
+        let current_tab = &self.state.tabs[self.state.active_tab];
+        let typing_names: Vec<String> = self.state.typing_peers.iter()
             .filter(|(peer_id, _)| {
                 match current_tab {
                     Tab::Global => true,
                     Tab::DirectMessage(dm_peer) => *peer_id == dm_peer,
                     Tab::Group(group_id) => {
-                        self.groups.get(group_id)
+                        self.state.groups.get(group_id)
                             .map(|g| g.members.contains(peer_id))
                             .unwrap_or(false)
                     }
                 }
             })
-            .map(|(peer_id, _)| self.get_peer_display_name(peer_id))
+            .map(|(peer_id, _)| self.state.get_peer_display_name(peer_id))
             .collect();
 
         if typing_names.is_empty() {
@@ -268,15 +308,19 @@ impl ChatUI {
         let input_lines = Self::count_input_lines(&self.input, inner_width);
         let input_height = (input_lines as u16) + 2; // +2 for borders
 
-        // Check for typing indicator
+        // Check for typing indicator, and whether the DM peer is away
         let typing_text = self.get_typing_text();
         let typing_height: u16 = if typing_text.is_some() { 1 } else { 0 };
+        let away_text = self.state.away_line();
+        let away_height: u16 = if away_text.is_some() { 1 } else { 0 };
 
         let left_chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
                 Constraint::Length(4),  // Header needs 4: border + 2 content lines + border
+                Constraint::Length(1),
                 Constraint::Min(1),
+                Constraint::Length(away_height),
                 Constraint::Length(typing_height),
                 Constraint::Length(input_height),
                 Constraint::Length(3),
@@ -284,46 +328,125 @@ impl ChatUI {
             .split(left_side);
 
         // Header
-        let nick_display = self.own_nickname.as_deref().unwrap_or("No nickname");
-        let mut header_line2 = vec![
+        let nick_display = self.state.own_nickname.as_deref().unwrap_or("No nickname");
+        let mut header_line2 = Vec::new();
+        if self.state.ephemeral {
+            header_line2.push(Span::styled(" EPHEMERAL ", Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD)));
+            header_line2.push(Span::raw(" "));
+        }
+        if let Some(ref profile) = self.state.profile {
+            header_line2.push(Span::styled(format!("[{}] ", profile), Style::default().fg(Color::Green).add_modifier(Modifier::BOLD)));
+        }
+        header_line2.extend([
             Span::raw("Your ID: "),
-            Span::styled(&self.own_id[..16.min(self.own_id.len())], Style::default().fg(Color::Yellow)),
+            Span::styled(&self.state.own_id[..16.min(self.state.own_id.len())], Style::default().fg(Color::Yellow)),
+            Span::raw(" | Key: "),
+            Span::styled(&self.state.own_fingerprint, Style::default().fg(Color::Cyan)),
             Span::raw(" | "),
             Span::styled(nick_display, Style::default().fg(Color::Magenta)),
-        ];
+        ]);
 
-        if let Some(ref call) = self.active_call {
-            let call_label = match &call.call_type {
-                CallType::Direct(peer_id) => self.get_peer_display_name(peer_id),
-                CallType::Group { group_id } => self.group_name(group_id),
+        if let Some(ref away) = self.state.away {
+            let label = match away.message {
+                Some(ref m) => format!("💤 Away: {}", m),
+                None if away.auto => "💤 Away (idle)".to_string(),
+                None => "💤 Away".to_string(),
             };
+            header_line2.push(Span::raw(" | "));
+            header_line2.push(Span::styled(label, Style::default().fg(Color::Yellow)));
+        }
+
+        if let Some(ref call) = self.state.active_call {
+            let call_label = self.state.call_label(call);
             let duration = chrono::Utc::now() - call.start_time;
             let duration_str = format_duration(duration);
-            let mute_icon = if call.muted { "🔇" } else { "🔊" };
-            let mute_hint = if call.muted { " [MUTED]" } else { "" };
+            let mute_icon = if call.mic.is_muted() { "🔇" } else { "🔊" };
+            let mute_hint = if call.mic.is_muted() { " [MUTED]" } else { "" };
+            let hold_hint = if call.held_by_peer { " [ON HOLD]" } else { "" };
+            header_line2.push(Span::raw(" | "));
+            header_line2.push(Span::styled(
+                format!("{} {} ({}){}{}", mute_icon, call_label, duration_str, mute_hint, hold_hint),
+                Style::default().fg(if call.mic.is_muted() { Color::Red } else { Color::Green }).add_modifier(Modifier::BOLD),
+            ));
+        }
+
+        if let Some(ref held) = self.state.held_call {
             header_line2.push(Span::raw(" | "));
             header_line2.push(Span::styled(
-                format!("{} {} ({}){}", mute_icon, call_label, duration_str, mute_hint),
-                Style::default().fg(if call.muted { Color::Red } else { Color::Green }).add_modifier(Modifier::BOLD),
+                format!("⏸ {} on hold", self.state.call_label(&held.call)),
+                Style::default().fg(Color::Yellow),
             ));
         }
 
+        if let Some(dnd) = self.state.dnd_label() {
+            header_line2.push(Span::raw(" | "));
+            header_line2.push(Span::styled(dnd, Style::default().fg(Color::Magenta)));
+        }
+
+        if let Some(low) = self.state.low_bandwidth_label() {
+            header_line2.push(Span::raw(" | "));
+            header_line2.push(Span::styled(low, Style::default().fg(Color::Yellow)));
+        }
+
+        if let Some(rate) = self.state.bandwidth_label(self.streaming.load(std::sync::atomic::Ordering::Relaxed) > 0) {
+            header_line2.push(Span::raw(" | "));
+            header_line2.push(Span::styled(rate, Style::default().fg(Color::Cyan)));
+        }
+
+        if let Some(presence) = self.state.presence_label(&self.state.tabs[self.state.active_tab]) {
+            header_line2.push(Span::raw(" | "));
+            header_line2.push(Span::styled(presence, Style::default().fg(Color::Green)));
+        }
+
         header_line2.push(Span::raw(" | "));
-        header_line2.push(Span::raw(&self.status));
+        header_line2.push(Span::raw(&self.state.status));
 
         let header = Paragraph::new(vec![
             Line::from(vec![
                 Span::styled("🔒 WSP v2", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
                 Span::raw(" | "),
                 Span::styled("E2EE Chat", Style::default().fg(Color::Green)),
+                Span::raw(" | "),
+                self.connection_span(),
             ]),
             Line::from(header_line2),
         ])
         .block(Block::default().borders(Borders::ALL).title("Status"));
         f.render_widget(header, left_chunks[0]);
 
+        // Encryption banner for the open tab, and who can read Global
+        let open_tab = &self.state.tabs[self.state.active_tab];
+        let banner = if self.state.global_refused(open_tab).is_some() {
+            Line::from(Span::styled(
+                " 📋 notices only — Global is off, chat in DMs and groups",
+                Style::default().fg(Color::DarkGray),
+            ))
+        } else {
+            let security = self.state.tab_security(open_tab);
+            let banner_color = match security {
+                Security::Encrypted { .. } => Color::Green,
+                Security::Partial { .. } => Color::Yellow,
+                Security::Blocked { .. } => Color::Red,
+            };
+            let mut spans = vec![Span::styled(format!(" {}", security.banner()), Style::default().fg(banner_color))];
+            if let Some(exposure) = self.state.global_exposure().filter(|_| *open_tab == Tab::Global) {
+                spans.push(Span::styled(format!(" · {}", exposure), Style::default().fg(Color::DarkGray)));
+            }
+            Line::from(spans)
+        };
+        f.render_widget(Paragraph::new(banner), left_chunks[1]);
+
         // Messages
-        self.render_messages(f, left_chunks[1]);
+        self.render_messages(f, left_chunks[2]);
+
+        // Away notice for the open DM
+        if let Some(ref away) = away_text {
+            let away_widget = Paragraph::new(Line::from(Span::styled(
+                format!(" ○ {}", away),
+                Style::default().fg(Color::Yellow).add_modifier(Modifier::ITALIC),
+            )));
+            f.render_widget(away_widget, left_chunks[3]);
+        }
 
         // Typing indicator
         if let Some(ref typing) = typing_text {
@@ -331,14 +454,14 @@ impl ChatUI {
                 format!(" ✍ {}", typing),
                 Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
             )));
-            f.render_widget(typing_widget, left_chunks[2]);
+            f.render_widget(typing_widget, left_chunks[4]);
         }
 
         // Input
         let input_text: String = self.input.iter().collect();
-        let current_tab_name = self.get_tab_name(&self.tabs[self.active_tab]);
-        let input_title = if self.active_call.is_some() {
-            let mute_status = if self.active_call.as_ref().map(|c| c.muted).unwrap_or(false) {
+        let current_tab_name = self.state.get_tab_name(&self.state.tabs[self.state.active_tab]);
+        let input_title = if self.state.active_call.is_some() {
+            let mute_status = if self.state.active_call.as_ref().map(|c| c.mic.is_muted()).unwrap_or(false) {
                 "🔇 MUTED"
             } else {
                 "🎤 LIVE"
@@ -351,70 +474,122 @@ impl ChatUI {
             .style(Style::default().fg(Color::White))
             .wrap(Wrap { trim: false })
             .block(Block::default().borders(Borders::ALL).title(input_title));
-        f.render_widget(input, left_chunks[3]);
+        f.render_widget(input, left_chunks[5]);
 
         // Position cursor
         let (cursor_x, cursor_y) = Self::cursor_position(&self.input, self.cursor, inner_width);
         f.set_cursor_position((
-            left_chunks[3].x + 1 + cursor_x,
-            left_chunks[3].y + 1 + cursor_y,
+            left_chunks[5].x + 1 + cursor_x,
+            left_chunks[5].y + 1 + cursor_y,
         ));
 
         // Tabs bar
-        self.render_tabs(f, left_chunks[4]);
+        self.render_tabs(f, left_chunks[6]);
 
         // Sidebar with online peers
         self.render_sidebar(f, sidebar);
 
         // Render autocomplete popup overlay (on top of everything)
         if let Some(ref ac) = self.autocomplete {
-            self.render_autocomplete(f, ac, left_chunks[3]);
+            self.render_autocomplete(f, ac, left_chunks[5]);
         }
+
+        if self.show_keys {
+            Self::render_keys_overlay(f, left_chunks[2]);
+        } else if let Some(ref qr) = self.qr {
+            Self::render_qr_overlay(f, qr, left_chunks[2]);
+        }
+    }
+
+    /// Coloured dot and text for the relay connection: green connected, yellow
+    /// (re)connecting, magenta when the proxy is at fault, red disconnected
+    fn connection_span(&self) -> Span<'static> {
+        let (color, text) = match &self.connection {
+            // Connected, but nothing back since we last sent chat: maybe not for long
+            ConnectionState::Connected { .. } => match self.client_stats.as_ref().and_then(|c| c.unanswered_for()) {
+                Some(secs) if secs >= QUIET_AFTER_SECS => {
+                    (Color::Yellow, format!("{} · nothing from the relay for {}", self.connection, format_ttl(secs)))
+                }
+                _ => (Color::Green, self.connection.to_string()),
+            },
+            ConnectionState::Connecting => (Color::Yellow, self.connection.to_string()),
+            ConnectionState::Reconnecting { attempt, next_retry_in } => {
+                let remaining = next_retry_in.saturating_sub(self.connection_since.elapsed());
+                let text = if remaining.is_zero() {
+                    format!("Reconnecting (attempt {})...", attempt)
+                } else {
+                    format!("Reconnecting (attempt {}, retry in {}s)", attempt, remaining.as_secs() + 1)
+                };
+                (Color::Yellow, text)
+            }
+            ConnectionState::Unresponsive { timeout, attempt, next_retry_in } => {
+                let remaining = next_retry_in.saturating_sub(self.connection_since.elapsed());
+                let text = if remaining.is_zero() {
+                    format!("Reconnecting: {} (attempt {})...", timeout, attempt)
+                } else {
+                    format!("Reconnecting: {} (attempt {}, retry in {}s)", timeout, attempt, remaining.as_secs() + 1)
+                };
+                (Color::Yellow, text)
+            }
+            ConnectionState::ProxyFailed { reason, next_retry_in: Some(retry) } => {
+                let remaining = retry.saturating_sub(self.connection_since.elapsed());
+                (Color::Magenta, format!("Proxy: {} (retry in {}s)", reason, remaining.as_secs() + 1))
+            }
+            ConnectionState::ProxyFailed { .. } => (Color::Magenta, self.connection.to_string()),
+            ConnectionState::Disconnected | ConnectionState::Refused { .. } => (Color::Red, self.connection.to_string()),
+        };
+        Span::styled(format!("● {}", text), Style::default().fg(color))
     }
 
     pub(crate) fn render_messages(&self, f: &mut Frame, area: Rect) {
-        let current_tab = &self.tabs[self.active_tab];
-        let messages = self.messages.get(current_tab).map(|v| v.as_slice()).unwrap_or(&[]);
+        let current_tab = &self.state.tabs[self.state.active_tab];
+        let messages = self.state.messages.get(current_tab).map(|v| v.as_slice()).unwrap_or(&[]);
 
         let msg_inner_width = if area.width > 2 { (area.width - 2) as usize } else { 1 };
         let msg_inner_height = if area.height > 2 { (area.height - 2) as usize } else { 0 };
 
         let mut msg_lines: Vec<Line> = Vec::new();
-        for m in messages {
-            if m.system && m.nickname.is_none() {
-                // Join/leave/system messages
-                let text = format!("[{}]", m.content);
+        let now = chrono::Utc::now().timestamp();
+        for (index, m) in messages.iter().enumerate() {
+            if m.system {
+                // Join/leave/system messages; transfer and call events carry their time
+                let (text, color) = match m.event {
+                    Some(event) => {
+                        let time = chrono::DateTime::from_timestamp(m.timestamp, 0)
+                            .map(|dt| dt.format("%H:%M:%S").to_string())
+                            .unwrap_or_default();
+                        let color = if event == ChatEvent::FileFailed { Color::Red } else { Color::Cyan };
+                        (format!("[{} {}]", time, self.state.event_text(m)), color)
+                    }
+                    None => (format!("[{}]", m.content), Color::Yellow),
+                };
                 let padding = msg_inner_width.saturating_sub(text.len()) / 2;
                 let padded = format!("{}{}", " ".repeat(padding), text);
                 msg_lines.push(Line::from(Span::styled(
                     padded,
-                    Style::default().fg(Color::Yellow).add_modifier(Modifier::ITALIC),
+                    Style::default().fg(color).add_modifier(Modifier::ITALIC),
                 )));
                 continue;
             }
 
-            if m.system {
-                // Skip nickname system messages (internal only)
-                continue;
-            }
-
             let timestamp = chrono::DateTime::from_timestamp(m.timestamp, 0)
                 .map(|dt| dt.format("%H:%M:%S").to_string())
                 .unwrap_or_else(|| "??:??:??".to_string());
 
-            let is_own = m.sender == self.own_id;
+            let is_own = m.sender == self.state.own_id;
             let sender_display = if is_own {
-                self.display_name()
+                self.state.display_name()
             } else {
-                self.get_peer_display_name(&m.sender)
+                self.state.get_peer_display_name(&m.sender)
             };
 
             // Read receipt indicator
             let receipt_indicator = if is_own {
                 if let Some(ref msg_id) = m.message_id {
-                    match self.read_status.get(msg_id) {
+                    match self.state.read_status.get(msg_id) {
                         Some(ReadStatus::Read) => " ✓✓",
                         Some(ReadStatus::Sent) => " ✓",
+                        Some(ReadStatus::Pending) => " ⏳",
                         None => " ✓", // sent but no status tracked yet
                     }
                 } else {
@@ -424,8 +599,26 @@ impl ChatUI {
                 ""
             };
 
-            let prefix = format!("[{}] {}: ", timestamp, sender_display);
-            let prefix_style = if is_own { Color::Cyan } else { Color::Magenta };
+            // Disappearing messages count down next to the receipt; catch-up and late copies say so
+            let mut expiry_indicator = m.expires_at()
+                .map(|at| format!(" ⏳ {}", format_ttl(at.saturating_sub(now).max(0) as u64)))
+                .unwrap_or_default();
+            if m.synced {
+                expiry_indicator.insert_str(0, " (synced)");
+            } else if m.delayed {
+                expiry_indicator.insert_str(0, " (delayed)");
+            }
+
+            // "/me waves" reads "· alice waves"
+            let byline = if m.action { format!("· {} ", sender_display) } else { format!("{}: ", sender_display) };
+            let star = if self.state.is_starred(m) { "⭐ " } else { "" };
+            let prefix = format!("[{}] {}{}", timestamp, star, byline);
+            let mut prefix_style = Style::default().fg(if is_own { Color::Cyan } else { Color::Magenta });
+            // A name only vouched for by a group invite, until the peer sends their own
+            if !is_own && self.state.unconfirmed_name(&m.sender).is_some() {
+                prefix_style = prefix_style.add_modifier(Modifier::DIM | Modifier::ITALIC);
+            }
+            let first_line = msg_lines.len();
 
             let content = &m.content;
             let available = msg_inner_width.saturating_sub(prefix.len());
@@ -434,47 +627,82 @@ impl ChatUI {
             if available == 0 || content.is_empty() {
                 let mut spans = vec![
                     Span::styled(format!("[{}] ", timestamp), Style::default().fg(Color::DarkGray)),
-                    Span::styled(format!("{}: ", sender_display), Style::default().fg(prefix_style)),
+                    Span::styled(star, Style::default().fg(Color::Yellow)),
+                    Span::styled(byline.clone(), prefix_style),
                     Span::raw(content.to_string()),
                 ];
                 if !receipt_indicator.is_empty() {
                     spans.push(Span::styled(receipt_indicator.to_string(), Style::default().fg(Color::Green)));
                 }
+                if !expiry_indicator.is_empty() {
+                    spans.push(Span::styled(expiry_indicator.clone(), Style::default().fg(Color::DarkGray)));
+                }
                 msg_lines.push(Line::from(spans));
             } else {
-                // Word-wrap content, then parse markdown on each wrapped line
-                let wrapped_lines = Self::word_wrap(content, available);
+                // Wrap content, then style each wrapped line
+                let expanded = m.message_id.as_ref().is_some_and(|id| self.state.expanded.contains(id));
+                let mut wrapped_lines = Self::wrap_message(content, available, (!expanded).then_some(index + 1));
+                let hidden = if is_long(content) && !expanded && wrapped_lines.len() > COLLAPSED_LINES {
+                    let hidden = wrapped_lines.len() - COLLAPSED_LINES;
+                    wrapped_lines.truncate(COLLAPSED_LINES);
+                    hidden
+                } else {
+                    0
+                };
                 let mut first = true;
 
-                for (line_idx, line) in wrapped_lines.iter().enumerate() {
+                for (line_idx, row) in wrapped_lines.iter().enumerate() {
                     let is_last = line_idx == wrapped_lines.len() - 1;
 
                     if first {
                         let mut spans = vec![
                             Span::styled(format!("[{}] ", timestamp), Style::default().fg(Color::DarkGray)),
-                            Span::styled(format!("{}: ", sender_display), Style::default().fg(prefix_style)),
+                            Span::styled(star, Style::default().fg(Color::Yellow)),
+                            Span::styled(byline.clone(), prefix_style),
                         ];
-                        spans.extend(Self::parse_markdown(line));
+                        spans.extend(Self::styled_row(row));
                         if is_last && !receipt_indicator.is_empty() {
                             spans.push(Span::styled(receipt_indicator.to_string(), Style::default().fg(Color::Green)));
                         }
+                        if is_last && !expiry_indicator.is_empty() {
+                            spans.push(Span::styled(expiry_indicator.clone(), Style::default().fg(Color::DarkGray)));
+                        }
                         msg_lines.push(Line::from(spans));
                         first = false;
                     } else {
                         let mut spans = vec![Span::raw(indent.clone())];
-                        spans.extend(Self::parse_markdown(line));
+                        spans.extend(Self::styled_row(row));
                         if is_last && !receipt_indicator.is_empty() {
                             spans.push(Span::styled(receipt_indicator.to_string(), Style::default().fg(Color::Green)));
                         }
+                        if is_last && !expiry_indicator.is_empty() {
+                            spans.push(Span::styled(expiry_indicator.clone(), Style::default().fg(Color::DarkGray)));
+                        }
                         msg_lines.push(Line::from(spans));
                     }
                 }
+                if hidden > 0 {
+                    msg_lines.push(Line::from(Span::styled(
+                        format!("{}… {} more lines — /expand {}", indent, hidden, index + 1),
+                        Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+                    )));
+                }
+            }
+            if m.mentions_me {
+                for line in &mut msg_lines[first_line..] {
+                    line.style = MENTION_STYLE;
+                }
+            }
+            if m.action {
+                for line in &mut msg_lines[first_line..] {
+                    line.style = line.style.add_modifier(Modifier::ITALIC);
+                }
             }
         }
 
         // Calculate scroll position
         let total_lines = msg_lines.len();
-        let user_scroll = self.scroll_offset.get(current_tab).copied().unwrap_or(0);
+        let user_scroll = self.state.scroll_offset.get(current_tab).copied().unwrap_or(0);
 
         // Clamp user scroll to valid range
         let max_scroll = total_lines.saturating_sub(msg_inner_height);
@@ -500,12 +728,21 @@ impl ChatUI {
     }
 
     pub(crate) fn render_tabs(&self, f: &mut Frame, area: Rect) {
-        let tab_names: Vec<String> = self.tabs.iter().enumerate().map(|(i, tab)| {
-            let name = self.get_tab_name(tab);
-            if i == self.active_tab {
+        let tab_names: Vec<String> = self.state.tabs.iter().enumerate().map(|(i, tab)| {
+            let name = match self.state.presence_label(tab) {
+                Some(presence) => format!("{} {}", self.state.get_tab_name(tab), presence),
+                None => self.state.get_tab_name(tab),
+            };
+            if i == self.state.active_tab {
                 format!("[{}]", name)
             } else {
-                format!(" {} ", name)
+                // Mentions still count during do not disturb, they just don't stand out
+                let mentions = self.state.mention_unread.get(tab).filter(|_| self.state.dnd.is_none());
+                match (mentions, self.state.unread.get(tab)) {
+                    (Some(mentions), _) => format!(" {}({}!) ", name, mentions),
+                    (None, Some(n)) => format!(" {} ({}) ", name, n),
+                    (None, None) => format!(" {} ", name),
+                }
             }
         }).collect();
 
@@ -517,27 +754,120 @@ impl ChatUI {
     }
 
     pub(crate) fn render_sidebar(&self, f: &mut Frame, area: Rect) {
-        let mut peer_items: Vec<ListItem> = self.peers.iter().map(|(id, info)| {
-            let verified_icon = if self.verified_peers.contains(id) { "✅" } else { "❓" };
-            let typing_icon = if self.typing_peers.contains_key(id) { " ✍" } else { "" };
-            let display = if let Some(ref nick) = info.nickname {
-                format!("{} ● {}{}", verified_icon, nick, typing_icon)
-            } else {
-                format!("{} ● {}{}", verified_icon, &id[..12.min(id.len())], typing_icon)
+        // In a group call, who's in it goes under the peer list
+        let in_group_call = self.state.active_call.as_ref().is_some_and(|c| matches!(c.call_type, CallType::Group { .. }));
+        let area = if in_group_call {
+            let participants = self.state.call_participants();
+            let height = (participants.len() as u16 + 3).min(area.height / 2);
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(3), Constraint::Length(height)])
+                .split(area);
+            self.render_call_participants(f, chunks[1], &participants);
+            chunks[0]
+        } else {
+            area
+        };
+
+        let mut peer_items: Vec<ListItem> = self.state.sidebar_peers().iter().map(|id| {
+            let verified_icon = self.state.verification_icon(id);
+            let typing_icon = if self.state.typing_peers.contains_key(id) { " ✍" } else { "" };
+            let away = self.state.peer_away.contains_key(id);
+            let dot = if away { "○" } else { "●" };
+            let display = format!("{} {} {}{}", verified_icon, dot, self.state.get_peer_display_name(id), typing_icon);
+            let color = match self.state.verification_of(id) {
+                _ if away => Color::Yellow,
+                Some(Verified::Mutual { .. }) => Color::Green,
+                Some(Verified::Local { .. }) => Color::Cyan,
+                None => Color::Yellow,
             };
-            let color = if self.verified_peers.contains(id) { Color::Green } else { Color::Yellow };
             ListItem::new(display).style(Style::default().fg(color))
         }).collect();
 
+        let online = peer_items.len();
         if peer_items.is_empty() {
             peer_items.push(ListItem::new("(no peers)").style(Style::default().fg(Color::DarkGray)));
         }
+        let gone = self.state.recently_seen_peers();
+        if !gone.is_empty() {
+            let dim = Style::default().fg(Color::DarkGray);
+            if self.state.show_recently_seen {
+                peer_items.push(ListItem::new("▾ recently seen").style(dim));
+                peer_items.extend(gone.iter().map(|id| ListItem::new(format!("   ◌ {}", self.state.get_peer_display_name(id))).style(dim)));
+            } else {
+                peer_items.push(ListItem::new(format!("▸ {} recently seen (/recent)", gone.len())).style(dim));
+            }
+        }
 
         let list = List::new(peer_items)
-            .block(Block::default().borders(Borders::ALL).title(format!("Online ({})", self.peers.len())));
+            .block(Block::default().borders(Borders::ALL).title(format!("Online ({})", online)));
         f.render_widget(list, area);
     }
 
+    /// Everyone in the group call: 🔊 speaking, 🔇 muted, ⏸ on hold, or gone quiet
+    fn render_call_participants(&self, f: &mut Frame, area: Rect, participants: &[(String, &Participant)]) {
+        let own_muted = self.state.active_call.as_ref().is_some_and(|c| c.mic.is_muted());
+        let mut items = vec![ListItem::new(format!("{} {} (you)", if own_muted { "🔇" } else { "🎤" }, self.state.display_name()))
+            .style(Style::default().fg(Color::Cyan))];
+        items.extend(participants.iter().map(|(name, p)| {
+            let (icon, color) = match p {
+                p if p.on_hold => ("⏸", Color::Yellow),
+                p if p.lost() => ("⚠", Color::DarkGray),
+                p if p.muted => ("🔇", Color::Red),
+                p if p.speaking() => ("🔊", Color::Green),
+                _ => ("  ", Color::White),
+            };
+            let lost = if p.lost() { " (connection lost?)" } else { "" };
+            ListItem::new(format!("{} {}{}", icon, name, lost)).style(Style::default().fg(color))
+        }));
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!("In call ({})", participants.len() + 1)));
+        f.render_widget(list, area);
+    }
+
+    /// Every key binding, by where it applies, over the message pane
+    fn render_keys_overlay(f: &mut Frame, area: Rect) {
+        let mut lines = Vec::new();
+        for context in [KeyContext::Global, KeyContext::Input, KeyContext::Autocomplete] {
+            if !lines.is_empty() {
+                lines.push(Line::raw(""));
+            }
+            lines.push(Line::from(Span::styled(context.title(), Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))));
+            for binding in KEY_BINDINGS.iter().filter(|b| b.context == context) {
+                lines.push(Line::from(vec![
+                    Span::styled(format!("  {:<14}", binding.keys), Style::default().fg(Color::Yellow)),
+                    Span::raw(binding.description),
+                    Span::styled(format!("  [{}]", binding.id), Style::default().fg(Color::DarkGray)),
+                ]));
+            }
+        }
+        f.render_widget(Clear, area);
+        let overlay = Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).title("Keys (any key to close)").style(Style::default().fg(Color::White)));
+        f.render_widget(overlay, area);
+    }
+
+    /// /invite-link's QR code, white on black so it scans on any terminal theme
+    fn render_qr_overlay(f: &mut Frame, qr: &[String], area: Rect) {
+        let width = qr.iter().map(|line| line.chars().count()).max().unwrap_or(0) as u16 + 2;
+        let height = qr.len() as u16 + 2;
+        f.render_widget(Clear, area);
+        if width > area.width || height > area.height {
+            let note = Paragraph::new("Make the window bigger to show the QR code, or use the link above it.")
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title("Invite link (any key to close)"));
+            f.render_widget(note, area);
+            return;
+        }
+        let centered = Rect::new(area.x + (area.width - width) / 2, area.y + (area.height - height) / 2, width, height);
+        let lines: Vec<Line> = qr.iter().map(|line| Line::raw(line.clone())).collect();
+        let overlay = Paragraph::new(lines)
+            .style(Style::default().fg(Color::White).bg(Color::Black))
+            .block(Block::default().borders(Borders::ALL).title("Scan me (any key to close)"));
+        f.render_widget(overlay, centered);
+    }
+
     /// Render autocomplete popup above the input box
     fn render_autocomplete(&self, f: &mut Frame, ac: &super::types::AutocompleteState, input_area: Rect) {
         let visible_count = ac.filtered.len().min(8) as u16;
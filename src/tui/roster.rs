@@ -0,0 +1,258 @@
+//! Cross-checking who is in a group. Every member sends the others its roster — the
+//! members it holds identity keys for, with a digest over the sorted keys — when it
+//! joins and whenever a session with another member comes up. Members we didn't know
+//! about are added and looked up on the relay, so nobody sits in the room unseen; a
+//! different key for someone, or a roster that still disagrees after both sides have
+//! swapped theirs, is flagged loudly in the group tab.
+
+use crate::client::OutgoingMessage;
+use crate::protocol::{short_id, GroupRoster, MemberHint, PlainMessage, MAX_INVITE_HINTS};
+
+use super::state::{ChatState, Effect};
+use super::types::Tab;
+
+/// Key derivation context for membership digests
+const DIGEST_CONTEXT: &str = "wsp group membership digest v1";
+
+/// Hash over the sorted identity keys of a group's members: equal for two members
+/// exactly when they see the same membership
+pub(crate) fn membership_digest<'a>(keys: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut keys: Vec<&[u8]> = keys.into_iter().collect();
+    keys.sort();
+    let mut hasher = blake3::Hasher::new_derive_key(DIGEST_CONTEXT);
+    for key in keys {
+        hasher.update(key);
+    }
+    hasher.finalize().as_bytes().to_vec()
+}
+
+impl ChatState {
+    /// The identity key we hold for `peer_id` in `group_id`: our session's if we have
+    /// one, otherwise the one an invite or another member's roster gave
+    pub(crate) fn member_key(&self, group_id: &str, peer_id: &str) -> Option<&[u8]> {
+        if peer_id == self.own_id {
+            return Some(&self.own_public_key);
+        }
+        self.peers.get(peer_id)
+            .map(|peer| peer.public_key.as_slice())
+            .or_else(|| self.member_keys.get(group_id)?.get(peer_id).map(Vec::as_slice))
+    }
+
+    /// `peer_id` as we'd describe them to another member: key, and the nickname we
+    /// have first-hand (or their short id)
+    pub(crate) fn member_hint(&self, group_id: &str, peer_id: &str) -> Option<MemberHint> {
+        let nickname = if peer_id == self.own_id {
+            self.own_nickname.clone()
+        } else {
+            self.peers.get(peer_id).and_then(|peer| peer.nickname.clone())
+        };
+        Some(MemberHint {
+            session_id: peer_id.to_string(),
+            nickname: nickname.unwrap_or_else(|| short_id(peer_id).to_string()),
+            public_key: self.member_key(group_id, peer_id)?.to_vec(),
+        })
+    }
+
+    /// Us and every member of `group_id` we hold a key for
+    fn own_roster(&self, group_id: &str, reply: bool) -> Option<GroupRoster> {
+        let group = self.groups.get(group_id)?;
+        let members: Vec<MemberHint> = std::iter::once(&self.own_id)
+            .chain(group.members.iter().filter(|id| **id != self.own_id))
+            .filter_map(|id| self.member_hint(group_id, id))
+            .take(MAX_INVITE_HINTS)
+            .collect();
+        let digest = membership_digest(members.iter().map(|m| m.public_key.as_slice()));
+        Some(GroupRoster { digest, members, reply })
+    }
+
+    fn send_roster(&self, group_id: &str, peer_id: &str, reply: bool, fx: &mut Vec<Effect>) {
+        if let Some(roster) = self.own_roster(group_id, reply) {
+            fx.push(Effect::Send(OutgoingMessage::Direct {
+                target_id: peer_id.to_string(),
+                message: PlainMessage::group_roster(self.own_id.clone(), group_id.to_string(), roster),
+            }));
+        }
+    }
+
+    /// Send our roster for `group_id` to every member we have a session with
+    pub(crate) fn announce_roster(&self, group_id: &str, fx: &mut Vec<Effect>) {
+        let Some(group) = self.groups.get(group_id) else {
+            return;
+        };
+        for id in group.members.iter().filter(|id| self.peers.contains_key(*id)) {
+            self.send_roster(group_id, id, false, fx);
+        }
+    }
+
+    /// A session with `peer_id` is up: check their key against the one their groups
+    /// were told about, and swap rosters
+    pub(crate) fn member_session_up(&mut self, peer_id: &str, fx: &mut Vec<Effect>) {
+        let group_ids: Vec<String> = self.groups.iter()
+            .filter(|(_, g)| g.members.iter().any(|id| id == peer_id))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for group_id in group_ids {
+            let told = self.member_keys.get_mut(&group_id).and_then(|keys| keys.remove(peer_id));
+            let key = self.peers.get(peer_id).map(|p| p.public_key.clone());
+            if told.is_some_and(|told| Some(told) != key) {
+                let name = self.get_peer_display_name(peer_id);
+                self.membership_inconsistent(
+                    &group_id,
+                    &format!("{}'s identity key is not the one the group was told about", name),
+                );
+            }
+            self.send_roster(&group_id, peer_id, false, fx);
+        }
+    }
+
+    /// Loudly, in the group tab: members disagree about who is in `group_id`
+    pub(crate) fn membership_inconsistent(&mut self, group_id: &str, detail: &str) {
+        let text = format!("⚠️ group membership view is inconsistent: {}", detail);
+        self.add_system_message(&Tab::Group(group_id.to_string()), text.clone());
+        self.status = text;
+    }
+
+    /// `sender`'s roster for `group_id`: learn the members it names that we didn't
+    /// know, and flag any it names with a different key. If the digests still differ,
+    /// answer with ours, or flag it if this was already the answer to ours.
+    pub(crate) fn handle_group_roster(&mut self, sender: &str, group_id: &str, roster: GroupRoster, fx: &mut Vec<Effect>) {
+        if !self.groups.contains_key(group_id) {
+            return;
+        }
+        let sender_name = self.get_peer_display_name(sender);
+        let tab = Tab::Group(group_id.to_string());
+        let mut clashes = Vec::new();
+        for hint in &roster.members {
+            let id = &hint.session_id;
+            match self.member_key(group_id, id) {
+                Some(key) if key != hint.public_key.as_slice() => {
+                    clashes.push(self.get_peer_display_name(id));
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    self.member_keys.entry(group_id.to_string()).or_default().insert(id.clone(), hint.public_key.clone());
+                    if hint.nickname != short_id(id) {
+                        self.name_hints.insert(id.clone(), hint.clone());
+                    }
+                    self.discover_quietly(id, fx);
+                }
+            }
+            let Some(group) = self.groups.get_mut(group_id) else {
+                return;
+            };
+            if *id == self.own_id || group.members.contains(id) {
+                continue;
+            }
+            group.members.push(id.clone());
+            let name = self.get_peer_display_name(id);
+            let text = if id == sender {
+                format!("{} joined the group", name)
+            } else {
+                format!("{} is in the group (per {})", name, sender_name)
+            };
+            self.add_system_message(&tab, text);
+        }
+        if !clashes.is_empty() {
+            self.membership_inconsistent(
+                group_id,
+                &format!("{} has a different identity key for {}", sender_name, clashes.join(", ")),
+            );
+        }
+
+        let Some(ours) = self.own_roster(group_id, true) else {
+            return;
+        };
+        if ours.digest == roster.digest {
+            return;
+        }
+        if !roster.reply {
+            self.send_roster(group_id, sender, true, fx);
+            return;
+        }
+        if !clashes.is_empty() {
+            return;
+        }
+        let differs = |a: &GroupRoster, b: &GroupRoster| -> Vec<String> {
+            a.members.iter()
+                .filter(|m| !b.members.iter().any(|n| n.session_id == m.session_id && n.public_key == m.public_key))
+                .map(|m| m.session_id.clone())
+                .collect()
+        };
+        let mut names: Vec<String> = differs(&ours, &roster).into_iter()
+            .chain(differs(&roster, &ours))
+            .map(|id| self.get_peer_display_name(&id))
+            .collect();
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            names.push("the member list".to_string());
+        }
+        self.membership_inconsistent(group_id, &format!("{} disagrees about {}", sender_name, names.join(", ")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::protocol::Message;
+    use crate::tui::types::GroupInfo;
+
+    fn peer(nick: &str, key: u8) -> PeerDisplay {
+        PeerDisplay { nickname: Some(nick.to_string()), public_key: vec![key; 32] }
+    }
+
+    fn roster(sender: &str, members: &[(&str, u8)], reply: bool) -> PlainMessage {
+        let members: Vec<MemberHint> = members.iter()
+            .map(|(id, key)| MemberHint { session_id: id.to_string(), nickname: short_id(id).to_string(), public_key: vec![*key; 32] })
+            .collect();
+        let digest = membership_digest(members.iter().map(|m| m.public_key.as_slice()));
+        PlainMessage::group_roster(sender.to_string(), "g1".to_string(), GroupRoster { digest, members, reply })
+    }
+
+    fn last_line(state: &ChatState) -> &str {
+        &state.messages[&Tab::Group("g1".to_string())].last().unwrap().content
+    }
+
+    #[test]
+    fn test_rosters_reveal_members_and_flag_disagreement() {
+        let me = "ee".repeat(16);
+        let (alice, bob, carol) = ("aa".repeat(16), "bb".repeat(16), "cc".repeat(16));
+        let mut state = ChatState::new(me.clone(), None, vec![0; 32]);
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), peer("alice", 1)), PeerUpdate::Added(bob.clone(), peer("bob", 2))]);
+        state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members: vec![alice.clone()], join_token: None });
+        state.ensure_tab(&Tab::Group("g1".to_string()));
+
+        // Alice's roster names bob, and carol whom we have no session with: both are
+        // added, carol is looked up, and the views now match so nothing is sent back
+        let everyone = [(alice.as_str(), 1), (me.as_str(), 0), (bob.as_str(), 2), (carol.as_str(), 3)];
+        let fx = state.ingest_message(roster(&alice, &everyone, false));
+        assert_eq!(state.groups["g1"].members, vec![alice.clone(), bob.clone(), carol.clone()]);
+        assert!(matches!(
+            &fx[..],
+            [Effect::Send(OutgoingMessage::Signal(Message::Discover { target_session, .. }))] if *target_session == carol
+        ));
+        assert_eq!(last_line(&state), format!("{} is in the group (per alice)", short_id(&carol)));
+
+        // Bob doesn't know carol: he gets our roster, and if his answer still leaves her
+        // out, that's flagged
+        let without_carol = [(bob.as_str(), 2), (me.as_str(), 0), (alice.as_str(), 1)];
+        let fx = state.ingest_message(roster(&bob, &without_carol, false));
+        assert!(matches!(
+            &fx[..],
+            [Effect::Send(OutgoingMessage::Direct { target_id, message })] if *target_id == bob && message.group_roster.as_ref().is_some_and(|r| r.reply && r.members.len() == 4)
+        ));
+        assert!(state.ingest_message(roster(&bob, &without_carol, true)).is_empty());
+        assert_eq!(last_line(&state), format!("⚠️ group membership view is inconsistent: bob disagrees about {}", short_id(&carol)));
+
+        // A different key for a member is flagged, and not taken
+        state.ingest_message(roster(&bob, &[(bob.as_str(), 2), (alice.as_str(), 9)], true));
+        assert!(last_line(&state).ends_with("bob has a different identity key for alice"));
+
+        // Carol's session comes up with another key than alice vouched for
+        let fx = state.apply_peer_updates(vec![PeerUpdate::Added(carol.clone(), peer("carol", 7))]);
+        assert!(last_line(&state).ends_with("carol's identity key is not the one the group was told about"));
+        assert!(matches!(&fx[..], [Effect::Send(OutgoingMessage::Direct { target_id, .. })] if *target_id == carol));
+    }
+}
@@ -0,0 +1,167 @@
+//! The encryption banner under the header: whether what's typed in the open tab reaches
+//! everyone it's addressed to, end-to-end encrypted. Messages only go to peers we have
+//! a secure session with; the rest are silently left out, so the banner says who. A
+//! verified peer turning up with a different identity key blocks sending to them
+//! until the new key is verified too.
+
+use crate::protocol::PlainMessage;
+
+use super::state::ChatState;
+use super::types::Tab;
+
+/// How safe it is to send in a tab
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Security {
+    /// Every recipient has a secure session
+    Encrypted { reachable: usize },
+    /// `missing` of `total` recipients have no secure session and won't get messages
+    Partial { missing: usize, total: usize },
+    /// A recipient's identity key changed away from a verified one
+    Blocked { names: Vec<String> },
+}
+
+impl Security {
+    /// The banner line
+    pub(crate) fn banner(&self) -> String {
+        match self {
+            Self::Encrypted { reachable } => {
+                format!("🔒 end-to-end encrypted ({}/{} members reachable)", reachable, reachable)
+            }
+            Self::Partial { total: 0, .. } => "⚠️ nobody else is here — messages go nowhere".to_string(),
+            Self::Partial { missing: 1, total: 1 } => {
+                "⚠️ no secure session — they will NOT receive messages".to_string()
+            }
+            Self::Partial { missing, total } => format!(
+                "⚠️ {} of {} members {} no secure session — they will NOT receive messages",
+                missing,
+                total,
+                if *missing == 1 { "has" } else { "have" }
+            ),
+            Self::Blocked { names } => format!("⛔ untrusted key ({}) — sending blocked", names.join(", ")),
+        }
+    }
+}
+
+impl ChatState {
+    /// Who a message typed in `tab` is for, besides us
+    fn recipients(&self, tab: &Tab) -> Vec<String> {
+        match tab {
+            Tab::Global => self.peers.keys().cloned().collect(),
+            Tab::DirectMessage(peer_id) => vec![peer_id.clone()],
+            Tab::Group(group_id) => self.groups.get(group_id)
+                .map(|g| g.members.iter().filter(|id| **id != self.own_id).cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// `peer_id` swapped a verified key for one we haven't verified
+    pub(crate) fn is_untrusted(&self, peer_id: &str) -> bool {
+        self.untrusted.contains(peer_id) && self.verification_of(peer_id).is_none()
+    }
+
+    /// Where sending in `tab` stands right now
+    pub(crate) fn tab_security(&self, tab: &Tab) -> Security {
+        let recipients = self.recipients(tab);
+        let mut blocked: Vec<String> = recipients.iter()
+            .filter(|id| self.is_untrusted(id))
+            .map(|id| self.get_peer_display_name(id))
+            .collect();
+        if !blocked.is_empty() {
+            blocked.sort();
+            return Security::Blocked { names: blocked };
+        }
+        let reachable = recipients.iter().filter(|id| self.peers.contains_key(*id)).count();
+        if reachable == recipients.len() && reachable > 0 {
+            Security::Encrypted { reachable }
+        } else {
+            Security::Partial { missing: recipients.len() - reachable, total: recipients.len() }
+        }
+    }
+
+    /// Why Enter won't send in `tab`, if it won't
+    pub(crate) fn send_blocked(&self, tab: &Tab) -> Option<String> {
+        if let Some(reason) = self.global_refused(tab) {
+            return Some(reason);
+        }
+        let Security::Blocked { names } = self.tab_security(tab) else {
+            return None;
+        };
+        Some(format!(
+            "⛔ Not sent: {}'s identity key changed since you verified it — /verify the new key first",
+            names.join(", ")
+        ))
+    }
+
+    /// Called when `peer_id`'s identity key changes from `old_key`. If the old one was
+    /// verified and the new one isn't, sending to them stops until it is.
+    pub(crate) fn distrust_new_key(&mut self, peer_id: &str, old_key: &[u8]) {
+        if !self.verified_peers.contains_key(old_key) || self.verification_of(peer_id).is_some() {
+            return;
+        }
+        self.untrusted.insert(peer_id.to_string());
+        let name = self.get_peer_display_name(peer_id);
+        let text = format!("⛔ {}'s identity key changed — sending to them is blocked until you /verify it", name);
+        let dm = Tab::DirectMessage(peer_id.to_string());
+        if self.messages.contains_key(&dm) {
+            self.push_message(dm, PlainMessage::system("system".to_string(), text.clone()));
+        }
+        self.add_system_message(&Tab::Global, text.clone());
+        self.status = text;
+    }
+
+    /// The client sent to `group_id` without `members`, having no session with them
+    pub(crate) fn group_skipped(&mut self, group_id: &str, members: &[String]) {
+        let names: Vec<String> = members.iter().map(|id| self.get_peer_display_name(id)).collect();
+        self.status = format!(
+            "⚠️ Not sent to {} in {}: no secure session",
+            names.join(", "),
+            self.group_name(group_id)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::tui::types::{GroupInfo, Verified};
+
+    fn peer(key: u8) -> PeerDisplay {
+        PeerDisplay { nickname: Some(format!("p{}", key)), public_key: vec![key; 32] }
+    }
+
+    #[test]
+    fn test_banner_counts_members_and_changed_keys_block_sending() {
+        let (alice, bob, carol) = ("aa".repeat(16), "bb".repeat(16), "cc".repeat(16));
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), peer(1)), PeerUpdate::Added(bob.clone(), peer(2))]);
+        let group = Tab::Group("g1".to_string());
+        let members = vec![state.own_id.clone(), alice.clone(), bob.clone()];
+        state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members, join_token: None });
+        assert_eq!(state.tab_security(&group).banner(), "🔒 end-to-end encrypted (2/2 members reachable)");
+
+        state.groups.get_mut("g1").unwrap().members.push(carol.clone());
+        assert_eq!(state.tab_security(&group), Security::Partial { missing: 1, total: 3 });
+        assert!(state.tab_security(&group).banner().starts_with("⚠️ 1 of 3 members has no secure session"));
+        state.group_skipped("g1", std::slice::from_ref(&carol));
+        assert!(state.status.contains("Not sent to"), "{}", state.status);
+
+        // An unverified key changing is nothing new; a verified one changing blocks
+        state.apply_peer_updates(vec![PeerUpdate::Changed(bob.clone(), peer(3))]);
+        assert!(state.send_blocked(&group).is_none());
+        state.verified_peers.insert(vec![1; 32], Verified::Local { at: 0 });
+        state.apply_peer_updates(vec![PeerUpdate::Changed(alice.clone(), peer(4))]);
+        assert!(matches!(state.tab_security(&group), Security::Blocked { .. }));
+        state.active_tab = state.tabs.len();
+        state.tabs.push(group.clone());
+        let fx = state.handle_command("hello");
+        assert!(fx.is_empty() && state.status.starts_with("⛔ Not sent"), "{}", state.status);
+        assert!(!state.messages.get(&group).is_some_and(|m| m.iter().any(|m| m.content == "hello")));
+        assert!(state.send_blocked(&Tab::DirectMessage(bob.clone())).is_none());
+
+        // Verifying the new key lifts it
+        state.verified_peers.insert(vec![4; 32], Verified::Local { at: 0 });
+        assert_eq!(state.tab_security(&group), Security::Partial { missing: 1, total: 3 });
+        assert!(!state.handle_command("hello").is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+//! Sounds for things worth looking up for: a DM, a mention, an incoming call (rung
+//! until it's answered or gives up), a file offer. Each event plays the terminal bell,
+//! a short tone through the speakers, or nothing, as set under `sounds` in the config;
+//! `/sounds on|off` mutes them all for the session. Do not disturb keeps them quiet.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{self, AudioPipeline};
+use crate::protocol::PlainMessage;
+
+use super::state::{ChatState, Effect};
+use super::types::Tab;
+use super::ChatUI;
+
+/// How often an incoming call rings
+const RING_EVERY: Duration = Duration::from_secs(3);
+/// When an unanswered call stops ringing
+const RING_FOR: Duration = Duration::from_secs(30);
+/// How long the speakers stay open after a tone, in case another follows
+const SPEAKERS_HOLD: Duration = Duration::from_secs(3);
+
+/// What an event sounds like
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sound {
+    Off,
+    /// The terminal bell (BEL)
+    Bell,
+    /// A short tone through the speakers; the bell where there are none
+    Tone,
+}
+
+/// Things that can make a sound, least to most pressing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SoundEvent {
+    /// A message in the tab we're looking at
+    Message,
+    FileOffer,
+    /// A DM in another tab
+    Dm,
+    Mention,
+    Call,
+}
+
+/// The `sounds` section of the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoundSettings {
+    pub enabled: bool,
+    pub message: Sound,
+    pub dm: Sound,
+    pub mention: Sound,
+    pub call: Sound,
+    pub file_offer: Sound,
+}
+
+impl Default for SoundSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            message: Sound::Off,
+            dm: Sound::Bell,
+            mention: Sound::Bell,
+            call: Sound::Bell,
+            file_offer: Sound::Bell,
+        }
+    }
+}
+
+impl SoundSettings {
+    fn sound_for(&self, event: SoundEvent) -> Sound {
+        match event {
+            SoundEvent::Message => self.message,
+            SoundEvent::FileOffer => self.file_offer,
+            SoundEvent::Dm => self.dm,
+            SoundEvent::Mention => self.mention,
+            SoundEvent::Call => self.call,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Sounds {
+    pub settings: SoundSettings,
+    /// The most pressing event since the last message was taken in
+    due: Option<SoundEvent>,
+    /// An incoming call ringing: when it started, and when it last rang
+    ringing: Option<(Instant, Instant)>,
+}
+
+impl ChatState {
+    /// Handle /sounds [on|off]
+    pub(crate) fn handle_sounds_command(&mut self, args: &[&str]) {
+        match args.first().copied() {
+            Some("on") => self.sounds.settings.enabled = true,
+            Some("off") => {
+                self.sounds.settings.enabled = false;
+                self.sounds.ringing = None;
+            }
+            None => {}
+            Some(_) => {
+                self.status = "Usage: /sounds [on|off]".to_string();
+                return;
+            }
+        }
+        self.status = if self.sounds.settings.enabled {
+            "🔔 Sounds on (set them per event under \"sounds\" in config.json)".to_string()
+        } else {
+            "🔕 Sounds off".to_string()
+        };
+    }
+
+    /// Note that `event` happened; the sound goes out with the message's effects
+    pub(crate) fn chime(&mut self, event: SoundEvent) {
+        if self.sounds.settings.enabled && self.dnd.is_none() {
+            self.sounds.due = self.sounds.due.max(Some(event));
+        }
+    }
+
+    /// The sound a chat message stored in `tab` calls for, if any
+    pub(crate) fn chime_for_message(&mut self, tab: &Tab, msg: &PlainMessage) {
+        if msg.system || msg.synced || msg.sender == self.own_id {
+            return;
+        }
+        if msg.mentions_me {
+            self.chime(SoundEvent::Mention);
+        } else if self.tabs[self.active_tab] == *tab {
+            self.chime(SoundEvent::Message);
+        } else if matches!(tab, Tab::DirectMessage(_)) {
+            self.chime(SoundEvent::Dm);
+        }
+    }
+
+    /// The sound owed for what just came in, as an effect
+    pub(crate) fn take_chime(&mut self) -> Option<Effect> {
+        let event = self.sounds.due.take()?;
+        if event == SoundEvent::Call {
+            let now = Instant::now();
+            self.sounds.ringing = Some((now, now));
+        }
+        match self.sounds.settings.sound_for(event) {
+            Sound::Off => None,
+            sound => Some(Effect::Sound(sound)),
+        }
+    }
+
+    /// Ring again for a call still waiting on us (run from housekeeping)
+    pub(crate) fn check_ringing(&mut self) -> Option<Effect> {
+        let (started, last) = self.sounds.ringing?;
+        let waiting = self.pending_call_from.is_some() || self.pending_group_call.is_some();
+        if !waiting || started.elapsed() >= RING_FOR || !self.sounds.settings.enabled || self.dnd.is_some() {
+            self.sounds.ringing = None;
+            return None;
+        }
+        if last.elapsed() < RING_EVERY {
+            return None;
+        }
+        self.sounds.ringing = Some((started, Instant::now()));
+        match self.sounds.settings.call {
+            Sound::Off => None,
+            sound => Some(Effect::Sound(sound)),
+        }
+    }
+}
+
+impl ChatUI {
+    /// Use `settings` for which events make which sound
+    pub fn set_sounds(&mut self, settings: SoundSettings) {
+        self.state.sounds.settings = settings;
+    }
+
+    pub(super) fn play_sound(&mut self, sound: Sound) {
+        if sound == Sound::Tone && self.play_tone() {
+            return;
+        }
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(b"\x07");
+        let _ = stdout.flush();
+    }
+
+    /// Two quick notes, through the call's speakers if one is up. False if there are
+    /// no speakers to play them on.
+    fn play_tone(&mut self) -> bool {
+        let mut pcm = audio::tone(880.0, Duration::from_millis(90), 0.3);
+        pcm.extend(audio::tone(1320.0, Duration::from_millis(130), 0.3));
+        if let Some(ref pipeline) = self.audio_pipeline {
+            pipeline.play_pcm(pcm);
+            return true;
+        }
+        if self.speakers.is_none() {
+            match AudioPipeline::playback_only() {
+                Ok(pipeline) => self.speakers = Some((pipeline, Instant::now())),
+                Err(_) => return false,
+            }
+        }
+        if let Some((ref pipeline, ref mut last)) = self.speakers {
+            pipeline.play_pcm(pcm);
+            *last = Instant::now();
+        }
+        true
+    }
+
+    /// Close the speakers once tones have stopped (run from housekeeping)
+    pub(super) fn close_idle_speakers(&mut self) {
+        if self.speakers.as_ref().is_some_and(|(_, last)| last.elapsed() >= SPEAKERS_HOLD) {
+            self.speakers = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::CallSalt;
+
+    fn sounds(fx: &[Effect]) -> Vec<Sound> {
+        fx.iter().filter_map(|e| match e {
+            Effect::Sound(sound) => Some(*sound),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn test_sounds_follow_the_settings() {
+        let peer = "peer".repeat(8);
+        let mut state = ChatState::new("me".repeat(16), Some("me".to_string()), vec![0; 32]);
+        state.sounds.settings.mention = Sound::Tone;
+
+        // In the tab we're looking at: off by default
+        assert!(sounds(&state.ingest_message(PlainMessage::new(peer.clone(), "hi".to_string()))).is_empty());
+        let dm = || PlainMessage::direct(peer.clone(), "psst".to_string());
+        assert_eq!(sounds(&state.ingest_message(dm())), vec![Sound::Bell]);
+        let mention = PlainMessage::new(peer.clone(), "hey @me".to_string());
+        assert_eq!(sounds(&state.ingest_message(mention)), vec![Sound::Tone]);
+
+        // Events the config leaves out keep their defaults
+        state.sounds.settings = serde_json::from_str(r#"{ "dm": "tone", "call": "off" }"#).unwrap();
+        assert_eq!(sounds(&state.ingest_message(dm())), vec![Sound::Tone]);
+        assert_eq!(state.sounds.settings.mention, Sound::Bell);
+
+        state.handle_command("/sounds off");
+        assert!(sounds(&state.ingest_message(dm())).is_empty());
+        state.handle_command("/sounds on");
+        state.handle_command("/dnd on");
+        assert!(sounds(&state.ingest_message(dm())).is_empty());
+    }
+
+    #[test]
+    fn test_call_rings_until_answered() {
+        let peer = "peer".repeat(8);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let fx = state.ingest_message(PlainMessage::call_request(peer.clone(), CallSalt::generate("c1".to_string())));
+        assert_eq!(sounds(&fx), vec![Sound::Bell]);
+
+        // Not again straight away, but once RING_EVERY has passed
+        assert!(state.check_ringing().is_none());
+        let long_ago = Instant::now() - RING_EVERY;
+        state.sounds.ringing = Some((long_ago, long_ago));
+        assert!(matches!(state.check_ringing(), Some(Effect::Sound(Sound::Bell))));
+
+        state.handle_command("/reject-call");
+        state.sounds.ringing = Some((long_ago, long_ago));
+        assert!(state.check_ringing().is_none());
+        assert!(state.sounds.ringing.is_none());
+    }
+}
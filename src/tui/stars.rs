@@ -0,0 +1,153 @@
+//! Local bookmarks: `/star [n]` toggles a ⭐ on one of the tab's last messages, and
+//! `/starred` lists them across all tabs (`/starred <n>` jumps to one). Stars are kept
+//! by message id on this machine only; nothing about them is ever sent.
+
+use crate::protocol::PlainMessage;
+
+use super::mentions::estimated_lines;
+use super::state::ChatState;
+use super::types::Tab;
+
+impl ChatState {
+    /// Whether `m` carries a star
+    pub(crate) fn is_starred(&self, m: &PlainMessage) -> bool {
+        m.message_id.as_ref().is_some_and(|id| self.starred.contains(id))
+    }
+
+    /// Handle /star [n]: star or unstar the nth-last message in this tab (default the last)
+    pub(crate) fn handle_star_command(&mut self, args: &[&str]) {
+        let n = match args.first().map(|arg| arg.parse::<usize>()) {
+            None => 1,
+            Some(Ok(n)) if n > 0 => n,
+            Some(_) => {
+                self.status = "Usage: /star [n] — n counts back from the last message (1 is the last)".to_string();
+                return;
+            }
+        };
+        let tab = self.tabs[self.active_tab].clone();
+        let found = self.messages.get(&tab)
+            .and_then(|msgs| msgs.iter().rev().filter(|m| !m.system && m.message_id.is_some()).nth(n - 1));
+        let Some(m) = found else {
+            self.status = format!("No message {} back here to star", n);
+            return;
+        };
+        let Some(id) = m.message_id.clone() else {
+            return;
+        };
+        let snippet: String = m.content.lines().next().unwrap_or_default().chars().take(40).collect();
+        let text = self.attributed_text(m, &snippet);
+        self.status = if self.starred.remove(&id) {
+            format!("Unstarred {}", text)
+        } else {
+            self.starred.insert(id);
+            format!("⭐ Starred {} — /starred lists them", text)
+        };
+    }
+
+    /// Handle /starred [n]: list starred messages across all tabs, or jump to one
+    pub(crate) fn handle_starred_command(&mut self, args: &[&str]) {
+        match args.first() {
+            None => self.list_starred(),
+            Some(arg) => match arg.parse::<usize>().ok().and_then(|n| self.star_list.get(n.wrapping_sub(1)).cloned()) {
+                Some((tab, id)) => self.jump_to_starred(&tab, &id),
+                None => self.status = "No such starred message — run /starred to list them".to_string(),
+            },
+        }
+    }
+
+    fn list_starred(&mut self) {
+        // Tab order breaks ties between messages from the same second
+        let mut found: Vec<(&Tab, &PlainMessage)> = self.tabs.iter()
+            .filter_map(|tab| Some((tab, self.messages.get(tab)?)))
+            .flat_map(|(tab, msgs)| msgs.iter().filter(|m| self.is_starred(m)).map(move |m| (tab, m)))
+            .collect();
+        if found.is_empty() {
+            self.status = "No starred messages — /star [n] stars one".to_string();
+            return;
+        }
+        found.sort_by_key(|(_, m)| m.timestamp);
+
+        let mut lines = vec![format!("⭐ {} starred message(s) — /starred <n> to jump:", found.len())];
+        for (i, (tab, m)) in found.iter().enumerate() {
+            let time = chrono::DateTime::from_timestamp(m.timestamp, 0)
+                .map(|dt| dt.with_timezone(&chrono::Local).format("%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            let snippet: String = m.content.lines().next().unwrap_or_default().chars().take(60).collect();
+            lines.push(format!(
+                "  {}. [{}] {} — {}",
+                i + 1,
+                time,
+                self.get_tab_name(tab),
+                self.attributed_text(m, &snippet)
+            ));
+        }
+        self.star_list = found.iter()
+            .filter_map(|(tab, m)| Some(((*tab).clone(), m.message_id.clone()?)))
+            .collect();
+
+        let tab = self.tabs[self.active_tab].clone();
+        self.add_system_message(&tab, lines.join("\n"));
+    }
+
+    /// Focus `tab` and scroll so the starred message sits at the bottom of the view
+    fn jump_to_starred(&mut self, tab: &Tab, id: &str) {
+        let Some(msgs) = self.messages.get(tab) else {
+            self.status = "That conversation is gone".to_string();
+            return;
+        };
+        let Some(position) = msgs.iter().position(|m| m.message_id.as_deref() == Some(id)) else {
+            self.status = "That message is gone (expired or cleared)".to_string();
+            return;
+        };
+        let lines_below: usize = msgs[position + 1..].iter().map(estimated_lines).sum();
+
+        if let Some(idx) = self.tabs.iter().position(|t| t == tab) {
+            self.set_active_tab(idx);
+        }
+        self.scroll_offset.insert(tab.clone(), lines_below);
+        self.status = format!("Jumped to a starred message in {}", self.get_tab_name(tab));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+
+    #[test]
+    fn test_stars_toggle_list_and_jump() {
+        let alice = "aa".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+        state.ingest_message(PlainMessage::new(alice.clone(), "we ship friday".to_string()));
+        state.ingest_message(PlainMessage::new(alice.clone(), "lunch?".to_string()));
+        state.ingest_message(PlainMessage::direct(alice.clone(), "the link: example.org".to_string()));
+
+        // Counted back from the last message; system lines don't count
+        state.handle_command("/star 2");
+        assert!(state.status.starts_with("⭐ Starred alice: we ship friday"), "{}", state.status);
+        let dm = Tab::DirectMessage(alice.clone());
+        state.set_active_tab(state.tabs.iter().position(|t| *t == dm).unwrap());
+        state.handle_command("/star");
+        assert_eq!(state.starred.len(), 2);
+        state.handle_command("/star 5");
+        assert_eq!(state.status, "No message 5 back here to star");
+
+        state.handle_command("/starred");
+        let listed = state.messages[&dm].last().unwrap().content.clone();
+        assert!(listed.starts_with("⭐ 2 starred message(s)"), "{}", listed);
+        assert!(listed.contains("1. [") && listed.contains("#global — alice: we ship friday"), "{}", listed);
+        assert!(listed.contains("— alice: the link: example.org"), "{}", listed);
+
+        // Jumping switches tab and scrolls up past what came after it
+        state.handle_command("/starred 1");
+        assert_eq!(state.tabs[state.active_tab], Tab::Global);
+        assert_eq!(state.scroll_offset[&Tab::Global], 1);
+
+        // Starring again takes the star off
+        state.handle_command("/star 2");
+        assert!(state.status.starts_with("Unstarred"), "{}", state.status);
+        assert_eq!(state.starred.len(), 1);
+    }
+}
@@ -0,0 +1,1557 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::audio::AudioSupport;
+use crate::client::{InviteLink, OutgoingMessage, PeerDisplay, PeerUpdate};
+use crate::crypto::safety_number::key_fingerprint;
+use crate::protocol::{MemberHint, Message, PlainMessage};
+use crate::strings::Key;
+
+use super::archive;
+use super::away::Away;
+use super::bandwidth::Bandwidth;
+use super::call_keys::CallKeys;
+use super::hold::HeldCall;
+use super::churn::Churn;
+use super::control::control_of;
+use super::transfer_control::{Sending, DEFAULT_CALL_FILE_RATE};
+use super::contacts::ContactPolicy;
+use super::dnd::Dnd;
+use super::downloads::{Download, DEFAULT_MAX_AUTO_SIZE};
+use super::group_seq::GroupOrder;
+use super::low_bandwidth::{DeferredFile, TrafficPolicy};
+use super::offer_rows::OfferRow;
+use super::pipe::Pipe;
+use super::sounds::{Sound, Sounds};
+use super::stats::SessionTally;
+use super::types::{
+    ActiveTransfer, CallState, GroupInfo, OutgoingTransfer, PartialMessage, PendingFileOffer,
+    PendingInvite, PendingVerification, ReadStatus, Tab, Verified,
+};
+
+/// Side effects requested by a state transition, applied by the run loop.
+/// Status line and tab changes are written straight into `ChatState`; only
+/// work that touches channels or devices is deferred here.
+// Effects are drained right after they're produced, so variant size doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum Effect {
+    /// Queue a message on the outbound channel
+    Send(OutgoingMessage),
+    /// Stream an accepted file to its recipient(s) in the background
+    StreamFile { file_id: String, transfer: OutgoingTransfer },
+    /// Open the audio devices for the call that was just entered
+    StartAudio,
+    /// Close the audio devices
+    StopAudio,
+    /// Post /stats, with the client's and audio pipeline's counters filled in
+    ShowStats,
+    /// Put up the key binding overlay
+    ShowKeys,
+    /// Put up this text as a QR code (/invite-link)
+    ShowQr(String),
+    /// Ring the bell or play a tone
+    Sound(Sound),
+    /// Keep a /nick in the config file
+    SaveNickname(String),
+    /// Start following a file or named pipe for /pipe (stopping any before it)
+    StartPipe(PathBuf),
+    /// Stop following it
+    StopPipe,
+    /// Low-bandwidth mode went on (true) or off: reconnect backoff follows it
+    LowBandwidth(bool),
+}
+
+/// Everything the chat knows about the session, independent of the terminal.
+/// The renderer only reads it; keys, commands and incoming messages mutate it.
+pub struct ChatState {
+    pub(crate) tabs: Vec<Tab>,
+    pub(crate) active_tab: usize,
+    pub(crate) messages: HashMap<Tab, Vec<PlainMessage>>,
+    /// Messages received in tabs that weren't focused
+    pub(crate) unread: HashMap<Tab, usize>,
+    /// Of those, the ones that mention us
+    pub(crate) mention_unread: HashMap<Tab, usize>,
+    /// What the last /mentions listed: (tab, timestamp, sender), for /mentions <n>
+    pub(crate) mention_list: Vec<(Tab, i64, String)>,
+    /// Ids of the messages we starred (kept on this machine only)
+    pub(crate) starred: HashSet<String>,
+    /// What the last /starred listed: (tab, message id), for /starred <n>
+    pub(crate) star_list: Vec<(Tab, String)>,
+    pub(crate) status: String,
+    pub(crate) peers: HashMap<String, PeerDisplay>,
+    /// Peers the client counts as gone for now, listed apart in the sidebar
+    pub(crate) recently_seen: HashSet<String>,
+    /// Whether the sidebar lists them, or just says how many (/recent)
+    pub(crate) show_recently_seen: bool,
+    /// Sessions asked for with Discover, and when
+    pub(crate) discovering: HashMap<String, Instant>,
+    /// Names group invites gave for members whose own nickname hasn't reached us yet
+    pub(crate) name_hints: HashMap<String, MemberHint>,
+    /// Ids of each peer's latest messages, oldest first, so a redelivered one is dropped
+    pub(crate) seen_ids: HashMap<String, VecDeque<String>>,
+    pub(crate) own_id: String,
+    pub(crate) own_nickname: Option<String>,
+    /// Our own identity public key (for safety number computation)
+    pub(crate) own_public_key: Vec<u8>,
+    /// Short fingerprint of `own_public_key` for the header (stable across sessions)
+    pub(crate) own_fingerprint: String,
+    /// Named profile we run as (`--profile`), shown in the header
+    pub(crate) profile: Option<String>,
+    /// `--ephemeral`: nothing goes to disk without a refusal or a loud warning
+    pub(crate) ephemeral: bool,
+    /// Verified identities, keyed by identity public key so they outlive session ids
+    pub(crate) verified_peers: HashMap<Vec<u8>, Verified>,
+    /// Peers whose verified identity key was swapped for one we haven't verified:
+    /// nothing is sent their way until the new key is
+    pub(crate) untrusted: HashSet<String>,
+    /// What verified contacts get without asking, keyed by identity public key too
+    pub(crate) contact_policies: HashMap<Vec<u8>, ContactPolicy>,
+    /// A call the contact policy will answer, and when
+    pub(crate) auto_answer: Option<(String, Instant)>,
+    /// Verification rounds in progress, by peer session id
+    pub(crate) verifications: HashMap<String, PendingVerification>,
+    pub(crate) pending_offers: HashMap<String, PendingFileOffer>,
+    /// What low-bandwidth mode lets out right now
+    pub(crate) traffic: TrafficPolicy,
+    /// Accepted files it's holding back, oldest first
+    pub(crate) deferred_files: Vec<DeferredFile>,
+    /// How each file offer's chat row starts, and who answered ours, by file id
+    pub(crate) offer_rows: HashMap<String, OfferRow>,
+    pub(crate) active_transfers: HashMap<String, ActiveTransfer>,
+    /// Where a bare /accept saves
+    pub(crate) download_dir: PathBuf,
+    /// Largest offer a bare /accept takes; bigger ones need a path
+    pub(crate) max_auto_size: u64,
+    /// Files saved this session, for /downloads
+    pub(crate) downloads: Vec<Download>,
+    pub(crate) outgoing_transfers: HashMap<String, OutgoingTransfer>,
+    pub(crate) groups: HashMap<String, GroupInfo>,
+    /// Identity keys of group members we have no session with yet, as an invite or
+    /// another member's roster gave them: group id -> session id -> key
+    pub(crate) member_keys: HashMap<String, HashMap<String, Vec<u8>>>,
+    /// Group invites waiting for /join or /decline, oldest first
+    pub(crate) pending_invites: Vec<PendingInvite>,
+    /// The file or named pipe /pipe is posting from
+    pub(crate) pipe: Option<Pipe>,
+    /// Join invites from verified peers without asking (config `auto_join_verified`)
+    pub(crate) auto_join_verified: bool,
+    // Voice call state
+    pub(crate) active_call: Option<CallState>,
+    pub(crate) pending_call_from: Option<String>,
+    /// Who we last missed a call from, for /callback
+    pub(crate) missed_call_from: Option<String>,
+    pub(crate) pending_group_call: Option<(String, String)>,
+    /// Salts and keys of the call we're placing, being offered or in
+    pub(crate) call_keys: Option<CallKeys>,
+    /// The call we put on hold
+    pub(crate) held_call: Option<HeldCall>,
+    /// A direct call that rang while we were in one, for /swap
+    pub(crate) call_waiting: Option<CallKeys>,
+    /// Whether the audio devices (and the build) allow calls
+    pub(crate) audio_support: AudioSupport,
+    // Scroll state per tab (0 = at bottom)
+    pub(crate) scroll_offset: HashMap<Tab, usize>,
+    // Typing indicators: peer_id -> last typing timestamp
+    pub(crate) typing_peers: HashMap<String, Instant>,
+    pub(crate) last_typing_sent: Option<Instant>,
+    // Read receipts: message_id -> ReadStatus
+    pub(crate) read_status: HashMap<String, ReadStatus>,
+    /// Disappearing-message TTL per conversation, in seconds
+    pub(crate) expiry: HashMap<Tab, u64>,
+    /// Split messages being reassembled, by (sender, part group id)
+    pub(crate) partial_messages: HashMap<(String, String), PartialMessage>,
+    /// Ids of long messages the user chose to see in full
+    pub(crate) expanded: HashSet<String>,
+    /// Largest file or directory `/send` will offer
+    pub(crate) max_share_bytes: u64,
+    /// Whether we ask for and share group history after reconnects
+    pub(crate) history_sync: bool,
+    /// Catch-ups in flight: group id -> (member asked, messages merged so far)
+    pub(crate) history_pending: HashMap<String, (String, usize)>,
+    /// When we last answered a catch-up, by (peer, group id)
+    pub(crate) history_served: HashMap<(String, String), Instant>,
+    /// Sessions in each group's relay room, as last reported by the relay
+    pub(crate) room_presence: HashMap<String, u32>,
+    /// Totals for /stats
+    pub(crate) tally: SessionTally,
+    /// Set while we're away from the keyboard
+    pub(crate) away: Option<Away>,
+    /// Last keypress, for going away when idle
+    pub(crate) last_input: Instant,
+    /// Idle time before we go away by ourselves (None = never)
+    pub(crate) away_after: Option<Duration>,
+    /// Sent to whoever DMs us while we're away (None = no auto-reply)
+    pub(crate) away_reply: Option<String>,
+    /// Peers already auto-replied to during this absence
+    pub(crate) away_replied: HashSet<String>,
+    /// Peers who are away, with their away message if they gave one
+    pub(crate) peer_away: HashMap<String, Option<String>>,
+    /// Set while do not disturb is on
+    pub(crate) dnd: Option<Dnd>,
+    /// When each peer last got an `@urgent` DM through do not disturb
+    pub(crate) urgent_allowed: HashMap<String, Instant>,
+    /// Live transfer rate and the session data warning
+    pub(crate) bandwidth: Bandwidth,
+    /// Which events make a sound, and any owed or ringing
+    pub(crate) sounds: Sounds,
+    /// Group message numbering, in and out
+    pub(crate) group_order: GroupOrder,
+    /// Lobby-less mode: Global only shows notices, and nothing is sent there
+    pub(crate) no_global: bool,
+    /// Whether this session's first Global send with strangers around was confirmed
+    pub(crate) global_confirmed: bool,
+    /// Text to put back in the input box (a held Global send)
+    pub(crate) held_input: Option<String>,
+    /// Messages added since `--plain` last printed (None in the full-screen UI)
+    pub(crate) transcript: Option<Vec<(Tab, PlainMessage)>>,
+    /// Join/leave notices held back while the relay churns
+    pub(crate) churn: Churn,
+    /// The relay we're connected to, for /invite-link
+    pub(crate) relay_url: Option<String>,
+    /// The invite link we were started with, until its owner turns up
+    pub(crate) awaiting_invite: Option<InviteLink>,
+    /// Files whose chunks are going out, for /pause and /limit
+    pub(crate) sending: Vec<Sending>,
+    /// What files are slowed to during a call, in bytes a second (0: not at all)
+    pub(crate) call_file_rate: u64,
+}
+
+impl ChatState {
+    pub fn new(own_id: String, nickname: Option<String>, own_public_key: Vec<u8>) -> Self {
+        let mut messages = HashMap::new();
+        messages.insert(Tab::Global, Vec::new());
+
+        Self {
+            tabs: vec![Tab::Global],
+            active_tab: 0,
+            messages,
+            unread: HashMap::new(),
+            mention_unread: HashMap::new(),
+            mention_list: Vec::new(),
+            starred: HashSet::new(),
+            star_list: Vec::new(),
+            status: "Connecting...".to_string(),
+            peers: HashMap::new(),
+            recently_seen: HashSet::new(),
+            show_recently_seen: false,
+            name_hints: HashMap::new(),
+            seen_ids: HashMap::new(),
+            discovering: HashMap::new(),
+            own_id,
+            own_nickname: nickname,
+            own_fingerprint: key_fingerprint(&own_public_key).short_numeric(),
+            profile: None,
+            ephemeral: false,
+            own_public_key,
+            verified_peers: HashMap::new(),
+            untrusted: HashSet::new(),
+            contact_policies: HashMap::new(),
+            auto_answer: None,
+            verifications: HashMap::new(),
+            pending_offers: HashMap::new(),
+            traffic: TrafficPolicy::Normal,
+            deferred_files: Vec::new(),
+            offer_rows: HashMap::new(),
+            active_transfers: HashMap::new(),
+            download_dir: crate::util::default_download_dir(),
+            max_auto_size: DEFAULT_MAX_AUTO_SIZE,
+            downloads: Vec::new(),
+            outgoing_transfers: HashMap::new(),
+            groups: HashMap::new(),
+            member_keys: HashMap::new(),
+            pending_invites: Vec::new(),
+            pipe: None,
+            auto_join_verified: false,
+            active_call: None,
+            pending_call_from: None,
+            missed_call_from: None,
+            pending_group_call: None,
+            call_keys: None,
+            held_call: None,
+            call_waiting: None,
+            audio_support: AudioSupport::Full,
+            scroll_offset: HashMap::new(),
+            typing_peers: HashMap::new(),
+            last_typing_sent: None,
+            read_status: HashMap::new(),
+            expiry: HashMap::new(),
+            partial_messages: HashMap::new(),
+            expanded: HashSet::new(),
+            max_share_bytes: archive::DEFAULT_MAX_SHARE_BYTES,
+            history_sync: true,
+            history_pending: HashMap::new(),
+            history_served: HashMap::new(),
+            room_presence: HashMap::new(),
+            tally: SessionTally::default(),
+            away: None,
+            last_input: Instant::now(),
+            away_after: None,
+            away_reply: None,
+            away_replied: HashSet::new(),
+            peer_away: HashMap::new(),
+            dnd: None,
+            urgent_allowed: HashMap::new(),
+            bandwidth: Bandwidth::default(),
+            sounds: Sounds::default(),
+            group_order: GroupOrder::default(),
+            no_global: false,
+            global_confirmed: false,
+            held_input: None,
+            transcript: None,
+            churn: Churn::default(),
+            relay_url: None,
+            awaiting_invite: None,
+            sending: Vec::new(),
+            call_file_rate: DEFAULT_CALL_FILE_RATE,
+        }
+    }
+
+    /// Handle a line typed into the input box (a /command or a chat message)
+    pub fn handle_command(&mut self, text: &str) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        self.handle_input(text.to_string(), &mut fx);
+        fx
+    }
+
+    /// Route one incoming message to signaling handlers or the right tab
+    pub fn ingest_message(&mut self, msg: PlainMessage) -> Vec<Effect> {
+        let mut fx = self.ingest(msg);
+        fx.extend(self.take_chime());
+        fx
+    }
+
+    fn ingest(&mut self, msg: PlainMessage) -> Vec<Effect> {
+        let mut fx = Vec::new();
+
+        // Long messages arrive in parts; nothing is shown until all of them are in
+        let Some(mut msg) = self.collect_part(msg) else {
+            return fx;
+        };
+        if self.seen_before(&msg) {
+            return fx;
+        }
+        // Control messages go to their handlers; only chat gets past here
+        if let Some(control) = control_of(&msg) {
+            self.handle_control(control, msg, &mut fx);
+            return fx;
+        }
+        self.mark_mention(&mut msg);
+
+        // Clear typing indicator for sender (they sent a real message)
+        self.typing_peers.remove(&msg.sender);
+
+        // Track read status for own messages
+        if msg.sender == self.own_id {
+            if let Some(ref msg_id) = msg.message_id {
+                self.read_status.entry(msg_id.clone()).or_insert(ReadStatus::Sent);
+            }
+        }
+
+        // Handle group messages
+        if let Some(ref group_id) = msg.group_id {
+            let group_tab = Tab::Group(group_id.clone());
+            if msg.group_seq.is_some() && msg.sender != self.own_id && !msg.synced {
+                self.order_group_message(msg);
+            } else {
+                self.push_message(group_tab, msg);
+            }
+            return fx;
+        }
+
+        if msg.system && msg.direct && !msg.content.is_empty() {
+            // A notice meant for our DM with the sender (e.g. "X is in do-not-disturb")
+            self.push_message(Tab::DirectMessage(msg.sender.clone()), msg);
+        } else if msg.system && !msg.content.is_empty() {
+            // Joins and leaves may be held back and summed up while peers churn
+            if let Some(msg) = self.hold_presence(msg, Instant::now()) {
+                self.push_message(Tab::Global, msg);
+            }
+        } else if !msg.system {
+            if msg.direct {
+                let dm_tab = Tab::DirectMessage(msg.sender.clone());
+                let sender = msg.sender.clone();
+                self.check_urgent(&msg);
+                self.push_message(dm_tab, msg);
+                self.auto_reply(&sender, &mut fx);
+            } else {
+                self.push_message(Tab::Global, msg);
+            }
+        }
+        fx
+    }
+
+    /// A peer opened a DM with us: give it a tab, if it hasn't one yet
+    pub(crate) fn dm_requested(&mut self, sender: &str) {
+        let dm_tab = Tab::DirectMessage(sender.to_string());
+        if !self.tabs.contains(&dm_tab) {
+            self.ensure_tab(&dm_tab);
+            let peer_name = self.get_peer_display_name(sender);
+            self.flash_status(format!("{} opened a DM with you", peer_name));
+        }
+    }
+
+        /// Apply a batch of peer list changes from the client
+    pub fn apply_peer_updates(&mut self, updates: Vec<PeerUpdate>) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        let mut new_peers = Vec::new();
+        for update in updates {
+            let inactive = matches!(update, PeerUpdate::Inactive(..));
+            match update {
+                PeerUpdate::Added(id, peer) | PeerUpdate::Changed(id, peer) | PeerUpdate::Inactive(id, peer) => {
+                    if inactive {
+                        self.recently_seen.insert(id.clone());
+                    } else {
+                        self.recently_seen.remove(&id);
+                    }
+                    let key = peer.public_key.clone();
+                    // The peer's own word (or a different key) settles any name we were given
+                    if peer.nickname.is_some() || self.name_hints.get(&id).is_some_and(|hint| hint.public_key != key) {
+                        self.name_hints.remove(&id);
+                    }
+                    match self.peers.insert(id.clone(), peer) {
+                        Some(old) if old.public_key != key => {
+                            self.distrust_new_key(&id, &old.public_key);
+                            self.forget_policy(&id, &old.public_key);
+                        }
+                        Some(_) => {}
+                        None if !inactive => new_peers.push(id),
+                        None => {}
+                    }
+                }
+                PeerUpdate::Removed(id) => {
+                    self.peers.remove(&id);
+                    self.recently_seen.remove(&id);
+                    self.peer_away.remove(&id);
+                    self.name_hints.remove(&id);
+                    self.seen_ids.remove(&id);
+                }
+            }
+        }
+        for id in &new_peers {
+            self.discovered(id, &mut fx);
+            self.invite_peer_up(id, &mut fx);
+            self.member_session_up(id, &mut fx);
+        }
+        self.announce_away_to(&new_peers, &mut fx);
+        fx
+    }
+
+    /// Peers in sidebar order: verified first, then by display name. Those recently
+    /// seen are listed apart.
+    pub(crate) fn sidebar_peers(&self) -> Vec<String> {
+        let mut peers: Vec<(bool, String, &String)> = self.peers.keys()
+            .filter(|id| !self.recently_seen.contains(*id))
+            .map(|id| (self.verification_of(id).is_none(), self.get_peer_display_name(id).to_lowercase(), id))
+            .collect();
+        peers.sort();
+        peers.into_iter().map(|(_, _, id)| id.clone()).collect()
+    }
+
+    /// Typing indicators for the current tab's peers (debounced, bypasses ratchet)
+    pub(crate) fn typing_indicator(&mut self) -> Vec<Effect> {
+        if !self.traffic.allows_typing() {
+            return Vec::new();
+        }
+        let now = Instant::now();
+        // Debounce: only send every 3 seconds
+        if let Some(last) = self.last_typing_sent {
+            if now.duration_since(last).as_secs() < 3 {
+                return Vec::new();
+            }
+        }
+        self.last_typing_sent = Some(now);
+
+        let targets: Vec<String> = match &self.tabs[self.active_tab] {
+            Tab::DirectMessage(peer_id) => vec![peer_id.clone()],
+            Tab::Group(group_id) => self.groups.get(group_id)
+                .map(|g| g.members.clone())
+                .unwrap_or_default(),
+            // Send to all peers (unless Global is only notices)
+            Tab::Global if self.no_global => Vec::new(),
+            Tab::Global => self.peers.keys().cloned().collect(),
+        };
+
+        targets.into_iter().map(|target| {
+            Effect::Send(OutgoingMessage::Signal(Message::Typing {
+                from: self.own_id.clone(),
+                target,
+                is_typing: true,
+            }))
+        }).collect()
+    }
+
+    /// Read receipts for visible messages in the current tab (bypasses ratchet)
+    pub(crate) fn read_receipts(&mut self) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        if !self.traffic.allows_receipts() {
+            return fx;
+        }
+        let current_tab = &self.tabs[self.active_tab];
+        let Some(messages) = self.messages.get(current_tab) else {
+            return fx;
+        };
+
+        for msg in messages {
+            if msg.sender == self.own_id || msg.system {
+                continue;
+            }
+            if let Some(ref msg_id) = msg.message_id {
+                if self.read_status.get(msg_id) == Some(&ReadStatus::Read) {
+                    continue; // Already sent read receipt
+                }
+                // Mark as read and send receipt via signal (no ratchet)
+                self.read_status.insert(msg_id.clone(), ReadStatus::Read);
+
+                fx.push(Effect::Send(OutgoingMessage::Signal(Message::ReadReceipt {
+                    from: self.own_id.clone(),
+                    target: msg.sender.clone(),
+                    message_id: msg_id.clone(),
+                })));
+            }
+        }
+        fx
+    }
+
+    /// A recipient confirmed one of our messages: ⏳ becomes ✓ (a read receipt may have beaten it)
+    pub(crate) fn mark_delivered(&mut self, message_id: &str) {
+        if let Some(status) = self.read_status.get_mut(message_id) {
+            if *status == ReadStatus::Pending {
+                *status = ReadStatus::Sent;
+            }
+        }
+    }
+
+    /// What peers should hear before we quit: hang up any call (so nobody is left
+    /// talking to a ghost), leave every group room, and say goodbye
+    pub(crate) fn farewell(&mut self) -> Vec<Effect> {
+        let mut fx = Vec::new();
+        if self.active_call.is_some() {
+            self.handle_hangup_command(&mut fx);
+        }
+        if self.held_call.is_some() {
+            self.on_held_call(|state| state.handle_hangup_command(&mut fx));
+        }
+        for group_id in self.groups.keys() {
+            fx.push(Effect::Send(OutgoingMessage::LeaveRoom { group_id: group_id.clone() }));
+        }
+        let leave_msg = PlainMessage {
+            message_id: Some(PlainMessage::generate_id()),
+            ..PlainMessage::notice(self.own_id.clone(), Key::Left, &[("name", &self.display_name())])
+        };
+        fx.push(Effect::Send(OutgoingMessage::Global(leave_msg)));
+        fx
+    }
+
+    /// Clean up expired typing indicators (>5 seconds old). Returns true if any expired.
+    pub(crate) fn cleanup_typing_indicators(&mut self) -> bool {
+        let now = Instant::now();
+        let before = self.typing_peers.len();
+        self.typing_peers.retain(|_, instant| {
+            now.duration_since(*instant).as_secs() < 5
+        });
+        self.typing_peers.len() != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{short_id, CallSalt, ChatEvent, FileOffer, GroupInvite, HistorySync};
+    use crate::tui::types::{CallType, FILE_CHUNK_SIZE};
+
+    const ME: &str = "me000000000000000000";
+    const ALICE: &str = "alice000000000000000";
+    const BOB: &str = "bob00000000000000000";
+
+    fn state() -> ChatState {
+        let mut state = ChatState::new(ME.to_string(), Some("me".to_string()), vec![0; 32]);
+        state.apply_peer_updates(vec![peer(ALICE, "alice", 1), peer(BOB, "bob", 1)]);
+        // Past the first-Global-send warning (see lobby.rs)
+        state.global_confirmed = true;
+        state
+    }
+
+    fn peer(id: &str, nick: &str, key: u8) -> PeerUpdate {
+        PeerUpdate::Added(id.to_string(), PeerDisplay { nickname: Some(nick.to_string()), public_key: vec![key; 32] })
+    }
+
+    fn sent(fx: &[Effect]) -> Vec<&OutgoingMessage> {
+        fx.iter().filter_map(|e| match e {
+            Effect::Send(msg) => Some(msg),
+            _ => None,
+        }).collect()
+    }
+
+    fn tab_len(state: &ChatState, tab: &Tab) -> usize {
+        state.messages.get(tab).map(|m| m.len()).unwrap_or(0)
+    }
+
+    fn join_group(state: &mut ChatState, group_id: &str) {
+        let invite = PlainMessage::group_invite_msg(ALICE.to_string(), GroupInvite {
+            group_id: group_id.to_string(),
+            group_name: "friends".to_string(),
+            join_token: None,
+            members: Vec::new(),
+        });
+        state.ingest_message(invite);
+        state.handle_command("/join");
+    }
+
+    #[test]
+    fn test_direct_message_opens_tab() {
+        let mut state = state();
+        state.ingest_message(PlainMessage::direct(ALICE.to_string(), "hi".to_string()));
+
+        let dm_tab = Tab::DirectMessage(ALICE.to_string());
+        assert!(state.tabs.contains(&dm_tab));
+        assert_eq!(tab_len(&state, &dm_tab), 1);
+        assert_eq!(tab_len(&state, &Tab::Global), 0);
+        assert_eq!(state.unread.get(&dm_tab), Some(&1));
+        // Focus stays where the user left it
+        assert_eq!(state.active_tab, 0);
+    }
+
+    #[test]
+    fn test_dm_request_opens_empty_tab_once() {
+        let mut state = state();
+        state.ingest_message(PlainMessage::dm_request(ALICE.to_string()));
+        state.ingest_message(PlainMessage::dm_request(ALICE.to_string()));
+
+        let dm_tab = Tab::DirectMessage(ALICE.to_string());
+        assert_eq!(state.tabs.iter().filter(|t| **t == dm_tab).count(), 1);
+        assert_eq!(tab_len(&state, &dm_tab), 0);
+        assert!(state.status.contains("alice opened a DM"));
+    }
+
+    #[test]
+    fn test_dm_command_sends_request() {
+        let mut state = state();
+        let fx = state.handle_command("/dm bob");
+
+        assert_eq!(state.tabs[state.active_tab], Tab::DirectMessage(BOB.to_string()));
+        assert!(matches!(
+            sent(&fx)[..],
+            [OutgoingMessage::Direct { target_id, message }] if target_id == BOB && message.dm_request
+        ));
+    }
+
+    #[test]
+    fn test_group_id_routes_to_group_tab() {
+        let mut state = state();
+        join_group(&mut state, "g1");
+        let global_len = tab_len(&state, &Tab::Global);
+        // Direct flag is irrelevant once a group id is set
+        let mut msg = PlainMessage::group(ALICE.to_string(), "hello".to_string(), "g1".to_string());
+        msg.direct = true;
+        state.ingest_message(msg);
+
+        let group_tab = Tab::Group("g1".to_string());
+        assert_eq!(state.messages[&group_tab].last().unwrap().content, "hello");
+        assert!(!state.tabs.contains(&Tab::DirectMessage(ALICE.to_string())));
+        assert_eq!(tab_len(&state, &Tab::Global), global_len);
+    }
+
+    #[test]
+    fn test_group_invite_joins_room() {
+        let mut state = state();
+        let invite = PlainMessage::group_invite_msg(ALICE.to_string(), GroupInvite {
+            group_id: "g1".to_string(),
+            group_name: "friends".to_string(),
+            join_token: Some(vec![7; 32]),
+            members: Vec::new(),
+        });
+        // Nothing happens until we say yes
+        assert!(state.ingest_message(invite).is_empty());
+        assert!(state.status.ends_with("/join or /decline"));
+        let fx = state.handle_command("/join");
+
+        assert!(matches!(
+            sent(&fx)[..],
+            [OutgoingMessage::JoinRoom { group_id, join_token: Some(token) }, OutgoingMessage::Direct { target_id, message }]
+                if group_id == "g1" && token == &vec![7; 32] && target_id == ALICE && message.group_roster.is_some()
+        ));
+        assert_eq!(state.groups["g1"].members, vec![ALICE.to_string()]);
+
+        // Our own invites pass the same token on
+        state.active_tab = state.tabs.iter().position(|t| t == &Tab::Group("g1".to_string())).unwrap();
+        let fx = state.handle_command("/group invite bob");
+        assert!(matches!(
+            sent(&fx)[..],
+            [OutgoingMessage::Direct { message: PlainMessage { group_invite: Some(GroupInvite { join_token: Some(token), .. }), .. }, .. }] if token == &vec![7; 32]
+        ));
+    }
+
+    #[test]
+    fn test_invite_names_members_until_they_do() {
+        const CAROL: &str = "carol000000000000000";
+        const DAN: &str = "dan00000000000000000";
+        const ERIN: &str = "erin0000000000000000";
+        let hint = |id: &str, nick: &str, key: u8| MemberHint {
+            session_id: id.to_string(),
+            nickname: nick.to_string(),
+            public_key: vec![key; 32],
+        };
+        let mut state = state();
+        let invite = PlainMessage::group_invite_msg(ALICE.to_string(), GroupInvite {
+            group_id: "g1".to_string(),
+            group_name: "friends".to_string(),
+            join_token: None,
+            members: vec![hint(CAROL, "carol", 3), hint(DAN, "dan", 4), hint(BOB, "notbob", 1)],
+        });
+        state.ingest_message(invite);
+        state.handle_command("/join");
+        assert_eq!(state.get_peer_display_name(CAROL), "~carol");
+        assert_eq!(state.get_peer_display_name(BOB), "bob");
+
+        // Key exchange done but no nickname yet: the hint still stands, unless the key differs
+        let nameless = |id: &str, key: u8| PeerUpdate::Added(id.to_string(), PeerDisplay { nickname: None, public_key: vec![key; 32] });
+        state.apply_peer_updates(vec![nameless(CAROL, 3), nameless(DAN, 5)]);
+        assert_eq!(state.unconfirmed_name(CAROL), Some("carol"));
+        assert_eq!(state.get_peer_display_name(DAN), short_id(DAN));
+
+        // Carol's own nickname wins and settles it
+        state.apply_peer_updates(vec![peer(CAROL, "caz", 3)]);
+        assert_eq!(state.get_peer_display_name(CAROL), "caz");
+        assert!(state.name_hints.is_empty());
+
+        // Dan's session key isn't the one alice's invite gave
+        let flagged = state.messages[&Tab::Group("g1".to_string())].iter()
+            .any(|m| m.content.contains("inconsistent: dan000000000's identity key is not the one"));
+        assert!(flagged);
+
+        // Our invites pass on every member's key, with the names we have first-hand
+        assert_eq!(state.groups["g1"].members, vec![ALICE.to_string(), CAROL.to_string(), DAN.to_string(), BOB.to_string()]);
+        state.apply_peer_updates(vec![peer(ERIN, "erin", 6)]);
+        state.active_tab = state.tabs.iter().position(|t| t == &Tab::Group("g1".to_string())).unwrap();
+        let fx = state.handle_command("/group invite erin");
+        let [OutgoingMessage::Direct { message: PlainMessage { group_invite: Some(invite), .. }, .. }] = &sent(&fx)[..] else {
+            panic!("no invite sent");
+        };
+        assert_eq!(invite.members, vec![hint(ALICE, "alice", 1), hint(CAROL, "caz", 3), hint(DAN, short_id(DAN), 5), hint(BOB, "bob", 1)]);
+    }
+
+    #[test]
+    fn test_group_create_sets_join_token() {
+        let mut state = state();
+        let fx = state.handle_command("/group create friends");
+        let Some(OutgoingMessage::JoinRoom { group_id, join_token: Some(token) }) = sent(&fx).first().copied() else {
+            panic!("expected a join with a token");
+        };
+        assert_eq!(token.len(), crate::protocol::JOIN_TOKEN_LEN);
+        assert_eq!(state.groups[group_id].join_token.as_ref(), Some(token));
+    }
+
+    #[test]
+    fn test_long_message_is_split_and_reassembled() {
+        let mut sender = state();
+        sender.handle_command("/dm bob");
+        let text = "line\n".repeat(5000);
+        let fx = sender.handle_command(&text);
+        let parts: Vec<PlainMessage> = sent(&fx).into_iter().map(|m| match m {
+            OutgoingMessage::Direct { message, .. } => PlainMessage { sender: ALICE.to_string(), ..message.clone() },
+            other => panic!("unexpected {:?}", other),
+        }).collect();
+        assert_eq!(parts.len(), 4);
+        // Our own copy is shown whole
+        assert_eq!(sender.messages[&Tab::DirectMessage(BOB.to_string())].last().unwrap().content, text);
+
+        // Out of order, with another message in between: shown once, whole
+        let mut receiver = state();
+        let dm_tab = Tab::DirectMessage(ALICE.to_string());
+        receiver.ingest_message(parts[3].clone());
+        receiver.ingest_message(parts[1].clone());
+        receiver.ingest_message(PlainMessage::direct(ALICE.to_string(), "short".to_string()));
+        receiver.ingest_message(parts[0].clone());
+        assert_eq!(tab_len(&receiver, &dm_tab), 1);
+        receiver.ingest_message(parts[2].clone());
+        let msgs = &receiver.messages[&dm_tab];
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[1].content, text);
+        assert!(msgs[1].part_total.is_none());
+        assert!(receiver.partial_messages.is_empty());
+    }
+
+    #[test]
+    fn test_stale_partial_messages_expire() {
+        let mut state = state();
+        let parts = PlainMessage::direct(ALICE.to_string(), "x".repeat(20_000)).into_parts();
+        state.ingest_message(parts[0].clone());
+        assert!(!state.expire_partial_messages());
+        for partial in state.partial_messages.values_mut() {
+            partial.started -= std::time::Duration::from_secs(61);
+        }
+        assert!(state.expire_partial_messages());
+        // A late part starts over rather than completing anything
+        state.ingest_message(parts[1].clone());
+        assert_eq!(tab_len(&state, &Tab::DirectMessage(ALICE.to_string())), 0);
+    }
+
+    #[test]
+    fn test_expand_toggles_long_message() {
+        let mut state = state();
+        state.handle_command("/dm alice");
+        assert_eq!(state.handle_command("/expand").len(), 0);
+        assert_eq!(state.status, "Nothing to expand");
+
+        state.ingest_message(PlainMessage::direct(ALICE.to_string(), "row\n".repeat(100)));
+        state.handle_command("/expand");
+        let id = state.messages[&Tab::DirectMessage(ALICE.to_string())][0].message_id.clone().unwrap();
+        assert!(state.expanded.contains(&id));
+        state.handle_command("/expand 1");
+        assert!(!state.expanded.contains(&id));
+        state.handle_command("/expand 9");
+        assert!(state.status.contains("No message 9"));
+    }
+
+    #[test]
+    fn test_farewell_leaves_rooms_and_hangs_up() {
+        let mut state = state();
+        state.handle_command("/group create friends");
+        state.handle_command("/group invite alice");
+        state.handle_command("/call");
+        assert!(state.active_call.is_some());
+
+        let fx = state.farewell();
+        let sent = sent(&fx);
+        assert!(matches!(sent[0], OutgoingMessage::Group { message, .. } if message.call_hangup == Some(true)));
+        assert!(sent.iter().any(|m| matches!(m, OutgoingMessage::LeaveRoom { .. })));
+        assert!(matches!(sent.last(), Some(OutgoingMessage::Global(msg)) if msg.content == "me has left"));
+        assert!(state.active_call.is_none());
+    }
+
+    #[test]
+    fn test_mentions_are_counted_listed_and_jumped_to() {
+        let mut state = state();
+        state.handle_command("/group create team");
+        let group_tab = state.tabs[state.active_tab].clone();
+        let Tab::Group(group_id) = group_tab.clone() else { panic!("expected a group tab") };
+        state.set_active_tab(0);
+
+        state.ingest_message(PlainMessage::group(ALICE.to_string(), "morning all".to_string(), group_id.clone()));
+        state.ingest_message(PlainMessage::group(ALICE.to_string(), "@me can you review?".to_string(), group_id.clone()));
+        state.ingest_message(PlainMessage::group(BOB.to_string(), "after that".to_string(), group_id.clone()));
+        assert_eq!(state.unread.get(&group_tab), Some(&3));
+        assert_eq!(state.mention_unread.get(&group_tab), Some(&1));
+        let flagged: Vec<_> = state.messages[&group_tab].iter().filter(|m| m.mentions_me).map(|m| m.content.as_str()).collect();
+        assert_eq!(flagged, ["@me can you review?"]);
+
+        state.handle_command("/mentions");
+        let listing = &state.messages[&Tab::Global].last().unwrap().content;
+        assert!(listing.contains("1. [") && listing.contains("alice: @me can you review?"));
+
+        state.handle_command("/mentions 1");
+        assert_eq!(state.tabs[state.active_tab], group_tab);
+        assert_eq!(state.scroll_offset.get(&group_tab), Some(&1));
+        assert!(!state.mention_unread.contains_key(&group_tab));
+    }
+
+    #[test]
+    fn test_unread_cleared_on_focus() {
+        let mut state = state();
+        state.ingest_message(PlainMessage::direct(ALICE.to_string(), "one".to_string()));
+        state.ingest_message(PlainMessage::direct(ALICE.to_string(), "two".to_string()));
+
+        let dm_tab = Tab::DirectMessage(ALICE.to_string());
+        assert_eq!(state.unread.get(&dm_tab), Some(&2));
+        state.next_tab();
+        assert_eq!(state.tabs[state.active_tab], dm_tab);
+        assert!(!state.unread.contains_key(&dm_tab));
+    }
+
+    #[test]
+    fn test_file_offer_accept_and_receive() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = b"hello file".to_vec();
+        let offer = FileOffer {
+            file_id: "f1".to_string(),
+            filename: "note.txt".to_string(),
+            size: data.len() as u64,
+            checksum: blake3::hash(&data).to_hex().to_string(),
+            total_chunks: 1,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        };
+
+        let mut state = state();
+        state.ingest_message(PlainMessage::file_offer(ALICE.to_string(), offer, true));
+        assert!(state.pending_offers.contains_key("f1"));
+
+        // Offers are accepted from the tab they arrived in
+        state.ingest_message(PlainMessage::dm_request(ALICE.to_string()));
+        state.handle_command("/dm alice");
+        let fx = state.handle_command(&format!("/accept {}", dir.path().display()));
+        assert!(matches!(
+            sent(&fx)[..],
+            [OutgoingMessage::Direct { target_id, message }]
+                if target_id == ALICE && message.file_response == Some(true) && message.content == "f1"
+        ));
+        assert!(state.active_transfers.contains_key("f1"));
+
+        // A chunk index past the offer is reported and ignored
+        let stray = crate::protocol::FileChunk { file_id: "f1".to_string(), index: 1, data: data.clone() };
+        state.ingest_message(PlainMessage::file_chunk(ALICE.to_string(), stray, true));
+        assert!(state.status.starts_with("⚠️ Ignored chunk 1"));
+        assert_eq!(state.active_transfers["f1"].chunks_done, 0);
+
+        let chunk = crate::protocol::FileChunk { file_id: "f1".to_string(), index: 0, data: data.clone() };
+        state.ingest_message(PlainMessage::file_chunk(ALICE.to_string(), chunk, true));
+        assert!(state.active_transfers.is_empty());
+        assert_eq!(std::fs::read(dir.path().join("note.txt")).unwrap(), data);
+    }
+
+    #[test]
+    fn test_transfers_and_calls_leave_chat_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = b"hello file".to_vec();
+        let offer = |file_id: &str, checksum: String| FileOffer {
+            file_id: file_id.to_string(),
+            filename: format!("{}.txt", file_id),
+            size: data.len() as u64,
+            checksum,
+            total_chunks: 1,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        };
+        let chunk = |file_id: &str| crate::protocol::FileChunk { file_id: file_id.to_string(), index: 0, data: data.clone() };
+
+        let mut state = state();
+        state.handle_command("/dm alice");
+        let checksum = blake3::hash(&data).to_hex().to_string();
+        state.ingest_message(PlainMessage::file_offer(ALICE.to_string(), offer("f1", checksum.clone()), true));
+        state.handle_command(&format!("/accept {}", dir.path().display()));
+        state.ingest_message(PlainMessage::file_chunk(ALICE.to_string(), chunk("f1"), true));
+        state.ingest_message(PlainMessage::file_offer(ALICE.to_string(), offer("f2", "bogus".to_string()), true));
+        state.handle_command(&format!("/accept {}", dir.path().display()));
+        state.ingest_message(PlainMessage::file_chunk(ALICE.to_string(), chunk("f2"), true));
+
+        state.ingest_message(PlainMessage::call_request(ALICE.to_string(), CallSalt::generate("c1".to_string())));
+        state.handle_command("/accept-call");
+        state.handle_command("/hangup");
+
+        let events: Vec<&PlainMessage> = state.messages[&Tab::DirectMessage(ALICE.to_string())].iter()
+            .filter(|m| m.event.is_some())
+            .collect();
+        let kinds: Vec<ChatEvent> = events.iter().filter_map(|m| m.event).collect();
+        // Each offer's row ends up saying how it went
+        assert_eq!(kinds, vec![ChatEvent::FileCompleted, ChatEvent::FileFailed, ChatEvent::CallStarted, ChatEvent::CallEnded]);
+        assert!(events[0].content.contains("10 bytes"));
+        let saved = dir.path().join("f1.txt").display().to_string();
+        assert!(events[0].content.contains(&saved) && events[0].content.contains(&checksum), "{}", events[0].content);
+        assert!(events[1].content.contains("checksum mismatch"));
+        assert!(events.iter().all(|m| m.system));
+    }
+
+    #[test]
+    fn test_concurrent_offers_are_addressable() {
+        let dir = tempfile::tempdir().unwrap();
+        let offer = |id: &str, name: &str| FileOffer {
+            file_id: id.to_string(),
+            filename: name.to_string(),
+            size: 2 * FILE_CHUNK_SIZE as u64,
+            checksum: String::new(),
+            total_chunks: 2,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        };
+        let mut state = state();
+        state.ingest_message(PlainMessage::file_offer(ALICE.to_string(), offer("f1", "a.txt"), false));
+        state.ingest_message(PlainMessage::file_offer(BOB.to_string(), offer("f2", "b.txt"), false));
+        state.ingest_message(PlainMessage::file_offer(BOB.to_string(), offer("f3", "c.txt"), false));
+        assert!(state.status.contains("3 offers waiting"));
+
+        // Ambiguous without a selector
+        assert!(sent(&state.handle_command("/accept")).is_empty());
+        assert!(state.status.starts_with("3 file offers pending"));
+
+        state.handle_command("/offers");
+        let listing = &state.messages[&Tab::Global].last().unwrap().content;
+        assert!(listing.contains("1. a.txt") && listing.contains("2. b.txt") && listing.contains("from bob"));
+
+        state.handle_command(&format!("/accept 2 {}", dir.path().display()));
+        state.handle_command(&format!("/accept a.txt {}", dir.path().display()));
+        assert_eq!(state.active_transfers["f2"].save_path, dir.path().join("b.txt"));
+        assert!(state.active_transfers.contains_key("f1"));
+
+        // One left, so the selector is optional again
+        let fx = state.handle_command("/reject");
+        assert!(matches!(sent(&fx)[..], [OutgoingMessage::Global(ref m)] if m.file_response == Some(false) && m.content == "f3"));
+        assert!(state.pending_offers.is_empty());
+
+        // Both downloads share the status line
+        let chunk = crate::protocol::FileChunk { file_id: "f2".to_string(), index: 0, data: vec![0; 4] };
+        state.ingest_message(PlainMessage::file_chunk(BOB.to_string(), chunk, false));
+        assert_eq!(state.status, "Receiving 2 files: a.txt 0% · b.txt 50%");
+    }
+
+    #[test]
+    fn test_file_offer_name_cannot_escape_or_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("bashrc"), b"mine").unwrap();
+        let data = b"\x7fELF sneaky".to_vec();
+        let offer = FileOffer {
+            file_id: "f1".to_string(),
+            filename: "../../.bashrc".to_string(),
+            size: data.len() as u64,
+            checksum: blake3::hash(&data).to_hex().to_string(),
+            total_chunks: 1,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        };
+
+        let mut state = state();
+        state.ingest_message(PlainMessage::file_offer(ALICE.to_string(), offer, false));
+        state.handle_command(&format!("/accept {}", dir.path().display()));
+        assert!(state.status.contains("renamed for safety"));
+
+        let chunk = crate::protocol::FileChunk { file_id: "f1".to_string(), index: 0, data: data.clone() };
+        state.ingest_message(PlainMessage::file_chunk(ALICE.to_string(), chunk, false));
+        let warned = state.messages[&Tab::Global].iter().any(|m| m.system && m.content.contains("executable"));
+        assert!(warned);
+        assert_eq!(std::fs::read(dir.path().join("bashrc")).unwrap(), b"mine");
+        assert_eq!(std::fs::read(dir.path().join("bashrc (1)")).unwrap(), data);
+    }
+
+    #[test]
+    fn test_file_accept_streams_outgoing_transfer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.bin");
+        std::fs::write(&path, vec![7u8; 100]).unwrap();
+
+        let mut state = state();
+        state.handle_command("/dm bob");
+        let fx = state.handle_command(&format!("/send {}", path.display()));
+        let file_id = match sent(&fx)[..] {
+            [OutgoingMessage::Direct { message, .. }] => message.file_offer.as_ref().unwrap().file_id.clone(),
+            _ => panic!("expected a direct file offer"),
+        };
+
+        let fx = state.ingest_message(PlainMessage::file_response(BOB.to_string(), file_id.clone(), true, true));
+        assert!(matches!(
+            &fx[..],
+            [Effect::StreamFile { file_id: id, transfer }] if *id == file_id && transfer.target_peer == BOB
+        ));
+        assert!(state.outgoing_transfers.is_empty());
+    }
+
+    #[test]
+    fn test_group_file_goes_to_each_accepting_member() {
+        const CAROL: &str = "carol000000000000000";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, vec![7u8; FILE_CHUNK_SIZE + 10]).unwrap();
+
+        let mut state = state();
+        state.apply_peer_updates(vec![peer(CAROL, "carol", 3)]);
+        let members = vec![ME.to_string(), ALICE.to_string(), BOB.to_string()];
+        state.groups.insert("g1".to_string(), crate::tui::types::GroupInfo { name: "team".to_string(), members, join_token: None });
+        let tab = Tab::Group("g1".to_string());
+        state.ensure_tab(&tab);
+        state.set_active_tab(state.tabs.iter().position(|t| *t == tab).unwrap());
+        let fx = state.handle_command(&format!("/send {}", path.display()));
+        let offer_msg = match sent(&fx)[..] {
+            [OutgoingMessage::Group { group_id, message, .. }] if group_id == "g1" => message.clone(),
+            _ => panic!("expected a group file offer"),
+        };
+        let file_id = offer_msg.file_offer.clone().unwrap().file_id;
+        let accept = |state: &mut ChatState, from: &str| state.ingest_message(PlainMessage::file_response(from.to_string(), file_id.clone(), true, true));
+
+        // Someone outside the group can't take it; each member who accepts gets their own stream
+        assert!(accept(&mut state, CAROL).is_empty());
+        let mut targets = Vec::new();
+        for member in [ALICE, BOB] {
+            let fx = accept(&mut state, member);
+            let [Effect::StreamFile { file_id: id, transfer }] = &fx[..] else { panic!("expected a stream to {}", member) };
+            assert_eq!(id, &file_id);
+            for index in 0..transfer.offer.total_chunks {
+                match crate::tui::files::chunk_message(ME, id, transfer, index, b"x") {
+                    OutgoingMessage::Direct { target_id, message } if message.direct => targets.push((target_id, index)),
+                    other => panic!("chunk not sent to the member alone: {:?}", other),
+                }
+            }
+            // Accepting twice doesn't send it twice
+            assert!(accept(&mut state, member).is_empty());
+        }
+        let expected: Vec<(String, u32)> = [ALICE, BOB].iter().flat_map(|m| [(m.to_string(), 0), (m.to_string(), 1)]).collect();
+        assert_eq!(targets, expected);
+        // Everyone has answered, so the offer is closed
+        assert!(state.outgoing_transfers.is_empty());
+
+        // A member receives the group offer's chunks in a DM, and only from whoever offered it
+        let mut receiver = ChatState::new(BOB.to_string(), None, vec![1; 32]);
+        receiver.apply_peer_updates(vec![peer(ME, "me", 0), peer(ALICE, "alice", 1)]);
+        receiver.groups = state.groups.clone();
+        receiver.ingest_message(offer_msg);
+        receiver.set_active_tab(receiver.tabs.iter().position(|t| *t == tab).unwrap());
+        receiver.handle_command(&format!("/accept {}", dir.path().join("got.txt").display()));
+        let data = std::fs::read(&path).unwrap();
+        for (index, piece) in data.chunks(FILE_CHUNK_SIZE).enumerate() {
+            let chunk = crate::protocol::FileChunk { file_id: file_id.clone(), index: index as u32, data: piece.to_vec() };
+            receiver.ingest_message(PlainMessage::file_chunk(ALICE.to_string(), chunk.clone(), true));
+            assert!(!receiver.status.starts_with("File saved"));
+            receiver.ingest_message(PlainMessage::file_chunk(ME.to_string(), chunk, true));
+        }
+        assert!(receiver.status.starts_with("File saved"), "{}", receiver.status);
+        assert_eq!(std::fs::read(dir.path().join("got.txt")).unwrap(), data);
+    }
+
+    #[test]
+    fn test_folder_share_extracts_on_accept() {
+        let src = tempfile::tempdir().unwrap();
+        let shared = src.path().join("photos");
+        std::fs::create_dir_all(shared.join("2024")).unwrap();
+        std::fs::write(shared.join("2024/cat.jpg"), b"meow").unwrap();
+
+        let mut sender = state();
+        let fx = sender.handle_command(&format!("/send {}", shared.display()));
+        let offer_msg = match sent(&fx)[..] {
+            [OutgoingMessage::Global(message)] => message.clone(),
+            _ => panic!("expected a global file offer"),
+        };
+        let offer = offer_msg.file_offer.clone().unwrap();
+        assert!(offer.is_archive);
+        assert_eq!((offer.filename.as_str(), offer.entry_count), ("photos.tar", 2));
+        let data = sender.outgoing_transfers[&offer.file_id].file_data.clone();
+
+        let dest = tempfile::tempdir().unwrap();
+        let mut receiver = state();
+        receiver.ingest_message(PlainMessage { sender: ALICE.to_string(), ..offer_msg });
+        receiver.handle_command(&format!("/accept {} --extract", dest.path().display()));
+        for (index, piece) in data.chunks(FILE_CHUNK_SIZE).enumerate() {
+            let chunk = crate::protocol::FileChunk { file_id: offer.file_id.clone(), index: index as u32, data: piece.to_vec() };
+            receiver.ingest_message(PlainMessage::file_chunk(ALICE.to_string(), chunk, false));
+        }
+        assert!(receiver.status.starts_with("Folder extracted"), "{}", receiver.status);
+        assert_eq!(std::fs::read(dest.path().join("photos/2024/cat.jpg")).unwrap(), b"meow");
+    }
+
+    #[test]
+    fn test_pasted_image_is_offered_as_png() {
+        let mut state = state();
+        let mut fx = Vec::new();
+        state.share_pasted_image(b"\x89PNG\r\n\x1a\n".to_vec(), &mut fx);
+        let offer = match sent(&fx)[..] {
+            [OutgoingMessage::Global(message)] => message.file_offer.clone().unwrap(),
+            _ => panic!("expected a global file offer"),
+        };
+        assert!(offer.filename.starts_with("pasted-") && offer.filename.ends_with(".png"));
+        assert_eq!(offer.mime_type.as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn test_id_shows_full_identity() {
+        let mut state = state();
+        state.handle_command("/id");
+        let shown = &state.messages[&Tab::Global].last().unwrap().content;
+        assert!(shown.contains(&state.own_identity_b64()) && shown.contains(ME));
+        assert!(shown.contains(&state.own_fingerprint));
+    }
+
+    #[test]
+    fn test_share_size_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("big.bin"), vec![0u8; 100]).unwrap();
+        let mut state = state();
+        state.max_share_bytes = 99;
+        assert!(sent(&state.handle_command(&format!("/send {}", dir.path().display()))).is_empty());
+        assert!(state.status.contains("share limit"), "{}", state.status);
+    }
+
+    #[test]
+    fn test_call_signaling_order() {
+        let mut state = state();
+        state.ingest_message(PlainMessage::call_request(ALICE.to_string(), CallSalt::generate("c1".to_string())));
+        assert_eq!(state.pending_call_from.as_deref(), Some(ALICE));
+        assert!(state.active_call.is_none());
+
+        // Accept (with our salt) and the call key go out before the audio devices are opened
+        let fx = state.handle_command("/accept-call");
+        assert!(matches!(
+            &fx[..],
+            [Effect::Send(OutgoingMessage::Direct { message, .. }), Effect::Send(OutgoingMessage::CallKey { salts: Some(_), .. }), Effect::StartAudio]
+                if message.call_accept == Some(true) && message.call_salt.as_ref().is_some_and(|s| s.call_id == "c1")
+        ));
+        assert!(matches!(&state.active_call, Some(c) if matches!(&c.call_type, CallType::Direct(p) if p == ALICE)));
+
+        // A second caller waits for /swap rather than ringing over the call
+        state.ingest_message(PlainMessage::call_request(BOB.to_string(), CallSalt::generate("c2".to_string())));
+        assert!(state.pending_call_from.is_none());
+        assert!(state.call_waiting.is_some());
+        assert!(state.is_current_call("c1"));
+
+        // Hangup from someone else doesn't end our call
+        let fx = state.ingest_message(PlainMessage::call_hangup(BOB.to_string()));
+        assert!(fx.is_empty());
+        assert!(state.active_call.is_some());
+
+        let fx = state.ingest_message(PlainMessage::call_hangup(ALICE.to_string()));
+        assert!(matches!(&fx[..], [Effect::StopAudio, Effect::Send(OutgoingMessage::CallKey { peer_id, salts: None })] if peer_id == ALICE));
+        assert!(state.active_call.is_none());
+    }
+
+    #[test]
+    fn test_busy_caller_is_told_and_can_be_called_back() {
+        const CAROL: &str = "carol000000000000000";
+        let mut state = state();
+        state.ingest_message(PlainMessage::call_request(ALICE.to_string(), CallSalt::generate("c1".to_string())));
+        state.handle_command("/accept-call");
+        state.ingest_message(PlainMessage::call_request(CAROL.to_string(), CallSalt::generate("c3".to_string())));
+
+        // Bob rings mid-call with Carol already waiting: he hears we're busy, we can
+        // ring him back later
+        let fx = state.ingest_message(PlainMessage::call_request(BOB.to_string(), CallSalt::generate("c2".to_string())));
+        assert!(matches!(
+            &fx[..],
+            [Effect::Send(OutgoingMessage::Direct { target_id, message })] if target_id == BOB && message.call_accept == Some(false) && message.call_busy
+        ));
+        assert_eq!(state.missed_call_from.as_deref(), Some(BOB));
+        assert!(state.handle_command("/callback").is_empty());
+
+        state.handle_command("/hangup");
+        let fx = state.handle_command("/callback");
+        assert!(matches!(&fx[..], [Effect::Send(OutgoingMessage::Direct { target_id, message })] if target_id == BOB && message.call_request == Some(true)));
+        assert_eq!(state.tabs[state.active_tab], Tab::DirectMessage(BOB.to_string()));
+        assert!(state.missed_call_from.is_none());
+
+        // Bob is busy now himself
+        state.ingest_message(PlainMessage::call_busy(BOB.to_string()));
+        assert!(state.status.ends_with("is in another call"), "{}", state.status);
+        assert!(state.call_keys.is_none());
+    }
+
+    #[test]
+    fn test_audio_failure_leaves_call() {
+        let mut state = state();
+        state.ingest_message(PlainMessage::call_request(ALICE.to_string(), CallSalt::generate("c1".to_string())));
+        state.handle_command("/accept-call");
+        state.audio_failed("no input device".to_string());
+
+        assert!(state.active_call.is_none());
+        let dm_tab = Tab::DirectMessage(ALICE.to_string());
+        assert!(state.messages[&dm_tab].last().unwrap().content.contains("no input device"));
+    }
+
+    #[test]
+    fn test_calls_follow_audio_support() {
+        let mut state = state();
+        state.audio_support = AudioSupport::Unavailable("built without audio support".to_string());
+        state.ingest_message(PlainMessage::call_request(ALICE.to_string(), CallSalt::generate("c1".to_string())));
+        assert!(state.handle_command("/accept-call").is_empty());
+        assert_eq!(state.status, "📵 Voice calls unavailable: built without audio support");
+        let call = state.get_all_commands().into_iter().find(|c| c.name == "call").unwrap();
+        assert!(call.description.ends_with("(unavailable: built without audio support)"));
+
+        // Speakers but no microphone: the call goes ahead, muted for good
+        state.audio_support = AudioSupport::ListenOnly;
+        let fx = state.handle_command("/accept-call");
+        assert!(matches!(fx.last(), Some(Effect::StartAudio)));
+        assert!(state.active_call.as_ref().is_some_and(|c| c.mic.is_muted()));
+        state.handle_command("/mute");
+        assert!(state.active_call.as_ref().is_some_and(|c| c.mic.is_muted()));
+    }
+
+    #[test]
+    fn test_read_receipts() {
+        let mut state = state();
+        let mut dm = PlainMessage::direct(ALICE.to_string(), "hi".to_string());
+        dm.message_id = Some("a1".to_string());
+        state.ingest_message(dm);
+
+        // Receipts go out once the conversation is on screen, and only once
+        assert!(state.read_receipts().is_empty());
+        state.active_tab = state.tabs.iter().position(|t| t == &Tab::DirectMessage(ALICE.to_string())).unwrap();
+        let fx = state.read_receipts();
+        assert!(matches!(
+            sent(&fx)[..],
+            [OutgoingMessage::Signal(Message::ReadReceipt { target, message_id, .. })] if target == ALICE && message_id == "a1"
+        ));
+        assert!(state.read_receipts().is_empty());
+
+        let mut own = PlainMessage::direct(ME.to_string(), "hey".to_string());
+        own.message_id = Some("m1".to_string());
+        state.ingest_message(own);
+        assert_eq!(state.read_status.get("m1"), Some(&ReadStatus::Sent));
+        state.ingest_message(PlainMessage::read_receipt(ALICE.to_string(), "m1".to_string(), true));
+        assert_eq!(state.read_status.get("m1"), Some(&ReadStatus::Read));
+    }
+
+    #[test]
+    fn test_delivery_confirmations() {
+        let mut state = state();
+        let tab = Tab::DirectMessage(ALICE.to_string());
+        state.open_dm_tab(ALICE, None);
+        state.active_tab = state.tabs.iter().position(|t| *t == tab).unwrap();
+
+        // Ours waits at ⏳ until Alice's client confirms it
+        state.handle_command("hello");
+        let mine = state.messages[&tab].last().unwrap().message_id.clone().unwrap();
+        assert_eq!(state.read_status.get(&mine), Some(&ReadStatus::Pending));
+        state.mark_delivered(&mine);
+        assert_eq!(state.read_status.get(&mine), Some(&ReadStatus::Sent));
+    }
+
+    #[test]
+    fn test_nickname_updates() {
+        let mut state = state();
+        // The raw nickname message is consumed by the client; if one slips through it stays invisible
+        state.ingest_message(PlainMessage::nickname(ALICE.to_string(), "ally".to_string()));
+        assert_eq!(tab_len(&state, &Tab::Global), 0);
+        assert_eq!(state.tabs, vec![Tab::Global]);
+
+        // The client's notice lands in #global and the peer map carries the new name
+        state.ingest_message(PlainMessage::system(ALICE.to_string(), "alice is now known as ally".to_string()));
+        let ally = PeerDisplay { nickname: Some("ally".to_string()), ..state.peers[ALICE].clone() };
+        state.apply_peer_updates(vec![PeerUpdate::Changed(ALICE.to_string(), ally)]);
+
+        assert_eq!(tab_len(&state, &Tab::Global), 1);
+        assert_eq!(state.get_peer_display_name(ALICE), "ally");
+        assert_eq!(state.find_peer_by_name_or_id("ally").as_deref(), Ok(ALICE));
+    }
+
+    #[test]
+    fn test_peer_updates_keep_a_stable_sidebar() {
+        let carol = "carol000000000000000";
+        let mut state = ChatState::new(ME.to_string(), None, vec![0; 32]);
+        state.apply_peer_updates(vec![peer(carol, "carol", 3), peer(BOB, "Bob", 2), peer(ALICE, "alice", 1)]);
+        assert_eq!(state.sidebar_peers(), vec![ALICE, BOB, carol]);
+
+        // Verified peers come first; a rename only moves that peer
+        state.verified_peers.insert(vec![3; 32], Verified::Local { at: 0 });
+        let zed = PeerDisplay { nickname: Some("zed".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Changed(ALICE.to_string(), zed)]);
+        assert_eq!(state.sidebar_peers(), vec![carol, BOB, ALICE]);
+
+        state.apply_peer_updates(vec![PeerUpdate::Removed(BOB.to_string())]);
+        assert_eq!(state.sidebar_peers(), vec![carol, ALICE]);
+        assert_eq!(state.get_peer_display_name(ALICE), "zed");
+    }
+
+    #[test]
+    fn test_shared_nicknames_get_suffixes() {
+        let mut state = state();
+        let namesake = PeerDisplay { nickname: Some("Alice".to_string()), ..state.peers[BOB].clone() };
+        state.apply_peer_updates(vec![PeerUpdate::Changed(BOB.to_string(), namesake)]);
+
+        assert_eq!(state.get_peer_display_name(ALICE), "alice#alic");
+        assert_eq!(state.get_peer_display_name(BOB), "Alice#bob0");
+        assert_eq!(
+            state.find_peer_by_name_or_id("alice"),
+            Err("Multiple peers named alice: Alice#bob0, alice#alic — use the suffixed form".to_string())
+        );
+        assert_eq!(state.find_peer_by_name_or_id("alice#bob0").as_deref(), Ok(BOB));
+        assert_eq!(state.find_peer_by_name_or_id("ALICE#ALIC").as_deref(), Ok(ALICE));
+        assert!(state.find_peer_by_name_or_id("alice#ffff").is_err());
+
+        state.handle_command("/dm alice");
+        assert_eq!(state.tabs, vec![Tab::Global]);
+        state.handle_command("/dm alice#bob0");
+        assert_eq!(state.tabs[state.active_tab], Tab::DirectMessage(BOB.to_string()));
+    }
+
+    #[test]
+    fn test_nick_command_notifies_peers() {
+        let mut state = state();
+        let fx = state.handle_command("/nick  new\u{202e}me ");
+
+        // One announcement for the client to fan out, already cleaned up
+        assert_eq!(state.own_nickname.as_deref(), Some("newme"));
+        assert!(matches!(sent(&fx)[..], [OutgoingMessage::Nickname(nick)] if nick == "newme"));
+    }
+
+    /// Two chat states that know each other as peers, with the given identity keys
+    fn verifying_pair(alice_key: u8, bob_key: u8, bob_sees_alice_as: u8) -> (ChatState, ChatState) {
+        let mut alice = ChatState::new(ALICE.to_string(), None, vec![alice_key; 32]);
+        alice.apply_peer_updates(vec![peer(BOB, "bob", bob_key)]);
+        let mut bob = ChatState::new(BOB.to_string(), None, vec![bob_key; 32]);
+        bob.apply_peer_updates(vec![peer(ALICE, "alice", bob_sees_alice_as)]);
+        (alice, bob)
+    }
+
+    /// Deliver every direct message in `fx` to `to`
+    fn deliver(fx: &[Effect], to: &mut ChatState) {
+        for msg in sent(fx) {
+            if let OutgoingMessage::Direct { message, .. } = msg {
+                to.ingest_message(message.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_mutual_verification() {
+        let (mut alice, mut bob) = verifying_pair(1, 2, 1);
+
+        alice.handle_command("/dm bob");
+        let fx = alice.handle_command("/verify");
+        deliver(&fx, &mut bob);
+        // Both screens show the same challenge words
+        let words = |state: &ChatState, peer: &str| {
+            let text = &state.messages[&Tab::DirectMessage(peer.to_string())].last().unwrap().content;
+            text.lines().find(|l| l.contains("Words:")).unwrap().to_string()
+        };
+        assert_eq!(words(&alice, BOB), words(&bob, ALICE));
+
+        // Alice confirming alone only verifies locally
+        let fx = alice.handle_command("/verified bob");
+        assert_eq!(alice.verification_icon(BOB), "✅");
+        deliver(&fx, &mut bob);
+        assert_eq!(bob.verification_icon(ALICE), "❓");
+
+        // Bob confirms too: both sides end up mutually verified
+        let fx = bob.handle_command("/verified alice");
+        assert_eq!(bob.verification_icon(ALICE), "🔒");
+        deliver(&fx, &mut alice);
+        assert_eq!(alice.verification_icon(BOB), "🔒");
+        assert!(alice.verifications.is_empty() && bob.verifications.is_empty());
+    }
+
+    #[test]
+    fn test_verification_detects_mismatched_keys() {
+        // Bob has a different key for Alice than the one she has
+        let (mut alice, mut bob) = verifying_pair(1, 2, 9);
+
+        let fx = alice.handle_command("/verify bob");
+        deliver(&fx, &mut bob);
+        let fx = bob.handle_command("/verified alice");
+        deliver(&fx, &mut alice);
+        assert!(alice.status.contains("failed"));
+
+        // Alice can still vouch for what she saw herself, but it never becomes mutual
+        alice.handle_command("/verified bob");
+        assert_eq!(alice.verification_icon(BOB), "✅");
+        let dm = &alice.messages[&Tab::DirectMessage(BOB.to_string())];
+        assert!(dm.iter().any(|m| m.content.contains("confirmed a different pair of keys")));
+    }
+
+    #[test]
+    fn test_expire_command_sets_conversation_ttl() {
+        let mut state = state();
+        let fx = state.handle_command("/expire 5m");
+        assert!(fx.is_empty());
+        assert!(state.status.contains("DM and group"));
+
+        state.handle_command("/dm alice");
+        let dm_tab = Tab::DirectMessage(ALICE.to_string());
+        let fx = state.handle_command("/expire 5m");
+        assert!(matches!(
+            sent(&fx)[..],
+            [OutgoingMessage::Direct { target_id, message }] if target_id == ALICE && message.expire_policy == Some(300)
+        ));
+        assert_eq!(state.expiry.get(&dm_tab), Some(&300));
+        assert!(state.messages[&dm_tab].last().unwrap().content.contains("disappear after 5m"));
+
+        // Messages sent from now on carry the TTL
+        let fx = state.handle_command("psst");
+        assert!(matches!(sent(&fx)[..], [OutgoingMessage::Direct { message, .. }] if message.expire_after == Some(300)));
+
+        assert!(state.handle_command("/expire soon").is_empty());
+        let fx = state.handle_command("/expire off");
+        assert!(matches!(sent(&fx)[..], [OutgoingMessage::Direct { message, .. }] if message.expire_policy == Some(0)));
+        assert!(!state.expiry.contains_key(&dm_tab));
+        let fx = state.handle_command("in the clear");
+        assert!(matches!(sent(&fx)[..], [OutgoingMessage::Direct { message, .. }] if message.expire_after.is_none()));
+    }
+
+    #[test]
+    fn test_expired_messages_are_swept() {
+        let mut state = state();
+        let dm_tab = Tab::DirectMessage(ALICE.to_string());
+        state.ingest_message(PlainMessage::expire_policy(ALICE.to_string(), 60, true));
+        assert_eq!(state.expiry.get(&dm_tab), Some(&60));
+        assert!(state.messages[&dm_tab][0].content.contains("alice set messages to disappear after 1m"));
+
+        let mut fleeting = PlainMessage::direct(ALICE.to_string(), "gone soon".to_string());
+        fleeting.expire_after = Some(60);
+        let sent_at = fleeting.timestamp;
+        state.ingest_message(fleeting);
+        state.ingest_message(PlainMessage::direct(ALICE.to_string(), "stays".to_string()));
+
+        assert!(!state.sweep_expired(sent_at + 59));
+        assert!(state.sweep_expired(sent_at + 60));
+        let contents: Vec<&str> = state.messages[&dm_tab].iter().map(|m| m.content.as_str()).collect();
+        assert!(!contents.contains(&"gone soon"));
+        assert!(contents.contains(&"stays"));
+    }
+
+    fn group_msg(sender: &str, id: &str, timestamp: i64) -> PlainMessage {
+        PlainMessage {
+            timestamp,
+            message_id: Some(id.to_string()),
+            ..PlainMessage::group(sender.to_string(), format!("msg {}", id), "g1".to_string())
+        }
+    }
+
+    #[test]
+    fn test_history_request_answered_for_members_only() {
+        let mut state = state();
+        join_group(&mut state, "g1");
+        let tab = Tab::Group("g1".to_string());
+        for (id, at) in [("m1", 100), ("m2", 200), ("m3", 300)] {
+            state.ingest_message(group_msg(ALICE, id, at));
+        }
+        let mut offer = group_msg(ALICE, "f1", 250);
+        offer.file_offer = Some(FileOffer {
+            file_id: "f1".to_string(),
+            filename: "a.txt".to_string(),
+            size: 1,
+            checksum: String::new(),
+            total_chunks: 1,
+            mime_type: None,
+            is_archive: false,
+            entry_count: 0,
+        });
+        state.push_message(tab, offer);
+
+        let request = |from: &str| PlainMessage::history(from.to_string(), HistorySync::Request { group_id: "g1".to_string(), since_timestamp: 200 });
+        // Bob isn't in the group
+        assert!(sent(&state.ingest_message(request(BOB))).is_empty());
+
+        let fx = state.ingest_message(request(ALICE));
+        let [OutgoingMessage::Direct { target_id, message }] = sent(&fx)[..] else { panic!("expected one batch") };
+        assert_eq!(target_id, ALICE);
+        let Some(HistorySync::Batch { ref messages, more: false, .. }) = message.history else { panic!("expected a final batch") };
+        let ids: Vec<_> = messages.iter().map(|m| m.message_id.as_deref().unwrap()).collect();
+        assert_eq!(ids, ["m2", "m3"]);
+
+        // Asking again straight away gets nothing, and neither does anyone once sharing is off
+        assert!(sent(&state.ingest_message(request(ALICE))).is_empty());
+        state.history_served.clear();
+        state.history_sync = false;
+        assert!(sent(&state.ingest_message(request(ALICE))).is_empty());
+    }
+
+    #[test]
+    fn test_history_batch_merges_only_what_was_asked_for() {
+        let mut state = state();
+        join_group(&mut state, "g1");
+        let tab = Tab::Group("g1".to_string());
+        state.ingest_message(group_msg(ALICE, "m1", 100));
+        state.ingest_message(group_msg(ALICE, "m4", 400));
+
+        let fx = state.request_group_history();
+        let [OutgoingMessage::Direct { target_id, message }] = sent(&fx)[..] else { panic!("expected one request") };
+        assert_eq!(target_id, ALICE);
+        assert!(matches!(message.history, Some(HistorySync::Request { since_timestamp: 400, .. })));
+
+        let batch = |from: &str, messages| PlainMessage::history(from.to_string(), HistorySync::Batch { group_id: "g1".to_string(), messages, more: false });
+        // Only the member we asked may answer
+        state.ingest_message(batch(BOB, vec![group_msg(BOB, "forged", 300)]));
+        assert_eq!(state.messages[&tab].iter().filter(|m| m.synced).count(), 0);
+
+        let mut smuggled = group_msg(BOB, "m3", 300);
+        smuggled.call_request = Some(true);
+        state.ingest_message(batch(ALICE, vec![group_msg(BOB, "m2", 200), smuggled, group_msg(ALICE, "m4", 400)]));
+        let chat: Vec<_> = state.messages[&tab].iter()
+            .filter(|m| !m.system)
+            .map(|m| (m.message_id.as_deref().unwrap(), m.synced))
+            .collect();
+        assert_eq!(chat, [("m1", false), ("m2", true), ("m3", true), ("m4", false)]);
+        assert!(state.messages[&tab].iter().all(|m| m.call_request.is_none()));
+        assert!(state.history_pending.is_empty());
+        assert!(state.status.contains("2 message(s)"));
+    }
+}
@@ -0,0 +1,188 @@
+//! /stats: what this session has sent and received, per tab and over the wire, plus
+//! ratchet, file transfer, call and reconnect figures, and what deflating saved.
+
+use std::collections::HashMap;
+
+use crate::audio::AudioCounts;
+use crate::client::{Compression, StatsSnapshot};
+use crate::protocol::{short_id, PlainMessage};
+
+use super::helpers::{format_duration, format_ttl};
+use super::state::ChatState;
+use super::types::Tab;
+
+/// Running totals the chat keeps for /stats
+#[derive(Debug, Default)]
+pub(crate) struct SessionTally {
+    /// (sent, received) chat messages per tab
+    pub messages: HashMap<Tab, (u64, u64)>,
+    /// Chat messages received, by sender session id
+    pub received_from: HashMap<String, u64>,
+    pub files_sent: u64,
+    pub file_bytes_sent: u64,
+    pub files_received: u64,
+    pub file_bytes_received: u64,
+    /// Time spent in calls that have ended
+    pub call_time: chrono::Duration,
+    /// Group messages that came early and were shown in their turn
+    pub group_reordered: u64,
+    /// Gaps in a member's group messages we gave up waiting on
+    pub group_gaps: u64,
+    /// Messages that turned up for a gap afterwards
+    pub group_recovered: u64,
+    /// Our group messages sent again because a member missed them
+    pub group_resent: u64,
+}
+
+impl SessionTally {
+    /// Count a chat message stored in `tab` (system and caught-up messages aren't counted)
+    pub fn count_message(&mut self, tab: &Tab, msg: &PlainMessage, own_id: &str) {
+        if msg.system || msg.synced {
+            return;
+        }
+        let (sent, received) = self.messages.entry(tab.clone()).or_default();
+        if msg.sender == own_id {
+            *sent += 1;
+        } else {
+            *received += 1;
+            *self.received_from.entry(msg.sender.clone()).or_default() += 1;
+        }
+    }
+}
+
+impl ChatState {
+    /// Post the /stats report in the current tab. `client` is None when the UI runs
+    /// without a connection handle.
+    pub(crate) fn show_stats(&mut self, client: Option<&StatsSnapshot>, audio: AudioCounts) {
+        let mut lines = vec!["📊 Session statistics".to_string()];
+
+        lines.push("Messages (sent / received):".to_string());
+        let mut tabs: Vec<(String, u64, u64)> = self.tally.messages.iter()
+            .map(|(tab, (sent, received))| (self.get_tab_name(tab), *sent, *received))
+            .collect();
+        tabs.sort();
+        if tabs.is_empty() {
+            lines.push("  none yet".to_string());
+        }
+        for (name, sent, received) in tabs {
+            lines.push(format!("  {}: {} / {}", name, sent, received));
+        }
+
+        if let Some(client) = client {
+            lines.push(format!(
+                "Relay traffic: ↑ {} in {} frames, ↓ {} in {} frames · {} reconnect(s)",
+                Self::format_size(client.bytes_sent),
+                client.frames_sent,
+                Self::format_size(client.bytes_received),
+                client.frames_received,
+                client.reconnects
+            ));
+            match client.compression {
+                Compression::Off => {}
+                Compression::Refused => lines.push("Compression: asked for, but this relay doesn't deflate".to_string()),
+                Compression::On => lines.push(format!(
+                    "Compression: ↑ {} of frames sent as {} ({}), ↓ {} received as {} ({})",
+                    Self::format_size(client.frame_bytes_sent),
+                    Self::format_size(client.bytes_sent),
+                    saved(client.frame_bytes_sent, client.bytes_sent),
+                    Self::format_size(client.frame_bytes_received),
+                    Self::format_size(client.bytes_received),
+                    saved(client.frame_bytes_received, client.bytes_received)
+                )),
+            }
+            if let Some(at) = client.last_received {
+                let ago = chrono::Utc::now().timestamp().saturating_sub(at).max(0) as u64;
+                lines.push(format!("Last frame from the relay: {} ago", format_ttl(ago)));
+            }
+            let (up, down) = (client.sent, client.received);
+            lines.push(format!(
+                "Traffic (↑ / ↓): chat {} / {} · files {} / {} · voice {} / {} · protocol {} / {}",
+                Self::format_size(up.chat),
+                Self::format_size(down.chat),
+                Self::format_size(up.files),
+                Self::format_size(down.files),
+                Self::format_size(up.voice),
+                Self::format_size(down.voice),
+                Self::format_size(up.overhead),
+                Self::format_size(down.overhead)
+            ));
+            match client.ratchets {
+                Some(ref ratchets) if ratchets.is_empty() => lines.push("Ratchets: no peer sessions".to_string()),
+                Some(ref ratchets) => {
+                    lines.push("Ratchets (send chain / receive chain / skipped keys held, used, evicted):".to_string());
+                    for (peer_id, r) in ratchets {
+                        lines.push(format!(
+                            "  {} ({}): {} / {} / {}, {}, {}",
+                            self.get_peer_display_name(peer_id),
+                            short_id(peer_id),
+                            r.send_chain_len,
+                            r.recv_chain_len,
+                            r.skipped_keys,
+                            r.skipped_used,
+                            r.skipped_evicted
+                        ));
+                    }
+                }
+                None => lines.push("Ratchets: busy, try again".to_string()),
+            }
+        }
+
+        let t = &self.tally;
+        lines.push(format!(
+            "Files: {} sent ({}), {} received ({})",
+            t.files_sent,
+            Self::format_size(t.file_bytes_sent),
+            t.files_received,
+            Self::format_size(t.file_bytes_received)
+        ));
+
+        lines.push(format!(
+            "Group delivery: {} reordered, {} gap(s) with {} message(s) recovered, {} resent on request",
+            t.group_reordered, t.group_gaps, t.group_recovered, t.group_resent
+        ));
+
+        let mut call_time = t.call_time;
+        if let Some(ref call) = self.active_call {
+            call_time += chrono::Utc::now() - call.start_time;
+        }
+        lines.push(format!("Call time: {}", format_duration(call_time)));
+        let (audio_sent, audio_received) = client.map_or((0, 0), |c| (c.audio_frames_sent, c.audio_frames_received));
+        lines.push(format!(
+            "Audio frames: {} sent, {} received, {} captured, {} played, {} dropped",
+            audio_sent, audio_received, audio.captured, audio.played, audio.dropped
+        ));
+
+        let tab = self.tabs[self.active_tab].clone();
+        self.add_system_message(&tab, lines.join("\n"));
+    }
+}
+
+/// "25% saved" going from `before` bytes to `after`
+fn saved(before: u64, after: u64) -> String {
+    match before {
+        0 => "nothing yet".to_string(),
+        _ => format!("{}% saved", before.saturating_sub(after) * 100 / before),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_counted_per_tab() {
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let tab = Tab::DirectMessage("peer".to_string());
+        let own = PlainMessage::direct("me".repeat(16), "hi".to_string());
+        let theirs = PlainMessage::direct("peer".to_string(), "hey".to_string());
+        state.push_message(tab.clone(), own);
+        state.push_message(tab.clone(), theirs.clone());
+        state.push_message(tab.clone(), PlainMessage { synced: true, ..theirs });
+        state.add_system_message(&tab, "not counted".to_string());
+        assert_eq!(state.tally.messages.get(&tab), Some(&(1, 1)));
+
+        state.show_stats(None, AudioCounts::default());
+        let report = &state.messages[&Tab::Global].last().unwrap().content;
+        assert!(report.contains(": 1 / 1"), "{}", report);
+    }
+}
@@ -0,0 +1,280 @@
+//! Files going out, while their chunks stream: /pause and /resume stop and restart a
+//! transfer, /limit caps its rate, and a call slows every transfer to a call-friendly
+//! rate (`call_file_rate_kbps` in the config) until it's over, so voice keeps room on
+//! the uplink. The task sending the chunks checks its transfer's control before each
+//! one; receivers just see chunks arrive more slowly.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use super::bandwidth::RateLimit;
+use super::state::ChatState;
+use super::types::OutgoingTransfer;
+
+/// What files are slowed to during a call unless the config says otherwise, in bytes a second
+pub const DEFAULT_CALL_FILE_RATE: u64 = 32 * 1024;
+
+/// Pause and pace for one transfer, shared by the chat and the task sending its chunks
+#[derive(Debug, Default)]
+pub struct TransferControl {
+    paused: AtomicBool,
+    resumed: Notify,
+    /// None: as fast as the link goes
+    limit: Mutex<Option<Arc<RateLimit>>>,
+    chunks_sent: AtomicU32,
+    finished: AtomicBool,
+}
+
+impl TransferControl {
+    /// Wait while the transfer is paused, then for `bytes` more to fit its rate
+    pub async fn ready(&self, bytes: usize) {
+        loop {
+            // Registered before the check, so a resume in between isn't missed
+            let resumed = self.resumed.notified();
+            if !self.paused.load(Ordering::Relaxed) {
+                break;
+            }
+            resumed.await;
+        }
+        let limit = self.limit.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Some(limit) = limit {
+            limit.take(bytes).await;
+        }
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        if !paused {
+            self.resumed.notify_waiters();
+        }
+    }
+
+    fn set_rate(&self, bytes_per_sec: Option<u64>) {
+        *self.limit.lock().unwrap_or_else(|e| e.into_inner()) = bytes_per_sec.map(|rate| Arc::new(RateLimit::new(rate)));
+    }
+
+    pub fn chunk_sent(&self) {
+        self.chunks_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The sending task is done with it, sent or not
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A transfer whose chunks are going out
+#[derive(Debug)]
+pub(crate) struct Sending {
+    file_id: String,
+    filename: String,
+    total_chunks: u32,
+    /// The cap set with /limit, in bytes a second
+    limit: Option<u64>,
+    control: Arc<TransferControl>,
+}
+
+/// "50KB/s" from a rate in bytes a second
+fn rate_text(bytes_per_sec: u64) -> String {
+    format!("{}KB/s", bytes_per_sec / 1024)
+}
+
+impl ChatState {
+    /// Keep hold of `transfer`'s control now its chunks are about to go out
+    pub(crate) fn track_sending(&mut self, file_id: &str, transfer: &OutgoingTransfer) {
+        let sending = Sending {
+            file_id: file_id.to_string(),
+            filename: transfer.offer.filename.clone(),
+            total_chunks: transfer.offer.total_chunks,
+            limit: None,
+            control: transfer.control.clone(),
+        };
+        sending.control.set_rate(self.rate_for(&sending));
+        self.sending.push(sending);
+    }
+
+    /// The rate `sending` goes at: its own cap, or the call's if that's lower
+    fn rate_for(&self, sending: &Sending) -> Option<u64> {
+        let call_cap = (self.active_call.is_some() && self.call_file_rate > 0).then_some(self.call_file_rate);
+        match (sending.limit, call_cap) {
+            (Some(own), Some(call)) => Some(own.min(call)),
+            (own, call) => own.or(call),
+        }
+    }
+
+    fn apply_rates(&self) {
+        for sending in &self.sending {
+            sending.control.set_rate(self.rate_for(sending));
+        }
+    }
+
+    /// A call started or ended: slow files down for it, or let them go again.
+    /// Says so on the status line if anything is going out.
+    pub(crate) fn pace_for_call(&mut self) {
+        self.sending.retain(|s| !s.control.finished.load(Ordering::Relaxed));
+        if self.sending.is_empty() || self.call_file_rate == 0 {
+            return;
+        }
+        self.apply_rates();
+        let note = if self.active_call.is_some() {
+            format!("📁 files slowed to {} for the call", rate_text(self.call_file_rate))
+        } else {
+            "📁 files back to full speed".to_string()
+        };
+        self.status = format!("{} · {}", self.status, note);
+    }
+
+    /// Forget transfers whose chunks have all gone (run from housekeeping)
+    pub(crate) fn sweep_sending(&mut self) {
+        self.sending.retain(|s| !s.control.finished.load(Ordering::Relaxed));
+    }
+
+    /// Which of the files going out `name` picks (all streams of it, for a group
+    /// offer several members took). With no name, the only one going out.
+    fn select_sending(&self, name: Option<&str>, usage: &str) -> Result<String, String> {
+        let mut ids: Vec<&str> = self.sending.iter()
+            .filter(|s| !s.control.finished.load(Ordering::Relaxed))
+            .filter(|s| name.is_none_or(|name| s.file_id == name || s.filename.eq_ignore_ascii_case(name)))
+            .map(|s| s.file_id.as_str())
+            .collect();
+        ids.dedup();
+        match (&ids[..], name) {
+            ([id], _) => Ok(id.to_string()),
+            ([], None) => Err("No files are going out".to_string()),
+            ([], Some(name)) => Err(format!("No file called {} is going out", name)),
+            (_, _) => Err(format!("Several files are going out — name one: {}", usage)),
+        }
+    }
+
+    /// Handle /pause [file] and /resume [file]
+    pub(crate) fn handle_pause_command(&mut self, args: &[&str], paused: bool) {
+        let name = (!args.is_empty()).then(|| args.join(" "));
+        let usage = if paused { "/pause <file>" } else { "/resume <file>" };
+        let file_id = match self.select_sending(name.as_deref(), usage) {
+            Ok(id) => id,
+            Err(e) => {
+                self.status = e;
+                return;
+            }
+        };
+        let mut streams = self.sending.iter().filter(|s| s.file_id == file_id).peekable();
+        let Some(first) = streams.peek() else {
+            return;
+        };
+        let (filename, total) = (first.filename.clone(), first.total_chunks);
+        let sent = first.control.chunks_sent.load(Ordering::Relaxed);
+        for sending in streams {
+            sending.control.set_paused(paused);
+        }
+        self.status = if paused {
+            format!("⏸ Paused {} ({}/{} chunks sent) — /resume {} to carry on", filename, sent, total, filename)
+        } else {
+            format!("▶ Resumed {}", filename)
+        };
+    }
+
+    /// Handle /limit [file] <KB/s|off>
+    pub(crate) fn handle_limit_command(&mut self, args: &[&str]) {
+        const USAGE: &str = "Usage: /limit [file] <KB/s|off>";
+        let Some((rate, name)) = args.split_last() else {
+            self.status = USAGE.to_string();
+            return;
+        };
+        let limit = match *rate {
+            "off" => None,
+            kbps => match kbps.parse::<u64>() {
+                Ok(kbps) if kbps > 0 => Some(kbps.saturating_mul(1024)),
+                _ => {
+                    self.status = USAGE.to_string();
+                    return;
+                }
+            },
+        };
+        let name = (!name.is_empty()).then(|| name.join(" "));
+        let file_id = match self.select_sending(name.as_deref(), "/limit <file> <KB/s|off>") {
+            Ok(id) => id,
+            Err(e) => {
+                self.status = e;
+                return;
+            }
+        };
+        let mut filename = String::new();
+        for sending in self.sending.iter_mut().filter(|s| s.file_id == file_id) {
+            sending.limit = limit;
+            filename = sending.filename.clone();
+        }
+        self.apply_rates();
+        self.status = match limit {
+            Some(rate) => format!("🐢 {} capped at {}", filename, rate_text(rate)),
+            None => format!("{} no longer capped", filename),
+        };
+        if self.active_call.is_some() && self.call_file_rate > 0 && limit.is_none_or(|rate| rate > self.call_file_rate) {
+            self.status.push_str(&format!(" (held to {} until the call ends)", rate_text(self.call_file_rate)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::protocol::CallSalt;
+    use crate::tui::state::Effect;
+    use crate::protocol::PlainMessage;
+
+    #[tokio::test]
+    async fn test_pause_limit_and_call_pacing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.bin");
+        std::fs::write(&path, vec![1u8; 1000]).unwrap();
+        let alice = "aa".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        state.call_file_rate = DEFAULT_CALL_FILE_RATE;
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+        state.handle_command(&format!("/dm {}", alice));
+        state.handle_command(&format!("/send {}", path.display()));
+        let file_id = state.outgoing_transfers.keys().next().unwrap().clone();
+        let fx = state.ingest_message(PlainMessage::file_response(alice.clone(), file_id, true, true));
+        let [Effect::StreamFile { transfer, .. }] = &fx[..] else { panic!("expected the file to stream") };
+        let control = transfer.control.clone();
+
+        // Paused, the sender waits at its next chunk until resumed
+        state.handle_command("/pause");
+        assert!(state.status.starts_with("⏸ Paused big.bin (0/1 chunks sent)"), "{}", state.status);
+        let waiting = tokio::spawn({
+            let control = control.clone();
+            async move { control.ready(10).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        state.handle_command("/resume big.bin");
+        assert_eq!(state.status, "▶ Resumed big.bin");
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap();
+
+        // A cap of its own, then a call holds it lower, and hanging up lets it go again
+        state.handle_command("/limit big.bin 100");
+        assert_eq!(state.status, "🐢 big.bin capped at 100KB/s");
+        let rate = |state: &ChatState| state.rate_for(&state.sending[0]);
+        assert_eq!(rate(&state), Some(100 * 1024));
+        state.ingest_message(PlainMessage::call_request(alice.clone(), CallSalt::generate("c1".to_string())));
+        state.handle_command("/accept-call");
+        assert!(state.status.ends_with("· 📁 files slowed to 32KB/s for the call"), "{}", state.status);
+        assert_eq!(rate(&state), Some(DEFAULT_CALL_FILE_RATE));
+        state.handle_command("/hangup");
+        assert!(state.status.ends_with("· 📁 files back to full speed"), "{}", state.status);
+        assert_eq!(rate(&state), Some(100 * 1024));
+        state.handle_command("/limit off");
+        assert_eq!(rate(&state), None);
+
+        // Once the sender's done with it, there's nothing left to pause
+        control.finish();
+        state.sweep_sending();
+        state.handle_command("/pause");
+        assert_eq!(state.status, "No files are going out");
+    }
+}
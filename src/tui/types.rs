@@ -1,12 +1,21 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::protocol::FileOffer;
+use crate::protocol::{FileOffer, GroupInvite};
 
-pub const FILE_CHUNK_SIZE: usize = 16384; // 16KB chunks for file transfer
+use super::mic::MicControls;
+use super::participants::Participant;
+use super::transfer_control::TransferControl;
+
+pub use crate::protocol::FILE_CHUNK_SIZE;
 
 /// Read receipt status for a message
 #[derive(Clone, Debug, PartialEq)]
 pub enum ReadStatus {
+    Pending,   // ⏳ — sent, not yet confirmed by the recipient
     Sent,      // ✓  — message sent/delivered
     Read,      // ✓✓ — peer has seen it
 }
@@ -24,7 +33,6 @@ pub struct AutocompleteState {
     pub commands: Vec<CommandEntry>,
     pub filtered: Vec<usize>,  // indices into commands
     pub selected: usize,       // index into filtered
-    pub filter: String,        // current filter text (after /)
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -38,6 +46,32 @@ pub enum Tab {
 pub struct GroupInfo {
     pub name: String,
     pub members: Vec<String>, // session_ids of members (excluding self)
+    pub join_token: Option<Vec<u8>>, // relay room password, passed on with invites
+}
+
+/// How far a peer's identity has been verified
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verified {
+    /// We compared safety numbers and ran /verified (unix time we did)
+    Local { at: i64 },
+    /// Both sides confirmed the same safety number (unix time it completed)
+    Mutual { at: i64 },
+}
+
+/// A verification round in progress with one peer
+#[derive(Clone, Debug)]
+pub struct PendingVerification {
+    pub challenge: Vec<u8>,
+    pub confirmed_by_us: bool,
+    pub confirmed_by_peer: bool,
+}
+
+/// A split message still being reassembled
+#[derive(Clone, Debug)]
+pub struct PartialMessage {
+    /// Content of each part, by index, once it has arrived
+    pub parts: Vec<Option<String>>,
+    pub started: Instant,
 }
 
 #[derive(Clone, Debug)]
@@ -45,6 +79,16 @@ pub struct PendingFileOffer {
     pub offer: FileOffer,
     pub from_peer: String,
     pub tab: Tab,
+    /// Arrival time, which orders the /offers list
+    pub received: Instant,
+}
+
+/// A group invite waiting for /join or /decline
+#[derive(Clone, Debug)]
+pub struct PendingInvite {
+    pub invite: GroupInvite,
+    pub from_peer: String,
+    pub received: Instant,
 }
 
 #[derive(Clone, Debug)]
@@ -53,15 +97,31 @@ pub struct ActiveTransfer {
     pub chunks_received: Vec<Option<Vec<u8>>>,
     pub save_path: PathBuf,
     pub chunks_done: u32,
+    /// Who is sending it
+    pub from_peer: String,
+    /// Where the offer was made, for warnings about the content
+    pub tab: Tab,
+    /// Unpack the archive into `save_path` instead of saving it there
+    pub extract: bool,
+    /// When we accepted it, for the rate in the completion entry
+    pub started: Instant,
 }
 
 #[derive(Clone, Debug)]
 pub struct OutgoingTransfer {
     pub offer: FileOffer,
     pub file_data: Vec<u8>,
+    /// Who the chunks go to (for a group offer, set per member as they accept)
     pub target_peer: String,
     pub chunks_sent: u32,
     pub is_direct: bool,
+    /// The group a group offer was made in: every member may accept it, and each
+    /// gets the chunks in DMs
+    pub group_id: Option<String>,
+    /// Members who have answered a group offer
+    pub answered: Vec<String>,
+    /// /pause and /limit for the task sending the chunks
+    pub control: Arc<TransferControl>,
 }
 
 #[derive(Clone, Debug)]
@@ -74,5 +134,157 @@ pub enum CallType {
 pub struct CallState {
     pub call_type: CallType,
     pub start_time: chrono::DateTime<chrono::Utc>,
-    pub muted: bool,
+    /// Mute, hang-up and targets for the task sending our microphone
+    pub mic: MicControls,
+    /// Who else is in a group call (empty for direct calls)
+    pub participants: HashMap<String, Participant>,
+    /// The peer of a direct call put it on hold
+    pub held_by_peer: bool,
+}
+
+/// Where a key binding applies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyContext {
+    Global,
+    /// Editing the input line
+    Input,
+    /// While the command autocomplete popup is open
+    Autocomplete,
+}
+
+impl KeyContext {
+    pub fn title(self) -> &'static str {
+        match self {
+            KeyContext::Global => "Global",
+            KeyContext::Input => "Input editing",
+            KeyContext::Autocomplete => "Command autocomplete",
+        }
+    }
+}
+
+/// What a key does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAction {
+    Quit,
+    ShowKeys,
+    NextTab,
+    PrevTab,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    Paste,
+    Send,
+    Newline,
+    CursorLeft,
+    CursorRight,
+    LineStart,
+    LineEnd,
+    DeleteBack,
+    DeleteForward,
+    CompletePrev,
+    CompleteNext,
+    Complete,
+    CompleteDismiss,
+}
+
+/// One key binding. The key dispatcher and the F1 overlay both read `KEY_BINDINGS`,
+/// so what's listed is what the keys do.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyBinding {
+    /// Stable name for the binding
+    pub id: &'static str,
+    pub context: KeyContext,
+    pub code: KeyCode,
+    /// Modifiers that must be held (others may be too)
+    pub modifiers: KeyModifiers,
+    /// How the key is written in the overlay
+    pub keys: &'static str,
+    pub action: KeyAction,
+    pub description: &'static str,
+}
+
+impl KeyBinding {
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        let code = match (self.code, key.code) {
+            (KeyCode::Char(a), KeyCode::Char(b)) => a.eq_ignore_ascii_case(&b),
+            (a, b) => a == b,
+        };
+        code && key.modifiers.contains(self.modifiers)
+    }
+
+    /// The first binding in `contexts` for `key`; earlier entries win, so a chord is
+    /// listed before the bare key
+    pub fn lookup(contexts: &[KeyContext], key: &KeyEvent) -> Option<KeyAction> {
+        KEY_BINDINGS.iter()
+            .find(|b| contexts.contains(&b.context) && b.matches(key))
+            .map(|b| b.action)
+    }
+}
+
+const fn bind(
+    id: &'static str,
+    context: KeyContext,
+    code: KeyCode,
+    modifiers: KeyModifiers,
+    keys: &'static str,
+    action: KeyAction,
+    description: &'static str,
+) -> KeyBinding {
+    KeyBinding { id, context, code, modifiers, keys, action, description }
+}
+
+const NONE: KeyModifiers = KeyModifiers::NONE;
+const CTRL: KeyModifiers = KeyModifiers::CONTROL;
+const ALT: KeyModifiers = KeyModifiers::ALT;
+const SHIFT: KeyModifiers = KeyModifiers::SHIFT;
+
+pub const KEY_BINDINGS: &[KeyBinding] = &[
+    bind("quit", KeyContext::Global, KeyCode::Char('c'), CTRL, "Ctrl+C", KeyAction::Quit, "Quit"),
+    bind("keys", KeyContext::Global, KeyCode::F(1), NONE, "F1", KeyAction::ShowKeys, "Show these key bindings (also /keys)"),
+    bind("next-tab", KeyContext::Global, KeyCode::Tab, NONE, "Tab", KeyAction::NextTab, "Next tab"),
+    bind("prev-tab-arrow", KeyContext::Global, KeyCode::Left, CTRL, "Ctrl+←", KeyAction::PrevTab, "Previous tab"),
+    bind("next-tab-arrow", KeyContext::Global, KeyCode::Right, CTRL, "Ctrl+→", KeyAction::NextTab, "Next tab"),
+    bind("scroll-up", KeyContext::Global, KeyCode::Up, ALT, "Alt+↑", KeyAction::ScrollUp, "Scroll up a line"),
+    bind("scroll-down", KeyContext::Global, KeyCode::Down, ALT, "Alt+↓", KeyAction::ScrollDown, "Scroll down a line"),
+    bind("page-up", KeyContext::Global, KeyCode::PageUp, NONE, "PgUp", KeyAction::PageUp, "Scroll up a page"),
+    bind("page-down", KeyContext::Global, KeyCode::PageDown, NONE, "PgDn", KeyAction::PageDown, "Scroll down a page"),
+    bind("paste", KeyContext::Global, KeyCode::Char('v'), CTRL.union(SHIFT), "Ctrl+Shift+V", KeyAction::Paste, "Paste (a clipboard image is offered as a file)"),
+    bind("newline", KeyContext::Input, KeyCode::Enter, SHIFT, "Shift+Enter", KeyAction::Newline, "New line"),
+    bind("send", KeyContext::Input, KeyCode::Enter, NONE, "Enter", KeyAction::Send, "Send the message or run the command"),
+    bind("cursor-left", KeyContext::Input, KeyCode::Left, NONE, "←", KeyAction::CursorLeft, "Move the cursor left"),
+    bind("cursor-right", KeyContext::Input, KeyCode::Right, NONE, "→", KeyAction::CursorRight, "Move the cursor right"),
+    bind("line-start", KeyContext::Input, KeyCode::Home, NONE, "Home", KeyAction::LineStart, "Start of the input"),
+    bind("line-end", KeyContext::Input, KeyCode::End, NONE, "End", KeyAction::LineEnd, "End of the input"),
+    bind("delete-back", KeyContext::Input, KeyCode::Backspace, NONE, "Backspace", KeyAction::DeleteBack, "Delete before the cursor"),
+    bind("delete-forward", KeyContext::Input, KeyCode::Delete, NONE, "Delete", KeyAction::DeleteForward, "Delete under the cursor"),
+    bind("complete-prev", KeyContext::Autocomplete, KeyCode::Up, NONE, "↑", KeyAction::CompletePrev, "Previous command"),
+    bind("complete-next", KeyContext::Autocomplete, KeyCode::Down, NONE, "↓", KeyAction::CompleteNext, "Next command"),
+    bind("complete", KeyContext::Autocomplete, KeyCode::Enter, NONE, "Enter", KeyAction::Complete, "Fill in the selected command"),
+    bind("complete-tab", KeyContext::Autocomplete, KeyCode::Tab, NONE, "Tab", KeyAction::Complete, "Fill in the selected command"),
+    bind("complete-dismiss", KeyContext::Autocomplete, KeyCode::Esc, NONE, "Esc", KeyAction::CompleteDismiss, "Close the list"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn action(contexts: &[KeyContext], code: KeyCode, modifiers: KeyModifiers) -> Option<KeyAction> {
+        KeyBinding::lookup(contexts, &KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn test_key_bindings_resolve_chords_first() {
+        let ids: HashSet<&str> = KEY_BINDINGS.iter().map(|b| b.id).collect();
+        assert_eq!(ids.len(), KEY_BINDINGS.len());
+
+        let editing = [KeyContext::Global, KeyContext::Input];
+        assert_eq!(action(&editing, KeyCode::Left, CTRL), Some(KeyAction::PrevTab));
+        assert_eq!(action(&editing, KeyCode::Left, NONE), Some(KeyAction::CursorLeft));
+        assert_eq!(action(&editing, KeyCode::Enter, SHIFT), Some(KeyAction::Newline));
+        assert_eq!(action(&editing, KeyCode::Char('V'), CTRL | SHIFT), Some(KeyAction::Paste));
+        assert_eq!(action(&editing, KeyCode::Char('v'), NONE), None);
+        assert_eq!(action(&[KeyContext::Autocomplete], KeyCode::Char('x'), NONE), None);
+    }
 }
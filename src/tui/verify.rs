@@ -0,0 +1,287 @@
+use crate::client::OutgoingMessage;
+use base64::Engine;
+
+use crate::crypto::safety_number::{
+    challenge_words, compute_safety_number, key_fingerprint, new_challenge, verification_proof, CHALLENGE_LEN,
+};
+use crate::protocol::{short_id, PlainMessage, Verification};
+
+use super::clipboard;
+use super::types::{PendingVerification, Tab, Verified};
+use super::state::{ChatState, Effect};
+
+impl ChatState {
+    /// How far `peer_id`'s identity key has been verified
+    pub(crate) fn verification_of(&self, peer_id: &str) -> Option<&Verified> {
+        let peer = self.peers.get(peer_id)?;
+        self.verified_peers.get(&peer.public_key)
+    }
+
+    /// Sidebar icon: ❓ unverified, ✅ verified on our side, 🔒 verified by both sides
+    pub(crate) fn verification_icon(&self, peer_id: &str) -> &'static str {
+        match self.verification_of(peer_id) {
+            None => "❓",
+            Some(Verified::Local { .. }) => "✅",
+            Some(Verified::Mutual { .. }) => "🔒",
+        }
+    }
+
+    /// Our identity key as peers see it (base64)
+    pub(crate) fn own_identity_b64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(&self.own_public_key)
+    }
+
+    /// Handle /id [copy] — show our full identity key, session id and key fingerprint, or
+    /// put the identity key on the clipboard
+    pub(crate) fn handle_id_command(&mut self, args: &[&str]) {
+        let identity = self.own_identity_b64();
+        match args.first().copied() {
+            Some("copy") => {
+                self.status = match clipboard::copy_text(&identity) {
+                    Ok(()) => "📋 Identity key copied to the clipboard".to_string(),
+                    Err(e) => format!("📋 {:#}", e),
+                };
+            }
+            Some(other) => self.status = format!("Unknown /id option {:?} — usage: /id [copy]", other),
+            None => {
+                let fingerprint = key_fingerprint(&self.own_public_key);
+                let text = format!(
+                    "🪪 Your identity\n  Identity key: {}\n  Session id:   {}\n  Fingerprint:  {}\n                {}\n\
+                     The identity key and fingerprint stay the same every session; the session id changes. /id copy puts the key on the clipboard.",
+                    identity,
+                    self.own_id,
+                    fingerprint.numeric(),
+                    fingerprint.emoji()
+                );
+                let tab = self.tabs[self.active_tab].clone();
+                self.add_system_message(&tab, text);
+            }
+        }
+    }
+
+    /// Handle /verify [nickname|peer_id] — show the safety number and ask the peer
+    /// to compare it too. With no argument, uses the current DM tab's peer.
+    pub(crate) fn handle_verify_command(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        let Some(peer_id) = self.verify_target(args, "/verify") else {
+            return;
+        };
+        let Some(peer_key) = self.connected_key(&peer_id) else {
+            return;
+        };
+
+        // A round we were asked to join is reused, so both screens show the same words
+        let challenge = match self.verifications.get(&peer_id) {
+            Some(pending) => pending.challenge.clone(),
+            None => {
+                let challenge = new_challenge();
+                self.verifications.insert(peer_id.clone(), PendingVerification {
+                    challenge: challenge.clone(),
+                    confirmed_by_us: false,
+                    confirmed_by_peer: false,
+                });
+                fx.push(Effect::Send(OutgoingMessage::Direct {
+                    target_id: peer_id.clone(),
+                    message: PlainMessage::verification(
+                        self.own_id.clone(),
+                        Verification::Request { challenge: challenge.clone() },
+                    ),
+                }));
+                challenge
+            }
+        };
+
+        let peer_name = self.get_peer_display_name(&peer_id);
+        let tab = self.tabs[self.active_tab].clone();
+        let text = self.safety_number_text(&peer_id, &peer_key, &challenge, args.first().copied());
+        self.push_message(tab, PlainMessage::system("system".to_string(), text));
+        self.status = format!("Safety number shown for {} — verification request sent", peer_name);
+    }
+
+    /// Handle /verified [nickname|peer_id] — mark a peer as verified, and confirm to
+    /// them if a verification round is open
+    pub(crate) fn handle_mark_verified(&mut self, args: &[&str], fx: &mut Vec<Effect>) {
+        let Some(peer_id) = self.verify_target(args, "/verified") else {
+            return;
+        };
+        let Some(peer_key) = self.connected_key(&peer_id) else {
+            return;
+        };
+
+        let peer_name = self.get_peer_display_name(&peer_id);
+        if !matches!(self.verified_peers.get(&peer_key), Some(Verified::Mutual { .. })) {
+            self.verified_peers.insert(peer_key.clone(), Verified::Local { at: chrono::Utc::now().timestamp() });
+        }
+        self.status = format!("✅ {} marked as verified", peer_name);
+
+        let tab = self.tabs[self.active_tab].clone();
+        let msg = PlainMessage::system(
+            "system".to_string(),
+            format!("✅ {} is now verified — identity confirmed!", peer_name),
+        );
+        self.push_message(tab, msg);
+
+        let Some(pending) = self.verifications.get_mut(&peer_id) else {
+            return;
+        };
+        pending.confirmed_by_us = true;
+        let proof = verification_proof(&self.own_public_key, &peer_key, &pending.challenge);
+        fx.push(Effect::Send(OutgoingMessage::Direct {
+            target_id: peer_id.clone(),
+            message: PlainMessage::verification(
+                self.own_id.clone(),
+                Verification::Confirm { challenge: pending.challenge.clone(), proof },
+            ),
+        }));
+        if pending.confirmed_by_peer {
+            self.complete_verification(&peer_id, &peer_key);
+        } else {
+            self.status = format!("✅ {} marked as verified — waiting for them to confirm", peer_name);
+        }
+    }
+
+    /// A verification request or confirmation from a peer
+    pub(crate) fn handle_verification(&mut self, msg: &PlainMessage, verification: Verification) {
+        let peer_id = msg.sender.clone();
+        let peer_name = self.get_peer_display_name(&peer_id);
+        let Some(peer_key) = self.peers.get(&peer_id).map(|p| p.public_key.clone()) else {
+            return;
+        };
+
+        match verification {
+            Verification::Request { challenge } => {
+                if challenge.len() != CHALLENGE_LEN {
+                    self.status = format!("⚠️ Malformed verification request from {}", peer_name);
+                    return;
+                }
+                // Both sides ran /verify at once: everyone keeps the smaller challenge,
+                // so the two rounds collapse into one
+                if let Some(pending) = self.verifications.get(&peer_id) {
+                    if pending.challenge <= challenge {
+                        return;
+                    }
+                }
+                self.verifications.insert(peer_id.clone(), PendingVerification {
+                    challenge: challenge.clone(),
+                    confirmed_by_us: false,
+                    confirmed_by_peer: false,
+                });
+
+                let dm_tab = Tab::DirectMessage(peer_id.clone());
+                let text = format!(
+                    "🔐 {} asked to verify your connection.\n{}",
+                    peer_name,
+                    self.safety_number_text(&peer_id, &peer_key, &challenge, None),
+                );
+                self.push_message(dm_tab, PlainMessage::system("system".to_string(), text));
+                self.status = format!("{} wants to verify safety numbers", peer_name);
+            }
+            Verification::Confirm { challenge, proof } => {
+                let Some(pending) = self.verifications.get_mut(&peer_id) else {
+                    self.status = format!("⚠️ Unexpected verification confirmation from {}", peer_name);
+                    return;
+                };
+                if pending.challenge != challenge {
+                    self.status = format!("⚠️ Stale verification confirmation from {}", peer_name);
+                    return;
+                }
+                if proof != verification_proof(&self.own_public_key, &peer_key, &challenge) {
+                    // They confirmed a different pair of keys than we see
+                    self.verifications.remove(&peer_id);
+                    let dm_tab = Tab::DirectMessage(peer_id.clone());
+                    let msg = PlainMessage::system(
+                        "system".to_string(),
+                        format!(
+                            "⚠️ {} confirmed a different pair of keys than the ones you see — one of you confirmed the wrong peer or key. Compare the safety number again before trusting it.",
+                            peer_name
+                        ),
+                    );
+                    self.push_message(dm_tab, msg);
+                    self.status = format!("⚠️ Verification with {} failed", peer_name);
+                    return;
+                }
+
+                pending.confirmed_by_peer = true;
+                if pending.confirmed_by_us {
+                    self.complete_verification(&peer_id, &peer_key);
+                } else {
+                    self.status = format!(
+                        "{} confirmed the safety number — run /verified once you've compared it too",
+                        peer_name
+                    );
+                }
+            }
+        }
+    }
+
+    /// Both sides confirmed: record the mutual verification
+    fn complete_verification(&mut self, peer_id: &str, peer_key: &[u8]) {
+        self.verifications.remove(peer_id);
+        self.verified_peers.insert(peer_key.to_vec(), Verified::Mutual { at: chrono::Utc::now().timestamp() });
+
+        let peer_name = self.get_peer_display_name(peer_id);
+        let msg = PlainMessage::system(
+            "system".to_string(),
+            format!("🔒 You and {} verified each other's safety number", peer_name),
+        );
+        self.push_message(Tab::DirectMessage(peer_id.to_string()), msg);
+        self.status = format!("🔒 {} verified mutually", peer_name);
+    }
+
+    /// Safety number, challenge words and what to do next, for a system message
+    fn safety_number_text(&self, peer_id: &str, peer_key: &[u8], challenge: &[u8], name_arg: Option<&str>) -> String {
+        let safety_number = compute_safety_number(&self.own_public_key, peer_key);
+        let peer_name = self.get_peer_display_name(peer_id);
+        let verified = match self.verification_of(peer_id) {
+            Some(Verified::Mutual { at }) => {
+                let date = chrono::DateTime::from_timestamp(*at, 0)
+                    .map(|d| d.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                format!(" 🔒 (verified mutually on {})", date)
+            }
+            Some(Verified::Local { .. }) => " ✅".to_string(),
+            None => String::new(),
+        };
+        format!(
+            "🔐 Safety Number with {}{}\n  Numbers: {}\n  Emoji:   {}\n  Words:   {}\n\nBoth sides should see the same code and words.\nIf they match, run /verified {} to mark as verified.",
+            peer_name,
+            verified,
+            safety_number.numeric(),
+            safety_number.emoji(),
+            challenge_words(challenge),
+            name_arg.unwrap_or(short_id(peer_id)),
+        )
+    }
+
+    /// Peer named in `args`, or the current DM tab's peer
+    fn verify_target(&mut self, args: &[&str], command: &str) -> Option<String> {
+        let peer_id = match args.first() {
+            None => match &self.tabs[self.active_tab] {
+                Tab::DirectMessage(id) => Ok(id.clone()),
+                _ => Err(format!("Usage: {} <nickname|peer_id> (or use in a DM tab)", command)),
+            },
+            Some(name) => self.find_peer_by_name_or_id(name),
+        };
+        match peer_id {
+            Ok(id) => Some(id),
+            Err(e) => {
+                self.status = e;
+                None
+            }
+        }
+    }
+
+    /// Identity key of a connected peer (verification is tied to the key, not the session)
+    fn connected_key(&mut self, peer_id: &str) -> Option<Vec<u8>> {
+        match self.peers.get(peer_id) {
+            None => {
+                self.status = "Peer not connected".to_string();
+                None
+            }
+            Some(peer) if peer.public_key.is_empty() => {
+                self.status = "No public key available for this peer yet".to_string();
+                None
+            }
+            Some(peer) => Some(peer.public_key.clone()),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! /whois: everything this chat knows about one peer in a single entry — names, keys,
+//! verification, what we share with them and what's going on with them right now.
+//! Verification and contact policies follow the identity key, so they show up for
+//! whichever session that key is on.
+
+use base64::Engine;
+
+use crate::crypto::safety_number::{compute_safety_number, key_fingerprint};
+
+use super::contacts::describe;
+use super::state::ChatState;
+use super::types::{CallType, Tab, Verified};
+
+impl ChatState {
+    /// Handle /whois <nickname|id> (or in a DM tab, that peer)
+    pub(crate) fn handle_whois_command(&mut self, args: &[&str]) {
+        let peer_id = match args.first() {
+            Some(target) => self.find_peer_by_name_or_id(target),
+            None => match &self.tabs[self.active_tab] {
+                Tab::DirectMessage(id) => Ok(id.clone()),
+                _ => Err("Usage: /whois <nickname|peer_id> (or use in a DM tab)".to_string()),
+            },
+        };
+        match peer_id {
+            Ok(id) => {
+                let text = self.whois(&id);
+                let tab = self.tabs[self.active_tab].clone();
+                self.add_system_message(&tab, text);
+            }
+            Err(e) => self.status = e,
+        }
+    }
+
+    /// The /whois entry for `peer_id`
+    pub(crate) fn whois(&self, peer_id: &str) -> String {
+        let name = self.get_peer_display_name(peer_id);
+        let peer = self.peers.get(peer_id);
+        let mut lines = vec![format!("👤 {}", name)];
+
+        let nickname = peer.and_then(|p| p.nickname.as_deref());
+        match nickname {
+            Some(nick) if nick != name => lines.push(format!("  Nickname:      {} (shown as {} to tell namesakes apart)", nick, name)),
+            Some(nick) => lines.push(format!("  Nickname:      {}", nick)),
+            None => lines.push("  Nickname:      none announced yet".to_string()),
+        }
+        if let Some(hint) = self.name_hints.get(peer_id).filter(|h| Some(h.nickname.as_str()) != nickname) {
+            lines.push(format!("  Introduced as: {} (by an invite or group roster)", hint.nickname));
+        }
+        lines.push(format!("  Session id:    {}", peer_id));
+
+        match peer.map(|p| p.public_key.as_slice()).filter(|key| !key.is_empty()) {
+            Some(key) => {
+                let fingerprint = key_fingerprint(key);
+                lines.push(format!("  Identity key:  {}", base64::engine::general_purpose::STANDARD.encode(key)));
+                lines.push(format!("  Fingerprint:   {}", fingerprint.numeric()));
+                lines.push(format!("                 {}", fingerprint.emoji()));
+                lines.push(format!("  Safety number: {}", compute_safety_number(&self.own_public_key, key).numeric()));
+            }
+            None => lines.push("  Identity key:  not received yet".to_string()),
+        }
+        lines.push(format!("  Verified:      {}", self.whois_verified(peer_id, &name)));
+        if let Some(policy) = peer.and_then(|p| self.contact_policies.get(&p.public_key)) {
+            lines.push(format!("  Policy:        {}", describe(policy)));
+        }
+
+        let mut groups: Vec<&str> = self.groups.values()
+            .filter(|g| g.members.iter().any(|id| id == peer_id))
+            .map(|g| g.name.as_str())
+            .collect();
+        groups.sort();
+        lines.push(format!("  Groups shared: {}", if groups.is_empty() { "none".to_string() } else { groups.join(", ") }));
+        let dm = Tab::DirectMessage(peer_id.to_string());
+        lines.push(format!("  DM tab:        {}", if self.tabs.contains(&dm) { "open" } else { "none" }));
+
+        let (sent, in_dm) = self.tally.messages.get(&dm).copied().unwrap_or_default();
+        let received = self.tally.received_from.get(peer_id).copied().unwrap_or_default();
+        lines.push(format!(
+            "  This session:  {} message{} sent in your DM, {} received from them ({} in your DM)",
+            sent,
+            if sent == 1 { "" } else { "s" },
+            received,
+            in_dm
+        ));
+
+        let activity = self.whois_activity(peer_id);
+        lines.push(format!("  Now:           {}", if activity.is_empty() { "nothing in progress".to_string() } else { activity.join(" · ") }));
+        lines.join("\n")
+    }
+
+    fn whois_verified(&self, peer_id: &str, name: &str) -> String {
+        let date = |at: i64| {
+            chrono::DateTime::from_timestamp(at, 0)
+                .map(|d| d.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default()
+        };
+        let verified = match self.verification_of(peer_id) {
+            Some(Verified::Mutual { at }) => format!("🔒 mutually, {}", date(*at)),
+            Some(Verified::Local { at }) => format!("✅ by you, {} (/verify {} to confirm it both ways)", date(*at), name),
+            None => format!("❓ no — compare safety numbers with /verify {}", name),
+        };
+        if self.is_untrusted(peer_id) {
+            format!("{} ⚠️ their identity key changed since it was verified", verified)
+        } else {
+            verified
+        }
+    }
+
+    /// Transfers and calls going on with `peer_id`
+    fn whois_activity(&self, peer_id: &str) -> Vec<String> {
+        let mut activity = Vec::new();
+        for t in self.outgoing_transfers.values().filter(|t| t.target_peer == peer_id) {
+            activity.push(format!("📤 {} ({}/{} chunks)", t.offer.filename, t.chunks_sent, t.offer.total_chunks));
+        }
+        for file in self.deferred_files.iter().filter(|f| f.peer == peer_id) {
+            activity.push(format!("📶 {} waiting for /lowbw off", file.transfer.offer.filename));
+        }
+        for t in self.active_transfers.values().filter(|t| t.from_peer == peer_id) {
+            activity.push(format!("📥 {} ({}/{} chunks)", t.offer.filename, t.chunks_done, t.offer.total_chunks));
+        }
+        for offer in self.pending_offers.values().filter(|o| o.from_peer == peer_id) {
+            activity.push(format!("📎 {} offered, waiting for /accept", offer.offer.filename));
+        }
+        if let Some(ref call) = self.active_call {
+            let with_them = match &call.call_type {
+                CallType::Direct(id) => id == peer_id,
+                CallType::Group { .. } => call.participants.contains_key(peer_id),
+            };
+            if with_them {
+                let since = call.start_time.with_timezone(&chrono::Local).format("%H:%M");
+                activity.push(format!("📞 in a call with you since {}", since));
+            }
+        }
+        if self.pending_call_from.as_deref() == Some(peer_id) {
+            activity.push("📞 calling you (/accept-call)".to_string());
+        }
+        activity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{PeerDisplay, PeerUpdate};
+    use crate::protocol::PlainMessage;
+    use crate::tui::types::GroupInfo;
+
+    #[test]
+    fn test_whois_gathers_what_we_know() {
+        let alice = "aa".repeat(16);
+        let mut state = ChatState::new("me".repeat(16), None, vec![0; 32]);
+        let display = PeerDisplay { nickname: Some("alice".to_string()), public_key: vec![1; 32] };
+        state.apply_peer_updates(vec![PeerUpdate::Added(alice.clone(), display)]);
+        state.groups.insert("g1".to_string(), GroupInfo { name: "team".to_string(), members: vec![alice.clone()], join_token: None });
+        state.ingest_message(PlainMessage::direct(alice.clone(), "hi".to_string()));
+
+        state.handle_command("/whois alice");
+        let text = state.messages[&state.tabs[state.active_tab]].last().unwrap().content.clone();
+        let key = base64::engine::general_purpose::STANDARD.encode([1; 32]);
+        assert!(text.starts_with("👤 alice\n  Nickname:      alice\n"), "{}", text);
+        assert!(text.contains(&format!("  Session id:    {}", alice)));
+        assert!(text.contains(&format!("  Identity key:  {}", key)));
+        assert!(text.contains(&compute_safety_number(&[0; 32], &[1; 32]).numeric()));
+        assert!(text.contains("  Verified:      ❓ no"));
+        assert!(text.contains("  Groups shared: team"));
+        assert!(text.contains("  DM tab:        open"));
+        assert!(text.contains("0 messages sent in your DM, 1 received from them (1 in your DM)"), "{}", text);
+        assert!(text.contains("  Now:           nothing in progress"));
+
+        // Verification follows the key
+        state.handle_command("/verified alice");
+        assert!(state.whois(&alice).contains("  Verified:      ✅ by you, "));
+
+        state.handle_command("/whois nobody");
+        assert_eq!(state.status, "Peer not found: nobody");
+    }
+}
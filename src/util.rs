@@ -0,0 +1,186 @@
+//! Filesystem paths: `~` expansion for user-typed paths, the default home of the
+//! identity and config files on each platform, and where named profiles keep theirs.
+
+use std::path::{Path, PathBuf};
+
+/// Directory name under the platform config dir (and, with a dot, under home)
+const APP_DIR: &str = "wsp";
+
+/// Expand a leading `~` to the home directory and use the platform's separators.
+/// `~`, `~/…` and `~\…` are expanded on every platform; `~user` forms and paths
+/// without a tilde are left as they are. If there's no home directory the path is
+/// returned unexpanded.
+pub fn expand_path(path: &str) -> PathBuf {
+    expand_path_with(path, dirs::home_dir().as_deref())
+}
+
+fn expand_path_with(path: &str, home: Option<&Path>) -> PathBuf {
+    let rest = match path.strip_prefix('~') {
+        Some("") => Some(""),
+        Some(rest) if rest.starts_with(['/', '\\']) => Some(&rest[1..]),
+        _ => None,
+    };
+    match (rest, home) {
+        (Some(rest), Some(home)) => {
+            let mut buf = home.to_path_buf();
+            // Push piece by piece so `~/a/b` comes out as `C:\Users\me\a\b` on Windows
+            buf.extend(rest.split(['/', '\\']).filter(|part| !part.is_empty()));
+            buf
+        }
+        _ => normalize(path),
+    }
+}
+
+/// Rebuild `path` from its components, which turns `/` into `\` on Windows and drops
+/// doubled separators. A no-op for ordinary Unix paths.
+fn normalize(path: &str) -> PathBuf {
+    if path.is_empty() {
+        return PathBuf::new();
+    }
+    Path::new(path).components().collect()
+}
+
+/// Where the identity and config live by default. An existing `~/.wsp` from earlier
+/// versions keeps being used; otherwise it's `wsp` under the platform config dir
+/// (`~/.config/wsp`, `~/Library/Application Support/wsp`, `%APPDATA%\wsp`).
+pub fn data_dir() -> PathBuf {
+    data_dir_with(dirs::home_dir().as_deref(), dirs::config_dir().as_deref())
+}
+
+fn data_dir_with(home: Option<&Path>, config_dir: Option<&Path>) -> PathBuf {
+    let legacy = home.map(|home| home.join(format!(".{}", APP_DIR)));
+    match (legacy, config_dir) {
+        (Some(legacy), _) if legacy.is_dir() => legacy,
+        (_, Some(config_dir)) => config_dir.join(APP_DIR),
+        (Some(legacy), None) => legacy,
+        (None, None) => PathBuf::from(format!(".{}", APP_DIR)),
+    }
+}
+
+/// Longest profile name `--profile` takes
+pub const MAX_PROFILE_NAME: usize = 32;
+
+/// Whether `name` works as a profile (and so a directory) name: letters, digits, `-`
+/// and `_`
+pub fn valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_PROFILE_NAME
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Where named profiles live, one directory each
+pub fn profiles_dir() -> PathBuf {
+    data_dir().join("profiles")
+}
+
+/// Where a profile keeps everything tied to its identity: the default profile at the
+/// top of `data_dir()`, a named one under `profiles_dir()`. Files an identity writes
+/// belong in here, so two profiles never read each other's.
+pub fn profile_dir(profile: Option<&str>) -> PathBuf {
+    match profile {
+        Some(name) => profiles_dir().join(name),
+        None => data_dir(),
+    }
+}
+
+/// A profile's identity file
+pub fn identity_path(profile: Option<&str>) -> PathBuf {
+    profile_dir(profile).join("identity")
+}
+
+/// Default identity file (`wsp init`, `wsp chat --identity`)
+pub fn default_identity_path() -> PathBuf {
+    identity_path(None)
+}
+
+/// The public key kept beside an identity file, so profiles can be listed without
+/// unlocking them
+pub fn public_key_path(identity_path: &Path) -> PathBuf {
+    identity_path.with_extension("pub")
+}
+
+/// The copy of an identity file written beside it on every save, to fall back on if
+/// the file itself is damaged
+pub fn identity_backup_path(identity_path: &Path) -> PathBuf {
+    identity_path.with_extension("bak")
+}
+
+/// The lock a running `wsp chat` keeps beside its identity file, holding its pid
+pub fn identity_lock_path(identity_path: &Path) -> PathBuf {
+    identity_path.with_extension("lock")
+}
+
+/// Where a bare `/accept` saves files unless the config says otherwise: `wsp` under
+/// the platform's downloads folder (`~/Downloads/wsp`)
+pub fn default_download_dir() -> PathBuf {
+    dirs::download_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join("Downloads")))
+        .unwrap_or_default()
+        .join(APP_DIR)
+}
+
+/// Where `wsp relay` keeps its key unless told otherwise
+pub fn relay_key_path() -> PathBuf {
+    data_dir().join("relay_key")
+}
+
+/// The relay keys a profile has seen, by relay URL, kept beside its identity file
+pub fn known_relays_path(identity_path: &Path) -> PathBuf {
+    identity_path.with_file_name("known_relays.json")
+}
+
+/// Default config file (`wsp chat --config`)
+pub fn default_config_path() -> PathBuf {
+    data_dir().join("config.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_path_matrix() {
+        let home = Path::new("/home/me");
+        let cases = [
+            ("~", home.to_path_buf()),
+            ("~/", home.to_path_buf()),
+            ("~/notes.txt", home.join("notes.txt")),
+            ("~/a/b", home.join("a").join("b")),
+            ("~\\a\\b", home.join("a").join("b")),
+            ("~/a\\b/", home.join("a").join("b")),
+            ("~other/x", normalize("~other/x")),
+            ("~~", normalize("~~")),
+            ("/abs/path", PathBuf::from("/abs/path")),
+            ("rel/path", PathBuf::from("rel/path")),
+            ("a//b", PathBuf::from("a/b")),
+            ("", PathBuf::new()),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(expand_path_with(input, Some(home)), expected, "{:?}", input);
+        }
+        // No home: nothing to expand into
+        assert_eq!(expand_path_with("~/x", None), normalize("~/x"));
+    }
+
+    #[test]
+    fn test_data_dir_prefers_existing_legacy_dir() {
+        let home = tempfile::tempdir().unwrap();
+        let config = home.path().join(".config");
+        assert_eq!(data_dir_with(Some(home.path()), Some(&config)), config.join("wsp"));
+        assert_eq!(data_dir_with(Some(home.path()), None), home.path().join(".wsp"));
+        assert_eq!(data_dir_with(None, None), PathBuf::from(".wsp"));
+
+        std::fs::create_dir(home.path().join(".wsp")).unwrap();
+        assert_eq!(data_dir_with(Some(home.path()), Some(&config)), home.path().join(".wsp"));
+    }
+
+    #[test]
+    fn test_profile_names() {
+        assert!(valid_profile_name("work") && valid_profile_name("side-project_2"));
+        for bad in ["", "../escape", "a/b", "with space", ".hidden", &"x".repeat(MAX_PROFILE_NAME + 1)] {
+            assert!(!valid_profile_name(bad), "{:?}", bad);
+        }
+        assert_eq!(identity_path(Some("work")), profiles_dir().join("work").join("identity"));
+        assert_eq!(public_key_path(Path::new("/p/identity")), PathBuf::from("/p/identity.pub"));
+    }
+}
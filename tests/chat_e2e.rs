@@ -0,0 +1,436 @@
+//! Two real clients talking through a real relay: key exchange, ratchet, delivery.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use wsp::client::{ChatClient, ClientStatus, ConnectionState, OutgoingMessage, OutgoingSender, PeerDisplay, PeerUpdate, SetupTimeout};
+use wsp::crypto::relay_key::RelayKey;
+use wsp::crypto::Identity;
+use wsp::protocol::{PlainMessage, JOIN_TOKEN_LEN};
+use wsp::relay::{RelayHandle, RelayServer};
+
+/// Upper bound for anything that should happen "soon"
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A relay on an ephemeral port, shut down when dropped
+struct Relay {
+    addr: SocketAddr,
+    _handle: RelayHandle,
+}
+
+async fn start_relay() -> Relay {
+    let handle = RelayServer::new("127.0.0.1:0".to_string()).bind().await.unwrap().spawn();
+    Relay { addr: handle.local_addr(), _handle: handle }
+}
+
+/// TCP pass-through to the relay whose open links can be cut, to simulate a dropped connection
+struct Proxy {
+    addr: SocketAddr,
+    links: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Proxy {
+    async fn start(upstream: SocketAddr) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let links = Arc::new(Mutex::new(Vec::new()));
+        let links_accept = links.clone();
+        tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                let link = tokio::spawn(async move {
+                    if let Ok(mut outbound) = TcpStream::connect(upstream).await {
+                        let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                    }
+                });
+                links_accept.lock().unwrap().push(link);
+            }
+        });
+        Self { addr, links }
+    }
+
+    /// Drop every open link (new connections are still accepted)
+    fn sever(&self) {
+        for link in self.links.lock().unwrap().drain(..) {
+            link.abort();
+        }
+    }
+}
+
+struct Peer {
+    id: String,
+    tx: OutgoingSender,
+    incoming: mpsc::UnboundedReceiver<PlainMessage>,
+    status: mpsc::UnboundedReceiver<ClientStatus>,
+    peer_updates: mpsc::UnboundedReceiver<Vec<PeerUpdate>>,
+    /// The peer list as built up from `peer_updates`
+    known: HashMap<String, PeerDisplay>,
+    /// Join notices skipped over by `next_chat`
+    joins: Vec<String>,
+    _audio: mpsc::UnboundedReceiver<(String, String, Vec<u8>)>,
+}
+
+impl Peer {
+    async fn connect(addr: SocketAddr) -> Self {
+        Self::connect_as(addr, None).await
+    }
+
+    async fn connect_as(addr: SocketAddr, nickname: Option<&str>) -> Self {
+        Self::connect_with(addr, Identity::generate(), nickname).await
+    }
+
+    async fn connect_with(addr: SocketAddr, identity: Identity, nickname: Option<&str>) -> Self {
+        let nickname = nickname.map(str::to_string);
+        Self::start(ChatClient::new(identity, format!("ws://{}", addr), nickname)).await
+    }
+
+    async fn start(mut client: ChatClient) -> Self {
+        client.set_reconnect_backoff(Duration::from_millis(20), Duration::from_millis(100));
+        let id = client.session_id().to_string();
+        let (tx, incoming, status, peer_updates, audio) = client.connect().await.unwrap();
+        Self { id, tx, incoming, status, peer_updates, known: HashMap::new(), joins: Vec::new(), _audio: audio }
+    }
+
+    /// Wait until the peer list satisfies `done`
+    async fn wait_for_peers(&mut self, done: impl Fn(&HashMap<String, PeerDisplay>) -> bool) -> bool {
+        tokio::time::timeout(TIMEOUT, async {
+            while !done(&self.known) {
+                for update in self.peer_updates.recv().await.expect("client shut down") {
+                    match update {
+                        PeerUpdate::Added(id, peer) | PeerUpdate::Changed(id, peer) => {
+                            self.known.insert(id, peer);
+                        }
+                        PeerUpdate::Inactive(id, _) | PeerUpdate::Removed(id) => {
+                            self.known.remove(&id);
+                        }
+                    }
+                }
+            }
+        }).await.is_ok()
+    }
+
+    /// Wait until a ratchet session with `peer_id` exists
+    async fn wait_for_peer(&mut self, peer_id: &str) {
+        assert!(self.wait_for_peers(|peers| peers.contains_key(peer_id)).await, "no session with peer");
+    }
+
+    /// Wait until the peer list shows `peer_id` under `nickname`
+    async fn wait_for_nickname(&mut self, peer_id: &str, nickname: &str) {
+        let seen = self.wait_for_peers(|peers| {
+            peers.get(peer_id).and_then(|p| p.nickname.as_deref()) == Some(nickname)
+        }).await;
+        assert!(seen, "never saw nickname {:?}", nickname);
+    }
+
+    /// Wait for a status line containing `needle`
+    async fn wait_for_status(&mut self, needle: &str) {
+        tokio::time::timeout(TIMEOUT, async {
+            while let Some(status) = self.status.recv().await {
+                if status.to_string().contains(needle) {
+                    return;
+                }
+            }
+        }).await.unwrap_or_else(|_| panic!("no status containing {:?}", needle));
+    }
+
+    /// Wait for a connection state matching `done`, skipping events
+    async fn wait_for_connection(&mut self, done: impl Fn(&ConnectionState) -> bool) {
+        tokio::time::timeout(TIMEOUT, async {
+            while let Some(status) = self.status.recv().await {
+                if matches!(status, ClientStatus::Connection(ref state) if done(state)) {
+                    return;
+                }
+            }
+        }).await.expect("connection state never reached");
+    }
+
+    /// Next chat message, skipping join notifications and other system lines
+    async fn next_chat(&mut self) -> PlainMessage {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let msg = self.incoming.recv().await.expect("client shut down");
+                if !msg.system {
+                    return msg;
+                }
+                if msg.content.ends_with(" has joined") {
+                    self.joins.push(msg.sender);
+                }
+            }
+        }).await.expect("timed out waiting for a message")
+    }
+
+    fn send_global(&self, text: &str) {
+        let msg = PlainMessage::new(self.id.clone(), text.to_string());
+        self.tx.send(OutgoingMessage::Global(msg)).unwrap();
+    }
+
+    fn send_direct(&self, to: &Peer, text: &str) {
+        let msg = PlainMessage::direct(self.id.clone(), text.to_string());
+        self.tx.send(OutgoingMessage::Direct { target_id: to.id.clone(), message: msg }).unwrap();
+    }
+}
+
+/// Connect two clients and wait until both have a ratchet with the other
+async fn pair(alice_addr: SocketAddr, bob_addr: SocketAddr) -> (Peer, Peer) {
+    let mut alice = Peer::connect(alice_addr).await;
+    alice.wait_for_status("Connected to relay").await;
+    let mut bob = Peer::connect(bob_addr).await;
+    alice.wait_for_peer(&bob.id).await;
+    bob.wait_for_peer(&alice.id).await;
+    (alice, bob)
+}
+
+async fn assert_exchange(alice: &mut Peer, bob: &mut Peer, round: &str) {
+    // Bob speaks first — the responder's first ratchet step has broken before
+    bob.send_global(&format!("{round}: global from bob"));
+    let msg = alice.next_chat().await;
+    assert_eq!((msg.sender.as_str(), msg.direct), (bob.id.as_str(), false));
+    assert_eq!(msg.content, format!("{round}: global from bob"));
+
+    alice.send_global(&format!("{round}: global from alice"));
+    let msg = bob.next_chat().await;
+    assert_eq!((msg.sender.as_str(), msg.direct), (alice.id.as_str(), false));
+    assert_eq!(msg.content, format!("{round}: global from alice"));
+
+    alice.send_direct(bob, &format!("{round}: dm from alice"));
+    let msg = bob.next_chat().await;
+    assert_eq!((msg.sender.as_str(), msg.direct), (alice.id.as_str(), true));
+    assert_eq!(msg.content, format!("{round}: dm from alice"));
+
+    bob.send_direct(alice, &format!("{round}: dm from bob"));
+    let msg = alice.next_chat().await;
+    assert_eq!((msg.sender.as_str(), msg.direct), (bob.id.as_str(), true));
+    assert_eq!(msg.content, format!("{round}: dm from bob"));
+}
+
+#[tokio::test]
+async fn test_two_clients_exchange_messages() {
+    let relay = start_relay().await;
+    let (mut alice, mut bob) = pair(relay.addr, relay.addr).await;
+
+    assert_exchange(&mut alice, &mut bob, "first").await;
+    assert_exchange(&mut alice, &mut bob, "second").await;
+}
+
+#[tokio::test]
+async fn test_reconnect_keeps_session() {
+    let relay = start_relay().await;
+    let proxy = Proxy::start(relay.addr).await;
+    // Bob reaches the relay through the proxy so his link can be cut on its own
+    let (mut alice, mut bob) = pair(relay.addr, proxy.addr).await;
+    assert_exchange(&mut alice, &mut bob, "before").await;
+    // Alice confirms Bob's DM; unconfirmed, it would be sent again after the reconnect
+    bob.wait_for_status("delivered").await;
+
+    proxy.sever();
+    bob.wait_for_connection(|state| matches!(state, ConnectionState::Reconnecting { attempt: 1, .. })).await;
+    bob.wait_for_connection(|state| matches!(state, ConnectionState::Connected { .. })).await;
+    bob.wait_for_status("Reconnected").await;
+
+    // Same session id and the same ratchets carry on after the reconnect
+    assert_exchange(&mut alice, &mut bob, "after").await;
+}
+
+#[tokio::test]
+async fn test_shutdown_flushes_queued_messages() {
+    let relay = start_relay().await;
+    let (mut alice, mut bob) = pair(relay.addr, relay.addr).await;
+
+    alice.send_global("goodbye");
+    let (done_tx, done_rx) = oneshot::channel();
+    alice.tx.send(OutgoingMessage::Shutdown { done: done_tx }).unwrap();
+
+    tokio::time::timeout(TIMEOUT, done_rx).await.unwrap().unwrap();
+    assert_eq!(bob.next_chat().await.content, "goodbye");
+    // No reconnecting after a requested shutdown
+    alice.wait_for_connection(|state| *state == ConnectionState::Disconnected).await;
+}
+
+#[tokio::test]
+async fn test_nicknames_reach_current_and_later_peers() {
+    let relay = start_relay().await;
+    let mut alice = Peer::connect_as(relay.addr, Some("ally")).await;
+    alice.wait_for_status("Connected to relay").await;
+    let mut bob = Peer::connect_as(relay.addr, Some("bobby")).await;
+
+    // Announced right after key exchange, in both directions
+    alice.wait_for_nickname(&bob.id, "bobby").await;
+    bob.wait_for_nickname(&alice.id, "ally").await;
+
+    // A rename reaches existing peers, and peers who join afterwards
+    alice.tx.send(OutgoingMessage::Nickname("alicia".to_string())).unwrap();
+    bob.wait_for_nickname(&alice.id, "alicia").await;
+    let mut carol = Peer::connect(relay.addr).await;
+    carol.wait_for_nickname(&alice.id, "alicia").await;
+    carol.wait_for_nickname(&bob.id, "bobby").await;
+}
+
+#[tokio::test]
+async fn test_second_chat_with_one_identity_takes_over() {
+    let relay = start_relay().await;
+    let mut alice = Peer::connect(relay.addr).await;
+    alice.wait_for_status("Connected to relay").await;
+    // Both windows load Bob's identity from the same file
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity");
+    Identity::generate().save_to_file(&path, "pw").unwrap();
+    let mut bob = Peer::connect_with(relay.addr, Identity::load_from_file(&path, "pw").unwrap(), None).await;
+    alice.wait_for_peer(&bob.id).await;
+    bob.wait_for_peer(&alice.id).await;
+    assert_exchange(&mut alice, &mut bob, "first window").await;
+
+    // Bob starts again with the same identity: same session id, and the first window
+    // is told it was taken over and stops reconnecting
+    let mut bob_again = Peer::connect_with(relay.addr, Identity::load_from_file(&path, "pw").unwrap(), None).await;
+    assert_eq!(bob_again.id, bob.id);
+    bob.wait_for_connection(|state| matches!(state, ConnectionState::Refused { reason } if reason.contains("took over"))).await;
+
+    // Alice drops the old session for the new one, and carries on with the new window
+    bob_again.wait_for_peer(&alice.id).await;
+    assert_exchange(&mut alice, &mut bob_again, "second window").await;
+    // Bob was talking a moment ago: that's a resume, not a second join
+    assert_eq!(alice.joins, [bob.id.clone()]);
+}
+
+#[tokio::test]
+async fn test_no_global_client_refuses_global_chat_both_ways() {
+    let relay = start_relay().await;
+    let mut bob = Peer::connect(relay.addr).await;
+    bob.wait_for_status("Connected to relay").await;
+    let mut client = ChatClient::new(Identity::generate(), format!("ws://{}", relay.addr), None);
+    client.set_no_global();
+    let mut alice = Peer::start(client).await;
+    alice.wait_for_peer(&bob.id).await;
+    bob.wait_for_peer(&alice.id).await;
+
+    // Bob's Global message is dropped; the DM after it arrives
+    bob.send_global("hello everyone");
+    bob.send_direct(&alice, "hello alice");
+    assert_eq!(alice.next_chat().await.content, "hello alice");
+
+    // Alice's Global message never leaves
+    alice.send_global("hello room");
+    alice.wait_for_status("Global is off").await;
+    alice.send_direct(&bob, "hello bob");
+    assert_eq!(bob.next_chat().await.content, "hello bob");
+}
+
+#[tokio::test]
+async fn test_a_relay_answering_with_another_key_is_refused() {
+    let key = RelayKey::generate();
+    let (public_key, fingerprint) = (key.public_key_bytes(), key.fingerprint());
+    let mut server = RelayServer::new("127.0.0.1:0".to_string());
+    server.set_key(key);
+    let handle = server.bind().await.unwrap().spawn();
+    let url = format!("ws://{}", handle.local_addr());
+    let dir = tempfile::tempdir().unwrap();
+    let pins = dir.path().join("known_relays.json");
+    let client = |expect: Option<&str>| {
+        let mut client = ChatClient::new(Identity::generate(), url.clone(), None);
+        client.set_relay_pins(pins.clone());
+        if let Some(fingerprint) = expect {
+            client.expect_relay(fingerprint.to_string());
+        }
+        client
+    };
+
+    // First visit: the key is pinned
+    let mut alice = Peer::start(client(None)).await;
+    alice.wait_for_status(&format!("pinned its key {}", fingerprint)).await;
+    alice.wait_for_status("Connected to relay").await;
+    assert!(std::fs::read_to_string(&pins).unwrap().contains(&hex::encode(&public_key)));
+
+    // Another relay at the same address, as far as the pin file goes, is refused for good
+    let other = RelayKey::generate();
+    std::fs::write(&pins, format!("{{\"{}\": \"{}\"}}", url, hex::encode(other.public_key_bytes()))).unwrap();
+    let mut bob = Peer::start(client(None)).await;
+    bob.wait_for_connection(|state| matches!(state, ConnectionState::Refused { reason } if reason.contains("RELAY KEY CHANGED"))).await;
+
+    // Named up front, the relay is taken whatever was pinned, and pinned from then on
+    let mut carol = Peer::start(client(Some(&fingerprint))).await;
+    carol.wait_for_status("Connected to relay").await;
+    let mut dave = Peer::start(client(Some(&other.fingerprint()))).await;
+    dave.wait_for_connection(|state| matches!(state, ConnectionState::Refused { reason } if reason.contains("--expect-relay"))).await;
+    Peer::start(client(None)).await.wait_for_status("Connected to relay").await;
+}
+
+/// A listener that takes connections and then never answers: with `upgrade`, it gets
+/// through the websocket handshake and reads (and ignores) everything after it
+async fn start_silent_relay(upgrade: bool) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                if !upgrade {
+                    // Held open, never read from
+                    let _stream = stream;
+                    return std::future::pending::<()>().await;
+                }
+                if let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await {
+                    while futures_util::StreamExt::next(&mut ws).await.is_some() {}
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_a_relay_that_stalls_setup_is_given_up_on_and_retried() {
+    let client = |addr: SocketAddr| {
+        let mut client = ChatClient::new(Identity::generate(), format!("ws://{}", addr), None);
+        client.set_setup_timeout(Duration::from_millis(200));
+        client
+    };
+
+    // Takes the TCP connection but never upgrades it
+    let mut alice = Peer::start(client(start_silent_relay(false).await)).await;
+    alice.wait_for_connection(|state| matches!(state, ConnectionState::Unresponsive { timeout: SetupTimeout::Unreachable, attempt: 1, .. })).await;
+    alice.wait_for_connection(|state| matches!(state, ConnectionState::Unresponsive { attempt: 2, .. })).await;
+
+    // Upgrades, then never answers Connect
+    let mut bob = Peer::start(client(start_silent_relay(true).await)).await;
+    bob.wait_for_connection(|state| matches!(state, ConnectionState::Unresponsive { timeout: SetupTimeout::NoAck, attempt: 1, .. })).await;
+    bob.wait_for_status("relay did not respond to handshake").await;
+    bob.wait_for_connection(|state| matches!(state, ConnectionState::Unresponsive { timeout: SetupTimeout::NoAck, attempt: 2, .. })).await;
+}
+
+#[tokio::test]
+async fn test_reconnect_rejoins_group_rooms() {
+    let relay = start_relay().await;
+    let proxy = Proxy::start(relay.addr).await;
+    let (mut alice, mut bob) = pair(relay.addr, proxy.addr).await;
+    let join = |peer: &Peer| {
+        let join_token = Some(vec![7; JOIN_TOKEN_LEN]);
+        peer.tx.send(OutgoingMessage::JoinRoom { group_id: "g1".to_string(), join_token }).unwrap();
+    };
+    let member_ids = vec![alice.id.clone(), bob.id.clone()];
+    let say = |from: &Peer, text: &str| {
+        let mut message = PlainMessage::new(from.id.clone(), text.to_string());
+        message.group_id = Some("g1".to_string());
+        let member_ids = member_ids.clone();
+        from.tx.send(OutgoingMessage::Group { group_id: "g1".to_string(), member_ids, message }).unwrap();
+    };
+    join(&alice);
+    alice.wait_for_status("1 online").await;
+    join(&bob);
+    alice.wait_for_status("2 online").await;
+    say(&alice, "before");
+    assert_eq!(bob.next_chat().await.content, "before");
+
+    // The relay drops Bob from the room with his connection; he's put back in on his own
+    proxy.sever();
+    bob.wait_for_connection(|state| matches!(state, ConnectionState::Reconnecting { attempt: 1, .. })).await;
+    bob.wait_for_status("Rejoined group rooms g1").await;
+    bob.wait_for_status("2 online").await;
+
+    say(&alice, "after, from alice");
+    assert_eq!(bob.next_chat().await.content, "after, from alice");
+    say(&bob, "after, from bob");
+    assert_eq!(alice.next_chat().await.content, "after, from bob");
+}
@@ -0,0 +1,498 @@
+//! Drives a real relay over websockets and checks its routing rules.
+
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, http::HeaderValue, Message as WsMessage};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use wsp::protocol::{codec, ErrorCode, Message};
+use wsp::relay::{bind_all, RelayHandle, RelayServer};
+
+type Ws = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How long to wait for a frame that should arrive
+const RECV_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to wait before concluding a frame was not delivered
+const SILENCE: Duration = Duration::from_millis(200);
+
+/// A relay on an ephemeral port, shut down when dropped
+struct Relay {
+    addr: SocketAddr,
+    _handle: RelayHandle,
+}
+
+async fn start_relay() -> Relay {
+    let handle = RelayServer::new("127.0.0.1:0".to_string()).bind().await.unwrap().spawn();
+    Relay { addr: handle.local_addr(), _handle: handle }
+}
+
+fn session_id(name: &str) -> String {
+    format!("{:0<64}", name)
+}
+
+async fn send(ws: &mut Ws, msg: &Message) {
+    ws.send(WsMessage::Binary(codec::encode(msg).unwrap())).await.unwrap();
+}
+
+/// Next frame of any kind, room presence included
+async fn recv_any(ws: &mut Ws) -> Message {
+    loop {
+        let frame = tokio::time::timeout(RECV_TIMEOUT, ws.next())
+            .await
+            .expect("timed out waiting for a frame")
+            .expect("connection closed")
+            .unwrap();
+        if let WsMessage::Binary(data) = frame {
+            return codec::decode(&codec::inflate(&data).unwrap()).unwrap();
+        }
+    }
+}
+
+/// Next frame's bytes as they came over the wire
+async fn recv_raw(ws: &mut Ws) -> Vec<u8> {
+    loop {
+        let frame = tokio::time::timeout(RECV_TIMEOUT, ws.next())
+            .await
+            .expect("timed out waiting for a frame")
+            .expect("connection closed")
+            .unwrap();
+        if let WsMessage::Binary(data) = frame {
+            return data;
+        }
+    }
+}
+
+/// Next routed frame, skipping room presence updates
+async fn recv(ws: &mut Ws) -> Message {
+    loop {
+        match recv_any(ws).await {
+            Message::RoomPresence { .. } => continue,
+            msg => return msg,
+        }
+    }
+}
+
+async fn assert_silent(ws: &mut Ws) {
+    while let Ok(Some(Ok(frame))) = tokio::time::timeout(SILENCE, ws.next()).await {
+        let WsMessage::Binary(data) = frame else { continue };
+        let msg = codec::decode(&codec::inflate(&data).unwrap()).unwrap();
+        if !matches!(msg, Message::RoomPresence { .. }) {
+            panic!("unexpected frame: {:?}", msg);
+        }
+    }
+}
+
+/// Next room count the relay reports
+async fn presence(ws: &mut Ws) -> u32 {
+    match recv_any(ws).await {
+        Message::RoomPresence { count, .. } => count,
+        other => panic!("expected room presence, got {:?}", other),
+    }
+}
+
+/// Connect with a session id and wait for the relay's Ack
+async fn connect(relay: &Relay, sid: &str) -> Ws {
+    connect_to(relay.addr, sid).await
+}
+
+async fn connect_to(addr: SocketAddr, sid: &str) -> Ws {
+    let (mut ws, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+    send(&mut ws, &Message::Connect { session_id: sid.to_string(), challenge: vec![] }).await;
+    assert!(matches!(recv(&mut ws).await, Message::Ack { .. }));
+    ws
+}
+
+/// Round-trip a Connect so every frame sent before it has been processed.
+/// Re-sending Connect on the same socket is a no-op resumption.
+async fn sync(ws: &mut Ws, sid: &str) {
+    send(ws, &Message::Connect { session_id: sid.to_string(), challenge: vec![] }).await;
+    assert!(matches!(recv(ws).await, Message::Ack { .. }));
+}
+
+/// Close a connection and wait until the relay has dropped it (and cleaned up)
+async fn disconnect(mut ws: Ws) {
+    ws.close(None).await.unwrap();
+    while let Ok(Some(Ok(_))) = tokio::time::timeout(RECV_TIMEOUT, ws.next()).await {}
+}
+
+async fn join(ws: &mut Ws, sid: &str, group_id: &str) {
+    send(ws, &Message::GroupJoin { session_id: sid.to_string(), group_id: group_id.to_string(), join_token: None }).await;
+    sync(ws, sid).await;
+}
+
+fn encrypted(from: &str, target: &str, ciphertext: &[u8]) -> Message {
+    Message::Encrypted {
+        from: from.to_string(),
+        target: target.to_string(),
+        header: Vec::new(),
+        nonce: vec![0; 12],
+        ciphertext: ciphertext.to_vec(),
+    }
+}
+
+fn group_encrypted(from: &str, group_id: &str, ciphertext: &[u8]) -> Message {
+    Message::GroupEncrypted {
+        from: from.to_string(),
+        group_id: group_id.to_string(),
+        header: Vec::new(),
+        nonce: vec![0; 12],
+        ciphertext: ciphertext.to_vec(),
+    }
+}
+
+fn ciphertext_of(msg: Message) -> Vec<u8> {
+    match msg {
+        Message::Encrypted { ciphertext, .. } | Message::GroupEncrypted { ciphertext, .. } => ciphertext,
+        other => panic!("expected an encrypted frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_connect_ack() {
+    let relay = start_relay().await;
+    let mut ws = connect(&relay, &session_id("alice")).await;
+    assert_silent(&mut ws).await;
+}
+
+#[tokio::test]
+async fn test_targeted_encrypted_reaches_only_target() {
+    let relay = start_relay().await;
+    let (a, b, c) = (session_id("alice"), session_id("bob"), session_id("carol"));
+    let mut ws_a = connect(&relay, &a).await;
+    let mut ws_b = connect(&relay, &b).await;
+    let mut ws_c = connect(&relay, &c).await;
+
+    send(&mut ws_a, &encrypted(&a, &b, b"for bob")).await;
+
+    assert_eq!(ciphertext_of(recv(&mut ws_b).await), b"for bob");
+    assert_silent(&mut ws_c).await;
+    assert_silent(&mut ws_a).await;
+}
+
+#[tokio::test]
+async fn test_broadcast_encrypted_skips_sender() {
+    let relay = start_relay().await;
+    let (a, b, c) = (session_id("alice"), session_id("bob"), session_id("carol"));
+    let mut ws_a = connect(&relay, &a).await;
+    let mut ws_b = connect(&relay, &b).await;
+    let mut ws_c = connect(&relay, &c).await;
+
+    send(&mut ws_a, &encrypted(&a, "", b"everyone")).await;
+
+    assert_eq!(ciphertext_of(recv(&mut ws_b).await), b"everyone");
+    assert_eq!(ciphertext_of(recv(&mut ws_c).await), b"everyone");
+    assert_silent(&mut ws_a).await;
+}
+
+#[tokio::test]
+async fn test_group_encrypted_reaches_room_members() {
+    let relay = start_relay().await;
+    let (a, b, c) = (session_id("alice"), session_id("bob"), session_id("carol"));
+    let mut ws_a = connect(&relay, &a).await;
+    let mut ws_b = connect(&relay, &b).await;
+    let mut ws_c = connect(&relay, &c).await;
+    join(&mut ws_a, &a, "room").await;
+    join(&mut ws_b, &b, "room").await;
+
+    send(&mut ws_a, &group_encrypted(&a, "room", b"hi room")).await;
+
+    assert_eq!(ciphertext_of(recv(&mut ws_b).await), b"hi room");
+    assert_silent(&mut ws_c).await;
+    assert_silent(&mut ws_a).await;
+}
+
+#[tokio::test]
+async fn test_non_members_cannot_send_to_room() {
+    let relay = start_relay().await;
+    let (a, b) = (session_id("alice"), session_id("bob"));
+    let mut ws_a = connect(&relay, &a).await;
+    let mut ws_b = connect(&relay, &b).await;
+    join(&mut ws_a, &a, "room").await;
+
+    send(&mut ws_b, &group_encrypted(&b, "room", b"let me in")).await;
+
+    assert_silent(&mut ws_a).await;
+}
+
+#[tokio::test]
+async fn test_group_leave_stops_delivery() {
+    let relay = start_relay().await;
+    let (a, b) = (session_id("alice"), session_id("bob"));
+    let mut ws_a = connect(&relay, &a).await;
+    let mut ws_b = connect(&relay, &b).await;
+    join(&mut ws_a, &a, "room").await;
+    join(&mut ws_b, &b, "room").await;
+
+    send(&mut ws_b, &Message::GroupLeave { session_id: b.clone(), group_id: "room".to_string() }).await;
+    sync(&mut ws_b, &b).await;
+    send(&mut ws_a, &group_encrypted(&a, "room", b"anyone?")).await;
+
+    assert_silent(&mut ws_b).await;
+}
+
+#[tokio::test]
+async fn test_disconnect_removes_session_from_rooms() {
+    let relay = start_relay().await;
+    let (a, b) = (session_id("alice"), session_id("bob"));
+    let mut ws_a = connect(&relay, &a).await;
+    let mut ws_b = connect(&relay, &b).await;
+    join(&mut ws_a, &a, "room").await;
+    join(&mut ws_b, &b, "room").await;
+
+    disconnect(ws_b).await;
+
+    // Targeted frames for the departed session go nowhere
+    send(&mut ws_a, &encrypted(&a, &b, b"gone")).await;
+    sync(&mut ws_a, &a).await;
+    // A fresh session under the same id starts outside the room
+    let mut ws_b = connect(&relay, &b).await;
+    send(&mut ws_a, &group_encrypted(&a, "room", b"still there?")).await;
+
+    assert_silent(&mut ws_b).await;
+}
+
+#[tokio::test]
+async fn test_session_resumption_replaces_channel() {
+    let relay = start_relay().await;
+    let (a, b) = (session_id("alice"), session_id("bob"));
+    let mut ws_a = connect(&relay, &a).await;
+    let mut old_b = connect(&relay, &b).await;
+    join(&mut ws_a, &a, "room").await;
+    join(&mut old_b, &b, "room").await;
+
+    // Reconnect under the same session id before the old socket goes away: the old
+    // one is told it was taken over, and closed
+    let mut new_b = connect(&relay, &b).await;
+    assert!(matches!(recv(&mut old_b).await, Message::Error { code: ErrorCode::SessionReplaced, .. }));
+    let closed = tokio::time::timeout(RECV_TIMEOUT, async {
+        while let Some(Ok(_)) = old_b.next().await {}
+    }).await;
+    assert!(closed.is_ok(), "replaced connection still open");
+
+    // ...which must not take the resumed session with it
+    send(&mut ws_a, &encrypted(&a, &b, b"one")).await;
+    assert_eq!(ciphertext_of(recv(&mut new_b).await), b"one");
+    send(&mut ws_a, &encrypted(&a, &b, b"two")).await;
+    assert_eq!(ciphertext_of(recv(&mut new_b).await), b"two");
+    send(&mut ws_a, &group_encrypted(&a, "room", b"three")).await;
+    assert_eq!(ciphertext_of(recv(&mut new_b).await), b"three");
+}
+
+#[tokio::test]
+async fn test_room_presence_counts_sessions_once() {
+    let relay = start_relay().await;
+    let (a, b, c) = (session_id("alice"), session_id("bob"), session_id("carol"));
+    let mut ws_a = connect(&relay, &a).await;
+    let mut old_b = connect(&relay, &b).await;
+    let mut ws_c = connect(&relay, &c).await;
+    let join_room = |sid: &str| Message::GroupJoin { session_id: sid.to_string(), group_id: "room".to_string(), join_token: None };
+    send(&mut ws_a, &join_room(&a)).await;
+    assert_eq!(presence(&mut ws_a).await, 1);
+    send(&mut old_b, &join_room(&b)).await;
+    assert_eq!(presence(&mut ws_a).await, 2);
+    assert_eq!(presence(&mut old_b).await, 2);
+
+    // Bob resumes on a new socket and re-joins: still two, and only bob is told
+    let mut new_b = connect(&relay, &b).await;
+    send(&mut new_b, &join_room(&b)).await;
+    assert_eq!(presence(&mut new_b).await, 2);
+    send(&mut ws_a, &Message::Connect { session_id: a.clone(), challenge: vec![] }).await;
+    assert!(matches!(recv_any(&mut ws_a).await, Message::Ack { .. }));
+    // The stale socket closing leaves the count alone
+    disconnect(old_b).await;
+
+    // Leaving is announced; outsiders never hear about the room
+    send(&mut new_b, &Message::GroupLeave { session_id: b.clone(), group_id: "room".to_string() }).await;
+    assert_eq!(presence(&mut ws_a).await, 1);
+    send(&mut ws_c, &Message::Connect { session_id: c.clone(), challenge: vec![] }).await;
+    assert!(matches!(recv_any(&mut ws_c).await, Message::Ack { .. }));
+}
+
+#[tokio::test]
+async fn test_legacy_raw_frames_still_accepted() {
+    let relay = start_relay().await;
+    let (a, b) = (session_id("alice"), session_id("bob"));
+    let mut ws_a = connect(&relay, &a).await;
+
+    // An old client speaks bare bincode and is answered the same way
+    let (mut ws_b, _) = connect_async(format!("ws://{}", relay.addr)).await.unwrap();
+    let raw_connect = bincode::serialize(&Message::Connect { session_id: b.clone(), challenge: vec![] }).unwrap();
+    assert_eq!(codec::wire_format(&raw_connect), Some(codec::WireFormat::Legacy));
+    ws_b.send(WsMessage::Binary(raw_connect)).await.unwrap();
+    let Some(Ok(WsMessage::Binary(ack))) = ws_b.next().await else { panic!("no ack") };
+    assert_eq!(codec::wire_format(&ack), Some(codec::WireFormat::Legacy));
+    assert!(matches!(codec::decode(&ack), Ok(Message::Ack { .. })));
+
+    // Its frames are routed, and newer clients can read them
+    ws_b.send(WsMessage::Binary(bincode::serialize(&encrypted(&b, &a, b"from the past")).unwrap())).await.unwrap();
+    assert_eq!(ciphertext_of(recv(&mut ws_a).await), b"from the past");
+}
+
+#[tokio::test]
+async fn test_shutdown_closes_connections() {
+    let relay = start_relay().await;
+    let addr = relay.addr;
+    let mut ws = connect(&relay, &session_id("alice")).await;
+
+    drop(relay);
+
+    let closed = tokio::time::timeout(RECV_TIMEOUT, async {
+        while let Some(Ok(_)) = ws.next().await {}
+    }).await;
+    assert!(closed.is_ok(), "connection still open after shutdown");
+    assert!(connect_async(format!("ws://{}", addr)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_relay_stops_cleanly_and_starts_again_on_its_port() {
+    let alive_tasks = || tokio::runtime::Handle::current().metrics().num_alive_tasks();
+    let before = alive_tasks();
+    let mut addr = "127.0.0.1:0".to_string();
+    for _ in 0..3 {
+        // After the first round this is the port the last relay had: it must be free again
+        let relay = RelayServer::new(addr).bind().await.unwrap().spawn();
+        addr = relay.local_addr().to_string();
+
+        let (a, b) = (session_id("alice"), session_id("bob"));
+        let mut ws_a = connect_to(relay.local_addr(), &a).await;
+        let mut ws_b = connect_to(relay.local_addr(), &b).await;
+        join(&mut ws_a, &a, "room").await;
+        join(&mut ws_b, &b, "room").await;
+
+        relay.shutdown().await.unwrap();
+        for ws in [&mut ws_a, &mut ws_b] {
+            // A Close frame, not just a dropped socket
+            let closing = loop {
+                match tokio::time::timeout(RECV_TIMEOUT, ws.next()).await.expect("not closed") {
+                    Some(Ok(WsMessage::Binary(_))) => continue,
+                    other => break other,
+                }
+            };
+            assert!(matches!(closing, Some(Ok(WsMessage::Close(_)))), "{:?}", closing);
+        }
+    }
+    // Every connection task (and its sender) finished along with the relay
+    assert_eq!(alive_tasks(), before);
+}
+
+#[tokio::test]
+async fn test_ipv4_and_ipv6_listeners_share_sessions() {
+    // Both loopbacks on one port, the way `--addr 0.0.0.0:8899,[::]:8899` binds
+    let mut listeners = bind_all(&["127.0.0.1:0".to_string()]).await.unwrap();
+    let port = listeners[0].local_addr().unwrap().port();
+    match bind_all(&[format!("[::1]:{}", port)]).await {
+        Ok(v6) => listeners.extend(v6),
+        Err(e) => {
+            eprintln!("skipped, no IPv6 loopback here: {:#}", e);
+            return;
+        }
+    }
+    let addrs: Vec<SocketAddr> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+    let (_shutdown, shutdown_rx) = oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let server = RelayServer::new(String::new());
+        server.serve_all(listeners, async { let _ = shutdown_rx.await; }).await
+    });
+
+    let (a, b) = (session_id("alice"), session_id("bob"));
+    let mut ws_a = connect_to(addrs[0], &a).await;
+    let mut ws_b = connect_to(addrs[1], &b).await;
+    send(&mut ws_a, &encrypted(&a, &b, b"over v4")).await;
+    assert_eq!(ciphertext_of(recv(&mut ws_b).await), b"over v4");
+    send(&mut ws_b, &encrypted(&b, &a, b"over v6")).await;
+    assert_eq!(ciphertext_of(recv(&mut ws_a).await), b"over v6");
+}
+
+/// A connection to a relay's admin console
+struct Console {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+}
+
+impl Console {
+    async fn open(addr: SocketAddr) -> Self {
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        Self { lines: BufReader::new(read).lines(), write }
+    }
+
+    async fn ask(&mut self, command: &str) -> String {
+        self.write.write_all(format!("{}\n", command).as_bytes()).await.unwrap();
+        tokio::time::timeout(RECV_TIMEOUT, self.lines.next_line()).await.unwrap().unwrap().unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_admin_console_lists_kicks_and_tunes() {
+    let mut server = RelayServer::new("127.0.0.1:0".to_string());
+    server.set_admin_addr("127.0.0.1:0".to_string());
+    let relay = server.bind().await.unwrap().spawn();
+    let (a, b) = (session_id("alice"), session_id("bob"));
+    let mut ws_a = connect_to(relay.local_addr(), &a).await;
+    let mut ws_b = connect_to(relay.local_addr(), &b).await;
+    join(&mut ws_a, &a, "room-one").await;
+
+    let mut console = Console::open(relay.admin_addr().unwrap()).await;
+    assert_eq!(console.ask("sessions").await, "2 sessions: alice0000000 bob000000000");
+    assert_eq!(console.ask("rooms").await, "1 room: room-one (1)");
+
+    // A kick closes the websocket itself, not just the routing entry
+    assert_eq!(console.ask("kick bob").await, "kicked bob000000000");
+    let closing = loop {
+        match tokio::time::timeout(RECV_TIMEOUT, ws_b.next()).await.expect("not closed") {
+            Some(Ok(WsMessage::Binary(_))) => continue,
+            other => break other,
+        }
+    };
+    assert!(matches!(closing, Some(Ok(WsMessage::Close(_)))), "{:?}", closing);
+    assert_eq!(console.ask("sessions").await, "1 session: alice0000000");
+
+    // Limits change for the running relay
+    assert_eq!(console.ask("set max_sessions 1").await, "max_sessions=1");
+    assert!(console.ask("limits").await.starts_with("max_sessions=1 "));
+    let (mut ws_c, _) = connect_async(format!("ws://{}", relay.local_addr())).await.unwrap();
+    send(&mut ws_c, &Message::Connect { session_id: session_id("carol"), challenge: vec![] }).await;
+    assert!(matches!(recv(&mut ws_c).await, Message::Error { code: ErrorCode::SessionLimit, .. }));
+    sync(&mut ws_a, &a).await;
+}
+
+#[tokio::test]
+async fn test_deflating_and_plain_clients_talk_through_the_relay() {
+    let relay = start_relay().await;
+    let (a, b) = (session_id("alice"), session_id("bob"));
+    let mut request = format!("ws://{}", relay.addr).into_client_request().unwrap();
+    request.headers_mut().insert(codec::COMPRESS_HEADER, HeaderValue::from_static(codec::COMPRESS_DEFLATE));
+    let (mut ws_a, response) = connect_async(request).await.unwrap();
+    assert_eq!(response.headers()[codec::COMPRESS_HEADER], codec::COMPRESS_DEFLATE);
+    let hello = codec::encode(&Message::Connect { session_id: a.clone(), challenge: vec![] }).unwrap();
+    ws_a.send(WsMessage::Binary(codec::deflate(hello))).await.unwrap();
+    assert!(matches!(recv(&mut ws_a).await, Message::Ack { .. }));
+    let mut ws_b = connect(&relay, &b).await;
+
+    // Alice's deflated frames reach bob as they were before deflating
+    let to_bob = Message::Encrypted { from: a.clone(), target: b.clone(), header: vec![0; 40], nonce: vec![0; 12], ciphertext: vec![7; 512] };
+    let frame = codec::encode(&to_bob).unwrap();
+    let deflated = codec::deflate(frame.clone());
+    assert!(deflated[0] == codec::DEFLATED && deflated.len() < frame.len());
+    ws_a.send(WsMessage::Binary(deflated)).await.unwrap();
+    assert_eq!(recv_raw(&mut ws_b).await, frame);
+
+    // Bob's plain frames reach alice deflated, except audio
+    let to_alice = Message::Encrypted { from: b.clone(), target: a.clone(), header: vec![0; 40], nonce: vec![0; 12], ciphertext: vec![8; 512] };
+    send(&mut ws_b, &to_alice).await;
+    let got = recv_raw(&mut ws_a).await;
+    assert_eq!(got[0], codec::DEFLATED);
+    assert_eq!(codec::inflate(&got).unwrap(), codec::encode(&to_alice).unwrap());
+    let audio = Message::AudioFrame { from: b.clone(), nonce: vec![0; 12], ciphertext: vec![0; 160], call_id: "c1".to_string() };
+    send(&mut ws_b, &audio).await;
+    assert_eq!(recv_raw(&mut ws_a).await, codec::encode(&audio).unwrap());
+
+    // Bob didn't ask to deflate, so a deflated frame from him isn't routed
+    ws_b.send(WsMessage::Binary(codec::deflate(codec::encode(&to_alice).unwrap()))).await.unwrap();
+    sync(&mut ws_b, &b).await;
+    assert_silent(&mut ws_a).await;
+}